@@ -0,0 +1,38 @@
+//! Generates the gRPC service of `proto/zippypack.proto` (grpc feature)
+//!
+//! The messages are written by hand in `src/grpc.rs` with prost's derive, so
+//! building needs no `protoc`; only the service stubs are generated here.
+
+fn main() {
+    #[cfg(feature = "grpc")]
+    grpc::generate();
+}
+
+#[cfg(feature = "grpc")]
+mod grpc {
+    use tonic_build::manual::{Builder, Method, Service};
+
+    /// Rust name, proto name, request, response, whether the response is a stream
+    const METHODS: [(&str, &str, &str, &str, bool); 5] = [
+        ("submit", "Submit", "Job", "JobId", false),
+        ("status", "Status", "JobId", "JobList", false),
+        ("watch", "Watch", "JobId", "Progress", true),
+        ("list", "List", "ArchivePath", "Listing", false),
+        ("read_entry", "ReadEntry", "EntryPath", "Chunk", true),
+    ];
+
+    pub fn generate() {
+        println!("cargo:rerun-if-changed=build.rs");
+        let mut service = Service::builder().name("Zippy").package("zippypack.v1");
+        for (name, route, input, output, streaming) in METHODS {
+            let method = Method::builder()
+                .name(name)
+                .route_name(route)
+                .input_type(format!("crate::grpc::{}", input))
+                .output_type(format!("crate::grpc::{}", output))
+                .codec_path("tonic::codec::ProstCodec");
+            service = service.method(if streaming { method.server_streaming() } else { method }.build());
+        }
+        Builder::new().compile(&[service.build()]);
+    }
+}
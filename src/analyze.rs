@@ -0,0 +1,229 @@
+//! Compression achieved per file extension in an existing archive or image, for `zippy analyze`
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use crate::compress::PROBE_MIN_SAVING;
+use crate::decompress::{compressed_size, list_archive, read_archive_header, read_codec, read_solid_frames, read_solid_index};
+use crate::error::{DecompressionError, ImageError, PathIoError};
+use crate::format::{CODEC_ZSTD, MODE_SOLID};
+use crate::image::list_image;
+use crate::list::{ContainerFormat, Listing};
+use crate::profile::{detect_profile, CompressionProfile};
+use crate::walk::EntryKind;
+
+/// Below this stored percentage, a binary category compresses like text and
+/// would gain from the higher level of the text profile
+const TEXT_LIKE_RATIO: f64 = 25.0;
+
+/// Below this stored percentage, files taken for already compressed still shrink
+/// enough to be worth a compressing profile
+const COMPRESSIBLE_RATIO: f64 = 90.0;
+
+/// Change suggested for a category
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Advice {
+    /// Barely shrinks: store it instead of spending time compressing
+    Store,
+    /// Compresses far better or worse than its profile assumes
+    Profile(CompressionProfile),
+}
+
+/// Entries sharing an extension
+#[derive(Debug, Clone)]
+pub struct CategoryStats {
+    /// Lowercase extension, empty for files without one
+    pub extension: String,
+    pub profile: CompressionProfile,
+    pub entries: u64,
+    pub input_size: u64,
+    /// Bytes stored for these entries, a block of an image being counted for every
+    /// entry using it. The frames of a solid archive mix categories, so its entries
+    /// are measured by compressing each on its own at the archive's level.
+    pub output_size: u64,
+    pub advice: Option<Advice>,
+}
+
+impl CategoryStats {
+    /// Output size as a percentage of the input size
+    pub fn ratio(&self) -> f64 {
+        if self.input_size == 0 {
+            return 100.0;
+        }
+        self.output_size as f64 / self.input_size as f64 * 100.0
+    }
+
+    fn advise(&self) -> Option<Advice> {
+        let ratio = self.ratio();
+        match self.profile {
+            _ if self.input_size == 0 => None,
+            CompressionProfile::AlreadyCompressed if ratio < COMPRESSIBLE_RATIO => Some(Advice::Profile(CompressionProfile::Binary)),
+            CompressionProfile::AlreadyCompressed => None,
+            _ if ratio > (100 - PROBE_MIN_SAVING) as f64 => Some(Advice::Store),
+            CompressionProfile::Binary if ratio < TEXT_LIKE_RATIO => Some(Advice::Profile(CompressionProfile::Text)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Analysis {
+    pub format: ContainerFormat,
+    /// Largest output first
+    pub categories: Vec<CategoryStats>,
+}
+
+/// Statistics per extension of a .zpp archive
+pub fn analyze_archive(path: &Path) -> Result<Analysis, DecompressionError> {
+    let listing = list_archive(path)?;
+    if listing.format != ContainerFormat::Solid {
+        return Ok(summarize(&listing, |index| listing.entries[index].compressed_size.unwrap_or(0)));
+    }
+    let sizes = solid_output_sizes(path)?;
+    Ok(summarize(&listing, |index| sizes[index]))
+}
+
+/// Statistics per extension of a .zpak image
+pub fn analyze_image(path: &Path) -> Result<Analysis, ImageError> {
+    let listing = list_image(path)?;
+    Ok(summarize(&listing, |index| listing.entries[index].compressed_size.unwrap_or(0)))
+}
+
+/// Group the files of a listing by extension, with the output size of each entry
+/// given by its position in the listing
+fn summarize(listing: &Listing, output_size: impl Fn(usize) -> u64) -> Analysis {
+    let mut categories: HashMap<String, CategoryStats> = HashMap::new();
+    for (index, entry) in listing.entries.iter().enumerate() {
+        if entry.kind != EntryKind::File {
+            continue;
+        }
+        let extension = entry.path.extension().map(|ext| ext.to_string_lossy().to_lowercase()).unwrap_or_default();
+        let category = categories.entry(extension).or_insert_with_key(|extension| CategoryStats {
+            extension: extension.clone(),
+            profile: detect_profile(&PathBuf::from("entry").with_extension(extension)),
+            entries: 0,
+            input_size: 0,
+            output_size: 0,
+            advice: None,
+        });
+        category.entries += 1;
+        category.input_size += entry.size;
+        category.output_size += output_size(index);
+    }
+
+    let mut categories: Vec<CategoryStats> = categories.into_values().collect();
+    for category in &mut categories {
+        category.advice = category.advise();
+    }
+    categories.sort_by(|a, b| b.output_size.cmp(&a.output_size).then_with(|| a.extension.cmp(&b.extension)));
+    Analysis { format: listing.format, categories }
+}
+
+/// Size of each entry of a solid archive compressed alone, in index order. Frames
+/// are decoded in order, keeping only the data of entries not yet complete.
+fn solid_output_sizes(path: &Path) -> Result<Vec<u64>, DecompressionError> {
+    let mut reader = BufReader::new(File::open(path).map_err(|e| DecompressionError::io_at(e, path))?);
+    let (mode, layout) = read_archive_header(&mut reader)?;
+    if mode != MODE_SOLID {
+        return Err(DecompressionError::InvalidFormat);
+    }
+    // Frames are decoded without the dictionary
+    let mut buffer = [0u8; 8];
+    reader.read_exact(&mut buffer)?;
+    reader.seek_relative(u64::from_le_bytes(buffer) as i64)?;
+    let codec = read_codec(&mut reader, &layout)?;
+    let frames = read_solid_frames(&mut reader, &layout)?;
+    let data_start = reader.stream_position()?;
+    reader.seek(SeekFrom::Start(data_start.checked_add(compressed_size(&frames)?).ok_or(DecompressionError::InvalidFormat)?))?;
+    let index = read_solid_index(&mut reader, &layout)?;
+    if !codec.is_supported() {
+        let path = index.first().map(|(path, _, _)| path.clone()).unwrap_or_default();
+        return Err(DecompressionError::UnsupportedCodec { path, codec });
+    }
+    let level = if codec.id == CODEC_ZSTD && codec.level > 0 { codec.level.into() } else { zstd::DEFAULT_COMPRESSION_LEVEL };
+
+    let mut pending: Vec<usize> = (0..index.len()).collect();
+    pending.sort_by_key(|&position| std::cmp::Reverse(index[position].1));
+    let mut sizes = vec![0; index.len()];
+    let mut window = Vec::new();
+    let mut window_start = 0u64;
+    reader.seek(SeekFrom::Start(data_start))?;
+    for frame in &frames {
+        let mut data = vec![0u8; frame.compressed_size as usize];
+        reader.read_exact(&mut data)?;
+        window.extend_from_slice(&codec.decode(&data)?);
+        let decoded_end = window_start + window.len() as u64;
+
+        while let Some(&position) = pending.last() {
+            let (path, start, length) = &index[position];
+            let out_of_range = || DecompressionError::CorruptIndex(format!("{:?} outside the data", path));
+            let end = start.checked_add(*length).ok_or_else(out_of_range)?;
+            if end > decoded_end {
+                break;
+            }
+            let from = start.checked_sub(window_start).ok_or_else(out_of_range)? as usize;
+            let data = window.get(from..from + *length as usize).ok_or_else(out_of_range)?;
+            sizes[position] = zstd::bulk::compress(data, level)?.len() as u64;
+            pending.pop();
+        }
+        let keep_from = pending.last().map_or(decoded_end, |&position| index[position].1.min(decoded_end)).max(window_start);
+        window.drain(..(keep_from - window_start) as usize);
+        window_start = keep_from;
+    }
+    if let Some(&position) = pending.last() {
+        return Err(DecompressionError::CorruptIndex(format!("{:?} outside the data", index[position].0)));
+    }
+    Ok(sizes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    use crate::compress::{compress_folder, CompressionOptions};
+
+    #[test]
+    fn test_analyze_archive_categories() {
+        let temp_dir = tempdir().unwrap();
+        let input_dir = temp_dir.path().join("input");
+        fs::create_dir(&input_dir).unwrap();
+        for i in 0..3 {
+            fs::write(input_dir.join(format!("{i}.txt")), "ligne de texte répétée\n".repeat(2000) + "fin").unwrap();
+        }
+        // Pseudo-random content that no profile shrinks
+        let mut state = 0x2545F4914F6CDD1Du64;
+        let noise: Vec<u8> = (0..200_000).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        }).collect();
+        fs::write(input_dir.join("noise.bin"), &noise).unwrap();
+
+        for solid in [false, true] {
+            let archive_path = temp_dir.path().join(format!("analyze-{solid}.zpp"));
+            compress_folder(&CompressionOptions {
+                input_path: input_dir.clone(),
+                output_path: archive_path.clone(),
+                solid,
+                ..Default::default()
+            }).unwrap();
+
+            let analysis = analyze_archive(&archive_path).unwrap();
+            assert_eq!(analysis.categories.len(), 2);
+            let category = |extension: &str| analysis.categories.iter().find(|category| category.extension == extension).unwrap();
+            let text = category("txt");
+            assert_eq!((text.entries, text.profile, text.advice), (3, CompressionProfile::Text, None));
+            assert_eq!(text.input_size, ("ligne de texte répétée\n".len() as u64 * 2000 + 3) * 3);
+            assert!(text.ratio() < 5.0);
+            let binary = category("bin");
+            assert_eq!(binary.input_size, noise.len() as u64);
+            assert_eq!(binary.advice, Some(Advice::Store));
+            assert_eq!(analysis.categories[0].extension, "bin");
+        }
+    }
+}
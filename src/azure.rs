@@ -0,0 +1,262 @@
+//! Azure Blob Storage backend for `azure://container/path` URLs
+//!
+//! Requests are authorized by a shared access signature (SAS) appended to
+//! their URL, from `[storage.azure]` in config.toml or the
+//! `ZIPPY_STORAGE__AZURE__*` variables. Objects are block blobs: uploads stage
+//! numbered blocks with Put Block and commit them with Put Block List, so an
+//! interrupted upload resumes with the blocks still missing.
+
+use std::io::{self, Read};
+use std::ops::Range;
+use std::path::Path;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use md5::{Digest, Md5};
+use tracing::debug;
+
+use crate::config::AzureConfig;
+use crate::http::{self, percent_encode, xml_elements, xml_unescape};
+use crate::storage::{ObjectInfo, StorageBackend};
+use crate::throttle::Bandwidth;
+use crate::upload::{self, CompletedPart, MultipartTarget, Part, UploadOptions};
+
+/// Blob service REST API version requests are made against
+const API_VERSION: &str = "2021-08-06";
+
+/// Most uncommitted blocks a blob may have
+const MAX_BLOCKS: u32 = 50_000;
+
+pub struct AzureBackend {
+    /// Container URL, without trailing slash
+    container_url: String,
+    sas_token: String,
+    agent: ureq::Agent,
+    bandwidth: Bandwidth,
+    upload: UploadOptions,
+}
+
+impl AzureBackend {
+    pub fn new(config: &AzureConfig, container: &str) -> io::Result<Self> {
+        let endpoint = match (&config.endpoint, &config.account) {
+            (Some(endpoint), _) => endpoint.trim_end_matches('/').to_string(),
+            (None, Some(account)) => format!("https://{}.blob.core.windows.net", account),
+            (None, None) => return Err(missing_setting("account")),
+        };
+        let sas_token = config.sas_token.as_deref().ok_or_else(|| missing_setting("sas_token"))?;
+        if container.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "azure:// URLs start with a container name"));
+        }
+        Ok(Self {
+            container_url: format!("{}/{}", endpoint, percent_encode(container, false)),
+            sas_token: sas_token.trim_start_matches('?').to_string(),
+            agent: http::agent(),
+            bandwidth: Bandwidth::default(),
+            upload: UploadOptions::default(),
+        })
+    }
+
+    /// Pace transfers within `bandwidth`
+    pub fn with_bandwidth(mut self, bandwidth: Bandwidth) -> Self {
+        self.bandwidth = bandwidth;
+        self
+    }
+
+    /// Backend and object name of the part of a URL after `azure://`
+    pub fn from_url(rest: &str, config: &AzureConfig) -> io::Result<(Self, String)> {
+        let (container, name) = rest.split_once('/').unwrap_or((rest, ""));
+        Ok((Self::new(config, container)?, name.trim_matches('/').to_string()))
+    }
+
+    /// URL of a blob, or of the container when `name` is empty, with `query` parameters and the SAS
+    fn url(&self, name: &str, query: &str) -> String {
+        let mut url = self.container_url.clone();
+        if !name.is_empty() {
+            url.push('/');
+            url.push_str(&percent_encode(name, true));
+        }
+        url.push('?');
+        if !query.is_empty() {
+            url.push_str(query);
+            url.push('&');
+        }
+        url.push_str(&self.sas_token);
+        url
+    }
+
+    fn request(&self, method: &str, url: &str) -> ureq::Request {
+        self.agent.request(method, url).set("x-ms-version", API_VERSION)
+    }
+
+    /// Run a request with the retry policy of uploads
+    fn retried<T>(&self, what: &str, request: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+        self.upload.retry.run(what, &mut 0, request)
+    }
+}
+
+fn missing_setting(key: &str) -> io::Error {
+    let message = format!("Azure storage needs storage.azure.{} in config.toml or ZIPPY_STORAGE__AZURE__{}", key, key.to_uppercase());
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+impl StorageBackend for AzureBackend {
+    fn open(&self, name: &str) -> io::Result<Box<dyn Read + Send>> {
+        let url = self.url(name, "");
+        let response = self.retried("get blob", || self.request("GET", &url).call().map_err(|e| http::request_error(name, e)))?;
+        Ok(Box::new(self.bandwidth.download(response.into_reader())))
+    }
+
+    fn read_range(&self, name: &str, range: Range<u64>) -> io::Result<Vec<u8>> {
+        if range.is_empty() {
+            return Ok(Vec::new());
+        }
+        let url = self.url(name, "");
+        let header = format!("bytes={}-{}", range.start, range.end - 1);
+        self.retried("get blob range", || {
+            let response = match self.request("GET", &url).set("x-ms-range", &header).call().map_err(|e| http::request_error(name, e)) {
+                // The range starts after the end of the blob
+                Err(e) if http::status(&e) == Some(416) => return Ok(Vec::new()),
+                response => response?,
+            };
+            let mut data = Vec::new();
+            self.bandwidth.download(response.into_reader()).read_to_end(&mut data)?;
+            Ok(data)
+        })
+    }
+
+    fn write(&self, name: &str, source: &Path) -> io::Result<()> {
+        let mut target = BlockUpload { backend: self, name };
+        let summary = upload::upload_file(source, &mut target, &self.upload)?;
+        debug!(blob = name, blocks = summary.parts, resumed = summary.resumed_parts, retries = summary.retries, "Blob committed");
+        Ok(())
+    }
+
+    fn list(&self, prefix: &str) -> io::Result<Vec<ObjectInfo>> {
+        let mut objects = Vec::new();
+        let mut marker = String::new();
+        loop {
+            let mut query = format!("restype=container&comp=list&prefix={}", percent_encode(prefix, false));
+            if !marker.is_empty() {
+                query.push_str(&format!("&marker={}", percent_encode(&marker, false)));
+            }
+            let url = self.url("", &query);
+            let body = self.retried("list blobs", || {
+                self.request("GET", &url).call().map_err(|e| http::request_error("list blobs", e))?.into_string()
+            })?;
+            let (page, next) = parse_blob_list(&body)?;
+            objects.extend(page);
+            match next {
+                Some(next) => marker = next,
+                None => break,
+            }
+        }
+        objects.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(objects)
+    }
+
+    fn delete(&self, name: &str) -> io::Result<()> {
+        let url = self.url(name, "");
+        self.retried("delete blob", || {
+            self.request("DELETE", &url).call().map_err(|e| http::request_error(name, e))?;
+            Ok(())
+        })
+    }
+}
+
+/// Blobs of a List Blobs response page, and the marker of the next page
+fn parse_blob_list(body: &str) -> io::Result<(Vec<ObjectInfo>, Option<String>)> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "malformed List Blobs response");
+    let mut objects = Vec::new();
+    for blob in xml_elements(body, "Blob") {
+        let name = xml_elements(blob, "Name").first().map(|name| xml_unescape(name)).ok_or_else(invalid)?;
+        let size = xml_elements(blob, "Content-Length").first().and_then(|size| size.trim().parse().ok()).ok_or_else(invalid)?;
+        objects.push(ObjectInfo { name, size });
+    }
+    let next = xml_elements(body, "NextMarker").first().map(|marker| xml_unescape(marker)).filter(|marker| !marker.is_empty());
+    Ok((objects, next))
+}
+
+/// Block id of a part; all the ids of a blob have the same length
+fn block_id(upload_id: &str, number: u32) -> String {
+    BASE64.encode(format!("{}-{:06}", upload_id, number))
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+/// Staged upload of one blob
+struct BlockUpload<'a> {
+    backend: &'a AzureBackend,
+    name: &'a str,
+}
+
+impl MultipartTarget for BlockUpload<'_> {
+    fn destination(&self) -> String {
+        format!("{}/{}", self.backend.container_url, percent_encode(self.name, true))
+    }
+
+    fn max_parts(&self) -> u32 {
+        MAX_BLOCKS
+    }
+
+    /// Blocks need no session: the upload id only keeps block ids apart from other uploads
+    fn create(&mut self, _size: u64) -> io::Result<String> {
+        let mut nonce = [0u8; 8];
+        getrandom::getrandom(&mut nonce).map_err(|e| io::Error::other(e.to_string()))?;
+        Ok(nonce.iter().map(|byte| format!("{:02x}", byte)).collect())
+    }
+
+    fn upload_part(&mut self, upload_id: &str, part: &Part, data: &[u8]) -> io::Result<String> {
+        let id = block_id(upload_id, part.number);
+        let url = self.backend.url(self.name, &format!("comp=block&blockid={}", percent_encode(&id, false)));
+        // The service checks the block against its MD5
+        let request = self.backend.request("PUT", &url).set("Content-MD5", &BASE64.encode(Md5::digest(data)));
+        http::send_bytes(self.name, request, data, &self.backend.bandwidth)?;
+        Ok(id)
+    }
+
+    fn complete(&mut self, _upload_id: &str, parts: &[CompletedPart]) -> io::Result<()> {
+        let mut body = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?><BlockList>");
+        for part in parts {
+            body.push_str(&format!("<Latest>{}</Latest>", xml_escape(&part.tag)));
+        }
+        body.push_str("</BlockList>");
+        let url = self.backend.url(self.name, "comp=blocklist");
+        self.backend
+            .request("PUT", &url)
+            .set("Content-Type", "application/xml")
+            .send_string(&body)
+            .map_err(|e| http::request_error(self.name, e))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blob_urls() {
+        let config = AzureConfig { account: Some("backups".to_string()), sas_token: Some("?sv=2022-11-02&sig=a%2Bb".to_string()), endpoint: None };
+        let (backend, name) = AzureBackend::from_url("nightly/host 1/root.zpak", &config).unwrap();
+        assert_eq!(name, "host 1/root.zpak");
+        assert_eq!(backend.url(&name, "comp=block"), "https://backups.blob.core.windows.net/nightly/host%201/root.zpak?comp=block&sv=2022-11-02&sig=a%2Bb");
+        assert_eq!(backend.url("", ""), "https://backups.blob.core.windows.net/nightly?sv=2022-11-02&sig=a%2Bb");
+        assert_eq!(block_id("0011223344556677", 7).len(), block_id("0011223344556677", 49_999).len());
+
+        let missing = AzureBackend::new(&AzureConfig { sas_token: None, ..config }, "nightly").err().unwrap();
+        assert!(missing.to_string().contains("ZIPPY_STORAGE__AZURE__SAS_TOKEN"));
+    }
+
+    #[test]
+    fn test_parse_blob_list() {
+        let body = r#"<?xml version="1.0" encoding="utf-8"?><EnumerationResults ContainerName="nightly"><Prefix>repo/</Prefix><Blobs>
+            <Blob><Name>repo/a&amp;b.zpak</Name><Properties><Content-Length>1024</Content-Length><BlobType>BlockBlob</BlobType></Properties></Blob>
+            <Blob><Name>repo/c.zpak</Name><Properties><Content-Length>0</Content-Length></Properties></Blob>
+            </Blobs><NextMarker>2!72!cmVwby9jLnpwYWs-</NextMarker></EnumerationResults>"#;
+        let (objects, next) = parse_blob_list(body).unwrap();
+        assert_eq!(objects, vec![ObjectInfo { name: "repo/a&b.zpak".to_string(), size: 1024 }, ObjectInfo { name: "repo/c.zpak".to_string(), size: 0 }]);
+        assert_eq!(next.as_deref(), Some("2!72!cmVwby9jLnpwYWs-"));
+        assert_eq!(parse_blob_list("<EnumerationResults><Blobs /><NextMarker /></EnumerationResults>").unwrap(), (Vec::new(), None));
+    }
+}
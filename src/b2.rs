@@ -0,0 +1,439 @@
+//! Backblaze B2 backend for `b2://bucket/path` URLs, over the native API
+//!
+//! The account is authorized with an application key from `[storage.b2]` in
+//! config.toml or the `ZIPPY_STORAGE__B2__*` variables; the session is opened
+//! on first use and again when its token expires. Files up to the recommended
+//! part size are sent in one request, larger ones as B2 large files whose
+//! parts resume after an interruption. Every upload carries its SHA-1, which
+//! B2 checks.
+
+use std::fs;
+use std::io::{self, Read};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha1::{Digest, Sha1};
+use tracing::debug;
+
+use crate::config::B2Config;
+use crate::http::{self, percent_encode};
+use crate::storage::{ObjectInfo, StorageBackend};
+use crate::throttle::Bandwidth;
+use crate::upload::{self, CompletedPart, MultipartTarget, Part, UploadOptions};
+
+const DEFAULT_ENDPOINT: &str = "https://api.backblazeb2.com";
+
+/// Most parts of a large file
+const MAX_PARTS: u32 = 10_000;
+
+/// Files listed per request
+const PAGE_SIZE: u32 = 1000;
+
+pub struct B2Backend {
+    endpoint: String,
+    /// `key_id:application_key`, base64
+    credentials: String,
+    bucket: String,
+    session: Mutex<Option<Arc<Session>>>,
+    agent: ureq::Agent,
+    bandwidth: Bandwidth,
+    upload: UploadOptions,
+}
+
+/// Authorized account
+#[derive(Debug)]
+struct Session {
+    account_id: String,
+    token: String,
+    api_url: String,
+    download_url: String,
+    bucket_id: String,
+    part_size: u64,
+}
+
+impl B2Backend {
+    pub fn new(config: &B2Config, bucket: &str) -> io::Result<Self> {
+        let key_id = config.key_id.as_deref().ok_or_else(|| missing_setting("key_id"))?;
+        let application_key = config.application_key.as_deref().ok_or_else(|| missing_setting("application_key"))?;
+        if bucket.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "b2:// URLs start with a bucket name"));
+        }
+        Ok(Self {
+            endpoint: config.endpoint.as_deref().unwrap_or(DEFAULT_ENDPOINT).trim_end_matches('/').to_string(),
+            credentials: BASE64.encode(format!("{}:{}", key_id, application_key)),
+            bucket: bucket.to_string(),
+            session: Mutex::new(None),
+            agent: http::agent(),
+            bandwidth: Bandwidth::default(),
+            upload: UploadOptions::default(),
+        })
+    }
+
+    /// Pace transfers within `bandwidth`
+    pub fn with_bandwidth(mut self, bandwidth: Bandwidth) -> Self {
+        self.bandwidth = bandwidth;
+        self
+    }
+
+    /// Backend and object name of the part of a URL after `b2://`
+    pub fn from_url(rest: &str, config: &B2Config) -> io::Result<(Self, String)> {
+        let (bucket, name) = rest.split_once('/').unwrap_or((rest, ""));
+        Ok((Self::new(config, bucket)?, name.trim_matches('/').to_string()))
+    }
+
+    /// Current session, authorizing the account when there is none
+    fn session(&self) -> io::Result<Arc<Session>> {
+        let mut session = self.session.lock().unwrap();
+        if let Some(session) = session.as_ref() {
+            return Ok(session.clone());
+        }
+        let authorized = Arc::new(self.authorize()?);
+        *session = Some(authorized.clone());
+        Ok(authorized)
+    }
+
+    fn authorize(&self) -> io::Result<Session> {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Authorization {
+            account_id: String,
+            authorization_token: String,
+            api_url: String,
+            download_url: String,
+            recommended_part_size: u64,
+            #[serde(default)]
+            allowed: Allowed,
+        }
+        #[derive(Default, Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Allowed {
+            bucket_id: Option<String>,
+            bucket_name: Option<String>,
+        }
+        let url = format!("{}/b2api/v2/b2_authorize_account", self.endpoint);
+        let response = self
+            .agent
+            .get(&url)
+            .set("Authorization", &format!("Basic {}", self.credentials))
+            .call()
+            .map_err(|e| http::request_error("authorize account", e))?;
+        let authorization: Authorization = parse_json(response.into_json()?)?;
+        let mut session = Session {
+            account_id: authorization.account_id,
+            token: authorization.authorization_token,
+            api_url: authorization.api_url,
+            download_url: authorization.download_url,
+            bucket_id: String::new(),
+            part_size: authorization.recommended_part_size,
+        };
+        // Keys restricted to a bucket name it; others look it up
+        session.bucket_id = match authorization.allowed {
+            Allowed { bucket_id: Some(id), bucket_name: Some(name) } if name == self.bucket => id,
+            _ => {
+                let buckets = self.api_with(&session, "b2_list_buckets", json!({ "accountId": session.account_id, "bucketName": self.bucket }))?;
+                buckets["buckets"][0]["bucketId"]
+                    .as_str()
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no B2 bucket named {}", self.bucket)))?
+                    .to_string()
+            }
+        };
+        debug!(bucket = %self.bucket, "B2 account authorized");
+        Ok(session)
+    }
+
+    /// Send a request made for the current session, signing in again once if its token expired
+    fn call(&self, request: impl Fn(&Session) -> io::Result<ureq::Response>) -> io::Result<ureq::Response> {
+        match request(&*self.session()?) {
+            Err(e) if http::status(&e) == Some(401) => {
+                debug!("B2 authorization expired, signing in again");
+                *self.session.lock().unwrap() = None;
+                request(&*self.session()?)
+            }
+            response => response,
+        }
+    }
+
+    /// Call an API operation
+    fn api(&self, operation: &str, body: Value) -> io::Result<Value> {
+        let response = self.call(|session| {
+            self.agent
+                .post(&format!("{}/b2api/v2/{}", session.api_url, operation))
+                .set("Authorization", &session.token)
+                .send_json(&body)
+                .map_err(|e| http::request_error(operation, e))
+        })?;
+        response.into_json()
+    }
+
+    /// Call an API operation with a session being set up
+    fn api_with(&self, session: &Session, operation: &str, body: Value) -> io::Result<Value> {
+        let response = self
+            .agent
+            .post(&format!("{}/b2api/v2/{}", session.api_url, operation))
+            .set("Authorization", &session.token)
+            .send_json(&body)
+            .map_err(|e| http::request_error(operation, e))?;
+        response.into_json()
+    }
+
+    fn download(&self, name: &str, range: Option<&str>) -> io::Result<ureq::Response> {
+        self.call(|session| {
+            let url = format!("{}/file/{}/{}", session.download_url, percent_encode(&self.bucket, false), percent_encode(name, true));
+            let request = self.agent.get(&url).set("Authorization", &session.token);
+            match range {
+                Some(range) => request.set("Range", range).call(),
+                None => request.call(),
+            }
+            .map_err(|e| http::request_error(name, e))
+        })
+    }
+
+    /// Send a file of at most one part in a single request
+    fn upload_small(&self, name: &str, source: &Path, session: &Session) -> io::Result<()> {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct UploadUrl {
+            upload_url: String,
+            authorization_token: String,
+        }
+        let data = fs::read(source)?;
+        let sha1 = hex(&Sha1::digest(&data));
+        self.upload.retry.run("upload file", &mut 0, || {
+            // A failed upload URL is not reused
+            let target: UploadUrl = parse_json(self.api("b2_get_upload_url", json!({ "bucketId": session.bucket_id }))?)?;
+            let request = self
+                .agent
+                .post(&target.upload_url)
+                .set("Authorization", &target.authorization_token)
+                .set("X-Bz-File-Name", &percent_encode(name, true))
+                .set("Content-Type", "b2/x-auto")
+                .set("X-Bz-Content-Sha1", &sha1);
+            http::send_bytes(name, request, &data, &self.bandwidth)?;
+            Ok(())
+        })
+    }
+}
+
+fn missing_setting(key: &str) -> io::Error {
+    let message = format!("B2 storage needs storage.b2.{} in config.toml or ZIPPY_STORAGE__B2__{}", key, key.to_uppercase());
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+fn parse_json<T: DeserializeOwned>(value: Value) -> io::Result<T> {
+    serde_json::from_value(value).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+impl StorageBackend for B2Backend {
+    fn open(&self, name: &str) -> io::Result<Box<dyn Read + Send>> {
+        let response = self.upload.retry.run("download file", &mut 0, || self.download(name, None))?;
+        Ok(Box::new(self.bandwidth.download(response.into_reader())))
+    }
+
+    fn read_range(&self, name: &str, range: Range<u64>) -> io::Result<Vec<u8>> {
+        if range.is_empty() {
+            return Ok(Vec::new());
+        }
+        let header = format!("bytes={}-{}", range.start, range.end - 1);
+        self.upload.retry.run("download range", &mut 0, || {
+            let response = match self.download(name, Some(&header)) {
+                // The range starts after the end of the file
+                Err(e) if http::status(&e) == Some(416) => return Ok(Vec::new()),
+                response => response?,
+            };
+            let mut data = Vec::new();
+            self.bandwidth.download(response.into_reader()).read_to_end(&mut data)?;
+            Ok(data)
+        })
+    }
+
+    fn write(&self, name: &str, source: &Path) -> io::Result<()> {
+        let session = self.session()?;
+        if fs::metadata(source)?.len() <= session.part_size {
+            return self.upload_small(name, source, &session);
+        }
+        let options = UploadOptions { part_size: session.part_size, ..self.upload.clone() };
+        let summary = upload::upload_file(source, &mut LargeFile { backend: self, name }, &options)?;
+        debug!(file = name, parts = summary.parts, resumed = summary.resumed_parts, retries = summary.retries, "Large file finished");
+        Ok(())
+    }
+
+    fn list(&self, prefix: &str) -> io::Result<Vec<ObjectInfo>> {
+        let bucket_id = self.session()?.bucket_id.clone();
+        let mut objects = Vec::new();
+        let mut start = None;
+        loop {
+            let mut request = json!({ "bucketId": bucket_id, "prefix": prefix, "maxFileCount": PAGE_SIZE });
+            if let Some(start) = start.take() {
+                request["startFileName"] = Value::String(start);
+            }
+            let page = self.upload.retry.run("list files", &mut 0, || self.api("b2_list_file_names", request.clone()))?;
+            let (files, next) = parse_file_names(page)?;
+            objects.extend(files);
+            match next {
+                Some(next) => start = Some(next),
+                None => break,
+            }
+        }
+        objects.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(objects)
+    }
+
+    /// Delete every version of the file
+    fn delete(&self, name: &str) -> io::Result<()> {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Versions {
+            files: Vec<Version>,
+            next_file_name: Option<String>,
+            next_file_id: Option<String>,
+        }
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Version {
+            file_name: String,
+            file_id: String,
+        }
+        let bucket_id = self.session()?.bucket_id.clone();
+        let mut request = json!({ "bucketId": bucket_id, "prefix": name, "startFileName": name, "maxFileCount": PAGE_SIZE });
+        let mut deleted = 0;
+        loop {
+            let versions: Versions = parse_json(self.upload.retry.run("list versions", &mut 0, || self.api("b2_list_file_versions", request.clone()))?)?;
+            for version in versions.files.iter().filter(|version| version.file_name == name) {
+                let body = json!({ "fileName": version.file_name, "fileId": version.file_id });
+                self.upload.retry.run("delete version", &mut 0, || self.api("b2_delete_file_version", body.clone()))?;
+                deleted += 1;
+            }
+            match (versions.next_file_name, versions.next_file_id) {
+                (Some(next_name), Some(next_id)) if next_name == name => {
+                    request["startFileId"] = Value::String(next_id);
+                }
+                _ => break,
+            }
+        }
+        if deleted == 0 {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("{}: no such file", name)));
+        }
+        Ok(())
+    }
+}
+
+/// Files of a `b2_list_file_names` page, and the name the next page starts at
+fn parse_file_names(page: Value) -> io::Result<(Vec<ObjectInfo>, Option<String>)> {
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Page {
+        files: Vec<File>,
+        next_file_name: Option<String>,
+    }
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct File {
+        file_name: String,
+        content_length: u64,
+        #[serde(default)]
+        action: String,
+    }
+    let page: Page = parse_json(page)?;
+    let objects = page
+        .files
+        .into_iter()
+        .filter(|file| file.action == "upload")
+        .map(|file| ObjectInfo { name: file.file_name, size: file.content_length })
+        .collect();
+    Ok((objects, page.next_file_name))
+}
+
+/// Large file upload; the upload id is the file id B2 gave it
+struct LargeFile<'a> {
+    backend: &'a B2Backend,
+    name: &'a str,
+}
+
+impl MultipartTarget for LargeFile<'_> {
+    fn destination(&self) -> String {
+        format!("b2://{}/{}", self.backend.bucket, self.name)
+    }
+
+    fn max_parts(&self) -> u32 {
+        MAX_PARTS
+    }
+
+    fn create(&mut self, _size: u64) -> io::Result<String> {
+        let bucket_id = self.backend.session()?.bucket_id.clone();
+        let started = self.backend.api("b2_start_large_file", json!({ "bucketId": bucket_id, "fileName": self.name, "contentType": "b2/x-auto" }))?;
+        started["fileId"].as_str().map(str::to_string).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "large file started without an id"))
+    }
+
+    fn upload_part(&mut self, file_id: &str, part: &Part, data: &[u8]) -> io::Result<String> {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct UploadPartUrl {
+            upload_url: String,
+            authorization_token: String,
+        }
+        let target: UploadPartUrl = parse_json(self.backend.api("b2_get_upload_part_url", json!({ "fileId": file_id }))?)?;
+        let sha1 = hex(&Sha1::digest(data));
+        let request = self
+            .backend
+            .agent
+            .post(&target.upload_url)
+            .set("Authorization", &target.authorization_token)
+            .set("X-Bz-Part-Number", &part.number.to_string())
+            .set("X-Bz-Content-Sha1", &sha1);
+        http::send_bytes(self.name, request, data, &self.backend.bandwidth)?;
+        Ok(sha1)
+    }
+
+    fn complete(&mut self, file_id: &str, parts: &[CompletedPart]) -> io::Result<()> {
+        let sha1s: Vec<&str> = parts.iter().map(|part| part.tag.as_str()).collect();
+        self.backend.api("b2_finish_large_file", json!({ "fileId": file_id, "partSha1Array": sha1s }))?;
+        Ok(())
+    }
+
+    fn abort(&mut self, file_id: &str) -> io::Result<()> {
+        self.backend.api("b2_cancel_large_file", json!({ "fileId": file_id }))?;
+        Ok(())
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_file_names() {
+        let page = json!({
+            "files": [
+                { "fileName": "repo/a.zpak", "contentLength": 1024, "action": "upload", "fileId": "4_z1" },
+                { "fileName": "repo/b.zpak", "contentLength": 0, "action": "hide", "fileId": "4_z2" }
+            ],
+            "nextFileName": "repo/c.zpak"
+        });
+        let (objects, next) = parse_file_names(page).unwrap();
+        assert_eq!(objects, vec![ObjectInfo { name: "repo/a.zpak".to_string(), size: 1024 }]);
+        assert_eq!(next.as_deref(), Some("repo/c.zpak"));
+        assert_eq!(parse_file_names(json!({ "files": [], "nextFileName": null })).unwrap(), (Vec::new(), None));
+    }
+
+    #[test]
+    fn test_backend_settings() {
+        let config = B2Config { key_id: Some("0012ab".to_string()), application_key: Some("K001secret".to_string()), endpoint: None };
+        let (backend, name) = B2Backend::from_url("my-bucket/nightly/root.zpak", &config).unwrap();
+        assert_eq!((backend.bucket.as_str(), name.as_str()), ("my-bucket", "nightly/root.zpak"));
+        assert_eq!(BASE64.decode(&backend.credentials).unwrap(), b"0012ab:K001secret");
+        assert_eq!(hex(&Sha1::digest(b"abc")), "a9993e364706816aba3e25717850c26c9cd0d89d");
+
+        let missing = B2Backend::new(&B2Config { application_key: None, ..config }, "my-bucket").err().unwrap();
+        assert!(missing.to_string().contains("ZIPPY_STORAGE__B2__APPLICATION_KEY"));
+        assert!(!format!("{:?}", B2Config { application_key: Some("K001secret".to_string()), ..Default::default() }).contains("secret"));
+    }
+}
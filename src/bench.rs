@@ -0,0 +1,168 @@
+//! Compression level sweep on a sample of the user's data
+
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use tracing::info;
+use zstd::zstd_safe::{self, CCtx, CParameter};
+
+use crate::error::{CompressionError, PathIoError};
+use crate::walk::{walk, EntryKind, WalkOptions};
+
+pub struct BenchOptions {
+    pub input_path: PathBuf,
+    /// Compression levels to try, in order
+    pub levels: Vec<i32>,
+    /// Maximum number of bytes read from the input
+    pub sample_size: u64,
+    pub walk: WalkOptions,
+}
+
+impl Default for BenchOptions {
+    fn default() -> Self {
+        Self {
+            input_path: PathBuf::new(),
+            levels: vec![1, 3, 6, 9, 12, 15, 19, 22],
+            sample_size: 64 * 1024 * 1024,
+            walk: WalkOptions::default(),
+        }
+    }
+}
+
+/// Measurements for one codec/level combination
+#[derive(Debug, Clone)]
+pub struct BenchResult {
+    pub codec: &'static str,
+    pub level: i32,
+    pub input_size: u64,
+    pub compressed_size: u64,
+    pub compress_time: Duration,
+    pub decompress_time: Duration,
+    /// Compression context memory in bytes
+    pub memory: usize,
+}
+
+impl BenchResult {
+    /// Compressed size as a percentage of the input
+    pub fn ratio(&self) -> f64 {
+        if self.input_size == 0 {
+            return 100.0;
+        }
+        self.compressed_size as f64 / self.input_size as f64 * 100.0
+    }
+
+    pub fn compress_speed(&self) -> f64 {
+        throughput(self.input_size, self.compress_time)
+    }
+
+    pub fn decompress_speed(&self) -> f64 {
+        throughput(self.input_size, self.decompress_time)
+    }
+}
+
+/// MB/s
+fn throughput(bytes: u64, elapsed: Duration) -> f64 {
+    let seconds = elapsed.as_secs_f64();
+    if seconds == 0.0 {
+        return 0.0;
+    }
+    bytes as f64 / (1024.0 * 1024.0) / seconds
+}
+
+/// Compress a sample of `input_path` once per level and measure each run
+pub fn run_bench(options: &BenchOptions) -> Result<Vec<BenchResult>, CompressionError> {
+    let sample = collect_sample(options)?;
+    let input_size: u64 = sample.iter().map(|file| file.len() as u64).sum();
+    info!(files = sample.len(), bytes = input_size, "Benchmark sample collected");
+
+    options.levels.iter().map(|&level| bench_level(&sample, input_size, level)).collect()
+}
+
+/// Files compressed independently, as in stream mode
+fn bench_level(sample: &[Vec<u8>], input_size: u64, level: i32) -> Result<BenchResult, CompressionError> {
+    let mut cctx = CCtx::create();
+    cctx.set_parameter(CParameter::CompressionLevel(level)).map_err(zstd_error)?;
+
+    let start = Instant::now();
+    let mut compressed = Vec::with_capacity(sample.len());
+    for file in sample {
+        let mut output = Vec::with_capacity(zstd_safe::compress_bound(file.len()));
+        cctx.compress2(&mut output, file).map_err(zstd_error)?;
+        compressed.push(output);
+    }
+    let compress_time = start.elapsed();
+
+    let start = Instant::now();
+    for (file, data) in sample.iter().zip(&compressed) {
+        zstd::bulk::decompress(data, file.len())?;
+    }
+    let decompress_time = start.elapsed();
+
+    let result = BenchResult {
+        codec: "zstd",
+        level,
+        input_size,
+        compressed_size: compressed.iter().map(|data| data.len() as u64).sum(),
+        compress_time,
+        decompress_time,
+        memory: cctx.sizeof(),
+    };
+    info!(level, ratio = result.ratio(), "Level benchmarked");
+    Ok(result)
+}
+
+/// Read files in walk order until `sample_size` bytes are collected
+fn collect_sample(options: &BenchOptions) -> Result<Vec<Vec<u8>>, CompressionError> {
+    let mut sample = Vec::new();
+    let mut remaining = options.sample_size;
+
+    for entry in walk(&options.input_path, &options.walk) {
+        if remaining == 0 {
+            break;
+        }
+        let entry = entry?;
+        if entry.kind != EntryKind::File {
+            continue;
+        }
+
+        let file = File::open(&entry.path).map_err(|e| CompressionError::io_at(e, &entry.path))?;
+        let mut content = Vec::new();
+        file.take(remaining).read_to_end(&mut content)?;
+        remaining -= content.len() as u64;
+        sample.push(content);
+    }
+    Ok(sample)
+}
+
+fn zstd_error(code: usize) -> CompressionError {
+    CompressionError::CompressionFailed(zstd_safe::get_error_name(code).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_bench_levels() {
+        let temp_dir = tempdir().unwrap();
+        std::fs::write(temp_dir.path().join("a.txt"), "répétition ".repeat(5000)).unwrap();
+        std::fs::write(temp_dir.path().join("b.bin"), vec![3u8; 20_000]).unwrap();
+
+        let results = run_bench(&BenchOptions {
+            input_path: temp_dir.path().to_path_buf(),
+            levels: vec![1, 19],
+            sample_size: 30_000,
+            ..Default::default()
+        }).unwrap();
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].level, 1);
+        for result in &results {
+            assert_eq!(result.input_size, 30_000);
+            assert!(result.ratio() < 10.0);
+            assert!(result.memory > 0);
+        }
+    }
+}
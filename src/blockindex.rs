@@ -0,0 +1,216 @@
+//! Lookup of block locations in an image, in memory or on disk for very large images
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use memmap2::Mmap;
+use tempfile::NamedTempFile;
+use tracing::info;
+
+use crate::format::{Codec, CODEC_SIZE};
+use crate::image::BlockHash;
+
+/// Above this many blocks the index is kept on disk (an in-memory entry costs
+/// roughly 80 bytes, so about 640 MB at the threshold)
+pub(crate) const DISK_INDEX_THRESHOLD: u64 = 8_000_000;
+
+/// Records sorted in memory before being written out as a run
+const RUN_RECORDS: usize = 1_000_000;
+
+/// Hash, offset, original size, compressed size, codec
+const RECORD_SIZE: usize = 32 + 8 + 8 + 8 + CODEC_SIZE;
+
+type Record = ([u8; 32], u64, u64, u64, [u8; CODEC_SIZE]);
+
+/// Block hash -> (absolute offset, original size, compressed size, codec)
+pub(crate) enum BlockIndex {
+    Memory(HashMap<BlockHash, (u64, usize, usize, Codec)>),
+    Disk(DiskIndex),
+}
+
+impl BlockIndex {
+    pub(crate) fn get(&self, hash: &BlockHash) -> Option<(u64, usize, usize, Codec)> {
+        match self {
+            BlockIndex::Memory(map) => map.get(hash).copied(),
+            BlockIndex::Disk(index) => index.get(hash),
+        }
+    }
+
+    pub(crate) fn contains(&self, hash: &BlockHash) -> bool {
+        self.get(hash).is_some()
+    }
+}
+
+/// Collects the block table of an image in file order
+pub(crate) struct BlockIndexBuilder {
+    memory: Option<HashMap<BlockHash, (u64, usize, usize, Codec)>>,
+    run: Vec<Record>,
+    runs: Vec<NamedTempFile>,
+}
+
+impl BlockIndexBuilder {
+    /// `block_count` comes from the image header and selects the index kind
+    pub(crate) fn new(block_count: u64, disk_threshold: u64) -> Self {
+        let memory = (block_count <= disk_threshold)
+            .then(|| HashMap::with_capacity(block_count.min(RUN_RECORDS as u64) as usize));
+        Self { memory, run: Vec::new(), runs: Vec::new() }
+    }
+
+    /// `offset` is relative to the start of the block data section
+    pub(crate) fn insert(&mut self, hash: BlockHash, offset: u64, original_size: usize, compressed_size: usize, codec: Codec) -> io::Result<()> {
+        match &mut self.memory {
+            Some(map) => {
+                map.insert(hash, (offset, original_size, compressed_size, codec));
+            }
+            None => {
+                self.run.push((hash.into(), offset, original_size as u64, compressed_size as u64, codec.to_bytes()));
+                if self.run.len() >= RUN_RECORDS {
+                    self.flush_run()?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Finish the index; `data_start` is added to every offset
+    pub(crate) fn finish(mut self, data_start: u64) -> io::Result<BlockIndex> {
+        if let Some(mut map) = self.memory.take() {
+            for (offset, _, _, _) in map.values_mut() {
+                *offset += data_start;
+            }
+            return Ok(BlockIndex::Memory(map));
+        }
+
+        self.flush_run()?;
+        // An empty file cannot be mapped
+        if self.runs.is_empty() {
+            return Ok(BlockIndex::Memory(HashMap::new()));
+        }
+        info!(runs = self.runs.len(), "Merging on-disk block index");
+        let merged = merge_runs(&self.runs)?;
+        // SAFETY: the file is private to this process and no longer written to
+        let map = unsafe { Mmap::map(merged.as_file())? };
+        Ok(BlockIndex::Disk(DiskIndex { _file: merged, map, data_start }))
+    }
+
+    fn flush_run(&mut self) -> io::Result<()> {
+        if self.run.is_empty() {
+            return Ok(());
+        }
+        self.run.sort_unstable_by_key(|record| record.0);
+        let file = NamedTempFile::new()?;
+        let mut writer = BufWriter::new(file.as_file());
+        for record in self.run.drain(..) {
+            write_record(&mut writer, &record)?;
+        }
+        writer.flush()?;
+        drop(writer);
+        self.runs.push(file);
+        Ok(())
+    }
+}
+
+/// Sorted records in a memory-mapped temporary file, searched by bisection
+pub(crate) struct DiskIndex {
+    _file: NamedTempFile,
+    map: Mmap,
+    data_start: u64,
+}
+
+impl DiskIndex {
+    fn get(&self, hash: &BlockHash) -> Option<(u64, usize, usize, Codec)> {
+        let wanted: &[u8; 32] = hash.as_ref();
+        let (mut low, mut high) = (0, self.map.len() / RECORD_SIZE);
+        while low < high {
+            let middle = (low + high) / 2;
+            let record = &self.map[middle * RECORD_SIZE..(middle + 1) * RECORD_SIZE];
+            match record[..32].cmp(&wanted[..]) {
+                std::cmp::Ordering::Less => low = middle + 1,
+                std::cmp::Ordering::Greater => high = middle,
+                std::cmp::Ordering::Equal => {
+                    let field = |i: usize| u64::from_le_bytes(record[32 + i * 8..40 + i * 8].try_into().unwrap());
+                    let codec = Codec::from_bytes(record[56..].try_into().unwrap());
+                    return Some((self.data_start + field(0), field(1) as usize, field(2) as usize, codec));
+                }
+            }
+        }
+        None
+    }
+}
+
+fn write_record(writer: &mut impl Write, record: &Record) -> io::Result<()> {
+    writer.write_all(&record.0)?;
+    writer.write_all(&record.1.to_le_bytes())?;
+    writer.write_all(&record.2.to_le_bytes())?;
+    writer.write_all(&record.3.to_le_bytes())?;
+    writer.write_all(&record.4)
+}
+
+fn read_record(reader: &mut impl Read) -> io::Result<Option<Record>> {
+    let mut bytes = [0u8; RECORD_SIZE];
+    match reader.read_exact(&mut bytes) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let field = |i: usize| u64::from_le_bytes(bytes[32 + i * 8..40 + i * 8].try_into().unwrap());
+    Ok(Some((bytes[..32].try_into().unwrap(), field(0), field(1), field(2), bytes[56..].try_into().unwrap())))
+}
+
+/// K-way merge of sorted run files into a single sorted file
+fn merge_runs(runs: &[NamedTempFile]) -> io::Result<NamedTempFile> {
+    let mut readers = runs
+        .iter()
+        .map(|run| File::open(run.path()).map(BufReader::new))
+        .collect::<io::Result<Vec<_>>>()?;
+    let mut heap = BinaryHeap::new();
+    for (i, reader) in readers.iter_mut().enumerate() {
+        if let Some(record) = read_record(reader)? {
+            heap.push(Reverse((record, i)));
+        }
+    }
+
+    let merged = NamedTempFile::new()?;
+    let mut writer = BufWriter::new(merged.as_file());
+    while let Some(Reverse((record, i))) = heap.pop() {
+        write_record(&mut writer, &record)?;
+        if let Some(next) = read_record(&mut readers[i])? {
+            heap.push(Reverse((next, i)));
+        }
+    }
+    writer.flush()?;
+    drop(writer);
+    Ok(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::calculate_hash;
+
+    #[test]
+    fn test_disk_index_matches_memory() {
+        let hashes: Vec<BlockHash> = (0..2_500u32).map(|i| calculate_hash(&i.to_le_bytes())).collect();
+
+        let mut memory = BlockIndexBuilder::new(hashes.len() as u64, DISK_INDEX_THRESHOLD);
+        let mut disk = BlockIndexBuilder::new(hashes.len() as u64, 0);
+        for (i, hash) in hashes.iter().enumerate() {
+            memory.insert(hash.clone(), i as u64 * 100, 65536, i, Codec::zstd(i as i32 % 20)).unwrap();
+            disk.insert(hash.clone(), i as u64 * 100, 65536, i, Codec::zstd(i as i32 % 20)).unwrap();
+            // Several runs to exercise the merge
+            if i % 1000 == 999 {
+                disk.flush_run().unwrap();
+            }
+        }
+        let memory = memory.finish(48).unwrap();
+        let disk = disk.finish(48).unwrap();
+        assert!(matches!(disk, BlockIndex::Disk(_)));
+
+        for (i, hash) in hashes.iter().enumerate() {
+            assert_eq!(disk.get(hash), Some((48 + i as u64 * 100, 65536, i, Codec::zstd(i as i32 % 20))));
+            assert_eq!(disk.get(hash), memory.get(hash));
+        }
+        assert!(!disk.contains(&calculate_hash(b"absent")));
+    }
+}
@@ -0,0 +1,165 @@
+//! Positioned block reads from an image file, optionally batched through io_uring
+
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use tracing::warn;
+
+/// How image blocks are fetched from disk during extraction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum IoBackend {
+    /// One positioned read per block
+    #[default]
+    Sync,
+    /// Batches of reads submitted together (Linux, built with the `io-uring` feature);
+    /// falls back to `sync` when unavailable
+    IoUring,
+}
+
+/// Reads issued per io_uring submission
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+const RING_DEPTH: u32 = 64;
+
+pub(crate) struct BlockReader {
+    file: File,
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    ring: Option<io_uring::IoUring>,
+}
+
+impl BlockReader {
+    pub(crate) fn open(path: &Path, backend: IoBackend) -> io::Result<Self> {
+        let file = File::open(path)?;
+
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        {
+            let ring = match backend {
+                IoBackend::Sync => None,
+                IoBackend::IoUring => match io_uring::IoUring::new(RING_DEPTH) {
+                    Ok(ring) => Some(ring),
+                    Err(e) => {
+                        warn!(error = %e, "io_uring unavailable, using synchronous reads");
+                        None
+                    }
+                },
+            };
+            Ok(Self { file, ring })
+        }
+
+        #[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+        {
+            if backend == IoBackend::IoUring {
+                warn!("io_uring support not built in, using synchronous reads");
+            }
+            Ok(Self { file })
+        }
+    }
+
+    /// Read each `(offset, length)` range, returning the buffers in request order
+    pub(crate) fn read_batch(&mut self, requests: &[(u64, usize)]) -> io::Result<Vec<Vec<u8>>> {
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        if let Some(ring) = &mut self.ring {
+            return read_batch_uring(ring, &self.file, requests);
+        }
+
+        requests
+            .iter()
+            .map(|&(offset, length)| {
+                let mut buffer = vec![0u8; length];
+                read_exact_at(&self.file, &mut buffer, offset)?;
+                Ok(buffer)
+            })
+            .collect()
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+fn read_batch_uring(ring: &mut io_uring::IoUring, file: &File, requests: &[(u64, usize)]) -> io::Result<Vec<Vec<u8>>> {
+    use io_uring::{opcode, types};
+    use std::os::unix::io::AsRawFd;
+
+    let mut buffers: Vec<Vec<u8>> = requests.iter().map(|&(_, length)| vec![0u8; length]).collect();
+    let fd = types::Fd(file.as_raw_fd());
+
+    for (first, batch) in requests.chunks(RING_DEPTH as usize).enumerate() {
+        let first = first * RING_DEPTH as usize;
+        for (i, &(offset, length)) in batch.iter().enumerate() {
+            let index = first + i;
+            let entry = opcode::Read::new(fd, buffers[index].as_mut_ptr(), length as u32)
+                .offset(offset)
+                .build()
+                .user_data(index as u64);
+            // SAFETY: the buffer outlives the submission, which is waited for below
+            unsafe { ring.submission().push(&entry) }
+                .map_err(|_| io::Error::other("io_uring submission queue full"))?;
+        }
+        ring.submit_and_wait(batch.len())?;
+
+        let completions: Vec<_> = ring.completion().map(|cqe| (cqe.user_data() as usize, cqe.result())).collect();
+        for (index, result) in completions {
+            if result < 0 {
+                return Err(io::Error::from_raw_os_error(-result));
+            }
+            // Short reads are rare; finish them synchronously
+            let done = result as usize;
+            let (offset, length) = requests[index];
+            if done < length {
+                read_exact_at(file, &mut buffers[index][done..], offset + done as u64)?;
+            }
+        }
+    }
+    Ok(buffers)
+}
+
+#[cfg(unix)]
+pub(crate) fn read_exact_at(file: &File, buffer: &mut [u8], offset: u64) -> io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.read_exact_at(buffer, offset)
+}
+
+#[cfg(windows)]
+pub(crate) fn read_exact_at(file: &File, mut buffer: &mut [u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buffer.is_empty() {
+        match file.seek_read(buffer, offset)? {
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            read => {
+                buffer = &mut buffer[read..];
+                offset += read as u64;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(any(unix, windows)))]
+pub(crate) fn read_exact_at(mut file: &File, buffer: &mut [u8], offset: u64) -> io::Result<()> {
+    use std::io::{Read, Seek, SeekFrom};
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_read_batch() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("blocks");
+        let content: Vec<u8> = (0..200_000u32).map(|i| (i % 253) as u8).collect();
+        std::fs::write(&path, &content).unwrap();
+
+        let requests: Vec<(u64, usize)> = (0..150).map(|i| (i * 1000 + 7, 500 + i as usize)).collect();
+        for backend in [IoBackend::Sync, IoBackend::IoUring] {
+            let mut reader = BlockReader::open(&path, backend).unwrap();
+            let buffers = reader.read_batch(&requests).unwrap();
+            for (&(offset, length), buffer) in requests.iter().zip(&buffers) {
+                assert_eq!(&buffer[..], &content[offset as usize..offset as usize + length]);
+            }
+        }
+
+        let mut reader = BlockReader::open(&path, IoBackend::Sync).unwrap();
+        assert!(reader.read_batch(&[(199_990, 100)]).is_err());
+    }
+}
@@ -0,0 +1,115 @@
+//! Bloom filter over block hashes, answering "definitely new" without an index lookup
+
+use std::io::{self, Read, Write};
+
+use crate::image::BlockHash;
+
+/// Target false positive rate when sizing a filter
+pub const DEFAULT_FALSE_POSITIVE_RATE: f64 = 0.01;
+
+/// Upper bound on the serialized filter, to reject corrupt lengths
+const MAX_FILTER_BYTES: u64 = 1 << 32;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    hashes: u32,
+}
+
+impl BloomFilter {
+    /// Filter holding `items` hashes at about `false_positive_rate`
+    pub fn with_capacity(items: u64, false_positive_rate: f64) -> Self {
+        let items = items.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let bits = (-items * false_positive_rate.ln() / (ln2 * ln2)).ceil().max(64.0);
+        let hashes = ((bits / items) * ln2).round().clamp(1.0, 16.0) as u32;
+        Self { bits: vec![0; (bits as usize).div_ceil(64)], hashes }
+    }
+
+    pub fn insert(&mut self, hash: &BlockHash) {
+        for position in positions(hash, self.hashes, self.bits.len() as u64 * 64) {
+            self.bits[(position / 64) as usize] |= 1 << (position % 64);
+        }
+    }
+
+    /// `false` means the hash was never inserted; `true` may be a false positive
+    pub fn may_contain(&self, hash: &BlockHash) -> bool {
+        positions(hash, self.hashes, self.bits.len() as u64 * 64).all(|position| self.bits[(position / 64) as usize] & (1 << (position % 64)) != 0)
+    }
+
+    /// Serialized size in bytes
+    pub fn byte_len(&self) -> u64 {
+        4 + self.bits.len() as u64 * 8
+    }
+
+    pub fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(&self.hashes.to_le_bytes())?;
+        for word in &self.bits {
+            writer.write_all(&word.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Read a filter of `byte_len` bytes written by [`BloomFilter::write_to`]
+    pub fn read_from(reader: &mut impl Read, byte_len: u64) -> io::Result<Self> {
+        if byte_len < 12 || !(byte_len - 4).is_multiple_of(8) || byte_len > MAX_FILTER_BYTES {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid bloom filter length"));
+        }
+        let mut buffer = [0u8; 8];
+        reader.read_exact(&mut buffer[..4])?;
+        let hashes = u32::from_le_bytes(buffer[..4].try_into().unwrap());
+        if hashes == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid bloom filter hash count"));
+        }
+        let mut bits = Vec::with_capacity(((byte_len - 4) / 8) as usize);
+        for _ in 0..(byte_len - 4) / 8 {
+            reader.read_exact(&mut buffer)?;
+            bits.push(u64::from_le_bytes(buffer));
+        }
+        Ok(Self { bits, hashes })
+    }
+}
+
+/// Bit positions of `hash` among `len` bits, by double hashing
+fn positions(hash: &BlockHash, hashes: u32, len: u64) -> impl Iterator<Item = u64> {
+    let bytes: &[u8; 32] = hash.as_ref();
+    let first = bytes
+        .chunks_exact(8)
+        .fold(0u64, |acc, word| mix(acc ^ u64::from_le_bytes(word.try_into().unwrap())));
+    let second = mix(first) | 1;
+    (0..hashes as u64).map(move |i| first.wrapping_add(i.wrapping_mul(second)) % len)
+}
+
+/// SplitMix64 finalizer
+fn mix(mut value: u64) -> u64 {
+    value = value.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    value = (value ^ (value >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    value = (value ^ (value >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    value ^ (value >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::calculate_hash;
+
+    #[test]
+    fn test_bloom_filter() {
+        let mut filter = BloomFilter::with_capacity(10_000, DEFAULT_FALSE_POSITIVE_RATE);
+        for i in 0..10_000u32 {
+            filter.insert(&calculate_hash(&i.to_le_bytes()));
+        }
+        assert!((0..10_000u32).all(|i| filter.may_contain(&calculate_hash(&i.to_le_bytes()))));
+
+        let false_positives = (10_000..20_000u32)
+            .filter(|i| filter.may_contain(&calculate_hash(&i.to_le_bytes())))
+            .count();
+        assert!(false_positives < 300, "{false_positives} false positives");
+
+        let mut bytes = Vec::new();
+        filter.write_to(&mut bytes).unwrap();
+        assert_eq!(bytes.len() as u64, filter.byte_len());
+        assert_eq!(BloomFilter::read_from(&mut bytes.as_slice(), filter.byte_len()).unwrap(), filter);
+        assert!(BloomFilter::read_from(&mut bytes.as_slice(), 7).is_err());
+    }
+}
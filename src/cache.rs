@@ -0,0 +1,264 @@
+//! Local cache of data read from remote backends
+//!
+//! Objects are kept as fixed-size chunks under `<dir>/<url hash>/`, named after
+//! the object size and chunk number so that an object replaced by one of
+//! another size is not served from stale chunks. Reading a chunk refreshes its
+//! modification time; after each read that downloaded something, the least
+//! recently used chunks are removed until the cache fits its cap.
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+use sha2::{Digest, Sha256};
+use tempfile::NamedTempFile;
+use tracing::{debug, warn};
+use walkdir::WalkDir;
+
+use crate::storage::StorageBackend;
+
+/// Objects are cached in chunks of this size, the last one shorter
+pub const CHUNK_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Cap of a cache whose size is not configured
+pub const DEFAULT_MAX_SIZE: u64 = 1024 * 1024 * 1024;
+
+/// Cached chunks of one object
+pub struct ObjectCache {
+    root: PathBuf,
+    max_size: u64,
+    /// Directory of the object's chunks
+    dir: PathBuf,
+    /// Size of the object, looked up on first use
+    size: Mutex<Option<u64>>,
+}
+
+impl ObjectCache {
+    /// Chunks of the object at `url`, in a cache under `root` holding at most `max_size` bytes
+    pub fn new(root: impl Into<PathBuf>, max_size: u64, url: &str) -> Self {
+        let root = root.into();
+        let dir = root.join(hex(&Sha256::digest(url.as_bytes())[..16]));
+        Self { root, max_size, dir, size: Mutex::new(None) }
+    }
+
+    /// Bytes `range` of the object, fewer if it ends first; chunks not cached yet are downloaded
+    pub fn read_range(&self, backend: &dyn StorageBackend, name: &str, range: Range<u64>) -> io::Result<Vec<u8>> {
+        let size = self.size(backend, name)?;
+        let range = range.start.min(size)..range.end.min(size);
+        let mut data = Vec::with_capacity((range.end - range.start) as usize);
+        let mut downloaded = 0;
+        if !range.is_empty() {
+            for index in range.start / CHUNK_SIZE..=(range.end - 1) / CHUNK_SIZE {
+                let (chunk, fetched) = self.chunk(backend, name, size, index)?;
+                downloaded += fetched as u64;
+                let start = index * CHUNK_SIZE;
+                let from = range.start.saturating_sub(start) as usize;
+                let to = ((range.end - start) as usize).min(chunk.len());
+                data.extend_from_slice(&chunk[from..to]);
+            }
+        }
+        if downloaded > 0 {
+            self.evict();
+        }
+        Ok(data)
+    }
+
+    /// Write the whole object to `output` and return its size
+    pub fn copy_to(&self, backend: &dyn StorageBackend, name: &str, output: &mut impl Write) -> io::Result<u64> {
+        let size = self.size(backend, name)?;
+        let chunks = size.div_ceil(CHUNK_SIZE);
+        let mut downloaded = 0;
+        for index in 0..chunks {
+            let (chunk, fetched) = self.chunk(backend, name, size, index)?;
+            downloaded += fetched as u64;
+            output.write_all(&chunk)?;
+        }
+        debug!(object = name, chunks, cached = chunks - downloaded, "Object read through the cache");
+        if downloaded > 0 {
+            self.evict();
+        }
+        Ok(size)
+    }
+
+    /// Forget the object, e.g. once it has been replaced
+    pub fn clear(&self) {
+        let _ = fs::remove_dir_all(&self.dir);
+        *self.size.lock().unwrap_or_else(|e| e.into_inner()) = None;
+    }
+
+    /// Size of the object; chunks of a previous version of another size are removed
+    fn size(&self, backend: &dyn StorageBackend, name: &str) -> io::Result<u64> {
+        let mut size = self.size.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(size) = *size {
+            return Ok(size);
+        }
+        let object_size = backend.size(name)?;
+        let prefix = format!("{}-", object_size);
+        for entry in fs::read_dir(&self.dir).into_iter().flatten().flatten() {
+            if !entry.file_name().to_string_lossy().starts_with(&prefix) {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+        *size = Some(object_size);
+        Ok(object_size)
+    }
+
+    /// Content of a chunk, and whether it had to be downloaded
+    fn chunk(&self, backend: &dyn StorageBackend, name: &str, size: u64, index: u64) -> io::Result<(Vec<u8>, bool)> {
+        let path = self.dir.join(format!("{}-{}", size, index));
+        let range = index * CHUNK_SIZE..((index + 1) * CHUNK_SIZE).min(size);
+        let length = (range.end - range.start) as usize;
+        if let Ok(data) = fs::read(&path) {
+            if data.len() == length {
+                // Most recently used
+                let _ = File::options().write(true).open(&path).and_then(|file| file.set_modified(SystemTime::now()));
+                return Ok((data, false));
+            }
+        }
+        let data = backend.read_range(name, range)?;
+        if data.len() != length {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, format!("{} changed while being read", name)));
+        }
+        // A cache that cannot be written only costs the next read a download
+        if let Err(e) = store(&self.dir, &path, &data) {
+            warn!(path = %path.display(), error = %e, "Failed to cache chunk");
+        }
+        Ok((data, true))
+    }
+
+    /// Remove the least recently used chunks of every object until the cache fits its cap
+    fn evict(&self) {
+        let mut chunks = Vec::new();
+        let mut total = 0;
+        for entry in WalkDir::new(&self.root).min_depth(2).max_depth(2).into_iter().flatten() {
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            if metadata.is_file() {
+                total += metadata.len();
+                chunks.push((metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH), metadata.len(), entry.into_path()));
+            }
+        }
+        if total <= self.max_size {
+            return;
+        }
+        chunks.sort();
+        let mut removed = 0;
+        for (_, length, path) in chunks {
+            if total <= self.max_size {
+                break;
+            }
+            if fs::remove_file(&path).is_ok() {
+                total -= length;
+                removed += 1;
+                // Only succeeds once the object has no chunk left
+                let _ = path.parent().map(fs::remove_dir);
+            }
+        }
+        debug!(removed, size = total, "Cache chunks evicted");
+    }
+}
+
+/// Write a chunk so that readers never see it partially written
+fn store(dir: &Path, path: &Path, data: &[u8]) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let mut staging = NamedTempFile::new_in(dir)?;
+    staging.write_all(data)?;
+    staging.persist(path).map_err(|e| e.error)?;
+    Ok(())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{LocalBackend, ObjectInfo};
+    use std::io::Read;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use tempfile::tempdir;
+
+    /// Local backend counting the bytes read from it
+    struct Counted(LocalBackend, AtomicU64);
+
+    impl StorageBackend for Counted {
+        fn open(&self, name: &str) -> io::Result<Box<dyn Read + Send>> {
+            self.0.open(name)
+        }
+        fn read_range(&self, name: &str, range: Range<u64>) -> io::Result<Vec<u8>> {
+            let data = self.0.read_range(name, range)?;
+            self.1.fetch_add(data.len() as u64, Ordering::Relaxed);
+            Ok(data)
+        }
+        fn write(&self, name: &str, source: &Path) -> io::Result<()> {
+            self.0.write(name, source)
+        }
+        fn list(&self, prefix: &str) -> io::Result<Vec<ObjectInfo>> {
+            self.0.list(prefix)
+        }
+        fn delete(&self, name: &str) -> io::Result<()> {
+            self.0.delete(name)
+        }
+    }
+
+    #[test]
+    fn test_cached_ranges() {
+        let temp_dir = tempdir().unwrap();
+        let content: Vec<u8> = (0..CHUNK_SIZE * 2 + 1000).map(|i| (i % 251) as u8).collect();
+        fs::create_dir(temp_dir.path().join("store")).unwrap();
+        fs::write(temp_dir.path().join("store/image.zpak"), &content).unwrap();
+        let backend = Counted(LocalBackend::new(temp_dir.path().join("store")), AtomicU64::new(0));
+        let downloaded = || backend.1.load(Ordering::Relaxed);
+        let cache_dir = temp_dir.path().join("cache");
+        let cache = || ObjectCache::new(&cache_dir, DEFAULT_MAX_SIZE, "azure://backups/image.zpak");
+
+        // Index at the end of the image, then a block straddling two chunks
+        let end = CHUNK_SIZE * 2 + 1000;
+        assert_eq!(cache().read_range(&backend, "image.zpak", end - 100..end + 50).unwrap(), &content[end as usize - 100..]);
+        assert_eq!(downloaded(), 1000);
+        let straddling = CHUNK_SIZE - 10..CHUNK_SIZE + 10;
+        assert_eq!(cache().read_range(&backend, "image.zpak", straddling.clone()).unwrap(), &content[CHUNK_SIZE as usize - 10..CHUNK_SIZE as usize + 10]);
+        assert_eq!(downloaded(), 1000 + CHUNK_SIZE * 2);
+
+        // Everything is cached now
+        let mut copy = Vec::new();
+        assert_eq!(cache().copy_to(&backend, "image.zpak", &mut copy).unwrap(), content.len() as u64);
+        assert_eq!(copy, content);
+        assert_eq!(downloaded(), 1000 + CHUNK_SIZE * 2);
+
+        // A replaced object of another size is read again
+        fs::write(temp_dir.path().join("store/image.zpak"), b"new").unwrap();
+        assert_eq!(cache().read_range(&backend, "image.zpak", 0..10).unwrap(), b"new");
+        assert_eq!(fs::read_dir(cache().dir).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_eviction() {
+        let temp_dir = tempdir().unwrap();
+        fs::create_dir(temp_dir.path().join("store")).unwrap();
+        for name in ["a", "b", "c"] {
+            fs::write(temp_dir.path().join("store").join(name), vec![0u8; 1000]).unwrap();
+        }
+        let backend = LocalBackend::new(temp_dir.path().join("store"));
+        let cache_dir = temp_dir.path().join("cache");
+        let cache = |name: &str| ObjectCache::new(&cache_dir, 2500, name);
+
+        cache("a").read_range(&backend, "a", 0..1000).unwrap();
+        cache("b").read_range(&backend, "b", 0..1000).unwrap();
+        // `a` is the oldest until read again, which leaves `b` the least recently used
+        for (name, age) in [("a", 120), ("b", 60)] {
+            let chunk = File::options().write(true).open(cache(name).dir.join("1000-0")).unwrap();
+            chunk.set_modified(SystemTime::now() - std::time::Duration::from_secs(age)).unwrap();
+        }
+        cache("a").read_range(&backend, "a", 0..10).unwrap();
+        cache("c").read_range(&backend, "c", 0..1000).unwrap();
+
+        assert!(cache("a").dir.join("1000-0").exists());
+        assert!(!cache("b").dir.exists());
+        assert!(cache("c").dir.join("1000-0").exists());
+    }
+}
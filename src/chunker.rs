@@ -0,0 +1,143 @@
+//! Splitting file contents into deduplication blocks
+
+use fastcdc::v2020::{self, FastCDC, Normalization};
+use serde::{Deserialize, Serialize};
+
+/// How file contents are cut into blocks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ChunkStrategy {
+    /// Blocks of exactly `avg_size` bytes: fastest, but an insertion shifts every following block
+    #[default]
+    Fixed,
+    /// Content-defined boundaries (FastCDC): blocks survive insertions and deletions
+    Cdc,
+}
+
+/// Chunking parameters, recorded in the image header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkerOptions {
+    pub strategy: ChunkStrategy,
+    /// Smallest content-defined block
+    pub min_size: u32,
+    /// Target block size; the exact size of fixed blocks
+    pub avg_size: u32,
+    /// Largest content-defined block
+    pub max_size: u32,
+    /// FastCDC normalization level (0-3): higher levels keep sizes closer to `avg_size`
+    pub normalization: u8,
+}
+
+impl ChunkerOptions {
+    /// Defaults around `avg_size`: a quarter of it to four times it
+    pub fn around(strategy: ChunkStrategy, avg_size: u32) -> Self {
+        Self {
+            strategy,
+            min_size: avg_size / 4,
+            avg_size,
+            max_size: avg_size.saturating_mul(4),
+            normalization: 1,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if !(v2020::AVERAGE_MIN..=v2020::AVERAGE_MAX).contains(&self.avg_size) {
+            return Err(format!(
+                "average chunk size must be between {} and {} bytes",
+                v2020::AVERAGE_MIN,
+                v2020::AVERAGE_MAX
+            ));
+        }
+        if self.strategy == ChunkStrategy::Fixed {
+            return Ok(());
+        }
+        if !(v2020::MINIMUM_MIN..=v2020::MINIMUM_MAX).contains(&self.min_size) {
+            return Err(format!(
+                "minimum chunk size must be between {} and {} bytes",
+                v2020::MINIMUM_MIN,
+                v2020::MINIMUM_MAX
+            ));
+        }
+        if !(v2020::MAXIMUM_MIN..=v2020::MAXIMUM_MAX).contains(&self.max_size) {
+            return Err(format!(
+                "maximum chunk size must be between {} and {} bytes",
+                v2020::MAXIMUM_MIN,
+                v2020::MAXIMUM_MAX
+            ));
+        }
+        if self.min_size > self.avg_size || self.avg_size > self.max_size {
+            return Err("chunk sizes must satisfy min <= avg <= max".to_string());
+        }
+        if self.normalization > 3 {
+            return Err("normalization level must be between 0 and 3".to_string());
+        }
+        Ok(())
+    }
+
+    /// Split `data` into blocks; the options must be valid
+    pub fn chunks<'a>(&self, data: &'a [u8]) -> Box<dyn Iterator<Item = &'a [u8]> + 'a> {
+        match self.strategy {
+            ChunkStrategy::Fixed => Box::new(data.chunks(self.avg_size as usize)),
+            ChunkStrategy::Cdc => {
+                let level = match self.normalization {
+                    0 => Normalization::Level0,
+                    1 => Normalization::Level1,
+                    2 => Normalization::Level2,
+                    _ => Normalization::Level3,
+                };
+                let chunker = FastCDC::with_level(data, self.min_size, self.avg_size, self.max_size, level);
+                Box::new(chunker.map(move |chunk| &data[chunk.offset..chunk.offset + chunk.length]))
+            }
+        }
+    }
+}
+
+impl Default for ChunkerOptions {
+    fn default() -> Self {
+        Self::around(ChunkStrategy::Fixed, 64 * 1024)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cdc_survives_insertions() {
+        let options = ChunkerOptions::around(ChunkStrategy::Cdc, 4096);
+        assert!(options.validate().is_ok());
+
+        let mut state = 7u64;
+        let data: Vec<u8> = (0..200_000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+        let mut shifted = b"inserted".to_vec();
+        shifted.extend_from_slice(&data);
+
+        let original: Vec<&[u8]> = options.chunks(&data).collect();
+        let moved: Vec<&[u8]> = options.chunks(&shifted).collect();
+        assert_eq!(original.concat(), data);
+        assert!(original.iter().all(|chunk| chunk.len() <= options.max_size as usize));
+        // Only the first block differs once boundaries resynchronize
+        let shared = moved.iter().filter(|chunk| original.contains(chunk)).count();
+        assert!(shared + 2 >= original.len(), "{} of {} blocks shared", shared, original.len());
+
+        let fixed = ChunkerOptions::default();
+        assert!(fixed.chunks(&data).all(|chunk| chunk.len() <= 65536));
+    }
+
+    #[test]
+    fn test_chunker_validation() {
+        assert!(ChunkerOptions::default().validate().is_ok());
+        let options = ChunkerOptions { min_size: 8192, avg_size: 4096, ..ChunkerOptions::around(ChunkStrategy::Cdc, 4096) };
+        assert!(options.validate().is_err());
+        let options = ChunkerOptions { normalization: 4, ..ChunkerOptions::around(ChunkStrategy::Cdc, 4096) };
+        assert!(options.validate().is_err());
+        assert!(ChunkerOptions::around(ChunkStrategy::Fixed, 16).validate().is_err());
+    }
+}
@@ -1,341 +1,1051 @@
-use std::collections::HashMap;
-use std::fs;
-use std::io::Write;
-use std::path::{Path, PathBuf};
-use std::sync::Arc;
-use rayon::prelude::*;
-use walkdir::WalkDir;
-use tracing::{info, warn};
-use std::io::Cursor;
-use zstd::encode_all;
-use std::io::Read;
-use anyhow::{Result, Context};
-use zstd::dict::from_samples;
-
-use crate::profile::{detect_profile, CompressionProfile};
-
-use crate::error::CompressionError;
-
-#[derive(Debug)]
-pub struct CompressionOptions {
-    pub input_path: PathBuf,
-    pub output_path: PathBuf,
-    pub threads: usize,
-    pub level: i32,
-    pub solid: bool,
-}
-
-#[derive(Debug, Clone, Copy)]
-enum FileType {
-    Text,
-    Binary,
-    Json,
-    Lua,
-    Python,
-    Other,
-}
-
-fn detect_file_type(path: &Path) -> FileType {
-    if let Some(ext) = path.extension() {
-        match ext.to_str().unwrap_or("").to_lowercase().as_str() {
-            "txt" | "md" | "log" => FileType::Text,
-            "json" => FileType::Json,
-            "lua" => FileType::Lua,
-            "py" => FileType::Python,
-            "bin" | "exe" | "dll" | "so" | "dylib" => FileType::Binary,
-            _ => FileType::Other,
-        }
-    } else {
-        FileType::Other
-    }
-}
-
-pub fn compress_folder(options: &CompressionOptions) -> Result<(), CompressionError> {
-    let start_time = std::time::Instant::now();
-    let mut total_size = 0;
-    let mut compressed_size = 0;
-
-    println!("Démarrage de la compression du dossier : {:?}", options.input_path);
-
-    // Collecter les fichiers et construire les dictionnaires
-    let mut dictionaries: HashMap<CompressionProfile, Vec<u8>> = HashMap::new();
-    let mut files_to_compress = Vec::new();
-
-    for entry in WalkDir::new(&options.input_path) {
-        let entry = entry.map_err(|e| CompressionError::Io(std::io::Error::other(e)))?;
-        if entry.file_type().is_file() {
-            let path = entry.path();
-            let relative_path = path.strip_prefix(&options.input_path)
-                .map_err(|e| CompressionError::Io(std::io::Error::other(e)))?;
-            println!("Fichier trouvé : {:?} (chemin relatif : {:?})", path, relative_path);
-            
-            let profile = detect_profile(path);
-            let file_size = fs::metadata(path)?.len();
-
-            // Ajouter les petits fichiers au dictionnaire
-            if file_size < 1024 * 1024 { // 1MB
-                let content = fs::read(path)?;
-                dictionaries.entry(profile)
-                    .or_insert_with(Vec::new)
-                    .extend(content);
-            }
-
-            files_to_compress.push((path.to_path_buf(), relative_path.to_path_buf(), profile));
-            total_size += file_size;
-        }
-    }
-
-    println!("Nombre de fichiers à compresser : {}", files_to_compress.len());
-
-    let compression_dicts = Arc::new(dictionaries);
-    let results: Vec<Result<(PathBuf, Vec<u8>), CompressionError>> = files_to_compress.par_iter()
-        .map(|(path, relative_path, profile)| {
-            println!("Compressing file: {path:?}");
-            let dict = compression_dicts.get(profile);
-            process_file(path, dict, profile.get_compression_level())
-                .map(|data| (relative_path.clone(), data))
-        })
-        .collect();
-
-    // Écrire les résultats
-    let mut output = fs::File::create(&options.output_path)?;
-    println!("Création de l'archive : {:?}", options.output_path);
-    
-    for result in results {
-        match result {
-            Ok((relative_path, data)) => {
-                // Écrire le chemin relatif
-                let path_str = relative_path.to_string_lossy();
-                println!("Écriture du fichier : {}", path_str);
-                output.write_all(path_str.as_bytes())?;
-                output.write_all(&[0])?; // Séparateur nul
-
-                // Écrire la taille des données compressées
-                let size = data.len() as u64;
-                println!("Taille des données compressées : {} octets", size);
-                output.write_all(&size.to_le_bytes())?;
-
-                // Écrire les données compressées
-                output.write_all(&data)?;
-                compressed_size += data.len() as u64;
-            }
-            Err(e) => warn!("Erreur lors de la compression: {}", e),
-        }
-    }
-
-    let duration = start_time.elapsed();
-    let ratio = (compressed_size as f64 / total_size as f64) * 100.0;
-    println!("Compression terminée en {:.2?}", duration);
-    println!("Taille originale: {} octets", total_size);
-    println!("Taille compressée: {} octets", compressed_size);
-    println!("Ratio de compression: {:.2}%", ratio);
-
-    Ok(())
-}
-
-fn process_file(
-    path: &Path,
-    _dict: Option<&Vec<u8>>,
-    level: i32,
-) -> Result<Vec<u8>, CompressionError> {
-    let content = fs::read(path).map_err(CompressionError::Io)?;
-    let file_type = detect_file_type(path);
-    let processed_content = match file_type {
-        FileType::Text | FileType::Json | FileType::Lua | FileType::Python => {
-            // Prétraitement pour les fichiers texte
-            let text = String::from_utf8_lossy(&content);
-            let processed = text.lines()
-                .map(|line| line.trim_end())
-                .collect::<Vec<&str>>()
-                .join("\n");
-            processed.into_bytes()
-        },
-        FileType::Binary => {
-            // Pas de prétraitement pour les fichiers binaires
-            content
-        },
-        FileType::Other => content,
-    };
-    let compressed = encode_all(Cursor::new(processed_content), level)
-        .map_err(|e| CompressionError::Io(std::io::Error::other(e)))?;
-    Ok(compressed)
-}
-
-// Nouvelle fonction pour générer un dictionnaire global à partir de tous les fichiers
-fn generate_global_dictionary(input_path: &Path) -> Result<Vec<u8>> {
-    let mut samples = Vec::new();
-    const MAX_SAMPLE_SIZE: usize = 64 * 1024; // 64 Ko par fichier
-    const MAX_SAMPLES: usize = 100; // Limite stricte pour zstd
-
-    for (i, entry) in fs::read_dir(input_path)?.enumerate() {
-        if i >= MAX_SAMPLES { break; }
-        let entry = entry?;
-        let path = entry.path();
-        if path.is_file() {
-            let mut file = fs::File::open(&path)?;
-            let mut buffer = vec![0u8; MAX_SAMPLE_SIZE];
-            let bytes_read = file.read(&mut buffer)?;
-            samples.push(buffer[..bytes_read].to_vec());
-        }
-    }
-
-    if samples.len() < 8 {
-        // Pas assez de fichiers pour générer un dictionnaire pertinent
-        Ok(Vec::new())
-    } else {
-        let dict = from_samples(&samples, 64 * 1024)?; // 64KB de dictionnaire
-        Ok(dict)
-    }
-}
-
-pub fn compress_directory(options: &CompressionOptions) -> Result<()> {
-    info!("Démarrage de la compression de {:?}", options.input_path);
-    
-    // Utiliser compress_folder avec gestion d'erreur appropriée
-    if options.solid {
-        // Mode solid : utiliser la compression simple
-        compress_directory_solid(options)
-    } else {
-        // Mode normal : utiliser compress_folder
-        compress_folder(options).map_err(|e| anyhow::anyhow!("Erreur de compression: {}", e))
-    }
-}
-
-fn compress_directory_solid(options: &CompressionOptions) -> Result<()> {
-    info!("Mode solid activé");
-    
-    // Générer le dictionnaire global
-    let dict = generate_global_dictionary(&options.input_path)?;
-    
-    let output_file = fs::File::create(&options.output_path)
-        .context("Impossible de créer le fichier de sortie")?;
-    let mut writer = std::io::BufWriter::new(output_file);
-
-    // Écrire la taille du dictionnaire
-    writer.write_all(&(dict.len() as u64).to_le_bytes())?;
-    // Écrire le dictionnaire
-    writer.write_all(&dict)?;
-
-    // Collecter tous les fichiers
-    let mut all_data = Vec::new();
-    let mut file_index = Vec::new();
-    
-    for entry in WalkDir::new(&options.input_path) {
-        let entry = entry.map_err(|e| anyhow::anyhow!("Erreur walkdir: {}", e))?;
-        if entry.file_type().is_file() {
-            let path = entry.path();
-            let relative_path = path.strip_prefix(&options.input_path)
-                .map_err(|e| anyhow::anyhow!("Erreur chemin: {}", e))?;
-            
-            let content = fs::read(&path)?;
-            let start_offset = all_data.len();
-            all_data.extend(content);
-            let end_offset = all_data.len();
-            
-            file_index.push((relative_path.to_path_buf(), start_offset, end_offset));
-        }
-    }
-
-    // Compression en mode solid avec le niveau et threads spécifiés
-    info!("Compression avec niveau {} et {} threads", options.level, options.threads);
-    let compressed = encode_all(Cursor::new(all_data), options.level)?;
-    writer.write_all(&compressed)?;
-    
-    // Écrire l'index des fichiers
-    writer.write_all(&(file_index.len() as u64).to_le_bytes())?;
-    for (path, start, end) in file_index {
-        let path_str = path.to_string_lossy();
-        writer.write_all(&(path_str.len() as u64).to_le_bytes())?;
-        writer.write_all(path_str.as_bytes())?;
-        writer.write_all(&(start as u64).to_le_bytes())?;
-        writer.write_all(&((end - start) as u64).to_le_bytes())?;
-    }
-
-    info!("Compression terminée avec succès");
-    Ok(())
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::fs;
-    use tempfile::tempdir;
-
-    fn create_test_file(dir: &Path, name: &str, content: &[u8]) -> PathBuf {
-        let path = dir.join(name);
-        fs::write(&path, content).unwrap();
-        path
-    }
-
-    #[test]
-    fn test_compression() {
-        let temp_dir = tempdir().unwrap();
-        let input_dir = temp_dir.path().join("input");
-        let output_dir = temp_dir.path().join("output");
-        fs::create_dir(&input_dir).unwrap();
-        fs::create_dir(&output_dir).unwrap();
-
-        // Créer des fichiers de test
-        let text_content = "Ceci est un fichier texte de test avec beaucoup de répétitions. ".repeat(1000);
-        create_test_file(&input_dir, "test.txt", text_content.as_bytes());
-
-        let binary_content = vec![0u8; 1024 * 1024]; // 1MB de zéros
-        create_test_file(&input_dir, "test.bin", &binary_content);
-
-        let options = CompressionOptions {
-            input_path: input_dir,
-            output_path: output_dir.join("test.zpp"),
-            threads: 2,
-            level: 22,
-            solid: false,
-        };
-
-        // Tester la compression
-        compress_folder(&options).unwrap();
-
-        // Vérifier que le fichier de sortie existe
-        assert!(output_dir.join("test.zpp").exists());
-
-        // Nettoyer
-        temp_dir.close().unwrap();
-    }
-
-    #[test]
-    fn test_compression_profiles() {
-        let temp_dir = tempdir().unwrap();
-        let input_dir = temp_dir.path().join("input");
-        let output_dir = temp_dir.path().join("output");
-        fs::create_dir(&input_dir).unwrap();
-        fs::create_dir(&output_dir).unwrap();
-
-        // Créer des fichiers pour chaque profil
-        let text_content = "Fichier texte de test".repeat(100);
-        create_test_file(&input_dir, "text.txt", text_content.as_bytes());
-
-        let binary_content = vec![0u8; 1024 * 10]; // 10KB de zéros
-        create_test_file(&input_dir, "binary.bin", &binary_content);
-
-        let image_content = vec![0u8; 1024 * 100]; // 100KB de données simulées d'image
-        create_test_file(&input_dir, "image.jpg", &image_content);
-
-        let unity_content = "Unity asset test data".repeat(100);
-        create_test_file(&input_dir, "test.unity", unity_content.as_bytes());
-
-        let options = CompressionOptions {
-            input_path: input_dir,
-            output_path: output_dir.join("test.zpp"),
-            threads: 2,
-            level: 22,
-            solid: false,
-        };
-
-        // Tester la compression
-        compress_folder(&options).unwrap();
-
-        // Vérifier que le fichier de sortie existe
-        assert!(output_dir.join("test.zpp").exists());
-
-        // Nettoyer
-        temp_dir.close().unwrap();
-    }
-}
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Cursor, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::{debug, debug_span, field, info, info_span};
+use std::io::Read;
+use zstd::dict::from_samples;
+
+use crate::frames::{compress_frames, write_frame_table, DEFAULT_FRAME_SIZE};
+use crate::format::{native_path_encoding, write_path, zstd_encoder, Codec, CODEC_STORED, DEFAULT_STORE_THRESHOLD, MODE_FILE, MODE_SOLID, MODE_STREAM, PREPROCESS_TRIM_LINES, ZPP_MAGIC, ZPP_VERSION};
+use crate::pipeline::{run_pipeline, BufferPool, PipelineOptions, MAX_POOLED_CAPACITY};
+use crate::incremental::{FileState, PendingSnapshot, SnapshotState};
+use crate::report::{Report, WarningKind};
+use crate::spill::{SpillBuffer, SpillOptions};
+use crate::storage::MirrorWriter;
+use crate::profile::{detect_profile, CompressionProfile};
+use crate::walk::{walk, EntryKind, WalkError, WalkOptions};
+
+use crate::error::{CompressionError, PathIoError};
+
+#[derive(Debug)]
+pub struct CompressionOptions {
+    pub input_path: PathBuf,
+    pub output_path: PathBuf,
+    pub threads: usize,
+    pub level: i32,
+    pub solid: bool,
+    /// Original bytes per independent zstd frame of solid data
+    pub solid_frame_size: u64,
+    /// Entries smaller than this many bytes are stored uncompressed (stream mode)
+    pub store_threshold: u64,
+    pub walk: WalkOptions,
+    /// Second file the archive is written to at the same time, e.g. the staged copy of an offsite mirror
+    pub mirror_path: Option<PathBuf>,
+    /// Write the archive around the page cache (O_DIRECT on Linux)
+    pub direct_io: bool,
+    /// Where solid mode stages its data once it no longer fits in memory
+    pub spill: SpillOptions,
+    /// Snapshot state file: only files new or changed since the recorded state are archived
+    pub listed_incremental: Option<PathBuf>,
+    /// Skip unreadable files and record them in the report instead of aborting
+    pub skip_errors: bool,
+    /// Compress every file at its profile's level, instead of adapting the level
+    /// to a fast probe of its first block (stream mode)
+    pub fixed_level: bool,
+    pub pipeline: PipelineOptions,
+}
+
+impl Default for CompressionOptions {
+    fn default() -> Self {
+        Self {
+            input_path: PathBuf::new(),
+            output_path: PathBuf::new(),
+            threads: num_cpus::get(),
+            level: 22,
+            solid: false,
+            solid_frame_size: DEFAULT_FRAME_SIZE,
+            store_threshold: DEFAULT_STORE_THRESHOLD,
+            walk: WalkOptions::default(),
+            mirror_path: None,
+            direct_io: false,
+            spill: SpillOptions::default(),
+            listed_incremental: None,
+            skip_errors: false,
+            fixed_level: false,
+            pipeline: PipelineOptions::default(),
+        }
+    }
+}
+
+/// Options of `compress-file`: one file compressed as a stream, never loaded in memory
+#[derive(Debug)]
+pub struct FileCompressionOptions {
+    pub input_path: PathBuf,
+    pub output_path: PathBuf,
+    /// Compression level; the level of the file's profile when not set
+    pub level: Option<i32>,
+    pub threads: usize,
+    /// Files smaller than this many bytes are stored uncompressed
+    pub store_threshold: u64,
+}
+
+impl Default for FileCompressionOptions {
+    fn default() -> Self {
+        Self {
+            input_path: PathBuf::new(),
+            output_path: PathBuf::new(),
+            level: None,
+            threads: num_cpus::get(),
+            store_threshold: DEFAULT_STORE_THRESHOLD,
+        }
+    }
+}
+
+/// Taille à partir de laquelle un fichier est compressé en flux plutôt que lu
+/// en entier (mode stream)
+const STREAM_MIN_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Octets compressés pour sonder un fichier réputé déjà compressé
+const PROBE_SIZE: usize = 64 * 1024;
+
+/// Longueur au-delà de laquelle une ligne d'un fichier texte lu en flux est
+/// recopiée telle quelle plutôt que gardée en mémoire pour être retouchée
+const MAX_TRIMMED_LINE: usize = 1024 * 1024;
+
+/// Gain minimal de la sonde, en pourcentage, pour compresser quand même
+pub(crate) const PROBE_MIN_SAVING: usize = 3;
+
+/// En dessous de ce gain de la sonde, le fichier est compressé au niveau 1 : un
+/// niveau élevé coûte beaucoup de temps pour quelques octets
+const PROBE_LOW_SAVING: usize = 15;
+
+/// Au-delà de ce gain de la sonde, le niveau est relevé : des données aussi
+/// redondantes se compressent vite, même à un niveau élevé
+const PROBE_HIGH_SAVING: usize = 90;
+
+/// Niveaux ajoutés à celui du profil pour les fichiers très redondants, sans
+/// dépasser `PROBE_MAX_LEVEL` (les niveaux suivants sont bien plus lents)
+const PROBE_LEVEL_BOOST: i32 = 3;
+const PROBE_MAX_LEVEL: i32 = 19;
+
+#[derive(Debug, Clone, Copy)]
+enum FileType {
+    Text,
+    Binary,
+    Json,
+    Lua,
+    Python,
+    Other,
+}
+
+pub(crate) fn write_archive_header(output: &mut impl Write, mode: u8) -> std::io::Result<()> {
+    output.write_all(&ZPP_MAGIC)?;
+    output.write_all(&ZPP_VERSION.to_le_bytes())?;
+    output.write_all(&[mode, native_path_encoding()])
+}
+
+/// État d'une exécution en mode incrémental
+struct Incremental {
+    /// État précédent, pour filtrer
+    previous: SnapshotState,
+    /// Nouvel état, à enregistrer
+    current: SnapshotState,
+    /// Fichiers parcourus, archivés ou non
+    seen: HashSet<PathBuf>,
+    /// Dossiers illisibles : leurs fichiers ne sont pas supprimés pour autant
+    unreadable: Vec<PathBuf>,
+}
+
+/// Snapshot précédent (pour filtrer) et nouveau snapshot (à enregistrer) en mode incrémental
+fn load_snapshot(options: &CompressionOptions) -> Result<Option<Incremental>, CompressionError> {
+    match &options.listed_incremental {
+        Some(path) => {
+            let previous = SnapshotState::load_or_default(path)?;
+            info!("Mode incrémental : {} fichiers dans l'état précédent", previous.len());
+            Ok(Some(Incremental { previous, current: SnapshotState::default(), seen: HashSet::new(), unreadable: Vec::new() }))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Enregistre le fichier dans le nouveau snapshot et indique s'il doit être archivé
+fn track_incremental(
+    snapshot: &mut Option<Incremental>,
+    relative_path: &Path,
+    metadata: &fs::Metadata,
+) -> bool {
+    match snapshot {
+        Some(incremental) => {
+            let state = FileState::from_metadata(metadata);
+            let changed = incremental.previous.has_changed(relative_path, &state);
+            incremental.current.record(relative_path.to_path_buf(), state);
+            incremental.seen.insert(relative_path.to_path_buf());
+            changed
+        }
+        None => true,
+    }
+}
+
+/// Note une entrée que le parcours n'a pas pu lire
+fn track_walk_error(snapshot: &mut Option<Incremental>, options: &CompressionOptions, error: &WalkError) {
+    if let Some(incremental) = snapshot {
+        if let Ok(relative_path) = error.path.strip_prefix(&options.input_path) {
+            incremental.unreadable.push(relative_path.to_path_buf());
+        }
+    }
+}
+
+/// Retire du nouveau snapshot un fichier qui n'a pas pu être archivé ; il
+/// reste parcouru, donc pas supprimé
+fn untrack_incremental(snapshot: &mut Option<Incremental>, relative_path: &Path) {
+    if let Some(incremental) = snapshot {
+        incremental.current.forget(relative_path);
+    }
+}
+
+/// Fichiers de l'état précédent absents de ce parcours, triés
+fn deleted_paths(snapshot: &Option<Incremental>) -> Vec<PathBuf> {
+    let Some(incremental) = snapshot else {
+        return Vec::new();
+    };
+    let mut deleted: Vec<PathBuf> = incremental.previous.paths()
+        .filter(|path| !incremental.seen.contains(*path))
+        .filter(|path| !incremental.unreadable.iter().any(|dir| path.starts_with(dir)))
+        .map(Path::to_path_buf)
+        .collect();
+    deleted.sort();
+    if !deleted.is_empty() {
+        info!("Mode incrémental : {} fichiers supprimés depuis l'état précédent", deleted.len());
+    }
+    deleted
+}
+
+/// Nouvel état à enregistrer une fois l'archive conservée
+fn pending_snapshot(options: &CompressionOptions, snapshot: Option<Incremental>) -> Option<PendingSnapshot> {
+    match (&options.listed_incremental, snapshot) {
+        (Some(path), Some(Incremental { current, .. })) => Some(PendingSnapshot::new(path.clone(), current)),
+        _ => None,
+    }
+}
+
+fn detect_file_type(path: &Path) -> FileType {
+    if let Some(ext) = path.extension() {
+        match ext.to_str().unwrap_or("").to_lowercase().as_str() {
+            "txt" | "md" | "log" => FileType::Text,
+            "json" => FileType::Json,
+            "lua" => FileType::Lua,
+            "py" => FileType::Python,
+            "bin" | "exe" | "dll" | "so" | "dylib" => FileType::Binary,
+            _ => FileType::Other,
+        }
+    } else {
+        FileType::Other
+    }
+}
+
+pub fn compress_folder(options: &CompressionOptions) -> Result<Report, CompressionError> {
+    let (report, snapshot) = compress_streams(options)?;
+    if let Some(snapshot) = snapshot {
+        snapshot.commit()?;
+    }
+    Ok(report)
+}
+
+fn compress_streams(options: &CompressionOptions) -> Result<(Report, Option<PendingSnapshot>), CompressionError> {
+    let _span = info_span!("compress", input = %options.input_path.display(), solid = false).entered();
+    let start_time = std::time::Instant::now();
+    let mut report = Report::default();
+    let mut total_size = 0;
+    let mut compressed_size = 0;
+    let mut file_count = 0;
+
+    println!("Démarrage de la compression du dossier : {:?}", options.input_path);
+
+    // Le parcours alimente directement le pipeline : la compression commence
+    // avant la fin du parcours et aucune liste complète n'est construite
+    let snapshot = Mutex::new(load_snapshot(options)?);
+    let entries = walk(&options.input_path, &options.walk).filter(|entry| match entry {
+        Ok(entry) if entry.kind == EntryKind::File => {
+            let mut snapshot = snapshot.lock().unwrap_or_else(|e| e.into_inner());
+            track_incremental(&mut snapshot, &entry.relative_path, &entry.metadata)
+        }
+        Ok(entry) => entry.kind != EntryKind::Directory,
+        Err(e) => {
+            track_walk_error(&mut snapshot.lock().unwrap_or_else(|e| e.into_inner()), options, e);
+            true
+        }
+    });
+
+    // Écrire les résultats au fil de l'eau : lecture, compression et écriture se recouvrent
+    let mut output = std::io::BufWriter::new(MirrorWriter::create::<CompressionError>(&options.output_path, options.mirror_path.as_deref(), options.direct_io)?);
+    println!("Création de l'archive : {:?}", options.output_path);
+    write_archive_header(&mut output, MODE_STREAM)?;
+    
+    // Contenu lu et données compressées de chaque fichier en cours
+    let buffers = BufferPool::new(options.pipeline.queue_depth * 2, MAX_POOLED_CAPACITY);
+    // Chaque worker de compression a sa part des threads zstd
+    let encoder_threads = (options.threads / options.pipeline.compress_threads.max(1)).max(1);
+    // Les gros fichiers en cours se partagent la limite mémoire
+    let stream_spill = SpillOptions {
+        memory_limit: options.spill.memory_limit / options.pipeline.queue_depth.max(1) as u64,
+        ..options.spill.clone()
+    };
+    run_pipeline(
+        &options.pipeline,
+        entries,
+        |entry| {
+            let content = match &entry {
+                // Lu en flux à l'étape de compression
+                Ok(file) if file.kind == EntryKind::File && file.metadata.len() >= STREAM_MIN_SIZE => Some(Ok(None)),
+                Ok(file) if file.kind == EntryKind::File => {
+                    println!("Fichier trouvé : {:?} (chemin relatif : {:?})", file.path, file.relative_path);
+                    let span = debug_span!("read", path = %file.relative_path.display(), size = field::Empty).entered();
+                    let content = read_into(&file.path, buffers.take()).map_err(|e| CompressionError::io_at(e, &file.path));
+                    if let Ok(content) = &content {
+                        span.record("size", content.len());
+                    }
+                    Some(content.map(Some))
+                }
+                _ => None,
+            };
+            (entry, content)
+        },
+        |(entry, content)| {
+            let result = match (&entry, content) {
+                (Ok(file), Some(content)) => {
+                    let _span = debug_span!("compress_file", path = %file.relative_path.display(), size = file.metadata.len()).entered();
+                    println!("Compressing file: {:?}", file.path);
+                    let profile = detect_profile(&file.path);
+                    Some(content.and_then(|content| match content {
+                        Some(content) => process_file(&file.path, content, profile, options.store_threshold, !options.fixed_level, encoder_threads, &buffers)
+                            .map(|(codec, data)| (codec, EntryData::Buffer(data))),
+                        None => stream_file(&file.path, profile, options.store_threshold, !options.fixed_level, encoder_threads, &stream_spill)
+                            .map(|(codec, data)| (codec, EntryData::Spilled(data))),
+                    }))
+                }
+                _ => None,
+            };
+            (entry, result)
+        },
+        |(entry, result)| {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    let path = e.path.clone();
+                    return report.skip_or_fail(options.skip_errors, &path, CompressionError::from(e));
+                }
+            };
+            let Some(result) = result else {
+                // Le format .zpp ne stocke que des fichiers réguliers
+                report.warn(&entry.path, WarningKind::Ignored, format!("Entrée ignorée ({:?})", entry.kind));
+                return Ok(());
+            };
+            let relative_path = entry.relative_path;
+            let _span = debug_span!("write", path = %relative_path.display()).entered();
+            match result {
+                Ok((codec, data)) => {
+                    // Écrire le chemin relatif puis le codec de l'entrée
+                    println!("Écriture du fichier : {:?}", relative_path);
+                    write_path(&mut output, &relative_path)?;
+                    codec.write_to(&mut output)?;
+
+                    // Écrire la taille des données compressées
+                    let size = data.len();
+                    println!("Taille des données compressées : {} octets", size);
+                    output.write_all(&size.to_le_bytes())?;
+
+                    // Écrire les données compressées
+                    match data {
+                        EntryData::Buffer(data) => {
+                            output.write_all(&data)?;
+                            buffers.give(data);
+                        }
+                        EntryData::Spilled(data) => {
+                            std::io::copy(&mut data.into_reader()?, &mut output)?;
+                        }
+                    }
+                    compressed_size += size;
+                    total_size += entry.metadata.len();
+                    file_count += 1;
+                }
+                Err(e) => {
+                    report.skip_or_fail(options.skip_errors, &entry.path, e)?;
+                    untrack_incremental(&mut snapshot.lock().unwrap_or_else(|e| e.into_inner()), &relative_path);
+                }
+            }
+            Ok::<_, CompressionError>(())
+        },
+    )?;
+    // Les fichiers supprimés depuis le niveau précédent, sans données
+    let snapshot = snapshot.into_inner().unwrap_or_else(|e| e.into_inner());
+    for path in deleted_paths(&snapshot) {
+        write_path(&mut output, &path)?;
+        Codec::DELETED.write_to(&mut output)?;
+        output.write_all(&0u64.to_le_bytes())?;
+    }
+    output.flush()?;
+    let snapshot = pending_snapshot(options, snapshot);
+
+    let duration = start_time.elapsed();
+    let ratio = (compressed_size as f64 / total_size as f64) * 100.0;
+    println!("Nombre de fichiers compressés : {}", file_count);
+    println!("Compression terminée en {:.2?}", duration);
+    println!("Taille originale: {} octets", total_size);
+    println!("Taille compressée: {} octets", compressed_size);
+    println!("Ratio de compression: {:.2}%", ratio);
+
+    Ok((report, snapshot))
+}
+
+/// Données compressées d'une entrée, prêtes à écrire
+enum EntryData {
+    /// Fichier lu en entier, dans un tampon du pool
+    Buffer(Vec<u8>),
+    /// Fichier compressé en flux
+    Spilled(SpillBuffer),
+}
+
+impl EntryData {
+    fn len(&self) -> u64 {
+        match self {
+            EntryData::Buffer(data) => data.len() as u64,
+            EntryData::Spilled(data) => data.len(),
+        }
+    }
+}
+
+/// Contenu d'un fichier lu dans `buffer`, repris du pool
+fn read_into(path: &Path, mut buffer: Vec<u8>) -> std::io::Result<Vec<u8>> {
+    File::open(path)?.read_to_end(&mut buffer)?;
+    Ok(buffer)
+}
+
+/// Données à écrire pour un fichier : compressées sur `threads` threads dans un
+/// tampon du pool, qui reprend le contenu ; stockées, le contenu lui-même
+fn process_file(
+    path: &Path,
+    content: Vec<u8>,
+    profile: CompressionProfile,
+    store_threshold: u64,
+    adaptive: bool,
+    threads: usize,
+    buffers: &BufferPool,
+) -> Result<(Codec, Vec<u8>), CompressionError> {
+    let Some(level) = choose_level(path, content.len() as u64, &content, profile, store_threshold, adaptive)? else {
+        return Ok((Codec::STORED, content));
+    };
+    let mut codec = Codec::zstd(level);
+    let processed_content = match detect_file_type(path) {
+        FileType::Text | FileType::Json | FileType::Lua | FileType::Python => {
+            // Prétraitement pour les fichiers texte
+            let processed = String::from_utf8_lossy(&content).lines()
+                .map(|line| line.trim_end())
+                .collect::<Vec<&str>>()
+                .join("\n");
+            codec = codec.with_preprocessing(PREPROCESS_TRIM_LINES);
+            buffers.give(content);
+            processed.into_bytes()
+        },
+        FileType::Binary => {
+            // Pas de prétraitement pour les fichiers binaires
+            content
+        },
+        FileType::Other => content,
+    };
+    let mut encoder = zstd_encoder(buffers.take(), level, threads)?;
+    encoder.write_all(&processed_content)?;
+    let compressed = encoder.finish()?;
+    buffers.give(processed_content);
+    Ok((codec, compressed))
+}
+
+/// Niveau de compression d'un fichier de `size` octets d'après le début de son
+/// contenu, `None` pour le stocker tel quel
+fn choose_level(
+    path: &Path,
+    size: u64,
+    content: &[u8],
+    profile: CompressionProfile,
+    store_threshold: u64,
+    adaptive: bool,
+) -> std::io::Result<Option<i32>> {
+    // Trop petit pour que la compression soit rentable
+    if size < store_threshold {
+        return Ok(None);
+    }
+    let level = profile.get_compression_level();
+    if adaptive {
+        // Niveau adapté à la sonde ; les fichiers qui ne gagnent presque rien sont stockés
+        let Some(probed) = probe_level(content, level)? else {
+            debug!(path = %path.display(), "Stored without compression");
+            return Ok(None);
+        };
+        if probed != level {
+            debug!(path = %path.display(), from = level, to = probed, "Level adapted to the probe");
+        }
+        return Ok(Some(probed));
+    }
+    if profile == CompressionProfile::AlreadyCompressed && !worth_compressing(content)? {
+        // Fichiers déjà compressés (jpg, mp4, zip...) : stockés tels quels sauf si la sonde trouve un gain
+        debug!(path = %path.display(), "Stored without compression");
+        return Ok(None);
+    }
+    Ok(Some(level))
+}
+
+/// Comme `process_file` pour un fichier d'au moins `STREAM_MIN_SIZE` octets, lu
+/// en flux : seul son début est gardé pour le seuil de stockage et la sonde, et
+/// les données compressées passent sur disque au-delà de `spill.memory_limit`
+fn stream_file(
+    path: &Path,
+    profile: CompressionProfile,
+    store_threshold: u64,
+    adaptive: bool,
+    threads: usize,
+    spill: &SpillOptions,
+) -> Result<(Codec, SpillBuffer), CompressionError> {
+    let file = File::open(path).map_err(|e| CompressionError::io_at(e, path))?;
+    let size = file.metadata()?.len();
+    let mut reader = BufReader::new(file);
+    let mut probe = Vec::with_capacity(PROBE_SIZE);
+    (&mut reader).take(PROBE_SIZE as u64).read_to_end(&mut probe)?;
+    let level = choose_level(path, size, &probe, profile, store_threshold, adaptive)?;
+    // La sonde est relue avant la suite du fichier
+    let mut input = Cursor::new(probe).chain(reader);
+    let mut output = SpillBuffer::new(spill);
+    let Some(level) = level else {
+        std::io::copy(&mut input, &mut output)?;
+        return Ok((Codec::STORED, output));
+    };
+    let mut codec = Codec::zstd(level);
+    let mut encoder = zstd_encoder(output, level, threads)?;
+    match detect_file_type(path) {
+        FileType::Text | FileType::Json | FileType::Lua | FileType::Python => {
+            trim_lines(input, &mut encoder)?;
+            codec = codec.with_preprocessing(PREPROCESS_TRIM_LINES);
+        }
+        FileType::Binary | FileType::Other => {
+            std::io::copy(&mut input, &mut encoder)?;
+        }
+    }
+    Ok((codec, encoder.finish()?))
+}
+
+/// Prétraitement des fichiers texte ligne par ligne, identique à celui de
+/// `process_file` : espaces de fin de ligne retirés, lignes jointes par `\n`.
+/// Une ligne de plus de `MAX_TRIMMED_LINE` octets est recopiée telle quelle par
+/// morceaux, pour qu'un fichier sans saut de ligne ne soit pas lu en entier.
+fn trim_lines(mut input: impl BufRead, output: &mut impl Write) -> std::io::Result<()> {
+    let mut line = Vec::new();
+    let mut first = true;
+    while (&mut input).take(MAX_TRIMMED_LINE as u64).read_until(b'\n', &mut line)? > 0 {
+        if !first {
+            output.write_all(b"\n")?;
+        }
+        first = false;
+        if line.len() == MAX_TRIMMED_LINE && line.last() != Some(&b'\n') {
+            output.write_all(&line)?;
+            copy_line_rest(&mut input, output)?;
+        } else {
+            output.write_all(String::from_utf8_lossy(&line).trim_end().as_bytes())?;
+        }
+        line.clear();
+    }
+    Ok(())
+}
+
+/// Recopie telle quelle la suite de la ligne en cours ; son saut de ligne est lu
+/// sans être écrit
+fn copy_line_rest(input: &mut impl BufRead, output: &mut impl Write) -> std::io::Result<()> {
+    loop {
+        let buffer = input.fill_buf()?;
+        if buffer.is_empty() {
+            return Ok(());
+        }
+        if let Some(end) = buffer.iter().position(|&byte| byte == b'\n') {
+            output.write_all(&buffer[..end])?;
+            input.consume(end + 1);
+            return Ok(());
+        }
+        let length = buffer.len();
+        output.write_all(buffer)?;
+        input.consume(length);
+    }
+}
+
+/// Sonde rapide : le début du fichier compressé au niveau 1 gagne-t-il au moins `PROBE_MIN_SAVING` % ?
+fn worth_compressing(content: &[u8]) -> std::io::Result<bool> {
+    let sample = &content[..content.len().min(PROBE_SIZE)];
+    let compressed = zstd::bulk::compress(sample, 1)?;
+    Ok(compressed.len() * 100 < sample.len() * (100 - PROBE_MIN_SAVING))
+}
+
+/// Niveau d'après une sonde au niveau 1 du début du fichier : `None` (stocker) si
+/// elle gagne moins de `PROBE_MIN_SAVING` %, le niveau 1 sous `PROBE_LOW_SAVING` %,
+/// un niveau relevé au-delà de `PROBE_HIGH_SAVING` %, sinon celui du profil
+fn probe_level(content: &[u8], level: i32) -> std::io::Result<Option<i32>> {
+    let sample = &content[..content.len().min(PROBE_SIZE)];
+    let compressed = zstd::bulk::compress(sample, 1)?.len() * 100;
+    let saved_under = |saving: usize| compressed >= sample.len() * (100 - saving);
+    Ok(if saved_under(PROBE_MIN_SAVING) {
+        None
+    } else if saved_under(PROBE_LOW_SAVING) {
+        Some(level.min(1))
+    } else if !saved_under(PROBE_HIGH_SAVING) {
+        Some((level + PROBE_LEVEL_BOOST).min(PROBE_MAX_LEVEL).max(level))
+    } else {
+        Some(level)
+    })
+}
+
+// Nouvelle fonction pour générer un dictionnaire global à partir de tous les fichiers
+fn generate_global_dictionary(input_path: &Path) -> Result<Vec<u8>, CompressionError> {
+    let mut samples = Vec::new();
+    const MAX_SAMPLE_SIZE: usize = 64 * 1024; // 64 Ko par fichier
+    const MAX_SAMPLES: usize = 100; // Limite stricte pour zstd
+
+    for (i, entry) in fs::read_dir(input_path)?.enumerate() {
+        if i >= MAX_SAMPLES { break; }
+        let entry = entry?;
+        let path = entry.path();
+        if path.is_file() {
+            let mut file = fs::File::open(&path)?;
+            let mut buffer = vec![0u8; MAX_SAMPLE_SIZE];
+            let bytes_read = file.read(&mut buffer)?;
+            samples.push(buffer[..bytes_read].to_vec());
+        }
+    }
+
+    if samples.len() < 8 {
+        // Pas assez de fichiers pour générer un dictionnaire pertinent
+        Ok(Vec::new())
+    } else {
+        let dict = from_samples(&samples, 64 * 1024) // 64KB de dictionnaire
+            .map_err(|e| CompressionError::DictionaryError(e.to_string()))?;
+        Ok(dict)
+    }
+}
+
+/// Compresse un seul fichier en archive .zpp (mode fichier), choisissant le codec
+/// comme pour les entrées d'une archive mais sans prétraitement : le contenu est
+/// restitué à l'octet près. Renvoie la taille de l'archive.
+pub fn compress_file(options: &FileCompressionOptions) -> Result<u64, CompressionError> {
+    let _span = info_span!("compress_file", input = %options.input_path.display()).entered();
+    let input_path = &options.input_path;
+    let mut input = File::open(input_path).map_err(|e| CompressionError::io_at(e, input_path))?;
+    let size = input.metadata()?.len();
+    let name = input_path.file_name().map(Path::new).ok_or(CompressionError::InvalidFormat)?;
+
+    let profile = detect_profile(input_path);
+    let codec = if size < options.store_threshold {
+        Codec::STORED
+    } else if profile == CompressionProfile::AlreadyCompressed {
+        // Seul le début du fichier est sondé, puis relu avec le reste
+        let mut probe = Vec::with_capacity(PROBE_SIZE);
+        (&mut input).take(PROBE_SIZE as u64).read_to_end(&mut probe)?;
+        input.rewind()?;
+        if worth_compressing(&probe)? { Codec::zstd(options.level.unwrap_or(profile.get_compression_level())) } else { Codec::STORED }
+    } else {
+        Codec::zstd(options.level.unwrap_or(profile.get_compression_level()))
+    };
+
+    let output_file = File::create(&options.output_path).map_err(|e| CompressionError::io_at(e, &options.output_path))?;
+    let mut output = BufWriter::new(output_file);
+    write_archive_header(&mut output, MODE_FILE)?;
+    write_path(&mut output, name)?;
+    codec.write_to(&mut output)?;
+    output.write_all(&size.to_le_bytes())?;
+    let copied = if codec.id == CODEC_STORED {
+        std::io::copy(&mut input, &mut output)?
+    } else {
+        let mut encoder = zstd_encoder(&mut output, codec.level.into(), options.threads)?;
+        encoder.set_pledged_src_size(Some(size))?;
+        let copied = std::io::copy(&mut input, &mut encoder)?;
+        encoder.finish()?;
+        copied
+    };
+    if copied != size {
+        return Err(CompressionError::CompressionFailed(format!("{} a changé pendant la compression", input_path.display())));
+    }
+    let output_file = output.into_inner().map_err(|e| e.into_error())?;
+    let compressed_size = output_file.metadata()?.len();
+    info!(size, compressed = compressed_size, %codec, "Fichier compressé");
+    Ok(compressed_size)
+}
+
+pub fn compress_directory(options: &CompressionOptions) -> Result<Report, CompressionError> {
+    let (report, snapshot) = compress_directory_pending(options)?;
+    if let Some(snapshot) = snapshot {
+        snapshot.commit()?;
+    }
+    Ok(report)
+}
+
+/// Comme `compress_directory`, mais l'état `--listed-incremental` est rendu au lieu
+/// d'être enregistré : l'appelant le valide une fois l'archive envoyée ou synchronisée,
+/// sans quoi un échec à ce moment ferait manquer ces changements au niveau suivant
+pub fn compress_directory_pending(options: &CompressionOptions) -> Result<(Report, Option<PendingSnapshot>), CompressionError> {
+    info!("Démarrage de la compression de {:?}", options.input_path);
+    
+    if options.solid {
+        compress_directory_solid(options)
+    } else {
+        compress_streams(options)
+    }
+}
+
+fn compress_directory_solid(options: &CompressionOptions) -> Result<(Report, Option<PendingSnapshot>), CompressionError> {
+    let _span = info_span!("compress", input = %options.input_path.display(), solid = true).entered();
+    info!("Mode solid activé");
+    let mut report = Report::default();
+    
+    // Générer le dictionnaire global
+    let dict = debug_span!("dictionary").in_scope(|| generate_global_dictionary(&options.input_path))?;
+    
+    let output_file = MirrorWriter::create::<CompressionError>(&options.output_path, options.mirror_path.as_deref(), options.direct_io)?;
+    let mut writer = std::io::BufWriter::new(output_file);
+    write_archive_header(&mut writer, MODE_SOLID)?;
+
+    // Écrire la taille du dictionnaire
+    writer.write_all(&(dict.len() as u64).to_le_bytes())?;
+    // Écrire le dictionnaire
+    writer.write_all(&dict)?;
+
+    // Collecter tous les fichiers, sur disque au-delà de la limite mémoire
+    let mut all_data = SpillBuffer::new(&options.spill);
+    let mut file_index = Vec::new();
+    let mut snapshot = load_snapshot(options)?;
+    
+    for entry in walk(&options.input_path, &options.walk) {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                track_walk_error(&mut snapshot, options, &e);
+                let path = e.path.clone();
+                report.skip_or_fail(options.skip_errors, &path, CompressionError::from(e))?;
+                continue;
+            }
+        };
+        if entry.kind == EntryKind::File {
+            if !track_incremental(&mut snapshot, &entry.relative_path, &entry.metadata) {
+                continue;
+            }
+            let _read = debug_span!("read", path = %entry.relative_path.display(), size = entry.metadata.len()).entered();
+            let content = match fs::read(&entry.path) {
+                Ok(content) => content,
+                Err(e) => {
+                    report.skip_or_fail(options.skip_errors, &entry.path, CompressionError::io_at(e, &entry.path))?;
+                    untrack_incremental(&mut snapshot, &entry.relative_path);
+                    continue;
+                }
+            };
+            let start_offset = all_data.len();
+            all_data.write_all(&content)?;
+            let end_offset = all_data.len();
+            
+            file_index.push((entry.relative_path, start_offset, end_offset));
+        } else if entry.kind != EntryKind::Directory {
+            report.warn(&entry.path, WarningKind::Ignored, format!("Entrée ignorée ({:?})", entry.kind));
+        }
+    }
+
+    // Compression en trames indépendantes, plusieurs à la fois
+    info!("Compression avec niveau {} et {} threads", options.level, options.threads);
+    let mut compressed = SpillBuffer::new(&options.spill);
+    let frames = debug_span!("compress_frames", size = all_data.len()).in_scope(|| {
+        compress_frames(all_data.into_reader()?, options.solid_frame_size, Codec::zstd(options.level), options.threads, &mut compressed)
+    })?;
+    debug!(frames = frames.len(), "Trames compressées");
+    // Le dictionnaire est stocké mais pas utilisé par la compression
+    Codec::zstd(options.level).write_to(&mut writer)?;
+    write_frame_table(&mut writer, &frames)?;
+    std::io::copy(&mut compressed.into_reader()?, &mut writer)?;
+    
+    // Écrire l'index des fichiers
+    writer.write_all(&(file_index.len() as u64).to_le_bytes())?;
+    for (path, start, end) in file_index {
+        write_path(&mut writer, &path)?;
+        writer.write_all(&start.to_le_bytes())?;
+        writer.write_all(&(end - start).to_le_bytes())?;
+    }
+    // Puis les fichiers supprimés depuis le niveau précédent
+    let deleted = deleted_paths(&snapshot);
+    writer.write_all(&(deleted.len() as u64).to_le_bytes())?;
+    for path in deleted {
+        write_path(&mut writer, &path)?;
+    }
+    writer.flush()?;
+
+    info!("Compression terminée avec succès");
+    Ok((report, pending_snapshot(options, snapshot)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::CODEC_ZSTD;
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn create_test_file(dir: &Path, name: &str, content: &[u8]) -> PathBuf {
+        let path = dir.join(name);
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_compression() {
+        let temp_dir = tempdir().unwrap();
+        let input_dir = temp_dir.path().join("input");
+        let output_dir = temp_dir.path().join("output");
+        fs::create_dir(&input_dir).unwrap();
+        fs::create_dir(&output_dir).unwrap();
+
+        // Créer des fichiers de test
+        let text_content = "Ceci est un fichier texte de test avec beaucoup de répétitions. ".repeat(1000);
+        create_test_file(&input_dir, "test.txt", text_content.as_bytes());
+
+        let binary_content = vec![0u8; 1024 * 1024]; // 1MB de zéros
+        create_test_file(&input_dir, "test.bin", &binary_content);
+
+        let options = CompressionOptions {
+            input_path: input_dir,
+            output_path: output_dir.join("test.zpp"),
+            threads: 2,
+            level: 22,
+            solid: false,
+            ..Default::default()
+        };
+
+        // Tester la compression
+        compress_folder(&options).unwrap();
+
+        // Vérifier que le fichier de sortie existe
+        assert!(output_dir.join("test.zpp").exists());
+
+        // Nettoyer
+        temp_dir.close().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_incremental_state_saved_on_commit() {
+        let temp_dir = tempdir().unwrap();
+        let input_dir = temp_dir.path().join("input");
+        fs::create_dir(&input_dir).unwrap();
+        create_test_file(&input_dir, "a.txt", b"premier niveau");
+
+        for solid in [false, true] {
+            let snar = temp_dir.path().join(format!("state-{solid}.snar"));
+            let options = CompressionOptions {
+                input_path: input_dir.clone(),
+                output_path: temp_dir.path().join(format!("level0-{solid}.zpp")),
+                solid,
+                listed_incremental: Some(snar.clone()),
+                ..Default::default()
+            };
+            // L'état n'avance qu'une fois l'archive conservée par l'appelant
+            let (_, snapshot) = compress_directory_pending(&options).unwrap();
+            assert!(!snar.exists());
+            snapshot.unwrap().commit().unwrap();
+            assert_eq!(SnapshotState::load_or_default(&snar).unwrap().len(), 1);
+        }
+    }
+
+    #[test]
+    fn test_skip_errors() {
+        let temp_dir = tempdir().unwrap();
+        let input_dir = temp_dir.path().join("input");
+        fs::create_dir(&input_dir).unwrap();
+        create_test_file(&input_dir, "ok.bin", &[1u8; 1000]);
+        // Lien cassé : illisible lorsque les liens sont suivis
+        std::os::unix::fs::symlink("missing", input_dir.join("broken")).unwrap();
+
+        for solid in [false, true] {
+            let mut options = CompressionOptions {
+                input_path: input_dir.clone(),
+                output_path: temp_dir.path().join(format!("test-{solid}.zpp")),
+                level: 3,
+                solid,
+                walk: WalkOptions { follow_symlinks: true, ..Default::default() },
+                ..Default::default()
+            };
+            assert!(compress_directory(&options).is_err());
+
+            options.skip_errors = true;
+            let report = compress_directory(&options).unwrap();
+            assert_eq!(report.skipped.len(), 1);
+            assert_eq!(report.skipped[0].path, input_dir.join("broken"));
+        }
+    }
+
+    #[test]
+    fn test_already_compressed_stored() {
+        use crate::decompress::{decompress_archive, list_archive, DecompressionOptions};
+
+        let temp_dir = tempdir().unwrap();
+        let input_dir = temp_dir.path().join("input");
+        fs::create_dir(&input_dir).unwrap();
+        // Données incompressibles, et une image "compressée" qui ne l'est pas
+        let mut photo = vec![0u8; 200 * 1024];
+        blake3::Hasher::new().update(b"photo").finalize_xof().fill(&mut photo);
+        create_test_file(&input_dir, "photo.jpg", &photo);
+        create_test_file(&input_dir, "blank.png", &[0u8; 100 * 1024]);
+
+        let archive = temp_dir.path().join("test.zpp");
+        compress_folder(&CompressionOptions { input_path: input_dir, output_path: archive.clone(), level: 3, ..Default::default() }).unwrap();
+
+        let listing = list_archive(&archive).unwrap();
+        let entry = |name: &str| listing.entries.iter().find(|entry| entry.path == Path::new(name)).unwrap();
+        assert_eq!(entry("photo.jpg").compressed_size, Some(photo.len() as u64));
+        assert!(entry("blank.png").compressed_size.unwrap() < 1024);
+
+        let output_dir = temp_dir.path().join("output");
+        decompress_archive(&DecompressionOptions { input_path: archive, output_path: output_dir.clone(), ..Default::default() }).unwrap();
+        assert_eq!(fs::read(output_dir.join("photo.jpg")).unwrap(), photo);
+        assert_eq!(fs::read(output_dir.join("blank.png")).unwrap(), vec![0u8; 100 * 1024]);
+    }
+
+    #[test]
+    fn test_probe_level() {
+        let mut noise = vec![0u8; PROBE_SIZE];
+        blake3::Hasher::new().update(b"bruit").finalize_xof().fill(&mut noise);
+        let profile_level = CompressionProfile::Binary.get_compression_level();
+        assert_eq!(probe_level(&noise, profile_level).unwrap(), None);
+
+        // Un octet sur six aléatoire : un gain modeste
+        let mut mostly_noise = noise.clone();
+        mostly_noise.iter_mut().enumerate().filter(|(i, _)| i % 6 == 0).for_each(|(_, byte)| *byte = 0);
+        assert_eq!(probe_level(&mostly_noise, profile_level).unwrap(), Some(1));
+
+        let text = "ligne de journal ordinaire\n".repeat(4000);
+        assert_eq!(probe_level(text.as_bytes(), profile_level).unwrap(), Some(profile_level + PROBE_LEVEL_BOOST));
+        assert_eq!(probe_level(text.as_bytes(), 19).unwrap(), Some(19));
+        assert_eq!(probe_level(text.as_bytes(), 22).unwrap(), Some(22));
+
+        // Données moyennement compressibles : le niveau du profil est gardé
+        let mixed: Vec<u8> = noise.chunks(16).flat_map(|chunk| chunk[..8].iter().chain(b"abcdefgh")).copied().collect();
+        assert_eq!(probe_level(&mixed, profile_level).unwrap(), Some(profile_level));
+
+        let (codec, data) = process_file(Path::new("bruit.txt"), noise.clone(), CompressionProfile::Text, DEFAULT_STORE_THRESHOLD, true, 1, &BufferPool::new(2, MAX_POOLED_CAPACITY)).unwrap();
+        assert_eq!((codec.id, data), (CODEC_STORED, noise));
+    }
+
+    #[test]
+    fn test_stream_file_matches_process_file() {
+        let temp_dir = tempdir().unwrap();
+        let mut noise = vec![0u8; 300 * 1024];
+        blake3::Hasher::new().update(b"flux").finalize_xof().fill(&mut noise);
+        let mut text = "ligne avec espaces   \r\nautre ligne\t\n\n".repeat(20_000).into_bytes();
+        text.extend_from_slice(b"octets invalides \xff\xfe  \nfin sans saut");
+        let buffers = BufferPool::new(2, MAX_POOLED_CAPACITY);
+        // Une limite basse fait passer les données compressées sur disque
+        let spill = SpillOptions { dir: Some(temp_dir.path().to_path_buf()), memory_limit: 1000 };
+
+        for (name, content, profile) in [
+            ("journal.txt", text, CompressionProfile::Text),
+            ("donnees.bin", noise.clone(), CompressionProfile::Binary),
+            ("donnees.dat", noise[..100 * 1024].repeat(3), CompressionProfile::Binary),
+        ] {
+            let path = temp_dir.path().join(name);
+            fs::write(&path, &content).unwrap();
+            let (codec, data) = process_file(&path, content, profile, DEFAULT_STORE_THRESHOLD, true, 1, &buffers).unwrap();
+            let (streamed_codec, streamed) = stream_file(&path, profile, DEFAULT_STORE_THRESHOLD, true, 1, &spill).unwrap();
+            let mut streamed_data = Vec::new();
+            streamed.into_reader().unwrap().read_to_end(&mut streamed_data).unwrap();
+            assert_eq!(streamed_codec, codec, "{name}");
+            assert_eq!(codec.decode(&streamed_data).unwrap(), codec.decode(&data).unwrap(), "{name}");
+        }
+    }
+
+    #[test]
+    fn test_stream_file_store_threshold() {
+        let temp_dir = tempdir().unwrap();
+        let content = "ligne de journal ordinaire\n".repeat(4000);
+        let path = temp_dir.path().join("journal.txt");
+        fs::write(&path, &content).unwrap();
+        let spill = SpillOptions::default();
+
+        // Le seuil porte sur la taille du fichier, pas sur celle de la sonde
+        let threshold = content.len() as u64 + 1;
+        let (codec, stored) = stream_file(&path, CompressionProfile::Text, threshold, true, 1, &spill).unwrap();
+        let mut data = Vec::new();
+        stored.into_reader().unwrap().read_to_end(&mut data).unwrap();
+        assert_eq!((codec, data), (Codec::STORED, content.into_bytes()));
+        let (codec, _) = stream_file(&path, CompressionProfile::Text, threshold - 1, true, 1, &spill).unwrap();
+        assert_eq!(codec.id, CODEC_ZSTD);
+    }
+
+    #[test]
+    fn test_trim_lines_long_line() {
+        // Une ligne trop longue est recopiée sans être retouchée, les autres restent nettoyées
+        let long_line = "mot  ".repeat(MAX_TRIMMED_LINE / 2);
+        let input = format!("avant  \n{}\naprès \t\r\n{}", long_line, long_line);
+        let mut output = Vec::new();
+        trim_lines(input.as_bytes(), &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), format!("avant\n{}\naprès\n{}", long_line, long_line));
+    }
+
+    #[test]
+    fn test_encoder_threads() {
+        let temp_dir = tempdir().unwrap();
+        let mut noise = vec![0u8; 64 * 1024];
+        blake3::Hasher::new().update(b"threads").finalize_xof().fill(&mut noise);
+        // Au-delà d'un job zstd (2 Mio au niveau 1), la sortie multithread diffère
+        // de celle d'un seul thread
+        let content: Vec<u8> = noise.chunks(8).cycle().take(400_000).flat_map(|chunk| chunk[..4].iter().chain(b"abcd")).copied().collect();
+        let path = temp_dir.path().join("donnees.dat");
+        fs::write(&path, &content).unwrap();
+        let buffers = BufferPool::new(2, MAX_POOLED_CAPACITY);
+        let spill = SpillOptions::default();
+
+        let encoded = |threads| {
+            let (codec, data) = process_file(&path, content.clone(), CompressionProfile::AlreadyCompressed, DEFAULT_STORE_THRESHOLD, false, threads, &buffers).unwrap();
+            let (streamed_codec, streamed) = stream_file(&path, CompressionProfile::AlreadyCompressed, DEFAULT_STORE_THRESHOLD, false, threads, &spill).unwrap();
+            let mut streamed_data = Vec::new();
+            streamed.into_reader().unwrap().read_to_end(&mut streamed_data).unwrap();
+            assert_eq!((codec.id, streamed_codec.id), (CODEC_ZSTD, CODEC_ZSTD));
+            assert_eq!(codec.decode(&data).unwrap(), content);
+            assert_eq!(codec.decode(&streamed_data).unwrap(), content);
+            (data, streamed_data)
+        };
+        let single = encoded(1);
+        let multi = encoded(2);
+        assert_ne!(single.0, multi.0);
+        assert_ne!(single.1, multi.1);
+        // Le nombre de workers ne change pas la sortie une fois le multithread actif
+        assert_eq!(multi, encoded(4));
+    }
+
+    #[test]
+    fn test_compression_profiles() {
+        let temp_dir = tempdir().unwrap();
+        let input_dir = temp_dir.path().join("input");
+        let output_dir = temp_dir.path().join("output");
+        fs::create_dir(&input_dir).unwrap();
+        fs::create_dir(&output_dir).unwrap();
+
+        // Créer des fichiers pour chaque profil
+        let text_content = "Fichier texte de test".repeat(100);
+        create_test_file(&input_dir, "text.txt", text_content.as_bytes());
+
+        let binary_content = vec![0u8; 1024 * 10]; // 10KB de zéros
+        create_test_file(&input_dir, "binary.bin", &binary_content);
+
+        let image_content = vec![0u8; 1024 * 100]; // 100KB de données simulées d'image
+        create_test_file(&input_dir, "image.jpg", &image_content);
+
+        let unity_content = "Unity asset test data".repeat(100);
+        create_test_file(&input_dir, "test.unity", unity_content.as_bytes());
+
+        let options = CompressionOptions {
+            input_path: input_dir,
+            output_path: output_dir.join("test.zpp"),
+            threads: 2,
+            level: 22,
+            solid: false,
+            ..Default::default()
+        };
+
+        // Tester la compression
+        compress_folder(&options).unwrap();
+
+        // Vérifier que le fichier de sortie existe
+        assert!(output_dir.join("test.zpp").exists());
+
+        // Nettoyer
+        temp_dir.close().unwrap();
+    }
+}
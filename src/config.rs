@@ -1,6 +1,15 @@
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 use anyhow::{Result, Context};
+use toml::Value;
+
+use crate::chunker::{ChunkStrategy, ChunkerOptions};
+use crate::format::DEFAULT_STORE_THRESHOLD;
+use crate::image::HashAlgorithm;
+use crate::pipeline::PipelineOptions;
+use crate::spill::SpillOptions;
+use crate::storage::FsyncPolicy;
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Config {
@@ -13,11 +22,229 @@ pub struct Config {
     /// Block size for deduplication (in bytes)
     pub block_size: usize,
     
-    /// Memory limit for compression (in MB)
+    /// Memory limit for compression (in MB); staged data beyond it spills to `temp_dir`
     pub memory_limit: usize,
     
+    /// Directory of spill files [default: the system temporary directory]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temp_dir: Option<PathBuf>,
+    
     /// Enable verbose logging
     pub verbose: bool,
+    
+    /// Block hash for image deduplication (xxh3 or blake3)
+    #[serde(default)]
+    pub hash_algorithm: HashAlgorithm,
+    
+    /// Entries and image blocks smaller than this many bytes are stored uncompressed
+    #[serde(default = "default_store_threshold")]
+    pub store_threshold: u64,
+    
+    /// Per-stage sizing of the read/compress/write pipeline
+    #[serde(default)]
+    pub pipeline: PipelineConfig,
+    
+    /// How image blocks are cut
+    #[serde(default)]
+    pub chunker: ChunkerConfig,
+    
+    /// Detailed log file written whatever the console verbosity
+    #[serde(default)]
+    pub log: LogConfig,
+    
+    /// Credentials and endpoints of storage backends
+    #[serde(default)]
+    pub storage: StorageConfig,
+    
+    /// Named bundles of settings selected with `--preset`
+    #[serde(default, rename = "preset", skip_serializing_if = "BTreeMap::is_empty")]
+    pub presets: BTreeMap<String, Preset>,
+}
+
+/// Settings a preset overrides; command-line flags still take precedence
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Preset {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compression_level: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_threads: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash_algorithm: Option<HashAlgorithm>,
+    /// Solid .zpp archives
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub solid: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub follow_symlinks: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_depth: Option<usize>,
+    /// Skip files smaller than this many bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_size: Option<u64>,
+    /// Skip files larger than this many bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<u64>,
+    /// Merged field by field over `[chunker]`
+    #[serde(default)]
+    pub chunker: ChunkerConfig,
+}
+
+/// Chunker overrides; sizes default to a range around `block_size`
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ChunkerConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strategy: Option<ChunkStrategy>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_size: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub avg_size: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub normalization: Option<u8>,
+}
+
+/// Log file settings; `--log-file` overrides `file`
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct LogConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<PathBuf>,
+    /// Rotate once the file reaches this many bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<u64>,
+    /// Rotated files kept
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_files: Option<usize>,
+}
+
+/// Settings of the backends behind storage URLs
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct StorageConfig {
+    /// Most bytes per second each way for transfers to and from remote backends;
+    /// `--bwlimit` overrides it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bwlimit: Option<u64>,
+    /// What is flushed to the device once a local archive or image is written;
+    /// `--fsync` overrides it
+    #[serde(default)]
+    pub fsync: FsyncPolicy,
+    #[serde(default)]
+    pub cache: CacheConfig,
+    #[serde(default)]
+    pub azure: AzureConfig,
+    #[serde(default)]
+    pub gcs: GcsConfig,
+    #[serde(default)]
+    pub b2: B2Config,
+    #[serde(default)]
+    pub webdav: WebDavConfig,
+}
+
+/// Local cache of data read from storage URLs; disabled unless `dir` is set
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct CacheConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dir: Option<PathBuf>,
+    /// Most bytes kept, least recently used first to go [default: 1 GiB]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<u64>,
+}
+
+/// Azure Blob Storage, for `azure://container/path` URLs
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct AzureConfig {
+    /// Storage account
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub account: Option<String>,
+    /// Shared access signature: the query string of a SAS URL, granting access to the containers used
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sas_token: Option<String>,
+    /// Blob service URL [default: https://ACCOUNT.blob.core.windows.net]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<String>,
+}
+
+/// Google Cloud Storage, for `gs://bucket/path` URLs
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct GcsConfig {
+    /// Service account key file (JSON) [default: $GOOGLE_APPLICATION_CREDENTIALS]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub credentials: Option<PathBuf>,
+    /// JSON API URL [default: https://storage.googleapis.com]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<String>,
+}
+
+/// Keeps the token out of logs
+impl std::fmt::Debug for AzureConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AzureConfig")
+            .field("account", &self.account)
+            .field("sas_token", &self.sas_token.as_ref().map(|_| "<redacted>"))
+            .field("endpoint", &self.endpoint)
+            .finish()
+    }
+}
+
+/// Backblaze B2, for `b2://bucket/path` URLs
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct B2Config {
+    /// Application key id
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub application_key: Option<String>,
+    /// API URL accounts are authorized at [default: https://api.backblazeb2.com]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<String>,
+}
+
+/// Keeps the key out of logs
+impl std::fmt::Debug for B2Config {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("B2Config")
+            .field("key_id", &self.key_id)
+            .field("application_key", &self.application_key.as_ref().map(|_| "<redacted>"))
+            .field("endpoint", &self.endpoint)
+            .finish()
+    }
+}
+
+/// WebDAV servers, for `dav://` and `davs://` URLs; basic auth with a username,
+/// bearer auth with a token
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct WebDavConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    /// Password, or app password on Nextcloud
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+/// Keeps the secrets out of logs
+impl std::fmt::Debug for WebDavConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WebDavConfig")
+            .field("username", &self.username)
+            .field("password", &self.password.as_ref().map(|_| "<redacted>"))
+            .field("token", &self.token.as_ref().map(|_| "<redacted>"))
+            .finish()
+    }
+}
+
+/// Pipeline overrides; unset values are derived from `max_threads`
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct PipelineConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub read_threads: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash_threads: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compress_threads: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub queue_depth: Option<usize>,
 }
 
 impl Default for Config {
@@ -27,12 +254,88 @@ impl Default for Config {
             max_threads: num_cpus::get(),
             block_size: 65536, // 64KB
             memory_limit: 1024, // 1GB
+            temp_dir: None,
             verbose: false,
+            hash_algorithm: HashAlgorithm::default(),
+            store_threshold: DEFAULT_STORE_THRESHOLD,
+            pipeline: PipelineConfig::default(),
+            chunker: ChunkerConfig::default(),
+            log: LogConfig::default(),
+            storage: StorageConfig::default(),
+            presets: BTreeMap::new(),
         }
     }
 }
 
+fn default_store_threshold() -> u64 {
+    DEFAULT_STORE_THRESHOLD
+}
+
+/// Project configuration, searched from the current directory upwards
+pub const PROJECT_CONFIG_FILE: &str = ".zippypack.toml";
+
+/// Prefix of environment variables overriding configuration keys
+/// (`ZIPPY_COMPRESSION_LEVEL`, `ZIPPY_CHUNKER__STRATEGY` for nested keys)
+pub const ENV_PREFIX: &str = "ZIPPY_";
+
+/// `$XDG_CONFIG_HOME/zippypack/config.toml`, `~/.config/zippypack/config.toml`
+/// or `%APPDATA%\zippypack\config.toml` on Windows
+pub fn user_config_path() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os(if cfg!(windows) { "APPDATA" } else { "HOME" }).map(|home| {
+            if cfg!(windows) { PathBuf::from(home) } else { Path::new(&home).join(".config") }
+        }))?;
+    Some(base.join("zippypack").join("config.toml"))
+}
+
+/// Nearest `.zippypack.toml` in `start` or one of its ancestors
+pub fn project_config_path(start: &Path) -> Option<PathBuf> {
+    start.ancestors().map(|dir| dir.join(PROJECT_CONFIG_FILE)).find(|path| path.is_file())
+}
+
 impl Config {
+    /// Layered configuration: defaults, then the user and project files (or only
+    /// `explicit` when given), then `ZIPPY_*` variables. Returns the files read.
+    pub fn discover(explicit: Option<&Path>) -> Result<(Self, Vec<PathBuf>)> {
+        let files: Vec<PathBuf> = match explicit {
+            Some(path) => vec![path.to_path_buf()],
+            None => {
+                let project = std::env::current_dir().ok().and_then(|dir| project_config_path(&dir));
+                user_config_path().filter(|path| path.is_file()).into_iter().chain(project).collect()
+            }
+        };
+        let config = Self::from_layers(&files, std::env::vars())?;
+        Ok((config, files))
+    }
+    
+    /// Merge `files` in order over the defaults, then the `ZIPPY_*` entries of `env`;
+    /// files may set only some keys
+    pub fn from_layers(files: &[PathBuf], env: impl IntoIterator<Item = (String, String)>) -> Result<Self> {
+        let mut merged = Value::try_from(Self::default()).context("Failed to serialize config")?;
+        for path in files {
+            let content = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read config file: {}", path.display()))?;
+            let layer: Value = toml::from_str(&content)
+                .with_context(|| format!("Failed to parse config file: {}", path.display()))?;
+            merge_values(&mut merged, layer);
+        }
+        for (name, raw) in env {
+            let Some(key) = name.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            let mut layer = env_value(&raw);
+            for part in key.to_lowercase().split("__").collect::<Vec<_>>().into_iter().rev() {
+                layer = Value::Table(toml::Table::from_iter([(part.to_string(), layer)]));
+            }
+            merge_values(&mut merged, layer);
+        }
+        
+        let config: Config = merged.try_into().context("Invalid configuration")?;
+        config.validate()?;
+        Ok(config)
+    }
+    
     /// Load configuration from a TOML file
     pub fn from_file(path: &PathBuf) -> Result<Self> {
         let content = std::fs::read_to_string(path)
@@ -74,9 +377,84 @@ impl Config {
             anyhow::bail!("Memory limit must be between 64MB and 64GB");
         }
         
+        let pipeline = &self.pipeline;
+        for value in [pipeline.read_threads, pipeline.hash_threads, pipeline.compress_threads].into_iter().flatten() {
+            if value == 0 || value > 1024 {
+                anyhow::bail!("Pipeline threads must be between 1 and 1024");
+            }
+        }
+        if pipeline.queue_depth == Some(0) {
+            anyhow::bail!("Pipeline queue depth must be at least 1");
+        }
+        
+        if self.log.max_size == Some(0) {
+            anyhow::bail!("Log file size limit must be at least 1 byte");
+        }
+        
+        if let Err(e) = self.chunker_options().validate() {
+            anyhow::bail!("Invalid chunker settings: {}", e);
+        }
+        
         Ok(())
     }
     
+    /// Stage sizing for compression and imaging
+    pub fn pipeline_options(&self) -> PipelineOptions {
+        let defaults = PipelineOptions::for_threads(self.max_threads);
+        PipelineOptions {
+            read_threads: self.pipeline.read_threads.unwrap_or(defaults.read_threads),
+            hash_threads: self.pipeline.hash_threads.unwrap_or(defaults.hash_threads),
+            compress_threads: self.pipeline.compress_threads.unwrap_or(defaults.compress_threads),
+            queue_depth: self.pipeline.queue_depth.unwrap_or(defaults.queue_depth),
+        }
+    }
+    
+    /// Where staged data goes once past the memory limit
+    pub fn spill_options(&self) -> SpillOptions {
+        SpillOptions { dir: self.temp_dir.clone(), memory_limit: self.memory_limit as u64 * 1024 * 1024 }
+    }
+    
+    /// Chunking parameters for image creation
+    pub fn chunker_options(&self) -> ChunkerOptions {
+        let chunker = &self.chunker;
+        let avg_size = chunker.avg_size.unwrap_or(self.block_size.min(u32::MAX as usize) as u32);
+        let defaults = ChunkerOptions::around(chunker.strategy.unwrap_or_default(), avg_size);
+        ChunkerOptions {
+            min_size: chunker.min_size.unwrap_or(defaults.min_size),
+            max_size: chunker.max_size.unwrap_or(defaults.max_size),
+            normalization: chunker.normalization.unwrap_or(defaults.normalization),
+            ..defaults
+        }
+    }
+    
+    /// Look up a preset defined in the configuration
+    pub fn preset(&self, name: &str) -> Result<&Preset> {
+        self.presets.get(name).with_context(|| {
+            let known: Vec<&str> = self.presets.keys().map(String::as_str).collect();
+            format!("Unknown preset '{}' (defined: {})", name, if known.is_empty() { "none".to_string() } else { known.join(", ") })
+        })
+    }
+    
+    /// Override settings with those of a preset
+    pub fn apply_preset(&mut self, preset: &Preset) -> Result<()> {
+        if let Some(level) = preset.compression_level {
+            self.compression_level = level;
+        }
+        if let Some(threads) = preset.max_threads {
+            self.max_threads = threads;
+        }
+        if let Some(hash_algorithm) = preset.hash_algorithm {
+            self.hash_algorithm = hash_algorithm;
+        }
+        let (chunker, overrides) = (&mut self.chunker, &preset.chunker);
+        chunker.strategy = overrides.strategy.or(chunker.strategy);
+        chunker.min_size = overrides.min_size.or(chunker.min_size);
+        chunker.avg_size = overrides.avg_size.or(chunker.avg_size);
+        chunker.max_size = overrides.max_size.or(chunker.max_size);
+        chunker.normalization = overrides.normalization.or(chunker.normalization);
+        self.validate()
+    }
+    
     /// Merge with CLI arguments, giving precedence to CLI
     pub fn merge_with_cli(&mut self, cli_level: Option<i32>, cli_threads: Option<usize>, cli_verbose: bool) {
         if let Some(level) = cli_level {
@@ -93,6 +471,31 @@ impl Config {
     }
 }
 
+/// Deep merge of TOML tables, `layer` winning
+fn merge_values(base: &mut Value, layer: Value) {
+    match (base, layer) {
+        (Value::Table(base), Value::Table(layer)) => {
+            for (key, value) in layer {
+                match base.get_mut(&key) {
+                    Some(existing) => merge_values(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, layer) => *base = layer,
+    }
+}
+
+/// Environment values are read as TOML (`3`, `true`), falling back to a plain string (`blake3`)
+fn env_value(raw: &str) -> Value {
+    toml::from_str::<toml::Table>(&format!("value = {}", raw))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| Value::String(raw.to_string()))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -108,10 +511,8 @@ mod tests {
     
     #[test]
     fn test_config_validation() {
-        let mut config = Config::default();
-        
         // Test invalid compression level
-        config.compression_level = 0;
+        let mut config = Config { compression_level: 0, ..Default::default() };
         assert!(config.validate().is_err());
         
         config.compression_level = 23;
@@ -133,6 +534,99 @@ mod tests {
         assert_eq!(config.max_threads, parsed.max_threads);
     }
     
+    #[test]
+    fn test_pipeline_config() {
+        let config: Config = toml::from_str(
+            "compression_level = 3\nmax_threads = 8\nblock_size = 65536\nmemory_limit = 1024\nverbose = false\n\n[pipeline]\nread_threads = 4\n",
+        ).unwrap();
+        assert!(config.validate().is_ok());
+        
+        let options = config.pipeline_options();
+        assert_eq!(options.read_threads, 4);
+        assert_eq!(options.hash_threads, 2);
+        assert_eq!(options.compress_threads, 8);
+        assert_eq!(options.queue_depth, 32);
+        assert_eq!(config.hash_algorithm, HashAlgorithm::Xxh3);
+        
+        let invalid = Config { pipeline: PipelineConfig { queue_depth: Some(0), ..Default::default() }, ..Default::default() };
+        assert!(invalid.validate().is_err());
+    }
+    
+    #[test]
+    fn test_hash_algorithm_config() {
+        let config: Config = toml::from_str(
+            "compression_level = 3\nmax_threads = 8\nblock_size = 65536\nmemory_limit = 1024\nverbose = false\nhash_algorithm = \"blake3\"\n",
+        ).unwrap();
+        assert_eq!(config.hash_algorithm, HashAlgorithm::Blake3);
+        assert!(toml::from_str::<Config>(
+            "compression_level = 3\nmax_threads = 8\nblock_size = 65536\nmemory_limit = 1024\nverbose = false\nhash_algorithm = \"md5\"\n",
+        ).is_err());
+    }
+    
+    #[test]
+    fn test_chunker_config() {
+        let config: Config = toml::from_str(
+            "compression_level = 3\nmax_threads = 8\nblock_size = 65536\nmemory_limit = 1024\nverbose = false\n\n[chunker]\nstrategy = \"cdc\"\navg_size = 8192\n",
+        ).unwrap();
+        assert!(config.validate().is_ok());
+        
+        let options = config.chunker_options();
+        assert_eq!(options.strategy, ChunkStrategy::Cdc);
+        assert_eq!((options.min_size, options.avg_size, options.max_size), (2048, 8192, 32768));
+        assert_eq!(Config::default().chunker_options(), ChunkerOptions::default());
+        
+        let invalid = Config { chunker: ChunkerConfig { normalization: Some(9), strategy: Some(ChunkStrategy::Cdc), ..Default::default() }, ..Default::default() };
+        assert!(invalid.validate().is_err());
+    }
+    
+    #[test]
+    fn test_presets() {
+        let mut config: Config = toml::from_str(
+            "compression_level = 3\nmax_threads = 8\nblock_size = 65536\nmemory_limit = 1024\nverbose = false\n\n\
+             [preset.archive]\ncompression_level = 19\nsolid = true\nmax_size = 1073741824\n\n\
+             [preset.archive.chunker]\nstrategy = \"cdc\"\n\n[preset.broken]\ncompression_level = 40\n",
+        ).unwrap();
+        let preset = config.preset("archive").unwrap().clone();
+        config.apply_preset(&preset).unwrap();
+        assert_eq!(config.compression_level, 19);
+        assert_eq!(config.max_threads, 8);
+        assert_eq!((preset.solid, preset.max_size), (Some(true), Some(1 << 30)));
+        assert_eq!(config.chunker_options().strategy, ChunkStrategy::Cdc);
+        
+        let broken = config.preset("broken").unwrap().clone();
+        assert!(config.apply_preset(&broken).is_err());
+        assert!(config.preset("fast").unwrap_err().to_string().contains("archive, broken"));
+        assert!(toml::from_str::<Preset>("level = 3").is_err());
+    }
+    
+    #[test]
+    fn test_layered_config() {
+        let temp_dir = tempdir().unwrap();
+        let user = temp_dir.path().join("user.toml");
+        std::fs::write(&user, "compression_level = 9\nmax_threads = 4\n\n[chunker]\nstrategy = \"cdc\"\n").unwrap();
+        let project_dir = temp_dir.path().join("project/sub");
+        std::fs::create_dir_all(&project_dir).unwrap();
+        let project = temp_dir.path().join("project").join(PROJECT_CONFIG_FILE);
+        std::fs::write(&project, "compression_level = 15\n\n[chunker]\navg_size = 8192\n").unwrap();
+        assert_eq!(project_config_path(&project_dir), Some(project.clone()));
+        
+        let env = [
+            ("ZIPPY_MAX_THREADS", "2"),
+            ("ZIPPY_HASH_ALGORITHM", "blake3"),
+            ("ZIPPY_CHUNKER__NORMALIZATION", "2"),
+            ("HOME", "/ignored"),
+        ].map(|(key, value)| (key.to_string(), value.to_string()));
+        let config = Config::from_layers(&[user, project], env).unwrap();
+        assert_eq!(config.compression_level, 15);
+        assert_eq!(config.max_threads, 2);
+        assert_eq!(config.hash_algorithm, HashAlgorithm::Blake3);
+        let chunker = config.chunker_options();
+        assert_eq!((chunker.strategy, chunker.avg_size, chunker.normalization), (ChunkStrategy::Cdc, 8192, 2));
+        
+        let invalid = [("ZIPPY_COMPRESSION_LEVEL".to_string(), "99".to_string())];
+        assert!(Config::from_layers(&[], invalid).is_err());
+    }
+    
     #[test]
     fn test_config_file_operations() {
         let temp_dir = tempdir().unwrap();
@@ -0,0 +1,450 @@
+//! Background job queue for `zippy daemon`, driven over a Unix socket by
+//! `zippy submit` and `zippy jobs`
+//!
+//! Each connection carries one JSON request line and gets one JSON response
+//! line back; a client that does not send its line within the request timeout
+//! is dropped, so it cannot hold up the others. Jobs run on a fixed number of
+//! worker threads in submission order.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use serde::{Deserialize, Serialize};
+use tracing::{info, info_span, warn};
+
+use crate::metrics::Metrics;
+use crate::report::Report;
+
+/// Operation queued on the daemon; paths are absolute once submitted
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, clap::Subcommand)]
+#[serde(rename_all = "snake_case", tag = "command")]
+pub enum JobSpec {
+    /// Compress a directory into a .zpp archive
+    Compress {
+        #[arg(short, long)]
+        input: PathBuf,
+        #[arg(short, long)]
+        output: PathBuf,
+        /// Compression level (1-22, defaults to the daemon's config)
+        #[arg(short = 'l', long)]
+        level: Option<i32>,
+        #[arg(long)]
+        #[serde(default)]
+        solid: bool,
+    },
+    /// Extract a .zpp archive
+    Decompress {
+        #[arg(short, long)]
+        input: PathBuf,
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+    /// Create a deduplicated .zpak image of a directory
+    CreateImage {
+        #[arg(short, long)]
+        input: PathBuf,
+        #[arg(short, long)]
+        output: PathBuf,
+        /// Compression level (1-22, defaults to the daemon's config)
+        #[arg(short = 'l', long)]
+        level: Option<i32>,
+    },
+    /// Extract a .zpak image
+    ExtractImage {
+        #[arg(short, long)]
+        input: PathBuf,
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+}
+
+impl JobSpec {
+    /// Resolve paths against the submitter's working directory, which the daemon does not share
+    pub fn make_absolute(&mut self) -> io::Result<()> {
+        let (input, output) = self.paths_mut();
+        *input = std::path::absolute(&*input)?;
+        *output = std::path::absolute(&*output)?;
+        Ok(())
+    }
+
+    /// Input and output paths
+    pub fn paths_mut(&mut self) -> (&mut PathBuf, &mut PathBuf) {
+        match self {
+            JobSpec::Compress { input, output, .. }
+            | JobSpec::Decompress { input, output }
+            | JobSpec::CreateImage { input, output, .. }
+            | JobSpec::ExtractImage { input, output } => (input, output),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "request")]
+pub enum Request {
+    Submit { job: JobSpec },
+    /// One job, or all of them
+    Status { id: Option<u64> },
+    /// Stop accepting jobs, cancel queued ones and exit once running ones finish
+    Shutdown,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "response")]
+pub enum Response {
+    Submitted { id: u64 },
+    Jobs { jobs: Vec<JobInfo> },
+    ShuttingDown,
+    Error { message: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JobInfo {
+    pub id: u64,
+    pub job: JobSpec,
+    pub state: JobState,
+}
+
+/// Times are seconds since the epoch
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "state")]
+pub enum JobState {
+    Queued,
+    Running { started: u64 },
+    Succeeded { started: u64, finished: u64, skipped: usize, warnings: usize },
+    Failed { started: u64, finished: u64, error: String },
+    /// Still queued when the daemon shut down
+    Cancelled,
+}
+
+impl JobState {
+    pub fn is_finished(&self) -> bool {
+        !matches!(self, JobState::Queued | JobState::Running { .. })
+    }
+}
+
+/// `$XDG_RUNTIME_DIR/zippypack/zippypack.sock`, else under `~/.cache`; the
+/// daemon keeps its socket in a directory only its user can enter
+pub fn default_socket_path() -> PathBuf {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .unwrap_or_else(std::env::temp_dir)
+        .join("zippypack")
+        .join("zippypack.sock")
+}
+
+/// Time a client has to send its request and read the response
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Longest request line accepted
+const MAX_REQUEST_SIZE: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct DaemonOptions {
+    pub socket_path: PathBuf,
+    /// Jobs run at the same time
+    pub workers: usize,
+    /// Time a client has to send its request and read the response
+    pub request_timeout: Duration,
+}
+
+/// Jobs known to a daemon or API server, and the queue feeding its workers
+pub struct JobQueue {
+    jobs: Mutex<BTreeMap<u64, JobInfo>>,
+    /// Counters of each job, filled in by the jobs that report them (images)
+    metrics: Mutex<HashMap<u64, Arc<Metrics>>>,
+    next_id: AtomicU64,
+    /// Dropped once the front end stops, ending the workers
+    queue: Mutex<Option<Sender<u64>>>,
+    stopping: AtomicBool,
+}
+
+impl JobQueue {
+    pub fn submit(&self, job: JobSpec) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        info!(id, job = ?job, "Job queued");
+        self.metrics.lock().unwrap_or_else(|e| e.into_inner()).insert(id, Metrics::new());
+        self.lock().insert(id, JobInfo { id, job, state: JobState::Queued });
+        if let Some(queue) = &*self.queue.lock().unwrap_or_else(|e| e.into_inner()) {
+            let _ = queue.send(id);
+        }
+        id
+    }
+
+    /// One job, or all of them; `None` for an unknown id
+    pub fn status(&self, id: Option<u64>) -> Option<Vec<JobInfo>> {
+        let jobs = self.lock();
+        match id {
+            Some(id) => jobs.get(&id).map(|job| vec![job.clone()]),
+            None => Some(jobs.values().cloned().collect()),
+        }
+    }
+
+    /// Progress counters of a job; `None` for an unknown id
+    pub fn metrics(&self, id: u64) -> Option<Arc<Metrics>> {
+        self.metrics.lock().unwrap_or_else(|e| e.into_inner()).get(&id).cloned()
+    }
+
+    /// Answer a request; `true` once asked to shut down
+    pub fn respond(&self, request: Request) -> (Response, bool) {
+        match request {
+            Request::Submit { job } => (Response::Submitted { id: self.submit(job) }, false),
+            Request::Status { id } => match self.status(id) {
+                Some(jobs) => (Response::Jobs { jobs }, false),
+                None => (Response::Error { message: format!("no job {}", id.unwrap_or_default()) }, false),
+            },
+            Request::Shutdown => (Response::ShuttingDown, true),
+        }
+    }
+
+    fn work(&self, queued: Receiver<u64>, run: &(impl Fn(&JobSpec, &Arc<Metrics>) -> Result<Report, String> + Sync)) {
+        for id in queued.iter() {
+            let Some(job) = self.lock().get(&id).map(|info| info.job.clone()) else {
+                continue;
+            };
+            if self.stopping.load(Ordering::Relaxed) {
+                self.set_state(id, JobState::Cancelled);
+                continue;
+            }
+            let started = now();
+            self.set_state(id, JobState::Running { started });
+
+            let metrics = self.metrics(id).unwrap_or_default();
+            let result = info_span!("job", id).in_scope(|| run(&job, &metrics));
+            let finished = now();
+            let state = match result {
+                Ok(report) => {
+                    info!(id, "Job succeeded");
+                    JobState::Succeeded { started, finished, skipped: report.skipped.len(), warnings: report.warnings.len() }
+                }
+                Err(error) => {
+                    warn!(id, error = %error, "Job failed");
+                    JobState::Failed { started, finished, error }
+                }
+            };
+            self.set_state(id, state);
+        }
+    }
+
+    fn set_state(&self, id: u64, state: JobState) {
+        if let Some(info) = self.lock().get_mut(&id) {
+            info.state = state;
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<u64, JobInfo>> {
+        self.jobs.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Run `front_end` with a queue whose jobs `workers` threads pass to `run`, along
+/// with the job's counters. Once `front_end` returns, queued jobs are cancelled
+/// and running ones awaited.
+pub fn run_queue<T>(
+    workers: usize,
+    run: impl Fn(&JobSpec, &Arc<Metrics>) -> Result<Report, String> + Sync,
+    front_end: impl FnOnce(&Arc<JobQueue>) -> T,
+) -> T {
+    let (queue, queued) = unbounded::<u64>();
+    let jobs = Arc::new(JobQueue {
+        jobs: Mutex::new(BTreeMap::new()),
+        metrics: Mutex::new(HashMap::new()),
+        next_id: AtomicU64::new(1),
+        queue: Mutex::new(Some(queue)),
+        stopping: AtomicBool::new(false),
+    });
+    thread::scope(|scope| {
+        for _ in 0..workers.max(1) {
+            let queued = queued.clone();
+            let jobs = &jobs;
+            let run = &run;
+            scope.spawn(move || jobs.work(queued, run));
+        }
+        // Workers end once the queue is dropped, even if the front end panicked
+        let result = panic::catch_unwind(AssertUnwindSafe(|| front_end(&jobs)));
+        jobs.stopping.store(true, Ordering::Relaxed);
+        jobs.queue.lock().unwrap_or_else(|e| e.into_inner()).take();
+        result.unwrap_or_else(|panic| panic::resume_unwind(panic))
+    })
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs())
+}
+
+#[cfg(unix)]
+pub use unix::{request, serve};
+
+#[cfg(unix)]
+mod unix {
+    use super::*;
+    use std::fs;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+    use std::os::unix::net::{UnixListener, UnixStream};
+
+    /// Send one request to the daemon listening on `socket_path`
+    pub fn request(socket_path: &Path, request: &Request) -> io::Result<Response> {
+        let mut stream = UnixStream::connect(socket_path)?;
+        let mut line = serde_json::to_string(request)?;
+        line.push('\n');
+        stream.write_all(line.as_bytes())?;
+        let mut response = String::new();
+        BufReader::new(stream).read_line(&mut response)?;
+        Ok(serde_json::from_str(&response)?)
+    }
+
+    /// Accept requests on the socket until a shutdown request, running queued
+    /// jobs through `run`. The socket is only accessible to its owner.
+    pub fn serve(options: &DaemonOptions, run: impl Fn(&JobSpec, &Arc<Metrics>) -> Result<Report, String> + Sync) -> io::Result<()> {
+        let listener = bind(&options.socket_path)?;
+        info!(socket = %options.socket_path.display(), workers = options.workers, "Daemon listening");
+        run_queue(options.workers, run, |jobs| {
+            for stream in listener.incoming() {
+                match stream.and_then(|stream| handle(stream, jobs, options.request_timeout)) {
+                    Ok(true) => break,
+                    Ok(false) => {}
+                    Err(e) => warn!(error = %e, "Daemon request failed"),
+                }
+            }
+        });
+        let _ = fs::remove_file(&options.socket_path);
+        info!("Daemon stopped");
+        Ok(())
+    }
+
+    /// Listen on `path`, replacing a socket left behind by a daemon that is no longer running
+    fn bind(path: &Path) -> io::Result<UnixListener> {
+        let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        private_directory(dir)?;
+        if path.exists() {
+            if UnixStream::connect(path).is_ok() {
+                return Err(io::Error::new(io::ErrorKind::AddrInUse, format!("a daemon is already listening on {}", path.display())));
+            }
+            fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        // Nobody else can reach it through the directory in the meantime
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+        Ok(listener)
+    }
+
+    /// Create the socket's directory 0700, or check that an existing one belongs
+    /// to this user and is closed to others: the socket accepts whoever reaches it
+    fn private_directory(dir: &Path) -> io::Result<()> {
+        if let Some(parent) = dir.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        match fs::DirBuilder::new().mode(0o700).create(dir) {
+            Err(e) if e.kind() != io::ErrorKind::AlreadyExists => return Err(e),
+            _ => {}
+        }
+        let metadata = fs::symlink_metadata(dir)?;
+        let owner = crate::platform::effective_ids().map(|(uid, _)| uid);
+        if !metadata.is_dir() || Some(metadata.uid()) != owner || metadata.mode() & 0o077 != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("socket directory {} must belong to you and be closed to other users (0700)", dir.display()),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Answer one request line; `true` once asked to shut down
+    fn handle(stream: UnixStream, jobs: &JobQueue, timeout: Duration) -> io::Result<bool> {
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        let mut line = String::new();
+        BufReader::new(&stream).take(MAX_REQUEST_SIZE).read_line(&mut line)?;
+        let (response, shutdown) = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => jobs.respond(request),
+            Err(e) => (Response::Error { message: format!("invalid request: {}", e) }, false),
+        };
+        let mut line = serde_json::to_string(&response)?;
+        line.push('\n');
+        (&stream).write_all(line.as_bytes())?;
+        Ok(shutdown)
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use std::time::{Duration, Instant};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_daemon_runs_submitted_jobs() {
+        let temp_dir = tempdir().unwrap();
+        let options = DaemonOptions {
+            socket_path: temp_dir.path().join("run/zippy.sock"),
+            workers: 2,
+            request_timeout: Duration::from_millis(200),
+        };
+        let socket = options.socket_path.clone();
+        let server = std::thread::spawn(move || {
+            serve(&options, |job, _| match job {
+                JobSpec::ExtractImage { .. } => Err("image not found".to_string()),
+                _ => Ok(Report::default()),
+            })
+        });
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !socket.exists() {
+            assert!(Instant::now() < deadline, "daemon did not start");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(std::fs::metadata(&socket).unwrap().permissions().mode() & 0o777, 0o600);
+        assert_eq!(std::fs::metadata(temp_dir.path().join("run")).unwrap().permissions().mode() & 0o777, 0o700);
+
+        // A client that never sends its request is dropped after the timeout
+        let silent = std::os::unix::net::UnixStream::connect(&socket).unwrap();
+        assert!(matches!(request(&socket, &Request::Status { id: None }).unwrap(), Response::Jobs { .. }));
+        drop(silent);
+
+        let submit = |job| match request(&socket, &Request::Submit { job }).unwrap() {
+            Response::Submitted { id } => id,
+            other => panic!("{:?}", other),
+        };
+        let paths = (PathBuf::from("/in"), PathBuf::from("/out"));
+        let compress = submit(JobSpec::Compress { input: paths.0.clone(), output: paths.1.clone(), level: Some(3), solid: false });
+        let extract = submit(JobSpec::ExtractImage { input: paths.0, output: paths.1 });
+        assert_eq!((compress, extract), (1, 2));
+
+        let jobs = loop {
+            let Response::Jobs { jobs } = request(&socket, &Request::Status { id: None }).unwrap() else {
+                panic!("expected jobs");
+            };
+            if jobs.iter().all(|job| job.state.is_finished()) {
+                break jobs;
+            }
+            assert!(Instant::now() < deadline, "jobs did not finish");
+            std::thread::sleep(Duration::from_millis(10));
+        };
+        assert!(matches!(jobs[0].state, JobState::Succeeded { skipped: 0, .. }));
+        assert!(matches!(&jobs[1].state, JobState::Failed { error, .. } if error == "image not found"));
+        assert!(matches!(request(&socket, &Request::Status { id: Some(9) }).unwrap(), Response::Error { .. }));
+
+        assert_eq!(request(&socket, &Request::Shutdown).unwrap(), Response::ShuttingDown);
+        server.join().unwrap().unwrap();
+        assert!(!socket.exists());
+    }
+
+    #[test]
+    fn test_socket_directory_must_be_private() {
+        let temp_dir = tempdir().unwrap();
+        let shared = temp_dir.path().join("shared");
+        std::fs::create_dir(&shared).unwrap();
+        std::fs::set_permissions(&shared, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let options = DaemonOptions { socket_path: shared.join("zippy.sock"), workers: 1, request_timeout: Duration::from_millis(200) };
+        let error = serve(&options, |_, _| Ok(Report::default())).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+        assert!(!options.socket_path.exists());
+    }
+}
@@ -1,161 +1,1094 @@
-use std::fs::{self, File};
-use std::io::{Read, Write, Cursor};
-use std::path::PathBuf;
-use anyhow::{Result, Context};
-use tracing::info;
-use zstd::decode_all;
-
-use crate::error::DecompressionError;
-
-pub struct DecompressionOptions {
-    pub input_path: PathBuf,
-    pub output_path: PathBuf,
-}
-
-impl Default for DecompressionOptions {
-    fn default() -> Self {
-        Self {
-            input_path: PathBuf::new(),
-            output_path: PathBuf::new(),
-        }
-    }
-}
-
-fn sanitize_path(path: &str) -> Result<PathBuf> {
-    // Validate and sanitize path to prevent path traversal attacks
-    let path = path.trim();
-    
-    // Reject empty paths
-    if path.is_empty() {
-        return Err(DecompressionError::InvalidFormat.into());
-    }
-    
-    // Reject absolute paths
-    if path.starts_with('/') || path.starts_with('\\') || path.contains(':') {
-        return Err(DecompressionError::InvalidFormat.into());
-    }
-    
-    // Split path into components and validate each one
-    let components: Vec<&str> = path.split(['/', '\\']).collect();
-    let mut safe_components = Vec::new();
-    
-    for component in components {
-        // Reject dangerous components
-        if component == "." || component == ".." || component.is_empty() {
-            continue; // Skip dangerous components
-        }
-        
-        // Sanitize component by removing invalid characters
-        let sanitized = component.replace(['<', '>', ':', '"', '|', '?', '*'], "_");
-        if !sanitized.is_empty() {
-            safe_components.push(sanitized);
-        }
-    }
-    
-    // Ensure we have at least one valid component
-    if safe_components.is_empty() {
-        return Err(DecompressionError::InvalidFormat.into());
-    }
-    
-    // Build safe path
-    let mut safe_path = PathBuf::new();
-    for component in safe_components {
-        safe_path.push(component);
-    }
-    
-    Ok(safe_path)
-}
-
-pub fn decompress_archive(options: &DecompressionOptions) -> Result<()> {
-    info!("Démarrage de la décompression de {:?}", options.input_path);
-    
-    let mut input_file = File::open(&options.input_path)
-        .context("Impossible d'ouvrir le fichier d'entrée")?;
-
-    // Créer le dossier de sortie s'il n'existe pas
-    fs::create_dir_all(&options.output_path)?;
-    println!("Dossier de sortie créé : {:?}", options.output_path);
-
-    // Lire la taille du dictionnaire
-    let mut dict_size_bytes = [0u8; 8];
-    input_file.read_exact(&mut dict_size_bytes)?;
-    let dict_size = u64::from_le_bytes(dict_size_bytes) as usize;
-    
-    // Validation: taille de dictionnaire raisonnable
-    if dict_size > 100 * 1024 * 1024 { // 100MB max
-        return Err(DecompressionError::InvalidFormat.into());
-    }
-    
-    info!("Taille du dictionnaire: {} octets", dict_size);
-
-    // Lire le dictionnaire
-    let mut dict = vec![0u8; dict_size];
-    input_file.read_exact(&mut dict)?;
-
-    // Lire les données compressées
-    let mut compressed_data = Vec::new();
-    input_file.read_to_end(&mut compressed_data)?;
-    info!("Données compressées lues: {} octets", compressed_data.len());
-
-    // Décompresser les données
-    let decompressed_data = decode_all(Cursor::new(&compressed_data))?;
-    info!("Données décompressées: {} octets", decompressed_data.len());
-
-    // Parcourir les données décompressées
-    let mut cursor = Cursor::new(decompressed_data);
-    loop {
-        let offset = cursor.position();
-        // Lire le chemin du fichier
-        let mut path_bytes = Vec::new();
-        let mut byte = [0u8; 1];
-        while cursor.read_exact(&mut byte).is_ok() && byte[0] != 0 {
-            path_bytes.push(byte[0]);
-        }
-        if path_bytes.is_empty() {
-            println!("Fin de l'archive à l'offset {}", offset);
-            break; // Fin du fichier
-        }
-        let path_str = String::from_utf8(path_bytes)
-            .map_err(|_| DecompressionError::InvalidFormat)?;
-        println!("Lecture du fichier : {} (offset: {})", path_str, offset);
-        
-        // Sanitize path to prevent path traversal attacks
-        let sanitized_path = sanitize_path(&path_str)?;
-        let file_path = options.output_path.join(&sanitized_path);
-        
-        // Additional security check: ensure the final path is within output directory
-        let canonical_output = options.output_path.canonicalize()
-            .context("Failed to canonicalize output path")?;
-        if let Ok(canonical_file) = file_path.canonicalize() {
-            if !canonical_file.starts_with(&canonical_output) {
-                return Err(DecompressionError::InvalidFormat.into());
-            }
-        }
-        println!("Chemin complet : {:?}", file_path);
-
-        // Créer les dossiers parents si nécessaire
-        if let Some(parent) = file_path.parent() {
-            println!("Création du dossier parent : {:?}", parent);
-            fs::create_dir_all(parent)?;
-        }
-
-        // Lire la taille du fichier (8 octets)
-        let mut size_bytes = [0u8; 8];
-        cursor.read_exact(&mut size_bytes)?;
-        let size = u64::from_le_bytes(size_bytes) as usize;
-        println!("Taille des données : {} octets (offset: {})", size, cursor.position());
-
-        // Lire les données
-        let mut buffer = vec![0u8; size];
-        cursor.read_exact(&mut buffer)?;
-        println!("Lecture de {} octets pour {} (offset après lecture: {})", size, path_str, cursor.position());
-
-        // Écrire le fichier
-        let mut output_file = File::create(&file_path)?;
-        output_file.write_all(&buffer)?;
-        println!("Fichier décompressé avec succès : {:?}", file_path);
-    }
-
-    println!("Décompression terminée avec succès");
-    Ok(())
-}
+use std::fs::{self, File};
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use tracing::{debug, debug_span, info, info_span, warn};
+
+use crate::error::{DecompressionError, PathIoError};
+use crate::report::{Report, WarningKind};
+use crate::paths::{normalize, remove_symlink, resolves_within, sanitize_path, CaseCollision, NormalizationForm, PathMapper};
+use crate::walk::EntryKind;
+use crate::list::{ContainerFormat, ListedEntry, Listing};
+use crate::frames::{decode_frames, decode_threads, frame_offsets, read_frame_table, Frame, UNKNOWN_SIZE};
+use crate::format::{read_path, Codec, CODEC_DELETED, MODE_FILE, MODE_SOLID, MODE_STREAM, PATH_ENCODING_UNIX, ZPP_MAGIC, ZPP_MIN_VERSION, ZPP_VERSION};
+
+pub struct DecompressionOptions {
+    pub input_path: PathBuf,
+    pub output_path: PathBuf,
+    /// Unicode normalization applied to entry names
+    pub normalize: Option<NormalizationForm>,
+    /// Policy for names differing only by case on case-insensitive destinations
+    pub case_collision: CaseCollision,
+    /// Skip entries that cannot be written and record them in the report instead of aborting
+    pub skip_errors: bool,
+    /// List the entries that would be written in the report without touching the destination
+    pub dry_run: bool,
+    /// Solid frames decoded at once, each on its own thread
+    pub threads: usize,
+}
+
+impl Default for DecompressionOptions {
+    fn default() -> Self {
+        Self {
+            input_path: PathBuf::new(),
+            output_path: PathBuf::new(),
+            normalize: None,
+            case_collision: CaseCollision::default(),
+            skip_errors: false,
+            dry_run: false,
+            threads: decode_threads(),
+        }
+    }
+}
+
+pub fn decompress_archive(options: &DecompressionOptions) -> Result<Report, DecompressionError> {
+    let _span = info_span!("decompress", input = %options.input_path.display()).entered();
+    info!("Démarrage de la décompression de {:?}", options.input_path);
+    
+    let input_file = File::open(&options.input_path)
+        .map_err(|e| DecompressionError::io_at(e, &options.input_path))?;
+    let mut reader = BufReader::new(input_file);
+    let (mode, layout) = read_archive_header(&mut reader)?;
+
+    // Créer le dossier de sortie s'il n'existe pas
+    let mapper = if options.dry_run {
+        PathMapper::for_dry_run(options.normalize, options.case_collision)
+    } else {
+        fs::create_dir_all(&options.output_path)
+            .map_err(|e| DecompressionError::io_at(e, &options.output_path))?;
+        println!("Dossier de sortie créé : {:?}", options.output_path);
+        PathMapper::for_destination(&options.output_path, options.normalize, options.case_collision)?
+    };
+
+    let mut writer = EntryWriter {
+        options,
+        mapper,
+        report: Report::default(),
+    };
+    match mode {
+        MODE_STREAM => decompress_stream(&mut reader, &layout, &mut writer)?,
+        MODE_SOLID => decompress_solid(&mut reader, &layout, &mut writer)?,
+        MODE_FILE => {
+            let (path, codec, size) = read_file_header(&mut reader, &layout)?;
+            if !codec.is_supported() {
+                writer.report.skip(&path, DecompressionError::UnsupportedCodec { path: path.clone(), codec });
+            } else {
+                let mut data = Vec::with_capacity(size as usize);
+                codec.decoder(&mut reader)?.read_to_end(&mut data)?;
+                writer.write(&path, &data)?;
+            }
+        }
+        _ => return Err(DecompressionError::InvalidFormat),
+    }
+
+    if !options.dry_run {
+        println!("Décompression terminée avec succès");
+    }
+    Ok(writer.report)
+}
+
+/// Restitue le fichier d'une archive créée par `compress-file` dans `output_path`,
+/// en flux. Renvoie le nombre d'octets écrits.
+pub fn decompress_file(input_path: &Path, output_path: &Path) -> Result<u64, DecompressionError> {
+    let _span = info_span!("decompress_file", input = %input_path.display()).entered();
+    let input_file = File::open(input_path).map_err(|e| DecompressionError::io_at(e, input_path))?;
+    let mut reader = BufReader::new(input_file);
+    let (mode, layout) = read_archive_header(&mut reader)?;
+    if mode != MODE_FILE {
+        return Err(DecompressionError::DecompressionFailed(format!(
+            "{} contient un dossier, à extraire avec decompress",
+            input_path.display()
+        )));
+    }
+    let (path, codec, size) = read_file_header(&mut reader, &layout)?;
+    if !codec.is_supported() {
+        return Err(DecompressionError::UnsupportedCodec { path, codec });
+    }
+
+    let output_file = File::create(output_path).map_err(|e| DecompressionError::io_at(e, output_path))?;
+    let mut output = std::io::BufWriter::new(output_file);
+    let written = std::io::copy(&mut codec.decoder(&mut reader)?, &mut output)?;
+    output.flush()?;
+    if written != size {
+        return Err(DecompressionError::DecompressionFailed(format!("{} octets restitués au lieu de {}", written, size)));
+    }
+    info!(name = %path.display(), size, "Fichier décompressé");
+    Ok(written)
+}
+
+/// Nom, codec et taille d'origine du fichier d'une archive en mode fichier
+pub(crate) fn read_file_header(reader: &mut impl Read, layout: &Layout) -> Result<(PathBuf, Codec, u64), DecompressionError> {
+    let path = read_path(reader, layout.path_encoding)?;
+    let codec = Codec::read_from(reader)?;
+    let mut size = [0u8; 8];
+    reader.read_exact(&mut size)?;
+    Ok((path, codec, u64::from_le_bytes(size)))
+}
+
+/// Disposition d'une version du format, choisie d'après l'en-tête : la lecture
+/// interroge la version pour chaque section plutôt que de comparer des numéros
+pub(crate) struct Layout {
+    pub(crate) version: u32,
+    pub(crate) path_encoding: u8,
+}
+
+impl Layout {
+    /// Chemins préfixés par leur longueur et octet d'encodage dans l'en-tête
+    /// (version 2+) ; de l'UTF-8 terminé par un octet nul auparavant
+    pub(crate) fn length_prefixed_paths(&self) -> bool {
+        self.version >= 2
+    }
+
+    /// Codec de chaque entrée ou des données solid (version 3+) ; zstd auparavant
+    pub(crate) fn codecs(&self) -> bool {
+        self.version >= 3
+    }
+
+    /// Données solid en trames listées dans une table (version 4+) ; un flux
+    /// unique précédé de sa taille auparavant
+    pub(crate) fn frame_table(&self) -> bool {
+        self.version >= 4
+    }
+
+    /// Chemins supprimés depuis le niveau incrémental précédent, après l'index
+    /// solid (version 5+)
+    pub(crate) fn deletions(&self) -> bool {
+        self.version >= 5
+    }
+}
+
+/// Lecture de l'en-tête : mode de compression et disposition
+pub(crate) fn read_archive_header(reader: &mut impl Read) -> Result<(u8, Layout), DecompressionError> {
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if magic != ZPP_MAGIC {
+        return Err(DecompressionError::InvalidFormat);
+    }
+    let mut version_bytes = [0u8; 4];
+    reader.read_exact(&mut version_bytes)?;
+    let version = u32::from_le_bytes(version_bytes);
+    if !(ZPP_MIN_VERSION..=ZPP_VERSION).contains(&version) {
+        return Err(DecompressionError::UnsupportedVersion { found: version, max_supported: ZPP_VERSION });
+    }
+    let mut mode = [0u8; 1];
+    reader.read_exact(&mut mode)?;
+    
+    let mut layout = Layout { version, path_encoding: PATH_ENCODING_UNIX };
+    if layout.length_prefixed_paths() {
+        let mut encoding = [0u8; 1];
+        reader.read_exact(&mut encoding)?;
+        layout.path_encoding = encoding[0];
+    }
+    Ok((mode[0], layout))
+}
+
+/// Inventaire d'une archive sans rien écrire. En mode stream, chaque entrée est
+/// décompressée pour connaître sa taille d'origine.
+pub fn list_archive(path: &Path) -> Result<Listing, DecompressionError> {
+    let input_file = File::open(path).map_err(|e| DecompressionError::io_at(e, path))?;
+    let mut reader = BufReader::new(input_file);
+    let (mode, layout) = read_archive_header(&mut reader)?;
+    
+    let mut entries = Vec::new();
+    let mut stored_size = 0;
+    let mut buffer = [0u8; 8];
+    let format = match mode {
+        MODE_STREAM => {
+            while !reader.fill_buf()?.is_empty() {
+                let path = read_stream_path(&mut reader, &layout)?;
+                let codec = read_codec(&mut reader, &layout)?;
+                reader.read_exact(&mut buffer)?;
+                let compressed_size = u64::from_le_bytes(buffer);
+                stored_size += compressed_size;
+                let mut data = (&mut reader).take(compressed_size);
+                if codec.id == CODEC_DELETED {
+                    debug!(path = %path.display(), "Deleted since the previous level");
+                    std::io::copy(&mut data, &mut std::io::sink())?;
+                    continue;
+                }
+                // La taille d'origine n'est connue qu'en décodant l'entrée
+                if !codec.is_supported() {
+                    warn!(path = %path.display(), %codec, "Entry not listed, unsupported codec");
+                    std::io::copy(&mut data, &mut std::io::sink())?;
+                    continue;
+                }
+                let size = std::io::copy(&mut codec.decoder(&mut data)?, &mut std::io::sink())?;
+                // Le décodeur peut s'arrêter à la fin de la frame zstd
+                std::io::copy(&mut data, &mut std::io::sink())?;
+                entries.push(ListedEntry {
+                    path,
+                    kind: EntryKind::File,
+                    size,
+                    compressed_size: Some(compressed_size),
+                    modified: None,
+                    blocks: None,
+                });
+            }
+            ContainerFormat::Stream
+        }
+        MODE_SOLID => {
+            // Dictionnaire puis trames compressées : seules les tailles sont utiles
+            reader.read_exact(&mut buffer)?;
+            reader.seek_relative(u64::from_le_bytes(buffer) as i64)?;
+            read_codec(&mut reader, &layout)?;
+            let frames = read_solid_frames(&mut reader, &layout)?;
+            stored_size = compressed_size(&frames)?;
+            reader.seek_relative(stored_size as i64)?;
+            
+            for (path, _, length) in read_solid_index(&mut reader, &layout)? {
+                entries.push(ListedEntry {
+                    path,
+                    kind: EntryKind::File,
+                    size: length,
+                    compressed_size: None,
+                    modified: None,
+                    blocks: None,
+                });
+            }
+            ContainerFormat::Solid
+        }
+        MODE_FILE => {
+            let (path, _, size) = read_file_header(&mut reader, &layout)?;
+            stored_size = std::io::copy(&mut reader, &mut std::io::sink())?;
+            entries.push(ListedEntry {
+                path,
+                kind: EntryKind::File,
+                size,
+                compressed_size: Some(stored_size),
+                modified: None,
+                blocks: None,
+            });
+            ContainerFormat::File
+        }
+        _ => return Err(DecompressionError::InvalidFormat),
+    };
+    Ok(Listing { format, entries, stored_size: Some(stored_size) })
+}
+
+/// Écrit le contenu d'une entrée de l'archive sans rien extraire d'autre.
+/// Renvoie le nombre d'octets écrits.
+pub fn read_archive_entry(path: &Path, entry_path: &Path, output: &mut impl Write) -> Result<u64, DecompressionError> {
+    let input_file = File::open(path).map_err(|e| DecompressionError::io_at(e, path))?;
+    let mut reader = BufReader::new(input_file);
+    let (mode, layout) = read_archive_header(&mut reader)?;
+    
+    let mut buffer = [0u8; 8];
+    match mode {
+        MODE_STREAM => {
+            while !reader.fill_buf()?.is_empty() {
+                let path = read_stream_path(&mut reader, &layout)?;
+                let codec = read_codec(&mut reader, &layout)?;
+                reader.read_exact(&mut buffer)?;
+                let compressed_size = u64::from_le_bytes(buffer);
+                if path != entry_path || codec.id == CODEC_DELETED {
+                    reader.seek_relative(compressed_size as i64)?;
+                    continue;
+                }
+                if !codec.is_supported() {
+                    return Err(DecompressionError::UnsupportedCodec { path, codec });
+                }
+                let mut decoder = codec.decoder((&mut reader).take(compressed_size))?;
+                return Ok(std::io::copy(&mut decoder, output)?);
+            }
+        }
+        MODE_SOLID => {
+            // L'index suit les trames : il faut le lire avant de savoir lesquelles décompresser
+            reader.read_exact(&mut buffer)?;
+            reader.seek_relative(u64::from_le_bytes(buffer) as i64)?;
+            let codec = read_codec(&mut reader, &layout)?;
+            let frames = read_solid_frames(&mut reader, &layout)?;
+            let data_start = reader.stream_position()?;
+            let data_end = data_start.checked_add(compressed_size(&frames)?).ok_or(DecompressionError::InvalidFormat)?;
+            reader.seek(SeekFrom::Start(data_end))?;
+            
+            let index = read_solid_index(&mut reader, &layout)?;
+            if let Some((path, start, length)) = index.into_iter().find(|(path, _, _)| path == entry_path) {
+                if !codec.is_supported() {
+                    return Err(DecompressionError::UnsupportedCodec { path, codec });
+                }
+                let out_of_range = || DecompressionError::CorruptIndex(format!("{:?} hors des données", path));
+                let end = start.checked_add(length).ok_or_else(out_of_range)?;
+                // Seules les trames couvrant l'entrée sont lues et décompressées
+                let offsets = frame_offsets(&frames);
+                let first = offsets.partition_point(|&offset| offset <= start).saturating_sub(1);
+                let last = offsets.partition_point(|&offset| offset < end).max(first + 1).min(frames.len());
+                let skipped: u64 = frames[..first].iter().map(|frame| frame.compressed_size).sum();
+                reader.seek(SeekFrom::Start(data_start + skipped))?;
+                let mut batch = Vec::new();
+                for frame in &frames[first..last] {
+                    let mut data = vec![0u8; frame.compressed_size as usize];
+                    reader.read_exact(&mut data)?;
+                    batch.push((*frame, data));
+                }
+                let mut data = Vec::new();
+                for batch in batch.chunks(decode_threads()) {
+                    for decoded in decode_frames(codec, batch) {
+                        data.extend_from_slice(&decoded?);
+                    }
+                }
+                let from = (start - offsets[first]) as usize;
+                let data = data.get(from..from + length as usize).ok_or_else(out_of_range)?;
+                output.write_all(data)?;
+                return Ok(length);
+            }
+        }
+        MODE_FILE => {
+            let (path, codec, _) = read_file_header(&mut reader, &layout)?;
+            if path == entry_path {
+                if !codec.is_supported() {
+                    return Err(DecompressionError::UnsupportedCodec { path, codec });
+                }
+                return Ok(std::io::copy(&mut codec.decoder(&mut reader)?, output)?);
+            }
+        }
+        _ => return Err(DecompressionError::InvalidFormat),
+    }
+    Err(DecompressionError::EntryNotFound { path: entry_path.to_path_buf() })
+}
+
+/// Écriture des entrées dans le dossier de sortie
+struct EntryWriter<'a> {
+    options: &'a DecompressionOptions,
+    mapper: PathMapper,
+    report: Report,
+}
+
+/// Entrées successives : chemin, codec, taille, données
+fn decompress_stream(
+    reader: &mut impl BufRead,
+    layout: &Layout,
+    writer: &mut EntryWriter,
+) -> Result<(), DecompressionError> {
+    loop {
+        if reader.fill_buf()?.is_empty() {
+            break; // Fin de l'archive
+        }
+        
+        // Lire le chemin du fichier et son codec
+        let path = read_stream_path(reader, layout)?;
+        let codec = read_codec(reader, layout)?;
+
+        // Lire la taille des données compressées (8 octets)
+        let mut size_bytes = [0u8; 8];
+        reader.read_exact(&mut size_bytes)?;
+        let size = u64::from_le_bytes(size_bytes) as usize;
+
+        let _span = debug_span!("decompress_file", path = %path.display(), size).entered();
+        let mut compressed = vec![0u8; size];
+        reader.read_exact(&mut compressed)?;
+        if codec.id == CODEC_DELETED {
+            writer.remove(&path)?;
+            continue;
+        }
+        // Une entrée écrite avec un codec inconnu de cette version est ignorée
+        if !codec.is_supported() {
+            writer.report.skip(&path, DecompressionError::UnsupportedCodec { path: path.clone(), codec });
+            continue;
+        }
+        let data = codec.decode(&compressed)?;
+
+        writer.write(&path, &data)?;
+    }
+    Ok(())
+}
+
+/// Dictionnaire, codec, table des trames, trames compressées puis index
+/// (chemin, offset, taille). Les trames sont décompressées par lots en parallèle
+/// et chaque entrée est écrite dès que les trames qui la couvrent sont décodées ;
+/// une trame illisible ne fait perdre que les entrées qui la chevauchent.
+fn decompress_solid<R: BufRead + Seek>(
+    reader: &mut R,
+    layout: &Layout,
+    writer: &mut EntryWriter,
+) -> Result<(), DecompressionError> {
+    let mut buffer = [0u8; 8];
+
+    // Lire la taille du dictionnaire
+    reader.read_exact(&mut buffer)?;
+    let dict_size = u64::from_le_bytes(buffer) as usize;
+    
+    // Validation: taille de dictionnaire raisonnable
+    if dict_size > 100 * 1024 * 1024 { // 100MB max
+        return Err(DecompressionError::InvalidFormat);
+    }
+    info!("Taille du dictionnaire: {} octets", dict_size);
+
+    let mut dict = vec![0u8; dict_size];
+    reader.read_exact(&mut dict)?;
+    let codec = read_codec(reader, layout)?;
+    let frames = read_solid_frames(reader, layout)?;
+    let data_start = reader.stream_position()?;
+    let data_size = compressed_size(&frames)?;
+    info!("{} trame(s), {} octets compressés", frames.len(), data_size);
+
+    // L'index suit les trames : il est lu d'abord pour savoir quand chaque entrée est complète
+    reader.seek(SeekFrom::Start(data_start.checked_add(data_size).ok_or(DecompressionError::InvalidFormat)?))?;
+    let mut index = read_solid_index(reader, layout)?;
+    // Un chemin supprimé n'est jamais aussi dans l'index : l'ordre importe peu
+    for path in read_solid_deletions(reader, layout)? {
+        writer.remove(&path)?;
+    }
+
+    // Codec inconnu : les entrées sont toutes ignorées
+    if !codec.is_supported() {
+        for (path, _, _) in index {
+            writer.report.skip(&path, DecompressionError::UnsupportedCodec { path: path.clone(), codec });
+        }
+        return Ok(());
+    }
+    index.sort_by_key(|(_, start, _)| *start);
+    let mut pending = VecDeque::from(index);
+    reader.seek(SeekFrom::Start(data_start))?;
+
+    // Données décodées de `window_start` à `decoded_end`, plages des trames illisibles
+    let mut window = Vec::new();
+    let mut window_start = 0u64;
+    let mut decoded_end = 0u64;
+    let mut damaged = Vec::new();
+    let threads = writer.options.threads.max(1);
+    for (batch_number, batch) in frames.chunks(threads).enumerate() {
+        let mut compressed = Vec::with_capacity(batch.len());
+        for frame in batch {
+            let mut data = vec![0u8; frame.compressed_size as usize];
+            reader.read_exact(&mut data)?;
+            compressed.push((*frame, data));
+        }
+        let decoded = debug_span!("decompress_frames", frames = batch.len()).in_scope(|| decode_frames(codec, &compressed));
+        for (position, (frame, result)) in batch.iter().zip(decoded).enumerate() {
+            let frame_start = decoded_end;
+            match result {
+                Ok(data) => {
+                    decoded_end += data.len() as u64;
+                    window.extend_from_slice(&data);
+                }
+                Err(e) => {
+                    let number = batch_number * threads + position;
+                    warn!(frame = number, error = %e, "Trame illisible");
+                    // Les entrées terminées avant la trame sont déjà écrites
+                    decoded_end = frame_start.saturating_add(frame.original_size);
+                    damaged.push((frame_start..decoded_end, format!("trame {} illisible : {}", number, e)));
+                    window.clear();
+                    window_start = decoded_end;
+                }
+            }
+
+            // Écrire les entrées désormais complètes
+            while let Some((path, start, length)) = pending.front() {
+                let out_of_range = || DecompressionError::CorruptIndex(format!("{:?} hors des données", path));
+                let end = start.checked_add(*length).ok_or_else(out_of_range)?;
+                if let Some((_, reason)) = damaged.iter().find(|(range, _)| *start < range.end && range.start < end) {
+                    let error = DecompressionError::DecompressionFailed(reason.clone());
+                    writer.report.skip_or_fail(writer.options.skip_errors, path, error)?;
+                } else if end <= decoded_end {
+                    let from = start.checked_sub(window_start).ok_or_else(out_of_range)? as usize;
+                    let data = window.get(from..from + *length as usize).ok_or_else(out_of_range)?;
+                    writer.write(path, data)?;
+                } else {
+                    break;
+                }
+                pending.pop_front();
+            }
+            // Garder seulement ce dont les entrées suivantes ont besoin
+            let keep_from = pending.front().map_or(decoded_end, |(_, start, _)| (*start).min(decoded_end)).max(window_start);
+            window.drain(..(keep_from - window_start) as usize);
+            window_start = keep_from;
+        }
+    }
+    debug!(decoded = decoded_end, "Trames décompressées");
+
+    if let Some((path, _, _)) = pending.front() {
+        return Err(DecompressionError::CorruptIndex(format!("{:?} hors des données", path)));
+    }
+    Ok(())
+}
+
+/// Table des trames (version 4+) ; les versions antérieures écrivaient une trame
+/// unique précédée de sa taille compressée
+pub(crate) fn read_solid_frames(reader: &mut impl Read, layout: &Layout) -> std::io::Result<Vec<Frame>> {
+    if layout.frame_table() {
+        return read_frame_table(reader);
+    }
+    let mut buffer = [0u8; 8];
+    reader.read_exact(&mut buffer)?;
+    Ok(vec![Frame { compressed_size: u64::from_le_bytes(buffer), original_size: UNKNOWN_SIZE }])
+}
+
+/// Taille totale des trames, refusée si la table déborde
+pub(crate) fn compressed_size(frames: &[Frame]) -> Result<u64, DecompressionError> {
+    frames.iter()
+        .try_fold(0u64, |total, frame| total.checked_add(frame.compressed_size))
+        .ok_or(DecompressionError::InvalidFormat)
+}
+
+/// Index du mode solid : chemin, offset et taille de chaque entrée dans les données décompressées
+pub(crate) fn read_solid_index(reader: &mut impl Read, layout: &Layout) -> std::io::Result<Vec<(PathBuf, u64, u64)>> {
+    let mut buffer = [0u8; 8];
+    reader.read_exact(&mut buffer)?;
+    let count = u64::from_le_bytes(buffer);
+    let mut index = Vec::with_capacity(count.min(1 << 20) as usize);
+    for _ in 0..count {
+        let path = read_path(reader, layout.path_encoding)?;
+        let mut range = [0u8; 16];
+        reader.read_exact(&mut range)?;
+        let start = u64::from_le_bytes(range[..8].try_into().unwrap());
+        index.push((path, start, u64::from_le_bytes(range[8..].try_into().unwrap())));
+    }
+    Ok(index)
+}
+
+/// Chemins supprimés depuis le niveau précédent, à la suite de l'index (version 5+)
+pub(crate) fn read_solid_deletions(reader: &mut impl Read, layout: &Layout) -> std::io::Result<Vec<PathBuf>> {
+    if !layout.deletions() {
+        return Ok(Vec::new());
+    }
+    let mut buffer = [0u8; 8];
+    reader.read_exact(&mut buffer)?;
+    let count = u64::from_le_bytes(buffer);
+    let mut deletions = Vec::with_capacity(count.min(1 << 20) as usize);
+    for _ in 0..count {
+        deletions.push(read_path(reader, layout.path_encoding)?);
+    }
+    Ok(deletions)
+}
+
+/// Codec enregistré (version 3+) ; les versions antérieures n'écrivaient que du zstd
+pub(crate) fn read_codec(reader: &mut impl Read, layout: &Layout) -> std::io::Result<Codec> {
+    if layout.codecs() {
+        Codec::read_from(reader)
+    } else {
+        Ok(Codec::zstd(0))
+    }
+}
+
+/// Chemin d'une entrée en mode stream
+pub(crate) fn read_stream_path(reader: &mut impl BufRead, layout: &Layout) -> Result<PathBuf, DecompressionError> {
+    if layout.length_prefixed_paths() {
+        return Ok(read_path(reader, layout.path_encoding)?);
+    }
+    // Version 1 : chemin UTF-8 terminé par un octet nul
+    let mut path_bytes = Vec::new();
+    reader.read_until(0, &mut path_bytes)?;
+    if path_bytes.pop() != Some(0) {
+        return Err(DecompressionError::InvalidFormat);
+    }
+    String::from_utf8(path_bytes).map(PathBuf::from).map_err(|_| DecompressionError::InvalidFormat)
+}
+
+impl EntryWriter<'_> {
+    /// Écrit une entrée ; les échecs sont consignés dans le rapport avec `skip_errors`
+    fn write(&mut self, path: &Path, data: &[u8]) -> Result<(), DecompressionError> {
+        let _span = debug_span!("write", path = %path.display(), size = data.len()).entered();
+        match write_entry(self.options, &mut self.mapper, &mut self.report, path, data) {
+            Ok(()) => Ok(()),
+            Err(e) => self.report.skip_or_fail(self.options.skip_errors, path, e),
+        }
+    }
+
+    /// Retire de la destination un fichier supprimé depuis le niveau incrémental
+    /// précédent ; les dossiers et les chemins absents sont laissés tels quels
+    fn remove(&mut self, path: &Path) -> Result<(), DecompressionError> {
+        let _span = debug_span!("remove", path = %path.display()).entered();
+        match remove_entry(self.options, path) {
+            Ok(()) => Ok(()),
+            Err(e) => self.report.skip_or_fail(self.options.skip_errors, path, e),
+        }
+    }
+}
+
+fn remove_entry(options: &DecompressionOptions, path: &Path) -> Result<(), DecompressionError> {
+    let unsafe_path = || DecompressionError::UnsafePath { path: path.to_path_buf() };
+    let mut relative_path = sanitize_path(path).ok_or_else(unsafe_path)?;
+    if let Some(form) = options.normalize {
+        relative_path = normalize(&relative_path, form);
+    }
+    let file_path = options.output_path.join(relative_path);
+    if options.dry_run || !options.output_path.exists() {
+        return Ok(());
+    }
+    if !resolves_within(&options.output_path.canonicalize()?, &file_path)? {
+        return Err(unsafe_path());
+    }
+    match fs::symlink_metadata(&file_path) {
+        Ok(metadata) if !metadata.is_dir() => {
+            fs::remove_file(&file_path).map_err(|e| DecompressionError::io_at(e, &file_path))?;
+            println!("Fichier supprimé : {:?}", file_path);
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn write_entry(
+    options: &DecompressionOptions,
+    mapper: &mut PathMapper,
+    report: &mut Report,
+    path: &Path,
+    data: &[u8],
+) -> Result<(), DecompressionError> {
+    // Sanitize path to prevent path traversal attacks
+    let unsafe_path = || DecompressionError::UnsafePath { path: path.to_path_buf() };
+    let sanitized_path = sanitize_path(path).ok_or_else(unsafe_path)?;
+    if sanitized_path != path {
+        report.warn(path, WarningKind::PathSanitized, format!("Chemin réécrit en {:?}", sanitized_path));
+    }
+    let Some(mapped_path) = mapper.map(&sanitized_path, false, report)? else {
+        return Ok(());
+    };
+    let file_path = options.output_path.join(mapped_path);
+    
+    // Additional security check: ensure the final path is within output directory
+    // (en simulation, une destination absente ne contient aucun lien)
+    if !options.dry_run || options.output_path.exists() {
+        let canonical_output = options.output_path.canonicalize()?;
+        if !resolves_within(&canonical_output, &file_path)? {
+            return Err(unsafe_path());
+        }
+    }
+    if options.dry_run {
+        report.plan(&file_path, EntryKind::File, data.len() as u64);
+        return Ok(());
+    }
+    remove_symlink(&file_path)?;
+
+    // Créer les dossiers parents si nécessaire
+    if let Some(parent) = file_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    // Écrire le fichier
+    let mut output_file = File::create(&file_path).map_err(|e| DecompressionError::io_at(e, &file_path))?;
+    output_file.write_all(data)?;
+    println!("Fichier décompressé avec succès : {:?} ({} octets)", file_path, data.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compress::{compress_directory, CompressionOptions};
+    use crate::report::PlannedAction;
+    use crate::spill::SpillOptions;
+    use tempfile::tempdir;
+
+    fn roundtrip(solid: bool) {
+        let temp_dir = tempdir().unwrap();
+        let input_dir = temp_dir.path().join("input");
+        fs::create_dir_all(input_dir.join("sub")).unwrap();
+        fs::write(input_dir.join("data.bin"), vec![42u8; 100_000]).unwrap();
+        fs::write(input_dir.join("sub/other.dat"), b"quelques octets").unwrap();
+
+        let archive = temp_dir.path().join("test.zpp");
+        compress_directory(&CompressionOptions {
+            input_path: input_dir.clone(),
+            output_path: archive.clone(),
+            level: 3,
+            solid,
+            // Le mode solid passe ses données sur disque
+            spill: SpillOptions { memory_limit: 4096, ..Default::default() },
+            ..Default::default()
+        }).unwrap();
+
+        let listing = list_archive(&archive).unwrap();
+        let format = if solid { ContainerFormat::Solid } else { ContainerFormat::Stream };
+        assert_eq!(listing.format, format);
+        assert_eq!(listing.entries.len(), 2);
+        assert_eq!(listing.total_size(), 100_000 + 15);
+        assert!(listing.stored_size.unwrap() < listing.total_size());
+        assert_eq!(listing.entries.iter().all(|entry| entry.compressed_size.is_some()), !solid);
+
+        let output_dir = temp_dir.path().join("output");
+        decompress_archive(&DecompressionOptions {
+            input_path: archive,
+            output_path: output_dir.clone(),
+            ..Default::default()
+        }).unwrap();
+
+        assert_eq!(fs::read(output_dir.join("data.bin")).unwrap(), vec![42u8; 100_000]);
+        assert_eq!(fs::read(output_dir.join("sub/other.dat")).unwrap(), b"quelques octets");
+    }
+
+    #[test]
+    fn test_stream_roundtrip() {
+        roundtrip(false);
+    }
+
+    #[test]
+    fn test_solid_roundtrip() {
+        roundtrip(true);
+    }
+
+    #[test]
+    fn test_damaged_frame() {
+        let temp_dir = tempdir().unwrap();
+        let input_dir = temp_dir.path().join("input");
+        fs::create_dir_all(&input_dir).unwrap();
+        // Données peu compressibles : chaque trame occupe une place proche de sa taille
+        let content = |seed: u32| -> Vec<u8> {
+            (0..30_000u32).map(|i| (i.wrapping_add(seed).wrapping_mul(2_654_435_761) >> 13) as u8).collect()
+        };
+        for (name, seed) in [("a.bin", 1), ("b.bin", 2), ("c.bin", 3)] {
+            fs::write(input_dir.join(name), content(seed)).unwrap();
+        }
+
+        let archive = temp_dir.path().join("frames.zpp");
+        compress_directory(&CompressionOptions {
+            input_path: input_dir.clone(),
+            output_path: archive.clone(),
+            level: 3,
+            solid: true,
+            solid_frame_size: 20_000,
+            ..Default::default()
+        }).unwrap();
+
+        // Trames de 20 000 octets : a.bin couvre les trames 0 et 1, b.bin 1 et 2, c.bin 3 et 4
+        let mut bytes = fs::read(&archive).unwrap();
+        let mut reader = &bytes[10..];
+        let dict_size = u64::from_le_bytes(reader[..8].try_into().unwrap()) as usize;
+        reader = &reader[8 + dict_size..];
+        Codec::read_from(&mut reader).unwrap();
+        let frames = read_frame_table(&mut reader).unwrap();
+        assert_eq!(frames.len(), 5);
+        let data_start = bytes.len() - reader.len();
+        let frame_3: u64 = frames[..3].iter().map(|frame| frame.compressed_size).sum();
+        bytes[data_start + frame_3 as usize + frames[3].compressed_size as usize / 2] ^= 0xff;
+        fs::write(&archive, &bytes).unwrap();
+
+        // Lecture d'une entrée seule : seules ses trames sont décodées
+        let mut entry = Vec::new();
+        read_archive_entry(&archive, Path::new("b.bin"), &mut entry).unwrap();
+        assert_eq!(entry, content(2));
+        assert!(read_archive_entry(&archive, Path::new("c.bin"), &mut Vec::new()).is_err());
+
+        let output_dir = temp_dir.path().join("output");
+        let options = DecompressionOptions {
+            input_path: archive,
+            output_path: output_dir.clone(),
+            ..Default::default()
+        };
+        assert!(matches!(decompress_archive(&options), Err(DecompressionError::DecompressionFailed(_))));
+        // Deux trames décodées à la fois : la trame 3 est la seconde de son lot
+        let report = decompress_archive(&DecompressionOptions { skip_errors: true, threads: 2, ..options }).unwrap();
+        assert_eq!(report.skipped.len(), 1);
+        assert_eq!(report.skipped[0].path, Path::new("c.bin"));
+        assert!(report.skipped[0].reason.contains("trame 3"));
+        assert_eq!(fs::read(output_dir.join("a.bin")).unwrap(), content(1));
+        assert_eq!(fs::read(output_dir.join("b.bin")).unwrap(), content(2));
+    }
+
+    #[test]
+    fn test_legacy_solid_frame() {
+        use crate::format::{write_path, PATH_ENCODING_UNIX};
+
+        // Archive de version 3 : une trame unique précédée de sa taille compressée
+        let temp_dir = tempdir().unwrap();
+        let mut archive = ZPP_MAGIC.to_vec();
+        archive.extend_from_slice(&3u32.to_le_bytes());
+        archive.extend_from_slice(&[MODE_SOLID, PATH_ENCODING_UNIX]);
+        archive.extend_from_slice(&0u64.to_le_bytes());
+        Codec::zstd(3).write_to(&mut archive).unwrap();
+        let compressed = zstd::encode_all(&b"premierdeuxieme"[..], 3).unwrap();
+        archive.extend_from_slice(&(compressed.len() as u64).to_le_bytes());
+        archive.extend_from_slice(&compressed);
+        archive.extend_from_slice(&2u64.to_le_bytes());
+        for (path, start, length) in [("un.txt", 0u64, 7u64), ("deux.txt", 7, 8)] {
+            write_path(&mut archive, Path::new(path)).unwrap();
+            archive.extend_from_slice(&start.to_le_bytes());
+            archive.extend_from_slice(&length.to_le_bytes());
+        }
+        let archive_path = temp_dir.path().join("v3.zpp");
+        fs::write(&archive_path, archive).unwrap();
+
+        let output_dir = temp_dir.path().join("output");
+        decompress_archive(&DecompressionOptions {
+            input_path: archive_path.clone(),
+            output_path: output_dir.clone(),
+            ..Default::default()
+        }).unwrap();
+        assert_eq!(fs::read(output_dir.join("un.txt")).unwrap(), b"premier");
+        assert_eq!(fs::read(output_dir.join("deux.txt")).unwrap(), b"deuxieme");
+        let mut entry = Vec::new();
+        read_archive_entry(&archive_path, Path::new("deux.txt"), &mut entry).unwrap();
+        assert_eq!(entry, b"deuxieme");
+        assert_eq!(list_archive(&archive_path).unwrap().stored_size, Some(compressed.len() as u64));
+    }
+
+    #[test]
+    fn test_single_file_roundtrip() {
+        use crate::compress::{compress_file, FileCompressionOptions};
+
+        let temp_dir = tempdir().unwrap();
+        let input = temp_dir.path().join("dump.sql");
+        // Les espaces en fin de ligne sont conservés, contrairement aux entrées d'archive
+        let content = "INSERT INTO t VALUES (1, 'a');   \n".repeat(10_000);
+        fs::write(&input, &content).unwrap();
+        let archive = temp_dir.path().join("dump.sql.zpp");
+        let compressed_size = compress_file(&FileCompressionOptions {
+            input_path: input.clone(),
+            output_path: archive.clone(),
+            level: Some(3),
+            ..Default::default()
+        }).unwrap();
+        assert!(compressed_size < content.len() as u64 / 10);
+
+        let restored = temp_dir.path().join("restored.sql");
+        assert_eq!(decompress_file(&archive, &restored).unwrap(), content.len() as u64);
+        assert_eq!(fs::read_to_string(&restored).unwrap(), content);
+
+        let listing = list_archive(&archive).unwrap();
+        assert_eq!(listing.format, ContainerFormat::File);
+        assert_eq!(listing.entries[0].path, Path::new("dump.sql"));
+        assert_eq!(listing.total_size(), content.len() as u64);
+        assert_eq!(listing.stored_size, listing.entries[0].compressed_size);
+
+        // decompress le restitue sous son nom dans le dossier de sortie
+        let output_dir = temp_dir.path().join("output");
+        decompress_archive(&DecompressionOptions { input_path: archive, output_path: output_dir.clone(), ..Default::default() }).unwrap();
+        assert_eq!(fs::read_to_string(output_dir.join("dump.sql")).unwrap(), content);
+
+        // Un dossier compressé n'est pas accepté
+        let directory_archive = temp_dir.path().join("dir.zpp");
+        compress_directory(&CompressionOptions { input_path: output_dir, output_path: directory_archive.clone(), level: 3, ..Default::default() }).unwrap();
+        assert!(matches!(decompress_file(&directory_archive, &restored), Err(DecompressionError::DecompressionFailed(_))));
+    }
+
+    #[test]
+    fn test_incremental_sequence() {
+        let temp_dir = tempdir().unwrap();
+        let input_dir = temp_dir.path().join("input");
+        fs::create_dir_all(&input_dir).unwrap();
+        fs::write(input_dir.join("a.dat"), b"version 1").unwrap();
+        fs::write(input_dir.join("b.dat"), b"stable").unwrap();
+
+        let snar = temp_dir.path().join("state.snar");
+        let level0 = temp_dir.path().join("level0.zpp");
+        let level1 = temp_dir.path().join("level1.zpp");
+        let options = |output: &PathBuf| CompressionOptions {
+            input_path: input_dir.clone(),
+            output_path: output.clone(),
+            level: 3,
+            listed_incremental: Some(snar.clone()),
+            ..Default::default()
+        };
+
+        compress_directory(&options(&level0)).unwrap();
+        fs::write(input_dir.join("a.dat"), b"version 2 plus longue").unwrap();
+        fs::write(input_dir.join("c.dat"), b"nouveau").unwrap();
+        compress_directory(&options(&level1)).unwrap();
+
+        // Le niveau 1 ne contient que les fichiers nouveaux ou modifiés
+        let only_level1 = temp_dir.path().join("only_level1");
+        decompress_archive(&DecompressionOptions { input_path: level1.clone(), output_path: only_level1.clone(), ..Default::default() }).unwrap();
+        assert!(only_level1.join("a.dat").exists());
+        assert!(only_level1.join("c.dat").exists());
+        assert!(!only_level1.join("b.dat").exists());
+
+        // Restauration dans l'ordre
+        let restored = temp_dir.path().join("restored");
+        for archive in [level0, level1] {
+            decompress_archive(&DecompressionOptions { input_path: archive, output_path: restored.clone(), ..Default::default() }).unwrap();
+        }
+        assert_eq!(fs::read(restored.join("a.dat")).unwrap(), b"version 2 plus longue");
+        assert_eq!(fs::read(restored.join("b.dat")).unwrap(), b"stable");
+        assert_eq!(fs::read(restored.join("c.dat")).unwrap(), b"nouveau");
+    }
+
+    #[test]
+    fn test_incremental_deletions() {
+        for solid in [false, true] {
+            let temp_dir = tempdir().unwrap();
+            let input_dir = temp_dir.path().join("input");
+            fs::create_dir_all(input_dir.join("sub")).unwrap();
+            fs::write(input_dir.join("a.txt"), b"garde").unwrap();
+            fs::write(input_dir.join("b.txt"), b"supprime ensuite").unwrap();
+            fs::write(input_dir.join("sub/c.txt"), b"aussi").unwrap();
+
+            let snar = temp_dir.path().join("state.snar");
+            let level0 = temp_dir.path().join("level0.zpp");
+            let level1 = temp_dir.path().join("level1.zpp");
+            let options = |output: &PathBuf| CompressionOptions {
+                input_path: input_dir.clone(),
+                output_path: output.clone(),
+                level: 3,
+                solid,
+                listed_incremental: Some(snar.clone()),
+                ..Default::default()
+            };
+
+            compress_directory(&options(&level0)).unwrap();
+            fs::remove_file(input_dir.join("b.txt")).unwrap();
+            fs::remove_file(input_dir.join("sub/c.txt")).unwrap();
+            compress_directory(&options(&level1)).unwrap();
+
+            // Les suppressions ne sont pas des entrées à lister
+            assert!(list_archive(&level1).unwrap().entries.is_empty());
+
+            let restored = temp_dir.path().join("restored");
+            for archive in [level0, level1] {
+                decompress_archive(&DecompressionOptions { input_path: archive, output_path: restored.clone(), ..Default::default() }).unwrap();
+            }
+            assert_eq!(fs::read(restored.join("a.txt")).unwrap(), b"garde", "solid: {solid}");
+            assert!(!restored.join("b.txt").exists(), "solid: {solid}");
+            assert!(!restored.join("sub/c.txt").exists(), "solid: {solid}");
+        }
+    }
+
+    #[test]
+    fn test_dry_run() {
+        let temp_dir = tempdir().unwrap();
+        let input_dir = temp_dir.path().join("input");
+        fs::create_dir_all(&input_dir).unwrap();
+        fs::write(input_dir.join("a.txt"), b"nouveau contenu").unwrap();
+        fs::write(input_dir.join("b.txt"), b"autre").unwrap();
+
+        let archive = temp_dir.path().join("test.zpp");
+        compress_directory(&CompressionOptions {
+            input_path: input_dir,
+            output_path: archive.clone(),
+            level: 3,
+            ..Default::default()
+        }).unwrap();
+
+        let output_dir = temp_dir.path().join("output");
+        let options = DecompressionOptions {
+            input_path: archive,
+            output_path: output_dir.clone(),
+            dry_run: true,
+            ..Default::default()
+        };
+        let report = decompress_archive(&options).unwrap();
+        assert!(!output_dir.exists());
+        assert_eq!(report.planned.len(), 2);
+        assert!(report.planned.iter().all(|entry| entry.action == PlannedAction::Create));
+
+        fs::create_dir_all(&output_dir).unwrap();
+        fs::write(output_dir.join("a.txt"), b"ancien").unwrap();
+        let report = decompress_archive(&options).unwrap();
+        let planned = report.planned.iter().find(|entry| entry.path.ends_with("a.txt")).unwrap();
+        assert_eq!(planned.action, PlannedAction::Overwrite);
+        assert_eq!(planned.size, 15);
+        assert_eq!(fs::read(output_dir.join("a.txt")).unwrap(), b"ancien");
+        assert!(!output_dir.join("b.txt").exists());
+    }
+
+    #[test]
+    fn test_typed_errors() {
+        let temp_dir = tempdir().unwrap();
+        let archive = temp_dir.path().join("future.zpp");
+        let mut header = ZPP_MAGIC.to_vec();
+        header.extend_from_slice(&99u32.to_le_bytes());
+        header.extend_from_slice(&[MODE_STREAM, PATH_ENCODING_UNIX]);
+        fs::write(&archive, header).unwrap();
+
+        let options = DecompressionOptions {
+            input_path: archive,
+            output_path: temp_dir.path().join("output"),
+            ..Default::default()
+        };
+        assert!(matches!(decompress_archive(&options), Err(DecompressionError::UnsupportedVersion { found: 99, max_supported: ZPP_VERSION })));
+
+        fs::write(&options.input_path, b"PK\x03\x04").unwrap();
+        assert!(matches!(decompress_archive(&options), Err(DecompressionError::InvalidFormat)));
+    }
+
+    #[test]
+    fn test_previous_versions() {
+        use crate::format::write_path;
+
+        // Les deux versions précédant la version courante restent lisibles
+        let temp_dir = tempdir().unwrap();
+        let data = zstd::encode_all(&b"ancien contenu"[..], 3).unwrap();
+        for version in [ZPP_VERSION - 2, ZPP_VERSION - 1] {
+            let mut archive = ZPP_MAGIC.to_vec();
+            archive.extend_from_slice(&version.to_le_bytes());
+            archive.extend_from_slice(&[MODE_STREAM, PATH_ENCODING_UNIX]);
+            write_path(&mut archive, Path::new("dir/ancien.txt")).unwrap();
+            if version >= 3 {
+                Codec::zstd(3).write_to(&mut archive).unwrap();
+            }
+            archive.extend_from_slice(&(data.len() as u64).to_le_bytes());
+            archive.extend_from_slice(&data);
+            let archive_path = temp_dir.path().join(format!("v{}.zpp", version));
+            fs::write(&archive_path, archive).unwrap();
+
+            let output_dir = temp_dir.path().join(format!("output{}", version));
+            decompress_archive(&DecompressionOptions {
+                input_path: archive_path,
+                output_path: output_dir.clone(),
+                ..Default::default()
+            }).unwrap();
+            assert_eq!(fs::read(output_dir.join("dir/ancien.txt")).unwrap(), b"ancien contenu");
+        }
+    }
+
+    #[test]
+    fn test_unsupported_codec_skipped() {
+        use crate::format::{write_path, PATH_ENCODING_UNIX};
+
+        // Archive d'une version future : une entrée avec un codec inconnu, une en zstd
+        let temp_dir = tempdir().unwrap();
+        let mut archive = ZPP_MAGIC.to_vec();
+        archive.extend_from_slice(&ZPP_VERSION.to_le_bytes());
+        archive.extend_from_slice(&[MODE_STREAM, PATH_ENCODING_UNIX]);
+        let entries = [
+            ("future.dat", Codec { id: 42, ..Codec::zstd(3) }, b"donnees opaques".to_vec()),
+            ("ok.dat", Codec::zstd(3), zstd::encode_all(&b"lisible"[..], 3).unwrap()),
+        ];
+        for (path, codec, data) in &entries {
+            write_path(&mut archive, Path::new(path)).unwrap();
+            codec.write_to(&mut archive).unwrap();
+            archive.extend_from_slice(&(data.len() as u64).to_le_bytes());
+            archive.extend_from_slice(data);
+        }
+        let archive_path = temp_dir.path().join("mixed.zpp");
+        fs::write(&archive_path, archive).unwrap();
+
+        let output_dir = temp_dir.path().join("output");
+        let report = decompress_archive(&DecompressionOptions {
+            input_path: archive_path.clone(),
+            output_path: output_dir.clone(),
+            ..Default::default()
+        }).unwrap();
+        assert_eq!(report.skipped.len(), 1);
+        assert_eq!(report.skipped[0].path, Path::new("future.dat"));
+        assert!(!output_dir.join("future.dat").exists());
+        assert_eq!(fs::read(output_dir.join("ok.dat")).unwrap(), b"lisible");
+
+        let listing = list_archive(&archive_path).unwrap();
+        assert_eq!(listing.entries.len(), 1);
+        let error = read_archive_entry(&archive_path, Path::new("future.dat"), &mut Vec::new()).unwrap_err();
+        assert!(matches!(error, DecompressionError::UnsupportedCodec { .. }));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_non_utf8_names_roundtrip() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let temp_dir = tempdir().unwrap();
+        let input_dir = temp_dir.path().join("input");
+        fs::create_dir_all(&input_dir).unwrap();
+        let name = std::ffi::OsStr::from_bytes(b"caf\xe9.dat");
+        if fs::write(input_dir.join(name), b"latin-1").is_err() {
+            return; // Système de fichiers n'acceptant que l'UTF-8
+        }
+
+        for solid in [false, true] {
+            let archive = temp_dir.path().join("names.zpp");
+            compress_directory(&CompressionOptions {
+                input_path: input_dir.clone(),
+                output_path: archive.clone(),
+                level: 3,
+                solid,
+                ..Default::default()
+            }).unwrap();
+
+            let output_dir = temp_dir.path().join(format!("output-{solid}"));
+            decompress_archive(&DecompressionOptions {
+                input_path: archive,
+                output_path: output_dir.clone(),
+                ..Default::default()
+            }).unwrap();
+            assert_eq!(fs::read(output_dir.join(name)).unwrap(), b"latin-1");
+        }
+    }
+}
@@ -0,0 +1,461 @@
+//! Block devices and disk files for `zippy create-image --device` and `zippy restore-device`
+//!
+//! A device is captured as a single file entry, read in segments. When it
+//! holds an ext2/3/4 filesystem, the block bitmaps tell which blocks are free:
+//! those ranges are not read and are captured as zeros, which deduplicate to a
+//! single block.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+use crate::blockio::read_exact_at;
+use crate::error::{ImageError, PathIoError};
+use crate::image::ImageFile;
+
+/// Amount of the device read by one pipeline job
+pub const SEGMENT_SIZE: u64 = 64 * 1024 * 1024;
+/// Amount of the image written at once when restoring
+const RESTORE_CHUNK: usize = 4 * 1024 * 1024;
+
+const EXT_SUPERBLOCK_OFFSET: u64 = 1024;
+const EXT_MAGIC: u16 = 0xEF53;
+const EXT_COMPAT_SPARSE_SUPER2: u32 = 0x200;
+const EXT_RO_COMPAT_SPARSE_SUPER: u32 = 0x1;
+const EXT_RO_COMPAT_GDT_CSUM: u32 = 0x10;
+const EXT_RO_COMPAT_METADATA_CSUM: u32 = 0x400;
+const EXT_INCOMPAT_META_BG: u32 = 0x10;
+const EXT_INCOMPAT_64BIT: u32 = 0x80;
+const EXT_BG_BLOCK_UNINIT: u16 = 0x2;
+
+pub struct Device {
+    file: File,
+    size: u64,
+    /// Free byte ranges, sorted and disjoint; empty when the filesystem is not recognized
+    free: Vec<Range<u64>>,
+    filesystem: Option<&'static str>,
+    modified: u64,
+}
+
+impl Device {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        // The length of a block device is only known by seeking to its end
+        let size = file.seek(SeekFrom::End(0))?;
+        let modified = file.metadata()?
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let mut device = Self { file, size, free: Vec::new(), filesystem: None, modified };
+        match ext_free_ranges(&device.file, size) {
+            Ok(Some(free)) => {
+                device.free = free;
+                device.filesystem = Some("ext");
+            }
+            Ok(None) => {}
+            Err(e) => warn!(device = %path.display(), error = %e, "Could not read the filesystem, capturing every block"),
+        }
+        info!(
+            device = %path.display(),
+            size,
+            filesystem = device.filesystem.unwrap_or("unknown"),
+            free = device.free_bytes(),
+            "Device opened"
+        );
+        Ok(device)
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Modification time of the device node or file, in seconds since the epoch
+    pub fn modified(&self) -> u64 {
+        self.modified
+    }
+
+    /// Filesystem whose free blocks are skipped, if recognized
+    pub fn filesystem(&self) -> Option<&'static str> {
+        self.filesystem
+    }
+
+    /// Bytes that are not read because the filesystem does not use them
+    pub fn free_bytes(&self) -> u64 {
+        self.free.iter().map(|range| range.end - range.start).sum()
+    }
+
+    /// Ranges of at most [`SEGMENT_SIZE`] covering the device; a single empty one for an empty device
+    pub fn segments(&self) -> Vec<Range<u64>> {
+        let mut segments: Vec<_> = (0..self.size)
+            .step_by(SEGMENT_SIZE as usize)
+            .map(|start| start..(start + SEGMENT_SIZE).min(self.size))
+            .collect();
+        if segments.is_empty() {
+            segments.push(0..0);
+        }
+        segments
+    }
+
+    /// Content of `range`, with free blocks returned as zeros without being read
+    pub fn read_segment(&self, range: Range<u64>) -> io::Result<Vec<u8>> {
+        let mut buffer = vec![0u8; (range.end - range.start) as usize];
+        let mut position = range.start;
+        let first = self.free.partition_point(|free| free.end <= range.start);
+        for free in self.free[first..].iter().take_while(|free| free.start < range.end) {
+            if free.start > position {
+                self.read_into(&mut buffer, range.start, position..free.start)?;
+            }
+            position = position.max(free.end);
+        }
+        if position < range.end {
+            self.read_into(&mut buffer, range.start, position..range.end)?;
+        }
+        Ok(buffer)
+    }
+
+    fn read_into(&self, buffer: &mut [u8], base: u64, range: Range<u64>) -> io::Result<()> {
+        let slice = &mut buffer[(range.start - base) as usize..(range.end - base) as usize];
+        read_exact_at(&self.file, slice, range.start)
+    }
+}
+
+/// Write a file of an image back to a device or disk file; returns the bytes written
+///
+/// `entry_path` may be omitted when the image holds a single file, as images
+/// captured with `--device` do. A device must be at least as large as the
+/// file; a regular file is replaced and left sparse where the content is zero.
+pub fn restore_device(image_path: &Path, device_path: &Path, entry_path: Option<&Path>) -> Result<u64, ImageError> {
+    let mut source = ImageFile::open(image_path, entry_path)?;
+    let size = source.size();
+    let mut device = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(device_path)
+        .map_err(|e| ImageError::io_at(e, device_path))?;
+    // Skipping zeros is only safe on a file emptied first; a device keeps its old content
+    let sparse = device.metadata()?.is_file();
+    if sparse {
+        device.set_len(0)?;
+        device.set_len(size)?;
+    } else {
+        let capacity = device.seek(SeekFrom::End(0))?;
+        if capacity < size {
+            let message = format!("{} holds {} bytes, {} are needed", device_path.display(), capacity, size);
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, message).into());
+        }
+    }
+    info!(image = %image_path.display(), device = %device_path.display(), size, "Restoring device");
+
+    let mut buffer = vec![0u8; RESTORE_CHUNK];
+    let mut offset = 0u64;
+    while offset < size {
+        let length = RESTORE_CHUNK.min((size - offset) as usize);
+        let chunk = &mut buffer[..length];
+        let mut filled = 0;
+        while filled < length {
+            match source.read_at(offset + filled as u64, &mut chunk[filled..])? {
+                0 => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+                read => filled += read,
+            }
+        }
+        if !(sparse && chunk.iter().all(|&byte| byte == 0)) {
+            device.seek(SeekFrom::Start(offset))?;
+            device.write_all(chunk)?;
+        }
+        offset += length as u64;
+    }
+    device.sync_all()?;
+    info!(bytes = size, "Device restored");
+    Ok(size)
+}
+
+/// Path of the device without its directories, used as the name of the captured entry
+pub fn entry_name(device_path: &Path) -> PathBuf {
+    device_path.file_name().map(PathBuf::from).unwrap_or_else(|| PathBuf::from("device"))
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().expect("4 bytes"))
+}
+
+/// Geometry of an ext2/3/4 filesystem, from its superblock
+struct ExtLayout {
+    block_size: u64,
+    blocks: u64,
+    first_data_block: u64,
+    blocks_per_group: u64,
+    groups: u64,
+    desc_size: usize,
+    /// Block group flags are maintained (uninit_bg or metadata_csum)
+    group_flags: bool,
+    sparse_super: bool,
+    sparse_super2: bool,
+    reserved_gdt_blocks: u64,
+    inode_table_blocks: u64,
+}
+
+impl ExtLayout {
+    fn parse(superblock: &[u8]) -> Option<Self> {
+        if u16_at(superblock, 0x38) != EXT_MAGIC {
+            return None;
+        }
+        let log_block_size = u32_at(superblock, 0x18);
+        if log_block_size > 6 {
+            return None;
+        }
+        let block_size = 1024u64 << log_block_size;
+        let compat = u32_at(superblock, 0x5C);
+        let incompat = u32_at(superblock, 0x60);
+        let ro_compat = u32_at(superblock, 0x64);
+        // Descriptors scattered by meta_bg are not handled
+        if incompat & EXT_INCOMPAT_META_BG != 0 {
+            return None;
+        }
+        let is_64bit = incompat & EXT_INCOMPAT_64BIT != 0;
+        let mut blocks = u32_at(superblock, 0x4) as u64;
+        if is_64bit {
+            blocks |= (u32_at(superblock, 0x150) as u64) << 32;
+        }
+        let desc_size = if is_64bit { u16_at(superblock, 0xFE) as usize } else { 32 };
+        let first_data_block = u32_at(superblock, 0x14) as u64;
+        let blocks_per_group = u32_at(superblock, 0x20) as u64;
+        let inodes_per_group = u32_at(superblock, 0x28) as u64;
+        let inode_size = if u32_at(superblock, 0x4C) == 0 { 128 } else { u16_at(superblock, 0x58) as u64 };
+        if desc_size < 32 || blocks_per_group == 0 || blocks_per_group > block_size * 8 || blocks <= first_data_block {
+            return None;
+        }
+        Some(Self {
+            block_size,
+            blocks,
+            first_data_block,
+            blocks_per_group,
+            groups: (blocks - first_data_block).div_ceil(blocks_per_group),
+            desc_size,
+            group_flags: ro_compat & (EXT_RO_COMPAT_GDT_CSUM | EXT_RO_COMPAT_METADATA_CSUM) != 0,
+            sparse_super: ro_compat & EXT_RO_COMPAT_SPARSE_SUPER != 0,
+            sparse_super2: compat & EXT_COMPAT_SPARSE_SUPER2 != 0,
+            reserved_gdt_blocks: u16_at(superblock, 0xCE) as u64,
+            inode_table_blocks: (inodes_per_group * inode_size).div_ceil(block_size),
+        })
+    }
+
+    fn group_start(&self, group: u64) -> u64 {
+        self.first_data_block + group * self.blocks_per_group
+    }
+
+    fn group_blocks(&self, group: u64) -> u64 {
+        self.blocks_per_group.min(self.blocks - self.group_start(group))
+    }
+
+    fn gdt_blocks(&self) -> u64 {
+        (self.groups * self.desc_size as u64).div_ceil(self.block_size)
+    }
+
+    /// Whether the group starts with a copy of the superblock and descriptors
+    fn has_superblock(&self, group: u64) -> bool {
+        if group <= 1 || !self.sparse_super {
+            return true;
+        }
+        [3, 5, 7].iter().any(|&base| {
+            let mut power = base;
+            while power < group {
+                power *= base;
+            }
+            power == group
+        })
+    }
+}
+
+/// Block group descriptor fields used to find allocated blocks
+struct GroupDescriptor {
+    block_bitmap: u64,
+    inode_bitmap: u64,
+    inode_table: u64,
+    flags: u16,
+}
+
+impl GroupDescriptor {
+    fn parse(data: &[u8]) -> Self {
+        let wide = data.len() >= 64;
+        let field = |lo: usize, hi: usize| u32_at(data, lo) as u64 | if wide { (u32_at(data, hi) as u64) << 32 } else { 0 };
+        Self {
+            block_bitmap: field(0x0, 0x20),
+            inode_bitmap: field(0x4, 0x24),
+            inode_table: field(0x8, 0x28),
+            flags: u16_at(data, 0x12),
+        }
+    }
+}
+
+/// Free byte ranges of an ext2/3/4 filesystem, `None` if there is none
+fn ext_free_ranges(file: &File, device_size: u64) -> io::Result<Option<Vec<Range<u64>>>> {
+    let mut superblock = [0u8; 1024];
+    if device_size < EXT_SUPERBLOCK_OFFSET + superblock.len() as u64 {
+        return Ok(None);
+    }
+    read_exact_at(file, &mut superblock, EXT_SUPERBLOCK_OFFSET)?;
+    let Some(layout) = ExtLayout::parse(&superblock) else {
+        return Ok(None);
+    };
+    // A filesystem larger than the device is truncated or not what it seems
+    if layout.blocks * layout.block_size > device_size {
+        return Ok(None);
+    }
+
+    let mut table = vec![0u8; (layout.gdt_blocks() * layout.block_size) as usize];
+    read_exact_at(file, &mut table, (layout.first_data_block + 1) * layout.block_size)?;
+    let descriptors: Vec<GroupDescriptor> = table
+        .chunks_exact(layout.desc_size)
+        .take(layout.groups as usize)
+        .map(GroupDescriptor::parse)
+        .collect();
+
+    let mut free = Vec::new();
+    let mut bitmap = vec![0u8; layout.block_size as usize];
+    for (group, descriptor) in descriptors.iter().enumerate() {
+        let group = group as u64;
+        let uninit = layout.group_flags && descriptor.flags & EXT_BG_BLOCK_UNINIT != 0;
+        if uninit {
+            // The bitmap was never written: every block but the metadata is free
+            if layout.sparse_super2 {
+                continue;
+            }
+            uninit_bitmap(&layout, &descriptors, group, &mut bitmap);
+        } else {
+            if descriptor.block_bitmap >= layout.blocks {
+                return Ok(None);
+            }
+            read_exact_at(file, &mut bitmap, descriptor.block_bitmap * layout.block_size)?;
+        }
+        let start = layout.group_start(group);
+        for bit in 0..layout.group_blocks(group) {
+            if bitmap[(bit / 8) as usize] & (1 << (bit % 8)) == 0 {
+                let offset = (start + bit) * layout.block_size;
+                match free.last_mut() {
+                    Some(Range { end, .. }) if *end == offset => *end += layout.block_size,
+                    _ => free.push(offset..offset + layout.block_size),
+                }
+            }
+        }
+    }
+    Ok(Some(free))
+}
+
+/// Block bitmap of a group whose bitmap is uninitialized: only metadata blocks are in use
+fn uninit_bitmap(layout: &ExtLayout, descriptors: &[GroupDescriptor], group: u64, bitmap: &mut [u8]) {
+    bitmap.fill(0);
+    let start = layout.group_start(group);
+    let end = start + layout.group_blocks(group);
+    let mut mark = |first: u64, count: u64| {
+        for block in first.max(start)..(first + count).min(end) {
+            let bit = block - start;
+            bitmap[(bit / 8) as usize] |= 1 << (bit % 8);
+        }
+    };
+    if layout.has_superblock(group) {
+        mark(start, 1 + layout.gdt_blocks() + layout.reserved_gdt_blocks);
+    }
+    // With flex_bg, the bitmaps and inode tables of other groups may live here
+    for descriptor in descriptors {
+        mark(descriptor.block_bitmap, 1);
+        mark(descriptor.inode_bitmap, 1);
+        mark(descriptor.inode_table, layout.inode_table_blocks);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::image::{create_image, list_image, ImageOptions};
+    use tempfile::tempdir;
+
+    /// ext2 layout with 1 KiB blocks and a single group of 64 blocks
+    fn write_ext(path: &Path, used: &[u64]) -> Vec<u8> {
+        let mut disk = vec![0u8; 64 * 1024];
+        for (index, byte) in disk.iter_mut().enumerate() {
+            *byte = (index % 251) as u8 + 1;
+        }
+        let superblock = &mut disk[1024..2048];
+        superblock.fill(0);
+        superblock[0x4..0x8].copy_from_slice(&64u32.to_le_bytes());
+        superblock[0x14..0x18].copy_from_slice(&1u32.to_le_bytes());
+        superblock[0x20..0x24].copy_from_slice(&8192u32.to_le_bytes());
+        superblock[0x38..0x3A].copy_from_slice(&EXT_MAGIC.to_le_bytes());
+        let descriptor = &mut disk[2048..3072];
+        descriptor.fill(0);
+        descriptor[0..4].copy_from_slice(&3u32.to_le_bytes());
+        let bitmap = &mut disk[3072..4096];
+        bitmap.fill(0);
+        for block in [1, 2, 3].iter().chain(used) {
+            let bit = block - 1;
+            bitmap[(bit / 8) as usize] |= 1 << (bit % 8);
+        }
+        // Bits past the last block are set, as mke2fs does
+        bitmap[8..].fill(0xFF);
+        fs::write(path, &disk).unwrap();
+        disk
+    }
+
+    #[test]
+    fn test_skips_free_ext_blocks() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("disk.img");
+        let disk = write_ext(&path, &[10, 11, 40]);
+
+        let device = Device::open(&path).unwrap();
+        assert_eq!(device.filesystem(), Some("ext"));
+        // Blocks 1-3, 10, 11 and 40 are used; block 0 precedes the filesystem and is always read
+        assert_eq!(device.free, vec![4 * 1024..10 * 1024, 12 * 1024..40 * 1024, 41 * 1024..64 * 1024]);
+        let content = device.read_segment(0..disk.len() as u64).unwrap();
+        for block in 0..64 {
+            let range = block * 1024..(block + 1) * 1024;
+            let used = matches!(block, 0..=3 | 10 | 11 | 40);
+            assert_eq!(content[range.clone()] == disk[range.clone()], used, "block {}", block);
+            if !used {
+                assert!(content[range].iter().all(|&byte| byte == 0));
+            }
+        }
+        assert_eq!(device.read_segment(10 * 1024 + 5..12 * 1024).unwrap(), disk[10 * 1024 + 5..12 * 1024]);
+
+        // Without a superblock, everything is read
+        fs::write(&path, vec![1u8; 5000]).unwrap();
+        let device = Device::open(&path).unwrap();
+        assert_eq!((device.filesystem(), device.free_bytes()), (None, 0));
+        assert_eq!(device.read_segment(0..5000).unwrap(), vec![1u8; 5000]);
+    }
+
+    #[test]
+    fn test_device_round_trip() {
+        let temp_dir = tempdir().unwrap();
+        let disk_path = temp_dir.path().join("disk.img");
+        write_ext(&disk_path, &[20]);
+        let image_path = temp_dir.path().join("disk.zpak");
+        create_image(&ImageOptions {
+            input_path: disk_path.clone(),
+            output_path: image_path.clone(),
+            compression_level: 3,
+            device: true,
+            ..Default::default()
+        })
+        .unwrap();
+        let listing = list_image(&image_path).unwrap();
+        assert_eq!(listing.entries.len(), 1);
+        assert_eq!((listing.entries[0].path.as_path(), listing.entries[0].size), (Path::new("disk.img"), 64 * 1024));
+
+        let expected = Device::open(&disk_path).unwrap().read_segment(0..64 * 1024).unwrap();
+        let restored = temp_dir.path().join("restored.img");
+        fs::write(&restored, vec![9u8; 100_000]).unwrap();
+        assert_eq!(restore_device(&image_path, &restored, None).unwrap(), 64 * 1024);
+        assert_eq!(fs::read(&restored).unwrap(), expected);
+    }
+}
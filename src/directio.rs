@@ -0,0 +1,160 @@
+//! Output written around the page cache, for very large sequential writes
+//!
+//! On Linux the file is opened with `O_DIRECT`: data goes from an aligned buffer
+//! straight to the device in whole multiples of [`ALIGNMENT`], and the unaligned
+//! tail left when the writer is flushed goes through a second, buffered
+//! descriptor; it is written again directly once the buffer fills. macOS turns
+//! caching off for the file with `F_NOCACHE`. Where neither is available, or the
+//! filesystem refuses direct I/O (tmpfs, some network mounts), the output is
+//! written normally.
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use tracing::warn;
+
+/// Alignment of direct writes in memory, in the file and in length; covers the
+/// logical block size of current devices
+pub const ALIGNMENT: usize = 4096;
+
+/// Bytes gathered before each direct write
+#[cfg(target_os = "linux")]
+const BUFFER_SIZE: usize = 8 * 1024 * 1024;
+
+/// Create `path` for writing around the page cache, or normally when that is not possible
+pub fn create(path: &Path) -> io::Result<Box<dyn Write + Send>> {
+    #[cfg(target_os = "linux")]
+    {
+        match DirectWriter::create(path) {
+            Ok(writer) => Ok(Box::new(writer)),
+            Err(e) if e.kind() == io::ErrorKind::InvalidInput => {
+                warn!(path = %path.display(), "Direct I/O not supported by the filesystem, writing through the page cache");
+                Ok(Box::new(File::create(path)?))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        use std::os::unix::io::AsRawFd;
+
+        let file = File::create(path)?;
+        // SAFETY: the descriptor is valid for the duration of the call
+        if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_NOCACHE, 1) } == -1 {
+            warn!(path = %path.display(), error = %io::Error::last_os_error(), "F_NOCACHE refused, writing through the page cache");
+        }
+        Ok(Box::new(file))
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    {
+        warn!("Direct I/O not supported on this platform, writing through the page cache");
+        Ok(Box::new(File::create(path)?))
+    }
+}
+
+/// Sequential writer of an `O_DIRECT` file
+#[cfg(target_os = "linux")]
+pub struct DirectWriter {
+    direct: File,
+    /// Buffered descriptor of the same file, for the unaligned tail
+    tail: File,
+    /// Allocation holding the aligned buffer at `start`
+    memory: Vec<u8>,
+    start: usize,
+    filled: usize,
+    /// File offset of the start of the buffer, always aligned
+    offset: u64,
+}
+
+#[cfg(target_os = "linux")]
+impl DirectWriter {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        let direct = File::options().write(true).create(true).truncate(true).custom_flags(libc::O_DIRECT).open(path)?;
+        let tail = File::options().write(true).open(path)?;
+        let memory = vec![0u8; BUFFER_SIZE + ALIGNMENT];
+        let start = memory.as_ptr().align_offset(ALIGNMENT);
+        Ok(Self { direct, tail, memory, start, filled: 0, offset: 0 })
+    }
+
+    fn buffer(&mut self) -> &mut [u8] {
+        &mut self.memory[self.start..self.start + BUFFER_SIZE]
+    }
+
+    /// Write the whole aligned part of the buffer directly and keep the rest at its start
+    fn write_aligned(&mut self) -> io::Result<()> {
+        use std::os::unix::fs::FileExt;
+
+        let length = self.filled - self.filled % ALIGNMENT;
+        if length == 0 {
+            return Ok(());
+        }
+        let (offset, filled) = (self.offset, self.filled);
+        let buffer = &self.memory[self.start..self.start + length];
+        self.direct.write_all_at(buffer, offset)?;
+        self.buffer().copy_within(length..filled, 0);
+        self.filled -= length;
+        self.offset += length as u64;
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Write for DirectWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let filled = self.filled;
+        let length = buf.len().min(BUFFER_SIZE - filled);
+        self.buffer()[filled..filled + length].copy_from_slice(&buf[..length]);
+        self.filled += length;
+        if self.filled == BUFFER_SIZE {
+            self.write_aligned()?;
+        }
+        Ok(length)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        use std::os::unix::fs::FileExt;
+
+        self.write_aligned()?;
+        if self.filled > 0 {
+            let (offset, filled) = (self.offset, self.filled);
+            let tail = &self.memory[self.start..self.start + filled];
+            self.tail.write_all_at(tail, offset)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for DirectWriter {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unaligned_writes() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("direct.zpak");
+        let data: Vec<u8> = (0..3 * 1024 * 1024 + 777).map(|i| (i % 251) as u8).collect();
+        let mut writer = create(&path).unwrap();
+        // Flushes in the middle leave an unaligned tail that later writes extend
+        for (i, chunk) in data.chunks(1_000_003).enumerate() {
+            writer.write_all(chunk).unwrap();
+            if i % 2 == 0 {
+                writer.flush().unwrap();
+                assert_eq!(std::fs::read(&path).unwrap().len(), (i + 1) * 1_000_003);
+            }
+        }
+        writer.flush().unwrap();
+        drop(writer);
+        assert_eq!(std::fs::read(&path).unwrap(), data);
+    }
+}
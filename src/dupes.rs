@@ -0,0 +1,181 @@
+//! Groups of identical files in a directory or an image, for `zippy dupes`
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use crate::error::{CompressionError, ImageError, PathIoError};
+use crate::image::{calculate_hash, list_image, BlockHash, BLOCK_SIZE};
+use crate::walk::{walk, EntryKind, WalkOptions};
+
+/// Files with the same content
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateGroup {
+    /// Size of each copy
+    pub size: u64,
+    /// Paths relative to the directory or image, sorted
+    pub paths: Vec<PathBuf>,
+}
+
+impl DuplicateGroup {
+    /// Bytes taken by every copy but one
+    pub fn wasted(&self) -> u64 {
+        self.size * (self.paths.len() as u64 - 1)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Duplicates {
+    /// Regular files examined
+    pub files: u64,
+    /// Groups of two files or more, most wasted space first
+    pub groups: Vec<DuplicateGroup>,
+}
+
+impl Duplicates {
+    /// Bytes that deduplicating whole files would save
+    pub fn wasted(&self) -> u64 {
+        self.groups.iter().map(DuplicateGroup::wasted).sum()
+    }
+
+    /// Keep the contents found more than once, ordered for display
+    fn from_contents<K>(files: u64, contents: HashMap<(u64, K), Vec<PathBuf>>) -> Self {
+        let mut groups: Vec<DuplicateGroup> = contents
+            .into_iter()
+            .filter(|(_, paths)| paths.len() > 1)
+            .map(|((size, _), mut paths)| {
+                paths.sort();
+                DuplicateGroup { size, paths }
+            })
+            .collect();
+        groups.sort_by(|a, b| b.wasted().cmp(&a.wasted()).then_with(|| a.paths.cmp(&b.paths)));
+        Self { files, groups }
+    }
+}
+
+/// Walk `input_path` and compare the files sharing a size by the hashes of their
+/// blocks, as an image would cut them. Empty files are never reported, and on Unix
+/// hard links to a file already seen are not counted as copies.
+pub fn find_duplicates(input_path: &Path, walk_options: &WalkOptions) -> Result<Duplicates, CompressionError> {
+    let mut by_size: HashMap<u64, Vec<(PathBuf, PathBuf)>> = HashMap::new();
+    #[cfg(unix)]
+    let mut inodes = std::collections::HashSet::new();
+    let mut files = 0;
+    for entry in walk(input_path, walk_options) {
+        let entry = entry?;
+        if entry.kind != EntryKind::File {
+            continue;
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            if entry.metadata.nlink() > 1 && !inodes.insert((entry.metadata.dev(), entry.metadata.ino())) {
+                continue;
+            }
+        }
+        files += 1;
+        let size = entry.metadata.len();
+        if size > 0 {
+            by_size.entry(size).or_default().push((entry.path, entry.relative_path));
+        }
+    }
+
+    // Only files with a same-sized peer need to be read
+    let mut contents: HashMap<(u64, Vec<BlockHash>), Vec<PathBuf>> = HashMap::new();
+    for (size, candidates) in by_size.into_iter().filter(|(_, candidates)| candidates.len() > 1) {
+        for (path, relative_path) in candidates {
+            let hashes = hash_blocks(&path).map_err(|e| CompressionError::io_at(e, &path))?;
+            contents.entry((size, hashes)).or_default().push(relative_path);
+        }
+    }
+    let duplicates = Duplicates::from_contents(files, contents);
+    info!(files, groups = duplicates.groups.len(), wasted = duplicates.wasted(), "Duplicates found");
+    Ok(duplicates)
+}
+
+/// Group the files of an image by the blocks they reference: within an image,
+/// identical contents are cut into the same blocks
+pub fn image_duplicates(image_path: &Path) -> Result<Duplicates, ImageError> {
+    let listing = list_image(image_path)?;
+    let mut contents: HashMap<(u64, Vec<BlockHash>), Vec<PathBuf>> = HashMap::new();
+    let mut files = 0;
+    for entry in listing.entries.into_iter().filter(|entry| entry.kind == EntryKind::File) {
+        files += 1;
+        if let (true, Some(blocks)) = (entry.size > 0, entry.blocks) {
+            contents.entry((entry.size, blocks)).or_default().push(entry.path);
+        }
+    }
+    Ok(Duplicates::from_contents(files, contents))
+}
+
+/// Hashes of the consecutive `BLOCK_SIZE` blocks of a file
+fn hash_blocks(path: &Path) -> io::Result<Vec<BlockHash>> {
+    let mut file = File::open(path)?;
+    let mut buffer = vec![0u8; BLOCK_SIZE];
+    let mut hashes = Vec::new();
+    loop {
+        let mut filled = 0;
+        while filled < buffer.len() {
+            match file.read(&mut buffer[filled..])? {
+                0 => break,
+                read => filled += read,
+            }
+        }
+        if filled == 0 {
+            return Ok(hashes);
+        }
+        hashes.push(calculate_hash(&buffer[..filled]));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    use crate::image::{create_image, ImageOptions};
+
+    #[test]
+    fn test_duplicate_groups() {
+        let temp_dir = tempdir().unwrap();
+        let input_dir = temp_dir.path().join("input");
+        fs::create_dir_all(input_dir.join("copies")).unwrap();
+        let large: Vec<u8> = (0..BLOCK_SIZE * 2 + 10).map(|i| (i % 251) as u8).collect();
+        fs::write(input_dir.join("large.bin"), &large).unwrap();
+        fs::write(input_dir.join("copies/large.bin"), &large).unwrap();
+        fs::write(input_dir.join("copies/large-2.bin"), &large).unwrap();
+        // Same size, different content
+        let mut other = large.clone();
+        other[BLOCK_SIZE] ^= 1;
+        fs::write(input_dir.join("other.bin"), &other).unwrap();
+        fs::write(input_dir.join("a.txt"), b"texte").unwrap();
+        fs::write(input_dir.join("b.txt"), b"texte").unwrap();
+        fs::write(input_dir.join("empty-1"), b"").unwrap();
+        fs::write(input_dir.join("empty-2"), b"").unwrap();
+
+        let check = |duplicates: &Duplicates| {
+            assert_eq!(duplicates.files, 8);
+            assert_eq!(duplicates.groups.len(), 2);
+            assert_eq!(duplicates.groups[0].paths, [
+                PathBuf::from("copies/large-2.bin"),
+                PathBuf::from("copies/large.bin"),
+                PathBuf::from("large.bin"),
+            ]);
+            assert_eq!(duplicates.groups[0].wasted(), large.len() as u64 * 2);
+            assert_eq!(duplicates.groups[1].paths, [PathBuf::from("a.txt"), PathBuf::from("b.txt")]);
+            assert_eq!(duplicates.wasted(), large.len() as u64 * 2 + 5);
+        };
+        check(&find_duplicates(&input_dir, &WalkOptions::default()).unwrap());
+
+        let image_path = temp_dir.path().join("dupes.zpak");
+        create_image(&ImageOptions {
+            input_path: input_dir,
+            output_path: image_path.clone(),
+            ..Default::default()
+        }).unwrap();
+        check(&image_duplicates(&image_path).unwrap());
+    }
+}
@@ -1,31 +1,166 @@
-use thiserror::Error;
-
-#[derive(Error, Debug)]
-pub enum CompressionError {
-    #[error("Compression failed: {0}")]
-    CompressionFailed(String),
-    
-    #[error("I/O error: {0}")]
-    Io(#[from] std::io::Error),
-    
-    #[error("Dictionary generation failed: {0}")]
-    DictionaryError(String),
-    
-    #[error("Invalid file format")]
-    InvalidFormat,
-    
-    #[error("Path traversal attack detected")]
-    PathTraversal,
-}
-
-#[derive(Error, Debug)]
-pub enum DecompressionError {
-    #[error("I/O error: {0}")]
-    Io(#[from] std::io::Error),
-    
-    #[error("Invalid file format")]
-    InvalidFormat,
-    
-    #[error("Decompression failed: {0}")]
-    DecompressionFailed(String),
-} 
\ No newline at end of file
+use std::io;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+use crate::format::Codec;
+use crate::walk::WalkError;
+
+/// Two entries would be extracted to paths differing only by case
+#[derive(Error, Debug)]
+#[error("Case collision between {} and {}", entry.display(), existing.display())]
+pub struct CaseCollisionError {
+    pub entry: PathBuf,
+    pub existing: PathBuf,
+}
+
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum CompressionError {
+    #[error("Compression failed: {0}")]
+    CompressionFailed(String),
+    
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    
+    #[error("Dictionary generation failed: {0}")]
+    DictionaryError(String),
+    
+    #[error("Invalid file format")]
+    InvalidFormat,
+    
+    #[error("Path traversal attack detected")]
+    PathTraversal,
+    
+    #[error("Permission denied: {}", path.display())]
+    PermissionDenied { path: PathBuf },
+    
+    #[error("Invalid snapshot state file: {}", path.display())]
+    InvalidSnapshot { path: PathBuf },
+}
+
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum DecompressionError {
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    
+    #[error("Invalid file format")]
+    InvalidFormat,
+    
+    #[error("Decompression failed: {0}")]
+    DecompressionFailed(String),
+    
+    #[error("Unsupported archive version {found} (this release reads versions up to {max_supported})")]
+    UnsupportedVersion { found: u32, max_supported: u32 },
+    
+    #[error("Corrupt archive index: {0}")]
+    CorruptIndex(String),
+    
+    #[error("Checksum mismatch: {}", path.display())]
+    ChecksumMismatch { path: PathBuf },
+    
+    #[error("Permission denied: {}", path.display())]
+    PermissionDenied { path: PathBuf },
+    
+    #[error("Unsafe entry path: {}", path.display())]
+    UnsafePath { path: PathBuf },
+    
+    #[error("No such entry: {}", path.display())]
+    EntryNotFound { path: PathBuf },
+    
+    #[error("Unsupported {codec}: {}", path.display())]
+    UnsupportedCodec { path: PathBuf, codec: Codec },
+    
+    #[error(transparent)]
+    CaseCollision(#[from] CaseCollisionError),
+}
+
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum ImageError {
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    
+    #[error("Unsupported image version {found} (this release reads versions up to {max_supported})")]
+    UnsupportedVersion { found: u32, max_supported: u32 },
+    
+    #[error("Corrupt image index: {0}")]
+    CorruptIndex(String),
+    
+    #[error("Hash collision between different blocks: {0} (use --hash-algorithm blake3)")]
+    HashCollision(String),
+    
+    #[error("Referenced image {}: {reason}", path.display())]
+    ExternalImage { path: PathBuf, reason: String },
+    
+    #[error("Manifest verification failed: {0} (use --force to extract anyway)")]
+    ManifestVerification(String),
+    
+    #[error("Checksum mismatch: {}", path.display())]
+    ChecksumMismatch { path: PathBuf },
+    
+    #[error("Permission denied: {}", path.display())]
+    PermissionDenied { path: PathBuf },
+    
+    #[error("Read-back verification of {} failed: {reason}", path.display())]
+    WriteVerification { path: PathBuf, reason: String },
+    
+    #[error("Unsafe entry path: {}", path.display())]
+    UnsafePath { path: PathBuf },
+    
+    #[error("No such entry: {}", path.display())]
+    EntryNotFound { path: PathBuf },
+    
+    #[error("Image holds {files} files: name the one to use")]
+    AmbiguousEntry { files: u64 },
+    
+    #[error("Unsupported {codec}: {}", path.display())]
+    UnsupportedCodec { path: PathBuf, codec: Codec },
+    
+    #[error(transparent)]
+    CaseCollision(#[from] CaseCollisionError),
+}
+
+/// Errors carrying the path of a file that could not be accessed
+pub(crate) trait PathIoError: From<io::Error> {
+    fn permission_denied(path: PathBuf) -> Self;
+
+    /// Report permission failures with the offending path, other I/O errors as-is
+    fn io_at(error: io::Error, path: &Path) -> Self {
+        if error.kind() == io::ErrorKind::PermissionDenied {
+            Self::permission_denied(path.to_path_buf())
+        } else {
+            error.into()
+        }
+    }
+}
+
+impl PathIoError for CompressionError {
+    fn permission_denied(path: PathBuf) -> Self {
+        Self::PermissionDenied { path }
+    }
+}
+
+impl PathIoError for DecompressionError {
+    fn permission_denied(path: PathBuf) -> Self {
+        Self::PermissionDenied { path }
+    }
+}
+
+impl PathIoError for ImageError {
+    fn permission_denied(path: PathBuf) -> Self {
+        Self::PermissionDenied { path }
+    }
+}
+
+impl From<WalkError> for CompressionError {
+    fn from(error: WalkError) -> Self {
+        Self::io_at(error.source, &error.path)
+    }
+}
+
+impl From<WalkError> for ImageError {
+    fn from(error: WalkError) -> Self {
+        Self::io_at(error.source, &error.path)
+    }
+}
@@ -0,0 +1,446 @@
+//! Output size and duration estimates for `--dry-run`, and size predictions
+//! from random blocks for `zippy estimate`
+
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tracing::info;
+use zstd::encode_all;
+
+use crate::compress::PROBE_MIN_SAVING;
+use crate::error::{CompressionError, PathIoError};
+use crate::format::CODEC_SIZE;
+use crate::image::{calculate_hash, BLOCK_SIZE};
+use crate::pipeline::PipelineOptions;
+use crate::profile::{detect_profile, CompressionProfile};
+use crate::testdata::Rng;
+use crate::walk::{walk, EntryKind, WalkOptions};
+
+/// Output format being estimated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EstimateTarget {
+    /// .zpp archive, files compressed independently
+    Stream,
+    /// .zpp archive, all files in one zstd frame
+    Solid,
+    /// Deduplicated .zpak image
+    Image,
+}
+
+pub struct EstimateOptions {
+    pub input_path: PathBuf,
+    pub target: EstimateTarget,
+    pub level: i32,
+    /// Maximum number of bytes read, spread evenly over the input
+    pub sample_size: u64,
+    pub walk: WalkOptions,
+    /// Sizing of the run being estimated
+    pub pipeline: PipelineOptions,
+}
+
+impl Default for EstimateOptions {
+    fn default() -> Self {
+        Self {
+            input_path: PathBuf::new(),
+            target: EstimateTarget::Stream,
+            level: 3,
+            sample_size: 64 * 1024 * 1024,
+            walk: WalkOptions::default(),
+            pipeline: PipelineOptions::default(),
+        }
+    }
+}
+
+/// Figures extrapolated from the sample to the whole input
+#[derive(Debug, Clone)]
+pub struct Estimate {
+    pub files: u64,
+    pub input_size: u64,
+    pub sampled_size: u64,
+    /// Bytes not stored thanks to block deduplication (images only)
+    pub dedup_savings: u64,
+    /// Compressed data plus indexes
+    pub output_size: u64,
+    pub duration: Duration,
+    /// Largest files of the input, relative to it, largest first
+    pub largest: Vec<(PathBuf, u64)>,
+}
+
+/// Number of files listed in [`Estimate::largest`]
+pub const LARGEST_FILES: usize = 5;
+
+struct InputFile {
+    relative_path: PathBuf,
+    path: PathBuf,
+    size: u64,
+    /// Bytes taken by this entry in the index, besides its blocks
+    index_size: u64,
+}
+
+/// Walk `input_path`, compress a sample of it and scale the results to the full input.
+/// Nothing is written.
+pub fn estimate(options: &EstimateOptions) -> Result<Estimate, CompressionError> {
+    let mut files = Vec::new();
+    let mut index_size = 0u64;
+    for entry in walk(&options.input_path, &options.walk) {
+        let entry = entry?;
+        // Path length prefix, size, mtime, kind and block count
+        let entry_index = entry.relative_path.as_os_str().len() as u64 + 33;
+        index_size += entry_index;
+        if entry.kind == EntryKind::File {
+            files.push(InputFile { relative_path: entry.relative_path, path: entry.path, size: entry.metadata.len(), index_size: entry_index });
+        }
+    }
+    let input_size: u64 = files.iter().map(|file| file.size).sum();
+    let mut largest: Vec<(PathBuf, u64)> = files.iter().map(|file| (file.relative_path.clone(), file.size)).collect();
+    largest.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    largest.truncate(LARGEST_FILES);
+
+    let start = Instant::now();
+    let sample = read_sample(&files, input_size, options.sample_size)?;
+    let sampled_size: u64 = sample.iter().map(|data| data.len() as u64).sum();
+
+    let (compressed, duplicated, unique_blocks) = match options.target {
+        EstimateTarget::Stream => {
+            let mut compressed = 0;
+            for data in &sample {
+                compressed += encode_all(&data[..], options.level)?.len() as u64;
+            }
+            (compressed, 0, 0)
+        }
+        EstimateTarget::Solid => (encode_all(&sample.concat()[..], options.level)?.len() as u64, 0, 0),
+        EstimateTarget::Image => {
+            let mut seen = HashSet::new();
+            let (mut compressed, mut duplicated) = (0, 0);
+            for block in sample.iter().flat_map(|data| data.chunks(BLOCK_SIZE)) {
+                if seen.insert(calculate_hash(block)) {
+                    compressed += encode_all(block, options.level)?.len() as u64;
+                } else {
+                    duplicated += block.len() as u64;
+                }
+            }
+            (compressed, duplicated, seen.len() as u64)
+        }
+    };
+    let mut elapsed = start.elapsed();
+    // Only solid archives are compressed as a single stream
+    if options.target != EstimateTarget::Solid {
+        elapsed /= options.pipeline.compress_threads.max(1) as u32;
+    }
+
+    let scale = if sampled_size == 0 { 0.0 } else { input_size as f64 / sampled_size as f64 };
+    let scaled = |value: u64| (value as f64 * scale) as u64;
+
+    let metadata_size = match options.target {
+        EstimateTarget::Image => {
+            let block_refs: u64 = files.iter().map(|file| file.size.div_ceil(BLOCK_SIZE as u64)).sum();
+            index_size + block_refs * 32 + scaled(unique_blocks) * 48
+        }
+        _ => files.iter().map(|file| file.index_size).sum(),
+    };
+
+    let estimate = Estimate {
+        files: files.len() as u64,
+        input_size,
+        sampled_size,
+        dedup_savings: scaled(duplicated),
+        output_size: scaled(compressed) + metadata_size,
+        duration: elapsed.mul_f64(scale),
+        largest,
+    };
+    info!(files = estimate.files, sampled = sampled_size, output = estimate.output_size, "Estimate computed");
+    Ok(estimate)
+}
+
+/// Options of [`predict`]
+pub struct PredictOptions {
+    pub input_path: PathBuf,
+    /// Level for every class; each profile's own level when `None`, as `compress` does
+    pub level: Option<i32>,
+    /// Blocks drawn at random in each class; smaller classes are read whole
+    pub blocks_per_class: u64,
+    /// Same seed, same blocks
+    pub seed: u64,
+    pub walk: WalkOptions,
+}
+
+impl Default for PredictOptions {
+    fn default() -> Self {
+        Self {
+            input_path: PathBuf::new(),
+            level: None,
+            blocks_per_class: 32,
+            seed: 42,
+            walk: WalkOptions::default(),
+        }
+    }
+}
+
+/// Two-sided normal quantile of the bounds, for 95% confidence
+pub const CONFIDENCE_Z: f64 = 1.96;
+
+/// Predicted output of the files sharing a compression profile
+#[derive(Debug, Clone)]
+pub struct ClassPrediction {
+    pub profile: CompressionProfile,
+    pub files: u64,
+    pub input_size: u64,
+    pub sampled_blocks: u64,
+    pub output_size: u64,
+    /// 95% confidence bounds of `output_size`
+    pub low: u64,
+    pub high: u64,
+}
+
+/// Size of a per-file .zpp archive predicted from a sample of blocks
+#[derive(Debug, Clone)]
+pub struct Prediction {
+    /// Largest input first
+    pub classes: Vec<ClassPrediction>,
+    pub input_size: u64,
+    pub output_size: u64,
+    pub low: u64,
+    pub high: u64,
+}
+
+/// Files of a class, with where each starts among the blocks of the class
+struct Class {
+    files: Vec<(PathBuf, u64)>,
+    first_blocks: Vec<u64>,
+    blocks: u64,
+    /// Entry headers in the archive
+    overhead: u64,
+}
+
+/// Walk `input_path`, group files by compression profile, compress
+/// `blocks_per_class` random blocks of each class and extrapolate the archive size.
+/// The compressed size of a class is estimated as its input size times the ratio
+/// of the sampled blocks, with bounds from the variance of that ratio estimator.
+pub fn predict(options: &PredictOptions) -> Result<Prediction, CompressionError> {
+    let mut profiles: HashMap<String, CompressionProfile> = HashMap::new();
+    let mut classes: HashMap<CompressionProfile, Class> = HashMap::new();
+    for entry in walk(&options.input_path, &options.walk) {
+        let entry = entry?;
+        if entry.kind != EntryKind::File {
+            continue;
+        }
+        // One lookup per extension rather than per file
+        let extension = entry.path.extension().map(|ext| ext.to_string_lossy().to_lowercase()).unwrap_or_default();
+        let profile = *profiles.entry(extension).or_insert_with(|| detect_profile(&entry.path));
+        let class = classes.entry(profile).or_insert_with(|| Class { files: Vec::new(), first_blocks: Vec::new(), blocks: 0, overhead: 0 });
+        let size = entry.metadata.len();
+        // Length-prefixed path, codec and compressed size
+        class.overhead += entry.relative_path.as_os_str().len() as u64 + 16 + CODEC_SIZE as u64;
+        class.first_blocks.push(class.blocks);
+        class.blocks += size.div_ceil(BLOCK_SIZE as u64);
+        class.files.push((entry.path, size));
+    }
+
+    // Drawn in walk order, so that a seed always picks the same blocks
+    let mut classes: Vec<(CompressionProfile, Class)> = classes.into_iter().collect();
+    classes.sort_by(|(_, a), (_, b)| a.files[0].0.cmp(&b.files[0].0));
+    let mut rng = Rng::new(options.seed);
+    let mut predictions = Vec::new();
+    for (profile, class) in classes {
+        let level = options.level.unwrap_or(profile.get_compression_level());
+        predictions.push(predict_class(profile, &class, level, options.blocks_per_class, &mut rng)?);
+    }
+    predictions.sort_by_key(|class| std::cmp::Reverse(class.input_size));
+
+    // Classes are sampled independently: their variances add up
+    let output_size: u64 = predictions.iter().map(|class| class.output_size).sum();
+    let margin = predictions.iter()
+        .map(|class| ((class.high - class.output_size) as f64).powi(2))
+        .sum::<f64>()
+        .sqrt() as u64;
+    let prediction = Prediction {
+        input_size: predictions.iter().map(|class| class.input_size).sum(),
+        output_size,
+        low: output_size.saturating_sub(margin),
+        high: output_size + margin,
+        classes: predictions,
+    };
+    info!(input = prediction.input_size, output = prediction.output_size, "Prediction computed");
+    Ok(prediction)
+}
+
+fn predict_class(profile: CompressionProfile, class: &Class, level: i32, wanted: u64, rng: &mut Rng) -> Result<ClassPrediction, CompressionError> {
+    let input_size: u64 = class.files.iter().map(|(_, size)| size).sum();
+    let picked: Vec<u64> = if class.blocks <= wanted {
+        (0..class.blocks).collect()
+    } else {
+        let mut picked = HashSet::new();
+        while (picked.len() as u64) < wanted {
+            picked.insert(rng.below(class.blocks));
+        }
+        let mut picked: Vec<u64> = picked.into_iter().collect();
+        picked.sort_unstable();
+        picked
+    };
+
+    // Original and compressed size of each sampled block
+    let mut samples = Vec::with_capacity(picked.len());
+    for block in &picked {
+        let file = class.first_blocks.partition_point(|&first| first <= *block) - 1;
+        let (path, _) = &class.files[file];
+        let data = read_block(path, (block - class.first_blocks[file]) * BLOCK_SIZE as u64)
+            .map_err(|e| CompressionError::io_at(e, path))?;
+        let compressed = encode_all(&data[..], level)?.len();
+        // Stored as is when compressing does not pay, as `compress` does for already compressed files
+        let stored = compressed >= data.len()
+            || profile == CompressionProfile::AlreadyCompressed && compressed * 100 >= data.len() * (100 - PROBE_MIN_SAVING);
+        samples.push((data.len() as f64, if stored { data.len() } else { compressed } as f64));
+    }
+
+    let n = samples.len() as f64;
+    let original: f64 = samples.iter().map(|(original, _)| original).sum();
+    let ratio = if original == 0.0 { 0.0 } else { samples.iter().map(|(_, compressed)| compressed).sum::<f64>() / original };
+    // Standard error of the ratio estimator, with the finite population correction;
+    // a class read whole is exact
+    let standard_error = if samples.len() < 2 || picked.len() as u64 == class.blocks {
+        0.0
+    } else {
+        let residuals = samples.iter().map(|(original, compressed)| (compressed - ratio * original).powi(2)).sum::<f64>() / (n - 1.0);
+        let mean_original = original / n;
+        ((1.0 - n / class.blocks as f64) * residuals / n).sqrt() / mean_original
+    };
+    let output_size = (ratio * input_size as f64) as u64 + class.overhead;
+    let margin = (CONFIDENCE_Z * standard_error * input_size as f64) as u64;
+    Ok(ClassPrediction {
+        profile,
+        files: class.files.len() as u64,
+        input_size,
+        sampled_blocks: picked.len() as u64,
+        output_size,
+        low: output_size.saturating_sub(margin).max(class.overhead),
+        high: output_size + margin,
+    })
+}
+
+/// Block of a file starting at `offset`, shorter at the end of the file
+fn read_block(path: &Path, offset: u64) -> std::io::Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(offset))?;
+    let mut data = Vec::with_capacity(BLOCK_SIZE);
+    file.take(BLOCK_SIZE as u64).read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// Read about `sample_size` bytes in block-sized pieces spread proportionally over
+/// all files, so that small files are sampled as a group and large ones by prefix
+fn read_sample(files: &[InputFile], input_size: u64, sample_size: u64) -> Result<Vec<Vec<u8>>, CompressionError> {
+    let fraction = if input_size == 0 { 0.0 } else { (sample_size as f64 / input_size as f64).min(1.0) };
+    let mut credit = 0.0;
+    let mut sample = Vec::new();
+
+    for file in files {
+        credit += file.size as f64 * fraction;
+        let wanted = (credit as u64).min(file.size);
+        if wanted == 0 || wanted < file.size.min(BLOCK_SIZE as u64) {
+            continue;
+        }
+        let wanted = wanted.next_multiple_of(BLOCK_SIZE as u64).min(file.size);
+
+        let reader = File::open(&file.path).map_err(|e| CompressionError::io_at(e, &file.path))?;
+        let mut data = Vec::with_capacity(wanted as usize);
+        reader.take(wanted).read_to_end(&mut data).map_err(|e| CompressionError::io_at(e, &file.path))?;
+        credit -= data.len() as f64;
+        sample.push(data);
+    }
+    Ok(sample)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_estimate_image_dedup() {
+        let temp_dir = tempdir().unwrap();
+        let content: Vec<u8> = (0..BLOCK_SIZE * 4).map(|i| (i * 7 % 251) as u8).collect();
+        fs::write(temp_dir.path().join("a.bin"), &content).unwrap();
+        fs::write(temp_dir.path().join("b.bin"), &content).unwrap();
+
+        let estimate = estimate(&EstimateOptions {
+            input_path: temp_dir.path().to_path_buf(),
+            target: EstimateTarget::Image,
+            ..Default::default()
+        }).unwrap();
+
+        assert_eq!(estimate.files, 2);
+        assert_eq!(estimate.input_size, content.len() as u64 * 2);
+        assert_eq!(estimate.sampled_size, estimate.input_size);
+        assert_eq!(estimate.dedup_savings, content.len() as u64);
+        assert!(estimate.output_size < content.len() as u64);
+        // Nothing written next to the input
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn test_estimate_sampling() {
+        let temp_dir = tempdir().unwrap();
+        for i in 0..50 {
+            fs::write(temp_dir.path().join(format!("{i}.txt")), "ligne de texte\n".repeat(1000 + i)).unwrap();
+        }
+
+        let estimate = estimate(&EstimateOptions {
+            input_path: temp_dir.path().to_path_buf(),
+            target: EstimateTarget::Solid,
+            sample_size: 100_000,
+            ..Default::default()
+        }).unwrap();
+
+        assert_eq!(estimate.files, 50);
+        assert!(estimate.sampled_size < estimate.input_size);
+        assert!(estimate.sampled_size >= 100_000 - BLOCK_SIZE as u64);
+        assert!(estimate.output_size < estimate.input_size / 10);
+        assert_eq!(estimate.dedup_savings, 0);
+        assert_eq!(estimate.largest.len(), LARGEST_FILES);
+        assert_eq!(estimate.largest[0], (PathBuf::from("49.txt"), "ligne de texte\n".len() as u64 * 1049));
+        assert!(estimate.largest.windows(2).all(|pair| pair[0].1 >= pair[1].1));
+    }
+
+    #[test]
+    fn test_predict_bounds() {
+        let temp_dir = tempdir().unwrap();
+        let mut rng = Rng::new(7);
+        // Blocks alternately empty and random, so that the sampled ratio varies
+        for i in 0..40u64 {
+            let mut content = vec![0u8; BLOCK_SIZE * 4];
+            for (j, block) in content.chunks_mut(BLOCK_SIZE).enumerate() {
+                if (i as usize + j) % 2 == 1 {
+                    block.iter_mut().for_each(|byte| *byte = rng.below(256) as u8);
+                }
+            }
+            fs::write(temp_dir.path().join(format!("{i}.bin")), &content).unwrap();
+        }
+        for i in 0..5 {
+            fs::write(temp_dir.path().join(format!("{i}.txt")), "ligne de texte\n".repeat(100 + i)).unwrap();
+        }
+
+        let predict_with = |blocks_per_class| predict(&PredictOptions {
+            input_path: temp_dir.path().to_path_buf(),
+            level: Some(1),
+            blocks_per_class,
+            ..Default::default()
+        }).unwrap();
+        // Read whole, the prediction is exact
+        let full = predict_with(u64::MAX);
+        assert_eq!((full.low, full.high), (full.output_size, full.output_size));
+        assert_eq!(full.input_size, BLOCK_SIZE as u64 * 160 + (0..5).map(|i| 15 * (100 + i)).sum::<u64>());
+
+        let sampled = predict_with(24);
+        assert_eq!(sampled.classes.len(), 2);
+        let binary = &sampled.classes[0];
+        assert_eq!((binary.profile, binary.files, binary.sampled_blocks), (CompressionProfile::Binary, 40, 24));
+        assert!(binary.low < binary.output_size && binary.output_size < binary.high);
+        assert!(sampled.low <= full.output_size && full.output_size <= sampled.high);
+        assert_eq!(sampled.classes[1].sampled_blocks, 5);
+        // Same seed, same blocks
+        assert_eq!(predict_with(24).output_size, sampled.output_size);
+    }
+}
@@ -0,0 +1,340 @@
+//! On-disk constants and encodings shared by archive writers and readers
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Magic bytes at the start of every .zpp archive
+pub const ZPP_MAGIC: [u8; 4] = *b"ZPP\0";
+
+/// Current .zpp format version
+///
+/// - 1: paths stored as UTF-8 (null-terminated in stream mode)
+/// - 2: paths stored as length-prefixed raw bytes, path encoding byte in the header
+/// - 3: codec of each entry (stream mode) or of the frame (solid mode) recorded
+/// - 4: solid data split into independent frames listed in a frame table
+/// - 5: paths deleted since the previous level of an incremental archive, as
+///   [`CODEC_DELETED`] entries (stream mode) or a list after the index (solid mode)
+pub const ZPP_VERSION: u32 = 5;
+
+/// Oldest .zpp version this release reads; a new version never drops the
+/// readers of earlier ones
+pub const ZPP_MIN_VERSION: u32 = 1;
+
+/// Entries stored one after another, each compressed independently
+pub const MODE_STREAM: u8 = 0;
+
+/// All entries concatenated, compressed as independent zstd frames, followed by an index
+pub const MODE_SOLID: u8 = 1;
+
+/// A single file: its name, codec and original size, then its data up to the end
+/// of the archive, written and read as a stream (`compress-file`)
+pub const MODE_FILE: u8 = 2;
+
+/// Paths are raw Unix bytes (also used for UTF-8 paths from older versions)
+pub const PATH_ENCODING_UNIX: u8 = 0;
+
+/// Paths are UTF-16LE code units as produced by Windows
+pub const PATH_ENCODING_UTF16: u8 = 1;
+
+/// Refuse to allocate absurd path buffers from corrupt archives
+const MAX_PATH_BYTES: usize = 64 * 1024;
+
+/// Data kept as is
+pub const CODEC_STORED: u8 = 0;
+
+/// Data compressed as a single zstd frame
+pub const CODEC_ZSTD: u8 = 1;
+
+/// No data: the path was deleted since the previous level of an incremental
+/// archive. Readers older than this codec skip the entry like any unknown one.
+pub const CODEC_DELETED: u8 = 2;
+
+/// Trailing whitespace removed from each line before compression; lossy, nothing to undo on decode
+pub const PREPROCESS_TRIM_LINES: u8 = 1;
+
+/// Preprocessing steps this version knows about
+const KNOWN_PREPROCESSING: u8 = PREPROCESS_TRIM_LINES;
+
+/// Entries (or image blocks) smaller than this are stored uncompressed by
+/// default: zstd framing costs more than it saves on them
+pub const DEFAULT_STORE_THRESHOLD: u64 = 200;
+
+/// Bytes of a codec descriptor as written by this version
+pub const CODEC_SIZE: usize = 7;
+
+/// How the stored bytes of an entry or block were produced
+///
+/// Written as a length byte followed by the fields, so a later version can
+/// append fields that older readers skip. Readers that do not support a codec
+/// skip the entries using it instead of failing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Codec {
+    pub id: u8,
+    /// Compression level, informative only
+    pub level: i8,
+    /// Id of the dictionary the data was compressed with, 0 for none
+    pub dictionary: u32,
+    /// `PREPROCESS_*` flags applied before compression
+    pub preprocessing: u8,
+}
+
+impl Codec {
+    /// Data kept as is
+    pub const STORED: Codec = Codec { id: CODEC_STORED, level: 0, dictionary: 0, preprocessing: 0 };
+
+    /// Deletion marker of an incremental archive
+    pub const DELETED: Codec = Codec { id: CODEC_DELETED, level: 0, dictionary: 0, preprocessing: 0 };
+
+    /// zstd without dictionary nor preprocessing
+    pub fn zstd(level: i32) -> Self {
+        Self { id: CODEC_ZSTD, level: level.clamp(i8::MIN.into(), i8::MAX.into()) as i8, dictionary: 0, preprocessing: 0 }
+    }
+
+    pub fn with_preprocessing(mut self, preprocessing: u8) -> Self {
+        self.preprocessing = preprocessing;
+        self
+    }
+
+    /// Whether this version can decode the data
+    pub fn is_supported(&self) -> bool {
+        matches!(self.id, CODEC_STORED | CODEC_ZSTD) && self.dictionary == 0 && self.preprocessing & !KNOWN_PREPROCESSING == 0
+    }
+
+    pub fn to_bytes(&self) -> [u8; CODEC_SIZE] {
+        let mut bytes = [0u8; CODEC_SIZE];
+        bytes[0] = self.id;
+        bytes[1] = self.level as u8;
+        bytes[2..6].copy_from_slice(&self.dictionary.to_le_bytes());
+        bytes[6] = self.preprocessing;
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8; CODEC_SIZE]) -> Self {
+        Self {
+            id: bytes[0],
+            level: bytes[1] as i8,
+            dictionary: u32::from_le_bytes(bytes[2..6].try_into().unwrap()),
+            preprocessing: bytes[6],
+        }
+    }
+
+    /// Write the length byte and the fields
+    pub fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(&[CODEC_SIZE as u8])?;
+        writer.write_all(&self.to_bytes())
+    }
+
+    /// Read a descriptor written by [`Codec::write_to`], by this version or a later one
+    pub fn read_from(reader: &mut impl Read) -> io::Result<Self> {
+        let mut length = [0u8; 1];
+        reader.read_exact(&mut length)?;
+        let length = length[0] as usize;
+        if length < CODEC_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "truncated codec descriptor"));
+        }
+        let mut bytes = [0u8; CODEC_SIZE];
+        reader.read_exact(&mut bytes)?;
+        // Fields added by later versions
+        io::copy(&mut reader.take((length - CODEC_SIZE) as u64), &mut io::sink())?;
+        Ok(Self::from_bytes(&bytes))
+    }
+
+    /// Original content of `data`
+    pub fn decode(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut decoded = Vec::new();
+        self.decoder(data)?.read_to_end(&mut decoded)?;
+        Ok(decoded)
+    }
+
+    /// Reader of the original content of the data read from `reader`
+    pub fn decoder<'a, R: io::BufRead + 'a>(&self, reader: R) -> io::Result<Box<dyn Read + 'a>> {
+        if !self.is_supported() {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, format!("unsupported {}", self)));
+        }
+        match self.id {
+            CODEC_ZSTD => Ok(Box::new(zstd::Decoder::with_buffer(reader)?)),
+            _ => Ok(Box::new(reader)),
+        }
+    }
+}
+
+/// zstd encoder compressing on `threads` worker threads; with a single thread
+/// it compresses in the calling thread
+pub fn zstd_encoder<'a, W: Write>(output: W, level: i32, threads: usize) -> io::Result<zstd::Encoder<'a, W>> {
+    let mut encoder = zstd::Encoder::new(output, level)?;
+    if threads > 1 {
+        encoder.multithread(threads as u32)?;
+    }
+    Ok(encoder)
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.id {
+            CODEC_STORED => write!(f, "codec stored")?,
+            CODEC_ZSTD => write!(f, "codec zstd (level {})", self.level)?,
+            CODEC_DELETED => write!(f, "deleted")?,
+            id => write!(f, "codec {}", id)?,
+        }
+        if self.dictionary != 0 {
+            write!(f, ", dictionary {}", self.dictionary)?;
+        }
+        if self.preprocessing != 0 {
+            write!(f, ", preprocessing {:#04x}", self.preprocessing)?;
+        }
+        Ok(())
+    }
+}
+
+/// Path encoding used by archives created on this platform
+pub fn native_path_encoding() -> u8 {
+    if cfg!(windows) {
+        PATH_ENCODING_UTF16
+    } else {
+        PATH_ENCODING_UNIX
+    }
+}
+
+/// Encode a path losslessly in the native encoding, using `/` as separator
+pub fn encode_path(path: &Path) -> Vec<u8> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        path.as_os_str().as_bytes().to_vec()
+    }
+
+    #[cfg(windows)]
+    {
+        use std::os::windows::ffi::OsStrExt;
+        path.as_os_str()
+            .encode_wide()
+            .map(|unit| if unit == u16::from(b'\\') { u16::from(b'/') } else { unit })
+            .flat_map(|unit| unit.to_le_bytes())
+            .collect()
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    {
+        path.to_string_lossy().replace('\\', "/").into_bytes()
+    }
+}
+
+/// Rebuild a path from its stored bytes. Paths from the same platform family are
+/// reconstructed exactly; foreign encodings fall back to a lossy conversion.
+pub fn decode_path(bytes: &[u8], encoding: u8) -> io::Result<PathBuf> {
+    match encoding {
+        PATH_ENCODING_UNIX => {
+            #[cfg(unix)]
+            {
+                use std::os::unix::ffi::OsStrExt;
+                Ok(PathBuf::from(std::ffi::OsStr::from_bytes(bytes)))
+            }
+
+            #[cfg(not(unix))]
+            {
+                Ok(PathBuf::from(String::from_utf8_lossy(bytes).into_owned()))
+            }
+        }
+        PATH_ENCODING_UTF16 => {
+            if !bytes.len().is_multiple_of(2) {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "odd-length UTF-16 path"));
+            }
+            let units: Vec<u16> = bytes
+                .chunks_exact(2)
+                .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+                .collect();
+
+            #[cfg(windows)]
+            {
+                use std::os::windows::ffi::OsStringExt;
+                Ok(PathBuf::from(std::ffi::OsString::from_wide(&units)))
+            }
+
+            #[cfg(not(windows))]
+            {
+                Ok(PathBuf::from(String::from_utf16_lossy(&units)))
+            }
+        }
+        _ => Err(io::Error::new(io::ErrorKind::InvalidData, format!("unknown path encoding: {encoding}"))),
+    }
+}
+
+/// Write a path as a u64 length followed by its encoded bytes
+pub fn write_path(writer: &mut impl Write, path: &Path) -> io::Result<()> {
+    let bytes = encode_path(path);
+    writer.write_all(&(bytes.len() as u64).to_le_bytes())?;
+    writer.write_all(&bytes)
+}
+
+/// Read a path written by [`write_path`]
+pub fn read_path(reader: &mut impl Read, encoding: u8) -> io::Result<PathBuf> {
+    let mut length = [0u8; 8];
+    reader.read_exact(&mut length)?;
+    let length = u64::from_le_bytes(length) as usize;
+    if length > MAX_PATH_BYTES {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "path length exceeds limit"));
+    }
+
+    let mut bytes = vec![0u8; length];
+    reader.read_exact(&mut bytes)?;
+    decode_path(&bytes, encoding)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_path_roundtrip() {
+        let path = Path::new("dossier/sous-dossier/fichier é.txt");
+        let mut buffer = Vec::new();
+        write_path(&mut buffer, path).unwrap();
+        let decoded = read_path(&mut buffer.as_slice(), native_path_encoding()).unwrap();
+        assert_eq!(decoded, path);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_non_utf8_path_roundtrip() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = Path::new(std::ffi::OsStr::from_bytes(b"latin1-\xe9t\xe9.txt"));
+        let decoded = decode_path(&encode_path(path), PATH_ENCODING_UNIX).unwrap();
+        assert_eq!(decoded, path);
+        assert!(decoded.to_str().is_none());
+    }
+
+    #[test]
+    fn test_foreign_utf16_path() {
+        let bytes: Vec<u8> = "dir/été.txt".encode_utf16().flat_map(|u| u.to_le_bytes()).collect();
+        let decoded = decode_path(&bytes, PATH_ENCODING_UTF16).unwrap();
+        assert_eq!(decoded, Path::new("dir/été.txt"));
+        assert!(decode_path(&bytes[1..], PATH_ENCODING_UTF16).is_err());
+    }
+
+    #[test]
+    fn test_codec_descriptor() {
+        let codec = Codec::zstd(19).with_preprocessing(PREPROCESS_TRIM_LINES);
+        let mut buffer = Vec::new();
+        codec.write_to(&mut buffer).unwrap();
+        // A later version with an extra field, followed by other data
+        let mut extended = vec![CODEC_SIZE as u8 + 2];
+        extended.extend_from_slice(&codec.to_bytes());
+        extended.extend_from_slice(&[0xAA, 0xBB, 0x42]);
+        for bytes in [&buffer[..], &extended[..]] {
+            let mut reader = bytes;
+            assert_eq!(Codec::read_from(&mut reader).unwrap(), codec);
+            assert!(reader.is_empty() || reader == [0x42]);
+        }
+
+        let compressed = zstd::encode_all(&b"contenu"[..], 3).unwrap();
+        assert_eq!(codec.decode(&compressed).unwrap(), b"contenu");
+        assert_eq!(Codec::STORED.decode(b"brut").unwrap(), b"brut");
+        let future = Codec { id: 9, ..Codec::zstd(3) };
+        assert!(!future.is_supported());
+        assert_eq!(future.decode(&compressed).unwrap_err().kind(), io::ErrorKind::Unsupported);
+        assert!(!Codec { dictionary: 7, ..codec }.is_supported());
+        assert!(!codec.with_preprocessing(0x80).is_supported());
+    }
+}
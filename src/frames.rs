@@ -0,0 +1,161 @@
+//! Independent zstd frames of solid archives
+//!
+//! Solid data is cut into frames of a fixed original size, each compressed on
+//! its own and listed in a frame table ahead of the data. Frames are encoded and
+//! decoded in parallel batches, a single entry is read by decoding only the
+//! frames it spans, and a damaged frame only loses the entries overlapping it.
+
+use std::io::{self, Read, Write};
+use std::thread;
+
+use crate::format::{Codec, CODEC_STORED};
+
+/// Original bytes per frame unless configured otherwise
+pub const DEFAULT_FRAME_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Original size of the single frame of archives older than frame tables, unknown
+/// until decoded
+pub const UNKNOWN_SIZE: u64 = u64::MAX;
+
+/// One entry of the frame table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    pub compressed_size: u64,
+    pub original_size: u64,
+}
+
+/// Write the frame count, then the compressed and original size of each frame
+pub fn write_frame_table(writer: &mut impl Write, frames: &[Frame]) -> io::Result<()> {
+    writer.write_all(&(frames.len() as u64).to_le_bytes())?;
+    for frame in frames {
+        writer.write_all(&frame.compressed_size.to_le_bytes())?;
+        writer.write_all(&frame.original_size.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+/// Read a table written by [`write_frame_table`]
+pub fn read_frame_table(reader: &mut impl Read) -> io::Result<Vec<Frame>> {
+    let mut buffer = [0u8; 8];
+    reader.read_exact(&mut buffer)?;
+    let count = u64::from_le_bytes(buffer);
+    let mut frames = Vec::with_capacity(count.min(1 << 20) as usize);
+    for _ in 0..count {
+        reader.read_exact(&mut buffer)?;
+        let compressed_size = u64::from_le_bytes(buffer);
+        reader.read_exact(&mut buffer)?;
+        frames.push(Frame { compressed_size, original_size: u64::from_le_bytes(buffer) });
+    }
+    Ok(frames)
+}
+
+/// Offset of each frame in the original data, followed by the total size
+pub fn frame_offsets(frames: &[Frame]) -> Vec<u64> {
+    let mut offsets = Vec::with_capacity(frames.len() + 1);
+    let mut offset = 0u64;
+    offsets.push(offset);
+    for frame in frames {
+        offset = offset.saturating_add(frame.original_size);
+        offsets.push(offset);
+    }
+    offsets
+}
+
+/// Encode `input` with `codec` as frames of `frame_size` original bytes, `threads`
+/// at a time, writing them to `output` in order
+pub fn compress_frames(mut input: impl Read, frame_size: u64, codec: Codec, threads: usize, output: &mut impl Write) -> io::Result<Vec<Frame>> {
+    let mut frames = Vec::new();
+    loop {
+        let mut batch = Vec::new();
+        while batch.len() < threads.max(1) {
+            let mut chunk = Vec::new();
+            (&mut input).take(frame_size.max(1)).read_to_end(&mut chunk)?;
+            if chunk.is_empty() {
+                break;
+            }
+            batch.push(chunk);
+        }
+        if batch.is_empty() {
+            return Ok(frames);
+        }
+        let compressed = in_parallel(&batch, |chunk| compress_frame(chunk, codec));
+        for (chunk, data) in batch.iter().zip(compressed) {
+            let data = data?;
+            output.write_all(&data)?;
+            frames.push(Frame { compressed_size: data.len() as u64, original_size: chunk.len() as u64 });
+        }
+    }
+}
+
+/// One frame, zstd with its content checksum so that damaged data fails to decode
+/// instead of coming back altered
+fn compress_frame(chunk: &[u8], codec: Codec) -> io::Result<Vec<u8>> {
+    if codec.id == CODEC_STORED {
+        return Ok(chunk.to_vec());
+    }
+    let mut compressor = zstd::bulk::Compressor::new(codec.level.into())?;
+    compressor.set_parameter(zstd::stream::raw::CParameter::ChecksumFlag(true))?;
+    compressor.compress(chunk)
+}
+
+/// Decode each frame of a batch with `codec`, in parallel; a frame whose size
+/// differs from the table is an error
+pub fn decode_frames(codec: Codec, batch: &[(Frame, Vec<u8>)]) -> Vec<io::Result<Vec<u8>>> {
+    in_parallel(batch, |(frame, data)| {
+        let decoded = codec.decode(data)?;
+        if frame.original_size != UNKNOWN_SIZE && decoded.len() as u64 != frame.original_size {
+            let message = format!("frame decoded to {} bytes instead of {}", decoded.len(), frame.original_size);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+        }
+        Ok(decoded)
+    })
+}
+
+/// Threads to decode frames with
+pub fn decode_threads() -> usize {
+    thread::available_parallelism().map_or(1, |threads| threads.get())
+}
+
+/// `work` applied to every item on its own thread, results in item order
+fn in_parallel<T: Sync, R: Send>(items: &[T], work: impl Fn(&T) -> R + Sync) -> Vec<R> {
+    if items.len() == 1 {
+        return vec![work(&items[0])];
+    }
+    thread::scope(|scope| {
+        let handles: Vec<_> = items.iter().map(|item| scope.spawn(|| work(item))).collect();
+        handles.into_iter().map(|handle| handle.join().expect("frame worker panicked")).collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frames_roundtrip() {
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8).collect();
+        let mut compressed = Vec::new();
+        let frames = compress_frames(&data[..], 3000, Codec::zstd(3), 2, &mut compressed).unwrap();
+        assert_eq!(frames.iter().map(|frame| frame.original_size).collect::<Vec<_>>(), [3000, 3000, 3000, 1000]);
+        assert_eq!(frame_offsets(&frames), [0, 3000, 6000, 9000, 10_000]);
+
+        let mut table = Vec::new();
+        write_frame_table(&mut table, &frames).unwrap();
+        assert_eq!(read_frame_table(&mut &table[..]).unwrap(), frames);
+
+        let mut rest = &compressed[..];
+        let mut batch = Vec::new();
+        for frame in &frames {
+            let (data, tail) = rest.split_at(frame.compressed_size as usize);
+            batch.push((*frame, data.to_vec()));
+            rest = tail;
+        }
+        // A damaged frame fails alone
+        let middle = batch[1].1.len() / 2;
+        batch[1].1[middle] ^= 0xff;
+        let decoded = decode_frames(Codec::zstd(3), &batch);
+        assert_eq!(decoded[0].as_deref().unwrap(), &data[..3000]);
+        assert!(decoded[1].is_err());
+        assert_eq!(decoded[3].as_deref().unwrap(), &data[9000..]);
+    }
+}
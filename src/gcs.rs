@@ -0,0 +1,366 @@
+//! Google Cloud Storage backend for `gs://bucket/path` URLs
+//!
+//! Requests carry an OAuth access token obtained for a service account: a JWT
+//! signed with the account's key is exchanged at its token URI, and the token
+//! is reused until shortly before it expires. The key file comes from
+//! `[storage.gcs] credentials` or `GOOGLE_APPLICATION_CREDENTIALS`. Uploads
+//! use resumable sessions, sent in parts that an interrupted upload resumes.
+
+use std::fs;
+use std::io::{self, Read};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use base64::engine::general_purpose::{STANDARD as BASE64, URL_SAFE_NO_PAD as BASE64_URL};
+use base64::Engine;
+use ring::rand::SystemRandom;
+use ring::signature::{RsaKeyPair, RSA_PKCS1_SHA256};
+use serde::Deserialize;
+use tracing::debug;
+
+use crate::config::GcsConfig;
+use crate::http::{self, percent_encode};
+use crate::storage::{ObjectInfo, StorageBackend};
+use crate::throttle::Bandwidth;
+use crate::upload::{self, CompletedPart, MultipartTarget, Part, UploadOptions};
+
+const DEFAULT_ENDPOINT: &str = "https://storage.googleapis.com";
+
+const SCOPE: &str = "https://www.googleapis.com/auth/devstorage.read_write";
+
+/// Tokens are renewed this long before they expire
+const TOKEN_MARGIN: Duration = Duration::from_secs(60);
+
+/// Parts of a resumable upload are multiples of this size, except the last one
+const CHUNK_GRANULARITY: u64 = 256 * 1024;
+
+pub struct GcsBackend {
+    endpoint: String,
+    bucket: String,
+    account: ServiceAccount,
+    agent: ureq::Agent,
+    bandwidth: Bandwidth,
+    upload: UploadOptions,
+}
+
+impl GcsBackend {
+    pub fn new(config: &GcsConfig, bucket: &str) -> io::Result<Self> {
+        let credentials = config
+            .credentials
+            .clone()
+            .or_else(|| std::env::var_os("GOOGLE_APPLICATION_CREDENTIALS").map(PathBuf::from))
+            .ok_or_else(|| {
+                let message = "Google Cloud Storage needs a service account key: storage.gcs.credentials in config.toml, \
+                               ZIPPY_STORAGE__GCS__CREDENTIALS or GOOGLE_APPLICATION_CREDENTIALS";
+                io::Error::new(io::ErrorKind::InvalidInput, message)
+            })?;
+        if bucket.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "gs:// URLs start with a bucket name"));
+        }
+        let upload = UploadOptions::default();
+        debug_assert_eq!(upload.part_size % CHUNK_GRANULARITY, 0);
+        Ok(Self {
+            endpoint: config.endpoint.as_deref().unwrap_or(DEFAULT_ENDPOINT).trim_end_matches('/').to_string(),
+            bucket: bucket.to_string(),
+            account: ServiceAccount::load(&credentials)?,
+            agent: http::agent(),
+            bandwidth: Bandwidth::default(),
+            upload,
+        })
+    }
+
+    /// Pace transfers within `bandwidth`
+    pub fn with_bandwidth(mut self, bandwidth: Bandwidth) -> Self {
+        self.bandwidth = bandwidth;
+        self
+    }
+
+    /// Backend and object name of the part of a URL after `gs://`
+    pub fn from_url(rest: &str, config: &GcsConfig) -> io::Result<(Self, String)> {
+        let (bucket, name) = rest.split_once('/').unwrap_or((rest, ""));
+        Ok((Self::new(config, bucket)?, name.trim_matches('/').to_string()))
+    }
+
+    /// JSON API URL of an object, or of the bucket's objects when `name` is empty
+    fn object_url(&self, name: &str) -> String {
+        let objects = format!("{}/storage/v1/b/{}/o", self.endpoint, percent_encode(&self.bucket, false));
+        if name.is_empty() {
+            objects
+        } else {
+            format!("{}/{}", objects, percent_encode(name, false))
+        }
+    }
+
+    /// Authorized request
+    fn request(&self, method: &str, url: &str) -> io::Result<ureq::Request> {
+        let token = self.account.token(&self.agent)?;
+        Ok(self.agent.request(method, url).set("Authorization", &format!("Bearer {}", token)))
+    }
+
+    /// Run a request with the retry policy of uploads
+    fn retried<T>(&self, what: &str, request: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+        self.upload.retry.run(what, &mut 0, request)
+    }
+}
+
+impl StorageBackend for GcsBackend {
+    fn open(&self, name: &str) -> io::Result<Box<dyn Read + Send>> {
+        let url = format!("{}?alt=media", self.object_url(name));
+        let response = self.retried("get object", || self.request("GET", &url)?.call().map_err(|e| http::request_error(name, e)))?;
+        Ok(Box::new(self.bandwidth.download(response.into_reader())))
+    }
+
+    fn read_range(&self, name: &str, range: Range<u64>) -> io::Result<Vec<u8>> {
+        if range.is_empty() {
+            return Ok(Vec::new());
+        }
+        let url = format!("{}?alt=media", self.object_url(name));
+        let header = format!("bytes={}-{}", range.start, range.end - 1);
+        self.retried("get object range", || {
+            let response = match self.request("GET", &url)?.set("Range", &header).call().map_err(|e| http::request_error(name, e)) {
+                // The range starts after the end of the object
+                Err(e) if http::status(&e) == Some(416) => return Ok(Vec::new()),
+                response => response?,
+            };
+            let mut data = Vec::new();
+            self.bandwidth.download(response.into_reader()).read_to_end(&mut data)?;
+            Ok(data)
+        })
+    }
+
+    fn write(&self, name: &str, source: &Path) -> io::Result<()> {
+        let size = fs::metadata(source)?.len();
+        let mut target = ResumableUpload { backend: self, name, size };
+        let summary = upload::upload_file(source, &mut target, &self.upload)?;
+        debug!(object = name, parts = summary.parts, resumed = summary.resumed_parts, retries = summary.retries, "Object uploaded");
+        Ok(())
+    }
+
+    fn list(&self, prefix: &str) -> io::Result<Vec<ObjectInfo>> {
+        let mut objects = Vec::new();
+        let mut page_token: Option<String> = None;
+        loop {
+            let mut url = format!("{}?prefix={}&fields=items(name,size),nextPageToken", self.object_url(""), percent_encode(prefix, false));
+            if let Some(token) = &page_token {
+                url.push_str(&format!("&pageToken={}", percent_encode(token, false)));
+            }
+            let body = self.retried("list objects", || {
+                self.request("GET", &url)?.call().map_err(|e| http::request_error("list objects", e))?.into_string()
+            })?;
+            let page = parse_object_list(&body)?;
+            objects.extend(page.0);
+            match page.1 {
+                Some(token) => page_token = Some(token),
+                None => break,
+            }
+        }
+        objects.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(objects)
+    }
+
+    fn delete(&self, name: &str) -> io::Result<()> {
+        let url = self.object_url(name);
+        self.retried("delete object", || {
+            self.request("DELETE", &url)?.call().map_err(|e| http::request_error(name, e))?;
+            Ok(())
+        })
+    }
+}
+
+/// Objects of a list page, and the token of the next page
+fn parse_object_list(body: &str) -> io::Result<(Vec<ObjectInfo>, Option<String>)> {
+    #[derive(Deserialize)]
+    struct Page {
+        #[serde(default)]
+        items: Vec<Item>,
+        #[serde(rename = "nextPageToken")]
+        next_page_token: Option<String>,
+    }
+    #[derive(Deserialize)]
+    struct Item {
+        name: String,
+        /// Decimal string
+        size: String,
+    }
+    let page: Page = serde_json::from_str(body).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let objects = page
+        .items
+        .into_iter()
+        .map(|item| {
+            let size = item.size.parse().map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("invalid object size: {}", item.size)))?;
+            Ok(ObjectInfo { name: item.name, size })
+        })
+        .collect::<io::Result<_>>()?;
+    Ok((objects, page.next_page_token))
+}
+
+/// `Content-Range` of a part of an upload of `size` bytes
+fn content_range(part: &Part, size: u64) -> String {
+    if part.length == 0 {
+        format!("bytes */{}", size)
+    } else {
+        format!("bytes {}-{}/{}", part.offset, part.offset + part.length - 1, size)
+    }
+}
+
+/// Resumable upload session of one object; the upload id is the session URI
+struct ResumableUpload<'a> {
+    backend: &'a GcsBackend,
+    name: &'a str,
+    size: u64,
+}
+
+impl MultipartTarget for ResumableUpload<'_> {
+    fn destination(&self) -> String {
+        format!("gs://{}/{}", self.backend.bucket, self.name)
+    }
+
+    /// Sessions take any number of parts
+    fn max_parts(&self) -> u32 {
+        u32::MAX
+    }
+
+    fn create(&mut self, size: u64) -> io::Result<String> {
+        let url = format!(
+            "{}/upload/storage/v1/b/{}/o?uploadType=resumable&name={}",
+            self.backend.endpoint,
+            percent_encode(&self.backend.bucket, false),
+            percent_encode(self.name, false)
+        );
+        let response = self
+            .backend
+            .request("POST", &url)?
+            .set("X-Upload-Content-Type", "application/octet-stream")
+            .set("X-Upload-Content-Length", &size.to_string())
+            .send_bytes(&[])
+            .map_err(|e| http::request_error(self.name, e))?;
+        response
+            .header("Location")
+            .map(str::to_string)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "resumable upload started without a session URI"))
+    }
+
+    /// Bytes the session already holds are ignored, so a part can be sent again after a failure
+    fn upload_part(&mut self, session: &str, part: &Part, data: &[u8]) -> io::Result<String> {
+        let request = self.backend.agent.put(session).set("Content-Range", &content_range(part, self.size));
+        let response = http::send_bytes(self.name, request, data, &self.backend.bandwidth)?;
+        let last = part.offset + part.length == self.size;
+        // 308 Resume Incomplete acknowledges an intermediate part
+        match response.status() {
+            308 if !last => Ok(response.header("Range").unwrap_or_default().to_string()),
+            200 | 201 if last => Ok(String::new()),
+            status => Err(io::Error::other(format!("{}: unexpected HTTP {} for part {}", self.name, status, part.number))),
+        }
+    }
+
+    /// The last part finalizes the object
+    fn complete(&mut self, _session: &str, _parts: &[CompletedPart]) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn abort(&mut self, session: &str) -> io::Result<()> {
+        // Cancelled sessions answer 499
+        match self.backend.agent.delete(session).call() {
+            Ok(_) | Err(ureq::Error::Status(499, _)) => Ok(()),
+            Err(e) => Err(http::request_error(self.name, e)),
+        }
+    }
+}
+
+/// Service account key file fields used to get tokens
+#[derive(Deserialize)]
+struct ServiceAccountKey {
+    client_email: String,
+    private_key: String,
+    token_uri: String,
+}
+
+struct ServiceAccount {
+    email: String,
+    token_uri: String,
+    key: RsaKeyPair,
+    /// Access token and when to renew it
+    token: Mutex<Option<(String, Instant)>>,
+}
+
+impl ServiceAccount {
+    fn load(path: &Path) -> io::Result<Self> {
+        let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), what));
+        let key: ServiceAccountKey = serde_json::from_slice(&fs::read(path)?).map_err(|e| invalid(&e.to_string()))?;
+        let der: String = key.private_key.lines().filter(|line| !line.starts_with("-----")).collect();
+        let der = BASE64.decode(der.trim()).map_err(|_| invalid("private key is not PEM"))?;
+        let pair = RsaKeyPair::from_pkcs8(&der).map_err(|e| invalid(&format!("unusable private key ({})", e)))?;
+        Ok(Self { email: key.client_email, token_uri: key.token_uri, key: pair, token: Mutex::new(None) })
+    }
+
+    /// Current access token, fetched when missing or about to expire
+    fn token(&self, agent: &ureq::Agent) -> io::Result<String> {
+        let mut cached = self.token.lock().unwrap();
+        if let Some((token, renew_at)) = cached.as_ref() {
+            if Instant::now() < *renew_at {
+                return Ok(token.clone());
+            }
+        }
+        #[derive(Deserialize)]
+        struct TokenResponse {
+            access_token: String,
+            expires_in: u64,
+        }
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let assertion = self.assertion(now)?;
+        let response = agent
+            .post(&self.token_uri)
+            .send_form(&[("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"), ("assertion", &assertion)])
+            .map_err(|e| http::request_error("service account token", e))?;
+        let response: TokenResponse = serde_json::from_str(&response.into_string()?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        debug!(account = %self.email, expires_in = response.expires_in, "Access token obtained");
+        let renew_at = Instant::now() + Duration::from_secs(response.expires_in).saturating_sub(TOKEN_MARGIN);
+        *cached = Some((response.access_token.clone(), renew_at));
+        Ok(response.access_token)
+    }
+
+    /// JWT asking the token URI for a one-hour token
+    fn assertion(&self, now: u64) -> io::Result<String> {
+        let mut jwt = format!("{}.{}", BASE64_URL.encode(r#"{"alg":"RS256","typ":"JWT"}"#), BASE64_URL.encode(jwt_claims(&self.email, &self.token_uri, now)));
+        let mut signature = vec![0; self.key.public().modulus_len()];
+        self.key
+            .sign(&RSA_PKCS1_SHA256, &SystemRandom::new(), jwt.as_bytes(), &mut signature)
+            .map_err(|_| io::Error::other("failed to sign the token request"))?;
+        jwt.push('.');
+        jwt.push_str(&BASE64_URL.encode(signature));
+        Ok(jwt)
+    }
+}
+
+fn jwt_claims(email: &str, token_uri: &str, now: u64) -> String {
+    serde_json::json!({ "iss": email, "scope": SCOPE, "aud": token_uri, "iat": now, "exp": now + 3600 }).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_object_list() {
+        let body = r#"{"items": [{"name": "repo/a.zpak", "size": "1024"}, {"name": "repo/b.zpak", "size": "0"}], "nextPageToken": "CgtyZXBvL2IuenBhaw=="}"#;
+        let (objects, next) = parse_object_list(body).unwrap();
+        assert_eq!(objects, vec![ObjectInfo { name: "repo/a.zpak".to_string(), size: 1024 }, ObjectInfo { name: "repo/b.zpak".to_string(), size: 0 }]);
+        assert_eq!(next.as_deref(), Some("CgtyZXBvL2IuenBhaw=="));
+        assert_eq!(parse_object_list("{}").unwrap(), (Vec::new(), None));
+        assert!(parse_object_list(r#"{"items": [{"name": "x", "size": "big"}]}"#).is_err());
+    }
+
+    #[test]
+    fn test_upload_requests() {
+        let part = |offset, length| Part { number: 1, offset, length, sha256: [0; 32] };
+        assert_eq!(content_range(&part(0, 262_144), 300_000), "bytes 0-262143/300000");
+        assert_eq!(content_range(&part(262_144, 37_856), 300_000), "bytes 262144-299999/300000");
+        assert_eq!(content_range(&part(0, 0), 0), "bytes */0");
+
+        let claims: serde_json::Value = serde_json::from_str(&jwt_claims("zippy@project.iam.gserviceaccount.com", "https://oauth2.googleapis.com/token", 1_700_000_000)).unwrap();
+        assert_eq!(claims["aud"], "https://oauth2.googleapis.com/token");
+        assert_eq!(claims["exp"], 1_700_003_600);
+        assert_eq!(claims["scope"], SCOPE);
+    }
+}
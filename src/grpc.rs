@@ -0,0 +1,621 @@
+//! gRPC service of `proto/zippypack.proto` for `zippy grpc-serve`
+//!
+//! Same jobs and paths as the HTTP API of `zippy serve`, with the progress of a
+//! job and the content of an entry streamed as they come. Every path is relative
+//! to the served root and may not leave it.
+//!
+//! The messages below mirror the proto file field for field; the service stubs
+//! are generated by `build.rs`.
+
+// Handlers return tonic's own `Status`, whatever its size
+#![allow(clippy::result_large_err)]
+
+use std::future::Future;
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tonic::{Request, Response, Status};
+use tracing::{debug, info};
+
+use crate::daemon::{self, run_queue, JobQueue, JobSpec};
+use crate::decompress::{list_archive, read_archive_entry};
+use crate::error::{DecompressionError, ImageError};
+use crate::image::{list_image, read_image_entry};
+use crate::list::{self, is_archive, ContainerFormat};
+use crate::metrics::Metrics;
+use crate::paths::stays_within;
+use crate::report::Report;
+
+include!(concat!(env!("OUT_DIR"), "/zippypack.v1.Zippy.rs"));
+
+pub use zippy_client::ZippyClient;
+pub use zippy_server::ZippyServer;
+
+/// Time between two progress messages of `Watch`
+const WATCH_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Bytes of entry content per `Chunk`
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Job {
+    #[prost(oneof = "job::Command", tags = "1, 2, 3, 4")]
+    pub command: Option<job::Command>,
+}
+
+pub mod job {
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum Command {
+        #[prost(message, tag = "1")]
+        Compress(super::Compress),
+        #[prost(message, tag = "2")]
+        Decompress(super::Decompress),
+        #[prost(message, tag = "3")]
+        CreateImage(super::CreateImage),
+        #[prost(message, tag = "4")]
+        ExtractImage(super::ExtractImage),
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Compress {
+    #[prost(string, tag = "1")]
+    pub input: String,
+    #[prost(string, tag = "2")]
+    pub output: String,
+    #[prost(int32, optional, tag = "3")]
+    pub level: Option<i32>,
+    #[prost(bool, tag = "4")]
+    pub solid: bool,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Decompress {
+    #[prost(string, tag = "1")]
+    pub input: String,
+    #[prost(string, tag = "2")]
+    pub output: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct CreateImage {
+    #[prost(string, tag = "1")]
+    pub input: String,
+    #[prost(string, tag = "2")]
+    pub output: String,
+    #[prost(int32, optional, tag = "3")]
+    pub level: Option<i32>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ExtractImage {
+    #[prost(string, tag = "1")]
+    pub input: String,
+    #[prost(string, tag = "2")]
+    pub output: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct JobId {
+    #[prost(uint64, tag = "1")]
+    pub id: u64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct JobList {
+    #[prost(message, repeated, tag = "1")]
+    pub jobs: Vec<JobInfo>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct JobInfo {
+    #[prost(uint64, tag = "1")]
+    pub id: u64,
+    #[prost(message, optional, tag = "2")]
+    pub job: Option<Job>,
+    #[prost(message, optional, tag = "3")]
+    pub state: Option<JobState>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct JobState {
+    #[prost(enumeration = "job_state::Kind", tag = "1")]
+    pub kind: i32,
+    #[prost(uint64, tag = "2")]
+    pub started: u64,
+    #[prost(uint64, tag = "3")]
+    pub finished: u64,
+    #[prost(uint64, tag = "4")]
+    pub skipped: u64,
+    #[prost(uint64, tag = "5")]
+    pub warnings: u64,
+    #[prost(string, tag = "6")]
+    pub error: String,
+}
+
+pub mod job_state {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+    #[repr(i32)]
+    pub enum Kind {
+        Queued = 0,
+        Running = 1,
+        Succeeded = 2,
+        Failed = 3,
+        Cancelled = 4,
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Progress {
+    #[prost(message, optional, tag = "1")]
+    pub state: Option<JobState>,
+    #[prost(uint64, tag = "2")]
+    pub files_processed: u64,
+    #[prost(uint64, tag = "3")]
+    pub files_total: u64,
+    #[prost(uint64, tag = "4")]
+    pub bytes_processed: u64,
+    #[prost(uint64, tag = "5")]
+    pub bytes_total: u64,
+    #[prost(uint64, tag = "6")]
+    pub bytes_compressed: u64,
+    #[prost(uint64, tag = "7")]
+    pub unique_blocks: u64,
+    #[prost(uint64, tag = "8")]
+    pub duplicate_blocks: u64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ArchivePath {
+    #[prost(string, tag = "1")]
+    pub path: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct EntryPath {
+    #[prost(string, tag = "1")]
+    pub path: String,
+    #[prost(string, tag = "2")]
+    pub entry: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Listing {
+    #[prost(enumeration = "listing::Format", tag = "1")]
+    pub format: i32,
+    #[prost(message, repeated, tag = "2")]
+    pub entries: Vec<ListedEntry>,
+    #[prost(uint64, tag = "3")]
+    pub total_size: u64,
+    #[prost(uint64, optional, tag = "4")]
+    pub stored_size: Option<u64>,
+}
+
+pub mod listing {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+    #[repr(i32)]
+    pub enum Format {
+        Stream = 0,
+        Solid = 1,
+        Image = 2,
+        File = 3,
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ListedEntry {
+    #[prost(string, tag = "1")]
+    pub path: String,
+    #[prost(string, tag = "2")]
+    pub kind: String,
+    #[prost(uint64, tag = "3")]
+    pub size: u64,
+    #[prost(uint64, optional, tag = "4")]
+    pub compressed_size: Option<u64>,
+    #[prost(uint64, optional, tag = "5")]
+    pub modified: Option<u64>,
+    #[prost(string, repeated, tag = "6")]
+    pub blocks: Vec<String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Chunk {
+    #[prost(bytes = "vec", tag = "1")]
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct GrpcOptions {
+    /// Address to listen on, e.g. `127.0.0.1:50051`
+    pub listen: String,
+    /// Directory that request paths are relative to
+    pub root: PathBuf,
+    /// Jobs run at the same time
+    pub workers: usize,
+}
+
+/// Answer requests until the process is stopped, running queued jobs through `run`
+pub fn serve(options: &GrpcOptions, run: impl Fn(&JobSpec, &Arc<Metrics>) -> Result<Report, String> + Sync) -> io::Result<()> {
+    let runtime = tokio::runtime::Runtime::new()?;
+    let listener = runtime.block_on(TcpListener::bind(&options.listen))?;
+    info!(listen = %options.listen, root = %options.root.display(), workers = options.workers, "gRPC server listening");
+    run_queue(options.workers, run, |jobs| {
+        runtime.block_on(serve_on(listener, jobs.clone(), options.root.clone(), std::future::pending()))
+    })
+}
+
+/// Answer requests on `listener` until `shutdown` completes
+pub async fn serve_on(
+    listener: TcpListener,
+    jobs: Arc<JobQueue>,
+    root: PathBuf,
+    shutdown: impl Future<Output = ()>,
+) -> io::Result<()> {
+    tonic::transport::Server::builder()
+        .add_service(ZippyServer::new(ZippyService { jobs, root }))
+        .serve_with_incoming_shutdown(TcpListenerStream::new(listener), shutdown)
+        .await
+        .map_err(io::Error::other)
+}
+
+pub struct ZippyService {
+    jobs: Arc<JobQueue>,
+    root: PathBuf,
+}
+
+#[tonic::async_trait]
+impl zippy_server::Zippy for ZippyService {
+    async fn submit(&self, request: Request<Job>) -> Result<Response<JobId>, Status> {
+        let job = job_spec(&self.root, request.into_inner())?;
+        Ok(Response::new(JobId { id: self.jobs.submit(job) }))
+    }
+
+    async fn status(&self, request: Request<JobId>) -> Result<Response<JobList>, Status> {
+        let id = request.into_inner().id;
+        let jobs = self.jobs.status((id != 0).then_some(id)).ok_or_else(|| Status::not_found(format!("no job {}", id)))?;
+        Ok(Response::new(JobList { jobs: jobs.iter().map(JobInfo::from).collect() }))
+    }
+
+    type WatchStream = ReceiverStream<Result<Progress, Status>>;
+
+    async fn watch(&self, request: Request<JobId>) -> Result<Response<Self::WatchStream>, Status> {
+        let id = request.into_inner().id;
+        let metrics = self.jobs.metrics(id).ok_or_else(|| Status::not_found(format!("no job {}", id)))?;
+        let jobs = self.jobs.clone();
+        let (sender, receiver) = mpsc::channel(4);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(WATCH_INTERVAL);
+            loop {
+                interval.tick().await;
+                let Some(info) = jobs.status(Some(id)).and_then(|mut jobs| jobs.pop()) else {
+                    break;
+                };
+                let finished = info.state.is_finished();
+                // The client went away, or the final state is sent
+                if sender.send(Ok(progress(&info.state, &metrics))).await.is_err() || finished {
+                    break;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+
+    async fn list(&self, request: Request<ArchivePath>) -> Result<Response<Listing>, Status> {
+        let path = resolve(&self.root, &request.into_inner().path)?;
+        let listing = tokio::task::spawn_blocking(move || {
+            if is_archive(&path).map_err(io_status)? {
+                list_archive(&path).map_err(archive_status)
+            } else {
+                list_image(&path).map_err(image_status)
+            }
+        })
+        .await
+        .map_err(|e| Status::internal(e.to_string()))??;
+        Ok(Response::new(Listing::from(&listing)))
+    }
+
+    type ReadEntryStream = ReceiverStream<Result<Chunk, Status>>;
+
+    async fn read_entry(&self, request: Request<EntryPath>) -> Result<Response<Self::ReadEntryStream>, Status> {
+        let request = request.into_inner();
+        let path = resolve(&self.root, &request.path)?;
+        let entry = relative_path(&request.entry)?;
+        let (sender, receiver) = mpsc::channel(4);
+        tokio::task::spawn_blocking(move || {
+            let mut chunks = ChunkWriter { sender: sender.clone(), buffer: Vec::with_capacity(CHUNK_SIZE) };
+            let result = match is_archive(&path) {
+                Ok(true) => read_archive_entry(&path, &entry, &mut chunks).map_err(archive_status),
+                Ok(false) => read_image_entry(&path, &entry, &mut chunks).map_err(image_status),
+                Err(e) => Err(io_status(e)),
+            };
+            let result = result.and_then(|_| chunks.flush().map_err(io_status));
+            if let Err(status) = result {
+                debug!(path = %path.display(), entry = %entry.display(), error = %status.message(), "Entry not sent");
+                let _ = sender.blocking_send(Err(status));
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+}
+
+/// Sends what is written as `Chunk`s of [`CHUNK_SIZE`] bytes
+struct ChunkWriter {
+    sender: mpsc::Sender<Result<Chunk, Status>>,
+    buffer: Vec<u8>,
+}
+
+impl Write for ChunkWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let length = data.len().min(CHUNK_SIZE - self.buffer.len());
+        self.buffer.extend_from_slice(&data[..length]);
+        if self.buffer.len() == CHUNK_SIZE {
+            self.flush()?;
+        }
+        Ok(length)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            let data = std::mem::replace(&mut self.buffer, Vec::with_capacity(CHUNK_SIZE));
+            self.sender
+                .blocking_send(Ok(Chunk { data }))
+                .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "client went away"))?;
+        }
+        Ok(())
+    }
+}
+
+/// Job of a request, its paths resolved under the served root
+fn job_spec(root: &Path, job: Job) -> Result<JobSpec, Status> {
+    let command = job.command.ok_or_else(|| Status::invalid_argument("missing command"))?;
+    Ok(match command {
+        job::Command::Compress(Compress { input, output, level, solid }) => {
+            JobSpec::Compress { input: resolve(root, &input)?, output: resolve(root, &output)?, level, solid }
+        }
+        job::Command::Decompress(Decompress { input, output }) => {
+            JobSpec::Decompress { input: resolve(root, &input)?, output: resolve(root, &output)? }
+        }
+        job::Command::CreateImage(CreateImage { input, output, level }) => {
+            JobSpec::CreateImage { input: resolve(root, &input)?, output: resolve(root, &output)?, level }
+        }
+        job::Command::ExtractImage(ExtractImage { input, output }) => {
+            JobSpec::ExtractImage { input: resolve(root, &input)?, output: resolve(root, &output)? }
+        }
+    })
+}
+
+impl From<&JobSpec> for Job {
+    fn from(job: &JobSpec) -> Self {
+        let path = |path: &Path| path.to_string_lossy().into_owned();
+        let command = match job {
+            JobSpec::Compress { input, output, level, solid } => {
+                job::Command::Compress(Compress { input: path(input), output: path(output), level: *level, solid: *solid })
+            }
+            JobSpec::Decompress { input, output } => job::Command::Decompress(Decompress { input: path(input), output: path(output) }),
+            JobSpec::CreateImage { input, output, level } => {
+                job::Command::CreateImage(CreateImage { input: path(input), output: path(output), level: *level })
+            }
+            JobSpec::ExtractImage { input, output } => job::Command::ExtractImage(ExtractImage { input: path(input), output: path(output) }),
+        };
+        Job { command: Some(command) }
+    }
+}
+
+impl From<&daemon::JobInfo> for JobInfo {
+    fn from(info: &daemon::JobInfo) -> Self {
+        JobInfo { id: info.id, job: Some(Job::from(&info.job)), state: Some(JobState::from(&info.state)) }
+    }
+}
+
+impl From<&daemon::JobState> for JobState {
+    fn from(state: &daemon::JobState) -> Self {
+        use job_state::Kind;
+        match state {
+            daemon::JobState::Queued => JobState { kind: Kind::Queued as i32, ..Default::default() },
+            daemon::JobState::Running { started } => JobState { kind: Kind::Running as i32, started: *started, ..Default::default() },
+            daemon::JobState::Succeeded { started, finished, skipped, warnings } => JobState {
+                kind: Kind::Succeeded as i32,
+                started: *started,
+                finished: *finished,
+                skipped: *skipped as u64,
+                warnings: *warnings as u64,
+                ..Default::default()
+            },
+            daemon::JobState::Failed { started, finished, error } => JobState {
+                kind: Kind::Failed as i32,
+                started: *started,
+                finished: *finished,
+                error: error.clone(),
+                ..Default::default()
+            },
+            daemon::JobState::Cancelled => JobState { kind: Kind::Cancelled as i32, ..Default::default() },
+        }
+    }
+}
+
+/// State of a job and its counters; only image jobs fill the counters in
+fn progress(state: &daemon::JobState, metrics: &Metrics) -> Progress {
+    Progress {
+        state: Some(JobState::from(state)),
+        files_processed: metrics.files_processed.load(Ordering::Relaxed),
+        files_total: metrics.files_total.load(Ordering::Relaxed),
+        bytes_processed: metrics.bytes_processed.load(Ordering::Relaxed),
+        bytes_total: metrics.bytes_total.load(Ordering::Relaxed),
+        bytes_compressed: metrics.bytes_compressed.load(Ordering::Relaxed),
+        unique_blocks: metrics.unique_blocks.load(Ordering::Relaxed),
+        duplicate_blocks: metrics.duplicate_blocks.load(Ordering::Relaxed),
+    }
+}
+
+impl From<&list::Listing> for Listing {
+    fn from(listing: &list::Listing) -> Self {
+        let format = match listing.format {
+            ContainerFormat::Stream => listing::Format::Stream,
+            ContainerFormat::Solid => listing::Format::Solid,
+            ContainerFormat::File => listing::Format::File,
+            ContainerFormat::Image => listing::Format::Image,
+        };
+        let entries = listing
+            .entries
+            .iter()
+            .map(|entry| ListedEntry {
+                path: entry.path.to_string_lossy().into_owned(),
+                kind: serde_json::to_value(entry.kind).ok().and_then(|kind| kind.as_str().map(str::to_owned)).unwrap_or_default(),
+                size: entry.size,
+                compressed_size: entry.compressed_size,
+                modified: entry.modified,
+                blocks: entry.blocks.iter().flatten().map(|hash| hash.to_hex()).collect(),
+            })
+            .collect();
+        Listing { format: format as i32, entries, total_size: listing.total_size(), stored_size: listing.stored_size }
+    }
+}
+
+fn io_status(error: io::Error) -> Status {
+    match error.kind() {
+        io::ErrorKind::NotFound => Status::not_found(error.to_string()),
+        _ => Status::internal(error.to_string()),
+    }
+}
+
+fn archive_status(error: DecompressionError) -> Status {
+    match error {
+        DecompressionError::Io(e) => io_status(e),
+        DecompressionError::EntryNotFound { .. } => Status::not_found(error.to_string()),
+        _ => Status::internal(error.to_string()),
+    }
+}
+
+fn image_status(error: ImageError) -> Status {
+    match error {
+        ImageError::Io(e) => io_status(e),
+        ImageError::EntryNotFound { .. } => Status::not_found(error.to_string()),
+        _ => Status::internal(error.to_string()),
+    }
+}
+
+/// Relative path made only of plain names
+fn relative_path(path: &str) -> Result<PathBuf, Status> {
+    let path = Path::new(path);
+    if path.as_os_str().is_empty() || !path.components().all(|component| matches!(component, Component::Normal(_))) {
+        return Err(Status::invalid_argument(format!("path must stay inside the served directory: {}", path.display())));
+    }
+    Ok(path.to_path_buf())
+}
+
+/// `path` under the served root, refused if a symlink leads it out
+fn resolve(root: &Path, path: &str) -> Result<PathBuf, Status> {
+    let resolved = root.join(relative_path(path)?);
+    let canonical_root = root.canonicalize().map_err(io_status)?;
+    if !stays_within(&canonical_root, &resolved).map_err(io_status)? {
+        return Err(Status::invalid_argument(format!("path must stay inside the served directory: {}", path)));
+    }
+    Ok(resolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tokio::sync::oneshot;
+    use tonic::Code;
+    use crate::compress::{compress_directory, CompressionOptions};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_service() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("src/docs")).unwrap();
+        let content = "hello over grpc\n".repeat(10_000) + "end";
+        fs::write(root.join("src/docs/read me.txt"), &content).unwrap();
+        compress_directory(&CompressionOptions {
+            input_path: root.join("src"),
+            output_path: root.join("src.zpp"),
+            level: 3,
+            ..Default::default()
+        })
+        .unwrap();
+
+        let run = |job: &JobSpec, metrics: &Arc<Metrics>| match job {
+            JobSpec::ExtractImage { .. } => Err("image not found".to_string()),
+            _ => {
+                metrics.files_processed.fetch_add(1, Ordering::Relaxed);
+                Ok(Report::default())
+            }
+        };
+        run_queue(1, run, |jobs| {
+            let runtime = tokio::runtime::Runtime::new().unwrap();
+            runtime.block_on(async {
+                let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+                let address = listener.local_addr().unwrap();
+                let (stop, stopped) = oneshot::channel::<()>();
+                let server = tokio::spawn(serve_on(listener, jobs.clone(), root.to_path_buf(), async {
+                    let _ = stopped.await;
+                }));
+                let mut client = ZippyClient::connect(format!("http://{}", address)).await.unwrap();
+
+                let decompress = Job { command: Some(job::Command::Decompress(Decompress { input: "src.zpp".into(), output: "out".into() })) };
+                let id = client.submit(decompress).await.unwrap().into_inner().id;
+                assert_eq!(id, 1);
+                let escape = Job { command: Some(job::Command::Decompress(Decompress { input: "src.zpp".into(), output: "../out".into() })) };
+                assert_eq!(client.submit(escape).await.unwrap_err().code(), Code::InvalidArgument);
+
+                // Updates end with the first final state
+                let mut updates = client.watch(JobId { id }).await.unwrap().into_inner();
+                let mut last = None;
+                while let Some(progress) = updates.message().await.unwrap() {
+                    last = Some(progress);
+                }
+                let last = last.unwrap();
+                assert_eq!(last.state.unwrap().kind(), job_state::Kind::Succeeded);
+                assert_eq!(last.files_processed, 1);
+
+                let listed = client.status(JobId { id: 0 }).await.unwrap().into_inner().jobs;
+                assert_eq!(listed.len(), 1);
+                let Some(job::Command::Decompress(Decompress { output, .. })) = listed[0].job.clone().unwrap().command else {
+                    panic!("expected a decompress job");
+                };
+                assert_eq!(output, root.join("out").to_str().unwrap());
+                assert_eq!(client.status(JobId { id: 9 }).await.unwrap_err().code(), Code::NotFound);
+                assert_eq!(client.watch(JobId { id: 9 }).await.unwrap_err().code(), Code::NotFound);
+
+                let listing = client.list(ArchivePath { path: "src.zpp".into() }).await.unwrap().into_inner();
+                assert_eq!(listing.format(), listing::Format::Stream);
+                assert_eq!(listing.entries[0].path, "docs/read me.txt");
+                assert_eq!(listing.entries[0].kind, "file");
+                assert_eq!(listing.total_size, content.len() as u64);
+                assert_eq!(client.list(ArchivePath { path: "/etc/passwd".into() }).await.unwrap_err().code(), Code::InvalidArgument);
+                #[cfg(unix)]
+                {
+                    let outside = tempdir().unwrap();
+                    std::os::unix::fs::symlink(outside.path(), root.join("elsewhere")).unwrap();
+                    let escape = ArchivePath { path: "elsewhere/src.zpp".into() };
+                    assert_eq!(client.list(escape).await.unwrap_err().code(), Code::InvalidArgument);
+                }
+
+                let mut chunks = client.read_entry(EntryPath { path: "src.zpp".into(), entry: "docs/read me.txt".into() }).await.unwrap().into_inner();
+                let mut data = Vec::new();
+                let mut count = 0;
+                while let Some(chunk) = chunks.message().await.unwrap() {
+                    data.extend_from_slice(&chunk.data);
+                    count += 1;
+                }
+                assert_eq!(data, content.as_bytes());
+                assert!(count > 1);
+                let mut missing = client.read_entry(EntryPath { path: "src.zpp".into(), entry: "docs/missing".into() }).await.unwrap().into_inner();
+                assert_eq!(missing.message().await.unwrap_err().code(), Code::NotFound);
+
+                // Graceful shutdown waits for open connections
+                drop((client, chunks, missing));
+                stop.send(()).unwrap();
+                server.await.unwrap().unwrap();
+            });
+        });
+    }
+}
@@ -0,0 +1,186 @@
+//! HTTP plumbing shared by the remote storage backends
+
+use std::fmt;
+use std::io::{self, Read};
+use std::time::Duration;
+
+use crate::throttle::Bandwidth;
+
+/// Agent with timeouts suited to large transfers
+pub(crate) fn agent() -> ureq::Agent {
+    ureq::AgentBuilder::new()
+        .timeout_connect(Duration::from_secs(30))
+        .timeout_read(Duration::from_secs(300))
+        .timeout_write(Duration::from_secs(300))
+        .build()
+}
+
+/// Send `data` as the body of `request`, paced by the upload limit of `bandwidth`
+pub(crate) fn send_bytes(what: &str, request: ureq::Request, data: &[u8], bandwidth: &Bandwidth) -> io::Result<ureq::Response> {
+    request.set("Content-Length", &data.len().to_string()).send(bandwidth.upload(data)).map_err(|e| request_error(what, e))
+}
+
+/// I/O error of a failed request, of a kind telling whether a retry may succeed
+pub(crate) fn request_error(what: &str, error: ureq::Error) -> io::Error {
+    match error {
+        ureq::Error::Status(status, response) => {
+            let kind = match status {
+                401 | 403 => io::ErrorKind::PermissionDenied,
+                404 => io::ErrorKind::NotFound,
+                408 | 429 => io::ErrorKind::Other,
+                400..=499 => io::ErrorKind::InvalidInput,
+                _ => io::ErrorKind::Other,
+            };
+            let detail = response_text(response);
+            let message = format!("{}: HTTP {}{}", what, status, if detail.is_empty() { String::new() } else { format!(" ({})", detail) });
+            io::Error::new(kind, StatusError { status, message })
+        }
+        ureq::Error::Transport(transport) => io::Error::other(format!("{}: {}", what, transport)),
+    }
+}
+
+/// Error response, kept inside the I/O error so callers can react to its status
+#[derive(Debug)]
+struct StatusError {
+    status: u16,
+    message: String,
+}
+
+impl fmt::Display for StatusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for StatusError {}
+
+/// HTTP status of an error made by [`request_error`]
+pub(crate) fn status(error: &io::Error) -> Option<u16> {
+    error.get_ref()?.downcast_ref::<StatusError>().map(|error| error.status)
+}
+
+/// Error code or message of an error body, XML or JSON
+fn response_text(response: ureq::Response) -> String {
+    let mut body = String::new();
+    let _ = response.into_reader().take(4096).read_to_string(&mut body);
+    let body = body.trim();
+    if let Some(code) = xml_elements(body, "Code").first() {
+        return xml_unescape(code);
+    }
+    if let Ok(json) = serde_json::from_str::<serde_json::Value>(body) {
+        if let Some(message) = json.pointer("/error/message").or(json.get("message")).and_then(|message| message.as_str()) {
+            return message.to_string();
+        }
+    }
+    body.lines().next().unwrap_or("").to_string()
+}
+
+/// Percent-encode everything but unreserved characters, and `/` when `keep_slash`
+pub(crate) fn percent_encode(text: &str, keep_slash: bool) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for &byte in text.as_bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.' | b'~') || (keep_slash && byte == b'/') {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+/// Content of each `<tag>` element, namespace prefixes ignored; enough for the
+/// listings services return, not a general XML parser
+pub(crate) fn xml_elements<'a>(xml: &'a str, tag: &str) -> Vec<&'a str> {
+    let mut elements = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        let name_end = rest.find(|c: char| c.is_whitespace() || c == '>' || c == '/').unwrap_or(rest.len());
+        let Some(tag_end) = rest.find('>') else {
+            break;
+        };
+        if local_name(&rest[..name_end]) != tag {
+            continue;
+        }
+        let self_closing = rest[..tag_end].ends_with('/');
+        rest = &rest[tag_end + 1..];
+        if self_closing {
+            elements.push("");
+            continue;
+        }
+        // First closing tag of the same name
+        let mut search = 0;
+        while let Some(close) = rest[search..].find("</") {
+            let close = search + close;
+            let name = &rest[close + 2..];
+            let name = &name[..name.find('>').unwrap_or(name.len())];
+            if local_name(name.trim()) == tag {
+                elements.push(&rest[..close]);
+                rest = &rest[close..];
+                break;
+            }
+            search = close + 2;
+        }
+    }
+    elements
+}
+
+fn local_name(name: &str) -> &str {
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+/// Resolve the predefined and numeric entities of XML text
+pub(crate) fn xml_unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let entity = rest.find(';').map(|end| &rest[1..end]);
+        let character = entity.and_then(|entity| match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => match entity.strip_prefix("#x").or_else(|| entity.strip_prefix("#X")) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                None => entity.strip_prefix('#').and_then(|decimal| decimal.parse().ok()),
+            }
+            .and_then(char::from_u32),
+        });
+        match (character, entity) {
+            (Some(character), Some(entity)) => {
+                unescaped.push(character);
+                rest = &rest[entity.len() + 2..];
+            }
+            _ => {
+                unescaped.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_xml_helpers() {
+        let xml = r#"<?xml version="1.0"?><d:multistatus xmlns:d="DAV:"><d:response><d:href>/a%20b</d:href><d:empty/></d:response>
+            <d:response><d:href>/R&amp;D &#233;t&#xE9;</d:href></d:response></d:multistatus>"#;
+        let responses = xml_elements(xml, "response");
+        assert_eq!(responses.len(), 2);
+        assert_eq!(xml_elements(responses[0], "href"), vec!["/a%20b"]);
+        assert_eq!(xml_elements(responses[0], "empty"), vec![""]);
+        assert_eq!(xml_unescape(xml_elements(responses[1], "href")[0]), "/R&D été");
+        assert_eq!(xml_unescape("a & b &bogus;"), "a & b &bogus;");
+        assert_eq!(percent_encode("dir/é x.zpak", true), "dir/%C3%A9%20x.zpak");
+        assert_eq!(percent_encode("a/b", false), "a%2Fb");
+        assert_eq!(status(&io::Error::new(io::ErrorKind::NotFound, StatusError { status: 404, message: String::new() })), Some(404));
+        assert_eq!(status(&io::Error::other("timed out")), None);
+    }
+}
@@ -1,401 +1,4657 @@
-/*!
- * ZippyPack - Système d'images avec déduplication
- * 
- * Créé par : Kamion (Matthéo Le Fur)
- * Date : 26/06/2025
- * Modifié le : 14/07/2025
- * 
- * Description : Implémentation du système d'images ZippyPack avec déduplication
- * par blocs de 64KB et compression zstd optimisée
- *
- * Version : 1.0.0
- */
-
-use std::collections::HashMap;
-use std::fs::{self, File};
-use std::io::{Read, Write, BufReader, BufWriter, Seek, SeekFrom};
-use std::path::PathBuf;
-use anyhow::Result;
-use tracing::info;
-use walkdir::WalkDir;
-use zstd::{encode_all, decode_all};
-
-const BLOCK_SIZE: usize = 65536; // 64KB blocks
-
-#[derive(Debug, Clone)]
-pub struct BlockHash([u8; 32]);
-
-impl From<[u8; 32]> for BlockHash {
-    fn from(hash: [u8; 32]) -> Self {
-        BlockHash(hash)
-    }
-}
-
-impl std::hash::Hash for BlockHash {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        self.0.hash(state);
-    }
-}
-
-impl PartialEq for BlockHash {
-    fn eq(&self, other: &Self) -> bool {
-        self.0 == other.0
-    }
-}
-
-impl Eq for BlockHash {}
-
-#[derive(Debug, Clone)]
-pub struct DataBlock {
-    pub compressed_data: Vec<u8>,
-    pub original_size: usize,
-}
-
-#[derive(Debug, Clone)]
-pub struct FileEntry {
-    pub path: PathBuf,
-    pub size: u64,
-    pub modified: u64,
-    pub is_directory: bool,
-    pub blocks: Vec<BlockHash>,
-}
-
-#[derive(Debug)]
-pub struct ImageHeader {
-    pub version: u32,
-    pub created: u64,
-    pub total_files: u64,
-    pub total_size: u64,
-    pub compressed_size: u64,
-    pub block_count: u64,
-}
-
-pub struct ImageOptions {
-    pub input_path: PathBuf,
-    pub output_path: PathBuf,
-    pub compression_level: i32,
-}
-
-pub struct ExtractOptions {
-    pub image_path: PathBuf,
-    pub output_path: PathBuf,
-}
-
-fn calculate_hash(data: &[u8]) -> BlockHash {
-    use std::collections::hash_map::DefaultHasher;
-    use std::hash::{Hash, Hasher};
-    
-    let mut hasher = DefaultHasher::new();
-    data.hash(&mut hasher);
-    let hash = hasher.finish();
-    
-    // Convert u64 to [u8; 32] (simple implementation)
-    let mut result = [0u8; 32];
-    result[0..8].copy_from_slice(&hash.to_le_bytes());
-    BlockHash(result)
-}
-
-fn split_into_blocks(data: &[u8]) -> Vec<(BlockHash, Vec<u8>)> {
-    data.chunks(BLOCK_SIZE)
-        .map(|chunk| {
-            let hash = calculate_hash(chunk);
-            (hash, chunk.to_vec())
-        })
-        .collect()
-}
-
-pub fn create_image(options: &ImageOptions) -> Result<()> {
-    info!("Création de l'image depuis {:?}", options.input_path);
-    
-    let mut file_entries = Vec::new();
-    let mut block_store: HashMap<BlockHash, DataBlock> = HashMap::new();
-    let mut total_size = 0u64;
-    let mut total_files = 0u64;
-    
-    // Calcul du nombre total de fichiers pour la progression
-    let total_entries: u64 = WalkDir::new(&options.input_path)
-        .into_iter()
-        .filter_map(|e| e.ok())
-        .filter(|e| e.path().is_file())
-        .count() as u64;
-    
-    info!("Nombre total de fichiers à traiter: {}", total_entries);
-    
-    let start_time = std::time::Instant::now();
-    let mut processed_size = 0u64;
-    
-    // Parcours récursif des fichiers
-    for entry in WalkDir::new(&options.input_path) {
-        let entry = entry?;
-        let path = entry.path();
-        let relative_path = path.strip_prefix(&options.input_path)?;
-        
-        if path.is_dir() {
-            file_entries.push(FileEntry {
-                path: relative_path.to_path_buf(),
-                size: 0,
-                modified: 0,
-                is_directory: true,
-                blocks: Vec::new(),
-            });
-            continue;
-        }
-        
-        let metadata = entry.metadata()?;
-        let size = metadata.len();
-        total_size += size;
-        processed_size += size;
-        total_files += 1;
-        
-        let modified = metadata
-            .modified()?
-            .duration_since(std::time::UNIX_EPOCH)?
-            .as_secs();
-        
-        // Optimized reading with buffered I/O for large files
-        use std::io::BufReader;
-        
-        let file = File::open(path)?;
-        let mut reader = BufReader::new(file);
-        let mut buffer = Vec::new();
-        
-        // For large files, read in chunks to avoid memory issues
-        if size > 10 * 1024 * 1024 { // 10MB threshold
-            const CHUNK_SIZE: usize = 1024 * 1024; // 1MB chunks
-            let mut chunk = vec![0u8; CHUNK_SIZE];
-            loop {
-                let bytes_read = reader.read(&mut chunk)?;
-                if bytes_read == 0 { break; }
-                buffer.extend_from_slice(&chunk[..bytes_read]);
-            }
-        } else {
-            reader.read_to_end(&mut buffer)?;
-        }
-        
-        let blocks = split_into_blocks(&buffer);
-        let mut file_blocks = Vec::new();
-        
-        for (hash, block_data) in blocks {
-            file_blocks.push(hash.clone());
-            
-            // Déduplication : ne stocker que les blocs uniques
-            if !block_store.contains_key(&hash) {
-                let compressed = encode_all(&block_data[..], options.compression_level)?;
-                block_store.insert(hash.clone(), DataBlock {
-                    compressed_data: compressed,
-                    original_size: block_data.len(),
-                });
-            }
-        }
-        
-        file_entries.push(FileEntry {
-            path: relative_path.to_path_buf(),
-            size,
-            modified,
-            is_directory: false,
-            blocks: file_blocks,
-        });
-        
-        // Progression améliorée
-        if total_files % 5 == 0 {
-            let elapsed = start_time.elapsed().as_secs_f64();
-            let progress = (total_files as f64 / total_entries as f64) * 100.0;
-            let speed_mbs = (processed_size as f64 / (1024.0 * 1024.0)) / elapsed;
-            let eta_seconds = if speed_mbs > 0.0 {
-                ((total_size - processed_size) as f64 / (1024.0 * 1024.0)) / speed_mbs
-            } else {
-                0.0
-            };
-            
-            info!(
-                "Progression: {:.1}% ({}/{}) - {:.1} MB/s - ETA: {:.0}s - Blocs uniques: {}",
-                progress, total_files, total_entries, speed_mbs, eta_seconds, block_store.len()
-            );
-        }
-    }
-    
-    // Calcul de la taille compressée
-    let compressed_size: usize = block_store.values()
-        .map(|block| block.compressed_data.len())
-        .sum();
-    
-    // Écriture de l'image
-    let mut output_file = BufWriter::new(File::create(&options.output_path)?);
-    
-    // Header
-    let header = ImageHeader {
-        version: 1,
-        created: std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)?
-            .as_secs(),
-        total_files,
-        total_size,
-        compressed_size: compressed_size as u64,
-        block_count: block_store.len() as u64,
-    };
-    
-    // Sérialisation simple du header
-    output_file.write_all(&header.version.to_le_bytes())?;
-    output_file.write_all(&header.created.to_le_bytes())?;
-    output_file.write_all(&header.total_files.to_le_bytes())?;
-    output_file.write_all(&header.total_size.to_le_bytes())?;
-    output_file.write_all(&header.compressed_size.to_le_bytes())?;
-    output_file.write_all(&header.block_count.to_le_bytes())?;
-    
-    // Index des blocs
-    for (hash, block) in &block_store {
-        output_file.write_all(&hash.0)?; // 32 bytes hash
-        output_file.write_all(&(block.original_size as u64).to_le_bytes())?;
-        output_file.write_all(&(block.compressed_data.len() as u64).to_le_bytes())?;
-    }
-    
-    // Données des blocs
-    for block in block_store.values() {
-        output_file.write_all(&block.compressed_data)?;
-    }
-    
-    // Index des fichiers
-    output_file.write_all(&(file_entries.len() as u64).to_le_bytes())?;
-    for file_entry in &file_entries {
-        let path_str = file_entry.path.to_string_lossy();
-        let path_bytes = path_str.as_bytes();
-        output_file.write_all(&(path_bytes.len() as u64).to_le_bytes())?;
-        output_file.write_all(path_bytes)?;
-        output_file.write_all(&file_entry.size.to_le_bytes())?;
-        output_file.write_all(&file_entry.modified.to_le_bytes())?;
-        output_file.write_all(&[if file_entry.is_directory { 1 } else { 0 }])?;
-        output_file.write_all(&(file_entry.blocks.len() as u64).to_le_bytes())?;
-        for block_hash in &file_entry.blocks {
-            output_file.write_all(&block_hash.0)?;
-        }
-    }
-    
-    output_file.flush()?;
-    
-    let ratio = (compressed_size as f64 / total_size as f64) * 100.0;
-    info!("Image créée: {} fichiers, {:.2}% de compression", total_files, 100.0 - ratio);
-    info!("Taille originale: {} bytes", total_size);
-    info!("Taille compressée: {} bytes", compressed_size);
-    info!("Blocs uniques: {}", block_store.len());
-    
-    Ok(())
-}
-
-pub fn extract_image(options: &ExtractOptions) -> Result<()> {
-    info!("Extraction de l'image {:?}", options.image_path);
-    
-    let mut input_file = BufReader::new(File::open(&options.image_path)?);
-    
-    // Lecture du header
-    let mut buffer = [0u8; 8];
-    input_file.read_exact(&mut buffer)?;
-    let version = u32::from_le_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]);
-    
-    input_file.read_exact(&mut buffer)?;
-    let _created = u64::from_le_bytes(buffer);
-    
-    input_file.read_exact(&mut buffer)?;
-    let total_files = u64::from_le_bytes(buffer);
-    
-    input_file.read_exact(&mut buffer)?;
-    let _total_size = u64::from_le_bytes(buffer);
-    
-    input_file.read_exact(&mut buffer)?;
-    let _compressed_size = u64::from_le_bytes(buffer);
-    
-    input_file.read_exact(&mut buffer)?;
-    let block_count = u64::from_le_bytes(buffer);
-    
-    info!("Version: {}, {} fichiers, {} blocs", version, total_files, block_count);
-    
-    // Lecture de l'index des blocs
-    let mut block_index = HashMap::new();
-    let mut current_offset = 6 * 8 + (block_count * (32 + 8 + 8)) as u64; // Skip to data section
-    
-    for _ in 0..block_count {
-        let mut hash_bytes = [0u8; 32];
-        input_file.read_exact(&mut hash_bytes)?;
-        let hash = BlockHash(hash_bytes);
-        
-        input_file.read_exact(&mut buffer)?;
-        let original_size = u64::from_le_bytes(buffer) as usize;
-        
-        input_file.read_exact(&mut buffer)?;
-        let compressed_size = u64::from_le_bytes(buffer) as usize;
-        
-        block_index.insert(hash, (current_offset, original_size, compressed_size));
-        current_offset += compressed_size as u64;
-    }
-    
-    // Créer le dossier de sortie
-    fs::create_dir_all(&options.output_path)?;
-    
-    // Lecture des métadonnées de fichiers
-    input_file.read_exact(&mut buffer)?;
-    let file_count = u64::from_le_bytes(buffer);
-    
-    for i in 0..file_count {
-        // Lecture du chemin
-        input_file.read_exact(&mut buffer)?;
-        let path_len = u64::from_le_bytes(buffer) as usize;
-        let mut path_bytes = vec![0u8; path_len];
-        input_file.read_exact(&mut path_bytes)?;
-        let relative_path = String::from_utf8(path_bytes)?;
-        
-        input_file.read_exact(&mut buffer)?;
-        let _size = u64::from_le_bytes(buffer);
-        
-        input_file.read_exact(&mut buffer)?;
-        let _modified = u64::from_le_bytes(buffer);
-        
-        let mut is_dir_byte = [0u8; 1];
-        input_file.read_exact(&mut is_dir_byte)?;
-        let is_directory = is_dir_byte[0] == 1;
-        
-        let full_path = options.output_path.join(&relative_path);
-        
-        if is_directory {
-            fs::create_dir_all(&full_path)?;
-            continue;
-        }
-        
-        // Créer le dossier parent si nécessaire
-        if let Some(parent) = full_path.parent() {
-            fs::create_dir_all(parent)?;
-        }
-        
-        // Lecture des blocs du fichier
-        input_file.read_exact(&mut buffer)?;
-        let block_count = u64::from_le_bytes(buffer);
-        
-        let mut file_data = Vec::new();
-        for _ in 0..block_count {
-            let mut hash_bytes = [0u8; 32];
-            input_file.read_exact(&mut hash_bytes)?;
-            let hash = BlockHash(hash_bytes);
-            
-            if let Some((offset, _original_size, compressed_size)) = block_index.get(&hash) {
-                // Lecture du bloc compressé
-                let mut file_handle = File::open(&options.image_path)?;
-                file_handle.seek(SeekFrom::Start(*offset))?;
-                let mut compressed_data = vec![0u8; *compressed_size];
-                file_handle.read_exact(&mut compressed_data)?;
-                
-                // Décompression
-                let decompressed = decode_all(&compressed_data[..])?;
-                file_data.extend_from_slice(&decompressed);
-            }
-        }
-        
-        // Écriture du fichier
-        let mut output_file = File::create(&full_path)?;
-        output_file.write_all(&file_data)?;
-        
-        if (i + 1) % 100 == 0 {
-            info!("Extrait {} fichiers", i + 1);
-        }
-    }
-    
-    info!("Extraction terminée: {} fichiers", file_count);
-    Ok(())
-}
\ No newline at end of file
+/*!
+ * ZippyPack - Système d'images avec déduplication
+ * 
+ * Créé par : Kamion (Matthéo Le Fur)
+ * Date : 26/06/2025
+ * Modifié le : 14/07/2025
+ * 
+ * Description : Implémentation du système d'images ZippyPack avec déduplication
+ * par blocs de 64KB et compression zstd optimisée
+ *
+ * Version : 1.0.0
+ */
+
+use std::cmp::Reverse;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufRead, Read, Write, BufReader, BufWriter, Seek, SeekFrom};
+use std::ops::{Deref, Range};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use ed25519_dalek::SigningKey;
+use memmap2::Mmap;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{debug, debug_span, info, info_span};
+use xxhash_rust::xxh3::xxh3_128;
+
+use crate::blockindex::{BlockIndex, BlockIndexBuilder, DISK_INDEX_THRESHOLD};
+use crate::blockio::{BlockReader, IoBackend};
+use crate::bloom::{BloomFilter, DEFAULT_FALSE_POSITIVE_RATE};
+use crate::chunker::{ChunkStrategy, ChunkerOptions};
+use crate::device::{entry_name, Device};
+use crate::error::{ImageError, PathIoError};
+use crate::list::{matches_entry, ContainerFormat, ListedEntry, Listing};
+use crate::metrics::Metrics;
+use crate::format::{native_path_encoding, read_path, write_path, zstd_encoder, Codec, DEFAULT_STORE_THRESHOLD, PATH_ENCODING_UNIX};
+use crate::owners::IdMap;
+use crate::paths::{remove_symlink, resolves_within, sanitize_path, CaseCollision, NormalizationForm, PathMapper};
+use crate::pipeline::{run_hashed_pipeline, BufferPool, PipelineOptions, MAX_POOLED_CAPACITY};
+use crate::platform;
+use crate::report::{Report, WarningKind};
+use crate::signing::{Manifest, ManifestEntry, SignedManifest};
+use crate::spill::{SpillBuffer, SpillOptions};
+use crate::storage::MirrorWriter;
+use crate::walk::{walk, EntryKind, WalkError, WalkOptions, WalkedEntry};
+
+pub(crate) const BLOCK_SIZE: usize = 65536; // 64KB blocks
+const IMAGE_VERSION: u32 = 17;
+/// Plus ancienne version lue : une nouvelle version ne retire jamais la lecture des
+/// précédentes
+const IMAGE_MIN_VERSION: u32 = 1;
+
+/// Données compressées lues par lot d'extraction
+const BATCH_BYTES: usize = 64 * 1024 * 1024;
+const BATCH_FILES: usize = 1024;
+/// Écart maximal entre deux blocs lus d'un seul tenant
+const MAX_READ_GAP: u64 = 256 * 1024;
+const MAX_READ_SIZE: usize = 8 * 1024 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct BlockHash([u8; 32]);
+
+impl From<[u8; 32]> for BlockHash {
+    fn from(hash: [u8; 32]) -> Self {
+        BlockHash(hash)
+    }
+}
+
+impl From<BlockHash> for [u8; 32] {
+    fn from(hash: BlockHash) -> Self {
+        hash.0
+    }
+}
+
+impl AsRef<[u8; 32]> for BlockHash {
+    fn as_ref(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl BlockHash {
+    pub fn to_hex(&self) -> String {
+        self.0.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+}
+
+/// Sérialisé en hexadécimal, comme dans les sorties JSON
+impl Serialize for BlockHash {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_hex())
+    }
+}
+
+impl std::hash::Hash for BlockHash {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.0.hash(state);
+    }
+}
+
+impl PartialEq for BlockHash {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl Eq for BlockHash {}
+
+#[derive(Debug, Clone)]
+pub struct DataBlock {
+    pub compressed_data: Vec<u8>,
+    pub original_size: usize,
+    pub codec: Codec,
+}
+
+#[derive(Debug, Clone)]
+pub struct FileEntry {
+    pub path: PathBuf,
+    pub size: u64,
+    pub modified: u64,
+    pub kind: EntryKind,
+    pub blocks: Vec<BlockHash>,
+    /// Cible du lien pour les entrées de type `Symlink`
+    pub link_target: Option<PathBuf>,
+    /// Descripteur de sécurité Windows (propriétaire, groupe, DACL et SACL) au
+    /// format SDDL, capturé avec `--acls` (version 13+)
+    pub security: Option<String>,
+    /// Numéros majeur et mineur des périphériques caractère et bloc (version 15+)
+    pub device_numbers: Option<(u32, u32)>,
+    /// Propriétaire, groupe et permissions Unix (version 16+)
+    pub attributes: Option<UnixAttributes>,
+    /// Date du dernier accès, capturée avec `--atime` (version 17+)
+    pub accessed: Option<u64>,
+}
+
+/// Propriétaire, groupe et permissions d'une entrée capturée sous Unix
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct UnixAttributes {
+    pub uid: u32,
+    pub gid: u32,
+    /// Bits de permission, setuid, setgid et sticky compris
+    pub mode: u32,
+}
+
+#[derive(Debug)]
+pub struct ImageHeader {
+    pub version: u32,
+    pub created: u64,
+    pub total_files: u64,
+    pub total_size: u64,
+    pub compressed_size: u64,
+    pub block_count: u64,
+    /// Index des blocs, compressé (version 5+)
+    pub block_index: IndexSection,
+    /// Index des fichiers, compressé (version 5+)
+    pub file_index: IndexSection,
+    /// Algorithme des hashes de blocs (version 6+) ; `None` pour les images
+    /// antérieures, hachées avec le `DefaultHasher` de la bibliothèque standard
+    pub hash_algorithm: Option<HashAlgorithm>,
+    /// Images dont des blocs sont référencés plutôt que stockés (version 7+)
+    pub external_images: Vec<ExternalImage>,
+    /// Image dont celle-ci est la différence, parmi `external_images` (version 11+)
+    pub base: Option<usize>,
+    /// Découpage des blocs (version 8+) ; blocs fixes de 64KB auparavant
+    pub chunker: ChunkerOptions,
+    /// Manifeste signé, compressé (version 9+) ; vide pour une image non signée
+    pub manifest: IndexSection,
+    /// Blocs ajoutés sur place par `add-to-image` (version 12+)
+    pub appended: AppendedBlocks,
+}
+
+impl ImageHeader {
+    fn format(&self) -> ImageFormat {
+        ImageFormat(self.version)
+    }
+}
+
+/// Disposition d'une version du format, choisie d'après le header : la lecture
+/// interroge la version pour chaque section plutôt que de comparer des numéros
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ImageFormat(u32);
+
+impl ImageFormat {
+    fn of(version: u32) -> Result<Self, ImageError> {
+        if !(IMAGE_MIN_VERSION..=IMAGE_VERSION).contains(&version) {
+            return Err(ImageError::UnsupportedVersion { found: version, max_supported: IMAGE_VERSION });
+        }
+        Ok(Self(version))
+    }
+    
+    /// Octet d'encodage des chemins (version 3+) ; de l'UTF-8 auparavant
+    fn path_encoding(self) -> bool {
+        self.0 >= 3
+    }
+    
+    /// Filtre de Bloom des blocs (version 4+)
+    fn bloom_filter(self) -> bool {
+        self.0 >= 4
+    }
+    
+    /// Index compressés avec zstd, tailles dans le header (version 5+)
+    fn compressed_indexes(self) -> bool {
+        self.0 >= 5
+    }
+    
+    /// Algorithme des hashes de blocs (version 6+)
+    fn hash_algorithm(self) -> bool {
+        self.0 >= 6
+    }
+    
+    /// Images référencées (version 7+)
+    fn external_images(self) -> bool {
+        self.0 >= 7
+    }
+    
+    /// Paramètres de découpage (version 8+)
+    fn chunker(self) -> bool {
+        self.0 >= 8
+    }
+    
+    /// Manifeste signé (version 9+)
+    fn manifest(self) -> bool {
+        self.0 >= 9
+    }
+    
+    /// Codec de chaque bloc (version 10+) ; zstd auparavant
+    fn block_codecs(self) -> bool {
+        self.0 >= 10
+    }
+    
+    /// Image de base et chemins supprimés d'une image différentielle (version 11+)
+    fn base_image(self) -> bool {
+        self.0 >= 11
+    }
+    
+    /// Blocs ajoutés sur place (version 12+)
+    fn appended_blocks(self) -> bool {
+        self.0 >= 12
+    }
+    
+    /// Descripteur de sécurité de chaque entrée (version 13+)
+    fn security_descriptors(self) -> bool {
+        self.0 >= 13
+    }
+    
+    /// Entrées des flux de données alternatifs (version 14+)
+    fn data_streams(self) -> bool {
+        self.0 >= 14
+    }
+    
+    /// Numéros majeur et mineur des périphériques (version 15+)
+    fn device_numbers(self) -> bool {
+        self.0 >= 15
+    }
+    
+    /// Propriétaire, groupe et permissions Unix de chaque entrée (version 16+)
+    fn unix_attributes(self) -> bool {
+        self.0 >= 16
+    }
+    
+    /// Date du dernier accès de chaque entrée (version 17+)
+    fn access_times(self) -> bool {
+        self.0 >= 17
+    }
+}
+
+/// Blocs ajoutés après la création de l'image : leurs données sont écrites en fin
+/// de fichier, suivies d'un index de ces blocs avec leur position puis du nouvel
+/// index des fichiers. Le header, réécrit en dernier, désigne ces sections.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AppendedBlocks {
+    /// Position de l'index des blocs ajoutés, 0 s'il n'y en a pas
+    pub offset: u64,
+    /// Nombre de blocs ajoutés, compris dans `block_count`
+    pub count: u64,
+    pub index: IndexSection,
+}
+
+/// Référence à une autre image, identifiée par sa date de création et son nombre de blocs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExternalImage {
+    pub path: PathBuf,
+    pub created: u64,
+    pub block_count: u64,
+}
+
+/// Tailles d'une section d'index compressée avec zstd
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IndexSection {
+    pub compressed_size: u64,
+    pub original_size: u64,
+}
+
+/// Hash identifying deduplicated blocks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    /// XXH3-128: fastest, not collision resistant against crafted input
+    #[default]
+    Xxh3,
+    /// BLAKE3: cryptographic, for tamper-evident content addressing
+    Blake3,
+}
+
+impl HashAlgorithm {
+    pub fn hash(self, data: &[u8]) -> BlockHash {
+        let mut result = [0u8; 32];
+        match self {
+            HashAlgorithm::Xxh3 => result[..16].copy_from_slice(&xxh3_128(data).to_le_bytes()),
+            HashAlgorithm::Blake3 => result = *blake3::hash(data).as_bytes(),
+        }
+        BlockHash(result)
+    }
+
+    pub(crate) fn to_byte(self) -> u8 {
+        match self {
+            HashAlgorithm::Xxh3 => 1,
+            HashAlgorithm::Blake3 => 2,
+        }
+    }
+
+    /// Second algorithm used to re-verify blocks whose hash matches
+    fn verifier(self) -> Self {
+        match self {
+            HashAlgorithm::Xxh3 => HashAlgorithm::Blake3,
+            HashAlgorithm::Blake3 => HashAlgorithm::Xxh3,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Result<Self, ImageError> {
+        match byte {
+            1 => Ok(HashAlgorithm::Xxh3),
+            2 => Ok(HashAlgorithm::Blake3),
+            _ => Err(ImageError::CorruptIndex(format!("Algorithme de hash inconnu: {}", byte))),
+        }
+    }
+}
+
+pub struct ImageOptions {
+    pub input_path: PathBuf,
+    pub output_path: PathBuf,
+    /// Second file the image is written to at the same time, e.g. the staged copy of an offsite mirror
+    pub mirror_path: Option<PathBuf>,
+    /// Write the image around the page cache (O_DIRECT on Linux), so that writing
+    /// hundreds of gigabytes does not evict the caches of other workloads
+    pub direct_io: bool,
+    pub compression_level: i32,
+    /// Blocks smaller than this many bytes are stored uncompressed
+    pub store_threshold: u64,
+    /// Hash used to deduplicate blocks, recorded in the image header
+    pub hash_algorithm: HashAlgorithm,
+    /// Re-verify blocks whose hash matches another block of the image with a second hash before deduplicating them
+    pub verify_dedup: bool,
+    /// Existing images whose blocks are referenced instead of stored again
+    pub dedup_against: Vec<PathBuf>,
+    /// Image this one is a delta of: only entries changed since the content of
+    /// the base (and of its own bases) are recorded, with the paths removed since
+    pub base: Option<PathBuf>,
+    /// How file contents are cut into blocks; must be valid
+    pub chunker: ChunkerOptions,
+    /// Sign a manifest of every entry and its BLAKE3 content hash with this key
+    pub sign_key: Option<SigningKey>,
+    pub walk: WalkOptions,
+    /// Skip unreadable files and record them in the report instead of aborting
+    pub skip_errors: bool,
+    pub pipeline: PipelineOptions,
+    /// Live counters and per-thread activity, for `--metrics` and the dashboard
+    pub metrics: Option<Arc<Metrics>>,
+    /// `input_path` is a block device or disk file, captured as a single file
+    /// entry named after it; free blocks of ext2/3/4 filesystems are not read.
+    /// Incompatible with `sign_key`
+    pub device: bool,
+    /// Where compressed blocks go once they no longer fit in memory
+    pub spill: SpillOptions,
+    /// Read the image back once written and check every block against its hash,
+    /// so that a bad disk or bad RAM is caught now rather than at restore time
+    pub verify_write: bool,
+    /// Capture the security descriptor (owner, group, DACL and SACL) of each
+    /// entry; only Windows has them, elsewhere nothing is recorded
+    pub acls: bool,
+    /// Record access times, restored on extraction with the modification times
+    pub atime: bool,
+}
+
+impl Default for ImageOptions {
+    fn default() -> Self {
+        Self {
+            input_path: PathBuf::new(),
+            output_path: PathBuf::new(),
+            mirror_path: None,
+            direct_io: false,
+            compression_level: 22,
+            store_threshold: DEFAULT_STORE_THRESHOLD,
+            hash_algorithm: HashAlgorithm::default(),
+            verify_dedup: false,
+            dedup_against: Vec::new(),
+            base: None,
+            chunker: ChunkerOptions::default(),
+            sign_key: None,
+            walk: WalkOptions::default(),
+            skip_errors: false,
+            pipeline: PipelineOptions::default(),
+            metrics: None,
+            device: false,
+            verify_write: false,
+            spill: SpillOptions::default(),
+            acls: false,
+            atime: false,
+        }
+    }
+}
+
+/// Options of `add-to-image`: content added to an existing image in place
+pub struct AddOptions {
+    pub image_path: PathBuf,
+    /// File or directory to add
+    pub input_path: PathBuf,
+    /// Directory of the image the input is added as [default: the input's name]
+    pub dest: Option<PathBuf>,
+    pub compression_level: i32,
+    /// Blocks smaller than this many bytes are stored uncompressed
+    pub store_threshold: u64,
+    pub walk: WalkOptions,
+    /// Skip unreadable files and record them in the report instead of aborting
+    pub skip_errors: bool,
+    /// Capture the security descriptor of each added entry
+    pub acls: bool,
+    /// Record the access time of each added entry
+    pub atime: bool,
+    /// Threads compressing the new indexes
+    pub threads: usize,
+}
+
+impl Default for AddOptions {
+    fn default() -> Self {
+        Self {
+            image_path: PathBuf::new(),
+            input_path: PathBuf::new(),
+            dest: None,
+            compression_level: 22,
+            store_threshold: DEFAULT_STORE_THRESHOLD,
+            walk: WalkOptions::default(),
+            skip_errors: false,
+            acls: false,
+            atime: false,
+            threads: num_cpus::get(),
+        }
+    }
+}
+
+/// Options of `repack`: an image rewritten without the blocks nothing references
+pub struct RepackOptions {
+    pub image_path: PathBuf,
+    /// Where to write the repacked image [default: replace the image]
+    pub output_path: Option<PathBuf>,
+    /// Cut file contents again with these parameters instead of keeping the stored blocks
+    pub rechunk: Option<ChunkerOptions>,
+    /// Recompress blocks at this level instead of copying them as stored
+    pub compression_level: Option<i32>,
+    /// Blocks smaller than this many bytes are stored uncompressed when recompressing
+    pub store_threshold: u64,
+    /// Staging of block data past the memory limit
+    pub spill: SpillOptions,
+    /// Threads compressing the indexes
+    pub threads: usize,
+}
+
+impl Default for RepackOptions {
+    fn default() -> Self {
+        Self {
+            image_path: PathBuf::new(),
+            output_path: None,
+            rechunk: None,
+            compression_level: None,
+            store_threshold: DEFAULT_STORE_THRESHOLD,
+            spill: SpillOptions::default(),
+            threads: num_cpus::get(),
+        }
+    }
+}
+
+/// What `repack` changed
+#[derive(Debug, Clone, Copy)]
+pub struct RepackSummary {
+    pub blocks_before: u64,
+    pub blocks_after: u64,
+    /// Size of the image file before and after, in bytes
+    pub size_before: u64,
+    pub size_after: u64,
+}
+
+impl RepackSummary {
+    /// Bytes the repacked image saves, 0 if it grew
+    pub fn reclaimed(&self) -> u64 {
+        self.size_before.saturating_sub(self.size_after)
+    }
+}
+
+/// How files with identical content are materialized on extraction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum ExtractDedup {
+    /// Write every file independently
+    #[default]
+    Copy,
+    /// Hardlink files whose block list, owner, mode and times match an already extracted file
+    Hardlink,
+    /// Clone already extracted files copy-on-write (Btrfs, XFS, APFS), copying when unsupported
+    Reflink,
+}
+
+pub struct ExtractOptions {
+    pub image_path: PathBuf,
+    pub output_path: PathBuf,
+    pub dedup: ExtractDedup,
+    /// Unicode normalization applied to entry names
+    pub normalize: Option<NormalizationForm>,
+    /// Policy for names differing only by case on case-insensitive destinations
+    pub case_collision: CaseCollision,
+    /// Skip entries that cannot be restored and record them in the report instead of aborting
+    pub skip_errors: bool,
+    /// List the entries that would be written in the report without touching the destination
+    pub dry_run: bool,
+    /// How compressed blocks are read from the image
+    pub io_backend: IoBackend,
+    /// Require a manifest signed by this Ed25519 public key
+    pub trusted_key: Option<[u8; 32]>,
+    /// Extract even if the signed manifest is missing or does not verify
+    pub force: bool,
+    /// Images applied over `image_path` in order: their entries replace those of the
+    /// same path and the deletions they record remove entries of the images before them
+    pub overlays: Vec<PathBuf>,
+    /// Entries matching one of these globs are extracted before all others
+    pub first: Vec<glob::Pattern>,
+    /// Only extract this entry and the entries under it
+    pub prefix: Option<PathBuf>,
+    /// Leading path components removed from each entry on output; entries with
+    /// no component left are not extracted
+    pub strip_components: usize,
+    /// Restore the recorded security descriptors once every entry is written
+    pub acls: bool,
+    /// Owners recorded in the image replaced when setting ownership
+    pub owner_map: IdMap,
+    /// Groups recorded in the image replaced when setting ownership
+    pub group_map: IdMap,
+    /// Fail on ownership, permissions or nodes that cannot be restored, instead of
+    /// reporting them; without root, ownership and device nodes are otherwise skipped
+    pub strict_metadata: bool,
+}
+
+impl Default for ExtractOptions {
+    fn default() -> Self {
+        Self {
+            image_path: PathBuf::new(),
+            output_path: PathBuf::new(),
+            dedup: ExtractDedup::default(),
+            normalize: None,
+            case_collision: CaseCollision::default(),
+            skip_errors: false,
+            dry_run: false,
+            io_backend: IoBackend::default(),
+            trusted_key: None,
+            force: false,
+            overlays: Vec::new(),
+            first: Vec::new(),
+            prefix: None,
+            strip_components: 0,
+            acls: false,
+            owner_map: IdMap::default(),
+            group_map: IdMap::default(),
+            strict_metadata: false,
+        }
+    }
+}
+
+/// Hash d'un bloc avec l'algorithme par défaut
+pub(crate) fn calculate_hash(data: &[u8]) -> BlockHash {
+    HashAlgorithm::default().hash(data)
+}
+
+/// Blocs déjà pris en charge par un thread de compression ; le filtre de Bloom
+/// répond « nouveau bloc » sans consulter l'ensemble dans le cas courant
+struct BlockClaims {
+    filter: BloomFilter,
+    /// Hash de vérification du premier bloc vu (mode `verify_dedup`)
+    claimed: HashMap<BlockHash, Option<BlockHash>>,
+}
+
+impl BlockClaims {
+    /// `true` si le bloc n'avait encore jamais été vu. Avec un hash de
+    /// vérification, un doublon dont le contenu diffère est une collision.
+    fn claim(&mut self, hash: &BlockHash, check: Option<BlockHash>) -> Result<bool, ImageError> {
+        if !self.filter.may_contain(hash) {
+            self.filter.insert(hash);
+            self.claimed.insert(hash.clone(), check);
+            return Ok(true);
+        }
+        match self.claimed.entry(hash.clone()) {
+            Entry::Vacant(entry) => {
+                entry.insert(check);
+                Ok(true)
+            }
+            Entry::Occupied(entry) => match (entry.get(), &check) {
+                (Some(first), Some(check)) if first != check => Err(ImageError::HashCollision(hash.to_hex())),
+                _ => Ok(false),
+            },
+        }
+    }
+}
+
+fn kind_to_byte(kind: EntryKind) -> u8 {
+    match kind {
+        EntryKind::File => 0,
+        EntryKind::Directory => 1,
+        EntryKind::Symlink => 2,
+        EntryKind::Fifo => 3,
+        EntryKind::Socket => 4,
+        EntryKind::CharDevice => 5,
+        EntryKind::BlockDevice => 6,
+        EntryKind::Stream => 7,
+    }
+}
+
+fn kind_from_byte(byte: u8) -> Result<EntryKind, ImageError> {
+    Ok(match byte {
+        0 => EntryKind::File,
+        1 => EntryKind::Directory,
+        2 => EntryKind::Symlink,
+        3 => EntryKind::Fifo,
+        4 => EntryKind::Socket,
+        5 => EntryKind::CharDevice,
+        6 => EntryKind::BlockDevice,
+        7 => EntryKind::Stream,
+        _ => return Err(ImageError::CorruptIndex(format!("Type d'entrée inconnu: {}", byte))),
+    })
+}
+
+/// Contenu d'un fichier source, projeté en mémoire pour éviter de le copier
+enum FileData {
+    Mapped(Mmap),
+    Read(Vec<u8>),
+}
+
+impl Deref for FileData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            FileData::Mapped(map) => map,
+            FileData::Read(buffer) => buffer,
+        }
+    }
+}
+
+fn read_file(path: &Path, size: u64) -> std::io::Result<FileData> {
+    let mut file = File::open(path)?;
+    // Une projection de taille nulle est refusée par le système
+    if size > 0 {
+        // SAFETY: la projection est en lecture seule ; un fichier modifié pendant la
+        // capture donne un contenu incohérent, comme avec une lecture classique
+        if let Ok(map) = unsafe { Mmap::map(&file) } {
+            #[cfg(unix)]
+            let _ = map.advise(memmap2::Advice::Sequential);
+            return Ok(FileData::Mapped(map));
+        }
+    }
+    // Systèmes de fichiers sans projection (pseudo-fichiers, certains montages réseau)
+    let mut buffer = Vec::with_capacity(size as usize);
+    file.read_to_end(&mut buffer)?;
+    Ok(FileData::Read(buffer))
+}
+
+/// Étape de lecture : métadonnées de l'entrée, cible des liens et contenu des fichiers,
+/// descripteur de sécurité avec `acls`, date d'accès avec `atime`
+fn load_entry(entry: &WalkedEntry, acls: bool, atime: bool) -> std::io::Result<(FileEntry, Option<FileData>)> {
+    let mut file_entry = FileEntry {
+        path: entry.relative_path.clone(),
+        size: 0,
+        modified: 0,
+        kind: entry.kind,
+        blocks: Vec::new(),
+        link_target: None,
+        security: None,
+        device_numbers: None,
+        attributes: None,
+        accessed: None,
+    };
+    // Les flux partagent le descripteur et le propriétaire de leur fichier
+    if entry.kind != EntryKind::Stream {
+        file_entry.attributes = platform::unix_attributes(&entry.metadata)
+            .map(|(uid, gid, mode)| UnixAttributes { uid, gid, mode });
+    }
+    // Relevée au parcours, avant que la lecture du contenu ne la modifie
+    if atime && entry.kind != EntryKind::Stream {
+        file_entry.accessed = entry.metadata.accessed().ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|d| d.as_secs());
+    }
+    if acls && !matches!(entry.kind, EntryKind::Symlink | EntryKind::Stream) {
+        file_entry.security = platform::read_security_descriptor(&entry.path)?;
+    }
+    if entry.kind.has_content() || entry.kind == EntryKind::Directory {
+        file_entry.modified = entry.metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+    }
+    match entry.kind {
+        EntryKind::Symlink => {
+            file_entry.link_target = Some(fs::read_link(&entry.path)?);
+            Ok((file_entry, None))
+        }
+        EntryKind::File | EntryKind::Stream => {
+            file_entry.size = entry.metadata.len();
+            let data = read_file(&entry.path, file_entry.size)?;
+            Ok((file_entry, Some(data)))
+        }
+        EntryKind::CharDevice | EntryKind::BlockDevice => {
+            file_entry.device_numbers = Some(platform::device_numbers(&entry.metadata));
+            Ok((file_entry, None))
+        }
+        _ => Ok((file_entry, None)),
+    }
+}
+
+/// Travail de l'étape de lecture
+enum CaptureJob {
+    Entry(Box<WalkedEntry>),
+    /// Portion d'un périphérique, capturé comme une seule entrée
+    Segment(Range<u64>),
+}
+
+impl CaptureJob {
+    /// Octets de contenu à lire
+    fn size(&self) -> u64 {
+        match self {
+            CaptureJob::Entry(entry) if entry.kind.has_content() => entry.metadata.len(),
+            CaptureJob::Entry(_) => 0,
+            CaptureJob::Segment(range) => range.end - range.start,
+        }
+    }
+}
+
+/// Entrée parcourue suivie des flux de données alternatifs du fichier, nommés
+/// `fichier:flux` ; hors de Windows, l'entrée seule
+fn with_streams(walked: Result<WalkedEntry, WalkError>) -> Vec<Result<WalkedEntry, WalkError>> {
+    let entry = match walked {
+        Ok(entry) if entry.kind == EntryKind::File => entry,
+        other => return vec![other],
+    };
+    let streams = match platform::data_streams(&entry.path) {
+        Ok(streams) => streams,
+        Err(source) => return vec![Err(WalkError { path: entry.path, source })],
+    };
+    let mut entries = Vec::with_capacity(streams.len() + 1);
+    for stream in streams {
+        let path = stream_path(&entry.path, &stream);
+        entries.push(match fs::metadata(&path) {
+            Ok(metadata) => Ok(WalkedEntry {
+                relative_path: stream_path(&entry.relative_path, &stream),
+                path,
+                kind: EntryKind::Stream,
+                metadata,
+            }),
+            Err(source) => Err(WalkError { path, source }),
+        });
+    }
+    entries.insert(0, Ok(entry));
+    entries
+}
+
+/// Chemin `fichier:flux` d'un flux de données alternatif
+fn stream_path(file: &Path, stream: &str) -> PathBuf {
+    let mut path = file.as_os_str().to_owned();
+    path.push(":");
+    path.push(stream);
+    PathBuf::from(path)
+}
+
+/// Fichier et nom du flux d'une entrée `Stream` ; le nom d'un flux ne contient pas `:`
+fn split_stream(path: &Path) -> Option<(PathBuf, &str)> {
+    let (file, stream) = path.file_name()?.to_str()?.rsplit_once(':')?;
+    Some((path.with_file_name(file), stream))
+}
+
+/// Étape de lecture d'une portion de périphérique, zéros compris pour les blocs libres
+fn load_segment(device: &Device, name: &Path, range: Range<u64>) -> std::io::Result<(FileEntry, Option<FileData>)> {
+    let file_entry = FileEntry {
+        path: name.to_path_buf(),
+        size: range.end - range.start,
+        modified: device.modified(),
+        kind: EntryKind::File,
+        blocks: Vec::new(),
+        link_target: None,
+        security: None,
+        device_numbers: None,
+        attributes: None,
+        accessed: None,
+    };
+    let data = device.read_segment(range)?;
+    Ok((file_entry, Some(FileData::Read(data))))
+}
+
+/// BLAKE3 du contenu d'un fichier ou de la cible d'un lien, nul pour les autres entrées
+fn content_hash(entry: &FileEntry, data: Option<&[u8]>) -> [u8; 32] {
+    match (&entry.link_target, data) {
+        (Some(target), _) => *blake3::hash(target.as_os_str().as_encoded_bytes()).as_bytes(),
+        (None, Some(data)) => *blake3::hash(data).as_bytes(),
+        (None, None) => [0; 32],
+    }
+}
+
+pub fn create_image(options: &ImageOptions) -> Result<Report, ImageError> {
+    let _span = info_span!("create_image", input = %options.input_path.display()).entered();
+    info!("Création de l'image depuis {:?}", options.input_path);
+    let mut report = Report::default();
+    
+    let mut file_entries = Vec::new();
+    let mut content_hashes = Vec::new();
+    // Blocs uniques dans l'ordre d'écriture ; leurs données compressées passent sur
+    // disque au-delà de la limite mémoire
+    let mut block_store: Vec<BlockRecord> = Vec::new();
+    let mut block_data = SpillBuffer::new(&options.spill);
+    let mut total_size = 0u64;
+    let mut total_files = 0u64;
+    
+    // Le manifeste signé porte un hash par entrée, qu'un périphérique lu par portions n'a pas
+    if options.device && options.sign_key.is_some() {
+        let message = "signed manifests are not supported for devices";
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, message).into());
+    }
+    if options.device && options.base.is_some() {
+        let message = "differential images are not supported for devices";
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, message).into());
+    }
+    let device = if options.device {
+        Some(Device::open(&options.input_path).map_err(|e| ImageError::io_at(e, &options.input_path))?)
+    } else {
+        None
+    };
+    let device_name = entry_name(&options.input_path);
+    // Une erreur de lecture d'une portion corromprait l'unique entrée du périphérique
+    let skip_errors = options.skip_errors && !options.device;
+    
+    // Parcours récursif des fichiers, ou portions du périphérique
+    let mut entries = Vec::new();
+    match &device {
+        Some(device) => entries.extend(device.segments().into_iter().map(CaptureJob::Segment)),
+        None => {
+            let walk_span = debug_span!("scan").entered();
+            for entry in walk(&options.input_path, &options.walk).flat_map(with_streams) {
+                match entry {
+                    Ok(entry) => entries.push(CaptureJob::Entry(Box::new(entry))),
+                    Err(e) => {
+                        let path = e.path.clone();
+                        report.skip_or_fail(options.skip_errors, &path, ImageError::from(e))?;
+                    }
+                }
+            }
+            drop(walk_span);
+        }
+    }
+    
+    // Nombre total de fichiers pour la progression
+    let total_entries = match device {
+        Some(_) => 1,
+        None => entries.iter().filter(|job| matches!(job, CaptureJob::Entry(e) if e.kind == EntryKind::File)).count() as u64,
+    };
+    info!("Nombre total de fichiers à traiter: {}", total_entries);
+    let metrics = options.metrics.as_deref();
+    if let Some(metrics) = metrics {
+        let total_bytes = entries.iter().map(CaptureJob::size).sum();
+        metrics.set_totals(total_entries, total_bytes);
+    }
+    
+    let start_time = std::time::Instant::now();
+    let mut processed_size = 0u64;
+    let expected_blocks: u64 = entries.iter()
+        .map(|job| job.size().div_ceil(options.chunker.avg_size as u64))
+        .sum();
+    // Image différentielle : seules les entrées modifiées depuis l'état de la chaîne de base sont gardées
+    let (base_chain, previous) = match &options.base {
+        Some(base) => {
+            let chain = image_chain(base)?;
+            let layers = chain.iter().map(|path| read_layer(path)).collect::<Result<Vec<_>, _>>()?;
+            (chain, merge_layers(layers))
+        }
+        None => (Vec::new(), Vec::new()),
+    };
+    let scanned: HashSet<&Path> = entries.iter()
+        .filter_map(|job| match job {
+            CaptureJob::Entry(entry) => Some(entry.relative_path.as_path()),
+            CaptureJob::Segment(_) => None,
+        })
+        .collect();
+    let deletions: Vec<PathBuf> = previous.iter()
+        .filter(|entry| !scanned.contains(entry.path.as_path()))
+        .map(|entry| entry.path.clone())
+        .collect();
+    let previous: HashMap<PathBuf, FileEntry> = previous.into_iter().map(|entry| (entry.path.clone(), entry)).collect();
+    // Blocs déjà présents dans les images de base : référencés, pas stockés
+    let bases = base_chain.iter().chain(&options.dedup_against)
+        .map(|path| BaseImage::open(path, options.hash_algorithm))
+        .collect::<Result<Vec<_>, _>>()?;
+    let claimed_blocks = Mutex::new(BlockClaims {
+        filter: BloomFilter::with_capacity(expected_blocks, DEFAULT_FALSE_POSITIVE_RATE),
+        claimed: HashMap::new(),
+    });
+    
+    // Lecture, hachage, compression et enregistrement se recouvrent ; les blocs
+    // compressés repassent par le pool une fois écrits
+    let buffers = BufferPool::new(options.pipeline.queue_depth * 16, MAX_POOLED_CAPACITY);
+    run_hashed_pipeline(
+        &options.pipeline,
+        entries,
+        |job| {
+            let (path, relative_path) = match &job {
+                CaptureJob::Entry(entry) => (entry.path.clone(), entry.relative_path.as_path()),
+                CaptureJob::Segment(_) => (options.input_path.clone(), device_name.as_path()),
+            };
+            let _span = debug_span!("read", path = %relative_path.display(), size = job.size()).entered();
+            if let Some(metrics) = metrics {
+                metrics.begin("read", relative_path);
+            }
+            let loaded = match (&job, &device) {
+                (CaptureJob::Entry(entry), _) => load_entry(entry, options.acls, options.atime),
+                (CaptureJob::Segment(range), Some(device)) => load_segment(device, &device_name, range.clone()),
+                (CaptureJob::Segment(_), None) => unreachable!("segments come from a device"),
+            };
+            if let Some(metrics) = metrics {
+                metrics.finish();
+            }
+            let loaded = loaded.map_err(|e| ImageError::io_at(e, &path));
+            (path, loaded)
+        },
+        |(path, loaded)| {
+            let (mut file_entry, data) = match loaded {
+                Ok(loaded) => loaded,
+                Err(e) => return (path, Err(e)),
+            };
+            let _span = debug_span!("hash", path = %file_entry.path.display(), size = file_entry.size).entered();
+            if let Some(metrics) = metrics {
+                metrics.begin("hash", &file_entry.path);
+            }
+            // Hash de chaque bloc, avec sa taille pour le retrouver dans les données
+            let mut chunks = Vec::new();
+            for block_data in options.chunker.chunks(data.as_deref().unwrap_or_default()) {
+                let hash = options.hash_algorithm.hash(block_data);
+                let check = options.verify_dedup.then(|| options.hash_algorithm.verifier().hash(block_data));
+                file_entry.blocks.push(hash.clone());
+                chunks.push((hash, check, block_data.len()));
+            }
+            // Hash du contenu pour le manifeste signé
+            let content_hash = options.sign_key.as_ref().map(|_| content_hash(&file_entry, data.as_deref()));
+            if let Some(metrics) = metrics {
+                metrics.finish();
+            }
+            (path, Ok((file_entry, data, chunks, content_hash)))
+        },
+        |(path, hashed)| -> Result<_, ImageError> {
+            // Les erreurs de lecture sont propres à l'entrée, celles de compression sont fatales
+            let (file_entry, data, chunks, content_hash) = match hashed {
+                Ok(hashed) => hashed,
+                Err(e) => return Ok((path, Err(e))),
+            };
+            let _span = debug_span!("dedup", path = %file_entry.path.display(), size = file_entry.size).entered();
+            if let Some(metrics) = metrics {
+                metrics.begin("dedup", &file_entry.path);
+            }
+            let mut new_blocks = Vec::new();
+            let mut remaining = data.as_deref().unwrap_or_default();
+            for (hash, check, length) in chunks {
+                let (block_data, rest) = remaining.split_at(length);
+                remaining = rest;
+                
+                // Déduplication : ne compresser que les blocs uniques
+                let claimed = claimed_blocks.lock().unwrap_or_else(|e| e.into_inner()).claim(&hash, check)?;
+                let stored = claimed && !bases.iter().any(|base| base.contains(&hash));
+                if let Some(metrics) = metrics {
+                    if stored {
+                        metrics.increment_unique_blocks();
+                    } else {
+                        metrics.increment_duplicate_blocks();
+                    }
+                }
+                if stored {
+                    let (codec, compressed_data) = compress_block(block_data, options.compression_level, options.store_threshold, buffers.take())?;
+                    new_blocks.push((hash, DataBlock {
+                        compressed_data,
+                        original_size: block_data.len(),
+                        codec,
+                    }));
+                }
+            }
+            if let Some(metrics) = metrics {
+                metrics.finish();
+            }
+            if let Some(FileData::Read(buffer)) = data {
+                buffers.give(buffer);
+            }
+            Ok((path, Ok((file_entry, new_blocks, content_hash))))
+        },
+        |processed| {
+            let (path, result) = processed?;
+            let _span = debug_span!("record", path = %path.display()).entered();
+            let (file_entry, new_blocks, content_hash) = match result {
+                Ok(processed) => processed,
+                Err(e) => return report.skip_or_fail(skip_errors, &path, e),
+            };
+            if let Some(metrics) = metrics {
+                metrics.add_bytes_compressed(new_blocks.iter().map(|(_, block)| block.compressed_data.len() as u64).sum());
+            }
+            // Entrée identique dans la base : ses blocs y sont déjà, rien à enregistrer
+            if device.is_none() && previous.get(&file_entry.path).is_some_and(|previous| same_entry(previous, &file_entry)) {
+                return Ok(());
+            }
+            for (hash, block) in new_blocks {
+                block_data.write_all(&block.compressed_data)?;
+                block_store.push((hash, block.original_size, block.compressed_data.len(), block.codec));
+                buffers.give(block.compressed_data);
+            }
+            content_hashes.extend(content_hash);
+            if file_entry.kind != EntryKind::File {
+                file_entries.push(file_entry);
+                return Ok(());
+            }
+            if let Some(metrics) = metrics {
+                metrics.add_bytes_processed(file_entry.size);
+            }
+            total_size += file_entry.size;
+            processed_size += file_entry.size;
+            // Les portions suivantes d'un périphérique prolongent son entrée
+            if let (Some(_), Some(captured)) = (&device, file_entries.last_mut()) {
+                captured.size += file_entry.size;
+                captured.blocks.extend(file_entry.blocks);
+                return Ok(());
+            }
+            if let Some(metrics) = metrics {
+                metrics.increment_files();
+            }
+            total_files += 1;
+            file_entries.push(file_entry);
+            
+            // Progression améliorée
+            if total_files.is_multiple_of(5) {
+                let elapsed = start_time.elapsed().as_secs_f64();
+                let progress = (total_files as f64 / total_entries as f64) * 100.0;
+                let speed_mbs = (processed_size as f64 / (1024.0 * 1024.0)) / elapsed;
+                
+                info!(
+                    "Progression: {:.1}% ({}/{}) - {:.1} MB/s - Blocs uniques: {}",
+                    progress, total_files, total_entries, speed_mbs, block_store.len()
+                );
+            }
+            Ok(())
+        },
+    )?;
+    let filter = claimed_blocks.into_inner().unwrap_or_else(|e| e.into_inner()).filter;
+    
+    // Calcul de la taille compressée
+    let compressed_size = block_data.len() as usize;
+    
+    // Index des blocs
+    let mut block_index = Vec::with_capacity(block_store.len() * 56);
+    for record in &block_store {
+        write_block_record(&mut block_index, record)?;
+    }
+    
+    // Index des fichiers
+    let file_index = encode_file_index(&file_entries, &deletions)?;
+    
+    // Les index sont compressés : sur des millions d'entrées, les chemins pèsent lourd
+    let threads = options.pipeline.compress_threads;
+    let compressed_block_index = encode_index(&block_index, options.compression_level, threads)?;
+    let compressed_file_index = encode_index(&file_index, options.compression_level, threads)?;
+    
+    // Manifeste signé : chaque entrée avec le hash BLAKE3 de son contenu
+    let signed_manifest = match &options.sign_key {
+        Some(key) => {
+            let entries = file_entries.iter().zip(&content_hashes)
+                .map(|(entry, &content_hash)| ManifestEntry {
+                    path: entry.path.clone(),
+                    kind: kind_to_byte(entry.kind),
+                    size: entry.size,
+                    content_hash,
+                })
+                .collect();
+            Some(Manifest { entries }.sign(key)?)
+        }
+        None => None,
+    };
+    let compressed_manifest = match &signed_manifest {
+        Some(signed) => encode_index(&signed.bytes, options.compression_level, threads)?,
+        None => Vec::new(),
+    };
+    
+    // Écriture de l'image
+    let mut output_file = BufWriter::new(MirrorWriter::create::<ImageError>(&options.output_path, options.mirror_path.as_deref(), options.direct_io)?);
+    
+    // Header
+    let header = ImageHeader {
+        version: IMAGE_VERSION,
+        created: std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0),
+        total_files,
+        total_size,
+        compressed_size: compressed_size as u64,
+        block_count: block_store.len() as u64,
+        block_index: IndexSection {
+            compressed_size: compressed_block_index.len() as u64,
+            original_size: block_index.len() as u64,
+        },
+        file_index: IndexSection {
+            compressed_size: compressed_file_index.len() as u64,
+            original_size: file_index.len() as u64,
+        },
+        hash_algorithm: Some(options.hash_algorithm),
+        external_images: bases.iter().map(|base| base.reference.clone()).collect(),
+        base: options.base.as_ref().map(|_| base_chain.len() - 1),
+        chunker: options.chunker,
+        manifest: IndexSection {
+            compressed_size: compressed_manifest.len() as u64,
+            original_size: signed_manifest.as_ref().map_or(0, |signed| signed.bytes.len() as u64),
+        },
+        appended: AppendedBlocks::default(),
+    };
+    
+    write_header(&mut output_file, &header, native_path_encoding())?;
+    
+    // Filtre de Bloom des blocs (version 4+), réutilisable par les exécutions suivantes
+    output_file.write_all(&filter.byte_len().to_le_bytes())?;
+    filter.write_to(&mut output_file)?;
+    
+    // Manifeste signé (version 9+), suivi de la clé publique et de la signature
+    if let Some(signed) = &signed_manifest {
+        output_file.write_all(&compressed_manifest)?;
+        output_file.write_all(&signed.public_key)?;
+        output_file.write_all(&signed.signature)?;
+    }
+    
+    output_file.write_all(&compressed_block_index)?;
+    
+    // Données des blocs
+    std::io::copy(&mut block_data.into_reader()?, &mut output_file)?;
+    
+    output_file.write_all(&compressed_file_index)?;
+    output_file.flush()?;
+    drop(output_file);
+    
+    if options.verify_write {
+        for path in std::iter::once(&options.output_path).chain(&options.mirror_path) {
+            verify_written(path, block_store.iter().map(|(hash, ..)| hash))?;
+        }
+    }
+    
+    let ratio = (compressed_size as f64 / total_size as f64) * 100.0;
+    info!("Image créée: {} fichiers, {:.2}% de compression", total_files, 100.0 - ratio);
+    info!("Taille originale: {} bytes", total_size);
+    info!("Taille compressée: {} bytes", compressed_size);
+    info!("Blocs uniques: {}", block_store.len());
+    
+    Ok(report)
+}
+
+/// Ajoute le contenu de `input_path` à une image existante sans la recréer. Les
+/// blocs absents de l'image sont écrits en fin de fichier, suivis de l'index de
+/// tous les blocs ajoutés depuis la création et du nouvel index des fichiers ; le
+/// header, réécrit sur place en dernier, les rend visibles. Jusque-là l'image reste
+/// lisible dans son état précédent. Les index remplacés restent dans le fichier,
+/// inutilisés. Une entrée de même chemin qu'une entrée existante la remplace.
+pub fn add_to_image(options: &AddOptions) -> Result<Report, ImageError> {
+    let _span = info_span!("add_to_image", image = %options.image_path.display(), input = %options.input_path.display()).entered();
+    let refuse = |message: String| ImageError::from(std::io::Error::new(std::io::ErrorKind::InvalidInput, message));
+    let mut report = Report::default();
+    
+    let OpenedImage { mut input_file, mut header, path_encoding, mut filter, block_index, .. } = open_image(&options.image_path, true, false)?;
+    // Le header doit garder sa taille pour être réécrit sur place
+    if header.version != IMAGE_VERSION {
+        return Err(refuse(format!("image version {} cannot be extended in place, recreate it with this version", header.version)));
+    }
+    // Le manifeste signé ne couvrirait pas les nouvelles entrées
+    if header.manifest.compressed_size > 0 {
+        return Err(refuse("signed images cannot be extended in place".to_string()));
+    }
+    if path_encoding != native_path_encoding() {
+        return Err(refuse("the image stores paths in another platform's encoding".to_string()));
+    }
+    let (mut entries, mut deletions) = read_layer_from(&mut input_file, &header, path_encoding)?;
+    let mut appended = read_appended_blocks(&mut input_file, &header)?;
+    drop(input_file);
+    
+    let dest: PathBuf = match &options.dest {
+        Some(dest) => dest.components().filter(|component| *component != std::path::Component::CurDir).collect(),
+        None => options.input_path.canonicalize()
+            .map_err(|e| ImageError::io_at(e, &options.input_path))?
+            .file_name()
+            .map(PathBuf::from)
+            .unwrap_or_default(),
+    };
+    let mut positions: HashMap<PathBuf, usize> = entries.iter().enumerate().map(|(i, entry)| (entry.path.clone(), i)).collect();
+    // Dossiers menant à la destination, absents de l'image
+    let mut ancestors: Vec<&Path> = dest.ancestors().skip(1).filter(|path| !path.as_os_str().is_empty()).collect();
+    ancestors.reverse();
+    for ancestor in ancestors {
+        if !positions.contains_key(ancestor) {
+            positions.insert(ancestor.to_path_buf(), entries.len());
+            entries.push(FileEntry {
+                path: ancestor.to_path_buf(),
+                size: 0,
+                modified: 0,
+                kind: EntryKind::Directory,
+                blocks: Vec::new(),
+                link_target: None,
+                security: None,
+                device_numbers: None,
+                attributes: None,
+                accessed: None,
+            });
+        }
+    }
+    
+    // Nouveaux blocs en fin de fichier, après les index actuels
+    let mut output_file = File::options().write(true).open(&options.image_path).map_err(|e| ImageError::io_at(e, &options.image_path))?;
+    let mut position = output_file.seek(SeekFrom::End(0))?;
+    let mut writer = BufWriter::new(output_file);
+    let algorithm = header.hash_algorithm.unwrap_or_default();
+    let level = options.compression_level;
+    let mut new_blocks = HashSet::new();
+    let (mut added, mut replaced, mut new_data) = (0u64, 0u64, 0u64);
+    // Tampon de compression repris d'un bloc à l'autre
+    let mut scratch = Vec::new();
+    for walked in walk(&options.input_path, &options.walk).flat_map(with_streams) {
+        let walked = match walked {
+            Ok(walked) => walked,
+            Err(e) => {
+                let path = e.path.clone();
+                report.skip_or_fail(options.skip_errors, &path, ImageError::from(e))?;
+                continue;
+            }
+        };
+        let (mut entry, data) = match load_entry(&walked, options.acls, options.atime) {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                report.skip_or_fail(options.skip_errors, &walked.path, ImageError::io_at(e, &walked.path))?;
+                continue;
+            }
+        };
+        entry.path = if entry.path.as_os_str().is_empty() { dest.clone() } else { dest.join(&entry.path) };
+        // La racine de l'image existe déjà
+        if entry.path.as_os_str().is_empty() {
+            continue;
+        }
+        let _span = debug_span!("add", path = %entry.path.display(), size = entry.size).entered();
+        for block_data in header.chunker.chunks(data.as_deref().unwrap_or_default()) {
+            let hash = algorithm.hash(block_data);
+            entry.blocks.push(hash.clone());
+            if block_index.contains(&hash) || !new_blocks.insert(hash.clone()) {
+                continue;
+            }
+            let (codec, compressed_data) = compress_block(block_data, level, options.store_threshold, std::mem::take(&mut scratch))?;
+            writer.write_all(&compressed_data)?;
+            if let Some(filter) = &mut filter {
+                filter.insert(&hash);
+            }
+            appended.push(((hash, block_data.len(), compressed_data.len(), codec), position));
+            position += compressed_data.len() as u64;
+            new_data += compressed_data.len() as u64;
+            scratch = compressed_data;
+        }
+        
+        deletions.retain(|path| *path != entry.path);
+        match positions.get(&entry.path) {
+            Some(&i) if entries[i].kind == EntryKind::Directory && entry.kind == EntryKind::Directory => {}
+            Some(&i) => {
+                entries[i] = entry;
+                replaced += 1;
+            }
+            None => {
+                positions.insert(entry.path.clone(), entries.len());
+                entries.push(entry);
+                added += 1;
+            }
+        }
+    }
+    
+    // Index de tous les blocs ajoutés, avec leur position, puis le nouvel index des fichiers
+    let mut appended_index = Vec::new();
+    for (record, offset) in &appended {
+        write_block_record(&mut appended_index, record)?;
+        appended_index.write_all(&offset.to_le_bytes())?;
+    }
+    let compressed_appended_index = encode_index(&appended_index, level, options.threads)?;
+    let file_index = encode_file_index(&entries, &deletions)?;
+    let compressed_file_index = encode_index(&file_index, level, options.threads)?;
+    writer.write_all(&compressed_appended_index)?;
+    writer.write_all(&compressed_file_index)?;
+    let mut output_file = writer.into_inner().map_err(|e| e.into_error())?;
+    output_file.sync_data()?;
+    
+    header.block_count += new_blocks.len() as u64;
+    header.compressed_size += new_data;
+    header.total_files = entries.iter().filter(|entry| entry.kind == EntryKind::File).count() as u64;
+    header.total_size = entries.iter().filter(|entry| entry.kind == EntryKind::File).map(|entry| entry.size).sum();
+    header.file_index = IndexSection { compressed_size: compressed_file_index.len() as u64, original_size: file_index.len() as u64 };
+    header.appended = AppendedBlocks {
+        offset: position,
+        count: appended.len() as u64,
+        index: IndexSection { compressed_size: compressed_appended_index.len() as u64, original_size: appended_index.len() as u64 },
+    };
+    
+    // Le filtre de Bloom, de taille fixe, puis le header qui valide l'ajout
+    let mut header_bytes = Vec::new();
+    write_header(&mut header_bytes, &header, path_encoding)?;
+    if let Some(filter) = &filter {
+        output_file.seek(SeekFrom::Start(header_bytes.len() as u64 + 8))?;
+        let mut filter_bytes = BufWriter::new(&mut output_file);
+        filter.write_to(&mut filter_bytes)?;
+        filter_bytes.flush()?;
+        drop(filter_bytes);
+        output_file.sync_data()?;
+    }
+    output_file.seek(SeekFrom::Start(0))?;
+    output_file.write_all(&header_bytes)?;
+    output_file.sync_all()?;
+    
+    info!("{} entrées ajoutées, {} remplacées, {} blocs nouveaux ({} octets)", added, replaced, new_blocks.len(), new_data);
+    Ok(report)
+}
+
+/// Réécrit une image sans les blocs qu'aucune entrée ne référence (entrées remplacées
+/// par `add-to-image`, index devenus inutiles), dans l'ordre où les fichiers les
+/// lisent. Les blocs sont copiés tels quels, sauf avec un nouveau niveau de
+/// compression ou un nouveau découpage. Les entrées, suppressions, images
+/// référencées et le manifeste signé sont conservés ; la nouvelle date de création
+/// fait refuser l'image par celles qui la référençaient.
+pub fn repack_image(options: &RepackOptions) -> Result<RepackSummary, ImageError> {
+    let _span = info_span!("repack_image", image = %options.image_path.display()).entered();
+    let size_before = fs::metadata(&options.image_path).map_err(|e| ImageError::io_at(e, &options.image_path))?.len();
+    let OpenedImage { mut input_file, header, path_encoding, signed_manifest, block_index, .. } = open_image(&options.image_path, false, true)?;
+    let (mut entries, deletions) = read_layer_from(&mut input_file, &header, path_encoding)?;
+    drop(input_file);
+    let blocks_before = header.block_count;
+    let mut sources = open_sources(&options.image_path, &header, block_index)?;
+    
+    let algorithm = header.hash_algorithm.unwrap_or_default();
+    let chunker = options.rechunk.unwrap_or(header.chunker);
+    // Niveau des index quand les blocs sont copiés tels quels
+    let level = options.compression_level.unwrap_or(22);
+    let mut block_store: Vec<BlockRecord> = Vec::new();
+    let mut block_data = SpillBuffer::new(&options.spill);
+    let mut written = HashSet::new();
+    let mut compressed_size = 0u64;
+    // Tampon de compression repris d'un bloc à l'autre
+    let mut scratch = Vec::new();
+    for entry in &mut entries {
+        let _span = debug_span!("repack", path = %entry.path.display(), size = entry.size).entered();
+        if let Some(chunker) = &options.rechunk {
+            let mut content = Vec::with_capacity(entry.size as usize);
+            for hash in &entry.blocks {
+                content.extend_from_slice(&read_block(&mut sources, hash, &entry.path)?);
+            }
+            entry.blocks.clear();
+            for block in chunker.chunks(&content) {
+                let hash = algorithm.hash(block);
+                entry.blocks.push(hash.clone());
+                // Bloc déjà présent dans une image référencée : il y reste
+                if sources[1..].iter().any(|source| source.block_index.contains(&hash)) || !written.insert(hash.clone()) {
+                    continue;
+                }
+                let (codec, compressed) = compress_block(block, level, options.store_threshold, std::mem::take(&mut scratch))?;
+                block_data.write_all(&compressed)?;
+                compressed_size += compressed.len() as u64;
+                block_store.push((hash, block.len(), compressed.len(), codec));
+                scratch = compressed;
+            }
+            continue;
+        }
+        for hash in &entry.blocks {
+            // Seuls les blocs stockés dans cette image sont réécrits
+            let Some((offset, original_size, stored_size, codec)) = sources[0].block_index.get(hash) else {
+                continue;
+            };
+            if !written.insert(hash.clone()) {
+                continue;
+            }
+            let stored = sources[0].reader.read_batch(&[(offset, stored_size)])?.remove(0);
+            let (codec, compressed) = match options.compression_level {
+                Some(level) if codec.is_supported() => compress_block(&codec.decode(&stored)?, level, options.store_threshold, std::mem::take(&mut scratch))?,
+                _ => (codec, stored),
+            };
+            block_data.write_all(&compressed)?;
+            compressed_size += compressed.len() as u64;
+            block_store.push((hash.clone(), original_size, compressed.len(), codec));
+            scratch = compressed;
+        }
+    }
+    drop(sources);
+    
+    let mut filter = BloomFilter::with_capacity(block_store.len() as u64, DEFAULT_FALSE_POSITIVE_RATE);
+    let mut block_index = Vec::with_capacity(block_store.len() * 56);
+    for record in &block_store {
+        filter.insert(&record.0);
+        write_block_record(&mut block_index, record)?;
+    }
+    let file_index = encode_file_index(&entries, &deletions)?;
+    let compressed_block_index = encode_index(&block_index, level, options.threads)?;
+    let compressed_file_index = encode_index(&file_index, level, options.threads)?;
+    let compressed_manifest = match &signed_manifest {
+        Some(signed) => encode_index(&signed.bytes, level, options.threads)?,
+        None => Vec::new(),
+    };
+    
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let files = entries.iter().filter(|entry| entry.kind == EntryKind::File);
+    let header = ImageHeader {
+        version: IMAGE_VERSION,
+        // Toujours postérieure à l'ancienne, même réécrite dans la même seconde
+        created: now.max(header.created + 1),
+        total_files: files.clone().count() as u64,
+        total_size: files.map(|entry| entry.size).sum(),
+        compressed_size,
+        block_count: block_store.len() as u64,
+        block_index: IndexSection { compressed_size: compressed_block_index.len() as u64, original_size: block_index.len() as u64 },
+        file_index: IndexSection { compressed_size: compressed_file_index.len() as u64, original_size: file_index.len() as u64 },
+        hash_algorithm: Some(algorithm),
+        chunker,
+        manifest: IndexSection {
+            compressed_size: compressed_manifest.len() as u64,
+            original_size: signed_manifest.as_ref().map_or(0, |signed| signed.bytes.len() as u64),
+        },
+        appended: AppendedBlocks::default(),
+        ..header
+    };
+    
+    // Écriture à côté de la destination, qui n'est remplacée qu'une fois l'image complète
+    let output_path = options.output_path.as_deref().unwrap_or(&options.image_path);
+    let output_dir = output_path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let staged = tempfile::NamedTempFile::new_in(output_dir).map_err(|e| ImageError::io_at(e, output_dir))?;
+    let mut output_file = BufWriter::new(staged);
+    write_header(&mut output_file, &header, native_path_encoding())?;
+    output_file.write_all(&filter.byte_len().to_le_bytes())?;
+    filter.write_to(&mut output_file)?;
+    if let Some(signed) = &signed_manifest {
+        output_file.write_all(&compressed_manifest)?;
+        output_file.write_all(&signed.public_key)?;
+        output_file.write_all(&signed.signature)?;
+    }
+    output_file.write_all(&compressed_block_index)?;
+    std::io::copy(&mut block_data.into_reader()?, &mut output_file)?;
+    output_file.write_all(&compressed_file_index)?;
+    let staged = output_file.into_inner().map_err(|e| e.into_error())?;
+    staged.as_file().sync_all()?;
+    let size_after = staged.as_file().metadata()?.len();
+    staged.persist(output_path).map_err(|e| ImageError::io_at(e.error, output_path))?;
+    
+    let summary = RepackSummary { blocks_before, blocks_after: block_store.len() as u64, size_before, size_after };
+    info!(
+        "Image réécrite: {} blocs sur {}, {} octets récupérés",
+        summary.blocks_after, summary.blocks_before, summary.reclaimed()
+    );
+    Ok(summary)
+}
+
+/// Bloc compressé au niveau donné, ou stocké tel quel sous le seuil : les très
+/// petits blocs grossiraient une fois compressés. Le résultat remplace le contenu
+/// de `output`, dont l'allocation est réutilisée
+fn compress_block(block: &[u8], level: i32, store_threshold: u64, mut output: Vec<u8>) -> std::io::Result<(Codec, Vec<u8>)> {
+    output.clear();
+    if (block.len() as u64) < store_threshold {
+        output.extend_from_slice(block);
+        Ok((Codec::STORED, output))
+    } else {
+        zstd::stream::copy_encode(block, &mut output, level)?;
+        Ok((Codec::zstd(level), output))
+    }
+}
+
+/// Index compressé sur `threads` threads : à haut niveau, celui d'une image de
+/// millions d'entrées prendrait sinon longtemps sur un seul cœur
+fn encode_index(index: &[u8], level: i32, threads: usize) -> std::io::Result<Vec<u8>> {
+    let mut encoder = zstd_encoder(Vec::new(), level, threads)?;
+    encoder.set_pledged_src_size(Some(index.len() as u64))?;
+    encoder.write_all(index)?;
+    encoder.finish()
+}
+
+/// Index des fichiers, avant compression : les entrées puis les chemins de la base
+/// absents de l'image (version 11+)
+fn encode_file_index(file_entries: &[FileEntry], deletions: &[PathBuf]) -> std::io::Result<Vec<u8>> {
+    let mut file_index = Vec::new();
+    file_index.write_all(&(file_entries.len() as u64).to_le_bytes())?;
+    for file_entry in file_entries {
+        write_path(&mut file_index, &file_entry.path)?;
+        file_index.write_all(&file_entry.size.to_le_bytes())?;
+        file_index.write_all(&file_entry.modified.to_le_bytes())?;
+        file_index.write_all(&[kind_to_byte(file_entry.kind)])?;
+        file_index.write_all(&(file_entry.blocks.len() as u64).to_le_bytes())?;
+        for block_hash in &file_entry.blocks {
+            file_index.write_all(&block_hash.0)?;
+        }
+        if let Some(target) = &file_entry.link_target {
+            write_path(&mut file_index, target)?;
+        }
+        if matches!(file_entry.kind, EntryKind::CharDevice | EntryKind::BlockDevice) {
+            let (major, minor) = file_entry.device_numbers.unwrap_or_default();
+            file_index.write_all(&major.to_le_bytes())?;
+            file_index.write_all(&minor.to_le_bytes())?;
+        }
+        let security = file_entry.security.as_deref().unwrap_or_default();
+        file_index.write_all(&(security.len() as u32).to_le_bytes())?;
+        file_index.write_all(security.as_bytes())?;
+        match file_entry.attributes {
+            Some(attributes) => {
+                file_index.write_all(&[1])?;
+                for field in [attributes.uid, attributes.gid, attributes.mode] {
+                    file_index.write_all(&field.to_le_bytes())?;
+                }
+            }
+            None => file_index.write_all(&[0])?,
+        }
+        file_index.write_all(&file_entry.accessed.unwrap_or(0).to_le_bytes())?;
+    }
+    file_index.write_all(&(deletions.len() as u64).to_le_bytes())?;
+    for path in deletions {
+        write_path(&mut file_index, path)?;
+    }
+    Ok(file_index)
+}
+
+/// Sérialisation simple du header, de taille fixe pour des images référencées
+/// inchangées : `add-to-image` le réécrit sur place
+fn write_header(output_file: &mut impl Write, header: &ImageHeader, path_encoding: u8) -> Result<(), ImageError> {
+    output_file.write_all(&header.version.to_le_bytes())?;
+    output_file.write_all(&header.created.to_le_bytes())?;
+    output_file.write_all(&header.total_files.to_le_bytes())?;
+    output_file.write_all(&header.total_size.to_le_bytes())?;
+    output_file.write_all(&header.compressed_size.to_le_bytes())?;
+    output_file.write_all(&header.block_count.to_le_bytes())?;
+    output_file.write_all(&[path_encoding])?;
+    for section in [header.block_index, header.file_index] {
+        output_file.write_all(&section.compressed_size.to_le_bytes())?;
+        output_file.write_all(&section.original_size.to_le_bytes())?;
+    }
+    output_file.write_all(&[header.hash_algorithm.unwrap_or_default().to_byte()])?;
+    
+    // Images référencées (version 7+)
+    output_file.write_all(&(header.external_images.len() as u64).to_le_bytes())?;
+    for external in &header.external_images {
+        write_path(&mut *output_file, &external.path)?;
+        output_file.write_all(&external.created.to_le_bytes())?;
+        output_file.write_all(&external.block_count.to_le_bytes())?;
+    }
+    // Image de base (version 11+) : rang parmi les images référencées plus un, 0 sinon
+    output_file.write_all(&header.base.map_or(0, |base| base as u64 + 1).to_le_bytes())?;
+    
+    // Paramètres de découpage (version 8+)
+    let chunker = &header.chunker;
+    output_file.write_all(&[chunker.strategy as u8])?;
+    for size in [chunker.min_size, chunker.avg_size, chunker.max_size] {
+        output_file.write_all(&size.to_le_bytes())?;
+    }
+    output_file.write_all(&[chunker.normalization])?;
+    output_file.write_all(&header.manifest.compressed_size.to_le_bytes())?;
+    output_file.write_all(&header.manifest.original_size.to_le_bytes())?;
+    
+    // Blocs ajoutés sur place (version 12+)
+    let appended = &header.appended;
+    for field in [appended.offset, appended.count, appended.index.compressed_size, appended.index.original_size] {
+        output_file.write_all(&field.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+/// Relit une image qui vient d'être écrite, après avoir vidé ses pages du cache
+/// quand le système le permet : chaque bloc doit se décompresser vers son hash et
+/// l'index des fichiers rester lisible
+fn verify_written<'a>(path: &Path, written: impl ExactSizeIterator<Item = &'a BlockHash>) -> Result<(), ImageError> {
+    let _span = debug_span!("verify_write", path = %path.display()).entered();
+    let failed = |reason: String| ImageError::WriteVerification { path: path.to_path_buf(), reason };
+    let file = File::open(path).map_err(|e| ImageError::io_at(e, path))?;
+    if let Err(e) = crate::platform::drop_cached(&file) {
+        debug!("Cache de {:?} conservé: {}", path, e);
+    }
+    drop(file);
+    
+    let OpenedImage { mut input_file, header, path_encoding, block_index, .. } =
+        open_image(path, false, false).map_err(|e| failed(e.to_string()))?;
+    if header.block_count != written.len() as u64 {
+        return Err(failed(format!("{} blocs relus, {} écrits", header.block_count, written.len())));
+    }
+    let algorithm = header.hash_algorithm.unwrap_or_default();
+    let mut sources = [BlockSource::open(path, block_index, IoBackend::default())?];
+    let mut blocks = 0;
+    for hash in written {
+        let data = read_block(&mut sources, hash, path).map_err(|e| failed(e.to_string()))?;
+        if algorithm.hash(&data) != *hash {
+            return Err(failed("un bloc ne correspond plus à son hash".to_string()));
+        }
+        blocks += 1;
+    }
+    for entry in index_entries(&mut input_file, &header, path_encoding).map_err(|e| failed(e.to_string()))? {
+        entry.map_err(|e| failed(e.to_string()))?;
+    }
+    info!("Relecture vérifiée: {} blocs", blocks);
+    Ok(())
+}
+
+/// Lecture du header et de l'encodage des chemins
+fn read_header(input_file: &mut impl Read) -> Result<(ImageHeader, u8), ImageError> {
+    let mut version_bytes = [0u8; 4];
+    input_file.read_exact(&mut version_bytes)?;
+    let version = u32::from_le_bytes(version_bytes);
+    let format = ImageFormat::of(version)?;
+    
+    let mut fields = [0u64; 5];
+    let mut buffer = [0u8; 8];
+    for field in &mut fields {
+        input_file.read_exact(&mut buffer)?;
+        *field = u64::from_le_bytes(buffer);
+    }
+    let [created, total_files, total_size, compressed_size, block_count] = fields;
+    
+    // Encodage des chemins (version 3+) ; les versions précédentes stockaient de l'UTF-8
+    let path_encoding = if format.path_encoding() {
+        let mut encoding = [0u8; 1];
+        input_file.read_exact(&mut encoding)?;
+        encoding[0]
+    } else {
+        PATH_ENCODING_UNIX
+    };
+    
+    // Tailles des index compressés (version 5+)
+    let mut sections = [IndexSection::default(); 2];
+    if format.compressed_indexes() {
+        for section in &mut sections {
+            input_file.read_exact(&mut buffer)?;
+            section.compressed_size = u64::from_le_bytes(buffer);
+            input_file.read_exact(&mut buffer)?;
+            section.original_size = u64::from_le_bytes(buffer);
+        }
+    }
+    let [block_index, file_index] = sections;
+    
+    let hash_algorithm = if format.hash_algorithm() {
+        let mut algorithm = [0u8; 1];
+        input_file.read_exact(&mut algorithm)?;
+        Some(HashAlgorithm::from_byte(algorithm[0])?)
+    } else {
+        None
+    };
+    
+    let mut external_images = Vec::new();
+    if format.external_images() {
+        input_file.read_exact(&mut buffer)?;
+        for _ in 0..u64::from_le_bytes(buffer) {
+            let path = read_path(input_file, path_encoding)?;
+            input_file.read_exact(&mut buffer)?;
+            let created = u64::from_le_bytes(buffer);
+            input_file.read_exact(&mut buffer)?;
+            external_images.push(ExternalImage { path, created, block_count: u64::from_le_bytes(buffer) });
+        }
+    }
+    let mut base = None;
+    if format.base_image() {
+        input_file.read_exact(&mut buffer)?;
+        base = match u64::from_le_bytes(buffer) {
+            0 => None,
+            rank if rank <= external_images.len() as u64 => Some(rank as usize - 1),
+            rank => return Err(ImageError::CorruptIndex(format!("Image de base inconnue: {}", rank))),
+        };
+    }
+    
+    let chunker = if format.chunker() {
+        let mut strategy = [0u8; 1];
+        input_file.read_exact(&mut strategy)?;
+        let strategy = match strategy[0] {
+            0 => ChunkStrategy::Fixed,
+            1 => ChunkStrategy::Cdc,
+            other => return Err(ImageError::CorruptIndex(format!("Découpage inconnu: {}", other))),
+        };
+        let mut sizes = [0u32; 3];
+        for size in &mut sizes {
+            input_file.read_exact(&mut buffer[..4])?;
+            *size = u32::from_le_bytes(buffer[..4].try_into().unwrap());
+        }
+        let mut normalization = [0u8; 1];
+        input_file.read_exact(&mut normalization)?;
+        let [min_size, avg_size, max_size] = sizes;
+        ChunkerOptions { strategy, min_size, avg_size, max_size, normalization: normalization[0] }
+    } else {
+        ChunkerOptions::default()
+    };
+    
+    let mut manifest = IndexSection::default();
+    if format.manifest() {
+        input_file.read_exact(&mut buffer)?;
+        manifest.compressed_size = u64::from_le_bytes(buffer);
+        input_file.read_exact(&mut buffer)?;
+        manifest.original_size = u64::from_le_bytes(buffer);
+    }
+    
+    let mut appended = AppendedBlocks::default();
+    if format.appended_blocks() {
+        let mut fields = [0u64; 4];
+        for field in &mut fields {
+            input_file.read_exact(&mut buffer)?;
+            *field = u64::from_le_bytes(buffer);
+        }
+        let [offset, count, compressed_size, original_size] = fields;
+        appended = AppendedBlocks { offset, count, index: IndexSection { compressed_size, original_size } };
+        if count > block_count {
+            return Err(ImageError::CorruptIndex(format!("{} blocs ajoutés sur {}", count, block_count)));
+        }
+    }
+    
+    let header = ImageHeader {
+        version,
+        created,
+        total_files,
+        total_size,
+        compressed_size,
+        block_count,
+        block_index,
+        file_index,
+        hash_algorithm,
+        external_images,
+        base,
+        chunker,
+        manifest,
+        appended,
+    };
+    Ok((header, path_encoding))
+}
+
+/// Lecteur d'une section d'index, décompressée à la volée à partir de la version 5
+fn index_reader<'a, R: BufRead + 'a>(
+    input_file: &'a mut R,
+    format: ImageFormat,
+    section: IndexSection,
+) -> std::io::Result<Box<dyn Read + 'a>> {
+    if !format.compressed_indexes() {
+        return Ok(Box::new(input_file));
+    }
+    Ok(Box::new(zstd::Decoder::with_buffer(input_file.take(section.compressed_size))?))
+}
+
+/// Filtre de Bloom des blocs d'une image, absent avant la version 4
+pub fn read_block_filter(image_path: &Path) -> Result<Option<BloomFilter>, ImageError> {
+    let mut input_file = BufReader::new(
+        File::open(image_path).map_err(|e| ImageError::io_at(e, image_path))?,
+    );
+    let (header, _) = read_header(&mut input_file)?;
+    if !header.format().bloom_filter() {
+        return Ok(None);
+    }
+    
+    let mut buffer = [0u8; 8];
+    input_file.read_exact(&mut buffer)?;
+    match u64::from_le_bytes(buffer) {
+        0 => Ok(None),
+        byte_len => Ok(Some(BloomFilter::read_from(&mut input_file, byte_len)?)),
+    }
+}
+
+/// Image lue jusqu'au début de son index des fichiers
+struct OpenedImage {
+    input_file: BufReader<File>,
+    header: ImageHeader,
+    path_encoding: u8,
+    filter: Option<BloomFilter>,
+    signed_manifest: Option<SignedManifest>,
+    block_index: BlockIndex,
+}
+
+/// Lit le header, le filtre de Bloom si `load_filter`, le manifeste signé si
+/// `load_manifest`, puis l'index des blocs
+fn open_image(image_path: &Path, load_filter: bool, load_manifest: bool) -> Result<OpenedImage, ImageError> {
+    let mut input_file = BufReader::new(
+        File::open(image_path).map_err(|e| ImageError::io_at(e, image_path))?,
+    );
+    
+    let (header, path_encoding) = read_header(&mut input_file)?;
+    let (format, block_count) = (header.format(), header.block_count);
+    let (filter, signed_manifest) = read_optional_sections(&mut input_file, &header, load_filter, load_manifest)?;
+    
+    // Lecture de l'index des blocs (offsets relatifs au début de la section des données),
+    // conservé sur disque pour les très grandes images
+    let mut block_index = BlockIndexBuilder::new(block_count, DISK_INDEX_THRESHOLD);
+    let mut current_offset = 0u64;
+    let index_start = input_file.stream_position()?;
+    
+    let mut index = index_reader(&mut input_file, format, header.block_index)?;
+    for _ in 0..block_count - header.appended.count {
+        let (hash, original_size, compressed_size, codec) = read_block_record(&mut index, format)?;
+        block_index.insert(hash, current_offset, original_size, compressed_size, codec)?;
+        current_offset += compressed_size as u64;
+    }
+    drop(index);
+    
+    let data_start = if format.compressed_indexes() {
+        index_start + header.block_index.compressed_size
+    } else {
+        input_file.stream_position()?
+    };
+    
+    // Sauter la section des données pour atteindre l'index des fichiers, qui suit
+    // l'index des blocs ajoutés sur place s'il y en a
+    input_file.seek(SeekFrom::Start(data_start + current_offset))?;
+    for ((hash, original_size, compressed_size, codec), offset) in read_appended_blocks(&mut input_file, &header)? {
+        let offset = offset.checked_sub(data_start)
+            .ok_or_else(|| ImageError::CorruptIndex(format!("Bloc ajouté avant les données: {}", offset)))?;
+        block_index.insert(hash, offset, original_size, compressed_size, codec)?;
+    }
+    let block_index = block_index.finish(data_start)?;
+    
+    Ok(OpenedImage { input_file, header, path_encoding, filter, signed_manifest, block_index })
+}
+
+/// Lit ou saute le filtre de Bloom et le manifeste signé qui précèdent l'index des blocs
+fn read_optional_sections(
+    input_file: &mut BufReader<File>,
+    header: &ImageHeader,
+    load_filter: bool,
+    load_manifest: bool,
+) -> Result<(Option<BloomFilter>, Option<SignedManifest>), ImageError> {
+    let mut buffer = [0u8; 8];
+    
+    // Le filtre de Bloom ne sert qu'à la création
+    let mut filter = None;
+    if header.format().bloom_filter() {
+        input_file.read_exact(&mut buffer)?;
+        let byte_len = u64::from_le_bytes(buffer);
+        if load_filter && byte_len > 0 {
+            filter = Some(BloomFilter::read_from(&mut *input_file, byte_len)?);
+        } else {
+            input_file.seek_relative(byte_len as i64)?;
+        }
+    }
+    
+    // Manifeste signé (version 9+) : utile uniquement à l'extraction
+    let mut signed_manifest = None;
+    let manifest_size = header.manifest.compressed_size;
+    if manifest_size > 0 {
+        if load_manifest {
+            let mut bytes = Vec::with_capacity(header.manifest.original_size as usize);
+            zstd::Decoder::with_buffer((&mut *input_file).take(manifest_size))?.read_to_end(&mut bytes)?;
+            let mut public_key = [0u8; 32];
+            input_file.read_exact(&mut public_key)?;
+            let mut signature = [0u8; 64];
+            input_file.read_exact(&mut signature)?;
+            signed_manifest = Some(SignedManifest { bytes, public_key, signature });
+        } else {
+            input_file.seek_relative((manifest_size + 32 + 64) as i64)?;
+        }
+    }
+    Ok((filter, signed_manifest))
+}
+
+/// Lit l'index des blocs ajoutés sur place, chacun avec la position de ses données,
+/// et laisse le lecteur au début de l'index des fichiers qui le suit
+fn read_appended_blocks(input_file: &mut BufReader<File>, header: &ImageHeader) -> Result<Vec<(BlockRecord, u64)>, ImageError> {
+    let appended = header.appended;
+    if appended.offset == 0 {
+        return Ok(Vec::new());
+    }
+    input_file.seek(SeekFrom::Start(appended.offset))?;
+    let mut records = Vec::with_capacity(appended.count as usize);
+    let mut index = index_reader(&mut *input_file, header.format(), appended.index)?;
+    let mut buffer = [0u8; 8];
+    for _ in 0..appended.count {
+        let record = read_block_record(&mut index, header.format())?;
+        index.read_exact(&mut buffer)?;
+        records.push((record, u64::from_le_bytes(buffer)));
+    }
+    drop(index);
+    input_file.seek(SeekFrom::Start(appended.offset + appended.index.compressed_size))?;
+    Ok(records)
+}
+
+/// Entrée de l'index des blocs : hash, taille originale, taille compressée, codec
+type BlockRecord = (BlockHash, usize, usize, Codec);
+
+/// Écrit une entrée lue par [`read_block_record`]
+fn write_block_record(index: &mut impl Write, (hash, original_size, compressed_size, codec): &BlockRecord) -> std::io::Result<()> {
+    index.write_all(&hash.0)?; // 32 bytes hash
+    index.write_all(&(*original_size as u64).to_le_bytes())?;
+    index.write_all(&(*compressed_size as u64).to_le_bytes())?;
+    codec.write_to(index)
+}
+
+/// Entrée de l'index des blocs : hash, taille originale, taille compressée et
+/// codec (version 10+, zstd auparavant)
+fn read_block_record(index: &mut impl Read, format: ImageFormat) -> std::io::Result<BlockRecord> {
+    let mut hash_bytes = [0u8; 32];
+    index.read_exact(&mut hash_bytes)?;
+    let mut buffer = [0u8; 8];
+    index.read_exact(&mut buffer)?;
+    let original_size = u64::from_le_bytes(buffer) as usize;
+    index.read_exact(&mut buffer)?;
+    let compressed_size = u64::from_le_bytes(buffer) as usize;
+    let codec = if format.block_codecs() { Codec::read_from(index)? } else { Codec::zstd(0) };
+    Ok((BlockHash(hash_bytes), original_size, compressed_size, codec))
+}
+
+/// Bloc stocké dans un fichier image
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredBlock {
+    pub hash: BlockHash,
+    /// Position de ses données compressées dans le fichier
+    pub offset: u64,
+    pub compressed_size: u64,
+    /// Codec avec lequel ses données se décodent
+    pub codec: Codec,
+}
+
+/// Blocs stockés dans une image, dans l'ordre du fichier, avec l'algorithme
+/// qui les a hachés (inconnu pour les images antérieures à la version 6)
+pub fn stored_blocks(image_path: &Path) -> Result<(Option<HashAlgorithm>, Vec<StoredBlock>), ImageError> {
+    let mut input_file = BufReader::new(
+        File::open(image_path).map_err(|e| ImageError::io_at(e, image_path))?,
+    );
+    let (header, _) = read_header(&mut input_file)?;
+    read_optional_sections(&mut input_file, &header, false, false)?;
+    let index_start = input_file.stream_position()?;
+    let mut records = Vec::with_capacity(header.block_count as usize);
+    let mut index = index_reader(&mut input_file, header.format(), header.block_index)?;
+    for _ in 0..header.block_count - header.appended.count {
+        records.push(read_block_record(&mut index, header.format())?);
+    }
+    drop(index);
+    let mut offset = if header.format().compressed_indexes() {
+        index_start + header.block_index.compressed_size
+    } else {
+        input_file.stream_position()?
+    };
+    let mut blocks: Vec<StoredBlock> = records
+        .into_iter()
+        .map(|(hash, _, compressed_size, codec)| {
+            let block = StoredBlock { hash, offset, compressed_size: compressed_size as u64, codec };
+            offset += compressed_size as u64;
+            block
+        })
+        .collect();
+    blocks.extend(read_appended_blocks(&mut input_file, &header)?.into_iter().map(|((hash, _, compressed_size, codec), offset)| {
+        StoredBlock { hash, offset, compressed_size: compressed_size as u64, codec }
+    }));
+    Ok((header.hash_algorithm, blocks))
+}
+
+/// Image existante dont les blocs sont référencés plutôt que stockés (`dedup_against`)
+struct BaseImage {
+    reference: ExternalImage,
+    filter: Option<BloomFilter>,
+    block_index: BlockIndex,
+}
+
+impl BaseImage {
+    fn open(path: &Path, hash_algorithm: HashAlgorithm) -> Result<Self, ImageError> {
+        let image = open_image(path, true, false)?;
+        if image.header.hash_algorithm != Some(hash_algorithm) {
+            return Err(ImageError::ExternalImage {
+                path: path.to_path_buf(),
+                reason: format!("blocs hachés avec {:?}, {:?} attendu", image.header.hash_algorithm, hash_algorithm),
+            });
+        }
+        let reference = ExternalImage {
+            path: path.canonicalize().map_err(|e| ImageError::io_at(e, path))?,
+            created: image.header.created,
+            block_count: image.header.block_count,
+        };
+        Ok(Self { reference, filter: image.filter, block_index: image.block_index })
+    }
+    
+    /// Le filtre de Bloom évite la recherche dans l'index pour les blocs nouveaux
+    fn contains(&self, hash: &BlockHash) -> bool {
+        self.filter.as_ref().is_none_or(|filter| filter.may_contain(hash)) && self.block_index.contains(hash)
+    }
+}
+
+/// Image dont l'extraction lit des blocs : l'image elle-même puis celles qu'elle référence
+struct BlockSource {
+    block_index: BlockIndex,
+    reader: BlockReader,
+}
+
+impl BlockSource {
+    fn open(image_path: &Path, block_index: BlockIndex, io_backend: IoBackend) -> Result<Self, ImageError> {
+        let reader = BlockReader::open(image_path, io_backend).map_err(|e| ImageError::io_at(e, image_path))?;
+        Ok(Self { block_index, reader })
+    }
+    
+    /// Image référencée, à son chemin d'origine ou à côté de `image_path` si
+    /// la famille d'images a été déplacée
+    fn open_external(image_path: &Path, external: &ExternalImage, io_backend: IoBackend) -> Result<Self, ImageError> {
+        let (path, image) = locate_external(image_path, external)?;
+        Self::open(&path, image.block_index, io_backend)
+    }
+}
+
+/// Ouvre une image référencée, à son chemin d'origine ou à côté de `image_path`,
+/// en vérifiant qu'elle n'a pas changé depuis
+fn locate_external(image_path: &Path, external: &ExternalImage) -> Result<(PathBuf, OpenedImage), ImageError> {
+    let sibling = external.path.file_name().map(|name| image_path.with_file_name(name));
+    let Some(path) = std::iter::once(external.path.clone()).chain(sibling).find(|path| path.is_file()) else {
+        return Err(ImageError::ExternalImage { path: external.path.clone(), reason: "introuvable".to_string() });
+    };
+    let image = open_image(&path, false, false)?;
+    // Une image étendue sur place garde sa date de création et ne fait que gagner des blocs
+    if image.header.created != external.created || image.header.block_count < external.block_count {
+        return Err(ImageError::ExternalImage { path, reason: "l'image a changé depuis sa référence".to_string() });
+    }
+    Ok((path, image))
+}
+
+/// Chaîne d'images différentielles aboutissant à `image_path`, de l'image complète à celle-ci
+fn image_chain(image_path: &Path) -> Result<Vec<PathBuf>, ImageError> {
+    let mut chain = vec![image_path.to_path_buf()];
+    let mut image = open_image(image_path, false, false)?;
+    while let Some(base) = image.header.base {
+        let current = chain.last().expect("chain starts with the image");
+        let (path, base_image) = locate_external(current, &image.header.external_images[base])?;
+        if chain.contains(&path) {
+            return Err(ImageError::ExternalImage { path, reason: "chaîne d'images circulaire".to_string() });
+        }
+        chain.push(path);
+        image = base_image;
+    }
+    chain.reverse();
+    Ok(chain)
+}
+
+/// Entrées d'une image, puis les chemins qu'elle supprime de sa base
+fn read_layer(image_path: &Path) -> Result<(Vec<FileEntry>, Vec<PathBuf>), ImageError> {
+    let OpenedImage { mut input_file, header, path_encoding, .. } = open_image(image_path, false, false)?;
+    read_layer_from(&mut input_file, &header, path_encoding)
+}
+
+fn read_layer_from(
+    input_file: &mut BufReader<File>,
+    header: &ImageHeader,
+    path_encoding: u8,
+) -> Result<(Vec<FileEntry>, Vec<PathBuf>), ImageError> {
+    let mut index = index_reader(input_file, header.format(), header.file_index)?;
+    let mut buffer = [0u8; 8];
+    index.read_exact(&mut buffer)?;
+    let entries = (0..u64::from_le_bytes(buffer))
+        .map(|_| read_file_entry(&mut index, header.format(), path_encoding))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut deletions = Vec::new();
+    if header.format().base_image() {
+        index.read_exact(&mut buffer)?;
+        for _ in 0..u64::from_le_bytes(buffer) {
+            deletions.push(read_path(&mut index, path_encoding)?);
+        }
+    }
+    Ok((entries, deletions))
+}
+
+/// Entrées lues au fil de l'index ou d'une vue fusionnée
+type EntryIter<'a> = Box<dyn Iterator<Item = Result<FileEntry, ImageError>> + 'a>;
+
+/// Entrées d'une image positionnée au début de son index des fichiers, dans l'ordre de
+/// l'index ; pour une image différentielle, vue fusionnée de sa chaîne, lue d'un coup
+fn image_entries<'a>(
+    image_path: &Path,
+    input_file: &'a mut BufReader<File>,
+    header: &ImageHeader,
+    path_encoding: u8,
+) -> Result<EntryIter<'a>, ImageError> {
+    if header.base.is_some() {
+        let chain = image_chain(image_path)?;
+        let mut layers = chain[..chain.len() - 1].iter().map(|path| read_layer(path)).collect::<Result<Vec<_>, _>>()?;
+        layers.push(read_layer_from(input_file, header, path_encoding)?);
+        return Ok(Box::new(merge_layers(layers).into_iter().map(Ok)));
+    }
+    index_entries(input_file, header, path_encoding)
+}
+
+/// Entrées lues une à une depuis l'index des fichiers
+fn index_entries<'a>(
+    input_file: &'a mut BufReader<File>,
+    header: &ImageHeader,
+    path_encoding: u8,
+) -> Result<EntryIter<'a>, ImageError> {
+    let mut index = index_reader(input_file, header.format(), header.file_index)?;
+    let mut buffer = [0u8; 8];
+    index.read_exact(&mut buffer)?;
+    let format = header.format();
+    Ok(Box::new((0..u64::from_le_bytes(buffer)).map(move |_| read_file_entry(&mut index, format, path_encoding))))
+}
+
+/// Vue fusionnée de couches appliquées dans l'ordre : les entrées d'une couche remplacent
+/// celles de même chemin à leur place, les nouvelles s'ajoutent à la fin, ses suppressions
+/// retirent les entrées des couches précédentes
+fn merge_layers(layers: impl IntoIterator<Item = (Vec<FileEntry>, Vec<PathBuf>)>) -> Vec<FileEntry> {
+    let mut merged: Vec<Option<FileEntry>> = Vec::new();
+    let mut positions = HashMap::new();
+    for (entries, deletions) in layers {
+        for path in deletions {
+            if let Some(position) = positions.remove(&path) {
+                merged[position] = None;
+            }
+        }
+        for entry in entries {
+            match positions.entry(entry.path.clone()) {
+                Entry::Occupied(position) => merged[*position.get()] = Some(entry),
+                Entry::Vacant(position) => {
+                    position.insert(merged.len());
+                    merged.push(Some(entry));
+                }
+            }
+        }
+    }
+    merged.into_iter().flatten().collect()
+}
+
+/// Même chemin, type, taille, date, contenu, cible de lien, descripteur de sécurité,
+/// numéros de périphérique, propriétaire, permissions et date d'accès
+fn same_entry(a: &FileEntry, b: &FileEntry) -> bool {
+    a.path == b.path && a.kind == b.kind && a.size == b.size && a.modified == b.modified && a.blocks == b.blocks
+        && a.link_target == b.link_target && a.security == b.security && a.device_numbers == b.device_numbers
+        && a.attributes == b.attributes && a.accessed == b.accessed
+}
+
+/// Lecture d'une entrée de l'index des fichiers
+fn read_file_entry(index: &mut impl Read, format: ImageFormat, path_encoding: u8) -> Result<FileEntry, ImageError> {
+    let mut buffer = [0u8; 8];
+    
+    // Lecture du chemin
+    let relative_path = read_path(index, path_encoding)?;
+    
+    index.read_exact(&mut buffer)?;
+    let size = u64::from_le_bytes(buffer);
+    
+    index.read_exact(&mut buffer)?;
+    let modified = u64::from_le_bytes(buffer);
+    
+    let mut kind_byte = [0u8; 1];
+    index.read_exact(&mut kind_byte)?;
+    let kind = kind_from_byte(kind_byte[0])?;
+    if kind == EntryKind::Stream && !format.data_streams() {
+        return Err(ImageError::CorruptIndex(format!("Type d'entrée inconnu: {}", kind_byte[0])));
+    }
+    
+    // Lecture de la liste des blocs
+    index.read_exact(&mut buffer)?;
+    let entry_block_count = u64::from_le_bytes(buffer);
+    let mut blocks = Vec::with_capacity(entry_block_count as usize);
+    for _ in 0..entry_block_count {
+        let mut hash_bytes = [0u8; 32];
+        index.read_exact(&mut hash_bytes)?;
+        blocks.push(BlockHash(hash_bytes));
+    }
+    
+    let link_target = if kind == EntryKind::Symlink {
+        Some(read_path(index, path_encoding)?)
+    } else {
+        None
+    };
+    
+    let mut device_numbers = None;
+    if matches!(kind, EntryKind::CharDevice | EntryKind::BlockDevice) && format.device_numbers() {
+        let mut numbers = [0u8; 4];
+        index.read_exact(&mut numbers)?;
+        let major = u32::from_le_bytes(numbers);
+        index.read_exact(&mut numbers)?;
+        device_numbers = Some((major, u32::from_le_bytes(numbers)));
+    }
+    
+    let mut security = None;
+    if format.security_descriptors() {
+        let mut length = [0u8; 4];
+        index.read_exact(&mut length)?;
+        let mut sddl = vec![0u8; u32::from_le_bytes(length) as usize];
+        index.read_exact(&mut sddl)?;
+        if !sddl.is_empty() {
+            security = Some(String::from_utf8(sddl)
+                .map_err(|_| ImageError::CorruptIndex(format!("Descripteur de sécurité invalide pour {:?}", relative_path)))?);
+        }
+    }
+    
+    let mut attributes = None;
+    if format.unix_attributes() {
+        let mut present = [0u8; 1];
+        index.read_exact(&mut present)?;
+        if present[0] != 0 {
+            let mut fields = [0u8; 12];
+            index.read_exact(&mut fields)?;
+            let field = |i: usize| u32::from_le_bytes(fields[i * 4..i * 4 + 4].try_into().unwrap());
+            attributes = Some(UnixAttributes { uid: field(0), gid: field(1), mode: field(2) });
+        }
+    }
+    
+    let mut accessed = None;
+    if format.access_times() {
+        index.read_exact(&mut buffer)?;
+        accessed = Some(u64::from_le_bytes(buffer)).filter(|&time| time != 0);
+    }
+    
+    Ok(FileEntry { path: relative_path, size, modified, kind, blocks, link_target, security, device_numbers, attributes, accessed })
+}
+
+/// Hash BLAKE3 attendu du contenu de chaque fichier, tiré des manifestes signés
+type ContentHashes = HashMap<PathBuf, [u8; 32]>;
+
+/// Vérifie le manifeste signé et sa concordance avec l'index des fichiers, avant
+/// toute écriture. Renvoie le hash attendu du contenu de chaque fichier.
+fn verify_manifest(
+    options: &ExtractOptions,
+    image_path: &Path,
+    image: &mut OpenedImage,
+    report: &mut Report,
+) -> Result<ContentHashes, ImageError> {
+    let manifest = match (&image.signed_manifest, &options.trusted_key) {
+        (None, None) => return Ok(HashMap::new()),
+        (None, Some(_)) => Err("image non signée".to_string()),
+        (Some(signed), trusted_key) => signed.verify(trusted_key.as_ref(), image.path_encoding),
+    };
+    let manifest = manifest.and_then(|manifest| {
+        let file_index_start = image.input_file.stream_position().map_err(|e| e.to_string())?;
+        let result = match_file_index(image, &manifest).map(|()| manifest);
+        image.input_file.seek(SeekFrom::Start(file_index_start)).map_err(|e| e.to_string())?;
+        result
+    });
+    match manifest {
+        Ok(manifest) => {
+            info!("Manifeste vérifié: {} entrées", manifest.entries.len());
+            Ok(manifest.entries.into_iter()
+                .filter(|entry| kind_from_byte(entry.kind).is_ok_and(|kind| kind.has_content()))
+                .map(|entry| (entry.path, entry.content_hash))
+                .collect())
+        }
+        Err(reason) if options.force => {
+            report.warn(image_path, WarningKind::Unverified, format!("Manifeste non vérifié ({}), extraction forcée", reason));
+            Ok(HashMap::new())
+        }
+        Err(reason) => Err(ImageError::ManifestVerification(reason)),
+    }
+}
+
+/// Vérifie le manifeste de l'image et, pour une image différentielle, ceux de sa chaîne
+/// de base. Renvoie le hash attendu du contenu de chaque fichier, pris dans la couche qui
+/// fournit l'entrée, et la vue fusionnée de la chaîne pour une image différentielle.
+fn verify_layers(
+    options: &ExtractOptions,
+    image: &mut OpenedImage,
+    report: &mut Report,
+) -> Result<(ContentHashes, Option<Vec<FileEntry>>), ImageError> {
+    if image.header.base.is_none() && options.overlays.is_empty() {
+        return Ok((verify_manifest(options, &options.image_path, image, report)?, None));
+    }
+    let chain = match image.header.base {
+        Some(_) => image_chain(&options.image_path)?,
+        None => vec![options.image_path.clone()],
+    };
+    let mut layers = Vec::new();
+    for path in &chain[..chain.len() - 1] {
+        let mut base = open_image(path, false, true)?;
+        let hashes = verify_manifest(options, path, &mut base, report)?;
+        layers.push((hashes, read_layer_from(&mut base.input_file, &base.header, base.path_encoding)?));
+    }
+    let hashes = verify_manifest(options, &options.image_path, image, report)?;
+    // L'index des fichiers reste lisible ensuite, comme pour une image complète
+    let file_index_start = image.input_file.stream_position()?;
+    layers.push((hashes, read_layer_from(&mut image.input_file, &image.header, image.path_encoding)?));
+    image.input_file.seek(SeekFrom::Start(file_index_start))?;
+    
+    // Chaque image superposée n'apporte que ses propres changements : une image
+    // différentielle doit suivre directement sa base dans la liste
+    let mut previous = options.image_path.clone();
+    for path in &options.overlays {
+        let mut overlay = open_image(path, false, true)?;
+        if let Some(base) = overlay.header.base {
+            let (base_path, _) = locate_external(path, &overlay.header.external_images[base])?;
+            if !same_file(&base_path, &previous) {
+                let reason = format!("image différentielle de {:?}, qui ne la précède pas", base_path);
+                return Err(ImageError::ExternalImage { path: path.clone(), reason });
+            }
+        }
+        let hashes = verify_manifest(options, path, &mut overlay, report)?;
+        layers.push((hashes, read_layer_from(&mut overlay.input_file, &overlay.header, overlay.path_encoding)?));
+        previous = path.clone();
+    }
+
+    let mut content_hashes = HashMap::new();
+    for (hashes, (entries, deletions)) in &mut layers {
+        for path in deletions.iter().chain(entries.iter().map(|entry| &entry.path)) {
+            content_hashes.remove(path);
+        }
+        content_hashes.extend(hashes.drain());
+    }
+    Ok((content_hashes, Some(merge_layers(layers.into_iter().map(|(_, layer)| layer)))))
+}
+
+/// Deux chemins désignant le même fichier
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+/// Sources des blocs d'une extraction : l'image, les images qu'elle référence, puis
+/// chaque image superposée suivie de ses références
+fn extraction_sources(options: &ExtractOptions, header: &ImageHeader, block_index: BlockIndex) -> Result<Vec<BlockSource>, ImageError> {
+    let mut sources = vec![BlockSource::open(&options.image_path, block_index, options.io_backend)?];
+    for external in &header.external_images {
+        sources.push(BlockSource::open_external(&options.image_path, external, options.io_backend)?);
+    }
+    for path in &options.overlays {
+        let OpenedImage { header, block_index, .. } = open_image(path, false, false)?;
+        sources.push(BlockSource::open(path, block_index, options.io_backend)?);
+        for external in &header.external_images {
+            sources.push(BlockSource::open_external(path, external, options.io_backend)?);
+        }
+    }
+    Ok(sources)
+}
+
+/// Compare chaque entrée de l'index des fichiers au manifeste signé
+fn match_file_index(image: &mut OpenedImage, manifest: &Manifest) -> Result<(), String> {
+    let describe = |e: ImageError| format!("index des fichiers illisible: {}", e);
+    let mut index = index_reader(&mut image.input_file, image.header.format(), image.header.file_index)
+        .map_err(|e| describe(e.into()))?;
+    let mut buffer = [0u8; 8];
+    index.read_exact(&mut buffer).map_err(|e| describe(e.into()))?;
+    if u64::from_le_bytes(buffer) != manifest.entries.len() as u64 {
+        return Err("le nombre d'entrées ne correspond pas".to_string());
+    }
+    for signed in &manifest.entries {
+        let entry = read_file_entry(&mut index, image.header.format(), image.path_encoding).map_err(describe)?;
+        let link_matches = entry.link_target.is_none() || content_hash(&entry, None) == signed.content_hash;
+        if entry.path != signed.path || kind_to_byte(entry.kind) != signed.kind || entry.size != signed.size || !link_matches {
+            return Err(format!("l'entrée {:?} ne correspond pas", entry.path));
+        }
+    }
+    Ok(())
+}
+
+pub fn extract_image(options: &ExtractOptions) -> Result<Report, ImageError> {
+    let _span = info_span!("extract_image", image = %options.image_path.display()).entered();
+    info!("Extraction de l'image {:?}", options.image_path);
+    
+    let mut image = open_image(&options.image_path, false, true)?;
+    let mut report = Report::default();
+    let (content_hashes, merged) = verify_layers(options, &mut image, &mut report)?;
+    let OpenedImage { mut input_file, header, path_encoding, block_index, .. } = image;
+    info!("Version: {}, {} fichiers, {} blocs", header.version, header.total_files, header.block_count);
+    
+    let sources = extraction_sources(options, &header, block_index)?;
+    
+    // Créer le dossier de sortie (en simulation, une destination absente ne contient aucun lien)
+    let (canonical_output, mapper) = if options.dry_run {
+        let mapper = PathMapper::for_dry_run(options.normalize, options.case_collision);
+        (options.output_path.canonicalize().ok(), mapper)
+    } else {
+        fs::create_dir_all(&options.output_path).map_err(|e| ImageError::io_at(e, &options.output_path))?;
+        let mapper = PathMapper::for_destination(&options.output_path, options.normalize, options.case_collision)?;
+        (Some(options.output_path.canonicalize()?), mapper)
+    };
+    
+    // Lecture des métadonnées de fichiers, ou vue fusionnée des couches
+    let entries = match merged {
+        Some(merged) => Box::new(merged.into_iter().map(Ok)),
+        None => index_entries(&mut input_file, &header, path_encoding)?,
+    };
+    let (entries, priority_count) = prioritize(under_prefix(entries, options), &options.first)?;
+    
+    let mut extractor = Extractor {
+        options,
+        canonical_output,
+        sources,
+        mapper,
+        extracted_contents: HashMap::new(),
+        reflink_supported: true,
+        read_cache: HashMap::new(),
+        content_hashes,
+        security: Vec::new(),
+        streams: Vec::new(),
+        directories: Vec::new(),
+        privileged: platform::is_privileged(),
+        owners_skipped: 0,
+        devices_skipped: 0,
+        report,
+    };
+    
+    // Les entrées sont extraites par lots dont les blocs sont lus ensemble
+    let mut batch = Vec::new();
+    let mut batch_bytes = 0usize;
+    let mut file_count = 0u64;
+    for entry in entries {
+        let entry = entry?;
+        file_count += 1;
+        batch_bytes += entry.blocks.iter()
+            .filter_map(|hash| extractor.locate(hash))
+            .map(|(_, (_, _, compressed_size, _))| compressed_size)
+            .sum::<usize>();
+        batch.push(entry);
+        
+        if batch_bytes >= BATCH_BYTES || batch.len() >= BATCH_FILES {
+            extractor.extract_batch(&batch)?;
+            batch.clear();
+            batch_bytes = 0;
+        }
+        
+        // Les entrées prioritaires sont toutes sur disque avant d'extraire les suivantes
+        if priority_count > 0 && file_count == priority_count {
+            extractor.extract_batch(&batch)?;
+            batch.clear();
+            batch_bytes = 0;
+            info!("{} entrées prioritaires extraites", priority_count);
+        }
+        
+        if file_count.is_multiple_of(100) {
+            info!("Extrait {} fichiers", file_count);
+        }
+    }
+    extractor.extract_batch(&batch)?;
+    extractor.extract_streams()?;
+    extractor.apply_directories()?;
+    extractor.apply_security();
+    extractor.report_unprivileged();
+    
+    info!("Extraction terminée: {} fichiers", file_count);
+    Ok(extractor.report)
+}
+
+/// Écrit le SHA-256 du contenu de chaque fichier de l'image au format de `sha256sum`,
+/// vérifiable avec `sha256sum -c` depuis l'arborescence extraite. Renvoie le nombre de fichiers.
+pub fn write_checksums(image_path: &Path, output: &mut impl Write) -> Result<u64, ImageError> {
+    let OpenedImage { mut input_file, header, path_encoding, block_index, .. } = open_image(image_path, false, false)?;
+    let mut sources = open_sources(image_path, &header, block_index)?;
+    
+    let mut index = index_reader(&mut input_file, header.format(), header.file_index)?;
+    let mut buffer = [0u8; 8];
+    index.read_exact(&mut buffer)?;
+    let mut files = 0;
+    for _ in 0..u64::from_le_bytes(buffer) {
+        let entry = read_file_entry(&mut index, header.format(), path_encoding)?;
+        if entry.kind != EntryKind::File {
+            continue;
+        }
+        let mut hasher = Sha256::new();
+        for hash in &entry.blocks {
+            hasher.update(read_block(&mut sources, hash, &entry.path)?);
+        }
+        writeln!(output, "{}", checksum_line(&hasher.finalize(), &entry.path))?;
+        files += 1;
+    }
+    Ok(files)
+}
+
+/// Écrit le contenu d'un fichier de l'image sans rien extraire d'autre.
+/// Renvoie le nombre d'octets écrits.
+pub fn read_image_entry(image_path: &Path, entry_path: &Path, output: &mut impl Write) -> Result<u64, ImageError> {
+    let OpenedImage { mut input_file, header, path_encoding, block_index, .. } = open_image(image_path, false, false)?;
+    let mut sources = open_sources(image_path, &header, block_index)?;
+    
+    let mut index = index_reader(&mut input_file, header.format(), header.file_index)?;
+    let mut buffer = [0u8; 8];
+    index.read_exact(&mut buffer)?;
+    for _ in 0..u64::from_le_bytes(buffer) {
+        let entry = read_file_entry(&mut index, header.format(), path_encoding)?;
+        if entry.kind != EntryKind::File || entry.path != entry_path {
+            continue;
+        }
+        let mut written = 0;
+        for hash in &entry.blocks {
+            let data = read_block(&mut sources, hash, &entry.path)?;
+            output.write_all(&data)?;
+            written += data.len() as u64;
+        }
+        return Ok(written);
+    }
+    Err(ImageError::EntryNotFound { path: entry_path.to_path_buf() })
+}
+
+/// Parcourt les entrées de l'image dans l'ordre de l'index, racine comprise, avec
+/// le contenu de chacune (vide sauf pour les fichiers), décompressé à la lecture
+pub fn for_each_entry(
+    image_path: &Path,
+    visit: impl FnMut(&FileEntry, &mut dyn Read) -> Result<(), ImageError>,
+) -> Result<(), ImageError> {
+    let OpenedImage { mut input_file, header, path_encoding, block_index, .. } = open_image(image_path, false, false)?;
+    let mut sources = open_sources(image_path, &header, block_index)?;
+    let entries = image_entries(image_path, &mut input_file, &header, path_encoding)?;
+    visit_entries(&mut sources, entries, visit)
+}
+
+fn visit_entries(
+    sources: &mut [BlockSource],
+    entries: impl Iterator<Item = Result<FileEntry, ImageError>>,
+    mut visit: impl FnMut(&FileEntry, &mut dyn Read) -> Result<(), ImageError>,
+) -> Result<(), ImageError> {
+    for entry in entries {
+        let entry = entry?;
+        let blocks = if entry.kind.has_content() { &entry.blocks[..] } else { &[] };
+        let mut content = EntryContent { sources, path: &entry.path, blocks, block: std::io::Cursor::new(Vec::new()) };
+        visit(&entry, &mut content)?;
+    }
+    Ok(())
+}
+
+/// Écrit le contenu de l'image sous forme d'archive tar, sans rien écrire sur le disque.
+/// Propriétaire et permissions sont ceux de l'image (version 16+) ; à défaut 0755 pour
+/// les dossiers, 0644 pour le reste.
+/// Les sockets et flux de données alternatifs sont ignorés. Renvoie le nombre d'entrées écrites.
+pub fn write_tar(image_path: &Path, output: impl Write) -> Result<u64, ImageError> {
+    let OpenedImage { mut input_file, header, path_encoding, block_index, .. } = open_image(image_path, false, false)?;
+    let mut sources = open_sources(image_path, &header, block_index)?;
+    let entries = image_entries(image_path, &mut input_file, &header, path_encoding)?;
+    append_tar_entries(&mut sources, entries, &HashMap::new(), 0, output)
+}
+
+/// Extrait l'image sous forme d'archive tar écrite dans `output` (typiquement la
+/// sortie standard, vers `tar -x` sur une autre machine), sans toucher au disque local.
+/// Le manifeste signé est vérifié avant d'écrire quoi que ce soit ; un fichier dont le
+/// contenu ne correspond pas interrompt le flux, que `tar` signale alors comme tronqué.
+pub fn extract_image_tar(options: &ExtractOptions, output: impl Write) -> Result<Report, ImageError> {
+    let _span = info_span!("extract_image_tar", image = %options.image_path.display()).entered();
+    let mut image = open_image(&options.image_path, false, true)?;
+    let mut report = Report::default();
+    let (content_hashes, merged) = verify_layers(options, &mut image, &mut report)?;
+    let OpenedImage { mut input_file, header, path_encoding, block_index, .. } = image;
+    let mut sources = extraction_sources(options, &header, block_index)?;
+    let entries = match merged {
+        Some(merged) => Box::new(merged.into_iter().map(Ok)),
+        None => index_entries(&mut input_file, &header, path_encoding)?,
+    };
+    let (entries, _) = prioritize(under_prefix(entries, options), &options.first)?;
+    let entries = append_tar_entries(&mut sources, entries, &content_hashes, options.strip_components, output)?;
+    info!("Flux tar terminé: {} entrées", entries);
+    Ok(report)
+}
+
+/// Entrées situées sous `options.prefix`, le préfixe compris ; toutes sans préfixe
+fn under_prefix<'a>(entries: EntryIter<'a>, options: &ExtractOptions) -> EntryIter<'a> {
+    match &options.prefix {
+        Some(prefix) => {
+            let prefix: PathBuf = prefix.components().filter(|component| *component != std::path::Component::CurDir).collect();
+            Box::new(entries.filter(move |entry| entry.as_ref().map_or(true, |entry| entry.path.starts_with(&prefix))))
+        }
+        None => entries,
+    }
+}
+
+/// Chemin de sortie d'une entrée sans ses `count` premiers composants, comme
+/// `tar --strip-components` ; vide s'il n'en reste aucun
+fn strip_components(path: &Path, count: usize) -> PathBuf {
+    path.components().skip(count).collect()
+}
+
+/// Place en tête, dans l'ordre de l'index, les entrées correspondant à l'un des
+/// motifs de `first` ; renvoie aussi leur nombre. Sans motif, les entrées restent
+/// lues au fil de l'index.
+fn prioritize<'a>(
+    entries: EntryIter<'a>,
+    first: &[glob::Pattern],
+) -> Result<(EntryIter<'a>, u64), ImageError> {
+    if first.is_empty() {
+        return Ok((entries, 0));
+    }
+    let (priority, rest): (Vec<_>, Vec<_>) = entries.collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .partition(|entry| first.iter().any(|pattern| matches_entry(pattern, &entry.path)));
+    debug!(priority = priority.len(), rest = rest.len(), "Entrées prioritaires");
+    let count = priority.len() as u64;
+    Ok((Box::new(priority.into_iter().chain(rest).map(Ok)), count))
+}
+
+/// Écrit les entrées de l'image dans une archive tar sans leurs `strip` premiers
+/// composants, en vérifiant le contenu des fichiers présents dans `content_hashes`
+fn append_tar_entries(
+    sources: &mut [BlockSource],
+    entries: impl Iterator<Item = Result<FileEntry, ImageError>>,
+    content_hashes: &ContentHashes,
+    strip: usize,
+    output: impl Write,
+) -> Result<u64, ImageError> {
+    let mut builder = tar::Builder::new(output);
+    let mut written = 0;
+    visit_entries(sources, entries, |entry, content| {
+        // La racine de l'image est stockée avec un chemin vide, comme les entrées
+        // dont il ne reste aucun composant
+        let path = strip_components(&entry.path, strip);
+        if path.as_os_str().is_empty() {
+            return Ok(());
+        }
+        let mut tar_header = tar::Header::new_gnu();
+        tar_header.set_mtime(entry.modified);
+        tar_header.set_size(0);
+        let (entry_type, mode) = match entry.kind {
+            EntryKind::File => (tar::EntryType::Regular, 0o644),
+            EntryKind::Directory => (tar::EntryType::Directory, 0o755),
+            EntryKind::Symlink => (tar::EntryType::Symlink, 0o777),
+            EntryKind::Fifo => (tar::EntryType::Fifo, 0o644),
+            EntryKind::CharDevice => (tar::EntryType::Char, 0o644),
+            EntryKind::BlockDevice => (tar::EntryType::Block, 0o644),
+            EntryKind::Socket | EntryKind::Stream => {
+                info!("Entrée ignorée dans le tar: {:?}", entry.path);
+                return Ok(());
+            }
+        };
+        tar_header.set_entry_type(entry_type);
+        tar_header.set_mode(mode);
+        if let Some(attributes) = entry.attributes {
+            tar_header.set_mode(attributes.mode);
+            tar_header.set_uid(attributes.uid.into());
+            tar_header.set_gid(attributes.gid.into());
+        }
+        if let Some((major, minor)) = entry.device_numbers {
+            tar_header.set_device_major(major)?;
+            tar_header.set_device_minor(minor)?;
+        }
+        match entry.kind {
+            EntryKind::File => {
+                tar_header.set_size(entry.size);
+                let mut content = HashingReader { inner: content, hasher: blake3::Hasher::new() };
+                builder.append_data(&mut tar_header, &path, &mut content)?;
+                if content_hashes.get(&entry.path).is_some_and(|expected| content.hasher.finalize().as_bytes() != expected) {
+                    return Err(ImageError::ChecksumMismatch { path: entry.path.clone() });
+                }
+            }
+            EntryKind::Symlink => {
+                let target = entry.link_target.as_deref().unwrap_or(Path::new(""));
+                builder.append_link(&mut tar_header, &path, target)?;
+            }
+            _ => builder.append_data(&mut tar_header, &path, content)?,
+        }
+        written += 1;
+        Ok(())
+    })?;
+    builder.finish()?;
+    Ok(written)
+}
+
+/// Lecteur calculant le BLAKE3 de ce qu'il lit
+struct HashingReader<R> {
+    inner: R,
+    hasher: blake3::Hasher,
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+/// Contenu d'un fichier, décompressé bloc par bloc à la lecture
+struct EntryContent<'a> {
+    sources: &'a mut [BlockSource],
+    path: &'a Path,
+    /// Blocs restant à décompresser
+    blocks: &'a [BlockHash],
+    block: std::io::Cursor<Vec<u8>>,
+}
+
+impl Read for EntryContent<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            let read = self.block.read(buf)?;
+            if read > 0 || buf.is_empty() {
+                return Ok(read);
+            }
+            let Some((hash, rest)) = self.blocks.split_first() else {
+                return Ok(0);
+            };
+            self.blocks = rest;
+            let data = read_block(self.sources, hash, self.path).map_err(|e| match e {
+                ImageError::Io(e) => e,
+                e => std::io::Error::new(std::io::ErrorKind::InvalidData, e),
+            })?;
+            self.block = std::io::Cursor::new(data);
+        }
+    }
+}
+
+/// Accès aléatoire au contenu d'un fichier de l'image, sans l'extraire
+pub struct ImageFile {
+    sources: Vec<BlockSource>,
+    path: PathBuf,
+    blocks: Vec<BlockHash>,
+    /// Position de chaque bloc dans le fichier
+    starts: Vec<u64>,
+    size: u64,
+    /// Dernier bloc décompressé, les lectures étant souvent séquentielles
+    cached: Option<(usize, Vec<u8>)>,
+}
+
+impl ImageFile {
+    /// Sans `entry_path`, l'image doit contenir un seul fichier (image d'un disque)
+    pub fn open(image_path: &Path, entry_path: Option<&Path>) -> Result<Self, ImageError> {
+        let OpenedImage { mut input_file, header, path_encoding, block_index, .. } = open_image(image_path, false, false)?;
+        let sources = open_sources(image_path, &header, block_index)?;
+        
+        let mut index = index_reader(&mut input_file, header.format(), header.file_index)?;
+        let mut buffer = [0u8; 8];
+        index.read_exact(&mut buffer)?;
+        let mut found = None;
+        let mut files = 0;
+        for _ in 0..u64::from_le_bytes(buffer) {
+            let entry = read_file_entry(&mut index, header.format(), path_encoding)?;
+            if entry.kind != EntryKind::File {
+                continue;
+            }
+            files += 1;
+            if entry_path.is_none_or(|path| entry.path == path) {
+                found.get_or_insert(entry);
+            }
+        }
+        let entry = match (found, entry_path) {
+            (Some(entry), Some(_)) => entry,
+            (Some(entry), None) if files == 1 => entry,
+            (None, Some(path)) => return Err(ImageError::EntryNotFound { path: path.to_path_buf() }),
+            _ => return Err(ImageError::AmbiguousEntry { files }),
+        };
+        
+        let mut starts = Vec::with_capacity(entry.blocks.len());
+        let mut size = 0;
+        for hash in &entry.blocks {
+            let Some((_, original_size, _, _)) = sources.iter().find_map(|source| source.block_index.get(hash)) else {
+                return Err(ImageError::CorruptIndex(format!("Bloc manquant pour {:?}", entry.path)));
+            };
+            starts.push(size);
+            size += original_size as u64;
+        }
+        Ok(Self { sources, path: entry.path, blocks: entry.blocks, starts, size, cached: None })
+    }
+    
+    /// Chemin du fichier dans l'image
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+    
+    /// Lit à partir de `offset` ; renvoie 0 à la fin du fichier
+    pub fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<usize, ImageError> {
+        let mut read = 0;
+        while read < buf.len() && offset + (read as u64) < self.size {
+            let position = offset + read as u64;
+            let block = self.starts.partition_point(|&start| start <= position) - 1;
+            if self.cached.as_ref().is_none_or(|(cached, _)| *cached != block) {
+                let data = read_block(&mut self.sources, &self.blocks[block], &self.path)?;
+                self.cached = Some((block, data));
+            }
+            let data = &self.cached.as_ref().expect("bloc en cache").1;
+            let within = (position - self.starts[block]) as usize;
+            let length = (data.len() - within).min(buf.len() - read);
+            buf[read..read + length].copy_from_slice(&data[within..within + length]);
+            read += length;
+        }
+        Ok(read)
+    }
+}
+
+/// Blocs de l'image puis ceux des images qu'elle référence
+fn open_sources(image_path: &Path, header: &ImageHeader, block_index: BlockIndex) -> Result<Vec<BlockSource>, ImageError> {
+    let mut sources = vec![BlockSource::open(image_path, block_index, IoBackend::default())?];
+    for external in &header.external_images {
+        sources.push(BlockSource::open_external(image_path, external, IoBackend::default())?);
+    }
+    Ok(sources)
+}
+
+/// Bloc décompressé, lu dans la première source qui le contient
+fn read_block(sources: &mut [BlockSource], hash: &BlockHash, path: &Path) -> Result<Vec<u8>, ImageError> {
+    let location = sources.iter_mut()
+        .find_map(|source| source.block_index.get(hash).map(|location| (source, location)));
+    let Some((source, (offset, _, compressed_size, codec))) = location else {
+        return Err(ImageError::CorruptIndex(format!("Bloc manquant pour {:?}", path)));
+    };
+    if !codec.is_supported() {
+        return Err(ImageError::UnsupportedCodec { path: path.to_path_buf(), codec });
+    }
+    Ok(codec.decode(&source.reader.read_batch(&[(offset, compressed_size)])?[0])?)
+}
+
+/// Inventaire de l'image sans lire les blocs de données
+pub fn list_image(image_path: &Path) -> Result<Listing, ImageError> {
+    let OpenedImage { mut input_file, header, path_encoding, block_index, .. } = open_image(image_path, false, false)?;
+    // Les tailles des blocs stockés dans les images référencées sont dans leurs propres index
+    let mut block_indexes = vec![block_index];
+    for external in &header.external_images {
+        block_indexes.push(BlockSource::open_external(image_path, external, IoBackend::default())?.block_index);
+    }
+    
+    let mut index = index_reader(&mut input_file, header.format(), header.file_index)?;
+    let mut buffer = [0u8; 8];
+    index.read_exact(&mut buffer)?;
+    let mut entries = Vec::new();
+    for _ in 0..u64::from_le_bytes(buffer) {
+        let entry = read_file_entry(&mut index, header.format(), path_encoding)?;
+        // La racine de l'image est stockée avec un chemin vide
+        if entry.path.as_os_str().is_empty() {
+            continue;
+        }
+        let compressed_size = entry.blocks.iter()
+            .filter_map(|hash| block_indexes.iter().find_map(|blocks| blocks.get(hash)))
+            .map(|(_, _, compressed_size, _)| compressed_size as u64)
+            .sum();
+        entries.push(ListedEntry {
+            path: entry.path,
+            kind: entry.kind,
+            size: entry.size,
+            compressed_size: Some(compressed_size),
+            // Seuls les fichiers, leurs flux et les dossiers enregistrent leur date de
+            // modification ; elle est nulle pour les dossiers des images plus anciennes
+            modified: (entry.kind.has_content() || entry.kind == EntryKind::Directory && entry.modified != 0)
+                .then_some(entry.modified),
+            blocks: Some(entry.blocks),
+        });
+    }
+    Ok(Listing { format: ContainerFormat::Image, entries, stored_size: Some(header.compressed_size) })
+}
+
+/// `hash  chemin`, avec l'échappement de GNU coreutils pour les noms contenant `\` ou un saut de ligne
+fn checksum_line(hash: &[u8], path: &Path) -> String {
+    let hex: String = hash.iter().map(|byte| format!("{:02x}", byte)).collect();
+    let name = path.components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+    if name.contains(['\\', '\n']) {
+        format!("\\{}  {}", hex, name.replace('\\', "\\\\").replace('\n', "\\n"))
+    } else {
+        format!("{}  {}", hex, name)
+    }
+}
+
+/// État partagé entre les entrées pendant l'extraction
+/// Ce qu'un fichier doit avoir en commun avec un fichier déjà extrait pour le partager :
+/// ses blocs, et pour un lien physique, qui partage aussi l'inode, ses propriétaire,
+/// permissions, dates et descripteur de sécurité
+#[derive(PartialEq, Eq, Hash)]
+struct SharedContent {
+    blocks: Vec<BlockHash>,
+    inode: Option<InodeMetadata>,
+}
+
+#[derive(PartialEq, Eq, Hash)]
+struct InodeMetadata {
+    attributes: Option<UnixAttributes>,
+    modified: u64,
+    accessed: Option<u64>,
+    security: Option<String>,
+}
+
+impl SharedContent {
+    fn of(entry: &FileEntry, dedup: ExtractDedup) -> Self {
+        let inode = (dedup == ExtractDedup::Hardlink).then(|| InodeMetadata {
+            attributes: entry.attributes,
+            modified: entry.modified,
+            accessed: entry.accessed,
+            security: entry.security.clone(),
+        });
+        Self { blocks: entry.blocks.clone(), inode }
+    }
+}
+
+struct Extractor<'a> {
+    options: &'a ExtractOptions,
+    /// Absent en simulation quand la destination n'existe pas encore
+    canonical_output: Option<PathBuf>,
+    sources: Vec<BlockSource>,
+    /// Blocs compressés du lot en cours, par image source et position
+    read_cache: HashMap<(usize, u64), Vec<u8>>,
+    mapper: PathMapper,
+    /// Premier fichier extrait pour chaque contenu partageable (modes hardlink/reflink)
+    extracted_contents: HashMap<SharedContent, PathBuf>,
+    reflink_supported: bool,
+    /// Hash BLAKE3 attendu du contenu des fichiers, d'après le manifeste vérifié
+    content_hashes: ContentHashes,
+    /// Descripteurs de sécurité à appliquer une fois toutes les entrées écrites,
+    /// pour qu'une DACL restrictive n'empêche pas d'écrire dans un dossier
+    security: Vec<(PathBuf, PathBuf, String)>,
+    /// Flux de données alternatifs et leur chemin de sortie, écrits une fois tous
+    /// les fichiers extraits, quel que soit l'ordre des entrées
+    streams: Vec<(FileEntry, PathBuf)>,
+    /// Dossiers créés, dont propriétaire, permissions et dates sont appliqués une fois
+    /// leur contenu écrit : écrire un enfant change la date de modification du dossier,
+    /// et un dossier en lecture seule empêcherait d'y écrire
+    directories: Vec<(FileEntry, PathBuf)>,
+    /// Droits root : sans eux, propriétaires et périphériques ne sont pas restaurés
+    privileged: bool,
+    /// Entrées dont le propriétaire n'a pas été restauré faute de droits
+    owners_skipped: u64,
+    /// Périphériques non créés faute de droits
+    devices_skipped: u64,
+    report: Report,
+}
+
+impl Extractor<'_> {
+    /// Source contenant le bloc et (offset absolu, taille originale, taille compressée, codec)
+    fn locate(&self, hash: &BlockHash) -> Option<(usize, (u64, usize, usize, Codec))> {
+        self.sources.iter()
+            .enumerate()
+            .find_map(|(source, blocks)| blocks.block_index.get(hash).map(|location| (source, location)))
+    }
+    
+    /// Précharge les blocs du lot puis extrait ses entrées
+    fn extract_batch(&mut self, entries: &[FileEntry]) -> Result<(), ImageError> {
+        if !self.options.dry_run {
+            debug_span!("prefetch", entries = entries.len()).in_scope(|| self.prefetch(entries))?;
+        }
+        for entry in entries {
+            let _span = debug_span!("extract", path = %entry.path.display(), size = entry.size).entered();
+            match self.extract(entry) {
+                Ok(()) => {}
+                // Entrée écrite par une version plus récente : ignorée même sans `skip_errors`
+                Err(e @ ImageError::UnsupportedCodec { .. }) => self.report.skip(&entry.path, e),
+                Err(e) => self.report.skip_or_fail(self.options.skip_errors, &entry.path, e)?,
+            }
+        }
+        self.read_cache.clear();
+        Ok(())
+    }
+    
+    /// Lit les blocs du lot dans l'ordre de l'image, en regroupant les blocs voisins
+    /// en grandes lectures séquentielles plutôt qu'un déplacement par bloc
+    fn prefetch(&mut self, entries: &[FileEntry]) -> Result<(), ImageError> {
+        let mut blocks = vec![Vec::new(); self.sources.len()];
+        let hashes = entries.iter()
+            .filter(|entry| entry.kind.has_content())
+            .flat_map(|entry| &entry.blocks);
+        for hash in hashes {
+            if let Some((source, (offset, _, compressed_size, _))) = self.locate(hash) {
+                blocks[source].push((offset, compressed_size));
+            }
+        }
+        
+        for (source, blocks) in blocks.into_iter().enumerate() {
+            let ranges = coalesce_reads(blocks);
+            let requests: Vec<_> = ranges.iter().map(|range| (range.offset, range.length)).collect();
+            let buffers = self.sources[source].reader.read_batch(&requests)?;
+            for (range, buffer) in ranges.iter().zip(buffers) {
+                for &(offset, size) in &range.blocks {
+                    let start = (offset - range.offset) as usize;
+                    self.read_cache.insert((source, offset), buffer[start..start + size].to_vec());
+                }
+            }
+        }
+        Ok(())
+    }
+    
+    /// Un seul avertissement pour les propriétaires et périphériques laissés de côté
+    /// faute de droits, plutôt qu'un par entrée
+    fn report_unprivileged(&mut self) {
+        if self.owners_skipped == 0 && self.devices_skipped == 0 {
+            return;
+        }
+        let message = format!(
+            "Extraction sans les droits root : {} propriétaires et {} périphériques non restaurés (--strict-metadata pour échouer)",
+            self.owners_skipped, self.devices_skipped
+        );
+        self.report.warn(&self.options.output_path, WarningKind::NotRestored, message);
+    }
+    
+    /// Refus d'une métadonnée que les droits ne permettent pas de restaurer, avec `strict_metadata`
+    fn unprivileged(&self, entry: &FileEntry, what: &str) -> ImageError {
+        let message = format!("{} of {:?} cannot be restored without root (--strict-metadata)", what, entry.path);
+        std::io::Error::new(std::io::ErrorKind::PermissionDenied, message).into()
+    }
+    
+    /// Applique les métadonnées des dossiers, les plus profonds d'abord pour que
+    /// restaurer un dossier ne modifie plus celle de son parent
+    fn apply_directories(&mut self) -> Result<(), ImageError> {
+        let mut directories = std::mem::take(&mut self.directories);
+        directories.sort_by_key(|(_, full_path)| Reverse(full_path.components().count()));
+        for (entry, full_path) in directories {
+            let result = self.restore_attributes(&entry, &full_path)
+                .and_then(|()| self.restore_times(&entry, &full_path));
+            if let Err(e) = result {
+                self.report.skip_or_fail(self.options.skip_errors, &entry.path, e)?;
+            }
+        }
+        Ok(())
+    }
+    
+    /// Applique les descripteurs de sécurité relevés pendant l'extraction ; un échec
+    /// laisse les permissions héritées et est signalé dans le rapport
+    fn apply_security(&mut self) {
+        for (entry_path, full_path, sddl) in std::mem::take(&mut self.security) {
+            if let Err(e) = platform::write_security_descriptor(&full_path, &sddl) {
+                self.report.warn(&entry_path, WarningKind::NotRestored, format!("Descripteur de sécurité non restauré: {}", e));
+            }
+        }
+    }
+    
+    /// Écrit les flux de données alternatifs relevés pendant l'extraction dans leurs fichiers
+    fn extract_streams(&mut self) -> Result<(), ImageError> {
+        for (entry, full_path) in std::mem::take(&mut self.streams) {
+            let _span = debug_span!("extract", path = %entry.path.display(), size = entry.size).entered();
+            let result = self.read_content(&entry)
+                .and_then(|data| fs::write(&full_path, data).map_err(|e| ImageError::io_at(e, &full_path)));
+            match result {
+                Ok(()) => {}
+                Err(e @ ImageError::UnsupportedCodec { .. }) => self.report.skip(&entry.path, e),
+                Err(e) => self.report.skip_or_fail(self.options.skip_errors, &entry.path, e)?,
+            }
+        }
+        Ok(())
+    }
+    
+    /// Chemin de sortie d'une entrée, `None` si elle n'est pas extraite ; celui d'un
+    /// flux est le chemin de sortie de son fichier suivi de `:flux`
+    fn output_path(&mut self, entry: &FileEntry) -> Result<Option<PathBuf>, ImageError> {
+        let options = self.options;
+        let kind = entry.kind;
+        let (entry_path, stream) = match kind {
+            EntryKind::Stream => match split_stream(&entry.path) {
+                Some((file, stream)) => (file, Some(stream)),
+                None => return Err(ImageError::CorruptIndex(format!("Flux sans fichier: {:?}", entry.path))),
+            },
+            _ => (entry.path.clone(), None),
+        };
+        
+        // La racine de l'image est stockée avec un chemin vide ; strip_components
+        // écarte les entrées dont il ne reste aucun composant
+        let path = strip_components(&entry_path, options.strip_components);
+        if path.as_os_str().is_empty() && (kind == EntryKind::Directory || options.strip_components > 0) {
+            return Ok(None);
+        }
+        
+        // Protection contre les chemins absolus et les `..`
+        let Some(safe_path) = sanitize_path(&path) else {
+            return Err(ImageError::UnsafePath { path: entry.path.clone() });
+        };
+        if safe_path != path {
+            self.report.warn(&entry.path, WarningKind::PathSanitized, format!("Chemin réécrit en {:?}", safe_path));
+        }
+        let Some(mapped_path) = self.mapper.map(&safe_path, kind == EntryKind::Directory, &mut self.report)? else {
+            return Ok(None);
+        };
+        let full_path = options.output_path.join(mapped_path);
+        
+        // Un lien symbolique extrait plus tôt ne doit pas permettre d'écrire hors de la destination
+        if let Some(canonical_output) = &self.canonical_output {
+            if !resolves_within(canonical_output, &full_path)? {
+                return Err(ImageError::UnsafePath { path: entry.path.clone() });
+            }
+        }
+        Ok(Some(match stream {
+            Some(stream) => stream_path(&full_path, stream),
+            None => full_path,
+        }))
+    }
+    
+    fn extract(&mut self, entry: &FileEntry) -> Result<(), ImageError> {
+        let options = self.options;
+        let kind = entry.kind;
+        
+        if kind == EntryKind::Stream && !cfg!(windows) {
+            self.report.warn(&entry.path, WarningKind::NotRestored, "Flux de données alternatifs non supportés sur cette plateforme");
+            return Ok(());
+        }
+        let Some(full_path) = self.output_path(entry)? else {
+            return Ok(());
+        };
+        if options.dry_run {
+            return self.plan(entry, &full_path);
+        }
+        if kind == EntryKind::Stream {
+            self.streams.push((entry.clone(), full_path));
+            return Ok(());
+        }
+        if let (true, Some(sddl)) = (options.acls && !options.dry_run && kind != EntryKind::Symlink, &entry.security) {
+            self.security.push((entry.path.clone(), full_path.clone(), sddl.clone()));
+        }
+        
+        match kind {
+            EntryKind::Directory => {
+                // Un lien à la place du dossier mènerait ses permissions et dates hors de la destination
+                remove_symlink(&full_path).map_err(|e| ImageError::io_at(e, &full_path))?;
+                fs::create_dir_all(&full_path).map_err(|e| ImageError::io_at(e, &full_path))?;
+                self.directories.push((entry.clone(), full_path));
+                return Ok(());
+            }
+            EntryKind::Symlink => {
+                if let Some(parent) = full_path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                let Some(target) = &entry.link_target else {
+                    return Ok(());
+                };
+                match create_symlink(target, &full_path) {
+                    Err(e) if e.kind() == std::io::ErrorKind::Unsupported => {
+                        self.report.warn(&entry.path, WarningKind::NotRestored, "Liens symboliques non supportés sur cette plateforme");
+                        return Ok(());
+                    }
+                    result => result.map_err(|e| ImageError::io_at(e, &full_path))?,
+                }
+            }
+            EntryKind::File => self.write_file(entry, &full_path)?,
+            _ => {
+                if !self.make_node(entry, &full_path)? {
+                    return Ok(());
+                }
+            }
+        }
+        self.restore_attributes(entry, &full_path)?;
+        self.restore_times(entry, &full_path)
+    }
+    
+    /// Écrit le contenu d'un fichier, ou le lie à un fichier identique déjà extrait
+    fn write_file(&mut self, entry: &FileEntry, full_path: &Path) -> Result<(), ImageError> {
+        let options = self.options;
+        
+        // Créer le dossier parent si nécessaire
+        if let Some(parent) = full_path.parent() {
+            fs::create_dir_all(parent).map_err(|e| ImageError::io_at(e, parent))?;
+        }
+        remove_symlink(full_path)?;
+        
+        let share_content = match options.dedup {
+            ExtractDedup::Copy => false,
+            ExtractDedup::Hardlink => true,
+            ExtractDedup::Reflink => self.reflink_supported,
+        };
+        if share_content && !entry.blocks.is_empty() {
+            if let Some(original) = self.extracted_contents.get(&SharedContent::of(entry, options.dedup)) {
+                if full_path.symlink_metadata().is_ok() {
+                    fs::remove_file(full_path)?;
+                }
+                let result = match options.dedup {
+                    ExtractDedup::Reflink => platform::reflink(original, full_path),
+                    _ => fs::hard_link(original, full_path),
+                };
+                match result {
+                    Ok(()) => return Ok(()),
+                    Err(e) if options.dedup == ExtractDedup::Reflink => {
+                        // Le système de fichiers ne gère pas les reflinks : inutile de réessayer
+                        self.report.warn(
+                            &entry.path,
+                            WarningKind::LinkFallback,
+                            format!("Reflinks non supportés sur la destination ({}), copie des fichiers", e),
+                        );
+                        self.reflink_supported = false;
+                    }
+                    Err(e) => self.report.warn(
+                        &entry.path,
+                        WarningKind::LinkFallback,
+                        format!("Lien physique impossible ({}), copie à la place", e),
+                    ),
+                }
+            }
+        }
+        
+        let file_data = self.read_content(entry)?;
+        
+        // Écriture du fichier
+        let mut output_file = File::create(full_path).map_err(|e| ImageError::io_at(e, full_path))?;
+        output_file.write_all(&file_data)?;
+        
+        if share_content && !entry.blocks.is_empty() {
+            self.extracted_contents.entry(SharedContent::of(entry, options.dedup)).or_insert_with(|| full_path.to_path_buf());
+        }
+        Ok(())
+    }
+    
+    /// Recrée une FIFO, un socket ou un périphérique ; un nœud que la plateforme ou les
+    /// droits ne permettent pas de créer est signalé dans le rapport. Renvoie si le nœud existe.
+    fn make_node(&mut self, entry: &FileEntry, full_path: &Path) -> Result<bool, ImageError> {
+        let kind = entry.kind;
+        if matches!(kind, EntryKind::CharDevice | EntryKind::BlockDevice) && entry.device_numbers.is_none() {
+            self.report.warn(&entry.path, WarningKind::NotRestored, format!("Numéros de périphérique absents de l'image ({:?})", kind));
+            return Ok(false);
+        }
+        if matches!(kind, EntryKind::CharDevice | EntryKind::BlockDevice) && !self.privileged {
+            if self.options.strict_metadata {
+                return Err(self.unprivileged(entry, "device node"));
+            }
+            self.devices_skipped += 1;
+            return Ok(false);
+        }
+        if let Some(parent) = full_path.parent() {
+            fs::create_dir_all(parent).map_err(|e| ImageError::io_at(e, parent))?;
+        }
+        let device_numbers = entry.device_numbers.unwrap_or_default();
+        let mut result = platform::make_node(full_path, kind, device_numbers);
+        // Remplace une entrée existante seulement une fois le nœud créable
+        if result.as_ref().is_err_and(|e| e.kind() == std::io::ErrorKind::AlreadyExists) && !full_path.is_dir() {
+            fs::remove_file(full_path).map_err(|e| ImageError::io_at(e, full_path))?;
+            result = platform::make_node(full_path, kind, device_numbers);
+        }
+        match result {
+            Ok(()) => Ok(true),
+            Err(e) if self.options.strict_metadata => Err(ImageError::io_at(e, full_path)),
+            Err(e) => {
+                self.report.warn(&entry.path, WarningKind::NotRestored, format!("Fichier spécial non restauré ({:?}): {}", kind, e));
+                Ok(false)
+            }
+        }
+    }
+    
+    /// Donne à l'entrée extraite son propriétaire, après remplacement par `owner_map`
+    /// et `group_map`, puis ses permissions ; un échec est signalé dans le rapport.
+    /// Sans les droits root, le propriétaire reste celui qui extrait.
+    fn restore_attributes(&mut self, entry: &FileEntry, full_path: &Path) -> Result<(), ImageError> {
+        let Some(attributes) = entry.attributes else {
+            return Ok(());
+        };
+        let strict = self.options.strict_metadata;
+        let uid = self.options.owner_map.map(attributes.uid);
+        let gid = self.options.group_map.map(attributes.gid);
+        if !self.privileged {
+            // Les entrées créées appartiennent déjà à qui extrait
+            if platform::effective_ids() != Some((uid, gid)) {
+                if strict {
+                    return Err(self.unprivileged(entry, "ownership"));
+                }
+                self.owners_skipped += 1;
+            }
+        } else {
+            match platform::set_owner(full_path, uid, gid) {
+                Ok(()) => {}
+                Err(e) if strict => return Err(ImageError::io_at(e, full_path)),
+                Err(e) => self.report.warn(&entry.path, WarningKind::NotRestored, format!("Propriétaire {}:{} non restauré: {}", uid, gid, e)),
+            }
+        }
+        // Les permissions d'un lien symbolique sont celles de sa cible
+        if entry.kind != EntryKind::Symlink {
+            match platform::set_mode(full_path, attributes.mode) {
+                Ok(()) => {}
+                Err(e) if strict => return Err(ImageError::io_at(e, full_path)),
+                Err(e) => self.report.warn(&entry.path, WarningKind::NotRestored, format!("Permissions {:o} non restaurées: {}", attributes.mode, e)),
+            }
+        }
+        Ok(())
+    }
+    
+    /// Date de modification des fichiers et dossiers et date d'accès des entrées qui
+    /// en ont une, une fois leur contenu et leurs permissions écrits
+    fn restore_times(&mut self, entry: &FileEntry, full_path: &Path) -> Result<(), ImageError> {
+        let has_modified = entry.kind.has_content() || entry.kind == EntryKind::Directory;
+        let modified = Some(entry.modified).filter(|&time| has_modified && time != 0);
+        if modified.is_none() && entry.accessed.is_none() {
+            return Ok(());
+        }
+        match platform::set_times(full_path, entry.accessed, modified) {
+            Ok(()) => Ok(()),
+            Err(e) if self.options.strict_metadata => Err(ImageError::io_at(e, full_path)),
+            Err(e) => {
+                self.report.warn(&entry.path, WarningKind::NotRestored, format!("Dates non restaurées: {}", e));
+                Ok(())
+            }
+        }
+    }
+    
+    /// Contenu d'une entrée, vérifié contre le manifeste signé
+    fn read_content(&mut self, entry: &FileEntry) -> Result<Vec<u8>, ImageError> {
+        let mut file_data = Vec::with_capacity(entry.size as usize);
+        for hash in &entry.blocks {
+            let Some((source, (offset, _original_size, compressed_size, codec))) = self.locate(hash) else {
+                return Err(ImageError::CorruptIndex(format!("Bloc manquant pour {:?}", entry.path)));
+            };
+            if !codec.is_supported() {
+                return Err(ImageError::UnsupportedCodec { path: entry.path.clone(), codec });
+            }
+            // Bloc préchargé avec le lot, ou lu individuellement à défaut
+            let decompressed = match self.read_cache.get(&(source, offset)) {
+                Some(compressed_data) => codec.decode(compressed_data)?,
+                None => codec.decode(&self.sources[source].reader.read_batch(&[(offset, compressed_size)])?[0])?,
+            };
+            file_data.extend_from_slice(&decompressed);
+        }
+        if self.content_hashes.get(&entry.path).is_some_and(|expected| blake3::hash(&file_data).as_bytes() != expected) {
+            return Err(ImageError::ChecksumMismatch { path: entry.path.clone() });
+        }
+        Ok(file_data)
+    }
+    
+    /// Simulation : vérifie que les blocs existent et consigne l'entrée sans rien écrire
+    fn plan(&mut self, entry: &FileEntry, full_path: &Path) -> Result<(), ImageError> {
+        for hash in &entry.blocks {
+            match self.locate(hash) {
+                None => return Err(ImageError::CorruptIndex(format!("Bloc manquant pour {:?}", entry.path))),
+                Some((_, (_, _, _, codec))) if !codec.is_supported() => {
+                    return Err(ImageError::UnsupportedCodec { path: entry.path.clone(), codec });
+                }
+                Some(_) => {}
+            }
+        }
+        self.report.plan(full_path, entry.kind, entry.size);
+        Ok(())
+    }
+}
+
+/// Lecture séquentielle couvrant plusieurs blocs voisins de l'image
+struct ReadRange {
+    offset: u64,
+    length: usize,
+    /// (position, taille compressée) des blocs couverts
+    blocks: Vec<(u64, usize)>,
+}
+
+/// Trie les blocs par position et fusionne ceux que sépare un petit écart :
+/// lire quelques octets inutiles coûte moins qu'un déplacement sur disque rotatif
+fn coalesce_reads(mut blocks: Vec<(u64, usize)>) -> Vec<ReadRange> {
+    blocks.sort_unstable();
+    blocks.dedup();
+    
+    let mut ranges: Vec<ReadRange> = Vec::new();
+    for (offset, size) in blocks {
+        if let Some(range) = ranges.last_mut() {
+            let end = range.offset + range.length as u64;
+            let merged_length = (offset + size as u64).saturating_sub(range.offset) as usize;
+            if offset <= end + MAX_READ_GAP && merged_length <= MAX_READ_SIZE {
+                range.length = range.length.max(merged_length);
+                range.blocks.push((offset, size));
+                continue;
+            }
+        }
+        ranges.push(ReadRange { offset, length: size, blocks: vec![(offset, size)] });
+    }
+    ranges
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    if link.symlink_metadata().is_ok() {
+        fs::remove_file(link)?;
+    }
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(not(unix))]
+fn create_symlink(_target: &Path, _link: &Path) -> std::io::Result<()> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "symlinks are not supported on this platform"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+    use zstd::encode_all;
+
+    /// Image sans blocs contenant les entrées données, telles quelles
+    fn write_raw_image(path: &Path, entries: &[(&str, EntryKind, Option<&str>)]) {
+        write_raw_image_version(path, entries, IMAGE_VERSION);
+    }
+
+    /// Image sans blocs de la version donnée, 10 au moins
+    fn write_raw_image_version(path: &Path, entries: &[(&str, EntryKind, Option<&str>)], version: u32) {
+        let mut file_index = Vec::new();
+        file_index.extend_from_slice(&(entries.len() as u64).to_le_bytes());
+        for (entry_path, kind, target) in entries {
+            write_path(&mut file_index, Path::new(entry_path)).unwrap();
+            file_index.extend_from_slice(&[0u8; 16]);
+            file_index.push(kind_to_byte(*kind));
+            file_index.extend_from_slice(&0u64.to_le_bytes());
+            if let Some(target) = target {
+                write_path(&mut file_index, Path::new(target)).unwrap();
+            }
+            // Périphériques 0:0, aucun descripteur de sécurité
+            if matches!(kind, EntryKind::CharDevice | EntryKind::BlockDevice) && version >= 15 {
+                file_index.extend_from_slice(&[0u8; 8]);
+            }
+            if version >= 13 {
+                file_index.extend_from_slice(&0u32.to_le_bytes());
+            }
+            // Sans propriétaire ni permissions, ni date d'accès
+            if version >= 16 {
+                file_index.push(0);
+            }
+            if version >= 17 {
+                file_index.extend_from_slice(&0u64.to_le_bytes());
+            }
+        }
+        // Aucune suppression
+        if version >= 11 {
+            file_index.extend_from_slice(&0u64.to_le_bytes());
+        }
+        let block_index = encode_all(&[][..], 3).unwrap();
+        let compressed_file_index = encode_all(file_index.as_slice(), 3).unwrap();
+
+        let mut image = Vec::new();
+        image.extend_from_slice(&version.to_le_bytes());
+        for _ in 0..5 {
+            image.extend_from_slice(&0u64.to_le_bytes());
+        }
+        image.push(native_path_encoding());
+        for size in [block_index.len(), 0, compressed_file_index.len(), file_index.len()] {
+            image.extend_from_slice(&(size as u64).to_le_bytes());
+        }
+        image.push(HashAlgorithm::Xxh3.to_byte());
+        // Aucune image référencée ni image de base, blocs fixes de 64KB
+        image.extend_from_slice(&0u64.to_le_bytes());
+        if version >= 11 {
+            image.extend_from_slice(&0u64.to_le_bytes());
+        }
+        image.push(ChunkStrategy::Fixed as u8);
+        for size in [16384u32, 65536, 262144] {
+            image.extend_from_slice(&size.to_le_bytes());
+        }
+        image.push(1);
+        // Image non signée, sans blocs ajoutés ni filtre de Bloom
+        image.extend_from_slice(&[0u8; 16]);
+        if version >= 12 {
+            image.extend_from_slice(&[0u8; 32]);
+        }
+        image.extend_from_slice(&0u64.to_le_bytes());
+        image.extend_from_slice(&block_index);
+        image.extend_from_slice(&compressed_file_index);
+        fs::write(path, image).unwrap();
+    }
+
+    #[test]
+    fn test_previous_versions() {
+        // Les deux versions précédant la version courante restent lisibles
+        let temp_dir = tempdir().unwrap();
+        for version in [IMAGE_VERSION - 2, IMAGE_VERSION - 1] {
+            let image_path = temp_dir.path().join(format!("v{}.zpak", version));
+            write_raw_image_version(&image_path, &[("dir", EntryKind::Directory, None), ("dir/lien", EntryKind::Symlink, Some("cible"))], version);
+            let listing = list_image(&image_path).unwrap();
+            assert_eq!(listing.entries.len(), 2);
+        }
+
+        let image_path = temp_dir.path().join("future.zpak");
+        write_raw_image_version(&image_path, &[], IMAGE_VERSION);
+        let mut image = fs::read(&image_path).unwrap();
+        image[..4].copy_from_slice(&(IMAGE_VERSION + 1).to_le_bytes());
+        fs::write(&image_path, image).unwrap();
+        let error = list_image(&image_path).unwrap_err();
+        assert!(matches!(error, ImageError::UnsupportedVersion { found, max_supported: IMAGE_VERSION } if found == IMAGE_VERSION + 1));
+        assert!(error.to_string().contains(&format!("up to {}", IMAGE_VERSION)));
+    }
+
+    #[test]
+    fn test_security_descriptor_roundtrip() {
+        let sddl = "O:BAG:SYD:PAI(A;OICI;FA;;;SY)(A;OICI;0x1200a9;;;BU)";
+        let entries = [
+            FileEntry {
+                path: PathBuf::from("partage"),
+                size: 0,
+                modified: 0,
+                kind: EntryKind::Directory,
+                blocks: Vec::new(),
+                link_target: None,
+                security: Some(sddl.to_string()),
+                device_numbers: None,
+                attributes: None,
+                accessed: None,
+            },
+            FileEntry {
+                path: PathBuf::from("partage/lien"),
+                size: 0,
+                modified: 0,
+                kind: EntryKind::Symlink,
+                blocks: Vec::new(),
+                link_target: Some(PathBuf::from("cible")),
+                security: None,
+                device_numbers: None,
+                attributes: None,
+                accessed: None,
+            },
+        ];
+        let file_index = encode_file_index(&entries, &[]).unwrap();
+        let mut index = &file_index[8..];
+        let format = ImageFormat(IMAGE_VERSION);
+        let first = read_file_entry(&mut index, format, native_path_encoding()).unwrap();
+        let second = read_file_entry(&mut index, format, native_path_encoding()).unwrap();
+        assert_eq!(first.security.as_deref(), Some(sddl));
+        assert!(same_entry(&first, &entries[0]));
+        assert_eq!(second.security, None);
+        assert_eq!(second.link_target, Some(PathBuf::from("cible")));
+        assert_eq!(index, &0u64.to_le_bytes()[..]);
+        
+        // Sans plateforme pour les appliquer, les descripteurs sont signalés
+        #[cfg(not(windows))]
+        {
+            let temp_dir = tempdir().unwrap();
+            let path = temp_dir.path().join("partage");
+            fs::create_dir(&path).unwrap();
+            assert_eq!(platform::read_security_descriptor(&path).unwrap(), None);
+            let error = platform::write_security_descriptor(&path, sddl).unwrap_err();
+            assert_eq!(error.kind(), std::io::ErrorKind::Unsupported);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_special_file_entries() {
+        use crate::walk::SpecialFilePolicy;
+        use std::os::unix::fs::FileTypeExt;
+        
+        let temp_dir = tempdir().unwrap();
+        let input_dir = temp_dir.path().join("input");
+        fs::create_dir(&input_dir).unwrap();
+        let fifo = std::ffi::CString::new(input_dir.join("tube").into_os_string().into_encoded_bytes()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o644) }, 0);
+        let _socket = std::os::unix::net::UnixListener::bind(input_dir.join("socket")).unwrap();
+        
+        let image_path = temp_dir.path().join("special.zpak");
+        create_image(&ImageOptions {
+            input_path: input_dir,
+            output_path: image_path.clone(),
+            walk: WalkOptions { special_files: SpecialFilePolicy::Record, ..Default::default() },
+            ..Default::default()
+        }).unwrap();
+        
+        let output_dir = temp_dir.path().join("output");
+        fs::create_dir(&output_dir).unwrap();
+        // Une entrée existante est remplacée par le nœud
+        fs::write(output_dir.join("tube"), b"ancien").unwrap();
+        let report = extract_image(&ExtractOptions {
+            image_path,
+            output_path: output_dir.clone(),
+            ..Default::default()
+        }).unwrap();
+        assert!(report.warnings.is_empty());
+        assert!(fs::symlink_metadata(output_dir.join("tube")).unwrap().file_type().is_fifo());
+        assert!(fs::symlink_metadata(output_dir.join("socket")).unwrap().file_type().is_socket());
+        
+        // Les numéros des périphériques font partie de l'entrée
+        let device = FileEntry {
+            path: PathBuf::from("dev/sda"),
+            size: 0,
+            modified: 0,
+            kind: EntryKind::BlockDevice,
+            blocks: Vec::new(),
+            link_target: None,
+            security: None,
+            device_numbers: Some((8, 0)),
+            attributes: Some(UnixAttributes { uid: 0, gid: 6, mode: 0o660 }),
+            accessed: Some(1_700_000_000),
+        };
+        let file_index = encode_file_index(std::slice::from_ref(&device), &[]).unwrap();
+        let read = read_file_entry(&mut &file_index[8..], ImageFormat(IMAGE_VERSION), native_path_encoding()).unwrap();
+        assert!(same_entry(&read, &device));
+    }
+
+    #[test]
+    fn test_unprivileged_extraction() {
+        // Les droits root permettent de tout restaurer : rien à laisser de côté
+        if platform::is_privileged() {
+            return;
+        }
+        let temp_dir = tempdir().unwrap();
+        let image_path = temp_dir.path().join("dev.zpak");
+        write_raw_image(&image_path, &[("dev", EntryKind::Directory, None), ("dev/null", EntryKind::CharDevice, None), ("dev/zero", EntryKind::CharDevice, None)]);
+        let output_dir = temp_dir.path().join("output");
+        let extract = |strict_metadata| extract_image(&ExtractOptions {
+            image_path: image_path.clone(),
+            output_path: output_dir.clone(),
+            strict_metadata,
+            ..Default::default()
+        });
+        
+        // Un seul avertissement pour tous les périphériques
+        let report = extract(false).unwrap();
+        assert_eq!(report.warnings.len(), 1);
+        assert!(report.warnings[0].message.contains("2 périphériques"));
+        assert!(!output_dir.join("dev/null").exists());
+        
+        let error = extract(true).unwrap_err();
+        assert!(error.to_string().contains("--strict-metadata"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_owner_map_on_extraction() {
+        use crate::owners::IdKind;
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+        
+        let temp_dir = tempdir().unwrap();
+        let input_dir = temp_dir.path().join("input");
+        fs::create_dir(&input_dir).unwrap();
+        let script = input_dir.join("script.sh");
+        fs::write(&script, b"#!/bin/sh").unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o750)).unwrap();
+        let image_path = temp_dir.path().join("owners.zpak");
+        create_image(&ImageOptions {
+            input_path: input_dir,
+            output_path: image_path.clone(),
+            ..Default::default()
+        }).unwrap();
+        
+        // Sans les droits root, seul son propre uid peut être donné
+        let uid = fs::metadata(&script).unwrap().uid();
+        let target = if platform::is_privileged() { 4321 } else { uid };
+        let output_dir = temp_dir.path().join("output");
+        let report = extract_image(&ExtractOptions {
+            image_path,
+            output_path: output_dir.clone(),
+            owner_map: IdMap::parse(&format!("{}:{}", uid, target), IdKind::User).unwrap(),
+            ..Default::default()
+        }).unwrap();
+        assert!(report.warnings.is_empty());
+        let metadata = fs::metadata(output_dir.join("script.sh")).unwrap();
+        assert_eq!(metadata.uid(), target);
+        assert_eq!(metadata.mode() & 0o7777, 0o750);
+    }
+
+    #[test]
+    fn test_access_times_restored() {
+        use std::time::{Duration, UNIX_EPOCH};
+        
+        let temp_dir = tempdir().unwrap();
+        let input_dir = temp_dir.path().join("input");
+        fs::create_dir(&input_dir).unwrap();
+        let file_path = input_dir.join("ancien.txt");
+        fs::write(&file_path, b"contenu ancien").unwrap();
+        let accessed = UNIX_EPOCH + Duration::from_secs(1_500_000_000);
+        let modified = UNIX_EPOCH + Duration::from_secs(1_400_000_000);
+        File::options().write(true).open(&file_path).unwrap()
+            .set_times(fs::FileTimes::new().set_accessed(accessed).set_modified(modified)).unwrap();
+        
+        let image_path = temp_dir.path().join("dates.zpak");
+        create_image(&ImageOptions {
+            input_path: input_dir,
+            output_path: image_path.clone(),
+            atime: true,
+            ..Default::default()
+        }).unwrap();
+        
+        let output_dir = temp_dir.path().join("output");
+        let report = extract_image(&ExtractOptions {
+            image_path,
+            output_path: output_dir.clone(),
+            ..Default::default()
+        }).unwrap();
+        assert!(report.warnings.is_empty());
+        let metadata = fs::metadata(output_dir.join("ancien.txt")).unwrap();
+        assert_eq!(metadata.modified().unwrap(), modified);
+        assert_eq!(metadata.accessed().unwrap(), accessed);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_directory_metadata_deferred() {
+        use std::os::unix::fs::PermissionsExt;
+        use std::time::{Duration, UNIX_EPOCH};
+        
+        let temp_dir = tempdir().unwrap();
+        let input_dir = temp_dir.path().join("input");
+        let locked_dir = input_dir.join("verrouillé");
+        fs::create_dir_all(locked_dir.join("sous")).unwrap();
+        fs::write(locked_dir.join("sous/fichier.txt"), b"contenu").unwrap();
+        let modified = UNIX_EPOCH + Duration::from_secs(1_400_000_000);
+        for dir in [locked_dir.join("sous"), locked_dir.clone()] {
+            File::open(&dir).unwrap().set_times(fs::FileTimes::new().set_modified(modified)).unwrap();
+        }
+        fs::set_permissions(&locked_dir, fs::Permissions::from_mode(0o555)).unwrap();
+        
+        let image_path = temp_dir.path().join("dossiers.zpak");
+        create_image(&ImageOptions {
+            input_path: input_dir,
+            output_path: image_path.clone(),
+            ..Default::default()
+        }).unwrap();
+        fs::set_permissions(&locked_dir, fs::Permissions::from_mode(0o755)).unwrap();
+        
+        // Le dossier en lecture seule ne bloque pas l'écriture de son contenu, et
+        // l'écriture des enfants ne change pas les dates restaurées
+        let output_dir = temp_dir.path().join("output");
+        let report = extract_image(&ExtractOptions {
+            image_path,
+            output_path: output_dir.clone(),
+            ..Default::default()
+        }).unwrap();
+        assert!(report.warnings.is_empty());
+        let extracted = output_dir.join("verrouillé");
+        assert_eq!(fs::read(extracted.join("sous/fichier.txt")).unwrap(), b"contenu");
+        assert_eq!(fs::metadata(extracted.join("sous")).unwrap().modified().unwrap(), modified);
+        let metadata = fs::metadata(&extracted).unwrap();
+        assert_eq!(metadata.modified().unwrap(), modified);
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o555);
+        fs::set_permissions(&extracted, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_directory_over_symlink() {
+        use std::os::unix::fs::PermissionsExt;
+        
+        let temp_dir = tempdir().unwrap();
+        let input_dir = temp_dir.path().join("input");
+        fs::create_dir_all(input_dir.join("partage")).unwrap();
+        fs::set_permissions(input_dir.join("partage"), fs::Permissions::from_mode(0o700)).unwrap();
+        let image_path = temp_dir.path().join("lien.zpak");
+        create_image(&ImageOptions {
+            input_path: input_dir,
+            output_path: image_path.clone(),
+            ..Default::default()
+        }).unwrap();
+        
+        // Un lien déjà présent à la place du dossier pointe hors de la destination
+        let victim = temp_dir.path().join("victime");
+        fs::create_dir(&victim).unwrap();
+        fs::set_permissions(&victim, fs::Permissions::from_mode(0o755)).unwrap();
+        let output_dir = temp_dir.path().join("output");
+        fs::create_dir(&output_dir).unwrap();
+        std::os::unix::fs::symlink(&victim, output_dir.join("partage")).unwrap();
+        assert!(platform::set_mode(&output_dir.join("partage"), 0o700).is_err());
+        
+        extract_image(&ExtractOptions {
+            image_path,
+            output_path: output_dir.clone(),
+            skip_errors: true,
+            ..Default::default()
+        }).unwrap();
+        assert_eq!(fs::metadata(&victim).unwrap().permissions().mode() & 0o777, 0o755);
+        let extracted = fs::symlink_metadata(output_dir.join("partage")).unwrap();
+        assert!(extracted.is_dir());
+        assert_eq!(extracted.permissions().mode() & 0o777, 0o700);
+    }
+
+    #[test]
+    fn test_data_stream_entries() {
+        let stream = Path::new("docs/rapport.txt:Zone.Identifier");
+        assert_eq!(stream_path(Path::new("docs/rapport.txt"), "Zone.Identifier"), stream);
+        assert_eq!(split_stream(stream), Some((PathBuf::from("docs/rapport.txt"), "Zone.Identifier")));
+        assert_eq!(split_stream(Path::new("docs/rapport.txt")), None);
+        
+        let temp_dir = tempdir().unwrap();
+        let image_path = temp_dir.path().join("flux.zpak");
+        let output_dir = temp_dir.path().join("output");
+        write_raw_image(&image_path, &[
+            ("docs", EntryKind::Directory, None),
+            ("docs/rapport.txt", EntryKind::File, None),
+            ("docs/rapport.txt:Zone.Identifier", EntryKind::Stream, None),
+        ]);
+        let listing = list_image(&image_path).unwrap();
+        assert_eq!(listing.entries[2].kind, EntryKind::Stream);
+        
+        let report = extract_image(&ExtractOptions {
+            image_path: image_path.clone(),
+            output_path: output_dir.clone(),
+            ..Default::default()
+        }).unwrap();
+        assert!(output_dir.join("docs/rapport.txt").is_file());
+        if cfg!(windows) {
+            assert!(report.warnings.is_empty());
+        } else {
+            // Le flux est signalé plutôt qu'écrit comme un fichier `rapport.txt:Zone.Identifier`
+            assert_eq!(report.warnings.len(), 1);
+            assert_eq!(report.warnings[0].kind, WarningKind::NotRestored);
+            assert_eq!(fs::read_dir(output_dir.join("docs")).unwrap().count(), 1);
+        }
+        
+        // Une version antérieure aux flux ne peut pas en contenir
+        write_raw_image_version(&image_path, &[("rapport.txt:flux", EntryKind::Stream, None)], 13);
+        assert!(matches!(list_image(&image_path), Err(ImageError::CorruptIndex(_))));
+    }
+
+    #[test]
+    fn test_extraction_rejects_path_traversal() {
+        let temp_dir = tempdir().unwrap();
+        let output_dir = temp_dir.path().join("output");
+        let image_path = temp_dir.path().join("evil.zpak");
+        let extract = |image_path: &Path| extract_image(&ExtractOptions {
+            image_path: image_path.to_path_buf(),
+            output_path: output_dir.clone(),
+            ..Default::default()
+        });
+
+        // Les `..` sont retirés : le fichier reste dans la destination
+        write_raw_image(&image_path, &[("../../escape.txt", EntryKind::File, None)]);
+        let report = extract(&image_path).unwrap();
+        assert_eq!(report.warnings[0].kind, WarningKind::PathSanitized);
+        assert!(output_dir.join("escape.txt").exists());
+        assert!(!temp_dir.path().join("escape.txt").exists());
+
+        // Chemin absolu refusé
+        write_raw_image(&image_path, &[("/tmp/absolute.txt", EntryKind::File, None)]);
+        assert!(matches!(extract(&image_path), Err(ImageError::UnsafePath { .. })));
+    }
+
+    #[test]
+    fn test_extraction_skip_errors() {
+        let temp_dir = tempdir().unwrap();
+        let output_dir = temp_dir.path().join("output");
+        let image_path = temp_dir.path().join("mixed.zpak");
+        write_raw_image(&image_path, &[
+            ("/absolute.txt", EntryKind::File, None),
+            ("kept.txt", EntryKind::File, None),
+        ]);
+
+        let report = extract_image(&ExtractOptions {
+            image_path,
+            output_path: output_dir.clone(),
+            skip_errors: true,
+            ..Default::default()
+        }).unwrap();
+        assert_eq!(report.skipped.len(), 1);
+        assert_eq!(report.skipped[0].path, PathBuf::from("/absolute.txt"));
+        assert!(output_dir.join("kept.txt").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_extraction_rejects_writes_through_symlinks() {
+        let temp_dir = tempdir().unwrap();
+        let outside = temp_dir.path().join("outside");
+        fs::create_dir_all(&outside).unwrap();
+        let output_dir = temp_dir.path().join("output");
+        let image_path = temp_dir.path().join("evil.zpak");
+
+        write_raw_image(&image_path, &[
+            ("link", EntryKind::Symlink, Some(outside.to_str().unwrap())),
+            ("link/pwned.txt", EntryKind::File, None),
+        ]);
+        assert!(matches!(
+            extract_image(&ExtractOptions {
+                image_path: image_path.clone(),
+                output_path: output_dir.clone(),
+                ..Default::default()
+            }),
+            Err(ImageError::UnsafePath { .. })
+        ));
+        assert!(!outside.join("pwned.txt").exists());
+
+        // Un fichier portant le nom d'un lien remplace le lien au lieu de le suivre
+        fs::write(outside.join("victim.txt"), b"intact").unwrap();
+        write_raw_image(&image_path, &[
+            ("victim.txt", EntryKind::Symlink, Some(outside.join("victim.txt").to_str().unwrap())),
+            ("victim.txt", EntryKind::File, None),
+        ]);
+        extract_image(&ExtractOptions {
+            image_path,
+            output_path: output_dir.clone(),
+            ..Default::default()
+        }).unwrap();
+        assert_eq!(fs::read(outside.join("victim.txt")).unwrap(), b"intact");
+        assert!(!output_dir.join("victim.txt").symlink_metadata().unwrap().file_type().is_symlink());
+    }
+
+    #[test]
+    fn test_image_roundtrip() {
+        let temp_dir = tempdir().unwrap();
+        let input_dir = temp_dir.path().join("input");
+        fs::create_dir_all(input_dir.join("sub/empty")).unwrap();
+        fs::write(input_dir.join("a.txt"), "contenu répété ".repeat(10_000)).unwrap();
+        fs::write(input_dir.join("sub/b.bin"), vec![7u8; BLOCK_SIZE * 2 + 10]).unwrap();
+
+        let image_path = temp_dir.path().join("test.zpak");
+        create_image(&ImageOptions {
+            input_path: input_dir.clone(),
+            output_path: image_path.clone(),
+            compression_level: 3,
+            ..Default::default()
+        }).unwrap();
+
+        // Index compressés
+        let (header, _) = read_header(&mut File::open(&image_path).unwrap()).unwrap();
+        assert!(header.file_index.compressed_size > 0);
+        assert_eq!(header.block_index.original_size, header.block_count * 56);
+
+        // Le filtre de Bloom persisté connaît les blocs de l'image
+        let filter = read_block_filter(&image_path).unwrap().unwrap();
+        assert!(filter.may_contain(&calculate_hash(&[7u8; BLOCK_SIZE])));
+
+        let output_dir = temp_dir.path().join("output");
+        extract_image(&ExtractOptions {
+            image_path,
+            output_path: output_dir.clone(),
+            ..Default::default()
+        }).unwrap();
+
+        assert_eq!(fs::read(output_dir.join("a.txt")).unwrap(), fs::read(input_dir.join("a.txt")).unwrap());
+        assert_eq!(fs::read(output_dir.join("sub/b.bin")).unwrap(), fs::read(input_dir.join("sub/b.bin")).unwrap());
+        assert!(output_dir.join("sub/empty").is_dir());
+    }
+
+    #[test]
+    fn test_hash_algorithm_recorded() {
+        let temp_dir = tempdir().unwrap();
+        let input_dir = temp_dir.path().join("input");
+        fs::create_dir_all(&input_dir).unwrap();
+        fs::write(input_dir.join("a.bin"), vec![1u8; BLOCK_SIZE + 1]).unwrap();
+
+        let image_path = temp_dir.path().join("blake3.zpak");
+        create_image(&ImageOptions {
+            input_path: input_dir.clone(),
+            output_path: image_path.clone(),
+            compression_level: 3,
+            hash_algorithm: HashAlgorithm::Blake3,
+            verify_dedup: true,
+            ..Default::default()
+        }).unwrap();
+
+        let (header, _) = read_header(&mut File::open(&image_path).unwrap()).unwrap();
+        assert_eq!(header.hash_algorithm, Some(HashAlgorithm::Blake3));
+        let filter = read_block_filter(&image_path).unwrap().unwrap();
+        assert!(filter.may_contain(&HashAlgorithm::Blake3.hash(&[1u8; BLOCK_SIZE])));
+
+        let output_dir = temp_dir.path().join("output");
+        extract_image(&ExtractOptions {
+            image_path,
+            output_path: output_dir.clone(),
+            ..Default::default()
+        }).unwrap();
+        assert_eq!(fs::read(output_dir.join("a.bin")).unwrap(), vec![1u8; BLOCK_SIZE + 1]);
+        assert_ne!(HashAlgorithm::Xxh3.hash(b"zippy"), HashAlgorithm::Blake3.hash(b"zippy"));
+    }
+
+    #[test]
+    fn test_signed_manifest_verified_before_extraction() {
+        let temp_dir = tempdir().unwrap();
+        let input_dir = temp_dir.path().join("input");
+        fs::create_dir_all(input_dir.join("docs")).unwrap();
+        fs::write(input_dir.join("docs/a.txt"), b"signed content").unwrap();
+        let key = SigningKey::from_bytes(&[7u8; 32]);
+        let public_key = key.verifying_key().to_bytes();
+
+        let image_path = temp_dir.path().join("signed.zpak");
+        create_image(&ImageOptions {
+            input_path: input_dir.clone(),
+            output_path: image_path.clone(),
+            compression_level: 3,
+            sign_key: Some(key),
+            ..Default::default()
+        }).unwrap();
+        let unsigned_path = temp_dir.path().join("unsigned.zpak");
+        create_image(&ImageOptions {
+            input_path: input_dir.clone(),
+            output_path: unsigned_path.clone(),
+            compression_level: 3,
+            ..Default::default()
+        }).unwrap();
+
+        let output_dir = temp_dir.path().join("output");
+        let report = extract_image(&ExtractOptions {
+            image_path: image_path.clone(),
+            output_path: output_dir.clone(),
+            trusted_key: Some(public_key),
+            ..Default::default()
+        }).unwrap();
+        assert!(report.warnings.is_empty());
+        assert_eq!(fs::read(output_dir.join("docs/a.txt")).unwrap(), b"signed content");
+
+        // Clé inattendue ou image non signée : rien n'est écrit
+        for (image_path, trusted_key) in [(&image_path, [1u8; 32]), (&unsigned_path, public_key)] {
+            let refused_dir = temp_dir.path().join("refused");
+            let result = extract_image(&ExtractOptions {
+                image_path: image_path.clone(),
+                output_path: refused_dir.clone(),
+                trusted_key: Some(trusted_key),
+                ..Default::default()
+            });
+            assert!(matches!(result, Err(ImageError::ManifestVerification(_))));
+            assert!(!refused_dir.join("docs").exists());
+        }
+
+        let forced_dir = temp_dir.path().join("forced");
+        let report = extract_image(&ExtractOptions {
+            image_path: unsigned_path,
+            output_path: forced_dir.clone(),
+            trusted_key: Some(public_key),
+            force: true,
+            ..Default::default()
+        }).unwrap();
+        assert_eq!(report.warnings[0].kind, WarningKind::Unverified);
+        assert_eq!(fs::read(forced_dir.join("docs/a.txt")).unwrap(), b"signed content");
+    }
+
+    #[test]
+    fn test_extract_image_tar() {
+        let temp_dir = tempdir().unwrap();
+        let input_dir = temp_dir.path().join("input");
+        fs::create_dir_all(input_dir.join("docs")).unwrap();
+        fs::write(input_dir.join("docs/a.txt"), b"streamed content").unwrap();
+        let key = SigningKey::from_bytes(&[7u8; 32]);
+        let public_key = key.verifying_key().to_bytes();
+        let image_path = temp_dir.path().join("signed.zpak");
+        create_image(&ImageOptions {
+            input_path: input_dir,
+            output_path: image_path.clone(),
+            compression_level: 3,
+            sign_key: Some(key),
+            ..Default::default()
+        }).unwrap();
+
+        let mut tar = Vec::new();
+        let report = extract_image_tar(&ExtractOptions {
+            image_path: image_path.clone(),
+            trusted_key: Some(public_key),
+            ..Default::default()
+        }, &mut tar).unwrap();
+        assert!(report.is_clean());
+        let mut archive = tar::Archive::new(&tar[..]);
+        let mut contents = HashMap::new();
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data).unwrap();
+            contents.insert(entry.path().unwrap().into_owned(), data);
+        }
+        assert_eq!(contents.len(), 2);
+        assert_eq!(contents[Path::new("docs/a.txt")], b"streamed content");
+
+        // Rien n'est écrit si le manifeste ne vérifie pas
+        let mut refused = Vec::new();
+        let result = extract_image_tar(&ExtractOptions { image_path, trusted_key: Some([1u8; 32]), ..Default::default() }, &mut refused);
+        assert!(matches!(result, Err(ImageError::ManifestVerification(_))));
+        assert!(refused.is_empty());
+    }
+
+    #[test]
+    fn test_priority_extraction() {
+        let temp_dir = tempdir().unwrap();
+        let input_dir = temp_dir.path().join("input");
+        for dir in ["assets", "bin", "config"] {
+            fs::create_dir_all(input_dir.join(dir)).unwrap();
+        }
+        fs::write(input_dir.join("assets/big.dat"), vec![1u8; 10_000]).unwrap();
+        fs::write(input_dir.join("bin/app"), b"binaire").unwrap();
+        fs::write(input_dir.join("config/app.toml"), b"port = 80").unwrap();
+        fs::write(input_dir.join("notes.so"), b"lib").unwrap();
+        let image_path = temp_dir.path().join("app.zpak");
+        create_image(&ImageOptions {
+            input_path: input_dir,
+            output_path: image_path.clone(),
+            compression_level: 3,
+            ..Default::default()
+        }).unwrap();
+
+        // Les motifs sans `/` portent sur le nom du fichier
+        let first = ["config/**", "*.so"].map(|pattern| glob::Pattern::new(pattern).unwrap()).to_vec();
+        let options = ExtractOptions { image_path, first, ..Default::default() };
+        let mut tar = Vec::new();
+        extract_image_tar(&options, &mut tar).unwrap();
+        let mut archive = tar::Archive::new(&tar[..]);
+        let paths: Vec<PathBuf> = archive.entries().unwrap().map(|entry| entry.unwrap().path().unwrap().into_owned()).collect();
+        assert_eq!(paths[..2], [PathBuf::from("config/app.toml"), PathBuf::from("notes.so")]);
+        assert_eq!(paths.len(), 7);
+
+        let output_dir = temp_dir.path().join("output");
+        extract_image(&ExtractOptions { output_path: output_dir.clone(), ..options }).unwrap();
+        assert_eq!(fs::read(output_dir.join("config/app.toml")).unwrap(), b"port = 80");
+        assert_eq!(fs::read(output_dir.join("assets/big.dat")).unwrap(), vec![1u8; 10_000]);
+    }
+
+    #[test]
+    fn test_prefix_strip_components() {
+        let temp_dir = tempdir().unwrap();
+        let input_dir = temp_dir.path().join("input");
+        fs::create_dir_all(input_dir.join("app-1.0/bin")).unwrap();
+        fs::create_dir_all(input_dir.join("app-1.0/doc")).unwrap();
+        fs::write(input_dir.join("app-1.0/bin/run"), b"#!/bin/sh").unwrap();
+        fs::write(input_dir.join("app-1.0/doc/README"), b"lisez-moi").unwrap();
+        fs::write(input_dir.join("top.txt"), b"hors du dossier").unwrap();
+        let image_path = temp_dir.path().join("release.zpak");
+        create_image(&ImageOptions {
+            input_path: input_dir,
+            output_path: image_path.clone(),
+            compression_level: 3,
+            ..Default::default()
+        }).unwrap();
+
+        let output_dir = temp_dir.path().join("output");
+        extract_image(&ExtractOptions {
+            image_path: image_path.clone(),
+            output_path: output_dir.clone(),
+            prefix: Some(PathBuf::from("./app-1.0/bin")),
+            strip_components: 1,
+            ..Default::default()
+        }).unwrap();
+        assert_eq!(fs::read(output_dir.join("bin/run")).unwrap(), b"#!/bin/sh");
+        assert!(!output_dir.join("doc").exists());
+        assert!(!output_dir.join("app-1.0").exists());
+
+        // Les entrées du premier niveau n'ont plus de nom : elles sont écartées
+        let mut tar = Vec::new();
+        extract_image_tar(&ExtractOptions { image_path, strip_components: 1, ..Default::default() }, &mut tar).unwrap();
+        let mut archive = tar::Archive::new(&tar[..]);
+        let mut paths: Vec<PathBuf> = archive.entries().unwrap().map(|entry| entry.unwrap().path().unwrap().into_owned()).collect();
+        paths.sort();
+        assert_eq!(paths, ["bin", "bin/run", "doc", "doc/README"].map(PathBuf::from));
+    }
+
+    #[test]
+    fn test_add_to_image() {
+        let temp_dir = tempdir().unwrap();
+        let input_dir = temp_dir.path().join("input");
+        fs::create_dir_all(&input_dir).unwrap();
+        let content: Vec<u8> = (0..BLOCK_SIZE as u32 * 2).map(|i| (i.wrapping_mul(2_654_435_761) >> 11) as u8).collect();
+        fs::write(input_dir.join("base.bin"), &content).unwrap();
+        let image_path = temp_dir.path().join("grow.zpak");
+        create_image(&ImageOptions {
+            input_path: input_dir,
+            output_path: image_path.clone(),
+            compression_level: 3,
+            ..Default::default()
+        }).unwrap();
+        let blocks_before = open_image(&image_path, false, false).unwrap().header.block_count;
+
+        let new_dir = temp_dir.path().join("newdir");
+        fs::create_dir_all(new_dir.join("sub")).unwrap();
+        fs::write(new_dir.join("sub/neuf.txt"), b"contenu ajout\xc3\xa9").unwrap();
+        fs::write(new_dir.join("copie.bin"), &content).unwrap();
+        add_to_image(&AddOptions {
+            image_path: image_path.clone(),
+            input_path: new_dir.clone(),
+            dest: Some(PathBuf::from("extra/newdir")),
+            compression_level: 3,
+            ..Default::default()
+        }).unwrap();
+        // Seul le petit fichier apporte un bloc, la copie est dédupliquée
+        let header = open_image(&image_path, false, false).unwrap().header;
+        assert_eq!(header.block_count, blocks_before + 1);
+        assert_eq!(header.appended.count, 1);
+        assert_eq!(header.total_files, 3);
+
+        // Un second ajout remplace un fichier et garde les blocs du premier
+        fs::write(new_dir.join("sub/neuf.txt"), b"remplac\xc3\xa9").unwrap();
+        add_to_image(&AddOptions {
+            image_path: image_path.clone(),
+            input_path: new_dir.join("sub"),
+            dest: Some(PathBuf::from("extra/newdir/sub")),
+            compression_level: 3,
+            ..Default::default()
+        }).unwrap();
+        assert_eq!(open_image(&image_path, false, false).unwrap().header.appended.count, 2);
+
+        let output_dir = temp_dir.path().join("output");
+        extract_image(&ExtractOptions { image_path, output_path: output_dir.clone(), ..Default::default() }).unwrap();
+        assert_eq!(fs::read(output_dir.join("base.bin")).unwrap(), content);
+        assert_eq!(fs::read(output_dir.join("extra/newdir/copie.bin")).unwrap(), content);
+        assert_eq!(fs::read(output_dir.join("extra/newdir/sub/neuf.txt")).unwrap(), "remplacé".as_bytes());
+    }
+
+    #[test]
+    fn test_repack_image() {
+        let temp_dir = tempdir().unwrap();
+        let input_dir = temp_dir.path().join("input");
+        fs::create_dir_all(&input_dir).unwrap();
+        let content: Vec<u8> = (0..BLOCK_SIZE as u32 * 3).map(|i| (i.wrapping_mul(2_654_435_761) >> 11) as u8).collect();
+        fs::write(input_dir.join("data.bin"), &content).unwrap();
+        fs::write(input_dir.join("notes.txt"), b"version 1").unwrap();
+        let image_path = temp_dir.path().join("repack.zpak");
+        create_image(&ImageOptions {
+            input_path: input_dir.clone(),
+            output_path: image_path.clone(),
+            compression_level: 3,
+            ..Default::default()
+        }).unwrap();
+        let created = open_image(&image_path, false, false).unwrap().header.created;
+
+        // Le fichier remplacé laisse ses blocs sans référence
+        let replacement: Vec<u8> = content.iter().map(|byte| byte ^ 0x5a).collect();
+        fs::write(input_dir.join("data.bin"), &replacement).unwrap();
+        add_to_image(&AddOptions {
+            image_path: image_path.clone(),
+            input_path: input_dir.join("data.bin"),
+            dest: Some(PathBuf::from("data.bin")),
+            compression_level: 3,
+            ..Default::default()
+        }).unwrap();
+
+        let summary = repack_image(&RepackOptions { image_path: image_path.clone(), ..Default::default() }).unwrap();
+        assert_eq!((summary.blocks_before, summary.blocks_after), (7, 4));
+        assert!(summary.reclaimed() > 0);
+        let header = open_image(&image_path, false, false).unwrap().header;
+        assert_eq!(header.appended.count, 0);
+        assert!(header.created > created);
+
+        // Nouveau découpage et nouveau niveau, vers une autre image
+        let rechunked_path = temp_dir.path().join("rechunked.zpak");
+        let rechunk = ChunkerOptions::around(ChunkStrategy::Cdc, 16384);
+        repack_image(&RepackOptions {
+            image_path: image_path.clone(),
+            output_path: Some(rechunked_path.clone()),
+            rechunk: Some(rechunk),
+            compression_level: Some(5),
+            ..Default::default()
+        }).unwrap();
+        assert_eq!(open_image(&rechunked_path, false, false).unwrap().header.chunker, rechunk);
+
+        for (i, path) in [image_path, rechunked_path].into_iter().enumerate() {
+            let output_dir = temp_dir.path().join(format!("output{}", i));
+            extract_image(&ExtractOptions { image_path: path, output_path: output_dir.clone(), ..Default::default() }).unwrap();
+            assert_eq!(fs::read(output_dir.join("data.bin")).unwrap(), replacement);
+            assert_eq!(fs::read(output_dir.join("notes.txt")).unwrap(), b"version 1");
+        }
+    }
+
+    #[test]
+    fn test_write_checksums() {
+        let temp_dir = tempdir().unwrap();
+        let input_dir = temp_dir.path().join("input");
+        fs::create_dir_all(input_dir.join("docs")).unwrap();
+        let content = vec![5u8; BLOCK_SIZE * 2 + 10];
+        fs::write(input_dir.join("docs/a.bin"), &content).unwrap();
+        fs::write(input_dir.join("empty"), b"").unwrap();
+
+        let image_path = temp_dir.path().join("sums.zpak");
+        create_image(&ImageOptions {
+            input_path: input_dir.clone(),
+            output_path: image_path.clone(),
+            compression_level: 3,
+            ..Default::default()
+        }).unwrap();
+
+        let mut sums = Vec::new();
+        assert_eq!(write_checksums(&image_path, &mut sums).unwrap(), 2);
+        let sums = String::from_utf8(sums).unwrap();
+        assert!(sums.contains(&checksum_line(&Sha256::digest(&content), Path::new("docs/a.bin"))));
+        assert!(sums.contains("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855  empty\n"));
+        assert!(checksum_line(&[0xab], Path::new("a\nb")).starts_with("\\ab  a\\nb"));
+    }
+
+    #[test]
+    fn test_list_image() {
+        let temp_dir = tempdir().unwrap();
+        let input_dir = temp_dir.path().join("input");
+        fs::create_dir_all(input_dir.join("docs")).unwrap();
+        fs::write(input_dir.join("docs/a.bin"), vec![3u8; BLOCK_SIZE * 2]).unwrap();
+        fs::write(input_dir.join("b.bin"), vec![3u8; BLOCK_SIZE]).unwrap();
+
+        let image_path = temp_dir.path().join("list.zpak");
+        create_image(&ImageOptions {
+            input_path: input_dir.clone(),
+            output_path: image_path.clone(),
+            compression_level: 3,
+            ..Default::default()
+        }).unwrap();
+
+        let listing = list_image(&image_path).unwrap();
+        assert_eq!(listing.format, ContainerFormat::Image);
+        assert_eq!(listing.entries.len(), 3);
+        assert_eq!(listing.total_size(), BLOCK_SIZE as u64 * 3);
+        let file = listing.entries.iter().find(|entry| entry.path == Path::new("docs/a.bin")).unwrap();
+        assert_eq!(file.blocks.as_ref().map(Vec::len), Some(2));
+        assert!(file.modified.is_some());
+        // Un seul bloc stocké, compté pour chaque entrée qui l'utilise
+        assert_eq!(file.compressed_size, listing.stored_size.map(|size| size * 2));
+        let directory = listing.entries.iter().find(|entry| entry.kind == EntryKind::Directory).unwrap();
+        assert!(directory.modified.is_some());
+    }
+
+    #[test]
+    fn test_small_blocks_stored() {
+        let temp_dir = tempdir().unwrap();
+        let input_dir = temp_dir.path().join("input");
+        fs::create_dir_all(&input_dir).unwrap();
+        fs::write(input_dir.join("tiny.txt"), b"quelques octets").unwrap();
+        fs::write(input_dir.join("large.bin"), vec![5u8; 1000]).unwrap();
+
+        for store_threshold in [0, DEFAULT_STORE_THRESHOLD] {
+            let image_path = temp_dir.path().join(format!("small-{store_threshold}.zpak"));
+            create_image(&ImageOptions {
+                input_path: input_dir.clone(),
+                output_path: image_path.clone(),
+                compression_level: 3,
+                store_threshold,
+                ..Default::default()
+            }).unwrap();
+
+            let listing = list_image(&image_path).unwrap();
+            let entry = |name: &str| listing.entries.iter().find(|entry| entry.path == Path::new(name)).unwrap();
+            // Une frame zstd est plus grande que ces 15 octets
+            assert_eq!(entry("tiny.txt").compressed_size == Some(15), store_threshold > 0);
+            assert!(entry("large.bin").compressed_size.unwrap() < 100);
+
+            let output_dir = temp_dir.path().join(format!("output-{store_threshold}"));
+            extract_image(&ExtractOptions { image_path, output_path: output_dir.clone(), ..Default::default() }).unwrap();
+            assert_eq!(fs::read(output_dir.join("tiny.txt")).unwrap(), b"quelques octets");
+        }
+    }
+
+    #[test]
+    fn test_verify_dedup_detects_collisions() {
+        let mut claims = BlockClaims {
+            filter: BloomFilter::with_capacity(16, DEFAULT_FALSE_POSITIVE_RATE),
+            claimed: HashMap::new(),
+        };
+        let hash = calculate_hash(b"bloc");
+        let check = |data: &[u8]| Some(HashAlgorithm::Blake3.hash(data));
+
+        assert!(claims.claim(&hash, check(b"bloc")).unwrap());
+        assert!(!claims.claim(&hash, check(b"bloc")).unwrap());
+        // Même hash principal, contenu différent
+        assert!(matches!(claims.claim(&hash, check(b"autre")), Err(ImageError::HashCollision(_))));
+        // Sans vérification, le doublon est accepté tel quel
+        assert!(!claims.claim(&hash, None).unwrap());
+    }
+
+    #[test]
+    fn test_dedup_against_base_image() {
+        let temp_dir = tempdir().unwrap();
+        let base_dir = temp_dir.path().join("base");
+        fs::create_dir_all(&base_dir).unwrap();
+        let shared: Vec<u8> = (0..BLOCK_SIZE * 3).map(|i| (i % 251) as u8).collect();
+        fs::write(base_dir.join("os.bin"), &shared).unwrap();
+        let app_dir = temp_dir.path().join("app");
+        fs::create_dir_all(&app_dir).unwrap();
+        fs::write(app_dir.join("os.bin"), &shared).unwrap();
+        fs::write(app_dir.join("app.txt"), "application").unwrap();
+
+        let family = temp_dir.path().join("family");
+        fs::create_dir_all(&family).unwrap();
+        let image = |input: &Path, output: &Path, dedup_against: Vec<PathBuf>, hash_algorithm| create_image(&ImageOptions {
+            input_path: input.to_path_buf(),
+            output_path: output.to_path_buf(),
+            compression_level: 3,
+            hash_algorithm,
+            dedup_against,
+            ..Default::default()
+        });
+        image(&base_dir, &family.join("base.zpak"), Vec::new(), HashAlgorithm::Xxh3).unwrap();
+        image(&app_dir, &family.join("app.zpak"), vec![family.join("base.zpak")], HashAlgorithm::Xxh3).unwrap();
+
+        // Seul le bloc propre à l'application est stocké
+        let (header, _) = read_header(&mut File::open(family.join("app.zpak")).unwrap()).unwrap();
+        assert_eq!(header.block_count, 1);
+        assert_eq!(header.external_images.len(), 1);
+
+        // Les images déplacées ensemble restent extractibles
+        let moved = temp_dir.path().join("moved");
+        fs::rename(&family, &moved).unwrap();
+        let output_dir = temp_dir.path().join("output");
+        extract_image(&ExtractOptions {
+            image_path: moved.join("app.zpak"),
+            output_path: output_dir.clone(),
+            ..Default::default()
+        }).unwrap();
+        assert_eq!(fs::read(output_dir.join("os.bin")).unwrap(), shared);
+        assert_eq!(fs::read_to_string(output_dir.join("app.txt")).unwrap(), "application");
+
+        // Une base sans ses blocs d'origine est refusée
+        fs::remove_file(moved.join("base.zpak")).unwrap();
+        let result = extract_image(&ExtractOptions {
+            image_path: moved.join("app.zpak"),
+            output_path: temp_dir.path().join("output2"),
+            ..Default::default()
+        });
+        assert!(matches!(result, Err(ImageError::ExternalImage { .. })));
+
+        // Les hashes doivent être comparables
+        image(&base_dir, &moved.join("base.zpak"), Vec::new(), HashAlgorithm::Blake3).unwrap();
+        let result = image(&app_dir, &moved.join("app2.zpak"), vec![moved.join("base.zpak")], HashAlgorithm::Xxh3);
+        assert!(matches!(result, Err(ImageError::ExternalImage { .. })));
+    }
+
+    #[test]
+    fn test_differential_image_chain() {
+        let temp_dir = tempdir().unwrap();
+        let input_dir = temp_dir.path().join("input");
+        fs::create_dir_all(input_dir.join("sub")).unwrap();
+        let large: Vec<u8> = (0..BLOCK_SIZE * 3).map(|i| (i % 251) as u8).collect();
+        fs::write(input_dir.join("large.bin"), &large).unwrap();
+        fs::write(input_dir.join("edited.txt"), "v1").unwrap();
+        fs::write(input_dir.join("sub/removed.txt"), "gone soon").unwrap();
+        let image = |output: &Path, base: Option<PathBuf>| create_image(&ImageOptions {
+            input_path: input_dir.clone(),
+            output_path: output.to_path_buf(),
+            compression_level: 3,
+            base,
+            ..Default::default()
+        }).unwrap();
+        let full = temp_dir.path().join("full.zpak");
+        image(&full, None);
+
+        fs::write(input_dir.join("edited.txt"), "v2, longer").unwrap();
+        fs::remove_dir_all(input_dir.join("sub")).unwrap();
+        let delta1 = temp_dir.path().join("delta1.zpak");
+        image(&delta1, Some(full.clone()));
+        fs::write(input_dir.join("added.txt"), "new").unwrap();
+        let delta2 = temp_dir.path().join("delta2.zpak");
+        image(&delta2, Some(delta1.clone()));
+
+        // Seules les entrées modifiées sont enregistrées, sans les blocs de la base
+        let (header, _) = read_header(&mut File::open(&delta1).unwrap()).unwrap();
+        assert_eq!(header.base, Some(0));
+        assert_eq!(header.block_count, 1);
+        let (entries, deletions) = read_layer(&delta1).unwrap();
+        assert!(entries.iter().all(|entry| entry.path != Path::new("large.bin")));
+        assert!(entries.iter().any(|entry| entry.path == Path::new("edited.txt")));
+        assert_eq!(deletions, [PathBuf::from("sub"), PathBuf::from("sub/removed.txt")]);
+        let (header, _) = read_header(&mut File::open(&delta2).unwrap()).unwrap();
+        assert_eq!((header.external_images.len(), header.base), (2, Some(1)));
+
+        // La dernière image restitue l'état complet de la chaîne
+        let output_dir = temp_dir.path().join("output");
+        extract_image(&ExtractOptions { image_path: delta2.clone(), output_path: output_dir.clone(), ..Default::default() }).unwrap();
+        assert_eq!(fs::read(output_dir.join("large.bin")).unwrap(), large);
+        assert_eq!(fs::read_to_string(output_dir.join("edited.txt")).unwrap(), "v2, longer");
+        assert_eq!(fs::read_to_string(output_dir.join("added.txt")).unwrap(), "new");
+        assert!(!output_dir.join("sub").exists());
+        let mut tar = Vec::new();
+        extract_image_tar(&ExtractOptions { image_path: delta2.clone(), ..Default::default() }, &mut tar).unwrap();
+        assert_eq!(tar::Archive::new(&tar[..]).entries().unwrap().count(), 3);
+
+        // Même résultat en superposant explicitement les couches ; une image différentielle
+        // ne peut suivre qu'une image qui est sa base
+        let layered_dir = temp_dir.path().join("layered");
+        let layered = |overlays: Vec<PathBuf>, output_path: PathBuf| extract_image(&ExtractOptions {
+            image_path: full.clone(),
+            output_path,
+            overlays,
+            ..Default::default()
+        });
+        layered(vec![delta1.clone(), delta2.clone()], layered_dir.clone()).unwrap();
+        assert_eq!(fs::read(layered_dir.join("large.bin")).unwrap(), large);
+        assert_eq!(fs::read_to_string(layered_dir.join("edited.txt")).unwrap(), "v2, longer");
+        assert_eq!(fs::read_to_string(layered_dir.join("added.txt")).unwrap(), "new");
+        assert!(!layered_dir.join("sub").exists());
+        let result = layered(vec![delta2.clone()], temp_dir.path().join("skipped"));
+        assert!(matches!(result, Err(ImageError::ExternalImage { .. })));
+
+        // Sans sa base, une image différentielle ne s'extrait pas
+        fs::remove_file(&full).unwrap();
+        let result = extract_image(&ExtractOptions { image_path: delta1, output_path: temp_dir.path().join("output2"), ..Default::default() });
+        assert!(matches!(result, Err(ImageError::ExternalImage { .. })));
+    }
+
+    #[test]
+    fn test_verify_write() {
+        let temp_dir = tempdir().unwrap();
+        let input_dir = temp_dir.path().join("input");
+        fs::create_dir_all(&input_dir).unwrap();
+        let content: Vec<u8> = (0..BLOCK_SIZE * 2).map(|i| (i % 251) as u8).collect();
+        fs::write(input_dir.join("data.bin"), &content).unwrap();
+        let image_path = temp_dir.path().join("verified.zpak");
+        create_image(&ImageOptions {
+            input_path: input_dir,
+            output_path: image_path.clone(),
+            compression_level: 3,
+            verify_write: true,
+            // Blocs relus depuis le fichier de débordement
+            spill: SpillOptions { memory_limit: 1000, ..Default::default() },
+            ..Default::default()
+        }).unwrap();
+        
+        // Un octet altéré dans les données d'un bloc est détecté à la relecture
+        let (entries, _) = read_layer(&image_path).unwrap();
+        let hashes = &entries.iter().find(|entry| entry.kind == EntryKind::File).unwrap().blocks;
+        verify_written(&image_path, hashes.iter()).unwrap();
+        let (offset, _, _, _) = open_image(&image_path, false, false).unwrap().block_index.get(&hashes[0]).unwrap();
+        let mut bytes = fs::read(&image_path).unwrap();
+        bytes[offset as usize + 10] ^= 0xff;
+        fs::write(&image_path, bytes).unwrap();
+        let result = verify_written(&image_path, hashes.iter());
+        assert!(matches!(result, Err(ImageError::WriteVerification { .. })));
+    }
+
+    #[test]
+    fn test_content_defined_chunking() {
+        let temp_dir = tempdir().unwrap();
+        let input_dir = temp_dir.path().join("input");
+        fs::create_dir_all(&input_dir).unwrap();
+        let content: Vec<u8> = (0..300_000u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8).collect();
+        fs::write(input_dir.join("a.bin"), &content).unwrap();
+        // Même contenu décalé : les blocs se resynchronisent
+        fs::write(input_dir.join("b.bin"), [b"en-tete".as_slice(), &content].concat()).unwrap();
+
+        let image_path = temp_dir.path().join("cdc.zpak");
+        let chunker = ChunkerOptions::around(ChunkStrategy::Cdc, 8192);
+        create_image(&ImageOptions {
+            input_path: input_dir.clone(),
+            output_path: image_path.clone(),
+            compression_level: 3,
+            chunker,
+            ..Default::default()
+        }).unwrap();
+
+        let (header, _) = read_header(&mut File::open(&image_path).unwrap()).unwrap();
+        assert_eq!(header.chunker, chunker);
+        let blocks_per_file = 300_000 / 8192;
+        assert!(header.block_count < blocks_per_file * 3 / 2, "{} blocs", header.block_count);
+
+        let output_dir = temp_dir.path().join("output");
+        extract_image(&ExtractOptions {
+            image_path,
+            output_path: output_dir.clone(),
+            ..Default::default()
+        }).unwrap();
+        assert_eq!(fs::read(output_dir.join("a.bin")).unwrap(), content);
+        assert_eq!(fs::read(output_dir.join("b.bin")).unwrap(), fs::read(input_dir.join("b.bin")).unwrap());
+    }
+
+    #[test]
+    fn test_coalesce_reads() {
+        let ranges = coalesce_reads(vec![
+            (10_000, 100),
+            (0, 1_000),
+            (1_000, 500),
+            (0, 1_000),
+            (5_000_000, 10),
+            (10_100 + MAX_READ_GAP + 1, 20),
+        ]);
+        let spans: Vec<_> = ranges.iter().map(|range| (range.offset, range.length, range.blocks.len())).collect();
+        assert_eq!(spans, [(0, 10_100, 3), (10_100 + MAX_READ_GAP + 1, 20, 1), (5_000_000, 10, 1)]);
+    }
+
+    #[test]
+    fn test_read_file() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("data.bin");
+        fs::write(&path, vec![5u8; BLOCK_SIZE + 1]).unwrap();
+        let data = read_file(&path, BLOCK_SIZE as u64 + 1).unwrap();
+        assert!(matches!(data, FileData::Mapped(_)));
+        assert_eq!(&data[..], &vec![5u8; BLOCK_SIZE + 1][..]);
+
+        let empty = temp_dir.path().join("empty");
+        fs::write(&empty, b"").unwrap();
+        assert!(read_file(&empty, 0).unwrap().is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_extraction_dry_run() {
+        use crate::report::PlannedAction;
+        
+        let temp_dir = tempdir().unwrap();
+        let input_dir = temp_dir.path().join("input");
+        fs::create_dir_all(input_dir.join("sub")).unwrap();
+        fs::write(input_dir.join("sub/a.txt"), "contenu").unwrap();
+        std::os::unix::fs::symlink("sub/a.txt", input_dir.join("lien")).unwrap();
+
+        let image_path = temp_dir.path().join("test.zpak");
+        create_image(&ImageOptions {
+            input_path: input_dir,
+            output_path: image_path.clone(),
+            compression_level: 3,
+            ..Default::default()
+        }).unwrap();
+
+        let output_dir = temp_dir.path().join("output");
+        fs::create_dir_all(output_dir.join("sub")).unwrap();
+        fs::write(output_dir.join("lien"), "fichier existant").unwrap();
+        let report = extract_image(&ExtractOptions {
+            image_path,
+            output_path: output_dir.clone(),
+            dry_run: true,
+            ..Default::default()
+        }).unwrap();
+
+        let action = |name: &str| report.planned.iter().find(|entry| entry.path == output_dir.join(name)).unwrap().action;
+        assert_eq!(action("sub"), PlannedAction::Keep);
+        assert_eq!(action("sub/a.txt"), PlannedAction::Create);
+        assert_eq!(action("lien"), PlannedAction::Replace);
+        assert!(!output_dir.join("sub/a.txt").exists());
+        assert_eq!(fs::read(output_dir.join("lien")).unwrap(), b"fichier existant");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinks_recorded_as_entries() {
+        let temp_dir = tempdir().unwrap();
+        let input_dir = temp_dir.path().join("input");
+        fs::create_dir_all(&input_dir).unwrap();
+        fs::write(input_dir.join("target.txt"), b"cible").unwrap();
+        std::os::unix::fs::symlink("target.txt", input_dir.join("link.txt")).unwrap();
+
+        let image_path = temp_dir.path().join("test.zpak");
+        create_image(&ImageOptions {
+            input_path: input_dir,
+            output_path: image_path.clone(),
+            compression_level: 3,
+            ..Default::default()
+        }).unwrap();
+
+        let output_dir = temp_dir.path().join("output");
+        extract_image(&ExtractOptions {
+            image_path,
+            output_path: output_dir.clone(),
+            ..Default::default()
+        }).unwrap();
+
+        let link = output_dir.join("link.txt");
+        assert!(link.symlink_metadata().unwrap().file_type().is_symlink());
+        assert_eq!(fs::read_link(&link).unwrap(), PathBuf::from("target.txt"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_hardlink_extraction() {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let temp_dir = tempdir().unwrap();
+        let input_dir = temp_dir.path().join("input");
+        fs::create_dir_all(input_dir.join("copy")).unwrap();
+        let content = vec![9u8; BLOCK_SIZE + 100];
+        fs::write(input_dir.join("original.bin"), &content).unwrap();
+        fs::write(input_dir.join("copy/duplicate.bin"), &content).unwrap();
+        fs::write(input_dir.join("different.bin"), b"autre").unwrap();
+        // Même contenu mais d'autres permissions : un lien physique les écraserait
+        fs::write(input_dir.join("private.bin"), &content).unwrap();
+        fs::set_permissions(input_dir.join("private.bin"), fs::Permissions::from_mode(0o600)).unwrap();
+        fs::set_permissions(input_dir.join("original.bin"), fs::Permissions::from_mode(0o644)).unwrap();
+        fs::set_permissions(input_dir.join("copy/duplicate.bin"), fs::Permissions::from_mode(0o644)).unwrap();
+        let modified = UNIX_EPOCH + Duration::from_secs(1_400_000_000);
+        for name in ["original.bin", "copy/duplicate.bin", "private.bin"] {
+            File::options().write(true).open(input_dir.join(name)).unwrap().set_modified(modified).unwrap();
+        }
+
+        let image_path = temp_dir.path().join("test.zpak");
+        create_image(&ImageOptions {
+            input_path: input_dir,
+            output_path: image_path.clone(),
+            compression_level: 3,
+            ..Default::default()
+        }).unwrap();
+
+        let output_dir = temp_dir.path().join("output");
+        extract_image(&ExtractOptions {
+            image_path,
+            output_path: output_dir.clone(),
+            dedup: ExtractDedup::Hardlink,
+            ..Default::default()
+        }).unwrap();
+
+        let original = fs::metadata(output_dir.join("original.bin")).unwrap();
+        let duplicate = fs::metadata(output_dir.join("copy/duplicate.bin")).unwrap();
+        assert_eq!(original.ino(), duplicate.ino());
+        assert_eq!(original.nlink(), 2);
+        assert_eq!(fs::metadata(output_dir.join("different.bin")).unwrap().nlink(), 1);
+        assert_eq!(fs::read(output_dir.join("copy/duplicate.bin")).unwrap(), content);
+        let private = fs::metadata(output_dir.join("private.bin")).unwrap();
+        assert_ne!(private.ino(), original.ino());
+        assert_eq!((private.mode() & 0o777, original.mode() & 0o777), (0o600, 0o644));
+    }
+
+    #[test]
+    fn test_reflink_extraction_falls_back_to_copy() {
+        let temp_dir = tempdir().unwrap();
+        let input_dir = temp_dir.path().join("input");
+        fs::create_dir_all(&input_dir).unwrap();
+        let content = vec![3u8; BLOCK_SIZE * 3];
+        fs::write(input_dir.join("one.bin"), &content).unwrap();
+        fs::write(input_dir.join("two.bin"), &content).unwrap();
+
+        let image_path = temp_dir.path().join("test.zpak");
+        create_image(&ImageOptions {
+            input_path: input_dir,
+            output_path: image_path.clone(),
+            compression_level: 3,
+            ..Default::default()
+        }).unwrap();
+
+        // Qu'il y ait reflink ou copie, le contenu doit être identique
+        let output_dir = temp_dir.path().join("output");
+        extract_image(&ExtractOptions {
+            image_path,
+            output_path: output_dir.clone(),
+            dedup: ExtractDedup::Reflink,
+            ..Default::default()
+        }).unwrap();
+
+        assert_eq!(fs::read(output_dir.join("one.bin")).unwrap(), content);
+        assert_eq!(fs::read(output_dir.join("two.bin")).unwrap(), content);
+    }
+}
@@ -0,0 +1,174 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use crate::error::{CompressionError, PathIoError};
+use crate::format::{native_path_encoding, read_path, write_path};
+
+const SNAPSHOT_MAGIC: [u8; 8] = *b"ZPPSNAR1";
+
+/// What is remembered about a file between incremental runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileState {
+    pub size: u64,
+    /// Modification time in nanoseconds since the Unix epoch
+    pub mtime: u64,
+    /// Inode number (0 on platforms without inodes)
+    pub inode: u64,
+}
+
+impl FileState {
+    pub fn from_metadata(metadata: &fs::Metadata) -> Self {
+        let mtime = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+
+        #[cfg(unix)]
+        let inode = std::os::unix::fs::MetadataExt::ino(metadata);
+        #[cfg(not(unix))]
+        let inode = 0;
+
+        Self { size: metadata.len(), mtime, inode }
+    }
+}
+
+/// Per-file state recorded by a `--listed-incremental` run
+#[derive(Debug, Default)]
+pub struct SnapshotState {
+    entries: HashMap<PathBuf, FileState>,
+}
+
+impl SnapshotState {
+    /// Load a state file, or start from an empty state (level 0 archive) if it does not exist
+    pub fn load_or_default(path: &Path) -> Result<Self, CompressionError> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let mut reader = BufReader::new(File::open(path).map_err(|e| CompressionError::io_at(e, path))?);
+
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if magic != SNAPSHOT_MAGIC {
+            return Err(CompressionError::InvalidSnapshot { path: path.to_path_buf() });
+        }
+
+        let mut buffer = [0u8; 8];
+        reader.read_exact(&mut buffer)?;
+        let count = u64::from_le_bytes(buffer);
+
+        let mut entries = HashMap::new();
+        for _ in 0..count {
+            let entry_path = read_path(&mut reader, native_path_encoding())?;
+
+            let mut fields = [0u64; 3];
+            for field in &mut fields {
+                reader.read_exact(&mut buffer)?;
+                *field = u64::from_le_bytes(buffer);
+            }
+
+            entries.insert(entry_path, FileState { size: fields[0], mtime: fields[1], inode: fields[2] });
+        }
+
+        Ok(Self { entries })
+    }
+
+    /// Write the state atomically (temporary file then rename)
+    pub fn save(&self, path: &Path) -> Result<(), CompressionError> {
+        let temp_path = path.with_extension("tmp");
+        {
+            let mut writer = BufWriter::new(
+                File::create(&temp_path).map_err(|e| CompressionError::io_at(e, &temp_path))?,
+            );
+            writer.write_all(&SNAPSHOT_MAGIC)?;
+            writer.write_all(&(self.entries.len() as u64).to_le_bytes())?;
+            for (entry_path, state) in &self.entries {
+                write_path(&mut writer, entry_path)?;
+                writer.write_all(&state.size.to_le_bytes())?;
+                writer.write_all(&state.mtime.to_le_bytes())?;
+                writer.write_all(&state.inode.to_le_bytes())?;
+            }
+            writer.flush()?;
+        }
+        fs::rename(&temp_path, path).map_err(|e| CompressionError::io_at(e, path))?;
+        Ok(())
+    }
+
+    /// Whether `path` is new or differs from the previously recorded state
+    pub fn has_changed(&self, path: &Path, state: &FileState) -> bool {
+        self.entries.get(path) != Some(state)
+    }
+
+    pub fn record(&mut self, path: PathBuf, state: FileState) {
+        self.entries.insert(path, state);
+    }
+
+    pub fn forget(&mut self, path: &Path) {
+        self.entries.remove(path);
+    }
+
+    /// Recorded paths, in no particular order
+    pub fn paths(&self) -> impl Iterator<Item = &Path> {
+        self.entries.keys().map(PathBuf::as_path)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// State of a `--listed-incremental` run not saved yet: committing it before the
+/// archive is stored for good would leave the run's changes out of the next level
+#[derive(Debug)]
+pub struct PendingSnapshot {
+    path: PathBuf,
+    state: SnapshotState,
+}
+
+impl PendingSnapshot {
+    pub fn new(path: PathBuf, state: SnapshotState) -> Self {
+        Self { path, state }
+    }
+
+    /// Save the state to its file
+    pub fn commit(self) -> Result<(), CompressionError> {
+        self.state.save(&self.path)?;
+        info!(path = %self.path.display(), "Incremental state saved");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_snapshot_roundtrip() {
+        let temp_dir = tempdir().unwrap();
+        let snar = temp_dir.path().join("state.snar");
+
+        let state = SnapshotState::load_or_default(&snar).unwrap();
+        assert!(state.is_empty());
+
+        let mut state = SnapshotState::default();
+        let file_state = FileState { size: 10, mtime: 1234, inode: 42 };
+        state.record(PathBuf::from("dir/file.txt"), file_state);
+        state.save(&snar).unwrap();
+
+        let loaded = SnapshotState::load_or_default(&snar).unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert!(!loaded.has_changed(Path::new("dir/file.txt"), &file_state));
+        assert!(loaded.has_changed(Path::new("dir/file.txt"), &FileState { size: 11, ..file_state }));
+        assert!(loaded.has_changed(Path::new("other.txt"), &file_state));
+    }
+}
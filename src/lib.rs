@@ -1,22 +1,73 @@
-/*!
- * ZippyPack - Bibliothèque de compression
- *
- * Créé par : Kamion (Matthéo Le Fur)
- * Date : 26/06/2025
- * Modifié le : 14/07/2025
- *
- * Description : Bibliothèque principale exposant les modules de compression,
- * décompression et système d'images de ZippyPack
- * 
- * Version : 1.0.0
- */
-
-pub mod compress;
-pub mod profile;
-pub mod error;
-pub mod decompress;
-pub mod image;
-pub mod config;
-pub mod metrics;
-
+/*!
+ * ZippyPack - Bibliothèque de compression
+ *
+ * Créé par : Kamion (Matthéo Le Fur)
+ * Date : 26/06/2025
+ * Modifié le : 14/07/2025
+ *
+ * Description : Bibliothèque principale exposant les modules de compression,
+ * décompression et système d'images de ZippyPack
+ * 
+ * Version : 1.0.0
+ */
+
+pub mod compress;
+pub mod profile;
+pub mod error;
+pub mod decompress;
+pub mod image;
+pub mod config;
+pub mod metrics;
+pub mod walk;
+pub mod units;
+pub mod format;
+pub mod frames;
+pub mod recompress;
+pub mod incremental;
+pub mod platform;
+pub mod paths;
+pub mod owners;
+pub mod report;
+pub mod bench;
+pub mod testdata;
+pub mod estimate;
+pub mod dupes;
+pub mod analyze;
+pub mod blockio;
+pub mod directio;
+pub mod blockindex;
+pub mod bloom;
+pub mod chunker;
+pub mod pipeline;
+pub mod signing;
+pub mod list;
+pub mod logfile;
+pub mod daemon;
+pub mod device;
+pub mod oci;
+pub mod squashfs;
+pub mod spill;
+pub mod storage;
+pub mod cache;
+pub mod sync;
+pub mod upload;
+pub mod throttle;
+pub mod nbd;
+#[cfg(any(feature = "azure", feature = "gcs", feature = "b2", feature = "webdav"))]
+mod http;
+#[cfg(feature = "azure")]
+pub mod azure;
+#[cfg(feature = "gcs")]
+pub mod gcs;
+#[cfg(feature = "b2")]
+pub mod b2;
+#[cfg(feature = "webdav")]
+pub mod webdav;
+#[cfg(feature = "tui")]
+pub mod tui;
+#[cfg(feature = "server")]
+pub mod server;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+
 // Tests are located in individual modules 
\ No newline at end of file
@@ -0,0 +1,265 @@
+//! Inventory of .zpp archives and .zpak images for `zippy list`
+
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use glob::Pattern;
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+
+use crate::format::ZPP_MAGIC;
+use crate::image::BlockHash;
+use crate::report::lossy_path;
+use crate::walk::EntryKind;
+
+/// How the listed container stores its entries
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContainerFormat {
+    /// .zpp archive, files compressed independently
+    Stream,
+    /// .zpp archive, all files in one zstd frame
+    Solid,
+    /// .zpp archive of a single file (`compress-file`)
+    File,
+    /// Deduplicated .zpak image
+    Image,
+}
+
+#[derive(Debug, Clone)]
+pub struct ListedEntry {
+    pub path: PathBuf,
+    pub kind: EntryKind,
+    /// Uncompressed size
+    pub size: u64,
+    /// Compressed bytes of this entry; unknown inside a solid frame. For images,
+    /// the sum of its blocks, shared blocks being counted for every entry using them
+    pub compressed_size: Option<u64>,
+    /// Modification time in seconds since the epoch (images only)
+    pub modified: Option<u64>,
+    /// Hashes of the deduplication blocks, in order (images only)
+    pub blocks: Option<Vec<BlockHash>>,
+}
+
+impl ListedEntry {
+    /// Compressed size as a percentage of the original size
+    pub fn ratio(&self) -> Option<f64> {
+        self.compressed_size.map(|compressed| ratio(compressed, self.size))
+    }
+}
+
+impl Serialize for ListedEntry {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut entry = serializer.serialize_struct("ListedEntry", 7)?;
+        entry.serialize_field("path", &LossyPath(&self.path))?;
+        entry.serialize_field("kind", &self.kind)?;
+        entry.serialize_field("size", &self.size)?;
+        entry.serialize_field("compressed_size", &self.compressed_size)?;
+        entry.serialize_field("ratio", &self.ratio())?;
+        entry.serialize_field("modified", &self.modified)?;
+        entry.serialize_field("blocks", &self.blocks)?;
+        entry.end()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Listing {
+    pub format: ContainerFormat,
+    pub entries: Vec<ListedEntry>,
+    /// Compressed data actually stored, after deduplication for images; unknown
+    /// once filtered when entries share a solid frame or blocks
+    pub stored_size: Option<u64>,
+}
+
+/// Whether `pattern` matches an entry path; a pattern without `/` is matched
+/// against the file name alone (`*.log`)
+pub fn matches_entry(pattern: &Pattern, path: &Path) -> bool {
+    match path.file_name() {
+        Some(name) if !pattern.as_str().contains('/') => pattern.matches(&name.to_string_lossy()),
+        _ => pattern.matches_path(path),
+    }
+}
+
+impl Listing {
+    pub fn total_size(&self) -> u64 {
+        self.entries.iter().map(|entry| entry.size).sum()
+    }
+
+    /// Stored size as a percentage of the total original size
+    pub fn ratio(&self) -> Option<f64> {
+        self.stored_size.map(|stored_size| ratio(stored_size, self.total_size()))
+    }
+
+    /// Keep the entries whose path matches `pattern` (see [`matches_entry`])
+    pub fn retain_matching(&mut self, pattern: &Pattern) {
+        self.entries.retain(|entry| matches_entry(pattern, &entry.path));
+        self.stored_size = match self.format {
+            ContainerFormat::Stream | ContainerFormat::File => Some(self.entries.iter().filter_map(|entry| entry.compressed_size).sum()),
+            ContainerFormat::Solid | ContainerFormat::Image => None,
+        };
+    }
+
+    /// Order entries by `key`: largest, worst-compressing or newest first, names
+    /// alphabetically. Entries missing the value come last.
+    pub fn sort(&mut self, key: SortKey, reverse: bool) {
+        match key {
+            SortKey::Name => self.entries.sort_by(|a, b| a.path.cmp(&b.path)),
+            SortKey::Size => self.entries.sort_by_key(|entry| Reverse(entry.size)),
+            SortKey::Ratio => self.entries.sort_by(|a, b| {
+                let ratio = |entry: &ListedEntry| entry.ratio().unwrap_or(f64::NEG_INFINITY);
+                ratio(b).total_cmp(&ratio(a))
+            }),
+            SortKey::Mtime => self.entries.sort_by_key(|entry| Reverse(entry.modified)),
+        }
+        if reverse {
+            self.entries.reverse();
+        }
+    }
+
+    /// Entries arranged by directory; directories missing from the listing
+    /// (.zpp archives store files only) are implied by the paths
+    pub fn tree(&self) -> TreeNode {
+        let mut root = TreeNode::default();
+        for entry in &self.entries {
+            let mut node = &mut root;
+            node.size += entry.size;
+            for component in entry.path.iter() {
+                node = node.children.entry(component.to_string_lossy().into_owned()).or_default();
+                node.size += entry.size;
+            }
+            node.kind = Some(entry.kind);
+        }
+        root
+    }
+}
+
+/// Order of `zippy list --sort`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum SortKey {
+    Name,
+    Size,
+    Ratio,
+    Mtime,
+}
+
+/// JSON layout of `zippy list --json`, documented in the README; keep fields stable
+impl Serialize for Listing {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut listing = serializer.serialize_struct("Listing", 5)?;
+        listing.serialize_field("format", &self.format)?;
+        listing.serialize_field("entries", &self.entries)?;
+        listing.serialize_field("total_size", &self.total_size())?;
+        listing.serialize_field("stored_size", &self.stored_size)?;
+        listing.serialize_field("ratio", &self.ratio())?;
+        listing.end()
+    }
+}
+
+struct LossyPath<'a>(&'a Path);
+
+impl Serialize for LossyPath<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        lossy_path(self.0, serializer)
+    }
+}
+
+/// Directory tree of a listing, children sorted by name
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TreeNode {
+    /// `None` for directories implied by the paths below them
+    pub kind: Option<EntryKind>,
+    /// Original size of the entry, or of everything below a directory
+    pub size: u64,
+    pub children: BTreeMap<String, TreeNode>,
+}
+
+fn ratio(compressed: u64, original: u64) -> f64 {
+    if original == 0 {
+        return 100.0;
+    }
+    compressed as f64 / original as f64 * 100.0
+}
+
+/// `true` for a .zpp archive; anything else is read as a .zpak image
+pub fn is_archive(path: &Path) -> io::Result<bool> {
+    let mut magic = [0u8; 4];
+    match File::open(path)?.read_exact(&mut magic) {
+        Ok(()) => Ok(magic == ZPP_MAGIC),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// `ls -l` style type character, `a` for alternate data streams
+pub fn type_char(kind: EntryKind) -> char {
+    match kind {
+        EntryKind::File => '-',
+        EntryKind::Directory => 'd',
+        EntryKind::Symlink => 'l',
+        EntryKind::Fifo => 'p',
+        EntryKind::Socket => 's',
+        EntryKind::CharDevice => 'c',
+        EntryKind::BlockDevice => 'b',
+        EntryKind::Stream => 'a',
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(path: &str, size: u64) -> ListedEntry {
+        ListedEntry { path: PathBuf::from(path), kind: EntryKind::File, size, compressed_size: None, modified: None, blocks: None }
+    }
+
+    #[test]
+    fn test_json_fields() {
+        let listing = Listing { format: ContainerFormat::Solid, entries: vec![file("a", 4)], stored_size: Some(2) };
+        let json = serde_json::to_value(&listing).unwrap();
+        assert_eq!(json["format"], "solid");
+        assert_eq!(json["ratio"], 50.0);
+        assert_eq!(json["entries"][0]["path"], "a");
+        assert!(json["entries"][0]["compressed_size"].is_null());
+        assert!(json["entries"][0]["blocks"].is_null());
+    }
+
+    #[test]
+    fn test_sort_and_filter() {
+        let compressed = |path, size, compressed_size| ListedEntry { compressed_size: Some(compressed_size), ..file(path, size) };
+        let mut listing = Listing {
+            format: ContainerFormat::Stream,
+            entries: vec![compressed("logs/a.log", 100, 10), compressed("b.txt", 50, 40), compressed("logs/c.log", 10, 9)],
+            stored_size: Some(59),
+        };
+        let paths = |listing: &Listing| listing.entries.iter().map(|entry| entry.path.to_str().unwrap().to_string()).collect::<Vec<_>>();
+
+        listing.sort(SortKey::Size, false);
+        assert_eq!(paths(&listing), ["logs/a.log", "b.txt", "logs/c.log"]);
+        listing.sort(SortKey::Ratio, false);
+        assert_eq!(paths(&listing), ["logs/c.log", "b.txt", "logs/a.log"]);
+        listing.sort(SortKey::Name, true);
+        assert_eq!(paths(&listing), ["logs/c.log", "logs/a.log", "b.txt"]);
+
+        listing.retain_matching(&Pattern::new("*.log").unwrap());
+        assert_eq!(paths(&listing), ["logs/c.log", "logs/a.log"]);
+        assert_eq!(listing.stored_size, Some(19));
+        listing.retain_matching(&Pattern::new("logs/a*").unwrap());
+        assert_eq!(paths(&listing), ["logs/a.log"]);
+    }
+
+    #[test]
+    fn test_tree_aggregates_sizes() {
+        let listing = Listing {
+            format: ContainerFormat::Stream,
+            entries: vec![file("src/main.rs", 10), file("src/lib/mod.rs", 5), file("README", 1)],
+            stored_size: None,
+        };
+        let tree = listing.tree();
+        assert_eq!(tree.size, 16);
+        assert_eq!(tree.children.keys().collect::<Vec<_>>(), ["README", "src"]);
+        let src = &tree.children["src"];
+        assert_eq!((src.kind, src.size), (None, 15));
+        assert_eq!(src.children["lib"].children["mod.rs"].kind, Some(EntryKind::File));
+    }
+}
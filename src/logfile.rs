@@ -0,0 +1,101 @@
+//! Log file with size-based rotation for `--log-file`
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Size at which the log file is rotated
+pub const DEFAULT_MAX_SIZE: u64 = 10 * 1024 * 1024;
+
+/// Rotated files kept next to the current one (`zippy.log.1` being the newest)
+pub const DEFAULT_MAX_FILES: usize = 5;
+
+/// Appends to `path`; once a write would take it past `max_size`, the file is
+/// renamed to `path.1` (older ones shifting up to `path.max_files`) and a new one started
+#[derive(Debug)]
+pub struct RotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    max_files: usize,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    pub fn open(path: &Path, max_size: u64, max_files: usize) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(Self { path: path.to_path_buf(), max_size, max_files, file, size })
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.max_files == 0 {
+            self.file = File::create(&self.path)?;
+        } else {
+            for index in (1..self.max_files).rev() {
+                let from = rotated_path(&self.path, index);
+                if from.exists() {
+                    fs::rename(&from, rotated_path(&self.path, index + 1))?;
+                }
+            }
+            fs::rename(&self.path, rotated_path(&self.path, 1))?;
+            self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        }
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Log events arrive in one write each, so files split between events
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// `zippy.log` -> `zippy.log.N`
+pub fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", index));
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_rotation() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("zippy.log");
+        let mut log = RotatingFile::open(&path, 100, 2).unwrap();
+        for i in 0..10 {
+            log.write_all(format!("{:039}\n", i).as_bytes()).unwrap();
+        }
+        log.flush().unwrap();
+
+        // Two 40-byte events per file: 8 and 9 current, 6 and 7 in .1, 4 and 5 in .2
+        let read = |path: &Path| fs::read_to_string(path).unwrap();
+        assert_eq!(read(&path).lines().count(), 2);
+        assert!(read(&path).ends_with("9\n"));
+        assert!(read(&rotated_path(&path, 1)).ends_with("7\n"));
+        assert!(read(&rotated_path(&path, 2)).ends_with("5\n"));
+        assert!(!rotated_path(&path, 3).exists());
+
+        // Reopening continues the current file
+        let mut log = RotatingFile::open(&path, 100, 2).unwrap();
+        log.write_all(b"appended\n").unwrap();
+        assert!(read(&path).ends_with("9\nappended\n"));
+    }
+}
@@ -1,232 +1,1854 @@
-/*!
- * ZippyPack - Outil de compression moderne
- *
- * Créé par : Kamion (Matthéo Le Fur)
- * Date : 26/06/2025
- * Modifié le : 14/07/2025
- *
- * Description : Interface CLI principale pour ZippyPack, un outil de compression
- * avancé utilisant zstd avec déduplication par blocs et système d'images
- * 
- * Version : 1.0.0
- */
-
-mod compress;
-mod decompress;
-mod profile;
-mod image;
-mod error;
-mod config;
-mod metrics;
-
-use std::path::PathBuf;
-use clap::{Parser, Subcommand};
-use anyhow::Result;
-use tracing::{info, error, warn};
-use tracing_subscriber::{fmt, EnvFilter};
-use compress::{compress_directory, CompressionOptions};
-use decompress::{decompress_archive, DecompressionOptions};
-use image::{create_image, extract_image, ImageOptions, ExtractOptions};
-use config::Config;
-use metrics::Metrics;
-
-#[derive(Parser)]
-#[command(name = "zippy")]
-#[command(about = "Modern compression tool with deduplication", long_about = None)]
-struct Cli {
-    #[command(subcommand)]
-    command: Commands,
-
-    /// Verbosity level (0-4)
-    #[arg(short, long, default_value = "2")]
-    verbosity: u8,
-    
-    /// Configuration file path
-    #[arg(short, long)]
-    config: Option<PathBuf>,
-    
-    /// Number of threads (overrides config file)
-    #[arg(long)]
-    threads: Option<usize>,
-    
-    /// Enable detailed metrics output
-    #[arg(long)]
-    metrics: bool,
-}
-
-#[derive(Subcommand)]
-enum Commands {
-    /// Compress a directory
-    Compress {
-        /// Directory to compress
-        #[arg(short, long)]
-        input: PathBuf,
-        /// Output .zpp file
-        #[arg(short, long)]
-        output: PathBuf,
-        /// Compression level (1-22, overrides config)
-        #[arg(short = 'l', long)]
-        level: Option<i32>,
-        /// Solid mode (compress as single stream)
-        #[arg(long)]
-        solid: bool,
-    },
-    /// Decompress a .zpp archive
-    Decompress {
-        /// .zpp archive to decompress
-        #[arg(short, long)]
-        input: PathBuf,
-        /// Output directory
-        #[arg(short, long)]
-        output: PathBuf,
-    },
-    /// Create system image with deduplication
-    CreateImage {
-        /// Directory to capture
-        #[arg(short, long)]
-        input: PathBuf,
-        /// Output .zpak image file
-        #[arg(short, long)]
-        output: PathBuf,
-        /// Compression level (1-22, overrides config)
-        #[arg(short = 'l', long)]
-        level: Option<i32>,
-    },
-    /// Extract system image
-    ExtractImage {
-        /// .zpak image file to extract
-        #[arg(short, long)]
-        input: PathBuf,
-        /// Output directory
-        #[arg(short, long)]
-        output: PathBuf,
-    },
-}
-
-fn main() -> Result<()> {
-    let cli = Cli::parse();
-
-    // Initialize structured logging
-    let log_level = match cli.verbosity {
-        0 => "error",
-        1 => "warn", 
-        2 => "info",
-        3 => "debug",
-        _ => "trace",
-    };
-    
-    tracing_subscriber::fmt()
-        .with_env_filter(EnvFilter::new(format!("zippy={}", log_level)))
-        .with_target(false)
-        .init();
-
-    info!(version = env!("CARGO_PKG_VERSION"), "ZippyPack starting");
-
-    // Load configuration
-    let mut config = if let Some(config_path) = &cli.config {
-        info!(config_file = %config_path.display(), "Loading configuration file");
-        Config::from_file(config_path).unwrap_or_else(|e| {
-            warn!(error = %e, "Failed to load config file, using defaults");
-            Config::default()
-        })
-    } else {
-        Config::default()
-    };
-
-    // Merge CLI arguments with config
-    config.merge_with_cli(None, cli.threads, cli.verbosity >= 3);
-
-    // Initialize metrics if requested
-    let metrics = if cli.metrics {
-        Some(Metrics::new())
-    } else {
-        None
-    };
-
-    info!(
-        compression_level = config.compression_level,
-        max_threads = config.max_threads,
-        "Configuration loaded"
-    );
-
-    match &cli.command {
-        Commands::Compress { input, output, level, solid } => {
-            let final_level = level.unwrap_or(config.compression_level);
-            info!(
-                input = %input.display(),
-                output = %output.display(),
-                level = final_level,
-                solid = solid,
-                "Starting compression"
-            );
-            
-            let options = CompressionOptions {
-                input_path: input.clone(),
-                output_path: output.clone(),
-                threads: config.max_threads,
-                level: final_level,
-                solid: *solid,
-            };
-            
-            if let Some(ref m) = metrics { m.start_compression(); }
-            let result = compress_directory(&options);
-            if let Some(ref m) = metrics { 
-                m.end_compression();
-                m.print_summary();
-            }
-            result?;
-        }
-        Commands::Decompress { input, output } => {
-            info!(
-                input = %input.display(),
-                output = %output.display(),
-                "Starting decompression"
-            );
-            
-            let options = DecompressionOptions {
-                input_path: input.clone(),
-                output_path: output.clone(),
-            };
-            decompress_archive(&options)?;
-        }
-        Commands::CreateImage { input, output, level } => {
-            let final_level = level.unwrap_or(config.compression_level);
-            info!(
-                input = %input.display(),
-                output = %output.display(),
-                level = final_level,
-                "Creating system image"
-            );
-            
-            let options = ImageOptions {
-                input_path: input.clone(),
-                output_path: output.clone(),
-                compression_level: final_level,
-            };
-            
-            if let Some(ref m) = metrics { m.start_compression(); }
-            let result = create_image(&options);
-            if let Some(ref m) = metrics { 
-                m.end_compression();
-                m.print_summary();
-            }
-            result?;
-        }
-        Commands::ExtractImage { input, output } => {
-            info!(
-                input = %input.display(),
-                output = %output.display(),
-                "Extracting system image"
-            );
-            
-            let options = ExtractOptions {
-                image_path: input.clone(),
-                output_path: output.clone(),
-            };
-            extract_image(&options)?;
-        }
-    }
-
-    info!("Operation completed successfully");
-    Ok(())
-}
+/*!
+ * ZippyPack - Outil de compression moderne
+ *
+ * Créé par : Kamion (Matthéo Le Fur)
+ * Date : 26/06/2025
+ * Modifié le : 14/07/2025
+ *
+ * Description : Interface CLI principale pour ZippyPack, un outil de compression
+ * avancé utilisant zstd avec déduplication par blocs et système d'images
+ * 
+ * Version : 1.0.0
+ */
+
+use std::io::{ErrorKind, IsTerminal, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use clap::{Args, Parser, Subcommand};
+use anyhow::{Context, Result};
+use tracing::{info, warn};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::{fmt, EnvFilter, Layer};
+use zippy::blockio::IoBackend;
+use zippy::chunker::{ChunkStrategy, ChunkerOptions};
+use zippy::bench::{run_bench, BenchOptions, BenchResult};
+use zippy::compress::{compress_directory, compress_directory_pending, compress_file, CompressionOptions, FileCompressionOptions};
+use zippy::decompress::{decompress_archive, decompress_file, list_archive, DecompressionOptions};
+use zippy::image::{add_to_image, create_image, repack_image, extract_image, extract_image_tar, list_image, write_checksums, ExtractDedup, HashAlgorithm, ImageOptions, AddOptions, RepackOptions, ExtractOptions};
+use zippy::config::{Config, Preset};
+use zippy::recompress::{migrate_archive, recompress_archive, RecompressOptions, TargetCodec};
+use zippy::device::restore_device;
+use zippy::daemon::{default_socket_path, DaemonOptions, DEFAULT_REQUEST_TIMEOUT, JobInfo, JobSpec, JobState, Request, Response};
+use zippy::analyze::{analyze_archive, analyze_image, Advice, Analysis};
+use zippy::dupes::{find_duplicates, image_duplicates, Duplicates};
+use zippy::estimate::{estimate, predict, Estimate, EstimateOptions, EstimateTarget, Prediction, PredictOptions};
+use zippy::error::{CompressionError, DecompressionError, ImageError};
+use zippy::list::{is_archive, type_char, Listing, SortKey, TreeNode};
+use zippy::logfile::{RotatingFile, DEFAULT_MAX_FILES, DEFAULT_MAX_SIZE};
+use zippy::metrics::Metrics;
+use zippy::nbd::NbdOptions;
+use zippy::oci::{export_oci, native_architecture, OciOptions};
+use zippy::squashfs::{export_squashfs, SquashfsOptions};
+use zippy::storage::{FsyncPolicy, StagedInput, StagedOutput};
+use zippy::sync::{receive as receive_sync, sync, Destination};
+use zippy::platform::available_space;
+use zippy::owners::{IdKind, IdMap};
+use zippy::paths::{CaseCollision, NormalizationForm};
+use zippy::signing::{fingerprint, generate_key_pair, public_key_path, read_public_key, read_signing_key};
+use zippy::report::{PlannedAction, Report};
+use zippy::testdata::{generate_test_data, TestDataOptions, TestDataProfile};
+use zippy::units::{parse_rate, parse_ratio, parse_size, parse_time_threshold};
+use zippy::walk::{read_file_list, EntryKind, SpecialFilePolicy, WalkOptions, DEFAULT_SCAN_THREADS};
+
+#[derive(Parser)]
+#[command(name = "zippy")]
+#[command(about = "Modern compression tool with deduplication", long_about = None)]
+struct Cli {
+    #[command(subcommand)]
+    command: Commands,
+
+    /// Verbosity level (0-4)
+    #[arg(short, long, default_value = "2")]
+    verbosity: u8,
+    
+    /// Configuration file, read instead of ~/.config/zippypack/config.toml and .zippypack.toml
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+    
+    /// Named settings from the [preset.NAME] tables of the configuration file
+    #[arg(long, global = true)]
+    preset: Option<String>,
+    
+    /// Number of threads (overrides config file)
+    #[arg(long)]
+    threads: Option<usize>,
+    
+    /// Enable detailed metrics output
+    #[arg(long)]
+    metrics: bool,
+    
+    /// Skip files that cannot be read or written instead of aborting, and list them at the end
+    #[arg(long, global = true)]
+    skip_errors: bool,
+    
+    /// Write the end-of-run report (skipped entries and warnings) as JSON
+    #[arg(long, global = true, value_name = "FILE")]
+    report: Option<PathBuf>,
+    
+    /// Also write debug-level JSON logs to this file, whatever the verbosity (rotated by size)
+    #[arg(long, global = true, value_name = "FILE")]
+    log_file: Option<PathBuf>,
+    
+    /// Overwrite existing archives, extract into non-empty directories and start long runs without asking
+    #[arg(short, long, global = true)]
+    yes: bool,
+    
+    /// Limit transfers to and from storage URLs to this rate each way (e.g. 10MB/s)
+    #[arg(long, global = true, value_name = "RATE", value_parser = parse_rate)]
+    bwlimit: Option<u64>,
+    
+    /// Flush archives and images written to local paths to the device before exiting:
+    /// the file, or the file and its directory entry (e.g. before unplugging removable media)
+    #[arg(long, global = true, value_enum)]
+    fsync: Option<FsyncPolicy>,
+}
+
+/// Directory traversal options shared by compress and create-image
+#[derive(Args)]
+struct WalkArgs {
+    /// Follow symbolic links instead of storing them as links
+    #[arg(long)]
+    follow_symlinks: bool,
+    /// What to do with sockets, FIFOs and device nodes
+    #[arg(long, value_enum, default_value = "skip")]
+    special_files: SpecialFilePolicy,
+    /// Maximum directory depth to descend into
+    #[arg(long)]
+    max_depth: Option<usize>,
+    /// Skip files smaller than this size (e.g. 4K, 10MB)
+    #[arg(long, value_parser = parse_size)]
+    min_size: Option<u64>,
+    /// Skip files larger than this size (e.g. 2G)
+    #[arg(long, value_parser = parse_size)]
+    max_size: Option<u64>,
+    /// Only include files modified after a date (YYYY-MM-DD) or within a duration (e.g. 30d)
+    #[arg(long, value_parser = parse_time_threshold)]
+    newer_than: Option<SystemTime>,
+    /// Take the entries from a list (`-` for stdin) instead of walking the input, e.g. from find or fd;
+    /// paths are relative to the current directory and must be inside the input
+    #[arg(short = 'T', long, value_name = "FILE")]
+    files_from: Option<PathBuf>,
+    /// The --files-from list is NUL-separated (find -print0, fd -0)
+    #[arg(short = '0', long, requires = "files_from")]
+    null: bool,
+    /// Threads listing directories in parallel; raise it on network filesystems, 1 walks sequentially
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_SCAN_THREADS)]
+    scan_threads: usize,
+    /// Also walk directories marked as caches (CACHEDIR.TAG) or excluded from backups (.nobackup)
+    #[arg(long)]
+    keep_cache_dirs: bool,
+}
+
+impl WalkArgs {
+    /// Flags left unset fall back to the preset
+    fn to_options(&self, preset: &Preset) -> Result<WalkOptions> {
+        let files_from = match &self.files_from {
+            Some(path) if path.as_os_str() == "-" => Some(read_file_list(std::io::stdin().lock(), self.null)?),
+            Some(path) => {
+                let file = std::fs::File::open(path)
+                    .with_context(|| format!("Failed to open file list: {}", path.display()))?;
+                Some(read_file_list(std::io::BufReader::new(file), self.null)?)
+            }
+            None => None,
+        };
+        Ok(WalkOptions {
+            follow_symlinks: self.follow_symlinks || preset.follow_symlinks.unwrap_or(false),
+            special_files: self.special_files,
+            max_depth: self.max_depth.or(preset.max_depth),
+            min_size: self.min_size.or(preset.min_size),
+            max_size: self.max_size.or(preset.max_size),
+            newer_than: self.newer_than,
+            files_from,
+            scan_threads: self.scan_threads,
+            keep_cache_dirs: self.keep_cache_dirs,
+        })
+    }
+}
+
+/// Block chunking options for create-image (override config)
+#[derive(Args)]
+struct ChunkerArgs {
+    /// How blocks are cut: fixed size, or content-defined to survive insertions
+    #[arg(long, value_enum)]
+    chunker: Option<ChunkStrategy>,
+    /// Smallest content-defined block (e.g. 16K)
+    #[arg(long, value_parser = parse_size)]
+    chunk_min: Option<u64>,
+    /// Target block size; the size of fixed blocks (e.g. 64K)
+    #[arg(long, value_parser = parse_size)]
+    chunk_avg: Option<u64>,
+    /// Largest content-defined block (e.g. 256K)
+    #[arg(long, value_parser = parse_size)]
+    chunk_max: Option<u64>,
+    /// FastCDC normalization level (0-3)
+    #[arg(long)]
+    chunk_normalization: Option<u8>,
+}
+
+impl ChunkerArgs {
+    fn to_options(&self, config: &ChunkerOptions) -> Result<ChunkerOptions> {
+        let size = |value: u64| u32::try_from(value).map_err(|_| anyhow::anyhow!("Chunk size too large: {}", value));
+        let strategy = self.chunker.unwrap_or(config.strategy);
+        // A new target size moves the default bounds with it
+        let defaults = match self.chunk_avg {
+            Some(avg) => ChunkerOptions::around(strategy, size(avg)?),
+            None => ChunkerOptions { strategy, ..*config },
+        };
+        let options = ChunkerOptions {
+            min_size: self.chunk_min.map(size).transpose()?.unwrap_or(defaults.min_size),
+            max_size: self.chunk_max.map(size).transpose()?.unwrap_or(defaults.max_size),
+            normalization: self.chunk_normalization.unwrap_or(defaults.normalization),
+            ..defaults
+        };
+        options.validate().map_err(|e| anyhow::anyhow!("Invalid chunker settings: {}", e))?;
+        Ok(options)
+    }
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Compress a directory
+    Compress {
+        /// Directory to compress
+        #[arg(short, long)]
+        input: PathBuf,
+        /// Output .zpp file, or storage URL
+        #[arg(short, long)]
+        output: PathBuf,
+        /// Compression level (1-22, overrides config)
+        #[arg(short = 'l', long)]
+        level: Option<i32>,
+        /// Solid mode (compress as single stream)
+        #[arg(long)]
+        solid: bool,
+        /// Original bytes per independent frame of solid data; smaller frames decompress
+        /// with more threads and lose less to a damaged region, at a small cost in ratio
+        #[arg(long, value_name = "SIZE", value_parser = parse_size, default_value = "16M")]
+        frame_size: u64,
+        /// Also write the archive to this path or storage URL, in the same pass as the output
+        #[arg(long, value_name = "PATH|URL", conflicts_with = "dry_run")]
+        mirror: Option<PathBuf>,
+        /// Write the archive around the page cache (O_DIRECT on Linux), for huge outputs on busy servers
+        #[arg(long, conflicts_with = "dry_run")]
+        direct_io: bool,
+        /// Snapshot state file: archive only files new or changed since the last run
+        #[arg(long, value_name = "STATE_FILE")]
+        listed_incremental: Option<PathBuf>,
+        /// Compress every file at its profile's level instead of adapting the level to a probe of its first block
+        #[arg(long)]
+        fixed_level: bool,
+        /// Estimate output size and duration from a sample without writing anything
+        #[arg(long)]
+        dry_run: bool,
+        #[command(flatten)]
+        walk: WalkArgs,
+    },
+    /// Decompress a .zpp archive
+    Decompress {
+        /// .zpp archive to decompress, or storage URL
+        #[arg(short, long)]
+        input: PathBuf,
+        /// Output directory
+        #[arg(short, long)]
+        output: PathBuf,
+        /// Normalize entry names to a Unicode form
+        #[arg(long, value_enum)]
+        normalize: Option<NormalizationForm>,
+        /// What to do with names differing only by case on case-insensitive destinations
+        #[arg(long, value_enum, default_value = "rename")]
+        case_collision: CaseCollision,
+        /// List what would be written, overwritten or replaced without touching the destination
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Compress a single file, e.g. in place of a `zstd` call
+    CompressFile {
+        /// File to compress
+        input: PathBuf,
+        /// Output .zpp file, or storage URL [default: INPUT.zpp]
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Compression level (1-22) [default: level of the file's profile]
+        #[arg(short = 'l', long)]
+        level: Option<i32>,
+    },
+    /// Decompress a .zpp archive made by compress-file
+    DecompressFile {
+        /// .zpp archive to decompress, or storage URL
+        input: PathBuf,
+        /// Output file [default: INPUT without .zpp]
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Transcode a .zpp archive to another codec or level, from its own data
+    Recompress {
+        /// .zpp archive to recompress, or storage URL
+        input: PathBuf,
+        /// Output .zpp file, or storage URL
+        #[arg(short, long)]
+        output: PathBuf,
+        /// Compression level (1-22, overrides config)
+        #[arg(short = 'l', long)]
+        level: Option<i32>,
+        /// Codec of the output: store trades size for reads without decoding
+        #[arg(long, value_enum, default_value = "zstd")]
+        codec: TargetCodec,
+        /// Original bytes per frame of a solid archive (e.g. 4M)
+        #[arg(long, value_parser = parse_size, default_value = "16M")]
+        frame_size: u64,
+    },
+    /// Upgrade a .zpp archive of an earlier format version to the current one, without recompressing entries
+    Migrate {
+        /// .zpp archive to upgrade, or storage URL
+        input: PathBuf,
+        /// Output .zpp file, or storage URL
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+    /// Create system image with deduplication
+    CreateImage {
+        /// Directory to capture
+        #[arg(short, long, required_unless_present = "device")]
+        input: Option<PathBuf>,
+        /// Capture a block device or disk file as a single entry instead of a directory, skipping free ext2/3/4 blocks
+        #[arg(long, value_name = "DEVICE", conflicts_with_all = ["input", "dry_run", "sign_key", "base"])]
+        device: Option<PathBuf>,
+        /// Output .zpak image file, or storage URL
+        #[arg(short, long)]
+        output: PathBuf,
+        /// Also write the image to this path or storage URL, in the same pass as the output
+        #[arg(long, value_name = "PATH|URL", conflicts_with = "dry_run")]
+        mirror: Option<PathBuf>,
+        /// Write the image around the page cache (O_DIRECT on Linux), for huge outputs on busy servers
+        #[arg(long, conflicts_with = "dry_run")]
+        direct_io: bool,
+        /// Compression level (1-22, overrides config)
+        #[arg(short = 'l', long)]
+        level: Option<i32>,
+        /// Block hash: xxh3 for throughput, blake3 for tamper-evident addressing (overrides config)
+        #[arg(long, value_enum)]
+        hash_algorithm: Option<HashAlgorithm>,
+        /// Re-verify blocks whose hash matches with a second hash before deduplicating (slower)
+        #[arg(long)]
+        verify_dedup: bool,
+        /// Reference blocks already stored in an existing image instead of storing them again (repeatable)
+        #[arg(long, value_name = "IMAGE")]
+        dedup_against: Vec<PathBuf>,
+        /// Differential image: record only what changed since this image, which extraction then needs
+        #[arg(long, value_name = "IMAGE")]
+        base: Option<PathBuf>,
+        #[command(flatten)]
+        chunker: ChunkerArgs,
+        /// Sign a manifest of every entry and its content hash with this key (see `zippy keygen`)
+        #[arg(long, value_name = "KEY")]
+        sign_key: Option<PathBuf>,
+        /// Read the image back from disk once written and check every block against its hash
+        #[arg(long, conflicts_with = "dry_run")]
+        verify_write: bool,
+        /// Record the Windows security descriptor (owner, group, DACL, SACL) of each entry
+        #[arg(long, conflicts_with = "device")]
+        acls: bool,
+        /// Record access times, restored on extraction along with modification times
+        #[arg(long, conflicts_with = "device")]
+        atime: bool,
+        /// Estimate output size, dedup savings and duration from a sample without writing anything
+        #[arg(long)]
+        dry_run: bool,
+        /// Live dashboard of progress, workers, dedup hit rate and throughput (requires the `tui` feature)
+        #[arg(long, conflicts_with = "dry_run")]
+        tui: bool,
+        #[command(flatten)]
+        walk: WalkArgs,
+    },
+    /// Add files to an existing image in place, writing only their new blocks and the indexes
+    AddToImage {
+        /// .zpak image file to extend
+        image: PathBuf,
+        /// File or directory to add
+        input: PathBuf,
+        /// Directory of the image to add the input as [default: the input's name]
+        #[arg(long, value_name = "PATH")]
+        dest: Option<PathBuf>,
+        /// Compression level (1-22, overrides config)
+        #[arg(short = 'l', long)]
+        level: Option<i32>,
+        /// Record the Windows security descriptor of each added entry
+        #[arg(long)]
+        acls: bool,
+        /// Record the access time of each added entry
+        #[arg(long)]
+        atime: bool,
+        #[command(flatten)]
+        walk: WalkArgs,
+    },
+    /// Rewrite an image without its unreferenced blocks, in the order files read them
+    Repack {
+        /// .zpak image file to repack
+        image: PathBuf,
+        /// Write the repacked image here instead of replacing the input
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Cut file contents again with the chunker options (or the configured ones)
+        #[arg(long)]
+        rechunk: bool,
+        #[command(flatten)]
+        chunker: ChunkerArgs,
+        /// Recompress blocks at this level (1-22) instead of copying them
+        #[arg(short = 'l', long)]
+        level: Option<i32>,
+    },
+    /// Extract system image
+    ExtractImage {
+        /// .zpak image file to extract, or storage URL
+        #[arg(short, long)]
+        input: PathBuf,
+        /// Image applied over the input, in order (repeatable); its entries replace those of
+        /// the images before it and the deletions it records remove theirs
+        #[arg(long, value_name = "IMAGE")]
+        overlay: Vec<PathBuf>,
+        /// Extract entries matching these comma-separated globs before everything else,
+        /// e.g. 'bin/**,config/**'; patterns without `/` match the file name
+        #[arg(long, value_name = "GLOBS", value_delimiter = ',', value_parser = parse_glob)]
+        first: Vec<glob::Pattern>,
+        /// Only extract the entries under this path of the image
+        #[arg(long, value_name = "PATH")]
+        prefix: Option<PathBuf>,
+        /// Remove this many leading components from each path on output, like
+        /// `tar --strip-components`; entries left without a name are skipped
+        #[arg(long, value_name = "N", default_value_t = 0)]
+        strip_components: usize,
+        /// Output directory
+        #[arg(short, long, required_unless_present = "to_stdout_tar")]
+        output: Option<PathBuf>,
+        /// Write the entries as a tar stream to stdout instead of a directory, e.g. to pipe into `ssh host tar -x`
+        #[arg(long, conflicts_with_all = ["output", "dry_run"])]
+        to_stdout_tar: bool,
+        /// How to materialize files with identical content
+        #[arg(long, value_enum, default_value = "copy")]
+        extract_dedup: ExtractDedup,
+        /// Normalize entry names to a Unicode form
+        #[arg(long, value_enum)]
+        normalize: Option<NormalizationForm>,
+        /// What to do with names differing only by case on case-insensitive destinations
+        #[arg(long, value_enum, default_value = "rename")]
+        case_collision: CaseCollision,
+        /// List what would be written, overwritten or replaced without touching the destination
+        #[arg(long)]
+        dry_run: bool,
+        /// How blocks are read from the image (io-uring requires Linux and the io-uring build feature)
+        #[arg(long, value_enum, default_value = "sync")]
+        io_backend: IoBackend,
+        /// Refuse images whose manifest is not signed by this public key
+        #[arg(long, value_name = "KEY.pub")]
+        trusted_key: Option<PathBuf>,
+        /// Extract even if the signed manifest is missing or does not verify
+        #[arg(long)]
+        force: bool,
+        /// Restore the recorded Windows security descriptors once every entry is written
+        #[arg(long, conflicts_with = "to_stdout_tar")]
+        acls: bool,
+        /// Give entries owned by a recorded uid to another user, e.g. '1000:2000,33:www-data'
+        #[arg(long, value_name = "UID:USER,...", value_parser = parse_owner_map, default_value = "")]
+        owner_map: IdMap,
+        /// Give entries of a recorded gid to another group, e.g. '1000:2000,33:www-data'
+        #[arg(long, value_name = "GID:GROUP,...", value_parser = parse_group_map, default_value = "")]
+        group_map: IdMap,
+        /// Fail when ownership, permissions or special files cannot be restored; without
+        /// root, ownership and device nodes are otherwise skipped with a single warning
+        #[arg(long)]
+        strict_metadata: bool,
+    },
+    /// List the entries of a .zpp archive or .zpak image
+    List {
+        /// .zpp archive or .zpak image, or storage URL
+        input: PathBuf,
+        /// Show sizes, compression ratio, modification time and block count, with totals
+        #[arg(short, long)]
+        long: bool,
+        /// Show entries as an indented tree with the total size of each directory
+        #[arg(long, conflicts_with = "long")]
+        tree: bool,
+        /// Print the listing as JSON (fields documented in the README)
+        #[arg(long, conflicts_with_all = ["long", "tree"])]
+        json: bool,
+        /// Order entries: largest, worst-compressing or newest first, or by name (default: archive order)
+        #[arg(long, value_enum)]
+        sort: Option<SortKey>,
+        /// Reverse the order
+        #[arg(long)]
+        reverse: bool,
+        /// Only list entries matching a glob; patterns without `/` match the file name (e.g. '*.log')
+        #[arg(long, value_name = "GLOB", value_parser = parse_glob)]
+        filter: Option<glob::Pattern>,
+    },
+    /// Show the compression achieved per extension and the categories worth another profile
+    Analyze {
+        /// .zpp archive or .zpak image, or storage URL
+        input: PathBuf,
+    },
+    /// Write the SHA-256 of every file in an image, in `sha256sum` format
+    Manifest {
+        /// .zpak image file
+        input: PathBuf,
+        /// Checksum file to write (e.g. SHA256SUMS); standard output if omitted
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Convert an image into an OCI image layout with a single zstd tar layer
+    ExportOci {
+        /// .zpak image file
+        input: PathBuf,
+        /// Layout directory to create
+        #[arg(short, long)]
+        output: PathBuf,
+        /// Reference name of the image in the layout
+        #[arg(long, default_value = "latest")]
+        tag: String,
+        /// OCI architecture of the container [default: this machine's]
+        #[arg(long)]
+        arch: Option<String>,
+    },
+    /// Convert an image into a zstd-compressed SquashFS filesystem, mountable without FUSE
+    ExportSquashfs {
+        /// .zpak image file
+        input: PathBuf,
+        /// SquashFS file to create
+        #[arg(short, long)]
+        output: PathBuf,
+        /// Data block size, a power of two from 4K to 1M
+        #[arg(long, default_value = "128K", value_parser = parse_size)]
+        block_size: u64,
+    },
+    /// Write a device captured with `create-image --device` back to a device or disk file
+    RestoreDevice {
+        /// .zpak image file
+        #[arg(short, long)]
+        input: PathBuf,
+        /// Device or disk file to overwrite
+        #[arg(long, value_name = "DEVICE")]
+        device: PathBuf,
+        /// File of the image to write [default: the only file of the image]
+        #[arg(long)]
+        entry: Option<PathBuf>,
+    },
+    /// Export a file of an image, such as a captured disk, as a read-only network block device
+    NbdServe {
+        /// .zpak image file
+        input: PathBuf,
+        /// File to export [default: the only file of the image]
+        #[arg(long)]
+        entry: Option<PathBuf>,
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:10809")]
+        listen: String,
+        /// Export name clients ask for; the default empty name is always accepted
+        #[arg(long, default_value = "")]
+        name: String,
+    },
+    /// Predict the size of a .zpp archive from random blocks of each file class, with 95% bounds
+    Estimate {
+        /// Directory to sample
+        #[arg(short, long)]
+        input: PathBuf,
+        /// Compression level [default: the level of each file's profile]
+        #[arg(short, long)]
+        level: Option<i32>,
+        /// Blocks of 64K drawn per file class
+        #[arg(long, value_name = "N", default_value_t = 32)]
+        blocks: u64,
+        /// Seed of the random draw
+        #[arg(long, default_value_t = 42)]
+        seed: u64,
+        #[command(flatten)]
+        walk: WalkArgs,
+    },
+    /// Report groups of identical files in a directory or an image, and the space they waste
+    Dupes {
+        /// .zpak image or storage URL, instead of a directory
+        #[arg(required_unless_present = "input", conflicts_with = "input")]
+        image: Option<PathBuf>,
+        /// Directory to scan
+        #[arg(short, long)]
+        input: Option<PathBuf>,
+        #[command(flatten)]
+        walk: WalkArgs,
+    },
+    /// Compare compression levels on a sample of a directory
+    Bench {
+        /// Directory to sample
+        #[arg(short, long)]
+        input: PathBuf,
+        /// Comma-separated compression levels to try
+        #[arg(long, value_delimiter = ',', default_value = "1,3,6,9,12,15,19,22")]
+        levels: Vec<i32>,
+        /// Amount of data to sample (e.g. 64M)
+        #[arg(long, value_parser = parse_size, default_value = "64M")]
+        sample_size: u64,
+        #[command(flatten)]
+        walk: WalkArgs,
+    },
+    /// Generate a reproducible dataset for benchmarks
+    GenTestdata {
+        /// Directory to create (must be empty or absent)
+        #[arg(short, long)]
+        output: PathBuf,
+        /// Kind of files to generate
+        #[arg(long, value_enum, default_value = "codebase")]
+        profile: TestDataProfile,
+        /// Total amount of data to write (e.g. 1G)
+        #[arg(long, value_parser = parse_size, default_value = "64M")]
+        size: u64,
+        /// Fraction of files that are exact copies of another file (e.g. 0.1 or 10%)
+        #[arg(long, value_parser = parse_ratio, default_value = "0.1")]
+        duplicate_ratio: f64,
+        /// Seed of the generator; the same seed produces the same dataset
+        #[arg(long, default_value_t = 42)]
+        seed: u64,
+    },
+    /// Generate an Ed25519 key pair for signing images
+    Keygen {
+        /// Secret key file to create; the public key is written next to it with a .pub suffix
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+    /// Run queued jobs in the background, accepting them from `zippy submit` on a Unix socket
+    Daemon {
+        /// Socket to listen on [default: $XDG_RUNTIME_DIR/zippypack/zippypack.sock]
+        #[arg(long)]
+        socket: Option<PathBuf>,
+        /// Jobs run at the same time
+        #[arg(short, long, default_value_t = 1)]
+        jobs: usize,
+        /// Ask the running daemon to stop once its current jobs finish; queued jobs are cancelled
+        #[arg(long)]
+        stop: bool,
+    },
+    /// Queue a job on the running daemon and print its id
+    Submit {
+        /// Daemon socket [default: $XDG_RUNTIME_DIR/zippypack/zippypack.sock]
+        #[arg(long)]
+        socket: Option<PathBuf>,
+        #[command(subcommand)]
+        job: JobSpec,
+    },
+    /// Serve an HTTP API to queue jobs, follow them, list archives and download entries
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        listen: String,
+        /// Directory that request paths are relative to [default: current directory]
+        #[arg(long)]
+        root: Option<PathBuf>,
+        /// Jobs run at the same time
+        #[arg(short, long, default_value_t = 1)]
+        jobs: usize,
+        /// File holding the bearer token requests must present; required to listen beyond loopback
+        #[arg(long)]
+        token_file: Option<PathBuf>,
+    },
+    /// Serve the gRPC API of proto/zippypack.proto: the same jobs, with streamed progress and entries
+    GrpcServe {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:50051")]
+        listen: String,
+        /// Directory that request paths are relative to [default: current directory]
+        #[arg(long)]
+        root: Option<PathBuf>,
+        /// Jobs run at the same time
+        #[arg(short, long, default_value_t = 1)]
+        jobs: usize,
+    },
+    /// Show the state of the daemon's jobs
+    Jobs {
+        /// Only this job
+        id: Option<u64>,
+        /// Daemon socket [default: $XDG_RUNTIME_DIR/zippypack/zippypack.sock]
+        #[arg(long)]
+        socket: Option<PathBuf>,
+        /// Print the job list as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Manage repositories, directories of .zpak images
+    Repo {
+        #[command(subcommand)]
+        command: RepoCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum RepoCommand {
+    /// Copy the images another repository lacks, sending only the blocks it does not already store
+    Sync {
+        /// Repository to copy from
+        source: PathBuf,
+        /// Local directory, ssh://[user@]host[:port]/path or storage URL
+        destination: String,
+        /// zippy executable on the SSH host
+        #[arg(long, default_value = "zippy")]
+        remote_zippy: String,
+    },
+    /// Receive a sync on standard input and output (started over SSH by `repo sync`)
+    Serve {
+        /// Repository to copy into, created if needed
+        path: PathBuf,
+    },
+}
+
+/// Process exit codes, so scripts can react without parsing logs
+mod exit_code {
+    /// Invalid command line, or confirmation declined at the prompt
+    pub const USAGE: u8 = 1;
+    /// Read/write failure (missing file, permission denied, disk full)
+    pub const IO: u8 = 2;
+    /// Archive or image is malformed, truncated or of an unsupported version
+    pub const CORRUPT: u8 = 3;
+    /// Stored checksums do not match the data
+    pub const VERIFICATION: u8 = 4;
+    /// Completed, but some entries were skipped (`--skip-errors`)
+    pub const PARTIAL: u8 = 5;
+}
+
+fn main() -> ExitCode {
+    let cli = match Cli::try_parse() {
+        Ok(cli) => cli,
+        Err(e) => {
+            // --help and --version also go through here
+            let _ = e.print();
+            return if e.use_stderr() { ExitCode::from(exit_code::USAGE) } else { ExitCode::SUCCESS };
+        }
+    };
+
+    // Configuration first, since it may name the log file
+    let loaded = Config::discover(cli.config.as_deref());
+    
+    // Initialize structured logging; the dashboard takes over the terminal
+    let dashboard = matches!(cli.command, Commands::CreateImage { tui: true, .. });
+    let log_level = match cli.verbosity {
+        _ if dashboard => "off",
+        0 => "error",
+        1 => "warn", 
+        2 => "info",
+        3 => "debug",
+        _ => "trace",
+    };
+    
+    let console = fmt::layer()
+        .with_target(false)
+        // Standard output is reserved for command output such as `manifest`
+        .with_writer(std::io::stderr)
+        // At trace level, each span closing reports its busy and idle time
+        .with_span_events(if cli.verbosity >= 4 { FmtSpan::CLOSE } else { FmtSpan::NONE })
+        .with_filter(EnvFilter::new(format!("zippy={}", log_level)));
+    
+    let log_config = loaded.as_ref().ok().map(|(config, _)| &config.log);
+    let log_file = match cli.log_file.as_ref().or(log_config.and_then(|log| log.file.as_ref())) {
+        Some(path) => {
+            let max_size = log_config.and_then(|log| log.max_size).unwrap_or(DEFAULT_MAX_SIZE);
+            let max_files = log_config.and_then(|log| log.max_files).unwrap_or(DEFAULT_MAX_FILES);
+            match RotatingFile::open(path, max_size, max_files) {
+                Ok(file) => Some(
+                    fmt::layer()
+                        .json()
+                        .with_writer(Mutex::new(file))
+                        .with_filter(EnvFilter::new("zippy=debug")),
+                ),
+                Err(e) => {
+                    eprintln!("Error: Cannot open log file {}: {}", path.display(), e);
+                    return ExitCode::from(exit_code::IO);
+                }
+            }
+        }
+        None => None,
+    };
+    
+    tracing_subscriber::registry().with(console).with(log_file).init();
+
+    info!(version = env!("CARGO_PKG_VERSION"), "ZippyPack starting");
+
+    match run(&cli, loaded) {
+        Ok(report) if report.is_complete() => ExitCode::SUCCESS,
+        Ok(_) => ExitCode::from(exit_code::PARTIAL),
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            ExitCode::from(classify(&e))
+        }
+    }
+}
+
+fn run(cli: &Cli, loaded: Result<(Config, Vec<PathBuf>)>) -> Result<Report> {
+    // Configuration layers: user file, project file (or --config alone), ZIPPY_* variables
+    let mut config = match loaded {
+        Ok((config, files)) => {
+            for file in &files {
+                info!(config_file = %file.display(), "Configuration file loaded");
+            }
+            config
+        }
+        Err(e) => {
+            warn!(error = %format!("{:#}", e), "Failed to load configuration, using defaults");
+            Config::default()
+        }
+    };
+
+    // Preset, then CLI arguments, over the config
+    let preset = match &cli.preset {
+        Some(name) => {
+            let preset = config.preset(name)?.clone();
+            config.apply_preset(&preset)
+                .with_context(|| format!("Invalid preset '{}'", name))?;
+            info!(preset = %name, "Preset applied");
+            preset
+        }
+        None => Preset::default(),
+    };
+    config.merge_with_cli(None, cli.threads, cli.verbosity >= 3);
+    if let Some(bwlimit) = cli.bwlimit {
+        config.storage.bwlimit = Some(bwlimit);
+    }
+    if let Some(fsync) = cli.fsync {
+        config.storage.fsync = fsync;
+    }
+
+    // Initialize metrics if requested
+    let metrics = if cli.metrics {
+        Some(Metrics::new())
+    } else {
+        None
+    };
+
+    info!(
+        compression_level = config.compression_level,
+        max_threads = config.max_threads,
+        "Configuration loaded"
+    );
+
+    let report = match &cli.command {
+        Commands::Compress { input, output, level, solid, frame_size, mirror, direct_io, listed_incremental, fixed_level, dry_run, walk } => {
+            let final_level = level.unwrap_or(config.compression_level);
+            let solid = *solid || preset.solid.unwrap_or(false);
+            // Built once: `--files-from -` consumes standard input
+            let walk = walk.to_options(&preset)?;
+            let estimate_options = EstimateOptions {
+                input_path: input.clone(),
+                target: if solid { EstimateTarget::Solid } else { EstimateTarget::Stream },
+                level: final_level,
+                walk: walk.clone(),
+                pipeline: config.pipeline_options(),
+                ..Default::default()
+            };
+            if *dry_run {
+                print_estimate(&estimate(&estimate_options)?, output);
+                return Ok(Report::default());
+            }
+            preflight(cli, estimate_options, output)?;
+            confirm_overwrite(cli, output)?;
+            if let Some(mirror) = mirror {
+                confirm_overwrite(cli, mirror)?;
+            }
+            info!(
+                input = %input.display(),
+                output = %output.display(),
+                level = final_level,
+                solid = solid,
+                "Starting compression"
+            );
+            
+            let staged = StagedOutput::new(output, &config.storage).with_context(|| format!("Failed to open {}", output.display()))?;
+            let mirror_staged = mirror.as_ref()
+                .map(|mirror| StagedOutput::new(mirror, &config.storage).with_context(|| format!("Failed to open {}", mirror.display())))
+                .transpose()?;
+            let options = CompressionOptions {
+                input_path: input.clone(),
+                output_path: staged.path().to_path_buf(),
+                mirror_path: mirror_staged.as_ref().map(|mirror| mirror.path().to_path_buf()),
+                direct_io: *direct_io,
+                spill: config.spill_options(),
+                threads: config.max_threads,
+                level: final_level,
+                solid,
+                solid_frame_size: *frame_size,
+                store_threshold: config.store_threshold,
+                walk,
+                listed_incremental: listed_incremental.clone(),
+                skip_errors: cli.skip_errors,
+                fixed_level: *fixed_level,
+                pipeline: config.pipeline_options(),
+            };
+            
+            if let Some(ref m) = metrics { m.start_compression(); }
+            let result = compress_directory_pending(&options);
+            if let Some(ref m) = metrics { 
+                m.end_compression();
+                m.print_summary();
+            }
+            let (report, snapshot) = result?;
+            staged.finish().with_context(|| format!("Failed to store {}", output.display()))?;
+            if let (Some(mirror_staged), Some(mirror)) = (mirror_staged, mirror) {
+                mirror_staged.finish().with_context(|| format!("Failed to store mirror {}", mirror.display()))?;
+            }
+            // Only once the archive is stored, or the next level would miss these changes
+            if let Some(snapshot) = snapshot {
+                snapshot.commit().context("Failed to save the incremental state")?;
+            }
+            report
+        }
+        Commands::Decompress { input, output, normalize, case_collision, dry_run } => {
+            if !*dry_run {
+                confirm_extraction(cli, output)?;
+            }
+            info!(
+                input = %input.display(),
+                output = %output.display(),
+                "Starting decompression"
+            );
+            
+            let staged = StagedInput::new(input, &config.storage).with_context(|| format!("Failed to open {}", input.display()))?;
+            let options = DecompressionOptions {
+                input_path: staged.path().to_path_buf(),
+                output_path: output.clone(),
+                normalize: *normalize,
+                case_collision: *case_collision,
+                skip_errors: cli.skip_errors,
+                dry_run: *dry_run,
+                threads: config.max_threads,
+            };
+            let report = decompress_archive(&options)?;
+            if *dry_run {
+                print_plan(&report);
+            }
+            report
+        }
+        Commands::CompressFile { input, output, level } => {
+            let output = output.clone().unwrap_or_else(|| {
+                let mut output = input.clone().into_os_string();
+                output.push(".zpp");
+                PathBuf::from(output)
+            });
+            confirm_overwrite(cli, &output)?;
+            let staged = StagedOutput::new(&output, &config.storage).with_context(|| format!("Failed to open {}", output.display()))?;
+            let options = FileCompressionOptions {
+                input_path: input.clone(),
+                output_path: staged.path().to_path_buf(),
+                level: *level,
+                threads: config.max_threads,
+                store_threshold: config.store_threshold,
+            };
+            let size = compress_file(&options)?;
+            staged.finish().with_context(|| format!("Failed to store {}", output.display()))?;
+            println!("{}: {} bytes", output.display(), size);
+            Report::default()
+        }
+        Commands::DecompressFile { input, output } => {
+            let output = match output {
+                Some(output) => output.clone(),
+                None if input.extension().is_some_and(|extension| extension == "zpp") => input.with_extension(""),
+                None => anyhow::bail!("{} does not end in .zpp; pass --output", input.display()),
+            };
+            confirm_overwrite(cli, &output)?;
+            let staged = StagedInput::new(input, &config.storage).with_context(|| format!("Failed to open {}", input.display()))?;
+            let size = decompress_file(staged.path(), &output)?;
+            println!("{}: {} bytes", output.display(), size);
+            Report::default()
+        }
+        Commands::Recompress { input, output, level, codec, frame_size } => {
+            confirm_overwrite(cli, output)?;
+            let staged_input = StagedInput::new(input, &config.storage).with_context(|| format!("Failed to open {}", input.display()))?;
+            let staged = StagedOutput::new(output, &config.storage).with_context(|| format!("Failed to open {}", output.display()))?;
+            let options = RecompressOptions {
+                input_path: staged_input.path().to_path_buf(),
+                output_path: staged.path().to_path_buf(),
+                codec: *codec,
+                level: level.unwrap_or(config.compression_level),
+                store_threshold: config.store_threshold,
+                solid_frame_size: *frame_size,
+                threads: config.max_threads,
+                spill: config.spill_options(),
+            };
+            let size = recompress_archive(&options)?;
+            staged.finish().with_context(|| format!("Failed to store {}", output.display()))?;
+            println!("{}: {} bytes", output.display(), size);
+            Report::default()
+        }
+        Commands::Migrate { input, output } => {
+            confirm_overwrite(cli, output)?;
+            let staged_input = StagedInput::new(input, &config.storage).with_context(|| format!("Failed to open {}", input.display()))?;
+            let staged = StagedOutput::new(output, &config.storage).with_context(|| format!("Failed to open {}", output.display()))?;
+            let size = migrate_archive(staged_input.path(), staged.path(), config.max_threads, &config.spill_options())?;
+            staged.finish().with_context(|| format!("Failed to store {}", output.display()))?;
+            println!("{}: {} bytes", output.display(), size);
+            Report::default()
+        }
+        Commands::CreateImage { input, device, output, mirror, direct_io, level, hash_algorithm, verify_dedup, dedup_against, base, chunker, sign_key, verify_write, acls, atime, dry_run, tui, walk } => {
+            let input = input.as_ref().or(device.as_ref()).context("--input or --device is required")?;
+            let final_level = level.unwrap_or(config.compression_level);
+            // Built once: `--files-from -` consumes standard input
+            let walk = walk.to_options(&preset)?;
+            let estimate_options = EstimateOptions {
+                input_path: input.clone(),
+                target: EstimateTarget::Image,
+                level: final_level,
+                walk: walk.clone(),
+                pipeline: config.pipeline_options(),
+                ..Default::default()
+            };
+            if *dry_run {
+                print_estimate(&estimate(&estimate_options)?, output);
+                return Ok(Report::default());
+            }
+            preflight(cli, estimate_options, output)?;
+            confirm_overwrite(cli, output)?;
+            if let Some(mirror) = mirror {
+                confirm_overwrite(cli, mirror)?;
+            }
+            info!(
+                input = %input.display(),
+                output = %output.display(),
+                level = final_level,
+                "Creating system image"
+            );
+            
+            let staged = StagedOutput::new(output, &config.storage).with_context(|| format!("Failed to open {}", output.display()))?;
+            let mirror_staged = mirror.as_ref()
+                .map(|mirror| StagedOutput::new(mirror, &config.storage).with_context(|| format!("Failed to open {}", mirror.display())))
+                .transpose()?;
+            let options = ImageOptions {
+                input_path: input.clone(),
+                output_path: staged.path().to_path_buf(),
+                mirror_path: mirror_staged.as_ref().map(|mirror| mirror.path().to_path_buf()),
+                direct_io: *direct_io,
+                spill: config.spill_options(),
+                compression_level: final_level,
+                store_threshold: config.store_threshold,
+                hash_algorithm: hash_algorithm.unwrap_or(config.hash_algorithm),
+                verify_dedup: *verify_dedup,
+                dedup_against: dedup_against.clone(),
+                base: base.clone(),
+                chunker: chunker.to_options(&config.chunker_options())?,
+                sign_key: sign_key.as_deref()
+                    .map(|path| read_signing_key(path).with_context(|| format!("Failed to read signing key: {}", path.display())))
+                    .transpose()?,
+                walk,
+                skip_errors: cli.skip_errors,
+                pipeline: config.pipeline_options(),
+                metrics: if *tui { Some(metrics.clone().unwrap_or_else(Metrics::new)) } else { metrics.clone() },
+                device: device.is_some(),
+                verify_write: *verify_write,
+                acls: *acls,
+                atime: *atime,
+            };
+            
+            #[cfg(not(feature = "tui"))]
+            if *tui {
+                anyhow::bail!("--tui requires zippy to be built with the tui feature (cargo build --features tui)");
+            }
+            #[cfg(feature = "tui")]
+            let dashboard = match (&options.metrics, *tui) {
+                (Some(metrics), true) => Some(
+                    zippy::tui::Dashboard::start(format!("create-image {}", input.display()), metrics.clone())
+                        .context("Failed to start the dashboard")?,
+                ),
+                _ => None,
+            };
+            
+            if let Some(ref m) = metrics { m.start_compression(); }
+            let result = create_image(&options);
+            #[cfg(feature = "tui")]
+            if let Some(dashboard) = dashboard {
+                dashboard.stop().context("Failed to restore the terminal")?;
+            }
+            if let Some(ref m) = metrics { 
+                m.end_compression();
+                m.print_summary();
+            }
+            let report = result?;
+            staged.finish().with_context(|| format!("Failed to store {}", output.display()))?;
+            if let (Some(mirror_staged), Some(mirror)) = (mirror_staged, mirror) {
+                mirror_staged.finish().with_context(|| format!("Failed to store mirror {}", mirror.display()))?;
+            }
+            report
+        }
+        Commands::AddToImage { image, input, dest, level, acls, atime, walk } => {
+            info!(image = %image.display(), input = %input.display(), "Adding to system image");
+            add_to_image(&AddOptions {
+                image_path: image.clone(),
+                input_path: input.clone(),
+                dest: dest.clone(),
+                compression_level: level.unwrap_or(config.compression_level),
+                store_threshold: config.store_threshold,
+                walk: walk.to_options(&preset)?,
+                skip_errors: cli.skip_errors,
+                acls: *acls,
+                atime: *atime,
+                threads: config.max_threads,
+            })?
+        }
+        Commands::Repack { image, output, rechunk, chunker, level } => {
+            if let Some(output) = output {
+                confirm_overwrite(cli, output)?;
+            }
+            info!(image = %image.display(), "Repacking system image");
+            let summary = repack_image(&RepackOptions {
+                image_path: image.clone(),
+                output_path: output.clone(),
+                rechunk: rechunk.then(|| chunker.to_options(&config.chunker_options())).transpose()?,
+                compression_level: *level,
+                store_threshold: config.store_threshold,
+                spill: config.spill_options(),
+                threads: config.max_threads,
+            })?;
+            println!(
+                "{}: {} -> {} bytes, {} reclaimed ({} -> {} blocks)",
+                output.as_ref().unwrap_or(image).display(),
+                summary.size_before,
+                summary.size_after,
+                summary.reclaimed(),
+                summary.blocks_before,
+                summary.blocks_after
+            );
+            Report::default()
+        }
+        Commands::ExtractImage { input, overlay, first, prefix, strip_components, output, to_stdout_tar, extract_dedup, normalize, case_collision, dry_run, io_backend, trusted_key, force, acls, owner_map, group_map, strict_metadata } => {
+            if let Some(output) = output.as_ref().filter(|_| !*dry_run) {
+                confirm_extraction(cli, output)?;
+            }
+            info!(
+                input = %input.display(),
+                output = %output.as_deref().map_or("<stdout tar>".into(), |output| output.display().to_string()),
+                "Extracting system image"
+            );
+            
+            let staged = StagedInput::new(input, &config.storage).with_context(|| format!("Failed to open {}", input.display()))?;
+            let overlays = overlay.iter()
+                .map(|path| StagedInput::new(path, &config.storage).with_context(|| format!("Failed to open {}", path.display())))
+                .collect::<Result<Vec<_>>>()?;
+            let options = ExtractOptions {
+                image_path: staged.path().to_path_buf(),
+                output_path: output.clone().unwrap_or_default(),
+                dedup: *extract_dedup,
+                normalize: *normalize,
+                case_collision: *case_collision,
+                skip_errors: cli.skip_errors,
+                dry_run: *dry_run,
+                io_backend: *io_backend,
+                trusted_key: trusted_key.as_deref()
+                    .map(|path| read_public_key(path).with_context(|| format!("Failed to read public key: {}", path.display())))
+                    .transpose()?,
+                force: *force,
+                overlays: overlays.iter().map(|staged| staged.path().to_path_buf()).collect(),
+                first: first.clone(),
+                prefix: prefix.clone(),
+                strip_components: *strip_components,
+                acls: *acls,
+                owner_map: owner_map.clone(),
+                group_map: group_map.clone(),
+                strict_metadata: *strict_metadata,
+            };
+            if *to_stdout_tar {
+                let mut stdout = std::io::stdout().lock();
+                let report = extract_image_tar(&options, &mut stdout)?;
+                stdout.flush()?;
+                report
+            } else {
+                let report = extract_image(&options)?;
+                if *dry_run {
+                    print_plan(&report);
+                }
+                report
+            }
+        }
+        Commands::List { input, long, tree, json, sort, reverse, filter } => {
+            let staged = StagedInput::new(input, &config.storage).with_context(|| format!("Failed to open {}", input.display()))?;
+            let is_archive = is_archive(staged.path())
+                .with_context(|| format!("Failed to open {}", input.display()))?;
+            let mut listing = if is_archive { list_archive(staged.path())? } else { list_image(staged.path())? };
+            if let Some(pattern) = filter {
+                listing.retain_matching(pattern);
+            }
+            match sort {
+                Some(key) => listing.sort(*key, *reverse),
+                None if *reverse => listing.entries.reverse(),
+                None => {}
+            }
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&listing)?);
+            } else if *tree {
+                println!("{} ({} bytes)", input.display(), listing.total_size());
+                print_tree(&listing.tree(), "");
+            } else {
+                print_listing(&listing, *long);
+            }
+            Report::default()
+        }
+        Commands::Analyze { input } => {
+            let staged = StagedInput::new(input, &config.storage).with_context(|| format!("Failed to open {}", input.display()))?;
+            let is_archive = is_archive(staged.path())
+                .with_context(|| format!("Failed to open {}", input.display()))?;
+            let analysis = if is_archive { analyze_archive(staged.path())? } else { analyze_image(staged.path())? };
+            print_analysis(&analysis);
+            Report::default()
+        }
+        Commands::Manifest { input, output } => {
+            info!(input = %input.display(), "Writing content checksums");
+            
+            let files = match output {
+                Some(path) => {
+                    let file = std::fs::File::create(path)
+                        .with_context(|| format!("Failed to create checksum file: {}", path.display()))?;
+                    let mut writer = std::io::BufWriter::new(file);
+                    let files = write_checksums(input, &mut writer)?;
+                    writer.flush()?;
+                    files
+                }
+                None => write_checksums(input, &mut std::io::stdout().lock())?,
+            };
+            info!(files, "Checksums written");
+            Report::default()
+        }
+        Commands::ExportOci { input, output, tag, arch } => {
+            confirm_extraction(cli, output)?;
+            let options = OciOptions {
+                image_path: input.clone(),
+                output_path: output.clone(),
+                tag: tag.clone(),
+                architecture: arch.clone().unwrap_or_else(|| native_architecture().to_string()),
+                compression_level: config.compression_level,
+            };
+            let export = export_oci(&options)
+                .with_context(|| format!("Failed to export {} to {}", input.display(), output.display()))?;
+            println!("{}: {} entries, {} ({} bytes layer)", output.display(), export.entries, export.manifest_digest, export.layer_size);
+            Report::default()
+        }
+        Commands::ExportSquashfs { input, output, block_size } => {
+            confirm_overwrite(cli, output)?;
+            let options = SquashfsOptions {
+                image_path: input.clone(),
+                output_path: output.clone(),
+                block_size: u32::try_from(*block_size).unwrap_or(u32::MAX),
+                compression_level: config.compression_level,
+            };
+            let export = export_squashfs(&options)
+                .with_context(|| format!("Failed to export {} to {}", input.display(), output.display()))?;
+            println!("{}: {} inodes, {} bytes", output.display(), export.inodes, export.bytes_used);
+            Report::default()
+        }
+        Commands::RestoreDevice { input, device, entry } => {
+            confirm_overwrite(cli, device)?;
+            let written = restore_device(input, device, entry.as_deref())?;
+            println!("{}: {} bytes restored", device.display(), written);
+            Report::default()
+        }
+        Commands::NbdServe { input, entry, listen, name } => {
+            let options = NbdOptions {
+                image_path: input.clone(),
+                entry_path: entry.clone(),
+                listen: listen.clone(),
+                export_name: name.clone(),
+            };
+            zippy::nbd::serve(&options).with_context(|| format!("NBD server failed for {}", input.display()))?;
+            Report::default()
+        }
+        Commands::Estimate { input, level, blocks, seed, walk } => {
+            let options = PredictOptions {
+                input_path: input.clone(),
+                level: *level,
+                blocks_per_class: *blocks,
+                seed: *seed,
+                walk: walk.to_options(&preset)?,
+            };
+            print_prediction(&predict(&options)?);
+            Report::default()
+        }
+        Commands::Dupes { image, input, walk } => {
+            let duplicates = match image {
+                Some(image) => {
+                    let staged = StagedInput::new(image, &config.storage).with_context(|| format!("Failed to open {}", image.display()))?;
+                    image_duplicates(staged.path())?
+                }
+                None => {
+                    let input = input.as_ref().context("An image or --input is required")?;
+                    find_duplicates(input, &walk.to_options(&preset)?)?
+                }
+            };
+            print_duplicates(&duplicates);
+            Report::default()
+        }
+        Commands::Bench { input, levels, sample_size, walk } => {
+            info!(input = %input.display(), "Benchmarking compression levels");
+            
+            let options = BenchOptions {
+                input_path: input.clone(),
+                levels: levels.clone(),
+                sample_size: *sample_size,
+                walk: walk.to_options(&preset)?,
+            };
+            print_bench(&run_bench(&options)?);
+            Report::default()
+        }
+        Commands::GenTestdata { output, profile, size, duplicate_ratio, seed } => {
+            info!(output = %output.display(), "Generating test data");
+
+            let options = TestDataOptions {
+                output_path: output.clone(),
+                profile: *profile,
+                size: *size,
+                duplicate_ratio: *duplicate_ratio,
+                seed: *seed,
+            };
+            let summary = generate_test_data(&options)
+                .with_context(|| format!("Failed to generate test data in {}", output.display()))?;
+            println!("{} files, {} bytes, {} duplicates", summary.files, summary.bytes, summary.duplicates);
+            Report::default()
+        }
+        Commands::Keygen { output } => {
+            let public_key = generate_key_pair(output)
+                .with_context(|| format!("Failed to create key: {}", output.display()))?;
+            println!("Secret key: {}", output.display());
+            println!("Public key: {} ({})", public_key_path(output).display(), fingerprint(&public_key));
+            Report::default()
+        }
+        Commands::Daemon { socket, jobs, stop } => {
+            let socket_path = socket.clone().unwrap_or_else(default_socket_path);
+            if *stop {
+                daemon_request(&socket_path, &Request::Shutdown)?;
+                println!("Daemon stopping");
+            } else {
+                let options = DaemonOptions {
+                    socket_path: socket_path.clone(),
+                    workers: *jobs,
+                    request_timeout: DEFAULT_REQUEST_TIMEOUT,
+                };
+                serve_daemon(&options, &config)
+                    .with_context(|| format!("Daemon failed on {}", socket_path.display()))?;
+            }
+            Report::default()
+        }
+        Commands::Submit { socket, job } => {
+            let mut job = job.clone();
+            job.make_absolute()?;
+            let socket_path = socket.clone().unwrap_or_else(default_socket_path);
+            if let Response::Submitted { id } = daemon_request(&socket_path, &Request::Submit { job })? {
+                println!("{}", id);
+            }
+            Report::default()
+        }
+        Commands::Serve { listen, root, jobs, token_file } => {
+            #[cfg(not(feature = "server"))]
+            {
+                let _ = (listen, root, jobs, token_file);
+                anyhow::bail!("serve requires zippy to be built with the server feature (cargo build --features server)");
+            }
+            #[cfg(feature = "server")]
+            {
+                let root = match root {
+                    Some(root) => std::path::absolute(root)?,
+                    None => std::env::current_dir()?,
+                };
+                let token = match token_file {
+                    Some(path) => Some(
+                        std::fs::read_to_string(path)
+                            .with_context(|| format!("Could not read token file {}", path.display()))?
+                            .trim()
+                            .to_string(),
+                    ),
+                    None => None,
+                };
+                let options = zippy::server::ServerOptions { listen: listen.clone(), root, workers: *jobs, token };
+                zippy::server::serve(&options, |job, metrics| run_job(job, metrics, &config).map_err(|e| format!("{:#}", e)))
+                    .with_context(|| format!("Server failed on {}", listen))?;
+                Report::default()
+            }
+        }
+        Commands::GrpcServe { listen, root, jobs } => {
+            #[cfg(not(feature = "grpc"))]
+            {
+                let _ = (listen, root, jobs);
+                anyhow::bail!("grpc-serve requires zippy to be built with the grpc feature (cargo build --features grpc)");
+            }
+            #[cfg(feature = "grpc")]
+            {
+                let root = match root {
+                    Some(root) => std::path::absolute(root)?,
+                    None => std::env::current_dir()?,
+                };
+                let options = zippy::grpc::GrpcOptions { listen: listen.clone(), root, workers: *jobs };
+                zippy::grpc::serve(&options, |job, metrics| run_job(job, metrics, &config).map_err(|e| format!("{:#}", e)))
+                    .with_context(|| format!("gRPC server failed on {}", listen))?;
+                Report::default()
+            }
+        }
+        Commands::Jobs { id, socket, json } => {
+            let socket_path = socket.clone().unwrap_or_else(default_socket_path);
+            if let Response::Jobs { jobs } = daemon_request(&socket_path, &Request::Status { id: *id })? {
+                if *json {
+                    println!("{}", serde_json::to_string_pretty(&jobs)?);
+                } else {
+                    print_jobs(&jobs);
+                }
+            }
+            Report::default()
+        }
+        Commands::Repo { command: RepoCommand::Sync { source, destination, remote_zippy } } => {
+            let target = Destination::parse(destination).map_err(anyhow::Error::msg)?;
+            info!(source = %source.display(), destination = %destination, "Syncing repository");
+            let summary = sync(source, &target, remote_zippy, &config.storage)
+                .with_context(|| format!("Failed to sync {} to {}", source.display(), destination))?;
+            println!(
+                "{} images copied, {} already present, {} bytes sent, {} bytes reused",
+                summary.images, summary.skipped, summary.bytes_sent, summary.bytes_reused
+            );
+            Report::default()
+        }
+        Commands::Repo { command: RepoCommand::Serve { path } } => {
+            std::fs::create_dir_all(path).with_context(|| format!("Failed to create {}", path.display()))?;
+            receive_sync(path, std::io::stdin().lock(), std::io::stdout().lock())?;
+            Report::default()
+        }
+    };
+    print_report(&report);
+    if let Some(path) = &cli.report {
+        let json = serde_json::to_string_pretty(&report)?;
+        std::fs::write(path, json)
+            .with_context(|| format!("Failed to write report: {}", path.display()))?;
+    }
+
+    info!("Operation completed successfully");
+    Ok(report)
+}
+
+#[cfg(unix)]
+fn serve_daemon(options: &DaemonOptions, config: &Config) -> Result<()> {
+    zippy::daemon::serve(options, |job, metrics| run_job(job, metrics, config).map_err(|e| format!("{:#}", e)))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn serve_daemon(_options: &DaemonOptions, _config: &Config) -> Result<()> {
+    anyhow::bail!("the daemon needs Unix domain sockets, not available on this platform")
+}
+
+#[cfg(unix)]
+fn daemon_request(socket_path: &std::path::Path, request: &Request) -> Result<Response> {
+    let response = zippy::daemon::request(socket_path, request)
+        .with_context(|| format!("Cannot reach the daemon on {}", socket_path.display()))?;
+    match response {
+        Response::Error { message } => anyhow::bail!("Daemon: {}", message),
+        response => Ok(response),
+    }
+}
+
+#[cfg(not(unix))]
+fn daemon_request(_socket_path: &std::path::Path, _request: &Request) -> Result<Response> {
+    anyhow::bail!("the daemon needs Unix domain sockets, not available on this platform")
+}
+
+/// Job submitted to the daemon or the HTTP API, run with the server's configuration
+#[cfg_attr(not(any(unix, feature = "server")), allow(dead_code))]
+/// Run a queued job; image jobs report their progress in `metrics`
+fn run_job(job: &JobSpec, metrics: &Arc<Metrics>, config: &Config) -> Result<Report> {
+    let report = match job {
+        JobSpec::Compress { input, output, level, solid } => compress_directory(&CompressionOptions {
+            input_path: input.clone(),
+            output_path: output.clone(),
+            threads: config.max_threads,
+            level: level.unwrap_or(config.compression_level),
+            solid: *solid,
+            store_threshold: config.store_threshold,
+            pipeline: config.pipeline_options(),
+            spill: config.spill_options(),
+            ..Default::default()
+        })?,
+        JobSpec::Decompress { input, output } => decompress_archive(&DecompressionOptions {
+            input_path: input.clone(),
+            output_path: output.clone(),
+            threads: config.max_threads,
+            ..Default::default()
+        })?,
+        JobSpec::CreateImage { input, output, level } => create_image(&ImageOptions {
+            input_path: input.clone(),
+            output_path: output.clone(),
+            compression_level: level.unwrap_or(config.compression_level),
+            store_threshold: config.store_threshold,
+            hash_algorithm: config.hash_algorithm,
+            chunker: config.chunker_options(),
+            pipeline: config.pipeline_options(),
+            spill: config.spill_options(),
+            metrics: Some(metrics.clone()),
+            ..Default::default()
+        })?,
+        JobSpec::ExtractImage { input, output } => extract_image(&ExtractOptions {
+            image_path: input.clone(),
+            output_path: output.clone(),
+            ..Default::default()
+        })?,
+    };
+    Ok(report)
+}
+
+fn print_jobs(jobs: &[JobInfo]) {
+    for info in jobs {
+        let (command, input, output) = match &info.job {
+            JobSpec::Compress { input, output, .. } => ("compress", input, output),
+            JobSpec::Decompress { input, output } => ("decompress", input, output),
+            JobSpec::CreateImage { input, output, .. } => ("create-image", input, output),
+            JobSpec::ExtractImage { input, output } => ("extract-image", input, output),
+        };
+        let state = match &info.state {
+            JobState::Queued => "queued".to_string(),
+            JobState::Running { started } => format!("running for {}s", unix_now().saturating_sub(*started)),
+            JobState::Succeeded { started, finished, skipped: 0, .. } => format!("done in {}s", finished - started),
+            JobState::Succeeded { started, finished, skipped, .. } => {
+                format!("done in {}s, {} skipped", finished - started, skipped)
+            }
+            JobState::Failed { error, .. } => format!("failed: {}", error),
+            JobState::Cancelled => "cancelled".to_string(),
+        };
+        println!("{:>4}  {:<13} {} -> {}  [{}]", info.id, command, input.display(), output.display(), state);
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// Confirmation refused on the terminal
+#[derive(Debug, thiserror::Error)]
+#[error("{0}")]
+struct Declined(String);
+
+/// Ask on the terminal before a destructive step; with `--yes` the answer is
+/// assumed, and without a terminal (scripts, cron) the operation proceeds with
+/// a warning
+fn confirm(cli: &Cli, question: &str) -> Result<()> {
+    if cli.yes {
+        return Ok(());
+    }
+    let stdin = std::io::stdin();
+    if !stdin.is_terminal() {
+        warn!(question, "No terminal to confirm on, proceeding");
+        return Ok(());
+    }
+    eprint!("{} [y/N] ", question);
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    stdin.read_line(&mut answer)?;
+    match answer.trim().to_lowercase().as_str() {
+        "y" | "yes" => Ok(()),
+        _ => Err(Declined("Aborted".to_string()).into()),
+    }
+}
+
+fn confirm_overwrite(cli: &Cli, output: &std::path::Path) -> Result<()> {
+    if output.exists() {
+        confirm(cli, &format!("{} already exists. Overwrite it?", output.display()))?;
+    }
+    Ok(())
+}
+
+fn confirm_extraction(cli: &Cli, output: &std::path::Path) -> Result<()> {
+    let non_empty = std::fs::read_dir(output).is_ok_and(|mut entries| entries.next().is_some());
+    if non_empty {
+        confirm(cli, &format!("{} is not empty. Extract into it?", output.display()))?;
+    }
+    Ok(())
+}
+
+/// Exit code for a failed operation
+fn classify(error: &anyhow::Error) -> u8 {
+    if error.downcast_ref::<Declined>().is_some() {
+        return exit_code::USAGE;
+    }
+    if let Some(e) = error.downcast_ref::<CompressionError>() {
+        return match e {
+            CompressionError::InvalidSnapshot { .. } => exit_code::CORRUPT,
+            _ => exit_code::IO,
+        };
+    }
+    if let Some(e) = error.downcast_ref::<DecompressionError>() {
+        return match e {
+            DecompressionError::Io(io) => io_code(io),
+            DecompressionError::PermissionDenied { .. }
+            | DecompressionError::EntryNotFound { .. }
+            | DecompressionError::CaseCollision(_) => exit_code::IO,
+            DecompressionError::ChecksumMismatch { .. } => exit_code::VERIFICATION,
+            _ => exit_code::CORRUPT,
+        };
+    }
+    if let Some(e) = error.downcast_ref::<ImageError>() {
+        return match e {
+            ImageError::Io(io) => io_code(io),
+            ImageError::PermissionDenied { .. } | ImageError::EntryNotFound { .. } | ImageError::CaseCollision(_) => {
+                exit_code::IO
+            }
+            ImageError::AmbiguousEntry { .. } => exit_code::USAGE,
+            ImageError::ChecksumMismatch { .. }
+            | ImageError::HashCollision(_)
+            | ImageError::ManifestVerification(_)
+            | ImageError::WriteVerification { .. } => {
+                exit_code::VERIFICATION
+            }
+            _ => exit_code::CORRUPT,
+        };
+    }
+    exit_code::IO
+}
+
+/// Truncated or garbled input surfaces as I/O errors while reading an archive
+fn io_code(error: &std::io::Error) -> u8 {
+    match error.kind() {
+        ErrorKind::UnexpectedEof | ErrorKind::InvalidData => exit_code::CORRUPT,
+        _ => exit_code::IO,
+    }
+}
+
+fn print_estimate(estimate: &Estimate, output: &std::path::Path) {
+    const MIB: f64 = 1024.0 * 1024.0;
+    println!("Files:           {}", estimate.files);
+    println!("Input size:      {:.1} MB", estimate.input_size as f64 / MIB);
+    println!("Sampled:         {:.1} MB", estimate.sampled_size as f64 / MIB);
+    if estimate.dedup_savings > 0 {
+        println!("Dedup savings:   ~{:.1} MB", estimate.dedup_savings as f64 / MIB);
+    }
+    println!("Output size:     ~{:.1} MB", estimate.output_size as f64 / MIB);
+    println!("Duration:        ~{:.1?}", estimate.duration);
+    if !estimate.largest.is_empty() {
+        println!("Largest files:");
+        for (path, size) in &estimate.largest {
+            println!("  {:>10.1} MB  {}", *size as f64 / MIB, path.display());
+        }
+    }
+
+    // The output file does not exist yet; query the closest existing directory
+    let destination = output
+        .ancestors()
+        .skip(1)
+        .find(|dir| dir.is_dir())
+        .unwrap_or(std::path::Path::new("."));
+    match available_space(destination) {
+        Ok(free) => {
+            println!("Free space:      {:.1} MB", free as f64 / MIB);
+            if estimate.output_size > free {
+                warn!(destination = %destination.display(), "Estimated output exceeds free space");
+            }
+        }
+        Err(e) => warn!(destination = %destination.display(), error = %e, "Could not query free space"),
+    }
+}
+
+/// Bytes compressed for the pre-flight estimate, less than `--dry-run` to answer quickly
+const PREFLIGHT_SAMPLE_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Summarize the input and ask before a long run, so that a wrong path is
+/// caught early; skipped with `--yes`, and without a terminal the run proceeds
+/// as in [`confirm`]
+fn preflight(cli: &Cli, options: EstimateOptions, output: &std::path::Path) -> Result<()> {
+    if cli.yes {
+        return Ok(());
+    }
+    if !std::io::stdin().is_terminal() {
+        return confirm(cli, "Proceed?");
+    }
+    let options = EstimateOptions { sample_size: PREFLIGHT_SAMPLE_SIZE, ..options };
+    print_estimate(&estimate(&options)?, output);
+    confirm(cli, "Proceed?")
+}
+
+fn print_analysis(analysis: &Analysis) {
+    println!(
+        "{:<10} {:<18} {:>8} {:>12} {:>12} {:>7}  advice",
+        "extension", "profile", "entries", "input", "output", "ratio"
+    );
+    for category in &analysis.categories {
+        let advice = match category.advice {
+            Some(Advice::Store) => "<- store without compressing".to_string(),
+            Some(Advice::Profile(profile)) => format!("<- use the {:?} profile", profile),
+            None => String::new(),
+        };
+        let line = format!(
+            "{:<10} {:<18} {:>8} {:>12} {:>12} {:>6.1}%  {}",
+            if category.extension.is_empty() { "(none)" } else { &category.extension },
+            format!("{:?}", category.profile),
+            category.entries,
+            category.input_size,
+            category.output_size,
+            category.ratio(),
+            advice,
+        );
+        println!("{}", line.trim_end());
+    }
+}
+
+fn print_prediction(prediction: &Prediction) {
+    const MIB: f64 = 1024.0 * 1024.0;
+    println!("{:<18} {:>8} {:>8} {:>12} {:>12} {:>25}", "class", "files", "sampled", "input MB", "output MB", "95% bounds MB");
+    for class in &prediction.classes {
+        println!(
+            "{:<18} {:>8} {:>8} {:>12.1} {:>12.1} {:>25}",
+            format!("{:?}", class.profile),
+            class.files,
+            class.sampled_blocks,
+            class.input_size as f64 / MIB,
+            class.output_size as f64 / MIB,
+            format!("{:.1} - {:.1}", class.low as f64 / MIB, class.high as f64 / MIB),
+        );
+    }
+    println!(
+        "Total: {:.1} MB -> ~{:.1} MB ({:.1} - {:.1} MB)",
+        prediction.input_size as f64 / MIB,
+        prediction.output_size as f64 / MIB,
+        prediction.low as f64 / MIB,
+        prediction.high as f64 / MIB,
+    );
+}
+
+fn print_duplicates(duplicates: &Duplicates) {
+    const MIB: f64 = 1024.0 * 1024.0;
+    for group in &duplicates.groups {
+        println!("{} copies of {} bytes, {:.1} MB wasted", group.paths.len(), group.size, group.wasted() as f64 / MIB);
+        for path in &group.paths {
+            println!("  {}", path.display());
+        }
+    }
+    println!(
+        "{} groups of identical files among {} files, {:.1} MB wasted",
+        duplicates.groups.len(), duplicates.files, duplicates.wasted() as f64 / MIB
+    );
+}
+
+fn print_bench(results: &[BenchResult]) {
+    println!(
+        "{:<6} {:>5} {:>12} {:>12} {:>8} {:>12} {:>12} {:>10}",
+        "codec", "level", "input", "output", "ratio", "comp MB/s", "decomp MB/s", "memory"
+    );
+    for result in results {
+        println!(
+            "{:<6} {:>5} {:>12} {:>12} {:>7.2}% {:>12.1} {:>12.1} {:>9.1}M",
+            result.codec,
+            result.level,
+            result.input_size,
+            result.compressed_size,
+            result.ratio(),
+            result.compress_speed(),
+            result.decompress_speed(),
+            result.memory as f64 / (1024.0 * 1024.0),
+        );
+    }
+}
+
+fn print_listing(listing: &Listing, long: bool) {
+    if !long {
+        for entry in &listing.entries {
+            println!("{}", entry.path.display());
+        }
+        return;
+    }
+    let optional = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+    println!(
+        "{:<4} {:>12} {:>12} {:>7} {:>7} {:<20}  name",
+        "type", "size", "compressed", "ratio", "blocks", "modified"
+    );
+    for entry in &listing.entries {
+        let modified = entry.modified.map(|seconds| {
+            humantime::format_rfc3339_seconds(SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(seconds)).to_string()
+        });
+        println!(
+            "{:<4} {:>12} {:>12} {:>7} {:>7} {:<20}  {}",
+            type_char(entry.kind),
+            entry.size,
+            optional(entry.compressed_size.map(|size| size.to_string())),
+            optional(entry.ratio().map(|ratio| format!("{:.1}%", ratio))),
+            optional(entry.blocks.as_ref().map(|blocks| blocks.len().to_string())),
+            optional(modified),
+            entry.path.display(),
+        );
+    }
+    println!(
+        "{:<4} {:>12} {:>12} {:>7} {:>7} {:<20}  {} entries ({})",
+        "",
+        listing.total_size(),
+        optional(listing.stored_size.map(|size| size.to_string())),
+        optional(listing.ratio().map(|ratio| format!("{:.1}%", ratio))),
+        "",
+        "",
+        listing.entries.len(),
+        format!("{:?}", listing.format).to_lowercase(),
+    );
+}
+
+fn parse_glob(pattern: &str) -> Result<glob::Pattern, String> {
+    glob::Pattern::new(pattern).map_err(|e| e.to_string())
+}
+
+fn parse_owner_map(spec: &str) -> Result<IdMap, String> {
+    IdMap::parse(spec, IdKind::User)
+}
+
+fn parse_group_map(spec: &str) -> Result<IdMap, String> {
+    IdMap::parse(spec, IdKind::Group)
+}
+
+fn print_tree(node: &TreeNode, prefix: &str) {
+    for (i, (name, child)) in node.children.iter().enumerate() {
+        let last = i + 1 == node.children.len();
+        let is_directory = child.kind.is_none_or(|kind| kind == EntryKind::Directory);
+        println!(
+            "{}{}{}{} ({} bytes)",
+            prefix,
+            if last { "└── " } else { "├── " },
+            name,
+            if is_directory { "/" } else { "" },
+            child.size
+        );
+        print_tree(child, &format!("{}{}", prefix, if last { "    " } else { "│   " }));
+    }
+}
+
+fn print_plan(report: &Report) {
+    for entry in &report.planned {
+        let mode = match (entry.action, entry.existing_mode) {
+            (PlannedAction::Replace, Some(mode)) => format!("  (mode {:o} reset)", mode),
+            _ => String::new(),
+        };
+        println!(
+            "{:<9} {:<9} {:>12}  {}{}",
+            format!("{:?}", entry.action).to_lowercase(),
+            format!("{:?}", entry.kind).to_lowercase(),
+            entry.size,
+            entry.path.display(),
+            mode
+        );
+    }
+
+    let count = |action| report.planned.iter().filter(|entry| entry.action == action).count();
+    println!(
+        "{} entries: {} created, {} overwritten, {} replaced, {} conflicts",
+        report.planned.len(),
+        count(PlannedAction::Create),
+        count(PlannedAction::Overwrite),
+        count(PlannedAction::Replace),
+        count(PlannedAction::Conflict)
+    );
+}
+
+fn print_report(report: &Report) {
+    if report.is_clean() {
+        return;
+    }
+    if !report.is_complete() {
+        warn!(count = report.skipped.len(), "Some entries were skipped");
+        eprintln!("{} entries skipped:", report.skipped.len());
+        for entry in &report.skipped {
+            eprintln!("  {}: {}", entry.path.display(), entry.reason);
+        }
+    }
+    if !report.warnings.is_empty() {
+        eprintln!("{} warnings:", report.warnings.len());
+        for warning in &report.warnings {
+            eprintln!("  [{:?}] {}: {}", warning.kind, warning.path.display(), warning.message);
+        }
+    }
+}
@@ -1,5 +1,8 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
+use std::thread::{self, ThreadId};
 use std::time::{Duration, Instant};
 use tracing::info;
 
@@ -20,11 +23,28 @@ pub struct Metrics {
     /// Total number of duplicate blocks found
     pub duplicate_blocks: AtomicU64,
     
+    /// Files expected, known once the input has been scanned
+    pub files_total: AtomicU64,
+    
+    /// Bytes expected, known once the input has been scanned
+    pub bytes_total: AtomicU64,
+    
+    /// What each worker thread is doing
+    active: Mutex<HashMap<ThreadId, Activity>>,
+    
     /// Total compression time tracking
     compression_timing: Mutex<Option<Instant>>,
     compression_duration: AtomicU64, // nanoseconds
 }
 
+/// File a worker thread is busy with
+#[derive(Debug, Clone)]
+pub struct Activity {
+    pub stage: &'static str,
+    pub path: PathBuf,
+    pub since: Instant,
+}
+
 impl Default for Metrics {
     fn default() -> Self {
         Self {
@@ -33,6 +53,9 @@ impl Default for Metrics {
             bytes_compressed: AtomicU64::new(0),
             unique_blocks: AtomicU64::new(0),
             duplicate_blocks: AtomicU64::new(0),
+            files_total: AtomicU64::new(0),
+            bytes_total: AtomicU64::new(0),
+            active: Mutex::new(HashMap::new()),
             compression_timing: Mutex::new(None),
             compression_duration: AtomicU64::new(0),
         }
@@ -79,6 +102,29 @@ impl Metrics {
         self.duplicate_blocks.fetch_add(1, Ordering::Relaxed);
     }
     
+    pub fn set_totals(&self, files: u64, bytes: u64) {
+        self.files_total.store(files, Ordering::Relaxed);
+        self.bytes_total.store(bytes, Ordering::Relaxed);
+    }
+    
+    /// The calling thread starts `stage` on `path`
+    pub fn begin(&self, stage: &'static str, path: &Path) {
+        let activity = Activity { stage, path: path.to_path_buf(), since: Instant::now() };
+        self.active.lock().unwrap_or_else(|e| e.into_inner()).insert(thread::current().id(), activity);
+    }
+    
+    /// The calling thread is idle again
+    pub fn finish(&self) {
+        self.active.lock().unwrap_or_else(|e| e.into_inner()).remove(&thread::current().id());
+    }
+    
+    /// Work in progress, longest-running first
+    pub fn activity(&self) -> Vec<Activity> {
+        let mut activity: Vec<Activity> = self.active.lock().unwrap_or_else(|e| e.into_inner()).values().cloned().collect();
+        activity.sort_by_key(|activity| activity.since);
+        activity
+    }
+    
     pub fn get_compression_ratio(&self) -> f64 {
         let processed = self.bytes_processed.load(Ordering::Relaxed);
         let compressed = self.bytes_compressed.load(Ordering::Relaxed);
@@ -139,6 +185,9 @@ impl Metrics {
         self.bytes_compressed.store(0, Ordering::Relaxed);
         self.unique_blocks.store(0, Ordering::Relaxed);
         self.duplicate_blocks.store(0, Ordering::Relaxed);
+        self.files_total.store(0, Ordering::Relaxed);
+        self.bytes_total.store(0, Ordering::Relaxed);
+        self.active.lock().unwrap_or_else(|e| e.into_inner()).clear();
         self.compression_duration.store(0, Ordering::Relaxed);
     }
 }
@@ -209,6 +258,19 @@ mod tests {
         assert_eq!(metrics.get_deduplication_ratio(), 60.0);
     }
     
+    #[test]
+    fn test_activity_per_thread() {
+        let metrics = Metrics::new();
+        metrics.begin("read", Path::new("a"));
+        let worker = Arc::clone(&metrics);
+        thread::spawn(move || worker.begin("dedup", Path::new("b"))).join().unwrap();
+        
+        let stages: Vec<_> = metrics.activity().iter().map(|activity| activity.stage).collect();
+        assert_eq!(stages, ["read", "dedup"]);
+        metrics.finish();
+        assert_eq!(metrics.activity()[0].path, Path::new("b"));
+    }
+    
     #[test]
     fn test_metrics_reset() {
         let metrics = Metrics::new();
@@ -0,0 +1,370 @@
+//! Read-only NBD server for `zippy nbd-serve`, exposing a file of an image
+//! (typically the only file of a disk image) as a network block device
+//!
+//! Implements the fixed newstyle handshake with `NBD_OPT_EXPORT_NAME`,
+//! `NBD_OPT_INFO`, `NBD_OPT_GO` and `NBD_OPT_LIST`, and simple replies.
+//! Writes are refused with `EPERM`.
+
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::thread;
+use tracing::{debug, info, info_span, warn};
+
+use crate::error::ImageError;
+use crate::image::ImageFile;
+
+/// Port registered for NBD
+pub const DEFAULT_PORT: u16 = 10809;
+
+const NBDMAGIC: u64 = 0x4e42_444d_4147_4943;
+const IHAVEOPT: u64 = 0x4948_4156_454f_5054;
+const REPLY_MAGIC: u64 = 0x0003_e889_0455_65a9;
+const REQUEST_MAGIC: u32 = 0x2560_9513;
+const SIMPLE_REPLY_MAGIC: u32 = 0x6744_6698;
+
+const FLAG_FIXED_NEWSTYLE: u16 = 1 << 0;
+const FLAG_NO_ZEROES: u16 = 1 << 1;
+const FLAG_HAS_FLAGS: u16 = 1 << 0;
+const FLAG_READ_ONLY: u16 = 1 << 1;
+const FLAG_SEND_FLUSH: u16 = 1 << 2;
+const FLAG_CAN_MULTI_CONN: u16 = 1 << 8;
+const TRANSMISSION_FLAGS: u16 = FLAG_HAS_FLAGS | FLAG_READ_ONLY | FLAG_SEND_FLUSH | FLAG_CAN_MULTI_CONN;
+
+const OPT_EXPORT_NAME: u32 = 1;
+const OPT_ABORT: u32 = 2;
+const OPT_LIST: u32 = 3;
+const OPT_INFO: u32 = 6;
+const OPT_GO: u32 = 7;
+
+const REP_ACK: u32 = 1;
+const REP_SERVER: u32 = 2;
+const REP_INFO: u32 = 3;
+const REP_ERR_UNSUP: u32 = (1 << 31) + 1;
+const REP_ERR_INVALID: u32 = (1 << 31) + 3;
+const REP_ERR_UNKNOWN: u32 = (1 << 31) + 6;
+const INFO_EXPORT: u16 = 0;
+
+const CMD_READ: u16 = 0;
+const CMD_WRITE: u16 = 1;
+const CMD_DISC: u16 = 2;
+const CMD_FLUSH: u16 = 3;
+
+const EPERM: u32 = 1;
+const EIO: u32 = 5;
+const EINVAL: u32 = 22;
+
+/// Largest read served in one request
+const MAX_READ: u32 = 32 * 1024 * 1024;
+/// Longest option payload accepted during the handshake
+const MAX_OPTION: u32 = 64 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct NbdOptions {
+    pub image_path: PathBuf,
+    /// File exported; the only file of the image when unset
+    pub entry_path: Option<PathBuf>,
+    /// Address to listen on
+    pub listen: String,
+    /// Name clients ask for; the empty default name is accepted too
+    pub export_name: String,
+}
+
+impl Default for NbdOptions {
+    fn default() -> Self {
+        Self {
+            image_path: PathBuf::new(),
+            entry_path: None,
+            listen: format!("127.0.0.1:{}", DEFAULT_PORT),
+            export_name: String::new(),
+        }
+    }
+}
+
+/// Serve the export until the process is stopped, one thread per connection
+pub fn serve(options: &NbdOptions) -> Result<(), ImageError> {
+    let listener = TcpListener::bind(&options.listen)?;
+    serve_on(listener, options)
+}
+
+fn serve_on(listener: TcpListener, options: &NbdOptions) -> Result<(), ImageError> {
+    // Fail on a missing or ambiguous entry before accepting clients
+    let file = ImageFile::open(&options.image_path, options.entry_path.as_deref())?;
+    info!(listen = %listener.local_addr()?, entry = %file.path().display(), size = file.size(), "NBD server listening");
+    let entry_path = Some(file.path().to_path_buf());
+    drop(file);
+
+    thread::scope(|scope| {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!(error = %e, "NBD connection failed");
+                    continue;
+                }
+            };
+            let entry_path = entry_path.clone();
+            scope.spawn(move || {
+                let peer = stream.peer_addr().map(|addr| addr.to_string()).unwrap_or_default();
+                let _span = info_span!("nbd", peer).entered();
+                let result = ImageFile::open(&options.image_path, entry_path.as_deref())
+                    .and_then(|mut file| Ok(handle(stream, &mut file, &options.export_name)?));
+                match result {
+                    Ok(()) => debug!("NBD client disconnected"),
+                    Err(e) => warn!(error = %e, "NBD connection ended"),
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+fn handle(stream: TcpStream, file: &mut ImageFile, export_name: &str) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+    if negotiate(&mut reader, &mut writer, export_name, file.size())? {
+        transmit(&mut reader, &mut writer, file)?;
+    }
+    Ok(())
+}
+
+/// Handshake and option haggling; `true` once the client enters transmission
+fn negotiate(reader: &mut impl Read, writer: &mut impl Write, export_name: &str, size: u64) -> io::Result<bool> {
+    writer.write_all(&NBDMAGIC.to_be_bytes())?;
+    writer.write_all(&IHAVEOPT.to_be_bytes())?;
+    writer.write_all(&(FLAG_FIXED_NEWSTYLE | FLAG_NO_ZEROES).to_be_bytes())?;
+    writer.flush()?;
+    let client_flags = read_u32(reader)?;
+    let no_zeroes = client_flags & FLAG_NO_ZEROES as u32 != 0;
+    let known = |name: &[u8]| name.is_empty() || name == export_name.as_bytes();
+
+    loop {
+        if read_u64(reader)? != IHAVEOPT {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "bad option magic"));
+        }
+        let option = read_u32(reader)?;
+        let length = read_u32(reader)?;
+        if length > MAX_OPTION {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "option too long"));
+        }
+        let mut data = vec![0u8; length as usize];
+        reader.read_exact(&mut data)?;
+        debug!(option, length, "NBD option");
+
+        match option {
+            OPT_EXPORT_NAME => {
+                // No way to report an error here but closing the connection
+                if !known(&data) {
+                    return Ok(false);
+                }
+                writer.write_all(&size.to_be_bytes())?;
+                writer.write_all(&TRANSMISSION_FLAGS.to_be_bytes())?;
+                if !no_zeroes {
+                    writer.write_all(&[0; 124])?;
+                }
+                writer.flush()?;
+                return Ok(true);
+            }
+            OPT_ABORT => {
+                option_reply(writer, option, REP_ACK, &[])?;
+                return Ok(false);
+            }
+            OPT_LIST => {
+                let mut server = (export_name.len() as u32).to_be_bytes().to_vec();
+                server.extend(export_name.as_bytes());
+                option_reply(writer, option, REP_SERVER, &server)?;
+                option_reply(writer, option, REP_ACK, &[])?;
+            }
+            OPT_INFO | OPT_GO => {
+                // Name length, name, then requested information types, ignored
+                let name = data
+                    .get(..4)
+                    .map(|length| u32::from_be_bytes(length.try_into().unwrap()) as usize)
+                    .and_then(|length| data.get(4..4 + length));
+                match name {
+                    None => option_reply(writer, option, REP_ERR_INVALID, &[])?,
+                    Some(name) if !known(name) => option_reply(writer, option, REP_ERR_UNKNOWN, &[])?,
+                    Some(_) => {
+                        let mut info = INFO_EXPORT.to_be_bytes().to_vec();
+                        info.extend(size.to_be_bytes());
+                        info.extend(TRANSMISSION_FLAGS.to_be_bytes());
+                        option_reply(writer, option, REP_INFO, &info)?;
+                        option_reply(writer, option, REP_ACK, &[])?;
+                        if option == OPT_GO {
+                            return Ok(true);
+                        }
+                    }
+                }
+            }
+            _ => option_reply(writer, option, REP_ERR_UNSUP, &[])?,
+        }
+    }
+}
+
+fn option_reply(writer: &mut impl Write, option: u32, reply: u32, data: &[u8]) -> io::Result<()> {
+    writer.write_all(&REPLY_MAGIC.to_be_bytes())?;
+    writer.write_all(&option.to_be_bytes())?;
+    writer.write_all(&reply.to_be_bytes())?;
+    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+    writer.write_all(data)?;
+    writer.flush()
+}
+
+/// Answer requests until the client disconnects
+fn transmit(reader: &mut impl Read, writer: &mut impl Write, file: &mut ImageFile) -> io::Result<()> {
+    let mut buffer = Vec::new();
+    loop {
+        let mut request = [0u8; 28];
+        match reader.read_exact(&mut request) {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            result => result?,
+        }
+        if u32::from_be_bytes(request[0..4].try_into().unwrap()) != REQUEST_MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "bad request magic"));
+        }
+        let command = u16::from_be_bytes(request[6..8].try_into().unwrap());
+        let handle = &request[8..16];
+        let offset = u64::from_be_bytes(request[16..24].try_into().unwrap());
+        let length = u32::from_be_bytes(request[24..28].try_into().unwrap());
+
+        let error = match command {
+            CMD_READ if length > MAX_READ || offset.checked_add(length as u64).is_none_or(|end| end > file.size()) => EINVAL,
+            CMD_READ => {
+                buffer.resize(length as usize, 0);
+                match file.read_at(offset, &mut buffer) {
+                    Ok(read) if read == buffer.len() => 0,
+                    Ok(_) => EIO,
+                    Err(e) => {
+                        warn!(offset, length, error = %e, "NBD read failed");
+                        EIO
+                    }
+                }
+            }
+            CMD_WRITE => {
+                // Consume the payload to stay in step with the client
+                io::copy(&mut reader.take(length as u64), &mut io::sink())?;
+                EPERM
+            }
+            CMD_DISC => return Ok(()),
+            CMD_FLUSH => 0,
+            _ => EINVAL,
+        };
+        writer.write_all(&SIMPLE_REPLY_MAGIC.to_be_bytes())?;
+        writer.write_all(&error.to_be_bytes())?;
+        writer.write_all(handle)?;
+        if command == CMD_READ && error == 0 {
+            writer.write_all(&buffer)?;
+        }
+        writer.flush()?;
+    }
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut buffer = [0u8; 4];
+    reader.read_exact(&mut buffer)?;
+    Ok(u32::from_be_bytes(buffer))
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut buffer = [0u8; 8];
+    reader.read_exact(&mut buffer)?;
+    Ok(u64::from_be_bytes(buffer))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::image::{create_image, ImageOptions};
+    use tempfile::tempdir;
+
+    /// Client side of `NBD_OPT_GO`; returns the export size
+    fn connect(stream: &mut TcpStream, name: &str) -> io::Result<u64> {
+        assert_eq!(read_u64(stream)?, NBDMAGIC);
+        assert_eq!(read_u64(stream)?, IHAVEOPT);
+        let mut flags = [0u8; 2];
+        stream.read_exact(&mut flags)?;
+        stream.write_all(&(FLAG_FIXED_NEWSTYLE as u32 | FLAG_NO_ZEROES as u32).to_be_bytes())?;
+
+        let mut data = (name.len() as u32).to_be_bytes().to_vec();
+        data.extend(name.as_bytes());
+        data.extend(0u16.to_be_bytes());
+        stream.write_all(&IHAVEOPT.to_be_bytes())?;
+        stream.write_all(&OPT_GO.to_be_bytes())?;
+        stream.write_all(&(data.len() as u32).to_be_bytes())?;
+        stream.write_all(&data)?;
+        let mut size = None;
+        loop {
+            assert_eq!(read_u64(stream)?, REPLY_MAGIC);
+            assert_eq!(read_u32(stream)?, OPT_GO);
+            let reply = read_u32(stream)?;
+            let mut data = vec![0u8; read_u32(stream)? as usize];
+            stream.read_exact(&mut data)?;
+            match reply {
+                REP_INFO => size = Some(u64::from_be_bytes(data[2..10].try_into().unwrap())),
+                REP_ACK => return Ok(size.unwrap()),
+                other => return Err(io::Error::other(format!("reply {:#x}", other))),
+            }
+        }
+    }
+
+    /// Send a request; returns the error and, for reads, the data
+    fn request(stream: &mut TcpStream, command: u16, offset: u64, length: u32, payload: &[u8]) -> (u32, Vec<u8>) {
+        let mut request = REQUEST_MAGIC.to_be_bytes().to_vec();
+        request.extend(0u16.to_be_bytes());
+        request.extend(command.to_be_bytes());
+        request.extend(42u64.to_be_bytes());
+        request.extend(offset.to_be_bytes());
+        request.extend(length.to_be_bytes());
+        request.extend(payload);
+        stream.write_all(&request).unwrap();
+        assert_eq!(read_u32(stream).unwrap(), SIMPLE_REPLY_MAGIC);
+        let error = read_u32(stream).unwrap();
+        assert_eq!(read_u64(stream).unwrap(), 42);
+        let mut data = vec![0u8; if command == CMD_READ && error == 0 { length as usize } else { 0 }];
+        stream.read_exact(&mut data).unwrap();
+        (error, data)
+    }
+
+    #[test]
+    fn test_reads_over_nbd() {
+        let temp_dir = tempdir().unwrap();
+        let disk_dir = temp_dir.path().join("disk");
+        fs::create_dir(&disk_dir).unwrap();
+        // Pseudo-random content, cut into several content-defined blocks
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let disk: Vec<u8> = (0..600_000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+        fs::write(disk_dir.join("disk.img"), &disk).unwrap();
+        let image_path = temp_dir.path().join("disk.zpak");
+        create_image(&ImageOptions { input_path: disk_dir, output_path: image_path.clone(), compression_level: 3, ..Default::default() }).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let options = NbdOptions { image_path, export_name: "disk".to_string(), ..Default::default() };
+        thread::spawn(move || serve_on(listener, &options));
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        assert!(connect(&mut stream, "other").is_err());
+        assert_eq!(connect(&mut TcpStream::connect(address).unwrap(), "disk").unwrap(), disk.len() as u64);
+        let mut stream = TcpStream::connect(address).unwrap();
+        assert_eq!(connect(&mut stream, "").unwrap(), disk.len() as u64);
+
+        for (offset, length) in [(0, 4096), (131_000, 200_000), (599_000, 1000)] {
+            let (error, data) = request(&mut stream, CMD_READ, offset, length, &[]);
+            assert_eq!(error, 0);
+            assert!(data == disk[offset as usize..][..length as usize], "read at {}", offset);
+        }
+        assert_eq!(request(&mut stream, CMD_READ, 599_000, 1001, &[]).0, EINVAL);
+        assert_eq!(request(&mut stream, CMD_WRITE, 0, 3, b"abc").0, EPERM);
+        assert_eq!(request(&mut stream, CMD_FLUSH, 0, 0, &[]).0, 0);
+        assert_eq!(request(&mut stream, CMD_READ, 10, 5, &[]).1, &disk[10..15]);
+    }
+}
@@ -0,0 +1,220 @@
+//! Export of a .zpak image as an OCI image layout for `zippy export-oci`
+//!
+//! The captured tree becomes a single zstd-compressed tar layer
+//! (`application/vnd.oci.image.layer.v1.tar+zstd`). The resulting directory can
+//! be loaded with `skopeo copy oci:DIR:TAG ...` or `podman load`.
+
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use tracing::info;
+
+use crate::error::ImageError;
+use crate::image::write_tar;
+
+pub const LAYER_MEDIA_TYPE: &str = "application/vnd.oci.image.layer.v1.tar+zstd";
+const MANIFEST_MEDIA_TYPE: &str = "application/vnd.oci.image.manifest.v1+json";
+const CONFIG_MEDIA_TYPE: &str = "application/vnd.oci.image.config.v1+json";
+
+pub struct OciOptions {
+    pub image_path: PathBuf,
+    /// Layout directory, created if needed
+    pub output_path: PathBuf,
+    /// Reference name recorded in `index.json`
+    pub tag: String,
+    /// OCI architecture of the container (`amd64`, `arm64`...)
+    pub architecture: String,
+    pub compression_level: i32,
+}
+
+impl Default for OciOptions {
+    fn default() -> Self {
+        Self {
+            image_path: PathBuf::new(),
+            output_path: PathBuf::new(),
+            tag: "latest".to_string(),
+            architecture: native_architecture().to_string(),
+            compression_level: 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OciExport {
+    /// `sha256:...` digest of the manifest
+    pub manifest_digest: String,
+    /// Compressed size of the layer
+    pub layer_size: u64,
+    /// Entries written to the layer
+    pub entries: u64,
+}
+
+/// OCI name of the architecture this binary was built for
+pub fn native_architecture() -> &'static str {
+    match std::env::consts::ARCH {
+        "x86_64" => "amd64",
+        "x86" => "386",
+        "aarch64" => "arm64",
+        "powerpc64" => "ppc64le",
+        arch => arch,
+    }
+}
+
+pub fn export_oci(options: &OciOptions) -> Result<OciExport, ImageError> {
+    info!(image = %options.image_path.display(), output = %options.output_path.display(), "Exporting OCI layout");
+    let blobs = options.output_path.join("blobs").join("sha256");
+    fs::create_dir_all(&blobs)?;
+
+    // The layer digest is only known once written: compress to a temporary blob, then rename it
+    let mut staging = tempfile::NamedTempFile::new_in(&blobs)?;
+    let mut compressed = HashingWriter::new(BufWriter::new(staging.as_file_mut()));
+    let mut encoder = zstd::Encoder::new(&mut compressed, options.compression_level)?;
+    let mut tar = HashingWriter::new(&mut encoder);
+    let entries = write_tar(&options.image_path, &mut tar)?;
+    let diff_id = tar.digest();
+    encoder.finish()?;
+    compressed.flush()?;
+    let (layer_digest, layer_size) = (compressed.digest(), compressed.written);
+    drop(compressed);
+    staging.persist(blob_path(&blobs, &layer_digest)).map_err(|e| e.error)?;
+
+    let config = json!({
+        "architecture": options.architecture,
+        "os": "linux",
+        "config": {},
+        "rootfs": { "type": "layers", "diff_ids": [diff_id] },
+    });
+    let (config_digest, config_size) = write_blob(&blobs, &config)?;
+    let manifest = json!({
+        "schemaVersion": 2,
+        "mediaType": MANIFEST_MEDIA_TYPE,
+        "config": { "mediaType": CONFIG_MEDIA_TYPE, "digest": config_digest, "size": config_size },
+        "layers": [{ "mediaType": LAYER_MEDIA_TYPE, "digest": layer_digest, "size": layer_size }],
+    });
+    let (manifest_digest, manifest_size) = write_blob(&blobs, &manifest)?;
+    let index = json!({
+        "schemaVersion": 2,
+        "manifests": [{
+            "mediaType": MANIFEST_MEDIA_TYPE,
+            "digest": manifest_digest,
+            "size": manifest_size,
+            "annotations": { "org.opencontainers.image.ref.name": options.tag },
+        }],
+    });
+    fs::write(options.output_path.join("index.json"), serde_json::to_vec_pretty(&index).map_err(io::Error::from)?)?;
+    fs::write(options.output_path.join("oci-layout"), br#"{"imageLayoutVersion": "1.0.0"}"#)?;
+
+    info!(entries, layer_size, "OCI export done");
+    Ok(OciExport { manifest_digest, layer_size, entries })
+}
+
+/// Store a JSON document as a blob; returns its digest and size
+fn write_blob(blobs: &Path, value: &serde_json::Value) -> io::Result<(String, u64)> {
+    let data = serde_json::to_vec(value)?;
+    let digest = format!("sha256:{}", hex(&Sha256::digest(&data)));
+    fs::write(blob_path(blobs, &digest), &data)?;
+    Ok((digest, data.len() as u64))
+}
+
+/// `blobs/sha256/<hex>` for `sha256:<hex>`
+fn blob_path(blobs: &Path, digest: &str) -> PathBuf {
+    blobs.join(digest.trim_start_matches("sha256:"))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Counts and hashes what passes through
+struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
+    written: u64,
+}
+
+impl<W: Write> HashingWriter<W> {
+    fn new(inner: W) -> Self {
+        Self { inner, hasher: Sha256::new(), written: 0 }
+    }
+
+    fn digest(&self) -> String {
+        format!("sha256:{}", hex(&self.hasher.clone().finalize()))
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use crate::image::{create_image, ImageOptions};
+    use tempfile::tempdir;
+
+    fn read_json(path: &Path) -> serde_json::Value {
+        serde_json::from_slice(&fs::read(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_export_layout() {
+        let temp_dir = tempdir().unwrap();
+        let rootfs = temp_dir.path().join("rootfs");
+        fs::create_dir_all(rootfs.join("etc")).unwrap();
+        fs::write(rootfs.join("etc/hostname"), "zippy\n").unwrap();
+        fs::write(rootfs.join("big.bin"), vec![7u8; 300_000]).unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("etc/hostname", rootfs.join("hostname")).unwrap();
+        let image_path = temp_dir.path().join("rootfs.zpak");
+        create_image(&ImageOptions { input_path: rootfs, output_path: image_path.clone(), compression_level: 3, ..Default::default() }).unwrap();
+
+        let layout = temp_dir.path().join("oci");
+        let options = OciOptions { image_path, output_path: layout.clone(), tag: "v1".to_string(), ..Default::default() };
+        let export = export_oci(&options).unwrap();
+
+        let blobs = layout.join("blobs/sha256");
+        let index = read_json(&layout.join("index.json"));
+        assert_eq!(index["manifests"][0]["digest"], export.manifest_digest.as_str());
+        assert_eq!(index["manifests"][0]["annotations"]["org.opencontainers.image.ref.name"], "v1");
+        let manifest = read_json(&blob_path(&blobs, &export.manifest_digest));
+        let layer = &manifest["layers"][0];
+        assert_eq!(layer["mediaType"], LAYER_MEDIA_TYPE);
+        assert_eq!(layer["size"], export.layer_size);
+
+        // Digests match the blobs, diff_id matches the uncompressed tar
+        let layer_blob = fs::read(blob_path(&blobs, layer["digest"].as_str().unwrap())).unwrap();
+        assert_eq!(format!("sha256:{}", hex(&Sha256::digest(&layer_blob))), layer["digest"].as_str().unwrap());
+        let tar = zstd::decode_all(&layer_blob[..]).unwrap();
+        let config = read_json(&blob_path(&blobs, manifest["config"]["digest"].as_str().unwrap()));
+        assert_eq!(config["rootfs"]["diff_ids"][0], format!("sha256:{}", hex(&Sha256::digest(&tar))).as_str());
+        assert_eq!(fs::read_dir(&blobs).unwrap().count(), 3);
+
+        let mut archive = tar::Archive::new(&tar[..]);
+        let mut contents = std::collections::BTreeMap::new();
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let path = entry.path().unwrap().to_string_lossy().into_owned();
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data).unwrap();
+            let link = entry.link_name().unwrap().map(|link| link.to_string_lossy().into_owned());
+            contents.insert(path, (entry.header().entry_type(), data.len(), link));
+        }
+        assert_eq!(contents["etc"].0, tar::EntryType::Directory);
+        assert_eq!(contents["etc/hostname"], (tar::EntryType::Regular, 6, None));
+        assert_eq!(contents["big.bin"].1, 300_000);
+        #[cfg(unix)]
+        assert_eq!(contents["hostname"], (tar::EntryType::Symlink, 0, Some("etc/hostname".to_string())));
+    }
+}
@@ -0,0 +1,70 @@
+//! Remapping of user and group ids when restoring ownership on extraction
+
+use std::collections::HashMap;
+
+use crate::platform;
+
+/// Whether the ids of an [`IdMap`] are users or groups
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdKind {
+    User,
+    Group,
+}
+
+/// Ids recorded in an image replaced by ids of the destination machine, parsed from
+/// `FROM:TO` pairs such as `1000:2000,33:www-data`. Sources are the numeric ids the
+/// image holds; targets are ids or names looked up on the machine extracting.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IdMap(HashMap<u32, u32>);
+
+impl IdMap {
+    pub fn parse(spec: &str, kind: IdKind) -> Result<Self, String> {
+        let mut ids = HashMap::new();
+        for pair in spec.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+            let (from, to) = pair.split_once(':').ok_or_else(|| format!("expected FROM:TO, got {:?}", pair))?;
+            let from = from.trim().parse().map_err(|_| format!("{:?} is not a numeric id", from.trim()))?;
+            ids.insert(from, resolve(to.trim(), kind)?);
+        }
+        Ok(Self(ids))
+    }
+
+    /// Id to give an entry recorded with `id`
+    pub fn map(&self, id: u32) -> u32 {
+        self.0.get(&id).copied().unwrap_or(id)
+    }
+}
+
+/// Numeric id, or the id of a user or group name on this machine
+fn resolve(name: &str, kind: IdKind) -> Result<u32, String> {
+    if let Ok(id) = name.parse() {
+        return Ok(id);
+    }
+    let found = match kind {
+        IdKind::User => platform::user_id(name),
+        IdKind::Group => platform::group_id(name),
+    };
+    match found {
+        Ok(Some(id)) => Ok(id),
+        Ok(None) => Err(format!("unknown {} {:?}", if kind == IdKind::User { "user" } else { "group" }, name)),
+        Err(e) => Err(format!("cannot look up {:?}: {}", name, e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_id_map() {
+        let map = IdMap::parse("1000:2000, 33:34,", IdKind::User).unwrap();
+        assert_eq!(map.map(1000), 2000);
+        assert_eq!(map.map(33), 34);
+        assert_eq!(map.map(0), 0);
+
+        #[cfg(unix)]
+        assert_eq!(IdMap::parse("1000:root", IdKind::User).unwrap().map(1000), 0);
+        assert!(IdMap::parse("1000", IdKind::User).is_err());
+        assert!(IdMap::parse("www-data:33", IdKind::Group).is_err());
+        assert!(IdMap::parse("1000:no-such-user-zippy", IdKind::User).is_err());
+    }
+}
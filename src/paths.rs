@@ -0,0 +1,370 @@
+//! Mapping of archive entry paths to output paths on extraction
+
+use std::collections::HashMap;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::error::CaseCollisionError;
+use crate::platform;
+use crate::report::{Report, WarningKind};
+
+/// Unicode normalization form applied to entry names on extraction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum NormalizationForm {
+    /// Composed form, expected on Linux and Windows
+    Nfc,
+    /// Decomposed form, as produced by older macOS filesystems
+    Nfd,
+}
+
+/// What to do when two entries differ only by case on a case-insensitive destination
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum CaseCollision {
+    /// Extract the later entry under a new name (`name (1).ext`)
+    #[default]
+    Rename,
+    /// Keep the first entry and skip the later one
+    Skip,
+    /// Abort the extraction
+    Error,
+}
+
+/// Rewrites entry paths according to the extraction options and detects
+/// distinct entries that would end up at the same output path
+#[derive(Debug, Default)]
+pub struct PathMapper {
+    normalization: Option<NormalizationForm>,
+    /// Output path -> archive path of the entry that claimed it
+    claimed: HashMap<PathBuf, PathBuf>,
+    /// Set when the destination folds case
+    case_collision: Option<CaseCollision>,
+    /// Case-folded output path -> (output path, is_directory)
+    folded: HashMap<PathBuf, (PathBuf, bool)>,
+}
+
+impl PathMapper {
+    pub fn new(normalization: Option<NormalizationForm>) -> Self {
+        Self { normalization, ..Default::default() }
+    }
+
+    /// Mapper for extracting into `destination`, detecting case collisions
+    /// only when the destination filesystem is case-insensitive
+    pub fn for_destination(
+        destination: &Path,
+        normalization: Option<NormalizationForm>,
+        case_collision: CaseCollision,
+    ) -> io::Result<Self> {
+        let mapper = Self::new(normalization);
+        if platform::is_case_insensitive(destination)? {
+            Ok(mapper.with_case_folding(case_collision))
+        } else {
+            Ok(mapper)
+        }
+    }
+
+    /// Mapper for a dry run, which must not probe the destination: case collisions
+    /// are detected on platforms whose filesystems usually fold case
+    pub fn for_dry_run(normalization: Option<NormalizationForm>, case_collision: CaseCollision) -> Self {
+        let mapper = Self::new(normalization);
+        if cfg!(any(windows, target_os = "macos")) {
+            mapper.with_case_folding(case_collision)
+        } else {
+            mapper
+        }
+    }
+
+    /// Treat output paths differing only by case as the same file
+    pub fn with_case_folding(mut self, policy: CaseCollision) -> Self {
+        self.case_collision = Some(policy);
+        self
+    }
+
+    /// Output path (relative to the extraction root) for an archive entry, or
+    /// `None` if the entry must be skipped.
+    /// When normalization would merge two distinct non-directory entries, the
+    /// later one keeps its original name so no data is overwritten.
+    /// Renamed and skipped entries are recorded in `report`.
+    pub fn map(
+        &mut self,
+        entry_path: &Path,
+        is_directory: bool,
+        report: &mut Report,
+    ) -> Result<Option<PathBuf>, CaseCollisionError> {
+        let output = self.normalized(entry_path, is_directory, report);
+        match self.case_collision {
+            Some(policy) => self.resolve_case(entry_path, output, is_directory, policy, report),
+            None => Ok(Some(output)),
+        }
+    }
+
+    fn normalized(&mut self, entry_path: &Path, is_directory: bool, report: &mut Report) -> PathBuf {
+        let Some(form) = self.normalization else {
+            return entry_path.to_path_buf();
+        };
+
+        let normalized = normalize(entry_path, form);
+        match self.claimed.get(&normalized) {
+            Some(owner) if owner != entry_path && !is_directory => {
+                report.warn(
+                    entry_path,
+                    WarningKind::Renamed,
+                    format!("Unicode normalization collision with {}, keeping the original name", owner.display()),
+                );
+                self.claimed.insert(entry_path.to_path_buf(), entry_path.to_path_buf());
+                entry_path.to_path_buf()
+            }
+            Some(_) => normalized,
+            None => {
+                self.claimed.insert(normalized.clone(), entry_path.to_path_buf());
+                normalized
+            }
+        }
+    }
+
+    fn resolve_case(
+        &mut self,
+        entry_path: &Path,
+        output: PathBuf,
+        is_directory: bool,
+        policy: CaseCollision,
+        report: &mut Report,
+    ) -> Result<Option<PathBuf>, CaseCollisionError> {
+        let key = fold_case(&output);
+        let owner = match self.folded.get(&key) {
+            // Same entry seen again, or directories merging into one
+            Some((owner, owner_is_directory)) if *owner != output && !(is_directory && *owner_is_directory) => {
+                owner.clone()
+            }
+            Some((owner, _)) => return Ok(Some(owner.clone())),
+            None => {
+                self.folded.insert(key, (output.clone(), is_directory));
+                return Ok(Some(output));
+            }
+        };
+
+        match policy {
+            CaseCollision::Error => Err(CaseCollisionError { entry: entry_path.to_path_buf(), existing: owner }),
+            CaseCollision::Skip => {
+                report.warn(
+                    entry_path,
+                    WarningKind::Ignored,
+                    format!("Case collision with {}, entry skipped", owner.display()),
+                );
+                Ok(None)
+            }
+            CaseCollision::Rename => {
+                let renamed = (1..)
+                    .map(|n| numbered(&output, n))
+                    .find(|candidate| !self.folded.contains_key(&fold_case(candidate)))
+                    .expect("unbounded range");
+                report.warn(
+                    entry_path,
+                    WarningKind::Renamed,
+                    format!("Case collision with {}, extracted as {}", owner.display(), renamed.display()),
+                );
+                self.folded.insert(fold_case(&renamed), (renamed.clone(), is_directory));
+                Ok(Some(renamed))
+            }
+        }
+    }
+}
+
+/// `dir/name.ext` -> `dir/name (n).ext`
+fn numbered(path: &Path, n: u32) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{stem} ({n}).{}", extension.to_string_lossy()),
+        None => format!("{stem} ({n})"),
+    };
+    path.with_file_name(name)
+}
+
+/// Lowercase every UTF-8 component of `path`; other components are kept as-is
+fn fold_case(path: &Path) -> PathBuf {
+    path.components()
+        .map(|component| match component.as_os_str().to_str() {
+            Some(name) => name.to_lowercase().into(),
+            None => component.as_os_str().to_os_string(),
+        })
+        .collect()
+}
+
+/// Validate and sanitize an archive entry path to prevent path traversal attacks.
+/// Returns `None` for absolute paths and paths with no usable component.
+pub fn sanitize_path(path: &Path) -> Option<PathBuf> {
+    // Reject absolute paths
+    if path.has_root() || path.components().any(|c| matches!(c, Component::Prefix(_))) {
+        return None;
+    }
+    
+    let mut safe_path = PathBuf::new();
+    for component in path.components() {
+        // Skip dangerous components
+        let Component::Normal(name) = component else {
+            continue;
+        };
+        
+        match name.to_str() {
+            Some(name) => {
+                // Archives created on Windows by older versions may use '\' separators
+                for part in name.split('\\') {
+                    if part.trim().is_empty() || part == "." || part == ".." {
+                        continue;
+                    }
+                    // Sanitize component by removing invalid characters
+                    safe_path.push(part.replace(['<', '>', ':', '"', '|', '?', '*'], "_"));
+                }
+            }
+            // Non-UTF-8 names are kept byte for byte
+            None => safe_path.push(name),
+        }
+    }
+    
+    // Ensure we have at least one valid component
+    if safe_path.as_os_str().is_empty() {
+        return None;
+    }
+    
+    Some(safe_path)
+}
+
+/// Check that writing `path` stays inside `canonical_root`, even when some of its
+/// parent directories are symlinks (e.g. created earlier by the same archive).
+/// The nearest existing ancestor is resolved, so this must be called before
+/// creating missing parent directories.
+pub fn resolves_within(canonical_root: &Path, path: &Path) -> io::Result<bool> {
+    let mut ancestor = path.parent();
+    while let Some(dir) = ancestor {
+        if dir.symlink_metadata().is_ok() {
+            return Ok(dir.canonicalize()?.starts_with(canonical_root));
+        }
+        ancestor = dir.parent();
+    }
+    Ok(false)
+}
+
+/// Check that `path` itself, with any symlink at its end, stays inside
+/// `canonical_root`; a path that does not exist yet is judged by its nearest
+/// existing ancestor, and a dangling symlink never passes
+pub fn stays_within(canonical_root: &Path, path: &Path) -> io::Result<bool> {
+    if path.symlink_metadata().is_err() {
+        return resolves_within(canonical_root, path);
+    }
+    match path.canonicalize() {
+        Ok(canonical) => Ok(canonical.starts_with(canonical_root)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Remove a symlink sitting where a file is about to be written, so the write
+/// cannot follow it outside the extraction root
+pub fn remove_symlink(path: &Path) -> io::Result<()> {
+    if path.symlink_metadata().is_ok_and(|m| m.file_type().is_symlink()) {
+        std::fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// Normalize every UTF-8 component of `path`; other components are kept as-is
+pub fn normalize(path: &Path, form: NormalizationForm) -> PathBuf {
+    path.components()
+        .map(|component| match component {
+            Component::Normal(name) => match name.to_str() {
+                Some(name) => match form {
+                    NormalizationForm::Nfc => name.nfc().collect::<String>().into(),
+                    NormalizationForm::Nfd => name.nfd().collect::<String>().into(),
+                },
+                None => name.to_os_string(),
+            },
+            other => other.as_os_str().to_os_string(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CAFE_NFC: &str = "caf\u{e9}";
+    const CAFE_NFD: &str = "cafe\u{301}";
+
+    #[test]
+    fn test_normalize() {
+        let nfd = PathBuf::from(CAFE_NFD).join("menu.txt");
+        assert_eq!(normalize(&nfd, NormalizationForm::Nfc), PathBuf::from(CAFE_NFC).join("menu.txt"));
+        assert_eq!(normalize(&nfd, NormalizationForm::Nfd), nfd);
+    }
+
+    #[test]
+    fn test_collision_keeps_original_name() {
+        let mut report = Report::default();
+        let mut mapper = PathMapper::new(Some(NormalizationForm::Nfc));
+        assert_eq!(mapper.map(Path::new(CAFE_NFC), false, &mut report).unwrap(), Some(PathBuf::from(CAFE_NFC)));
+        assert_eq!(mapper.map(Path::new(CAFE_NFD), false, &mut report).unwrap(), Some(PathBuf::from(CAFE_NFD)));
+
+        // Directories are merged instead
+        let mut mapper = PathMapper::new(Some(NormalizationForm::Nfc));
+        mapper.map(Path::new(CAFE_NFC), true, &mut report).unwrap();
+        assert_eq!(mapper.map(Path::new(CAFE_NFD), true, &mut report).unwrap(), Some(PathBuf::from(CAFE_NFC)));
+    }
+
+    #[test]
+    fn test_sanitize_path() {
+        assert_eq!(sanitize_path(Path::new("a/../b/./c.txt")).unwrap(), PathBuf::from("a/b/c.txt"));
+        assert_eq!(sanitize_path(Path::new("dir\\file?.txt")).unwrap(), PathBuf::from("dir/file_.txt"));
+        assert!(sanitize_path(Path::new("/etc/passwd")).is_none());
+        assert!(sanitize_path(Path::new("../..")).is_none());
+        assert!(sanitize_path(Path::new("")).is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_resolves_within_detects_symlinked_parents() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path().join("root");
+        let outside = temp_dir.path().join("outside");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::create_dir_all(&outside).unwrap();
+        std::os::unix::fs::symlink(&outside, root.join("escape")).unwrap();
+
+        let root = root.canonicalize().unwrap();
+        assert!(resolves_within(&root, &root.join("sub/dir/file.txt")).unwrap());
+        assert!(!resolves_within(&root, &root.join("escape/file.txt")).unwrap());
+        assert!(!resolves_within(&root, &root.join("escape/deeper/file.txt")).unwrap());
+        assert!(!stays_within(&root, &root.join("escape")).unwrap());
+        assert!(stays_within(&root, &root.join("sub/new.txt")).unwrap());
+    }
+
+    #[test]
+    fn test_no_normalization_is_identity() {
+        let mut report = Report::default();
+        let mut mapper = PathMapper::default();
+        assert_eq!(mapper.map(Path::new(CAFE_NFD), false, &mut report).unwrap(), Some(PathBuf::from(CAFE_NFD)));
+    }
+
+    #[test]
+    fn test_case_collision_policies() {
+        let mut report = Report::default();
+        let mut mapper = PathMapper::default().with_case_folding(CaseCollision::Rename);
+        assert_eq!(mapper.map(Path::new("Dir/Foo.txt"), false, &mut report).unwrap(), Some(PathBuf::from("Dir/Foo.txt")));
+        assert_eq!(mapper.map(Path::new("dir/foo.txt"), false, &mut report).unwrap(), Some(PathBuf::from("dir/foo (1).txt")));
+        assert_eq!(mapper.map(Path::new("DIR/FOO.TXT"), false, &mut report).unwrap(), Some(PathBuf::from("DIR/FOO (2).TXT")));
+        // Directories differing by case are merged
+        assert_eq!(mapper.map(Path::new("Dir"), true, &mut report).unwrap(), Some(PathBuf::from("Dir")));
+        assert_eq!(mapper.map(Path::new("DIR"), true, &mut report).unwrap(), Some(PathBuf::from("Dir")));
+        assert_eq!(report.warnings.len(), 2);
+        assert!(report.warnings.iter().all(|w| w.kind == WarningKind::Renamed));
+
+        let mut mapper = PathMapper::default().with_case_folding(CaseCollision::Skip);
+        mapper.map(Path::new("README"), false, &mut report).unwrap();
+        assert_eq!(mapper.map(Path::new("readme"), false, &mut report).unwrap(), None);
+
+        let mut mapper = PathMapper::default().with_case_folding(CaseCollision::Error);
+        mapper.map(Path::new("README"), false, &mut report).unwrap();
+        assert!(mapper.map(Path::new("readme"), false, &mut report).is_err());
+        // Only case-folded clashes count
+        assert!(mapper.map(Path::new("README.md"), false, &mut report).unwrap().is_some());
+    }
+}
@@ -0,0 +1,293 @@
+//! Bounded read → compress → write pipeline shared by archive and image creation
+//!
+//! Reader threads, hashing threads (images only) and compression threads run
+//! concurrently and hand items over through bounded channels, so I/O and CPU work overlap while at most
+//! `queue_depth` items are in flight. Results reach the writer in input order.
+//! Worker threads run inside the caller's tracing span.
+//!
+//! Stages take their byte buffers from a [`BufferPool`] and the writer gives them
+//! back once written, so a run over millions of files reuses a few allocations.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::thread;
+use crossbeam_channel::{bounded, Receiver, Sender};
+use tracing::Span;
+
+/// Thread and queue sizing for each stage
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PipelineOptions {
+    /// Threads reading input files
+    pub read_threads: usize,
+    /// Threads hashing image blocks for deduplication
+    pub hash_threads: usize,
+    /// Threads compressing data
+    pub compress_threads: usize,
+    /// Items read but not yet written; bounds memory use
+    pub queue_depth: usize,
+}
+
+impl PipelineOptions {
+    /// Sizing for `threads` CPU workers: a couple of readers keep them busy
+    /// on most disks, and hashing runs an order of magnitude faster than
+    /// compression, so a quarter of the workers keep up with the rest
+    pub fn for_threads(threads: usize) -> Self {
+        let threads = threads.max(1);
+        Self {
+            read_threads: threads.min(2),
+            hash_threads: threads.div_ceil(4),
+            compress_threads: threads,
+            queue_depth: threads * 4,
+        }
+    }
+}
+
+impl Default for PipelineOptions {
+    fn default() -> Self {
+        Self::for_threads(num_cpus::get())
+    }
+}
+
+/// Pooled buffers grown past this size are freed: most files and every block fit
+pub const MAX_POOLED_CAPACITY: usize = 4 * 1024 * 1024;
+
+/// Byte buffers handed back by the writer for the next items. At most
+/// `max_buffers` are kept, and none grown past `max_capacity`, so one huge file
+/// does not stay allocated for the rest of the run.
+pub struct BufferPool {
+    buffers: Mutex<Vec<Vec<u8>>>,
+    max_buffers: usize,
+    max_capacity: usize,
+}
+
+impl BufferPool {
+    pub fn new(max_buffers: usize, max_capacity: usize) -> Self {
+        Self { buffers: Mutex::new(Vec::new()), max_buffers, max_capacity }
+    }
+
+    /// An empty buffer, reusing a returned one when there is any
+    pub fn take(&self) -> Vec<u8> {
+        self.buffers.lock().unwrap_or_else(|e| e.into_inner()).pop().unwrap_or_default()
+    }
+
+    /// Keep `buffer` for a later `take`, or free it when the pool is full
+    pub fn give(&self, mut buffer: Vec<u8>) {
+        if buffer.capacity() == 0 || buffer.capacity() > self.max_capacity {
+            return;
+        }
+        buffer.clear();
+        let mut buffers = self.buffers.lock().unwrap_or_else(|e| e.into_inner());
+        if buffers.len() < self.max_buffers {
+            buffers.push(buffer);
+        }
+    }
+}
+
+/// Run every job through `read` then `compress`, each stage on its own pool of
+/// threads, and pass the results to `write` on the calling thread in job order.
+/// Jobs are pulled from `jobs` as the pipeline makes room, so they can be
+/// produced while earlier ones are processed. The first error returned by
+/// `write` stops the pipeline.
+pub fn run_pipeline<I, J, R, C, E>(
+    options: &PipelineOptions,
+    jobs: I,
+    read: impl Fn(J) -> R + Sync,
+    compress: impl Fn(R) -> C + Sync,
+    write: impl FnMut(C) -> Result<(), E>,
+) -> Result<(), E>
+where
+    I: IntoIterator<Item = J>,
+    I::IntoIter: Send,
+    J: Send,
+    R: Send,
+    C: Send,
+{
+    // A single thread only forwards items where a hashing stage would run
+    let options = PipelineOptions { hash_threads: 1, ..*options };
+    run_hashed_pipeline(&options, jobs, read, |item| item, compress, write)
+}
+
+/// Same as [`run_pipeline`] with a `hash` stage between reading and compressing,
+/// on its own pool of `hash_threads` threads: hashing and compression have very
+/// different costs, and sharing threads would make each wait for the other.
+pub fn run_hashed_pipeline<I, J, R, H, C, E>(
+    options: &PipelineOptions,
+    jobs: I,
+    read: impl Fn(J) -> R + Sync,
+    hash: impl Fn(R) -> H + Sync,
+    compress: impl Fn(H) -> C + Sync,
+    mut write: impl FnMut(C) -> Result<(), E>,
+) -> Result<(), E>
+where
+    I: IntoIterator<Item = J>,
+    I::IntoIter: Send,
+    J: Send,
+    R: Send,
+    H: Send,
+    C: Send,
+{
+    let depth = options.queue_depth.max(1);
+    let (job_tx, job_rx) = bounded::<(usize, J)>(depth);
+    let (read_tx, read_rx) = bounded::<(usize, R)>(depth);
+    let (hash_tx, hash_rx) = bounded::<(usize, H)>(depth);
+    let (done_tx, done_rx) = bounded::<(usize, C)>(depth);
+    // One token per item in flight, returned once the item is written, so a
+    // slow item cannot make the reorder buffer grow without limit
+    let (token_tx, token_rx) = bounded::<()>(depth);
+    for _ in 0..depth {
+        let _ = token_tx.send(());
+    }
+
+    let jobs = jobs.into_iter();
+    let parent = Span::current();
+    thread::scope(|scope| {
+        scope.spawn(move || {
+            for job in jobs.enumerate() {
+                if token_rx.recv().is_err() || job_tx.send(job).is_err() {
+                    return;
+                }
+            }
+        });
+        for _ in 0..options.read_threads.max(1) {
+            spawn_stage(scope, &parent, job_rx.clone(), read_tx.clone(), &read);
+        }
+        for _ in 0..options.hash_threads.max(1) {
+            spawn_stage(scope, &parent, read_rx.clone(), hash_tx.clone(), &hash);
+        }
+        for _ in 0..options.compress_threads.max(1) {
+            spawn_stage(scope, &parent, hash_rx.clone(), done_tx.clone(), &compress);
+        }
+        // Only the workers hold these now: channels close when a stage finishes
+        drop((job_rx, read_tx, read_rx, hash_tx, hash_rx, done_tx));
+
+        let mut pending = BTreeMap::new();
+        let mut next = 0;
+        let result = done_rx.iter().try_for_each(|(index, item)| {
+            pending.insert(index, item);
+            while let Some(item) = pending.remove(&next) {
+                write(item)?;
+                next += 1;
+                let _ = token_tx.send(());
+            }
+            Ok(())
+        });
+        // After an error, closing these makes every stage stop at its next send
+        drop((done_rx, token_tx));
+        result
+    })
+}
+
+fn spawn_stage<'scope, 'env, I: Send + 'scope, O: Send + 'scope>(
+    scope: &'scope thread::Scope<'scope, 'env>,
+    parent: &Span,
+    input: Receiver<(usize, I)>,
+    output: Sender<(usize, O)>,
+    stage: &'scope (impl Fn(I) -> O + Sync),
+) {
+    let parent = parent.clone();
+    scope.spawn(move || {
+        let _entered = parent.enter();
+        for (index, item) in input.iter() {
+            if output.send((index, stage(item))).is_err() {
+                return;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_pipeline_keeps_order() {
+        let options = PipelineOptions { read_threads: 3, hash_threads: 2, compress_threads: 4, queue_depth: 2 };
+        let mut output = Vec::new();
+        run_pipeline(
+            &options,
+            0..200u64,
+            |n| {
+                // Uneven delays shuffle completion order
+                thread::sleep(std::time::Duration::from_micros((n * 37) % 200));
+                n
+            },
+            |n| n * 2,
+            |n| {
+                output.push(n);
+                Ok::<_, ()>(())
+            },
+        ).unwrap();
+
+        assert_eq!(output, (0..200u64).map(|n| n * 2).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_pipeline_stops_on_error() {
+        let read = AtomicUsize::new(0);
+        let result = run_pipeline(
+            &PipelineOptions { read_threads: 1, hash_threads: 1, compress_threads: 1, queue_depth: 4 },
+            0..10_000,
+            |n: usize| {
+                read.fetch_add(1, Ordering::Relaxed);
+                n
+            },
+            |n| n,
+            |n| if n == 5 { Err(n) } else { Ok(()) },
+        );
+
+        assert_eq!(result, Err(5));
+        assert!(read.load(Ordering::Relaxed) < 100);
+    }
+
+    #[test]
+    fn test_buffer_pool_reuse() {
+        let pool = BufferPool::new(1, 1024);
+        let mut buffer = pool.take();
+        buffer.extend_from_slice(&[1; 100]);
+        let address = buffer.as_ptr();
+        pool.give(buffer);
+        // Full pool and oversized buffers are freed
+        pool.give(Vec::with_capacity(10));
+        pool.give(Vec::with_capacity(2048));
+
+        let reused = pool.take();
+        assert!(reused.is_empty() && reused.capacity() >= 100);
+        assert_eq!(reused.as_ptr(), address);
+        assert_eq!(pool.take().capacity(), 0);
+    }
+
+    #[test]
+    fn test_hashed_pipeline_stages() {
+        let options = PipelineOptions { read_threads: 2, hash_threads: 3, compress_threads: 2, queue_depth: 4 };
+        let hashers = Mutex::new(HashSet::new());
+        let compressors = Mutex::new(HashSet::new());
+        let mut output = Vec::new();
+        run_hashed_pipeline(
+            &options,
+            0..100u64,
+            |n| n,
+            |n| {
+                hashers.lock().unwrap().insert(thread::current().id());
+                thread::sleep(std::time::Duration::from_micros((n * 53) % 300));
+                (n, n + 1)
+            },
+            |(n, hash)| {
+                compressors.lock().unwrap().insert(thread::current().id());
+                n * hash
+            },
+            |n| {
+                output.push(n);
+                Ok::<_, ()>(())
+            },
+        ).unwrap();
+
+        assert_eq!(output, (0..100u64).map(|n| n * (n + 1)).collect::<Vec<_>>());
+        // Each stage runs on its own threads
+        let (hashers, compressors) = (hashers.into_inner().unwrap(), compressors.into_inner().unwrap());
+        assert!(hashers.len() <= 3 && compressors.len() <= 2);
+        assert!(hashers.is_disjoint(&compressors));
+    }
+}
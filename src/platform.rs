@@ -0,0 +1,536 @@
+//! Platform-specific filesystem operations
+
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+
+use crate::walk::EntryKind;
+
+/// Whether the filesystem holding `dir` treats names differing only by case as
+/// the same file. Probes by creating a temporary file in `dir`.
+pub fn is_case_insensitive(dir: &Path) -> io::Result<bool> {
+    let name = format!(".zippy-case-probe-{}", std::process::id());
+    let probe = dir.join(&name);
+    File::create(&probe)?;
+    let folded = dir.join(name.to_uppercase()).exists();
+    fs::remove_file(&probe)?;
+    Ok(folded)
+}
+
+/// Create `dst` as a copy-on-write clone of `src` (FICLONE on Linux, clonefile on macOS).
+/// Fails with `Unsupported` when the platform or filesystem cannot share extents.
+#[cfg(target_os = "linux")]
+pub fn reflink(src: &Path, dst: &Path) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let source = File::open(src)?;
+    let target = File::create(dst)?;
+    // SAFETY: both descriptors are valid for the duration of the call
+    let result = unsafe { libc::ioctl(target.as_raw_fd(), libc::FICLONE, source.as_raw_fd()) };
+    if result == -1 {
+        let error = io::Error::last_os_error();
+        drop(target);
+        let _ = fs::remove_file(dst);
+        return Err(error);
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+pub fn reflink(src: &Path, dst: &Path) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let src = CString::new(src.as_os_str().as_bytes())?;
+    let dst = CString::new(dst.as_os_str().as_bytes())?;
+    // SAFETY: both pointers are valid NUL-terminated strings
+    if unsafe { libc::clonefile(src.as_ptr(), dst.as_ptr(), 0) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn reflink(_src: &Path, _dst: &Path) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "reflinks are not supported on this platform"))
+}
+
+/// Bytes available to unprivileged users on the filesystem holding `path`
+#[cfg(unix)]
+pub fn available_space(path: &Path) -> io::Result<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    // SAFETY: the path is a valid NUL-terminated string and `stat` is plain data
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+pub fn available_space(_path: &Path) -> io::Result<u64> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "free space query is not supported on this platform"))
+}
+
+/// Flush `file` to the device and evict its pages from the page cache, so that the
+/// next read comes from the disk rather than from memory
+#[cfg(target_os = "linux")]
+pub fn drop_cached(file: &File) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    file.sync_data()?;
+    // SAFETY: the descriptor is valid for the duration of the call
+    let result = unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED) };
+    if result != 0 {
+        return Err(io::Error::from_raw_os_error(result));
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn drop_cached(file: &File) -> io::Result<()> {
+    file.sync_data()?;
+    Err(io::Error::new(io::ErrorKind::Unsupported, "page cache eviction is not supported on this platform"))
+}
+
+/// Win32 security descriptor APIs from advapi32
+#[cfg(windows)]
+mod security {
+    use std::ffi::c_void;
+
+    pub const OWNER_SECURITY_INFORMATION: u32 = 0x1;
+    pub const GROUP_SECURITY_INFORMATION: u32 = 0x2;
+    pub const DACL_SECURITY_INFORMATION: u32 = 0x4;
+    pub const SACL_SECURITY_INFORMATION: u32 = 0x8;
+    pub const SDDL_REVISION_1: u32 = 1;
+    pub const ERROR_INSUFFICIENT_BUFFER: i32 = 122;
+    pub const ERROR_PRIVILEGE_NOT_HELD: i32 = 1314;
+
+    #[link(name = "advapi32")]
+    extern "system" {
+        pub fn GetFileSecurityW(name: *const u16, info: u32, descriptor: *mut c_void, length: u32, needed: *mut u32) -> i32;
+        pub fn SetFileSecurityW(name: *const u16, info: u32, descriptor: *const c_void) -> i32;
+        pub fn ConvertSecurityDescriptorToStringSecurityDescriptorW(
+            descriptor: *const c_void,
+            revision: u32,
+            info: u32,
+            sddl: *mut *mut u16,
+            length: *mut u32,
+        ) -> i32;
+        pub fn ConvertStringSecurityDescriptorToSecurityDescriptorW(
+            sddl: *const u16,
+            revision: u32,
+            descriptor: *mut *mut c_void,
+            length: *mut u32,
+        ) -> i32;
+    }
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        pub fn LocalFree(memory: *mut c_void) -> *mut c_void;
+    }
+}
+
+#[cfg(windows)]
+fn wide(value: &std::ffi::OsStr) -> Vec<u16> {
+    use std::os::windows::ffi::OsStrExt;
+
+    value.encode_wide().chain(std::iter::once(0)).collect()
+}
+
+/// Security descriptor of `path` (owner, group, DACL, and SACL when the process
+/// holds SeSecurityPrivilege) as an SDDL string
+#[cfg(windows)]
+pub fn read_security_descriptor(path: &Path) -> io::Result<Option<String>> {
+    use security::*;
+    use std::ffi::c_void;
+
+    let name = wide(path.as_os_str());
+    let mut info = OWNER_SECURITY_INFORMATION | GROUP_SECURITY_INFORMATION | DACL_SECURITY_INFORMATION | SACL_SECURITY_INFORMATION;
+    let mut descriptor = Vec::new();
+    loop {
+        let mut needed = 0;
+        // SAFETY: the name is NUL-terminated and the buffer holds `descriptor.len()` bytes
+        let result = unsafe {
+            GetFileSecurityW(name.as_ptr(), info, descriptor.as_mut_ptr().cast(), descriptor.len() as u32, &mut needed)
+        };
+        if result != 0 {
+            break;
+        }
+        let error = io::Error::last_os_error();
+        match error.raw_os_error() {
+            Some(ERROR_INSUFFICIENT_BUFFER) => descriptor.resize(needed as usize, 0u8),
+            // Reading the SACL is privileged: keep the rest of the descriptor
+            Some(ERROR_PRIVILEGE_NOT_HELD) if info & SACL_SECURITY_INFORMATION != 0 => info &= !SACL_SECURITY_INFORMATION,
+            _ => return Err(error),
+        }
+    }
+    let mut sddl: *mut u16 = std::ptr::null_mut();
+    let mut length = 0;
+    // SAFETY: `descriptor` holds the self-relative descriptor filled in above
+    let converted = unsafe {
+        ConvertSecurityDescriptorToStringSecurityDescriptorW(
+            descriptor.as_ptr().cast(),
+            SDDL_REVISION_1,
+            info,
+            &mut sddl,
+            &mut length,
+        )
+    };
+    if converted == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: the string was allocated by the call above, `length` includes the NUL
+    let text = unsafe { String::from_utf16_lossy(std::slice::from_raw_parts(sddl, length.saturating_sub(1) as usize)) };
+    unsafe { LocalFree(sddl.cast::<c_void>()) };
+    Ok(Some(text))
+}
+
+#[cfg(not(windows))]
+pub fn read_security_descriptor(_path: &Path) -> io::Result<Option<String>> {
+    Ok(None)
+}
+
+/// Apply an SDDL security descriptor to `path`. Only the parts present in the
+/// string are set; the SACL is dropped when the process may not write it
+#[cfg(windows)]
+pub fn write_security_descriptor(path: &Path, sddl: &str) -> io::Result<()> {
+    use security::*;
+    use std::ffi::{c_void, OsStr};
+
+    let name = wide(path.as_os_str());
+    let text = wide(OsStr::new(sddl));
+    let mut descriptor: *mut c_void = std::ptr::null_mut();
+    // SAFETY: the string is NUL-terminated; the descriptor is freed below
+    if unsafe { ConvertStringSecurityDescriptorToSecurityDescriptorW(text.as_ptr(), SDDL_REVISION_1, &mut descriptor, std::ptr::null_mut()) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut info = 0;
+    for (prefix, flag) in [
+        ("O:", OWNER_SECURITY_INFORMATION),
+        ("G:", GROUP_SECURITY_INFORMATION),
+        ("D:", DACL_SECURITY_INFORMATION),
+        ("S:", SACL_SECURITY_INFORMATION),
+    ] {
+        if sddl.contains(prefix) {
+            info |= flag;
+        }
+    }
+    let result = loop {
+        // SAFETY: the name is NUL-terminated and the descriptor valid until freed
+        if unsafe { SetFileSecurityW(name.as_ptr(), info, descriptor) } != 0 {
+            break Ok(());
+        }
+        let error = io::Error::last_os_error();
+        match error.raw_os_error() {
+            Some(ERROR_PRIVILEGE_NOT_HELD) if info & SACL_SECURITY_INFORMATION != 0 => info &= !SACL_SECURITY_INFORMATION,
+            _ => break Err(error),
+        }
+    };
+    // SAFETY: allocated by ConvertStringSecurityDescriptorToSecurityDescriptorW
+    unsafe { LocalFree(descriptor) };
+    result
+}
+
+#[cfg(not(windows))]
+pub fn write_security_descriptor(_path: &Path, _sddl: &str) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "security descriptors are not supported on this platform"))
+}
+
+/// Names of the alternate data streams of `path` (NTFS `file:name`), without the
+/// unnamed default stream; always empty outside Windows
+#[cfg(windows)]
+pub fn data_streams(path: &Path) -> io::Result<Vec<String>> {
+    use std::ffi::c_void;
+
+    #[repr(C)]
+    struct FindStreamData {
+        size: i64,
+        name: [u16; 296],
+    }
+
+    const FIND_STREAM_INFO_STANDARD: u32 = 0;
+    const ERROR_HANDLE_EOF: i32 = 38;
+    const INVALID_HANDLE_VALUE: *mut c_void = -1isize as *mut c_void;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn FindFirstStreamW(name: *const u16, level: u32, data: *mut FindStreamData, flags: u32) -> *mut c_void;
+        fn FindNextStreamW(handle: *mut c_void, data: *mut FindStreamData) -> i32;
+        fn FindClose(handle: *mut c_void) -> i32;
+    }
+
+    let name = wide(path.as_os_str());
+    let mut data = FindStreamData { size: 0, name: [0; 296] };
+    // SAFETY: the name is NUL-terminated and `data` has the WIN32_FIND_STREAM_DATA layout
+    let handle = unsafe { FindFirstStreamW(name.as_ptr(), FIND_STREAM_INFO_STANDARD, &mut data, 0) };
+    if handle == INVALID_HANDLE_VALUE {
+        let error = io::Error::last_os_error();
+        // No stream at all, e.g. a directory without named streams
+        return if error.raw_os_error() == Some(ERROR_HANDLE_EOF) { Ok(Vec::new()) } else { Err(error) };
+    }
+    let mut streams = Vec::new();
+    let result = loop {
+        // Names look like `:name:$DATA`, `::$DATA` for the default stream
+        let length = data.name.iter().position(|&c| c == 0).unwrap_or(data.name.len());
+        let full = String::from_utf16_lossy(&data.name[..length]);
+        if let Some(stream) = full.strip_prefix(':').and_then(|name| name.strip_suffix(":$DATA")) {
+            if !stream.is_empty() {
+                streams.push(stream.to_string());
+            }
+        }
+        // SAFETY: the handle is open until FindClose below
+        if unsafe { FindNextStreamW(handle, &mut data) } == 0 {
+            let error = io::Error::last_os_error();
+            break if error.raw_os_error() == Some(ERROR_HANDLE_EOF) { Ok(streams) } else { Err(error) };
+        }
+    };
+    // SAFETY: the handle came from FindFirstStreamW
+    unsafe { FindClose(handle) };
+    result
+}
+
+#[cfg(not(windows))]
+pub fn data_streams(_path: &Path) -> io::Result<Vec<String>> {
+    Ok(Vec::new())
+}
+
+/// Major and minor numbers of a character or block device
+#[cfg(unix)]
+pub fn device_numbers(metadata: &fs::Metadata) -> (u32, u32) {
+    use std::os::unix::fs::MetadataExt;
+
+    let rdev = metadata.rdev() as libc::dev_t;
+    (libc::major(rdev) as u32, libc::minor(rdev) as u32)
+}
+
+#[cfg(not(unix))]
+pub fn device_numbers(_metadata: &fs::Metadata) -> (u32, u32) {
+    (0, 0)
+}
+
+/// Create a FIFO, socket or device node at `path`, readable by everyone and
+/// writable by its owner. Device nodes need CAP_MKNOD (usually root).
+#[cfg(unix)]
+pub fn make_node(path: &Path, kind: EntryKind, (major, minor): (u32, u32)) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let file_type = match kind {
+        EntryKind::Fifo => libc::S_IFIFO,
+        EntryKind::Socket => libc::S_IFSOCK,
+        EntryKind::CharDevice => libc::S_IFCHR,
+        EntryKind::BlockDevice => libc::S_IFBLK,
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "not a special file")),
+    };
+    let device = match kind {
+        EntryKind::CharDevice | EntryKind::BlockDevice => libc::makedev(major as _, minor as _),
+        _ => 0,
+    };
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    // SAFETY: the path is a valid NUL-terminated string
+    if unsafe { libc::mknod(path.as_ptr(), file_type | 0o644, device) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn make_node(_path: &Path, _kind: EntryKind, _numbers: (u32, u32)) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "special files are not supported on this platform"))
+}
+
+/// Whether the process may give files away and create device nodes (root on Unix)
+#[cfg(unix)]
+pub fn is_privileged() -> bool {
+    // SAFETY: geteuid has no preconditions
+    unsafe { libc::geteuid() == 0 }
+}
+
+#[cfg(not(unix))]
+pub fn is_privileged() -> bool {
+    false
+}
+
+/// Effective user and group ids of the process, given to the files it creates
+#[cfg(unix)]
+pub fn effective_ids() -> Option<(u32, u32)> {
+    // SAFETY: geteuid and getegid have no preconditions
+    unsafe { Some((libc::geteuid(), libc::getegid())) }
+}
+
+#[cfg(not(unix))]
+pub fn effective_ids() -> Option<(u32, u32)> {
+    None
+}
+
+/// Owner, group and permission bits of an entry; `None` where files have no Unix ownership
+#[cfg(unix)]
+pub fn unix_attributes(metadata: &fs::Metadata) -> Option<(u32, u32, u32)> {
+    use std::os::unix::fs::MetadataExt;
+
+    Some((metadata.uid(), metadata.gid(), metadata.mode() & 0o7777))
+}
+
+#[cfg(not(unix))]
+pub fn unix_attributes(_metadata: &fs::Metadata) -> Option<(u32, u32, u32)> {
+    None
+}
+
+/// Give `path` to `uid` and `gid`, the link itself for a symlink; needs root
+/// (CAP_CHOWN) for any owner but the caller. Nothing to do outside Unix.
+#[cfg(unix)]
+pub fn set_owner(path: &Path, uid: u32, gid: u32) -> io::Result<()> {
+    std::os::unix::fs::lchown(path, Some(uid), Some(gid))
+}
+
+#[cfg(not(unix))]
+pub fn set_owner(_path: &Path, _uid: u32, _gid: u32) -> io::Result<()> {
+    Ok(())
+}
+
+/// Set the permission bits (including setuid, setgid and sticky) of `path`,
+/// never following a symlink: a link planted at `path` fails instead of
+/// handing its target the mode
+#[cfg(unix)]
+pub fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::os::unix::io::AsRawFd;
+
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    // SAFETY: the path is NUL-terminated
+    if unsafe { libc::fchmodat(libc::AT_FDCWD, c_path.as_ptr(), mode as libc::mode_t, libc::AT_SYMLINK_NOFOLLOW) } == 0 {
+        return Ok(());
+    }
+    let error = io::Error::last_os_error();
+    // C libraries without AT_SYMLINK_NOFOLLOW support (or without /proc on Linux):
+    // change the mode through a descriptor opened without following links
+    let unsupported = error.raw_os_error().is_some_and(|code| [libc::EOPNOTSUPP, libc::ENOTSUP, libc::ENOSYS].contains(&code));
+    let file_type = fs::symlink_metadata(path)?.file_type();
+    if !unsupported || !(file_type.is_dir() || file_type.is_file()) {
+        return Err(error);
+    }
+    let file = fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NOFOLLOW | libc::O_NONBLOCK | libc::O_CLOEXEC)
+        .open(path)?;
+    // SAFETY: the descriptor stays open for the duration of the call
+    if unsafe { libc::fchmod(file.as_raw_fd(), mode as libc::mode_t) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn set_mode(_path: &Path, _mode: u32) -> io::Result<()> {
+    Ok(())
+}
+
+/// Set the access and modification times of `path` (the link itself for a
+/// symlink), in seconds since the epoch; `None` leaves a time unchanged
+#[cfg(unix)]
+pub fn set_times(path: &Path, accessed: Option<u64>, modified: Option<u64>) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let time = |seconds: Option<u64>| {
+        // SAFETY: timespec is plain data
+        let mut time: libc::timespec = unsafe { std::mem::zeroed() };
+        match seconds {
+            Some(seconds) => time.tv_sec = seconds as libc::time_t,
+            None => time.tv_nsec = libc::UTIME_OMIT,
+        }
+        time
+    };
+    let times = [time(accessed), time(modified)];
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    // SAFETY: the path is NUL-terminated and `times` holds the two entries utimensat reads
+    if unsafe { libc::utimensat(libc::AT_FDCWD, path.as_ptr(), times.as_ptr(), libc::AT_SYMLINK_NOFOLLOW) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Directories cannot be opened for writing here, so only file times are set
+#[cfg(not(unix))]
+pub fn set_times(path: &Path, accessed: Option<u64>, modified: Option<u64>) -> io::Result<()> {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    if path.is_dir() {
+        return Ok(());
+    }
+    let mut times = fs::FileTimes::new();
+    if let Some(accessed) = accessed {
+        times = times.set_accessed(UNIX_EPOCH + Duration::from_secs(accessed));
+    }
+    if let Some(modified) = modified {
+        times = times.set_modified(UNIX_EPOCH + Duration::from_secs(modified));
+    }
+    File::options().write(true).open(path)?.set_times(times)
+}
+
+/// Uid of a user name in the user database
+#[cfg(unix)]
+pub fn user_id(name: &str) -> io::Result<Option<u32>> {
+    let name = std::ffi::CString::new(name)?;
+    let mut buffer = vec![0 as libc::c_char; 16 * 1024];
+    // SAFETY: plain data filled in by getpwnam_r
+    let mut entry: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut found = std::ptr::null_mut();
+    // SAFETY: every pointer is valid and `buffer.len()` is the size of `buffer`
+    let code = unsafe { libc::getpwnam_r(name.as_ptr(), &mut entry, buffer.as_mut_ptr(), buffer.len(), &mut found) };
+    if code != 0 {
+        return Err(io::Error::from_raw_os_error(code));
+    }
+    Ok((!found.is_null()).then_some(entry.pw_uid))
+}
+
+#[cfg(not(unix))]
+pub fn user_id(_name: &str) -> io::Result<Option<u32>> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "user names are not supported on this platform"))
+}
+
+/// Gid of a group name in the group database
+#[cfg(unix)]
+pub fn group_id(name: &str) -> io::Result<Option<u32>> {
+    let name = std::ffi::CString::new(name)?;
+    let mut buffer = vec![0 as libc::c_char; 16 * 1024];
+    // SAFETY: plain data filled in by getgrnam_r
+    let mut entry: libc::group = unsafe { std::mem::zeroed() };
+    let mut found = std::ptr::null_mut();
+    // SAFETY: every pointer is valid and `buffer.len()` is the size of `buffer`
+    let code = unsafe { libc::getgrnam_r(name.as_ptr(), &mut entry, buffer.as_mut_ptr(), buffer.len(), &mut found) };
+    if code != 0 {
+        return Err(io::Error::from_raw_os_error(code));
+    }
+    Ok((!found.is_null()).then_some(entry.gr_gid))
+}
+
+#[cfg(not(unix))]
+pub fn group_id(_name: &str) -> io::Result<Option<u32>> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "group names are not supported on this platform"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_case_probe_cleans_up() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        is_case_insensitive(temp_dir.path()).unwrap();
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_available_space() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        assert!(available_space(temp_dir.path()).unwrap() > 0);
+    }
+}
@@ -0,0 +1,408 @@
+//! Transcoding of .zpp archives to another codec or level
+//!
+//! An archive is rewritten from its own compressed data, so the original files
+//! are not needed: each entry of a per-file archive, each frame of a solid
+//! archive or the single file of a `compress-file` archive is decoded and
+//! encoded again. Paths, solid offsets and preprocessing flags are kept, and the
+//! output uses the current format version. Entries in a codec this version
+//! cannot decode, or already in the target codec, are copied unchanged, which is
+//! how `migrate` upgrades old archives without recompressing them.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+use tracing::{debug, info, info_span, warn};
+
+use crate::compress::write_archive_header;
+use crate::decompress::{
+    compressed_size, read_archive_header, read_codec, read_file_header, read_solid_deletions, read_solid_frames, read_solid_index, read_stream_path, Layout,
+};
+use crate::error::{DecompressionError, PathIoError};
+use crate::format::{write_path, zstd_encoder, Codec, CODEC_STORED, DEFAULT_STORE_THRESHOLD, MODE_FILE, MODE_SOLID, MODE_STREAM};
+use crate::frames::{compress_frames, decode_frames, write_frame_table, Frame, DEFAULT_FRAME_SIZE};
+use crate::spill::{SpillBuffer, SpillOptions};
+
+/// Codec the payloads are transcoded to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum TargetCodec {
+    /// zstd at the requested level
+    #[default]
+    Zstd,
+    /// Uncompressed: the largest output, but reads cost no decoding
+    Store,
+    /// The codec each entry already uses, for `migrate`
+    #[value(skip)]
+    Keep,
+}
+
+pub struct RecompressOptions {
+    pub input_path: PathBuf,
+    pub output_path: PathBuf,
+    pub codec: TargetCodec,
+    /// zstd level (1-22)
+    pub level: i32,
+    /// Entries smaller than this many bytes are stored uncompressed
+    pub store_threshold: u64,
+    /// Original bytes per frame of a solid archive
+    pub solid_frame_size: u64,
+    pub threads: usize,
+    /// Staging of recompressed solid frames past the memory limit
+    pub spill: SpillOptions,
+}
+
+impl Default for RecompressOptions {
+    fn default() -> Self {
+        Self {
+            input_path: PathBuf::new(),
+            output_path: PathBuf::new(),
+            codec: TargetCodec::default(),
+            level: 22,
+            store_threshold: DEFAULT_STORE_THRESHOLD,
+            solid_frame_size: DEFAULT_FRAME_SIZE,
+            threads: 4,
+            spill: SpillOptions::default(),
+        }
+    }
+}
+
+impl RecompressOptions {
+    /// Codec of `size` original bytes encoded with `source`, keeping the
+    /// preprocessing they went through
+    fn codec_for(&self, size: u64, source: Codec) -> Codec {
+        let codec = match self.codec {
+            TargetCodec::Keep => return source,
+            TargetCodec::Store => Codec::STORED,
+            TargetCodec::Zstd if size < self.store_threshold => Codec::STORED,
+            TargetCodec::Zstd => Codec::zstd(self.level),
+        };
+        codec.with_preprocessing(source.preprocessing)
+    }
+}
+
+/// Rewrite the archive at `input_path` with the target codec. Returns the size of
+/// the new archive.
+pub fn recompress_archive(options: &RecompressOptions) -> Result<u64, DecompressionError> {
+    let _span = info_span!("recompress", input = %options.input_path.display(), codec = ?options.codec, level = options.level).entered();
+    let input_file = File::open(&options.input_path).map_err(|e| DecompressionError::io_at(e, &options.input_path))?;
+    let mut reader = BufReader::new(input_file);
+    let (mode, layout) = read_archive_header(&mut reader)?;
+
+    let output_file = File::create(&options.output_path).map_err(|e| DecompressionError::io_at(e, &options.output_path))?;
+    let mut writer = BufWriter::new(output_file);
+    write_archive_header(&mut writer, mode)?;
+    match mode {
+        MODE_STREAM => recompress_stream(&mut reader, &layout, options, &mut writer)?,
+        MODE_SOLID => recompress_solid(&mut reader, &layout, options, &mut writer)?,
+        MODE_FILE => recompress_file(&mut reader, &layout, options, &mut writer)?,
+        _ => return Err(DecompressionError::InvalidFormat),
+    }
+    let output_file = writer.into_inner().map_err(|e| e.into_error())?;
+    let size = output_file.metadata()?.len();
+    info!(size, "Archive recompressed");
+    Ok(size)
+}
+
+/// Rewrite an archive of any earlier version in the current layout: paths,
+/// codec descriptors and the frame table of solid archives. Entry data is copied
+/// as is, only the single stream of a solid archive older than frame tables is
+/// cut into frames on `threads` threads. Returns the size of the new archive.
+pub fn migrate_archive(input_path: &Path, output_path: &Path, threads: usize, spill: &SpillOptions) -> Result<u64, DecompressionError> {
+    recompress_archive(&RecompressOptions {
+        input_path: input_path.to_path_buf(),
+        output_path: output_path.to_path_buf(),
+        codec: TargetCodec::Keep,
+        threads,
+        spill: spill.clone(),
+        ..Default::default()
+    })
+}
+
+/// Per-file entries, transcoded one at a time
+fn recompress_stream(
+    reader: &mut impl BufRead,
+    layout: &Layout,
+    options: &RecompressOptions,
+    writer: &mut impl Write,
+) -> Result<(), DecompressionError> {
+    let mut buffer = [0u8; 8];
+    while !reader.fill_buf()?.is_empty() {
+        let path = read_stream_path(reader, layout)?;
+        let codec = read_codec(reader, layout)?;
+        reader.read_exact(&mut buffer)?;
+        let mut data = vec![0u8; u64::from_le_bytes(buffer) as usize];
+        reader.read_exact(&mut data)?;
+
+        let (codec, data) = if codec == Codec::DELETED {
+            (codec, data)
+        } else if !codec.is_supported() {
+            warn!(path = %path.display(), %codec, "Entry copied unchanged, unsupported codec");
+            (codec, data)
+        } else if options.codec == TargetCodec::Keep {
+            (codec, data)
+        } else {
+            let decoded = codec.decode(&data).map_err(|e| DecompressionError::DecompressionFailed(format!("{}: {}", path.display(), e)))?;
+            let target = options.codec_for(decoded.len() as u64, codec);
+            (target, encode(&decoded, target, options.threads)?)
+        };
+        write_path(writer, &path)?;
+        codec.write_to(writer)?;
+        writer.write_all(&(data.len() as u64).to_le_bytes())?;
+        writer.write_all(&data)?;
+    }
+    Ok(())
+}
+
+/// Dictionary and index copied, frames decoded in parallel batches and cut again
+/// at the configured frame size
+fn recompress_solid(
+    reader: &mut impl BufRead,
+    layout: &Layout,
+    options: &RecompressOptions,
+    writer: &mut impl Write,
+) -> Result<(), DecompressionError> {
+    let mut buffer = [0u8; 8];
+    reader.read_exact(&mut buffer)?;
+    let dict_size = u64::from_le_bytes(buffer);
+    writer.write_all(&buffer)?;
+    io::copy(&mut reader.take(dict_size), writer)?;
+
+    let codec = read_codec(reader, layout)?;
+    let frames = read_solid_frames(reader, layout)?;
+    // Frames already in the target codec only need re-cutting without a frame table
+    let target = options.codec_for(u64::MAX, codec);
+    let reframe = target != codec || !layout.frame_table();
+    if codec.is_supported() && reframe {
+        let mut compressed = SpillBuffer::new(&options.spill);
+        let decoded = DecodedFrames { reader: &mut *reader, codec, frames: frames.into(), threads: options.threads, number: 0, current: Cursor::default(), decoded: VecDeque::new() };
+        let frames = compress_frames(decoded, options.solid_frame_size, target, options.threads, &mut compressed)?;
+        debug!(frames = frames.len(), "Frames recompressed");
+        target.write_to(writer)?;
+        write_frame_table(writer, &frames)?;
+        io::copy(&mut compressed.into_reader()?, writer)?;
+    } else {
+        if !codec.is_supported() {
+            warn!(%codec, "Solid data copied unchanged, unsupported codec");
+        }
+        codec.write_to(writer)?;
+        write_frame_table(writer, &frames)?;
+        io::copy(&mut reader.take(compressed_size(&frames)?), writer)?;
+    }
+
+    let index = read_solid_index(reader, layout)?;
+    writer.write_all(&(index.len() as u64).to_le_bytes())?;
+    for (path, start, length) in index {
+        write_path(writer, &path)?;
+        writer.write_all(&start.to_le_bytes())?;
+        writer.write_all(&length.to_le_bytes())?;
+    }
+    let deletions = read_solid_deletions(reader, layout)?;
+    writer.write_all(&(deletions.len() as u64).to_le_bytes())?;
+    for path in deletions {
+        write_path(writer, &path)?;
+    }
+    Ok(())
+}
+
+/// The single file of a `compress-file` archive, transcoded as a stream
+fn recompress_file(
+    reader: &mut impl BufRead,
+    layout: &Layout,
+    options: &RecompressOptions,
+    writer: &mut impl Write,
+) -> Result<(), DecompressionError> {
+    let (path, codec, size) = read_file_header(reader, layout)?;
+    let target = options.codec_for(size, codec);
+    if target != codec && !codec.is_supported() {
+        return Err(DecompressionError::UnsupportedCodec { path, codec });
+    }
+    write_path(writer, &path)?;
+    target.write_to(writer)?;
+    writer.write_all(&size.to_le_bytes())?;
+    if target == codec {
+        io::copy(reader, writer)?;
+        return Ok(());
+    }
+    let mut decoder = codec.decoder(reader)?;
+    let copied = if target.id == CODEC_STORED {
+        io::copy(&mut decoder, writer)?
+    } else {
+        let mut encoder = zstd_encoder(writer, target.level.into(), options.threads)?;
+        encoder.set_pledged_src_size(Some(size))?;
+        let copied = io::copy(&mut decoder, &mut encoder)?;
+        encoder.finish()?;
+        copied
+    };
+    if copied != size {
+        return Err(DecompressionError::DecompressionFailed(format!("{} bytes decoded instead of {}", copied, size)));
+    }
+    Ok(())
+}
+
+/// `data` encoded with `codec` on `threads` threads
+fn encode(data: &[u8], codec: Codec, threads: usize) -> io::Result<Vec<u8>> {
+    if codec.id == CODEC_STORED {
+        return Ok(data.to_vec());
+    }
+    let mut encoder = zstd_encoder(Vec::with_capacity(data.len() / 2), codec.level.into(), threads)?;
+    encoder.set_pledged_src_size(Some(data.len() as u64))?;
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+/// Original data of solid frames read in order, decoded `threads` at a time
+struct DecodedFrames<'a, R> {
+    reader: &'a mut R,
+    codec: Codec,
+    frames: VecDeque<Frame>,
+    threads: usize,
+    /// Number of the next frame to read
+    number: usize,
+    current: Cursor<Vec<u8>>,
+    decoded: VecDeque<Vec<u8>>,
+}
+
+impl<R: Read> Read for DecodedFrames<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let read = self.current.read(buf)?;
+            if read > 0 || buf.is_empty() {
+                return Ok(read);
+            }
+            if let Some(data) = self.decoded.pop_front() {
+                self.current = Cursor::new(data);
+                continue;
+            }
+            if self.frames.is_empty() {
+                return Ok(0);
+            }
+            let mut batch = Vec::new();
+            while batch.len() < self.threads.max(1) {
+                let Some(frame) = self.frames.pop_front() else {
+                    break;
+                };
+                let mut data = vec![0u8; frame.compressed_size as usize];
+                self.reader.read_exact(&mut data)?;
+                batch.push((frame, data));
+            }
+            for decoded in decode_frames(self.codec, &batch) {
+                // A damaged frame would be written back with the wrong data: no output
+                let data = decoded.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("frame {} unreadable: {}", self.number, e)))?;
+                self.decoded.push_back(data);
+                self.number += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compress::{compress_directory, compress_file, CompressionOptions, FileCompressionOptions};
+    use crate::decompress::{list_archive, read_archive_entry};
+    use std::fs;
+    use std::path::Path;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_recompress_archive() {
+        let temp_dir = tempdir().unwrap();
+        let input_dir = temp_dir.path().join("input");
+        fs::create_dir_all(input_dir.join("sub")).unwrap();
+        let text = "ligne répétée pour la compression\n".repeat(2000);
+        fs::write(input_dir.join("a.txt"), &text).unwrap();
+        let binary: Vec<u8> = (0..50_000u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8).collect();
+        fs::write(input_dir.join("sub/b.bin"), &binary).unwrap();
+
+        for solid in [false, true] {
+            let archive = temp_dir.path().join(format!("solid-{}.zpp", solid));
+            compress_directory(&CompressionOptions {
+                input_path: input_dir.clone(),
+                output_path: archive.clone(),
+                level: 3,
+                solid,
+                solid_frame_size: 16 * 1024,
+                ..Default::default()
+            }).unwrap();
+
+            for codec in [TargetCodec::Store, TargetCodec::Zstd] {
+                let output = temp_dir.path().join(format!("solid-{}-{:?}.zpp", solid, codec));
+                recompress_archive(&RecompressOptions {
+                    input_path: archive.clone(),
+                    output_path: output.clone(),
+                    codec,
+                    level: 19,
+                    solid_frame_size: 40 * 1024,
+                    threads: 2,
+                    ..Default::default()
+                }).unwrap();
+                assert_eq!(list_archive(&output).unwrap().entries.len(), 2);
+                let mut read = Vec::new();
+                read_archive_entry(&output, Path::new("sub/b.bin"), &mut read).unwrap();
+                assert_eq!(read, binary);
+            }
+            // Stored entries make the archive larger
+            let stored = fs::metadata(temp_dir.path().join(format!("solid-{}-Store.zpp", solid))).unwrap().len();
+            assert!(stored > fs::metadata(&archive).unwrap().len());
+        }
+
+        let file = input_dir.join("a.txt");
+        let archive = temp_dir.path().join("a.txt.zpp");
+        compress_file(&FileCompressionOptions { input_path: file, output_path: archive.clone(), level: Some(1), ..Default::default() }).unwrap();
+        let output = temp_dir.path().join("a.txt.19.zpp");
+        recompress_archive(&RecompressOptions { input_path: archive, output_path: output.clone(), level: 19, ..Default::default() }).unwrap();
+        let mut read = Vec::new();
+        read_archive_entry(&output, Path::new("a.txt"), &mut read).unwrap();
+        assert_eq!(read, text.as_bytes());
+    }
+
+    #[test]
+    fn test_migrate_archive() {
+        use crate::format::{ZPP_MAGIC, ZPP_VERSION};
+
+        let temp_dir = tempdir().unwrap();
+        let first = zstd::encode_all(&b"premier"[..], 3).unwrap();
+        let second = zstd::encode_all(&b"deuxieme"[..], 3).unwrap();
+
+        // Version 1 per-file archive: NUL-terminated UTF-8 paths, no codec descriptors
+        let mut stream = ZPP_MAGIC.to_vec();
+        stream.extend_from_slice(&1u32.to_le_bytes());
+        stream.push(MODE_STREAM);
+        for (path, data) in [("un.txt", &first), ("dir/deux.txt", &second)] {
+            stream.extend_from_slice(path.as_bytes());
+            stream.push(0);
+            stream.extend_from_slice(&(data.len() as u64).to_le_bytes());
+            stream.extend_from_slice(data);
+        }
+        // Version 1 solid archive: a single stream, no frame table
+        let mut solid = ZPP_MAGIC.to_vec();
+        solid.extend_from_slice(&1u32.to_le_bytes());
+        solid.push(MODE_SOLID);
+        solid.extend_from_slice(&0u64.to_le_bytes());
+        let data = zstd::encode_all(&b"premierdeuxieme"[..], 3).unwrap();
+        solid.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        solid.extend_from_slice(&data);
+        solid.extend_from_slice(&2u64.to_le_bytes());
+        for (path, start, length) in [("un.txt", 0u64, 7u64), ("dir/deux.txt", 7, 8)] {
+            write_path(&mut solid, Path::new(path)).unwrap();
+            solid.extend_from_slice(&start.to_le_bytes());
+            solid.extend_from_slice(&length.to_le_bytes());
+        }
+
+        for (name, archive) in [("stream", stream), ("solid", solid)] {
+            let old = temp_dir.path().join(format!("{}-v1.zpp", name));
+            fs::write(&old, archive).unwrap();
+            let new = temp_dir.path().join(format!("{}.zpp", name));
+            migrate_archive(&old, &new, 2, &SpillOptions::default()).unwrap();
+
+            let (_, layout) = read_archive_header(&mut File::open(&new).unwrap()).unwrap();
+            assert_eq!(layout.version, ZPP_VERSION);
+            let mut read = Vec::new();
+            read_archive_entry(&new, Path::new("dir/deux.txt"), &mut read).unwrap();
+            assert_eq!(read, b"deuxieme");
+        }
+        // Per-file entries are copied, not recompressed
+        let stored = list_archive(&temp_dir.path().join("stream.zpp")).unwrap().stored_size;
+        assert_eq!(stored, Some((first.len() + second.len()) as u64));
+    }
+}
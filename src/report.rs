@@ -0,0 +1,204 @@
+//! Outcome of an operation that ran to completion
+
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use serde::{Serialize, Serializer};
+use tracing::warn;
+
+use crate::walk::EntryKind;
+
+/// Entry left out of an operation because of a per-file error
+#[derive(Debug, Clone, Serialize)]
+pub struct SkippedEntry {
+    #[serde(serialize_with = "lossy_path")]
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+/// Category of a non-fatal issue
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WarningKind {
+    /// Entry the format or the options cannot represent, left out on purpose
+    Ignored,
+    /// Entry path rewritten to stay inside the destination
+    PathSanitized,
+    /// Entry extracted under a different name to avoid a collision
+    Renamed,
+    /// Entry or metadata that cannot be recreated on this platform
+    NotRestored,
+    /// Hardlink or reflink replaced by a plain copy
+    LinkFallback,
+    /// Image extracted without a valid signed manifest (`--force`)
+    Unverified,
+}
+
+/// Non-fatal issue affecting a single entry
+#[derive(Debug, Clone, Serialize)]
+pub struct Warning {
+    #[serde(serialize_with = "lossy_path")]
+    pub path: PathBuf,
+    pub kind: WarningKind,
+    pub message: String,
+}
+
+/// Effect an extraction would have on an output path
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlannedAction {
+    /// Nothing exists at the path yet
+    Create,
+    /// Existing file truncated and rewritten, keeping its permissions
+    Overwrite,
+    /// Existing entry removed first (a symlink, or anything but a directory for
+    /// links); the new entry gets default permissions
+    Replace,
+    /// Directory already present, left untouched
+    Keep,
+    /// An entry of another type is in the way; extraction would fail
+    Conflict,
+}
+
+/// Entry an extraction would write, listed by dry runs
+#[derive(Debug, Clone, Serialize)]
+pub struct PlannedEntry {
+    /// Destination path
+    #[serde(serialize_with = "lossy_path")]
+    pub path: PathBuf,
+    pub kind: EntryKind,
+    pub size: u64,
+    pub action: PlannedAction,
+    /// Permission bits of the entry currently at the path (Unix)
+    pub existing_mode: Option<u32>,
+}
+
+/// Summary returned by compression and extraction functions
+#[derive(Debug, Default, Serialize)]
+pub struct Report {
+    /// Entries skipped under `skip_errors`
+    pub skipped: Vec<SkippedEntry>,
+    /// Entries processed with a caveat
+    pub warnings: Vec<Warning>,
+    /// Entries that would be written, filled only by dry runs
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub planned: Vec<PlannedEntry>,
+}
+
+impl Report {
+    /// Record a per-file failure when `skip_errors` is set, otherwise return it
+    pub(crate) fn skip_or_fail<E: Display>(&mut self, skip_errors: bool, path: &Path, error: E) -> Result<(), E> {
+        if !skip_errors {
+            return Err(error);
+        }
+        self.skip(path, error);
+        Ok(())
+    }
+
+    /// Record an entry left out whatever `skip_errors`, e.g. one written with a codec this version cannot decode
+    pub(crate) fn skip(&mut self, path: &Path, error: impl Display) {
+        warn!(path = %path.display(), error = %error, "Skipping entry");
+        self.skipped.push(SkippedEntry { path: path.to_path_buf(), reason: error.to_string() });
+    }
+
+    /// Log a warning and keep it for the end-of-run summary
+    pub(crate) fn warn(&mut self, path: &Path, kind: WarningKind, message: impl Into<String>) {
+        let message = message.into();
+        warn!(path = %path.display(), kind = ?kind, "{}", message);
+        self.warnings.push(Warning { path: path.to_path_buf(), kind, message });
+    }
+
+    /// Record what writing an entry at `path` would do, without touching it
+    pub(crate) fn plan(&mut self, path: &Path, kind: EntryKind, size: u64) {
+        let existing = path.symlink_metadata().ok();
+        let action = match &existing {
+            None => PlannedAction::Create,
+            Some(metadata) => match (kind, metadata.is_dir(), metadata.file_type().is_symlink()) {
+                (EntryKind::Directory, true, _) => PlannedAction::Keep,
+                (EntryKind::File | EntryKind::Symlink, _, true) => PlannedAction::Replace,
+                (EntryKind::File | EntryKind::Stream, false, false) => PlannedAction::Overwrite,
+                (EntryKind::Symlink, false, false) => PlannedAction::Replace,
+                _ => PlannedAction::Conflict,
+            },
+        };
+        self.planned.push(PlannedEntry {
+            path: path.to_path_buf(),
+            kind,
+            size,
+            action,
+            existing_mode: existing.as_ref().and_then(permission_bits),
+        });
+    }
+
+    /// Whether every entry was processed
+    pub fn is_complete(&self) -> bool {
+        self.skipped.is_empty()
+    }
+
+    /// Whether every entry was processed without caveats
+    pub fn is_clean(&self) -> bool {
+        self.skipped.is_empty() && self.warnings.is_empty()
+    }
+}
+
+#[cfg(unix)]
+fn permission_bits(metadata: &std::fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(metadata.permissions().mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+fn permission_bits(_metadata: &std::fs::Metadata) -> Option<u32> {
+    None
+}
+
+/// Paths are not always valid UTF-8; reports favour readability over exactness
+pub(crate) fn lossy_path<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&path.to_string_lossy())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_skip_or_fail() {
+        let mut report = Report::default();
+        assert_eq!(report.skip_or_fail(false, Path::new("a"), "boom"), Err("boom"));
+        assert!(report.is_complete());
+
+        report.skip_or_fail(true, Path::new("a"), "boom").unwrap();
+        assert_eq!(report.skipped.len(), 1);
+        assert_eq!(report.skipped[0].reason, "boom");
+    }
+
+    #[test]
+    fn test_serialize() {
+        let mut report = Report::default();
+        report.warn(Path::new("dir/a.txt"), WarningKind::PathSanitized, "rewritten");
+        assert!(report.is_complete());
+        assert!(!report.is_clean());
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["warnings"][0]["path"], "dir/a.txt");
+        assert_eq!(json["warnings"][0]["kind"], "path_sanitized");
+    }
+
+    #[test]
+    fn test_plan() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let existing = temp_dir.path().join("existing.txt");
+        std::fs::write(&existing, "contenu").unwrap();
+
+        let mut report = Report::default();
+        report.plan(&temp_dir.path().join("new.txt"), EntryKind::File, 3);
+        report.plan(&existing, EntryKind::File, 5);
+        report.plan(temp_dir.path(), EntryKind::Directory, 0);
+        report.plan(temp_dir.path(), EntryKind::File, 1);
+
+        let actions: Vec<_> = report.planned.iter().map(|entry| entry.action).collect();
+        assert_eq!(actions, [PlannedAction::Create, PlannedAction::Overwrite, PlannedAction::Keep, PlannedAction::Conflict]);
+        assert!(report.planned[0].existing_mode.is_none());
+        assert_eq!(report.planned[1].existing_mode.is_some(), cfg!(unix));
+        assert!(report.is_clean());
+    }
+}
@@ -0,0 +1,427 @@
+//! HTTP API for `zippy serve`, so other services can drive ZippyPack
+//!
+//! - `POST /jobs` queues the job in the body (same JSON as the daemon's jobs) and returns `{"id": N}`
+//! - `GET /jobs` and `GET /jobs/{id}` return job states
+//! - `GET /list?path=ARCHIVE` returns the listing of `zippy list --json`
+//! - `GET /entry?path=ARCHIVE&entry=PATH` returns the content of one file
+//!
+//! Every path is relative to the served root and may not leave it, symlinks
+//! included. Entries are streamed as they are decoded. Errors are
+//! returned as `{"error": "..."}`. With a token, every request must carry
+//! `Authorization: Bearer TOKEN`; listening beyond loopback requires one.
+
+use std::io::{self, PipeReader, PipeWriter, Read, Write};
+use std::net::ToSocketAddrs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::Arc;
+use std::thread;
+use serde::Serialize;
+use serde_json::json;
+use tiny_http::{Header, Method, Server, StatusCode};
+use tracing::{debug, info, warn};
+
+use crate::daemon::{run_queue, JobQueue, JobSpec};
+use crate::decompress::{list_archive, read_archive_entry};
+use crate::error::{DecompressionError, ImageError};
+use crate::image::{list_image, read_image_entry};
+use crate::list::is_archive;
+use crate::metrics::Metrics;
+use crate::paths::stays_within;
+use crate::report::Report;
+
+#[derive(Debug, Clone)]
+pub struct ServerOptions {
+    /// Address to listen on, e.g. `127.0.0.1:8080`
+    pub listen: String,
+    /// Directory that request paths are relative to
+    pub root: PathBuf,
+    /// Jobs run at the same time
+    pub workers: usize,
+    /// Bearer token requests must present; required unless `listen` is a loopback address
+    pub token: Option<String>,
+}
+
+/// Largest request body accepted; jobs are a few hundred bytes of JSON
+const MAX_BODY: u64 = 1024 * 1024;
+
+/// Answer requests until the process is stopped, running queued jobs through `run`
+pub fn serve(options: &ServerOptions, run: impl Fn(&JobSpec, &Arc<Metrics>) -> Result<Report, String> + Sync) -> io::Result<()> {
+    if options.token.is_none() && !is_loopback(&options.listen)? {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("refusing to serve {} without a token outside loopback", options.listen),
+        ));
+    }
+    let server = Server::http(&options.listen).map_err(io::Error::other)?;
+    info!(listen = %options.listen, root = %options.root.display(), workers = options.workers, token = options.token.is_some(), "Server listening");
+    run_queue(options.workers, run, |jobs| {
+        for mut request in server.incoming_requests() {
+            let header = request.headers().iter().find(|header| header.field.equiv("Authorization"));
+            let reply = if !authorized(options.token.as_deref(), header.map(|header| header.value.as_str())) {
+                Reply::error(401, "missing or invalid token")
+            } else {
+                match read_body(request.as_reader()) {
+                    Ok(body) => respond(jobs, &options.root, request.method(), request.url(), &body),
+                    Err(reply) => reply,
+                }
+            };
+            debug!(method = %request.method(), url = request.url(), status = reply.status, "Request");
+            let content_type = Header::from_bytes("Content-Type", reply.content_type).expect("valid header");
+            let response = match reply.body {
+                Body::Data(data) => tiny_http::Response::from_data(data).with_status_code(reply.status).with_header(content_type).boxed(),
+                // Without a length, the body is sent chunked as it is read
+                Body::Stream(reader) => tiny_http::Response::new(StatusCode(reply.status), vec![content_type], reader, None, None).boxed(),
+            };
+            if let Err(e) = request.respond(response) {
+                warn!(error = %e, "Could not send response");
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Whether every address `listen` resolves to is a loopback one
+fn is_loopback(listen: &str) -> io::Result<bool> {
+    Ok(listen.to_socket_addrs()?.all(|address| address.ip().is_loopback()))
+}
+
+/// Whether the `Authorization` header carries the token, if one is required
+fn authorized(token: Option<&str>, header: Option<&str>) -> bool {
+    match token {
+        Some(token) => header.and_then(|header| header.strip_prefix("Bearer ")) == Some(token),
+        None => true,
+    }
+}
+
+/// Request body, refused with 413 past `MAX_BODY`
+fn read_body(reader: impl Read) -> Result<Vec<u8>, Reply> {
+    let mut body = Vec::new();
+    reader.take(MAX_BODY + 1).read_to_end(&mut body).map_err(|e| Reply::error(400, e))?;
+    if body.len() as u64 > MAX_BODY {
+        return Err(Reply::error(413, format!("request body larger than {} bytes", MAX_BODY)));
+    }
+    Ok(body)
+}
+
+/// Response to one request
+#[derive(Debug)]
+pub struct Reply {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Body,
+}
+
+pub enum Body {
+    Data(Vec<u8>),
+    /// Entry content, read while it is being sent
+    Stream(Box<dyn Read + Send>),
+}
+
+impl std::fmt::Debug for Body {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Body::Data(data) => write!(f, "Data({} bytes)", data.len()),
+            Body::Stream(_) => f.write_str("Stream"),
+        }
+    }
+}
+
+impl Reply {
+    fn json(status: u16, value: &impl Serialize) -> Self {
+        let body = serde_json::to_vec(value).expect("serializable value");
+        Self { status, content_type: "application/json", body: Body::Data(body) }
+    }
+
+    fn error(status: u16, message: impl ToString) -> Self {
+        Self::json(status, &json!({ "error": message.to_string() }))
+    }
+
+    /// Whole body, read to the end when streamed
+    pub fn into_data(self) -> Vec<u8> {
+        match self.body {
+            Body::Data(data) => data,
+            Body::Stream(mut reader) => {
+                let mut data = Vec::new();
+                let _ = reader.read_to_end(&mut data);
+                data
+            }
+        }
+    }
+}
+
+/// Route a request; `url` is the path with its query string
+pub fn respond(jobs: &JobQueue, root: &Path, method: &Method, url: &str, body: &[u8]) -> Reply {
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    let result = match (method, path.trim_end_matches('/')) {
+        (Method::Post, "/jobs") => submit(jobs, root, body),
+        (Method::Get, "/jobs") => Ok(Reply::json(200, &jobs.status(None).unwrap_or_default())),
+        (Method::Get, path) if path.starts_with("/jobs/") => job(jobs, &path["/jobs/".len()..]),
+        (Method::Get, "/list") => list(root, query),
+        (Method::Get, "/entry") => entry(root, query),
+        (_, "/jobs" | "/list" | "/entry") => Err(Reply::error(405, "method not allowed")),
+        _ => Err(Reply::error(404, "not found")),
+    };
+    result.unwrap_or_else(|reply| reply)
+}
+
+fn submit(jobs: &JobQueue, root: &Path, body: &[u8]) -> Result<Reply, Reply> {
+    let mut job: JobSpec = serde_json::from_slice(body).map_err(|e| Reply::error(400, format!("invalid job: {}", e)))?;
+    let (input, output) = job.paths_mut();
+    *input = resolve(root, &input.to_string_lossy())?;
+    *output = resolve(root, &output.to_string_lossy())?;
+    Ok(Reply::json(202, &json!({ "id": jobs.submit(job) })))
+}
+
+fn job(jobs: &JobQueue, id: &str) -> Result<Reply, Reply> {
+    let id = id.parse().map_err(|_| Reply::error(400, "invalid job id"))?;
+    match jobs.status(Some(id)) {
+        Some(mut job) => Ok(Reply::json(200, &job.remove(0))),
+        None => Err(Reply::error(404, format!("no job {}", id))),
+    }
+}
+
+fn list(root: &Path, query: &str) -> Result<Reply, Reply> {
+    let path = resolve(root, &param(query, "path")?)?;
+    let listing = if is_archive(&path).map_err(io_reply)? {
+        list_archive(&path).map_err(archive_reply)?
+    } else {
+        list_image(&path).map_err(image_reply)?
+    };
+    Ok(Reply::json(200, &listing))
+}
+
+/// Entry content, decoded on its own thread into a pipe; an error before the
+/// first byte still gets its status, a later one aborts the response
+fn entry(root: &Path, query: &str) -> Result<Reply, Reply> {
+    let path = resolve(root, &param(query, "path")?)?;
+    let entry = relative_path(&param(query, "entry")?)?;
+    let archive = is_archive(&path).map_err(io_reply)?;
+    let (reader, writer) = io::pipe().map_err(io_reply)?;
+    // The start of the content, then the outcome of the read
+    let (events, received) = mpsc::sync_channel(2);
+    thread::spawn(move || {
+        let mut output = EntryWriter { pipe: writer, started: Some(events.clone()) };
+        let result = if archive {
+            read_archive_entry(&path, &entry, &mut output).map(drop).map_err(archive_reply)
+        } else {
+            read_image_entry(&path, &entry, &mut output).map(drop).map_err(image_reply)
+        };
+        // Closed first, so the reader reaches the end before the outcome
+        drop(output);
+        let _ = events.send(result);
+    });
+    match received.recv() {
+        Ok(Err(reply)) => Err(reply),
+        _ => Ok(Reply { status: 200, content_type: "application/octet-stream", body: Body::Stream(Box::new(EntryReader { pipe: reader, outcome: received })) }),
+    }
+}
+
+/// Pipe end of the reading thread, announcing the first write
+struct EntryWriter {
+    pipe: PipeWriter,
+    started: Option<SyncSender<Result<(), Reply>>>,
+}
+
+impl Write for EntryWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if let Some(started) = self.started.take() {
+            let _ = started.send(Ok(()));
+        }
+        self.pipe.write(data)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.pipe.flush()
+    }
+}
+
+/// Pipe end sent to the client, failing at the end if the read did
+struct EntryReader {
+    pipe: PipeReader,
+    outcome: Receiver<Result<(), Reply>>,
+}
+
+impl Read for EntryReader {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let read = self.pipe.read(buffer)?;
+        if read == 0 && !buffer.is_empty() {
+            // Already received when the read failed before writing anything
+            if let Ok(Err(reply)) = self.outcome.recv() {
+                return Err(io::Error::other(String::from_utf8_lossy(&reply.into_data()).into_owned()));
+            }
+        }
+        Ok(read)
+    }
+}
+
+fn io_reply(error: io::Error) -> Reply {
+    match error.kind() {
+        io::ErrorKind::NotFound => Reply::error(404, error),
+        _ => Reply::error(500, error),
+    }
+}
+
+fn archive_reply(error: DecompressionError) -> Reply {
+    match error {
+        DecompressionError::Io(e) => io_reply(e),
+        DecompressionError::EntryNotFound { .. } => Reply::error(404, error),
+        _ => Reply::error(500, error),
+    }
+}
+
+fn image_reply(error: ImageError) -> Reply {
+    match error {
+        ImageError::Io(e) => io_reply(e),
+        ImageError::EntryNotFound { .. } => Reply::error(404, error),
+        _ => Reply::error(500, error),
+    }
+}
+
+/// Decoded value of a required query parameter
+fn param(query: &str, name: &str) -> Result<String, Reply> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| percent_decode(value))
+        .ok_or_else(|| Reply::error(400, format!("missing parameter {}", name)))?
+        .ok_or_else(|| Reply::error(400, format!("invalid encoding of {}", name)))
+}
+
+/// `%XX` escapes and `+` for spaces; `None` unless the result is UTF-8
+fn percent_decode(value: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut input = value.bytes();
+    while let Some(byte) = input.next() {
+        bytes.push(match byte {
+            b'+' => b' ',
+            b'%' => {
+                let hex = [input.next()?, input.next()?];
+                u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?
+            }
+            byte => byte,
+        });
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// Relative path made only of plain names
+fn relative_path(path: &str) -> Result<PathBuf, Reply> {
+    let path = Path::new(path);
+    if path.as_os_str().is_empty() || !path.components().all(|component| matches!(component, Component::Normal(_))) {
+        return Err(Reply::error(400, format!("path must stay inside the served directory: {}", path.display())));
+    }
+    Ok(path.to_path_buf())
+}
+
+/// `path` under the served root, refused if a symlink leads it out
+fn resolve(root: &Path, path: &str) -> Result<PathBuf, Reply> {
+    let resolved = root.join(relative_path(path)?);
+    let canonical_root = root.canonicalize().map_err(io_reply)?;
+    if !stays_within(&canonical_root, &resolved).map_err(io_reply)? {
+        return Err(Reply::error(400, format!("path must stay inside the served directory: {}", path)));
+    }
+    Ok(resolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::compress::{compress_directory, CompressionOptions};
+    use crate::image::{create_image, ImageOptions};
+    use tempfile::tempdir;
+
+    fn get(jobs: &JobQueue, root: &Path, url: &str) -> Reply {
+        respond(jobs, root, &Method::Get, url, b"")
+    }
+
+    fn body(reply: Reply) -> serde_json::Value {
+        serde_json::from_slice(&reply.into_data()).unwrap()
+    }
+
+    #[test]
+    fn test_routes() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("src/docs")).unwrap();
+        fs::write(root.join("src/docs/read me.txt"), "hello over http").unwrap();
+        // Larger than a pipe buffer: the entry can only arrive streamed
+        let large: Vec<u8> = (0..3 * 1024 * 1024u32).map(|i| (i * 7 % 251) as u8).collect();
+        fs::write(root.join("src/large.bin"), &large).unwrap();
+        compress_directory(&CompressionOptions {
+            input_path: root.join("src"),
+            output_path: root.join("src.zpp"),
+            ..Default::default()
+        })
+        .unwrap();
+        create_image(&ImageOptions {
+            input_path: root.join("src"),
+            output_path: root.join("src.zpak"),
+            compression_level: 3,
+            ..Default::default()
+        })
+        .unwrap();
+
+        run_queue(1, |_, _| Ok(Report::default()), |jobs| {
+            let listing = get(jobs, root, "/list?path=src.zpp");
+            assert_eq!(listing.status, 200);
+            assert!(body(listing)["entries"].as_array().unwrap().iter().any(|entry| entry["path"] == "docs/read me.txt"));
+
+            let entry = get(jobs, root, "/entry?path=src.zpp&entry=docs%2Fread+me.txt");
+            assert_eq!((entry.status, entry.content_type), (200, "application/octet-stream"));
+            assert_eq!(entry.into_data(), b"hello over http");
+            assert_eq!(get(jobs, root, "/entry?path=src.zpak&entry=docs/read%20me.txt").into_data(), b"hello over http");
+            assert!(get(jobs, root, "/entry?path=src.zpak&entry=large.bin").into_data() == large);
+            assert_eq!(get(jobs, root, "/entry?path=src.zpp&entry=docs/missing").status, 404);
+            assert_eq!(get(jobs, root, "/entry?path=src.zpak&entry=docs").status, 404);
+            assert_eq!(get(jobs, root, "/list?path=../etc/passwd").status, 400);
+            assert_eq!(get(jobs, root, "/list").status, 400);
+
+            let job = br#"{"command": "decompress", "input": "src.zpp", "output": "out"}"#;
+            let submitted = respond(jobs, root, &Method::Post, "/jobs", job);
+            assert_eq!(submitted.status, 202);
+            assert_eq!(body(submitted)["id"], 1);
+            let job = body(get(jobs, root, "/jobs/1"));
+            assert_eq!(job["job"]["output"], root.join("out").to_str().unwrap());
+            assert_eq!(body(get(jobs, root, "/jobs")).as_array().unwrap().len(), 1);
+            assert_eq!(get(jobs, root, "/jobs/7").status, 404);
+
+            let escape = br#"{"command": "decompress", "input": "src.zpp", "output": "/tmp/out"}"#;
+            assert_eq!(respond(jobs, root, &Method::Post, "/jobs", escape).status, 400);
+            assert_eq!(respond(jobs, root, &Method::Delete, "/jobs", b"").status, 405);
+
+            // A symlink under the root does not lead out of it
+            #[cfg(unix)]
+            {
+                let outside = tempdir().unwrap();
+                fs::copy(root.join("src.zpp"), outside.path().join("src.zpp")).unwrap();
+                std::os::unix::fs::symlink(outside.path(), root.join("elsewhere")).unwrap();
+                assert_eq!(get(jobs, root, "/list?path=elsewhere/src.zpp").status, 400);
+                let job = br#"{"command": "decompress", "input": "src.zpp", "output": "elsewhere/out"}"#;
+                assert_eq!(respond(jobs, root, &Method::Post, "/jobs", job).status, 400);
+            }
+        });
+    }
+
+    #[test]
+    fn test_request_limits() {
+        let job = br#"{"command": "decompress", "input": "src.zpp", "output": "out"}"#;
+        assert_eq!(read_body(&job[..]).unwrap(), job);
+        assert_eq!(read_body(io::repeat(b' ')).unwrap_err().status, 413);
+
+        assert!(authorized(None, None));
+        assert!(authorized(Some("secret"), Some("Bearer secret")));
+        assert!(!authorized(Some("secret"), Some("Bearer other")));
+        assert!(!authorized(Some("secret"), Some("secret")));
+        assert!(!authorized(Some("secret"), None));
+
+        assert!(is_loopback("127.0.0.1:8080").unwrap());
+        assert!(is_loopback("[::1]:8080").unwrap());
+        assert!(!is_loopback("0.0.0.0:8080").unwrap());
+        let options = ServerOptions { listen: "0.0.0.0:0".into(), root: PathBuf::new(), workers: 1, token: None };
+        let error = serve(&options, |_, _| Ok(Report::default())).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,177 @@
+//! Ed25519-signed manifests listing every entry of an image with its BLAKE3 content hash
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+
+use crate::format::{read_path, write_path};
+
+/// Domain separation for manifest signatures
+const MANIFEST_CONTEXT: &[u8] = b"zippypack manifest v1\0";
+
+/// One entry as recorded when the image was signed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestEntry {
+    pub path: PathBuf,
+    /// Entry kind, as stored in the image file index
+    pub kind: u8,
+    pub size: u64,
+    /// BLAKE3 of the file content or symlink target; zero for other entries
+    pub content_hash: [u8; 32],
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Manifest {
+    pub entries: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    pub fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        bytes.write_all(&(self.entries.len() as u64).to_le_bytes())?;
+        for entry in &self.entries {
+            write_path(&mut bytes, &entry.path)?;
+            bytes.write_all(&[entry.kind])?;
+            bytes.write_all(&entry.size.to_le_bytes())?;
+            bytes.write_all(&entry.content_hash)?;
+        }
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(mut bytes: &[u8], path_encoding: u8) -> io::Result<Self> {
+        let mut buffer = [0u8; 8];
+        bytes.read_exact(&mut buffer)?;
+        let count = u64::from_le_bytes(buffer);
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            let path = read_path(&mut bytes, path_encoding)?;
+            let mut kind = [0u8; 1];
+            bytes.read_exact(&mut kind)?;
+            bytes.read_exact(&mut buffer)?;
+            let mut content_hash = [0u8; 32];
+            bytes.read_exact(&mut content_hash)?;
+            entries.push(ManifestEntry { path, kind: kind[0], size: u64::from_le_bytes(buffer), content_hash });
+        }
+        Ok(Self { entries })
+    }
+
+    pub fn sign(&self, key: &SigningKey) -> io::Result<SignedManifest> {
+        let bytes = self.to_bytes()?;
+        let signature = key.sign(&signed_message(&bytes));
+        Ok(SignedManifest { bytes, public_key: key.verifying_key().to_bytes(), signature: signature.to_bytes() })
+    }
+}
+
+/// Serialized manifest with the key that signed it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignedManifest {
+    pub bytes: Vec<u8>,
+    pub public_key: [u8; 32],
+    pub signature: [u8; 64],
+}
+
+impl SignedManifest {
+    /// Check the signature, and that it was made by `trusted_key` when given
+    pub fn verify(&self, trusted_key: Option<&[u8; 32]>, path_encoding: u8) -> Result<Manifest, String> {
+        if trusted_key.is_some_and(|key| *key != self.public_key) {
+            return Err(format!("signed by untrusted key {}", fingerprint(&self.public_key)));
+        }
+        let key = VerifyingKey::from_bytes(&self.public_key).map_err(|e| e.to_string())?;
+        key.verify(&signed_message(&self.bytes), &Signature::from_bytes(&self.signature))
+            .map_err(|_| "invalid signature".to_string())?;
+        Manifest::from_bytes(&self.bytes, path_encoding).map_err(|e| format!("unreadable manifest: {}", e))
+    }
+}
+
+fn signed_message(bytes: &[u8]) -> Vec<u8> {
+    [MANIFEST_CONTEXT, bytes].concat()
+}
+
+/// Short hex form of a public key for messages
+pub fn fingerprint(public_key: &[u8; 32]) -> String {
+    to_hex(&public_key[..8])
+}
+
+/// Write a new key pair: the secret key to `path` (owner-only) and the public key to `path.pub`
+pub fn generate_key_pair(path: &Path) -> io::Result<[u8; 32]> {
+    let mut seed = [0u8; 32];
+    getrandom::getrandom(&mut seed).map_err(|e| io::Error::other(e.to_string()))?;
+    let key = SigningKey::from_bytes(&seed);
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    writeln!(options.open(path)?, "{}", to_hex(&seed))?;
+
+    let public_key = key.verifying_key().to_bytes();
+    fs::write(public_key_path(path), format!("{}\n", to_hex(&public_key)))?;
+    Ok(public_key)
+}
+
+/// `key` -> `key.pub`
+pub fn public_key_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".pub");
+    PathBuf::from(name)
+}
+
+pub fn read_signing_key(path: &Path) -> io::Result<SigningKey> {
+    Ok(SigningKey::from_bytes(&read_key(path)?))
+}
+
+pub fn read_public_key(path: &Path) -> io::Result<[u8; 32]> {
+    read_key(path)
+}
+
+/// 32 bytes written as 64 hex digits
+fn read_key(path: &Path) -> io::Result<[u8; 32]> {
+    let text = fs::read_to_string(path)?;
+    from_hex(text.trim())
+        .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("invalid key file: {}", path.display())))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| text.get(i..i + 2).and_then(|digits| u8::from_str_radix(digits, 16).ok()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::native_path_encoding;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_signed_manifest() {
+        let temp_dir = tempdir().unwrap();
+        let key_path = temp_dir.path().join("release.key");
+        let public_key = generate_key_pair(&key_path).unwrap();
+        assert_eq!(read_public_key(&public_key_path(&key_path)).unwrap(), public_key);
+        assert!(generate_key_pair(&key_path).is_err());
+
+        let manifest = Manifest {
+            entries: vec![ManifestEntry { path: PathBuf::from("a.txt"), kind: 0, size: 3, content_hash: *blake3::hash(b"abc").as_bytes() }],
+        };
+        let signed = manifest.sign(&read_signing_key(&key_path).unwrap()).unwrap();
+        let encoding = native_path_encoding();
+        assert_eq!(signed.verify(Some(&public_key), encoding).unwrap(), manifest);
+        assert!(signed.verify(Some(&[1u8; 32]), encoding).is_err());
+
+        let mut tampered = signed.clone();
+        let last = tampered.bytes.len() - 1;
+        tampered.bytes[last] ^= 1;
+        assert!(tampered.verify(None, encoding).is_err());
+    }
+}
@@ -0,0 +1,151 @@
+//! Staging of data too large to keep in memory
+//!
+//! A [`SpillBuffer`] holds what is written to it in memory up to the memory
+//! limit, then moves everything to an anonymous temporary file in the
+//! configured directory. The file has no name (or loses it right away), so the
+//! system reclaims it however the process exits, and running out of space on
+//! its device is reported with the directory to change.
+
+use std::fs::File;
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+/// Where and when staged data is moved out of memory
+#[derive(Debug, Clone)]
+pub struct SpillOptions {
+    /// Directory of spill files [default: the system temporary directory]
+    pub dir: Option<PathBuf>,
+    /// Bytes a buffer keeps in memory before spilling
+    pub memory_limit: u64,
+}
+
+impl Default for SpillOptions {
+    fn default() -> Self {
+        Self { dir: None, memory_limit: 1024 * 1024 * 1024 }
+    }
+}
+
+impl SpillOptions {
+    fn dir(&self) -> PathBuf {
+        self.dir.clone().unwrap_or_else(std::env::temp_dir)
+    }
+}
+
+/// Bytes written in memory, then in a spill file past the memory limit
+pub struct SpillBuffer {
+    options: SpillOptions,
+    memory: Vec<u8>,
+    file: Option<File>,
+    len: u64,
+}
+
+impl SpillBuffer {
+    pub fn new(options: &SpillOptions) -> Self {
+        Self { options: options.clone(), memory: Vec::new(), file: None, len: 0 }
+    }
+
+    /// Bytes written so far
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Whether the content has moved to a spill file
+    pub fn spilled(&self) -> bool {
+        self.file.is_some()
+    }
+
+    /// Reader of everything written, from the start
+    pub fn into_reader(self) -> io::Result<Box<dyn Read + Send>> {
+        match self.file {
+            Some(mut file) => {
+                file.seek(SeekFrom::Start(0))?;
+                Ok(Box::new(io::BufReader::new(file)))
+            }
+            None => Ok(Box::new(Cursor::new(self.memory))),
+        }
+    }
+
+    /// Move the content held in memory to a new spill file
+    fn spill(&mut self) -> io::Result<&mut File> {
+        let dir = self.options.dir();
+        info!(dir = %dir.display(), size = self.memory.len(), "Memory limit reached, spilling to disk");
+        let mut file = tempfile::tempfile_in(&dir).map_err(|e| match e.kind() {
+            io::ErrorKind::StorageFull => spill_error(e, &dir),
+            kind => io::Error::new(kind, format!("cannot create a spill file in {}: {}", dir.display(), e)),
+        })?;
+        file.write_all(&self.memory).map_err(|e| spill_error(e, &dir))?;
+        self.memory = Vec::new();
+        Ok(self.file.insert(file))
+    }
+}
+
+impl Write for SpillBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let file = match &mut self.file {
+            Some(file) => file,
+            None if self.len + buf.len() as u64 <= self.options.memory_limit => {
+                self.memory.extend_from_slice(buf);
+                self.len += buf.len() as u64;
+                return Ok(buf.len());
+            }
+            None => self.spill()?,
+        };
+        let written = file.write(buf).map_err(|e| spill_error(e, &self.options.dir()))?;
+        self.len += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Error naming the spill directory and what to change when its device is full
+fn spill_error(error: io::Error, dir: &Path) -> io::Error {
+    if error.kind() != io::ErrorKind::StorageFull {
+        return error;
+    }
+    let message = format!(
+        "spill directory {} is full: set temp_dir in config.toml to a device with more space, or raise memory_limit",
+        dir.display()
+    );
+    io::Error::new(io::ErrorKind::StorageFull, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spill_buffer() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let options = SpillOptions { dir: Some(temp_dir.path().to_path_buf()), memory_limit: 1000 };
+        let data: Vec<u8> = (0..5000u32).map(|i| (i % 251) as u8).collect();
+
+        let mut small = SpillBuffer::new(&options);
+        small.write_all(&data[..1000]).unwrap();
+        assert!(!small.spilled());
+
+        let mut large = SpillBuffer::new(&options);
+        for chunk in data.chunks(300) {
+            large.write_all(chunk).unwrap();
+        }
+        assert!(large.spilled());
+        assert_eq!(large.len(), data.len() as u64);
+        // The spill file has no name in the directory
+        assert_eq!(std::fs::read_dir(temp_dir.path()).unwrap().count(), 0);
+        let mut read = Vec::new();
+        large.into_reader().unwrap().read_to_end(&mut read).unwrap();
+        assert_eq!(read, data);
+
+        let full = spill_error(io::Error::from(io::ErrorKind::StorageFull), temp_dir.path());
+        assert!(full.to_string().contains("temp_dir"));
+        let missing = SpillOptions { dir: Some(temp_dir.path().join("missing")), memory_limit: 0 };
+        assert!(SpillBuffer::new(&missing).write_all(b"x").is_err());
+    }
+}
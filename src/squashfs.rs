@@ -0,0 +1,701 @@
+//! Export of a .zpak image as a SquashFS 4.0 filesystem for `zippy export-squashfs`
+//!
+//! Blocks are compressed with zstd, which Linux mounts natively since 4.14.
+//! Files with identical content share their data blocks and all-zero blocks are
+//! stored sparse. Fragments are not used, so the tail of every file takes a
+//! block of its own. Entries belong to root, with the permissions of
+//! [`write_tar`](crate::image::write_tar); device nodes are left out.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use tracing::info;
+
+use crate::error::ImageError;
+use crate::image::{for_each_entry, BlockHash};
+use crate::walk::EntryKind;
+
+pub const DEFAULT_BLOCK_SIZE: u32 = 128 * 1024;
+
+const MAGIC: u32 = 0x7371_7368;
+const COMPRESSION_ZSTD: u16 = 6;
+/// NO_FRAGMENTS | DUPLICATES | NO_XATTRS
+const FLAGS: u16 = 0x0010 | 0x0040 | 0x0200;
+const SUPERBLOCK_SIZE: u64 = 96;
+/// Images are padded to this size so they can be attached as loop devices
+const DEVICE_BLOCK: u64 = 4096;
+const NO_TABLE: u64 = u64::MAX;
+const NO_FRAGMENT: u32 = u32::MAX;
+const NO_XATTR: u32 = u32::MAX;
+const METADATA_SIZE: usize = 8192;
+const METADATA_UNCOMPRESSED: u16 = 0x8000;
+const DATA_UNCOMPRESSED: u32 = 1 << 24;
+
+const DIRECTORY: u16 = 1;
+const FILE: u16 = 2;
+const SYMLINK: u16 = 3;
+const FIFO: u16 = 6;
+const SOCKET: u16 = 7;
+const EXTENDED_DIRECTORY: u16 = 8;
+const EXTENDED_FILE: u16 = 9;
+
+pub struct SquashfsOptions {
+    pub image_path: PathBuf,
+    pub output_path: PathBuf,
+    /// Data block size, a power of two from 4 KiB to 1 MiB
+    pub block_size: u32,
+    pub compression_level: i32,
+}
+
+impl Default for SquashfsOptions {
+    fn default() -> Self {
+        Self { image_path: PathBuf::new(), output_path: PathBuf::new(), block_size: DEFAULT_BLOCK_SIZE, compression_level: 3 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SquashfsExport {
+    pub inodes: u32,
+    /// Size of the filesystem, before padding
+    pub bytes_used: u64,
+}
+
+pub fn export_squashfs(options: &SquashfsOptions) -> Result<SquashfsExport, ImageError> {
+    if !options.block_size.is_power_of_two() || !(4096..=1024 * 1024).contains(&options.block_size) {
+        let message = format!("invalid SquashFS block size {}: use a power of two from 4K to 1M", options.block_size);
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, message).into());
+    }
+    info!(image = %options.image_path.display(), output = %options.output_path.display(), "Exporting SquashFS");
+
+    let mut output = Output { file: BufWriter::new(File::create(&options.output_path)?), position: 0 };
+    output.write_all(&[0; SUPERBLOCK_SIZE as usize])?;
+
+    // Data blocks in index order, then the tree they belong to
+    let mut tree = Tree::new();
+    let mut stored: HashMap<Vec<BlockHash>, FileData> = HashMap::new();
+    let mut buffer = vec![0u8; options.block_size as usize];
+    for_each_entry(&options.image_path, |entry, content| {
+        let data = match entry.kind {
+            EntryKind::File => {
+                let data = match stored.get(&entry.blocks) {
+                    Some(data) => data.clone(),
+                    None => output.write_file(content, &mut buffer, options.compression_level)?,
+                };
+                stored.entry(entry.blocks.clone()).or_insert_with(|| data.clone());
+                NodeData::File(data)
+            }
+            EntryKind::Directory => NodeData::Directory(BTreeMap::new()),
+            EntryKind::Symlink => NodeData::Symlink(name_bytes(entry.link_target.as_deref().unwrap_or(Path::new("")))),
+            EntryKind::Fifo => NodeData::Special(FIFO),
+            EntryKind::Socket => NodeData::Special(SOCKET),
+            EntryKind::CharDevice | EntryKind::BlockDevice => {
+                info!(path = %entry.path.display(), "Device node left out of the SquashFS image");
+                return Ok(());
+            }
+            EntryKind::Stream => {
+                info!(path = %entry.path.display(), "Alternate data stream left out of the SquashFS image");
+                return Ok(());
+            }
+        };
+        tree.insert(&entry.path, entry.modified.min(u32::MAX as u64) as u32, data);
+        Ok(())
+    })?;
+
+    let mut inodes = MetadataWriter::new(options.compression_level);
+    let mut directories = MetadataWriter::new(options.compression_level);
+    let root_inode = tree.write_directory(0, tree.nodes.len() as u32 + 1, &mut inodes, &mut directories)?;
+
+    let inode_table = output.position;
+    output.write_all(&inodes.finish()?)?;
+    let directory_table = output.position;
+    output.write_all(&directories.finish()?)?;
+    // Everything belongs to root: a single id, then the table locating its metadata block
+    let ids = output.position;
+    let mut id_block = MetadataWriter::new(options.compression_level);
+    id_block.write(&0u32.to_le_bytes())?;
+    output.write_all(&id_block.finish()?)?;
+    let id_table = output.position;
+    output.write_all(&ids.to_le_bytes())?;
+    let bytes_used = output.position;
+    let padding = bytes_used.next_multiple_of(DEVICE_BLOCK) - bytes_used;
+    output.write_all(&vec![0; padding as usize])?;
+
+    let mut superblock = Vec::with_capacity(SUPERBLOCK_SIZE as usize);
+    superblock.extend(MAGIC.to_le_bytes());
+    superblock.extend((tree.nodes.len() as u32).to_le_bytes());
+    superblock.extend(tree.nodes.iter().map(|node| node.mtime).max().unwrap_or(0).to_le_bytes());
+    superblock.extend(options.block_size.to_le_bytes());
+    superblock.extend(0u32.to_le_bytes()); // fragments
+    superblock.extend(COMPRESSION_ZSTD.to_le_bytes());
+    superblock.extend((options.block_size.trailing_zeros() as u16).to_le_bytes());
+    superblock.extend(FLAGS.to_le_bytes());
+    superblock.extend(1u16.to_le_bytes()); // ids
+    superblock.extend(4u16.to_le_bytes());
+    superblock.extend(0u16.to_le_bytes());
+    superblock.extend(root_inode.to_le_bytes());
+    superblock.extend(bytes_used.to_le_bytes());
+    superblock.extend(id_table.to_le_bytes());
+    superblock.extend(NO_TABLE.to_le_bytes()); // xattrs
+    superblock.extend(inode_table.to_le_bytes());
+    superblock.extend(directory_table.to_le_bytes());
+    superblock.extend(NO_TABLE.to_le_bytes()); // fragments
+    superblock.extend(NO_TABLE.to_le_bytes()); // NFS export
+    let mut file = output.file.into_inner().map_err(|e| e.into_error())?;
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(&superblock)?;
+    file.sync_all()?;
+
+    info!(inodes = tree.nodes.len(), bytes_used, "SquashFS export done");
+    Ok(SquashfsExport { inodes: tree.nodes.len() as u32, bytes_used })
+}
+
+/// Output file with its write position
+struct Output {
+    file: BufWriter<File>,
+    position: u64,
+}
+
+impl Output {
+    fn write_all(&mut self, data: &[u8]) -> io::Result<()> {
+        self.file.write_all(data)?;
+        self.position += data.len() as u64;
+        Ok(())
+    }
+
+    /// Write the content as consecutive data blocks
+    fn write_file(&mut self, content: &mut dyn Read, buffer: &mut [u8], level: i32) -> io::Result<FileData> {
+        let mut data = FileData { start: self.position, size: 0, blocks: Vec::new() };
+        loop {
+            let read = read_full(content, buffer)?;
+            if read == 0 {
+                break;
+            }
+            data.size += read as u64;
+            let block = &buffer[..read];
+            if block.iter().all(|&byte| byte == 0) {
+                data.blocks.push(0);
+            } else {
+                let compressed = zstd::bulk::compress(block, level)?;
+                if compressed.len() < block.len() {
+                    self.write_all(&compressed)?;
+                    data.blocks.push(compressed.len() as u32);
+                } else {
+                    self.write_all(block)?;
+                    data.blocks.push(block.len() as u32 | DATA_UNCOMPRESSED);
+                }
+            }
+            if read < buffer.len() {
+                break;
+            }
+        }
+        Ok(data)
+    }
+}
+
+/// Fill `buffer` unless the content ends first
+fn read_full(content: &mut dyn Read, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match content.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// Location of a file's data blocks; size of each block, 0 for a sparse one
+#[derive(Debug, Clone)]
+struct FileData {
+    start: u64,
+    size: u64,
+    blocks: Vec<u32>,
+}
+
+enum NodeData {
+    /// Children by name, sorted as SquashFS lookups expect
+    Directory(BTreeMap<Vec<u8>, usize>),
+    File(FileData),
+    Symlink(Vec<u8>),
+    Special(u16),
+}
+
+struct Node {
+    mtime: u32,
+    data: NodeData,
+}
+
+/// Entries by index; the root is 0 and inode numbers are indexes + 1
+struct Tree {
+    nodes: Vec<Node>,
+}
+
+impl Tree {
+    fn new() -> Self {
+        Self { nodes: vec![Node { mtime: 0, data: NodeData::Directory(BTreeMap::new()) }] }
+    }
+
+    /// Add an entry, creating the directories missing above it
+    fn insert(&mut self, path: &Path, mtime: u32, data: NodeData) {
+        let names: Vec<Vec<u8>> = path
+            .components()
+            .filter_map(|component| match component {
+                Component::Normal(name) => Some(name_bytes(Path::new(name))),
+                _ => None,
+            })
+            .collect();
+        let Some((name, parents)) = names.split_last() else {
+            self.nodes[0].mtime = mtime;
+            return;
+        };
+        let mut parent = 0;
+        for name in parents {
+            parent = match self.child(parent, name) {
+                Some(child) => child,
+                None => self.add(parent, name.clone(), Node { mtime: 0, data: NodeData::Directory(BTreeMap::new()) }),
+            };
+        }
+        match self.child(parent, name) {
+            // Directory implied by an earlier entry
+            Some(existing) if matches!(data, NodeData::Directory(_)) => self.nodes[existing].mtime = mtime,
+            _ => {
+                self.add(parent, name.clone(), Node { mtime, data });
+            }
+        }
+    }
+
+    fn child(&self, parent: usize, name: &[u8]) -> Option<usize> {
+        match &self.nodes[parent].data {
+            NodeData::Directory(children) => children.get(name).copied(),
+            _ => None,
+        }
+    }
+
+    fn add(&mut self, parent: usize, name: Vec<u8>, node: Node) -> usize {
+        let index = self.nodes.len();
+        self.nodes.push(node);
+        // An entry below a non-directory is dropped, as extraction would
+        if let NodeData::Directory(children) = &mut self.nodes[parent].data {
+            children.insert(name, index);
+        }
+        index
+    }
+
+    /// Write the inodes below `index`, its listing and its own inode; returns its inode reference
+    fn write_directory(
+        &self,
+        index: usize,
+        parent_inode: u32,
+        inodes: &mut MetadataWriter,
+        directories: &mut MetadataWriter,
+    ) -> io::Result<u64> {
+        let NodeData::Directory(children) = &self.nodes[index].data else {
+            unreachable!("not a directory");
+        };
+        let mut listing = Vec::with_capacity(children.len());
+        let mut subdirectories = 0u32;
+        for (name, &child) in children {
+            let inode_type = match &self.nodes[child].data {
+                NodeData::Directory(_) => {
+                    subdirectories += 1;
+                    DIRECTORY
+                }
+                NodeData::File(_) => FILE,
+                NodeData::Symlink(_) => SYMLINK,
+                NodeData::Special(inode_type) => *inode_type,
+            };
+            let reference = match inode_type {
+                DIRECTORY => self.write_directory(child, inode_number(index), inodes, directories)?,
+                _ => self.write_inode(child, inodes)?,
+            };
+            listing.push(ListingEntry { name, inode_type, inode: inode_number(child), reference });
+        }
+
+        let (block, offset) = directories.position();
+        let size = write_listing(directories, &listing)? + 3;
+        let reference = inodes.reference();
+        let node = &self.nodes[index];
+        let link_count = 2 + subdirectories;
+        let mut inode = Vec::new();
+        if size <= u16::MAX as u32 {
+            inode_header(&mut inode, DIRECTORY, 0o755, node.mtime, inode_number(index));
+            inode.extend(block.to_le_bytes());
+            inode.extend(link_count.to_le_bytes());
+            inode.extend((size as u16).to_le_bytes());
+            inode.extend(offset.to_le_bytes());
+            inode.extend(parent_inode.to_le_bytes());
+        } else {
+            inode_header(&mut inode, EXTENDED_DIRECTORY, 0o755, node.mtime, inode_number(index));
+            inode.extend(link_count.to_le_bytes());
+            inode.extend(size.to_le_bytes());
+            inode.extend(block.to_le_bytes());
+            inode.extend(parent_inode.to_le_bytes());
+            inode.extend(0u16.to_le_bytes()); // no directory index
+            inode.extend(offset.to_le_bytes());
+            inode.extend(NO_XATTR.to_le_bytes());
+        }
+        inodes.write(&inode)?;
+        Ok(reference)
+    }
+
+    fn write_inode(&self, index: usize, inodes: &mut MetadataWriter) -> io::Result<u64> {
+        let reference = inodes.reference();
+        let node = &self.nodes[index];
+        let mut inode = Vec::new();
+        match &node.data {
+            NodeData::File(data) => {
+                if data.start <= u32::MAX as u64 && data.size <= u32::MAX as u64 {
+                    inode_header(&mut inode, FILE, 0o644, node.mtime, inode_number(index));
+                    inode.extend((data.start as u32).to_le_bytes());
+                    inode.extend(NO_FRAGMENT.to_le_bytes());
+                    inode.extend(0u32.to_le_bytes());
+                    inode.extend((data.size as u32).to_le_bytes());
+                } else {
+                    inode_header(&mut inode, EXTENDED_FILE, 0o644, node.mtime, inode_number(index));
+                    inode.extend(data.start.to_le_bytes());
+                    inode.extend(data.size.to_le_bytes());
+                    inode.extend(0u64.to_le_bytes()); // sparse bytes, informative only
+                    inode.extend(1u32.to_le_bytes());
+                    inode.extend(NO_FRAGMENT.to_le_bytes());
+                    inode.extend(0u32.to_le_bytes());
+                    inode.extend(NO_XATTR.to_le_bytes());
+                }
+                inode.extend(data.blocks.iter().flat_map(|size| size.to_le_bytes()));
+            }
+            NodeData::Symlink(target) => {
+                inode_header(&mut inode, SYMLINK, 0o777, node.mtime, inode_number(index));
+                inode.extend(1u32.to_le_bytes());
+                inode.extend((target.len() as u32).to_le_bytes());
+                inode.extend(target);
+            }
+            NodeData::Special(inode_type) => {
+                inode_header(&mut inode, *inode_type, 0o644, node.mtime, inode_number(index));
+                inode.extend(1u32.to_le_bytes());
+            }
+            NodeData::Directory(_) => unreachable!("directories are written by write_directory"),
+        }
+        inodes.write(&inode)?;
+        Ok(reference)
+    }
+}
+
+fn inode_number(index: usize) -> u32 {
+    index as u32 + 1
+}
+
+/// Type, permissions, uid and gid indexes (root), mtime and inode number
+fn inode_header(inode: &mut Vec<u8>, inode_type: u16, mode: u16, mtime: u32, number: u32) {
+    inode.extend(inode_type.to_le_bytes());
+    inode.extend(mode.to_le_bytes());
+    inode.extend(0u16.to_le_bytes());
+    inode.extend(0u16.to_le_bytes());
+    inode.extend(mtime.to_le_bytes());
+    inode.extend(number.to_le_bytes());
+}
+
+struct ListingEntry<'a> {
+    name: &'a [u8],
+    /// Basic type, even for extended inodes
+    inode_type: u16,
+    inode: u32,
+    reference: u64,
+}
+
+/// Entries grouped under headers sharing the metadata block of their inodes
+/// (256 entries at most, inode numbers within an `i16` of the header's);
+/// returns the listing size
+fn write_listing(directories: &mut MetadataWriter, entries: &[ListingEntry]) -> io::Result<u32> {
+    let mut size = 0;
+    let mut rest = entries;
+    while let Some(first) = rest.first() {
+        let block = (first.reference >> 16) as u32;
+        let count = rest
+            .iter()
+            .take(256)
+            .take_while(|entry| {
+                (entry.reference >> 16) as u32 == block && (entry.inode as i64 - first.inode as i64).abs() <= i16::MAX as i64
+            })
+            .count();
+        let mut listing = Vec::new();
+        listing.extend((count as u32 - 1).to_le_bytes());
+        listing.extend(block.to_le_bytes());
+        listing.extend(first.inode.to_le_bytes());
+        for entry in &rest[..count] {
+            listing.extend((entry.reference as u16).to_le_bytes());
+            listing.extend(((entry.inode as i64 - first.inode as i64) as i16).to_le_bytes());
+            listing.extend(entry.inode_type.to_le_bytes());
+            listing.extend((entry.name.len() as u16 - 1).to_le_bytes());
+            listing.extend(entry.name);
+        }
+        directories.write(&listing)?;
+        size += listing.len() as u32;
+        rest = &rest[count..];
+    }
+    Ok(size)
+}
+
+/// Stream cut into 8 KiB blocks, each compressed unless that does not make it smaller
+struct MetadataWriter {
+    level: i32,
+    blocks: Vec<u8>,
+    pending: Vec<u8>,
+}
+
+impl MetadataWriter {
+    fn new(level: i32) -> Self {
+        Self { level, blocks: Vec::new(), pending: Vec::with_capacity(METADATA_SIZE) }
+    }
+
+    /// Offset of the current block in the table, and position in that block
+    fn position(&self) -> (u32, u16) {
+        (self.blocks.len() as u32, self.pending.len() as u16)
+    }
+
+    /// Reference to what is written next, as stored in inodes and the superblock
+    fn reference(&self) -> u64 {
+        let (block, offset) = self.position();
+        ((block as u64) << 16) | offset as u64
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.pending.extend_from_slice(data);
+        while self.pending.len() >= METADATA_SIZE {
+            let rest = self.pending.split_off(METADATA_SIZE);
+            self.flush_block()?;
+            self.pending = rest;
+        }
+        Ok(())
+    }
+
+    fn flush_block(&mut self) -> io::Result<()> {
+        let compressed = zstd::bulk::compress(&self.pending, self.level)?;
+        if compressed.len() < self.pending.len() {
+            self.blocks.extend((compressed.len() as u16).to_le_bytes());
+            self.blocks.extend(compressed);
+        } else {
+            self.blocks.extend((self.pending.len() as u16 | METADATA_UNCOMPRESSED).to_le_bytes());
+            self.blocks.extend(&self.pending);
+        }
+        self.pending.clear();
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<Vec<u8>> {
+        if !self.pending.is_empty() {
+            self.flush_block()?;
+        }
+        Ok(self.blocks)
+    }
+}
+
+#[cfg(unix)]
+fn name_bytes(path: &Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+fn name_bytes(path: &Path) -> Vec<u8> {
+    path.to_string_lossy().replace('\\', "/").into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::image::{create_image, ImageOptions};
+    use tempfile::tempdir;
+
+    /// Just enough of a SquashFS reader to walk what `export_squashfs` writes
+    struct Reader {
+        data: Vec<u8>,
+        block_size: usize,
+        inodes: Metadata,
+        directories: Metadata,
+    }
+
+    /// Decompressed table, with the uncompressed offset of each block
+    struct Metadata {
+        bytes: Vec<u8>,
+        blocks: HashMap<u64, usize>,
+    }
+
+    impl Metadata {
+        fn read(data: &[u8], start: u64, end: u64) -> Self {
+            let mut metadata = Metadata { bytes: Vec::new(), blocks: HashMap::new() };
+            let mut position = start;
+            while position < end {
+                metadata.blocks.insert(position - start, metadata.bytes.len());
+                let header = u16::from_le_bytes(data[position as usize..][..2].try_into().unwrap());
+                let size = (header & !METADATA_UNCOMPRESSED) as usize;
+                let block = &data[position as usize + 2..][..size];
+                if header & METADATA_UNCOMPRESSED != 0 {
+                    metadata.bytes.extend(block);
+                } else {
+                    metadata.bytes.extend(zstd::decode_all(block).unwrap());
+                }
+                position += 2 + size as u64;
+            }
+            metadata
+        }
+
+        fn at(&self, block: u64, offset: u64) -> &[u8] {
+            &self.bytes[self.blocks[&block] + offset as usize..]
+        }
+    }
+
+    fn u16_at(bytes: &[u8], at: usize) -> u16 {
+        u16::from_le_bytes(bytes[at..at + 2].try_into().unwrap())
+    }
+
+    fn u32_at(bytes: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+    }
+
+    fn u64_at(bytes: &[u8], at: usize) -> u64 {
+        u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+    }
+
+    impl Reader {
+        fn open(path: &Path) -> Self {
+            let data = fs::read(path).unwrap();
+            assert_eq!(u32_at(&data, 0), MAGIC);
+            assert_eq!(data.len() as u64 % DEVICE_BLOCK, 0);
+            let inode_table = u64_at(&data, 64);
+            let directory_table = u64_at(&data, 72);
+            let ids = u64_at(&data, u64_at(&data, 48) as usize);
+            Reader {
+                block_size: u32_at(&data, 12) as usize,
+                inodes: Metadata::read(&data, inode_table, directory_table),
+                directories: Metadata::read(&data, directory_table, ids),
+                data,
+            }
+        }
+
+        fn root(&self) -> u64 {
+            u64_at(&self.data, 32)
+        }
+
+        fn inode(&self, reference: u64) -> &[u8] {
+            self.inodes.at(reference >> 16, reference & 0xFFFF)
+        }
+
+        /// Names and inode references of a directory, with its link count
+        fn list(&self, reference: u64) -> (Vec<(String, u64)>, u32) {
+            let inode = self.inode(reference);
+            let (block, offset, size, links) = match u16_at(inode, 0) {
+                DIRECTORY => (u32_at(inode, 16), u16_at(inode, 26), u16_at(inode, 24) as u32, u32_at(inode, 20)),
+                EXTENDED_DIRECTORY => (u32_at(inode, 24), u16_at(inode, 34), u32_at(inode, 20), u32_at(inode, 16)),
+                other => panic!("not a directory: {}", other),
+            };
+            let listing = &self.directories.at(block as u64, offset as u64)[..size as usize - 3];
+            let mut entries = Vec::new();
+            let mut at = 0;
+            while at < listing.len() {
+                let count = u32_at(listing, at) + 1;
+                let start = u32_at(listing, at + 4) as u64;
+                at += 12;
+                for _ in 0..count {
+                    let name_size = u16_at(listing, at + 6) as usize + 1;
+                    let name = String::from_utf8(listing[at + 8..][..name_size].to_vec()).unwrap();
+                    entries.push((name, (start << 16) | u16_at(listing, at) as u64));
+                    at += 8 + name_size;
+                }
+            }
+            (entries, links)
+        }
+
+        fn lookup(&self, path: &str) -> u64 {
+            path.split('/').fold(self.root(), |reference, name| {
+                let (entries, _) = self.list(reference);
+                entries.into_iter().find(|(entry, _)| entry == name).unwrap_or_else(|| panic!("no {}", path)).1
+            })
+        }
+
+        /// Content of a basic file inode, with its block sizes
+        fn read_file(&self, path: &str) -> (Vec<u8>, u32, Vec<u32>) {
+            let inode = self.inode(self.lookup(path));
+            assert_eq!(u16_at(inode, 0), FILE);
+            let (start, size) = (u32_at(inode, 16), u32_at(inode, 28) as usize);
+            let sizes: Vec<u32> = (0..size.div_ceil(self.block_size)).map(|i| u32_at(inode, 32 + 4 * i)).collect();
+            let mut content = Vec::new();
+            let mut position = start as usize;
+            for &block in &sizes {
+                let length = (block & !DATA_UNCOMPRESSED) as usize;
+                let expected = self.block_size.min(size - content.len());
+                if block == 0 {
+                    content.resize(content.len() + expected, 0);
+                } else if block & DATA_UNCOMPRESSED != 0 {
+                    content.extend(&self.data[position..][..length]);
+                } else {
+                    content.extend(zstd::decode_all(&self.data[position..][..length]).unwrap());
+                }
+                position += length;
+            }
+            (content, start, sizes)
+        }
+    }
+
+    #[test]
+    fn test_export_readable_tree() {
+        let temp_dir = tempdir().unwrap();
+        let rootfs = temp_dir.path().join("rootfs");
+        fs::create_dir_all(rootfs.join("etc/empty")).unwrap();
+        fs::create_dir_all(rootfs.join("many")).unwrap();
+        let text: Vec<u8> = (0..10_000u32).flat_map(|i| (i % 251).to_le_bytes()).collect();
+        fs::write(rootfs.join("etc/data.bin"), &text).unwrap();
+        fs::write(rootfs.join("copy.bin"), &text).unwrap();
+        fs::write(rootfs.join("zeros"), vec![0u8; 8192]).unwrap();
+        for i in 0..600 {
+            fs::write(rootfs.join(format!("many/file-with-a-longer-name-{:04}", i)), i.to_string()).unwrap();
+        }
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("etc/data.bin", rootfs.join("link")).unwrap();
+        let image_path = temp_dir.path().join("rootfs.zpak");
+        create_image(&ImageOptions { input_path: rootfs, output_path: image_path.clone(), compression_level: 3, ..Default::default() }).unwrap();
+
+        let output_path = temp_dir.path().join("rootfs.squashfs");
+        let options = SquashfsOptions { image_path, output_path: output_path.clone(), block_size: 4096, ..Default::default() };
+        let export = export_squashfs(&options).unwrap();
+        let reader = Reader::open(&output_path);
+
+        let (root, links) = reader.list(reader.root());
+        let names: Vec<&str> = root.iter().map(|(name, _)| name.as_str()).collect();
+        #[cfg(unix)]
+        assert_eq!(names, ["copy.bin", "etc", "link", "many", "zeros"]);
+        assert_eq!(links, 4);
+        assert_eq!(reader.list(reader.lookup("etc/empty")), (vec![], 2));
+
+        // Multi-block content, shared by identical files
+        let (content, start, sizes) = reader.read_file("etc/data.bin");
+        assert_eq!(content, text);
+        assert_eq!(sizes.len(), 10);
+        let (copy, copy_start, _) = reader.read_file("copy.bin");
+        assert_eq!((copy, copy_start), (text, start));
+        let (zeros, _, sizes) = reader.read_file("zeros");
+        assert_eq!((zeros.len(), sizes), (8192, vec![0, 0]));
+
+        // Headers split every 256 entries, the listing spans metadata blocks
+        let (many, _) = reader.list(reader.lookup("many"));
+        assert_eq!(many.len(), 600);
+        assert_eq!(reader.read_file("many/file-with-a-longer-name-0599").0, b"599");
+
+        #[cfg(unix)]
+        {
+            let link = reader.inode(reader.lookup("link"));
+            assert_eq!(u16_at(link, 0), SYMLINK);
+            assert_eq!(&link[24..][..u32_at(link, 20) as usize], b"etc/data.bin");
+        }
+        assert_eq!(export.inodes, u32_at(&reader.data, 4));
+        assert_eq!(export.inodes as usize, 7 + 600 + usize::from(cfg!(unix)));
+    }
+
+    #[test]
+    fn test_rejects_invalid_block_size() {
+        let options = SquashfsOptions { block_size: 3000, ..Default::default() };
+        assert!(export_squashfs(&options).is_err());
+    }
+}
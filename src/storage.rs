@@ -0,0 +1,482 @@
+//! Storage backends holding archives and images
+//!
+//! Format code reads and writes local files. A location given as a URL
+//! (`scheme://...`) is staged around it: an input is downloaded to a temporary
+//! file before being read, an output is written to a temporary file and stored
+//! once complete. Plain paths and `file://` URLs use the local filesystem
+//! without staging.
+
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use tempfile::NamedTempFile;
+use tracing::info;
+use walkdir::WalkDir;
+
+use crate::cache::{ObjectCache, DEFAULT_MAX_SIZE};
+use crate::config::StorageConfig;
+use crate::error::PathIoError;
+
+/// Object store holding archives and images under `/`-separated names
+pub trait StorageBackend: Send + Sync {
+    /// Read a whole object
+    fn open(&self, name: &str) -> io::Result<Box<dyn Read + Send>>;
+
+    /// Bytes `range` of an object, fewer if it ends first
+    fn read_range(&self, name: &str, range: Range<u64>) -> io::Result<Vec<u8>>;
+
+    /// Store the content of a local file as an object, replacing any previous one
+    fn write(&self, name: &str, source: &Path) -> io::Result<()>;
+
+    /// Objects whose name starts with `prefix`, sorted by name
+    fn list(&self, prefix: &str) -> io::Result<Vec<ObjectInfo>>;
+
+    fn delete(&self, name: &str) -> io::Result<()>;
+
+    /// Size of an object
+    fn size(&self, name: &str) -> io::Result<u64> {
+        let objects = self.list(name)?;
+        objects.into_iter().find(|object| object.name == name).map(|object| object.size).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("no such object: {}", name))
+        })
+    }
+
+    /// Local file holding the object, when it can be used without staging
+    fn local_path(&self, _name: &str) -> Option<PathBuf> {
+        None
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectInfo {
+    pub name: String,
+    pub size: u64,
+}
+
+/// Objects are files under a root directory
+#[derive(Debug, Clone)]
+pub struct LocalBackend {
+    root: PathBuf,
+}
+
+impl LocalBackend {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// File of an object; names may not leave the root
+    fn path(&self, name: &str) -> io::Result<PathBuf> {
+        let relative = Path::new(name);
+        if name.is_empty() || !relative.components().all(|component| matches!(component, Component::Normal(_))) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid object name: {}", name)));
+        }
+        Ok(self.root.join(relative))
+    }
+}
+
+impl StorageBackend for LocalBackend {
+    fn open(&self, name: &str) -> io::Result<Box<dyn Read + Send>> {
+        Ok(Box::new(File::open(self.path(name)?)?))
+    }
+
+    fn read_range(&self, name: &str, range: Range<u64>) -> io::Result<Vec<u8>> {
+        let mut file = File::open(self.path(name)?)?;
+        file.seek(SeekFrom::Start(range.start))?;
+        let mut data = Vec::new();
+        file.take(range.end.saturating_sub(range.start)).read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    fn write(&self, name: &str, source: &Path) -> io::Result<()> {
+        let path = self.path(name)?;
+        let directory = path.parent().expect("object path has a parent");
+        fs::create_dir_all(directory)?;
+        // Readers never see a partial object
+        let mut staging = NamedTempFile::new_in(directory)?;
+        io::copy(&mut File::open(source)?, staging.as_file_mut())?;
+        staging.persist(&path).map_err(|e| e.error)?;
+        Ok(())
+    }
+
+    fn list(&self, prefix: &str) -> io::Result<Vec<ObjectInfo>> {
+        let mut objects = Vec::new();
+        // A store nothing was written to yet is empty
+        if !self.root.exists() {
+            return Ok(objects);
+        }
+        for entry in WalkDir::new(&self.root).min_depth(1).sort_by_file_name() {
+            let entry = entry.map_err(io::Error::from)?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let relative = entry.path().strip_prefix(&self.root).expect("entry under the root");
+            let Some(name) = relative.to_str().map(|name| name.replace(std::path::MAIN_SEPARATOR, "/")) else {
+                continue;
+            };
+            if name.starts_with(prefix) {
+                objects.push(ObjectInfo { name, size: entry.metadata().map_err(io::Error::from)?.len() });
+            }
+        }
+        objects.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(objects)
+    }
+
+    fn delete(&self, name: &str) -> io::Result<()> {
+        fs::remove_file(self.path(name)?)
+    }
+
+    fn size(&self, name: &str) -> io::Result<u64> {
+        Ok(fs::metadata(self.path(name)?)?.len())
+    }
+
+    fn local_path(&self, name: &str) -> Option<PathBuf> {
+        self.path(name).ok()
+    }
+}
+
+/// Object of a backend, designated by a URL
+pub struct Location {
+    pub backend: Box<dyn StorageBackend>,
+    pub name: String,
+    /// Local copy of the ranges read, when `[storage.cache]` has a directory
+    cache: Option<ObjectCache>,
+}
+
+impl Location {
+    /// Backend of a `scheme://...` URL; `None` for a plain path
+    pub fn parse(location: &Path, config: &StorageConfig) -> io::Result<Option<Self>> {
+        let Some((scheme, rest)) = location.to_str().and_then(split_url) else {
+            return Ok(None);
+        };
+        #[cfg(any(feature = "azure", feature = "gcs", feature = "b2", feature = "webdav"))]
+        let bandwidth = crate::throttle::Bandwidth::limited(config.bwlimit);
+        let (backend, name): (Box<dyn StorageBackend>, String) = match scheme {
+            "file" => {
+                let path = Path::new(rest);
+                let (Some(parent), Some(name)) = (path.parent(), path.file_name().and_then(|name| name.to_str())) else {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid file URL: {}", location.display())));
+                };
+                (Box::new(LocalBackend::new(parent)), name.to_string())
+            }
+            #[cfg(feature = "azure")]
+            "azure" => {
+                let (backend, name) = crate::azure::AzureBackend::from_url(rest, &config.azure)?;
+                (Box::new(backend.with_bandwidth(bandwidth)), name)
+            }
+            #[cfg(feature = "gcs")]
+            "gs" => {
+                let (backend, name) = crate::gcs::GcsBackend::from_url(rest, &config.gcs)?;
+                (Box::new(backend.with_bandwidth(bandwidth)), name)
+            }
+            #[cfg(feature = "b2")]
+            "b2" => {
+                let (backend, name) = crate::b2::B2Backend::from_url(rest, &config.b2)?;
+                (Box::new(backend.with_bandwidth(bandwidth)), name)
+            }
+            #[cfg(feature = "webdav")]
+            "dav" | "davs" => {
+                let (backend, name) = crate::webdav::WebDavBackend::from_url(scheme, rest, &config.webdav)?;
+                (Box::new(backend.with_bandwidth(bandwidth)), name)
+            }
+            _ => {
+                let _ = config;
+                let message = match feature_of(scheme) {
+                    Some(feature) => format!("{}:// URLs require zippy to be built with the {} feature (cargo build --features {})", scheme, feature, feature),
+                    None => format!("no storage backend for {}:// URLs", scheme),
+                };
+                return Err(io::Error::new(io::ErrorKind::Unsupported, message));
+            }
+        };
+        let cache = config.cache.dir.as_ref().map(|dir| {
+            ObjectCache::new(dir, config.cache.max_size.unwrap_or(DEFAULT_MAX_SIZE), &location.to_string_lossy())
+        });
+        Ok(Some(Self { backend, name, cache }))
+    }
+
+    /// Bytes `range` of the object, fewer if it ends first, through the cache if any
+    pub fn read_range(&self, range: Range<u64>) -> io::Result<Vec<u8>> {
+        match &self.cache {
+            Some(cache) if self.backend.local_path(&self.name).is_none() => cache.read_range(&*self.backend, &self.name, range),
+            _ => self.backend.read_range(&self.name, range),
+        }
+    }
+
+    /// Local copy of the object, downloaded unless the backend has it on disk
+    pub fn fetch(&self) -> io::Result<StagedInput> {
+        if let Some(path) = self.backend.local_path(&self.name) {
+            return Ok(StagedInput::Local(path));
+        }
+        info!(object = %self.name, "Downloading");
+        let mut staging = NamedTempFile::new()?;
+        match &self.cache {
+            Some(cache) => {
+                cache.copy_to(&*self.backend, &self.name, staging.as_file_mut())?;
+            }
+            None => {
+                io::copy(&mut self.backend.open(&self.name)?, staging.as_file_mut())?;
+            }
+        }
+        Ok(StagedInput::Downloaded(staging))
+    }
+
+    /// File to write the object to, stored by [`StagedOutput::finish`]
+    pub fn stage_output(self) -> io::Result<StagedOutput> {
+        if let Some(path) = self.backend.local_path(&self.name) {
+            return Ok(StagedOutput { path, upload: None, fsync: FsyncPolicy::None });
+        }
+        // Keep the extension, which some formats are recognized by
+        let suffix = Path::new(&self.name).extension().map(|extension| format!(".{}", extension.to_string_lossy()));
+        let staging = tempfile::Builder::new().suffix(suffix.as_deref().unwrap_or("")).tempfile()?;
+        Ok(StagedOutput { path: staging.path().to_path_buf(), upload: Some((self, staging)), fsync: FsyncPolicy::None })
+    }
+}
+
+/// Cargo feature providing the backend of a URL scheme
+fn feature_of(scheme: &str) -> Option<&'static str> {
+    match scheme {
+        "azure" => Some("azure"),
+        "gs" => Some("gcs"),
+        "b2" => Some("b2"),
+        "dav" | "davs" => Some("webdav"),
+        _ => None,
+    }
+}
+
+/// `(scheme, rest)` of a URL; drive letters such as `C:\` are not schemes
+pub(crate) fn split_url(location: &str) -> Option<(&str, &str)> {
+    let (scheme, rest) = location.split_once("://")?;
+    let valid = scheme.len() > 1
+        && scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'));
+    valid.then_some((scheme, rest))
+}
+
+/// Input available as a local file for as long as this value lives
+pub enum StagedInput {
+    Local(PathBuf),
+    Downloaded(NamedTempFile),
+}
+
+impl StagedInput {
+    /// Stage a path or URL for reading
+    pub fn new(location: &Path, config: &StorageConfig) -> io::Result<Self> {
+        match Location::parse(location, config)? {
+            Some(location) => location.fetch(),
+            None => Ok(StagedInput::Local(location.to_path_buf())),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        match self {
+            StagedInput::Local(path) => path,
+            StagedInput::Downloaded(file) => file.path(),
+        }
+    }
+}
+
+/// What is flushed to the device once a local output is complete
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum FsyncPolicy {
+    /// Leave it to the operating system
+    #[default]
+    None,
+    /// The output file
+    Output,
+    /// The output file and the directory entry naming it
+    All,
+}
+
+/// Flush `path`, and its parent directory under [`FsyncPolicy::All`], to the device
+pub fn sync_output(path: &Path, policy: FsyncPolicy) -> io::Result<()> {
+    if policy == FsyncPolicy::None {
+        return Ok(());
+    }
+    File::open(path)?.sync_all()?;
+    // Directories cannot be opened as files on Windows
+    #[cfg(unix)]
+    if policy == FsyncPolicy::All {
+        let parent = path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        File::open(parent)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Output written to a local file, then stored at its location
+pub struct StagedOutput {
+    path: PathBuf,
+    upload: Option<(Location, NamedTempFile)>,
+    /// Applied to local outputs; uploads are as durable as their backend makes them
+    fsync: FsyncPolicy,
+}
+
+impl StagedOutput {
+    /// Stage a path or URL for writing
+    pub fn new(location: &Path, config: &StorageConfig) -> io::Result<Self> {
+        let staged = match Location::parse(location, config)? {
+            Some(location) => location.stage_output()?,
+            None => StagedOutput { path: location.to_path_buf(), upload: None, fsync: FsyncPolicy::None },
+        };
+        Ok(StagedOutput { fsync: config.fsync, ..staged })
+    }
+
+    /// File the output is written to
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Store the written file; dropping the output without finishing discards it
+    pub fn finish(self) -> io::Result<()> {
+        match self.upload {
+            Some((location, staging)) => {
+                info!(object = %location.name, "Uploading");
+                location.backend.write(&location.name, staging.path())?;
+                if let Some(cache) = &location.cache {
+                    cache.clear();
+                }
+            }
+            None => sync_output(&self.path, self.fsync)?,
+        }
+        Ok(())
+    }
+}
+
+/// Writer copying everything written to its output to a mirror file as well,
+/// so both are produced in the same pass
+pub(crate) struct MirrorWriter {
+    output: Box<dyn Write + Send>,
+    mirror: Option<File>,
+}
+
+impl MirrorWriter {
+    /// Create `output`, written around the page cache when `direct_io`, and `mirror` when given
+    pub(crate) fn create<E: PathIoError>(output: &Path, mirror: Option<&Path>, direct_io: bool) -> Result<Self, E> {
+        let create = |path: &Path| File::create(path).map_err(|e| E::io_at(e, path));
+        let output: Box<dyn Write + Send> = if direct_io {
+            crate::directio::create(output).map_err(|e| E::io_at(e, output))?
+        } else {
+            Box::new(create(output)?)
+        };
+        Ok(Self { output, mirror: mirror.map(create).transpose()? })
+    }
+}
+
+impl Write for MirrorWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.output.write(buf)?;
+        if let Some(mirror) = &mut self.mirror {
+            mirror.write_all(&buf[..written])?;
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()?;
+        if let Some(mirror) = &mut self.mirror {
+            mirror.flush()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    /// Local backend whose objects must be staged, as with a remote one
+    struct Staged(LocalBackend);
+
+    impl StorageBackend for Staged {
+        fn open(&self, name: &str) -> io::Result<Box<dyn Read + Send>> {
+            self.0.open(name)
+        }
+        fn read_range(&self, name: &str, range: Range<u64>) -> io::Result<Vec<u8>> {
+            self.0.read_range(name, range)
+        }
+        fn write(&self, name: &str, source: &Path) -> io::Result<()> {
+            self.0.write(name, source)
+        }
+        fn list(&self, prefix: &str) -> io::Result<Vec<ObjectInfo>> {
+            self.0.list(prefix)
+        }
+        fn delete(&self, name: &str) -> io::Result<()> {
+            self.0.delete(name)
+        }
+    }
+
+    #[test]
+    fn test_local_backend() {
+        let temp_dir = tempdir().unwrap();
+        let backend = LocalBackend::new(temp_dir.path().join("store"));
+        let source = temp_dir.path().join("source.zpak");
+        fs::write(&source, b"0123456789").unwrap();
+
+        backend.write("images/monday.zpak", &source).unwrap();
+        backend.write("images/tuesday.zpak", &source).unwrap();
+        backend.write("notes.txt", &source).unwrap();
+        let names: Vec<_> = backend.list("images/").unwrap().into_iter().map(|object| (object.name, object.size)).collect();
+        assert_eq!(names, vec![("images/monday.zpak".to_string(), 10), ("images/tuesday.zpak".to_string(), 10)]);
+        assert_eq!(backend.read_range("images/monday.zpak", 3..6).unwrap(), b"345");
+        assert_eq!(backend.read_range("images/monday.zpak", 8..20).unwrap(), b"89");
+        let mut content = String::new();
+        backend.open("notes.txt").unwrap().read_to_string(&mut content).unwrap();
+        assert_eq!(content, "0123456789");
+
+        backend.delete("images/monday.zpak").unwrap();
+        assert_eq!(backend.list("").unwrap().len(), 2);
+        assert_eq!(backend.open("../source.zpak").err().unwrap().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_staging() {
+        let temp_dir = tempdir().unwrap();
+        let store = temp_dir.path().join("store");
+        let location = || Location { backend: Box::new(Staged(LocalBackend::new(&store))), name: "out/image.zpak".to_string(), cache: None };
+
+        let output = location().stage_output().unwrap();
+        assert_eq!(output.path().extension().unwrap(), "zpak");
+        fs::write(output.path(), b"image").unwrap();
+        output.finish().unwrap();
+        assert_eq!(fs::read(store.join("out/image.zpak")).unwrap(), b"image");
+
+        let input = location().fetch().unwrap();
+        assert!(matches!(input, StagedInput::Downloaded(_)));
+        assert_eq!(fs::read(input.path()).unwrap(), b"image");
+
+        // Plain paths and file:// URLs are used in place
+        let config = StorageConfig::default();
+        let url = format!("file://{}", store.join("out/image.zpak").display());
+        assert_eq!(StagedInput::new(Path::new(&url), &config).unwrap().path(), store.join("out/image.zpak"));
+        assert_eq!(StagedInput::new(Path::new("C:/backup.zpak"), &config).unwrap().path(), Path::new("C:/backup.zpak"));
+        assert_eq!(Location::parse(Path::new("s4://bucket/x"), &config).err().unwrap().kind(), io::ErrorKind::Unsupported);
+
+        // Local outputs are flushed to the device as configured
+        let config = StorageConfig { fsync: FsyncPolicy::All, ..Default::default() };
+        let output = StagedOutput::new(&store.join("synced.zpak"), &config).unwrap();
+        assert_eq!(output.fsync, FsyncPolicy::All);
+        fs::write(output.path(), b"image").unwrap();
+        output.finish().unwrap();
+        assert!(sync_output(Path::new("missing.zpak"), FsyncPolicy::Output).is_err());
+        sync_output(Path::new("missing.zpak"), FsyncPolicy::None).unwrap();
+    }
+
+    #[test]
+    fn test_mirror_writer() {
+        use crate::error::ImageError;
+
+        let temp_dir = tempdir().unwrap();
+        let (output, mirror) = (temp_dir.path().join("image.zpak"), temp_dir.path().join("mirror.zpak"));
+        let mut writer = io::BufWriter::with_capacity(4, MirrorWriter::create::<ImageError>(&output, Some(&mirror), false).unwrap());
+        writer.write_all(b"header").unwrap();
+        writer.write_all(b"blocks and index").unwrap();
+        writer.flush().unwrap();
+        assert_eq!(fs::read(&output).unwrap(), b"headerblocks and index");
+        assert_eq!(fs::read(&mirror).unwrap(), fs::read(&output).unwrap());
+
+        let missing = temp_dir.path().join("missing/mirror.zpak");
+        assert!(MirrorWriter::create::<ImageError>(&output, Some(&missing), false).is_err());
+    }
+}
@@ -0,0 +1,526 @@
+//! Block-level replication of image repositories for `zippy repo sync`
+//!
+//! A repository is a directory of .zpak images. The receiving side announces
+//! the images it holds and every block stored in them; the sender then streams
+//! each image the receiver lacks, with the data of blocks the receiver already
+//! stores replaced by references. The transfer is proportional to the new
+//! blocks, and a copy is identical to its original whenever the referenced
+//! blocks were compressed the same way.
+//!
+//! Over SSH the receiver is `zippy repo serve PATH`, started on the remote
+//! host and speaking on its standard input and output. Storage backends cannot
+//! assemble images, so missing images are uploaded whole to them.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use tracing::{debug, info, warn};
+
+use crate::blockio::read_exact_at;
+use crate::error::{ImageError, PathIoError};
+use crate::config::StorageConfig;
+use crate::format::Codec;
+use crate::image::{stored_blocks, StoredBlock};
+use crate::storage::{split_url, Location, StorageBackend};
+
+const MAGIC: &[u8; 8] = b"ZPSYNC3\n";
+/// Largest piece of image data sent in one message
+const CHUNK_SIZE: usize = 1024 * 1024;
+
+const TAG_END: u8 = 0;
+const TAG_IMAGE: u8 = 1;
+const TAG_DATA: u8 = 2;
+const TAG_BLOCK: u8 = 3;
+
+const STATUS_OK: u8 = 0;
+const STATUS_ERROR: u8 = 1;
+
+/// Block as announced by the receiver: hash algorithm (0 when unrecorded), hash,
+/// compressed size and codec. The hash is that of the content, so the same
+/// content stored with another codec is another block.
+type BlockKey = (u8, [u8; 32], u64, Codec);
+
+fn block_key(algorithm: u8, block: &StoredBlock) -> BlockKey {
+    (algorithm, *block.hash.as_ref(), block.compressed_size, block.codec)
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyncSummary {
+    /// Images copied
+    pub images: u64,
+    /// Images the destination already held
+    pub skipped: u64,
+    /// Image bytes transferred
+    pub bytes_sent: u64,
+    /// Image bytes taken from blocks the destination already held
+    pub bytes_reused: u64,
+}
+
+/// Where `zippy repo sync` copies images to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Destination {
+    Local(PathBuf),
+    /// `ssh://[user@]host[:port]/path`
+    Ssh { host: String, port: Option<u16>, path: String },
+    /// URL of a storage backend, e.g. `file:///srv/repo`
+    Storage(String),
+}
+
+impl Destination {
+    pub fn parse(destination: &str) -> Result<Self, String> {
+        let Some(rest) = destination.strip_prefix("ssh://") else {
+            return Ok(match split_url(destination) {
+                Some(_) => Destination::Storage(destination.to_string()),
+                None => Destination::Local(PathBuf::from(destination)),
+            });
+        };
+        let (authority, path) = rest.split_at(rest.find('/').ok_or("ssh:// destination needs a path")?);
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port.parse().map_err(|_| format!("invalid port: {}", port))?)),
+            None => (authority, None),
+        };
+        if host.is_empty() {
+            return Err("ssh:// destination needs a host".to_string());
+        }
+        // ssh would take it for an option
+        if host.starts_with('-') {
+            return Err(format!("invalid ssh host: {}", host));
+        }
+        Ok(Destination::Ssh { host: host.to_string(), port, path: path.to_string() })
+    }
+}
+
+/// Copy the images of `source` that `destination` lacks
+///
+/// `remote_command` is the `zippy` executable started on SSH destinations,
+/// `storage` configures the backends of storage URLs.
+pub fn sync(source: &Path, destination: &Destination, remote_command: &str, storage: &StorageConfig) -> Result<SyncSummary, ImageError> {
+    match destination {
+        Destination::Local(path) => {
+            fs::create_dir_all(path).map_err(|e| ImageError::io_at(e, path))?;
+            let (request_reader, request_writer) = io::pipe()?;
+            let (reply_reader, reply_writer) = io::pipe()?;
+            thread::scope(|scope| {
+                let receiver = scope.spawn(|| receive(path, request_reader, reply_writer));
+                let sent = send(source, reply_reader, request_writer);
+                // The receiver's error explains a broken pipe on the sending side
+                let received = receiver.join().expect("receiver panicked");
+                received.and(sent)
+            })
+        }
+        Destination::Ssh { host, port, path } => {
+            let mut ssh = Command::new("ssh");
+            if let Some(port) = port {
+                ssh.arg("-p").arg(port.to_string());
+            }
+            ssh.arg("--").arg(host).arg(format!("{} repo serve {}", remote_command, shell_quote(path)));
+            debug!(command = ?ssh, "Starting remote receiver");
+            let mut child = ssh.stdin(Stdio::piped()).stdout(Stdio::piped()).spawn()?;
+            let stdin = child.stdin.take().expect("piped stdin");
+            let stdout = child.stdout.take().expect("piped stdout");
+            let sent = send(source, stdout, stdin);
+            let status = child.wait()?;
+            let remote_failed = || io::Error::other(format!("remote receiver failed: {}", status)).into();
+            match sent {
+                // A receiver that could not start or stopped early closes the stream
+                Err(ImageError::Io(e)) if !status.success() && matches!(e.kind(), io::ErrorKind::UnexpectedEof | io::ErrorKind::BrokenPipe) => {
+                    Err(remote_failed())
+                }
+                Ok(_) if !status.success() => Err(remote_failed()),
+                sent => sent,
+            }
+        }
+        Destination::Storage(url) => {
+            let location = Location::parse(Path::new(url), storage)?.expect("storage destinations are URLs");
+            upload_images(source, &*location.backend, &location.name)
+        }
+    }
+}
+
+/// Upload the images missing under `prefix`/ of a backend
+fn upload_images(source: &Path, backend: &dyn StorageBackend, prefix: &str) -> Result<SyncSummary, ImageError> {
+    let prefix = format!("{}/", prefix.trim_end_matches('/'));
+    let existing: HashMap<String, u64> = backend
+        .list(&prefix)?
+        .into_iter()
+        .map(|object| (object.name[prefix.len()..].to_string(), object.size))
+        .collect();
+    let mut summary = SyncSummary::default();
+    for (name, path) in repository_images(source)? {
+        let size = fs::metadata(&path)?.len();
+        if let Some(&remote_size) = existing.get(&name) {
+            if remote_size != size {
+                warn!(image = %name, size, remote_size, "Image differs on the destination, leaving it as is");
+            }
+            summary.skipped += 1;
+            continue;
+        }
+        backend.write(&format!("{}{}", prefix, name), &path)?;
+        info!(image = %name, size, "Image uploaded");
+        summary.images += 1;
+        summary.bytes_sent += size;
+    }
+    Ok(summary)
+}
+
+/// Sending side: read the receiver's inventory from `input`, stream the missing images to `output`
+pub fn send(repository: &Path, input: impl Read, output: impl Write) -> Result<SyncSummary, ImageError> {
+    let mut input = BufReader::new(input);
+    let mut output = BufWriter::new(output);
+    let mut magic = [0u8; 8];
+    input.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(protocol_error("not a zippy sync receiver"));
+    }
+    let mut remote_images = HashMap::new();
+    for _ in 0..read_u64(&mut input)? {
+        let name = read_string(&mut input)?;
+        remote_images.insert(name, read_u64(&mut input)?);
+    }
+    let mut remote_blocks = HashSet::new();
+    for _ in 0..read_u64(&mut input)? {
+        remote_blocks.insert(read_block_key(&mut input)?);
+    }
+    info!(images = remote_images.len(), blocks = remote_blocks.len(), "Destination inventory received");
+
+    let mut summary = SyncSummary::default();
+    for (name, path) in repository_images(repository)? {
+        let size = fs::metadata(&path)?.len();
+        if let Some(&remote_size) = remote_images.get(&name) {
+            if remote_size != size {
+                warn!(image = %name, size, remote_size, "Image differs on the destination, leaving it as is");
+            }
+            summary.skipped += 1;
+            continue;
+        }
+        let (algorithm, blocks) = stored_blocks(&path)?;
+        let algorithm = algorithm.map_or(0, |algorithm| algorithm.to_byte());
+        let mut file = File::open(&path).map_err(|e| ImageError::io_at(e, &path))?;
+        output.write_all(&[TAG_IMAGE])?;
+        write_string(&mut output, &name)?;
+        output.write_all(&size.to_le_bytes())?;
+
+        let (mut position, mut sent, mut reused) = (0, 0, 0);
+        for block in &blocks {
+            let key = block_key(algorithm, block);
+            if !remote_blocks.contains(&key) {
+                continue;
+            }
+            sent += send_data(&mut file, &mut output, position, block.offset)?;
+            output.write_all(&[TAG_BLOCK])?;
+            write_block_key(&mut output, &key)?;
+            position = block.offset + block.compressed_size;
+            reused += block.compressed_size;
+        }
+        sent += send_data(&mut file, &mut output, position, size)?;
+        output.write_all(&[TAG_END])?;
+        // The receiver indexes the image once stored, so later images can reference its blocks
+        remote_blocks.extend(blocks.iter().map(|block| block_key(algorithm, block)));
+        info!(image = %name, size, sent, reused, "Image sent");
+        summary.images += 1;
+        summary.bytes_sent += sent;
+        summary.bytes_reused += reused;
+    }
+    output.write_all(&[TAG_END])?;
+    output.flush()?;
+
+    let mut status = [0u8; 1];
+    input.read_exact(&mut status)?;
+    match status[0] {
+        STATUS_OK => Ok(summary),
+        _ => Err(io::Error::other(format!("destination: {}", read_string(&mut input)?)).into()),
+    }
+}
+
+/// Receiving side: announce the inventory of `repository` on `output`, store the images read from `input`
+pub fn receive(repository: &Path, input: impl Read, output: impl Write) -> Result<SyncSummary, ImageError> {
+    let mut input = BufReader::new(input);
+    let mut output = BufWriter::new(output);
+    let result = receive_images(repository, &mut input, &mut output);
+    // The sender may be gone already: the status is best effort
+    let _ = match &result {
+        Ok(_) => output.write_all(&[STATUS_OK]),
+        Err(e) => output.write_all(&[STATUS_ERROR]).and_then(|_| write_string(&mut output, &e.to_string())),
+    };
+    let _ = output.flush();
+    result
+}
+
+fn receive_images(repository: &Path, input: &mut impl Read, output: &mut impl Write) -> Result<SyncSummary, ImageError> {
+    let images = repository_images(repository)?;
+    let mut sources = Vec::new();
+    let mut blocks = HashMap::new();
+    output.write_all(MAGIC)?;
+    output.write_all(&(images.len() as u64).to_le_bytes())?;
+    for (name, path) in &images {
+        write_string(output, name)?;
+        output.write_all(&fs::metadata(path)?.len().to_le_bytes())?;
+        index_blocks(path, &mut sources, &mut blocks);
+    }
+    output.write_all(&(blocks.len() as u64).to_le_bytes())?;
+    for key in blocks.keys() {
+        write_block_key(output, key)?;
+    }
+    output.flush()?;
+
+    let mut summary = SyncSummary::default();
+    let mut buffer = vec![0u8; CHUNK_SIZE];
+    loop {
+        match read_u8(input)? {
+            TAG_END => break,
+            TAG_IMAGE => {}
+            tag => return Err(protocol_error(&format!("unexpected message {}", tag))),
+        }
+        let name = read_string(input)?;
+        if !is_image_name(&name) {
+            return Err(protocol_error(&format!("invalid image name {:?}", name)));
+        }
+        let size = read_u64(input)?;
+        let mut staging = tempfile::NamedTempFile::new_in(repository)?;
+        let mut writer = BufWriter::new(staging.as_file_mut());
+        let (mut received, mut reused) = (0u64, 0u64);
+        loop {
+            match read_u8(input)? {
+                TAG_END => break,
+                TAG_DATA => {
+                    let length = read_u64(input)?;
+                    io::copy(&mut input.by_ref().take(length), &mut writer)?;
+                    received += length;
+                }
+                TAG_BLOCK => {
+                    let key = read_block_key(input)?;
+                    let &(source, offset) = blocks.get(&key).ok_or_else(|| protocol_error("reference to an unknown block"))?;
+                    if buffer.len() < key.2 as usize {
+                        buffer.resize(key.2 as usize, 0);
+                    }
+                    let data = &mut buffer[..key.2 as usize];
+                    read_exact_at(&sources[source], data, offset)?;
+                    writer.write_all(data)?;
+                    reused += key.2;
+                }
+                tag => return Err(protocol_error(&format!("unexpected message {}", tag))),
+            }
+        }
+        writer.flush()?;
+        drop(writer);
+        if received + reused != size {
+            return Err(protocol_error(&format!("{} has {} bytes, {} announced", name, received + reused, size)));
+        }
+        let path = repository.join(&name);
+        staging.persist_noclobber(&path).map_err(|e| e.error)?;
+        index_blocks(&path, &mut sources, &mut blocks);
+        info!(image = %name, size, received, reused, "Image received");
+        summary.images += 1;
+        summary.bytes_sent += received;
+        summary.bytes_reused += reused;
+    }
+    Ok(summary)
+}
+
+/// Record where the blocks of an image are; an unreadable image only warns
+fn index_blocks(path: &Path, sources: &mut Vec<File>, blocks: &mut HashMap<BlockKey, (usize, u64)>) {
+    let indexed = stored_blocks(path).and_then(|(algorithm, stored)| {
+        let file = File::open(path)?;
+        let algorithm = algorithm.map_or(0, |algorithm| algorithm.to_byte());
+        for block in stored {
+            blocks.entry(block_key(algorithm, &block)).or_insert((sources.len(), block.offset));
+        }
+        sources.push(file);
+        Ok(())
+    });
+    if let Err(e) = indexed {
+        warn!(image = %path.display(), error = %e, "Image not indexed, its blocks will be transferred again");
+    }
+}
+
+/// .zpak images at the top of a repository, sorted by name
+fn repository_images(repository: &Path) -> Result<Vec<(String, PathBuf)>, ImageError> {
+    let mut images = Vec::new();
+    for entry in fs::read_dir(repository).map_err(|e| ImageError::io_at(e, repository))? {
+        let entry = entry?;
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+        match entry.file_name().into_string() {
+            Ok(name) if is_image_name(&name) => images.push((name, entry.path())),
+            Ok(_) => {}
+            Err(name) => warn!(name = ?name, "Skipping image whose name is not UTF-8"),
+        }
+    }
+    images.sort();
+    Ok(images)
+}
+
+fn is_image_name(name: &str) -> bool {
+    name.ends_with(".zpak") && !name.starts_with('.') && !name.contains(['/', '\\'])
+}
+
+/// Copy `start..end` of the image as data messages; returns the bytes sent
+fn send_data(file: &mut File, output: &mut impl Write, start: u64, end: u64) -> io::Result<u64> {
+    if start >= end {
+        return Ok(0);
+    }
+    file.seek(SeekFrom::Start(start))?;
+    let mut buffer = vec![0u8; CHUNK_SIZE.min((end - start) as usize)];
+    let mut position = start;
+    while position < end {
+        let chunk = &mut buffer[..CHUNK_SIZE.min((end - position) as usize)];
+        file.read_exact(chunk)?;
+        output.write_all(&[TAG_DATA])?;
+        output.write_all(&(chunk.len() as u64).to_le_bytes())?;
+        output.write_all(chunk)?;
+        position += chunk.len() as u64;
+    }
+    Ok(end - start)
+}
+
+/// Single-quote an argument for the remote shell
+fn shell_quote(argument: &str) -> String {
+    format!("'{}'", argument.replace('\'', r"'\''"))
+}
+
+fn protocol_error(message: &str) -> ImageError {
+    io::Error::new(io::ErrorKind::InvalidData, format!("invalid sync stream: {}", message)).into()
+}
+
+fn read_u8(input: &mut impl Read) -> io::Result<u8> {
+    let mut byte = [0u8; 1];
+    input.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+fn read_u64(input: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn write_string(output: &mut impl Write, value: &str) -> io::Result<()> {
+    output.write_all(&(value.len() as u32).to_le_bytes())?;
+    output.write_all(value.as_bytes())
+}
+
+fn read_string(input: &mut impl Read) -> io::Result<String> {
+    let mut length = [0u8; 4];
+    input.read_exact(&mut length)?;
+    let mut bytes = Vec::new();
+    input.take(u32::from_le_bytes(length) as u64).read_to_end(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid sync stream: string is not UTF-8"))
+}
+
+fn write_block_key(output: &mut impl Write, (algorithm, hash, compressed_size, codec): &BlockKey) -> io::Result<()> {
+    output.write_all(&[*algorithm])?;
+    output.write_all(hash)?;
+    output.write_all(&compressed_size.to_le_bytes())?;
+    codec.write_to(output)
+}
+
+fn read_block_key(input: &mut impl Read) -> io::Result<BlockKey> {
+    let algorithm = read_u8(input)?;
+    let mut hash = [0u8; 32];
+    input.read_exact(&mut hash)?;
+    let compressed_size = read_u64(input)?;
+    if compressed_size > CHUNK_SIZE as u64 * 64 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid sync stream: block too large"));
+    }
+    Ok((algorithm, hash, compressed_size, Codec::read_from(input)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::{create_image, ImageOptions};
+    use tempfile::tempdir;
+
+    fn image(input: &Path, output: PathBuf) {
+        create_image(&ImageOptions { input_path: input.to_path_buf(), output_path: output, compression_level: 3, ..Default::default() }).unwrap();
+    }
+
+    #[test]
+    fn test_sync_sends_missing_blocks() {
+        let temp_dir = tempdir().unwrap();
+        let (source, destination) = (temp_dir.path().join("source"), temp_dir.path().join("destination"));
+        let data = temp_dir.path().join("data");
+        fs::create_dir_all(&source).unwrap();
+        fs::create_dir_all(&data).unwrap();
+        let random: Vec<u8> = (0..2_000_000u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8).collect();
+        fs::write(data.join("large.bin"), &random).unwrap();
+        image(&data, source.join("monday.zpak"));
+        fs::write(source.join("notes.txt"), "not an image").unwrap();
+
+        let first = sync(&source, &Destination::Local(destination.clone()), "zippy", &StorageConfig::default()).unwrap();
+        assert_eq!((first.images, first.skipped, first.bytes_reused), (1, 0, 0));
+        assert_eq!(fs::read(destination.join("monday.zpak")).unwrap(), fs::read(source.join("monday.zpak")).unwrap());
+        assert!(!destination.join("notes.txt").exists());
+
+        // A new image sharing most blocks only sends what changed
+        fs::write(data.join("small.txt"), "tuesday").unwrap();
+        image(&data, source.join("tuesday.zpak"));
+        let second = sync(&source, &Destination::Local(destination.clone()), "zippy", &StorageConfig::default()).unwrap();
+        let size = fs::metadata(source.join("tuesday.zpak")).unwrap().len();
+        assert_eq!((second.images, second.skipped), (1, 1));
+        assert_eq!(second.bytes_sent + second.bytes_reused, size);
+        assert!(second.bytes_sent < size / 10, "sent {} of {}", second.bytes_sent, size);
+        assert_eq!(fs::read(destination.join("tuesday.zpak")).unwrap(), fs::read(source.join("tuesday.zpak")).unwrap());
+
+        let third = sync(&source, &Destination::Local(destination), "zippy", &StorageConfig::default()).unwrap();
+        assert_eq!((third.images, third.skipped, third.bytes_sent), (0, 2, 0));
+
+        // Storage backends receive whole images
+        let url = format!("file://{}", temp_dir.path().join("bucket/repo").display());
+        let uploaded = sync(&source, &Destination::Storage(url.clone()), "zippy", &StorageConfig::default()).unwrap();
+        assert_eq!((uploaded.images, uploaded.skipped), (2, 0));
+        assert_eq!(fs::read(temp_dir.path().join("bucket/repo/tuesday.zpak")).unwrap(), fs::read(source.join("tuesday.zpak")).unwrap());
+        assert_eq!(sync(&source, &Destination::Storage(url), "zippy", &StorageConfig::default()).unwrap().skipped, 2);
+    }
+
+    #[test]
+    fn test_parse_destination() {
+        assert_eq!(Destination::parse("backups/repo"), Ok(Destination::Local(PathBuf::from("backups/repo"))));
+        assert_eq!(
+            Destination::parse("ssh://me@host:2222/srv/repo"),
+            Ok(Destination::Ssh { host: "me@host".to_string(), port: Some(2222), path: "/srv/repo".to_string() })
+        );
+        assert_eq!(Destination::parse("file:///srv/repo"), Ok(Destination::Storage("file:///srv/repo".to_string())));
+        assert!(Destination::parse("ssh://host").is_err());
+        assert!(Destination::parse("ssh://host:port/repo").is_err());
+        assert!(Destination::parse("ssh://-oProxyCommand=touch%20pwned/repo").is_err());
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+    }
+
+    #[test]
+    fn test_block_key_includes_codec() {
+        let zstd = StoredBlock { hash: [5u8; 32].into(), offset: 0, compressed_size: 100, codec: Codec::zstd(3) };
+        let stored = StoredBlock { codec: Codec::STORED, ..zstd.clone() };
+        assert_ne!(block_key(1, &zstd), block_key(1, &stored));
+
+        let mut announced = Vec::new();
+        write_block_key(&mut announced, &block_key(1, &stored)).unwrap();
+        assert_eq!(read_block_key(&mut &announced[..]).unwrap(), block_key(1, &stored));
+    }
+
+    #[test]
+    fn test_receive_messages() {
+        let temp_dir = tempdir().unwrap();
+        let mut stream = vec![TAG_IMAGE];
+        write_string(&mut stream, "raw.zpak").unwrap();
+        stream.extend(5u64.to_le_bytes());
+        for chunk in ["abc", "de"] {
+            stream.push(TAG_DATA);
+            stream.extend((chunk.len() as u64).to_le_bytes());
+            stream.extend(chunk.as_bytes());
+        }
+        stream.extend([TAG_END, TAG_END]);
+        let summary = receive(temp_dir.path(), &stream[..], Vec::new()).unwrap();
+        assert_eq!((summary.images, summary.bytes_sent), (1, 5));
+        assert_eq!(fs::read(temp_dir.path().join("raw.zpak")).unwrap(), b"abcde");
+
+        // Data outside an image is not taken for the start of one
+        let mut stray = vec![TAG_DATA];
+        stray.extend(stream[1..].iter());
+        assert!(receive(temp_dir.path(), &stray[..], Vec::new()).is_err());
+    }
+}
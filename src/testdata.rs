@@ -0,0 +1,284 @@
+//! Synthetic datasets for reproducing compression and deduplication benchmarks
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use clap::ValueEnum;
+use tracing::info;
+
+/// Kind of content generated
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum TestDataProfile {
+    /// Source and configuration files sharing most of their content
+    #[default]
+    Codebase,
+    /// Incompressible binary files, as photos or videos would be
+    Media,
+    /// Three source files for every binary file
+    Mixed,
+}
+
+pub struct TestDataOptions {
+    pub output_path: PathBuf,
+    pub profile: TestDataProfile,
+    /// Total number of bytes to write, rounded up to the last file
+    pub size: u64,
+    /// Fraction of files that are exact copies of an earlier file
+    pub duplicate_ratio: f64,
+    /// Same seed, same dataset
+    pub seed: u64,
+}
+
+impl Default for TestDataOptions {
+    fn default() -> Self {
+        Self {
+            output_path: PathBuf::from("test_files"),
+            profile: TestDataProfile::default(),
+            size: 64 * 1024 * 1024,
+            duplicate_ratio: 0.1,
+            seed: 42,
+        }
+    }
+}
+
+/// What was written to disk
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct TestDataSummary {
+    pub files: u64,
+    pub bytes: u64,
+    pub duplicates: u64,
+}
+
+const REACT_COMPONENTS: &[(&str, &str)] = &[
+    ("Header", "function Header() { return <header><h1>Mon App</h1><nav>Navigation</nav></header>; }"),
+    ("Footer", "function Footer() { return <footer><p>&copy; 2024 Mon App</p></footer>; }"),
+    ("Button", "function Button({ onClick, children }) { return <button onClick={onClick}>{children}</button>; }"),
+    ("Modal", "function Modal({ isOpen, onClose, children }) { return isOpen ? <div className=\"modal\">{children}</div> : null; }"),
+    ("Card", "function Card({ title, content }) { return <div className=\"card\"><h3>{title}</h3><p>{content}</p></div>; }"),
+    ("List", "function List({ items }) { return <ul>{items.map(item => <li key={item.id}>{item.name}</li>)}</ul>; }"),
+];
+
+const PYTHON_SERVICES: &[(&str, &str)] = &[
+    ("UserService", "class UserService:\n    def __init__(self):\n        self.users = {}\n    \n    def create_user(self, name, email):\n        return {'id': len(self.users), 'name': name, 'email': email}\n    \n    def get_user(self, user_id):\n        return self.users.get(user_id)\n"),
+    ("AuthService", "class AuthService:\n    def __init__(self):\n        self.tokens = {}\n    \n    def login(self, username, password):\n        if self.validate_credentials(username, password):\n            return self.generate_token(username)\n        return None\n    \n    def validate_credentials(self, username, password):\n        return username == 'admin' and password == 'secret'\n"),
+    ("DatabaseService", "class DatabaseService:\n    def __init__(self, connection_string):\n        self.connection = connection_string\n        self.queries = []\n    \n    def execute_query(self, query):\n        self.queries.append(query)\n        return {'status': 'success', 'rows': 42}\n    \n    def get_connection(self):\n        return self.connection\n"),
+    ("EmailService", "class EmailService:\n    def __init__(self, smtp_server):\n        self.smtp_server = smtp_server\n        self.sent_emails = []\n    \n    def send_email(self, to, subject, body):\n        email = {'to': to, 'subject': subject, 'body': body}\n        self.sent_emails.append(email)\n        return True\n"),
+    ("LoggerService", "class LoggerService:\n    def __init__(self, log_level='INFO'):\n        self.log_level = log_level\n        self.logs = []\n    \n    def log(self, message, level='INFO'):\n        log_entry = {'message': message, 'level': level, 'timestamp': 'now'}\n        self.logs.append(log_entry)\n        print(f'[{level}] {message}')\n"),
+];
+
+const RUST_MODULES: &[(&str, &str)] = &[
+    ("cache", "use std::collections::HashMap;\n\npub struct Cache<K, V> {\n    data: HashMap<K, V>,\n    max_size: usize,\n}\n\nimpl<K, V> Cache<K, V> where K: std::hash::Hash + Eq {\n    pub fn new(max_size: usize) -> Self {\n        Cache { data: HashMap::new(), max_size }\n    }\n    \n    pub fn get(&self, key: &K) -> Option<&V> {\n        self.data.get(key)\n    }\n    \n    pub fn insert(&mut self, key: K, value: V) {\n        if self.data.len() >= self.max_size {\n            self.evict_oldest();\n        }\n        self.data.insert(key, value);\n    }\n}"),
+    ("queue", "use std::collections::VecDeque;\n\npub struct Queue<T> {\n    items: VecDeque<T>,\n    max_capacity: usize,\n}\n\nimpl<T> Queue<T> {\n    pub fn new(capacity: usize) -> Self {\n        Queue { items: VecDeque::new(), max_capacity: capacity }\n    }\n    \n    pub fn enqueue(&mut self, item: T) -> Result<(), &'static str> {\n        if self.items.len() >= self.max_capacity {\n            return Err(\"Queue is full\");\n        }\n        self.items.push_back(item);\n        Ok(())\n    }\n    \n    pub fn dequeue(&mut self) -> Option<T> {\n        self.items.pop_front()\n    }\n}"),
+    ("tree", "pub struct TreeNode<T> {\n    value: T,\n    left: Option<Box<TreeNode<T>>>,\n    right: Option<Box<TreeNode<T>>>,\n}\n\nimpl<T> TreeNode<T> where T: Ord {\n    pub fn new(value: T) -> Self {\n        TreeNode { value, left: None, right: None }\n    }\n    \n    pub fn insert(&mut self, value: T) {\n        if value < self.value {\n            match &mut self.left {\n                Some(left) => left.insert(value),\n                None => self.left = Some(Box::new(TreeNode::new(value))),\n            }\n        } else {\n            match &mut self.right {\n                Some(right) => right.insert(value),\n                None => self.right = Some(Box::new(TreeNode::new(value))),\n            }\n        }\n    }\n}"),
+    ("parser", "pub struct Parser {\n    input: String,\n    position: usize,\n}\n\nimpl Parser {\n    pub fn new(input: String) -> Self {\n        Parser { input, position: 0 }\n    }\n    \n    pub fn parse_number(&mut self) -> Result<i32, &'static str> {\n        let start = self.position;\n        while self.position < self.input.len() && self.current_char().is_ascii_digit() {\n            self.position += 1;\n        }\n        \n        if start == self.position {\n            return Err(\"Expected number\");\n        }\n        \n        self.input[start..self.position].parse().map_err(|_| \"Invalid number\")\n    }\n    \n    fn current_char(&self) -> char {\n        self.input.chars().nth(self.position).unwrap_or('\\0')\n    }\n}"),
+];
+
+const CONFIG_TYPES: &[(&str, &str)] = &[
+    ("database", "{\n  \"host\": \"localhost\",\n  \"port\": 5432,\n  \"database\": \"myapp\",\n  \"username\": \"admin\",\n  \"password\": \"secret\",\n  \"ssl\": true,\n  \"timeout\": 30,\n  \"pool_size\": 10\n}"),
+    ("server", "server:\n  host: 0.0.0.0\n  port: 8080\n  threads: 4\n  timeout: 60\n\nlogging:\n  level: info\n  file: app.log\n  max_size: 10MB\n\nfeatures:\n  auth: true\n  cache: true\n  metrics: true"),
+    ("build", "[package]\nname = \"myapp\"\nversion = \"1.0.0\"\nedition = \"2021\"\n\n[dependencies]\nserde = { version = \"1.0\", features = [\"derive\"] }\ntokio = { version = \"1.0\", features = [\"full\"] }\naxum = \"0.6\"\n\n[dev-dependencies]\ntokio-test = \"0.4\""),
+];
+
+const TEMPLATE_COUNT: u64 = (REACT_COMPONENTS.len() + PYTHON_SERVICES.len() + RUST_MODULES.len() + CONFIG_TYPES.len()) as u64;
+
+const MEDIA_EXTENSIONS: &[&str] = &["jpg", "png", "mp4", "webm"];
+const MEDIA_MIN_SIZE: u64 = 64 * 1024;
+const MEDIA_MAX_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Write a dataset into `output_path`, which must be empty or absent
+pub fn generate_test_data(options: &TestDataOptions) -> io::Result<TestDataSummary> {
+    if !(0.0..=1.0).contains(&options.duplicate_ratio) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "duplicate ratio must be between 0 and 1"));
+    }
+    if options.output_path.exists() && fs::read_dir(&options.output_path)?.next().is_some() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} is not empty", options.output_path.display()),
+        ));
+    }
+    fs::create_dir_all(&options.output_path)?;
+
+    let mut rng = Rng::new(options.seed);
+    let mut summary = TestDataSummary::default();
+    let mut originals: Vec<PathBuf> = Vec::new();
+    let mut generated = 0u64;
+
+    while summary.bytes < options.size {
+        if !originals.is_empty() && rng.chance(options.duplicate_ratio) {
+            let source = &originals[rng.below(originals.len() as u64) as usize];
+            let name = source.file_name().unwrap_or_default().to_string_lossy();
+            let target = options.output_path.join("copies").join(format!("{:06}_{}", summary.duplicates, name));
+            fs::create_dir_all(target.parent().unwrap_or(&options.output_path))?;
+            summary.bytes += fs::copy(source, &target)?;
+            summary.duplicates += 1;
+        } else {
+            let (name, content) = match options.profile {
+                TestDataProfile::Codebase => source_file(generated),
+                TestDataProfile::Media => media_file(generated, &mut rng),
+                TestDataProfile::Mixed if generated % 4 == 3 => media_file(generated / 4, &mut rng),
+                TestDataProfile::Mixed => source_file(generated - generated / 4),
+            };
+            let target = options.output_path.join(name);
+            fs::write(&target, &content)?;
+            summary.bytes += content.len() as u64;
+            originals.push(target);
+            generated += 1;
+        }
+        summary.files += 1;
+    }
+
+    info!(files = summary.files, bytes = summary.bytes, duplicates = summary.duplicates, "Test data generated");
+    Ok(summary)
+}
+
+/// Variants of one template differ only by their number, so they deduplicate well
+fn source_file(index: u64) -> (String, Vec<u8>) {
+    let variant = index / TEMPLATE_COUNT + 1;
+    let mut template = (index % TEMPLATE_COUNT) as usize;
+
+    if let Some((name, component)) = REACT_COMPONENTS.get(template) {
+        let content = format!(
+            "import React from 'react';\nimport './styles.css';\n\n{}\n\nexport default {};\n\n// Variant {}\n{}",
+            component,
+            name,
+            variant,
+            "// Component utility functions\nconst utils = { format: (text) => text.toUpperCase() };\n".repeat(100)
+        );
+        return (format!("react_{}_{:02}.jsx", name.to_lowercase(), variant), content.into_bytes());
+    }
+    template -= REACT_COMPONENTS.len();
+
+    if let Some((name, service_code)) = PYTHON_SERVICES.get(template) {
+        let content = format!(
+            "#!/usr/bin/env python3\n# -*- coding: utf-8 -*-\n\nimport json\nimport logging\nfrom typing import Dict, List, Optional\n\n{}\n\n# Utility functions (variant {})\ndef format_response(data):\n    return json.dumps(data, indent=2)\n\ndef validate_input(data):\n    return data is not None and len(str(data)) > 0\n\n{}\n\nif __name__ == '__main__':\n    service = {}()\n    print('Service initialized successfully')\n",
+            service_code,
+            variant,
+            "# Additional helper functions\ndef process_data(items):\n    return [item.upper() for item in items]\n\ndef calculate_hash(text):\n    return hash(text) % 10000\n".repeat(50),
+            name
+        );
+        return (format!("service_{}_{:02}.py", name.to_lowercase(), variant), content.into_bytes());
+    }
+    template -= PYTHON_SERVICES.len();
+
+    if let Some((module_name, module_code)) = RUST_MODULES.get(template) {
+        let content = format!(
+            "// Module: {} - Variant {}\n\nuse std::fmt::Debug;\nuse std::collections::HashMap;\n\n{}\n\n// Common utilities\nfn log_debug(message: &str) {{\n    println!(\"[DEBUG] {{}}\", message);\n}}\n\nfn validate_input<T: Debug>(input: &T) -> bool {{\n    println!(\"Validating: {{:?}}\", input);\n    true\n}}\n\n{}\n\n#[cfg(test)]\nmod tests {{\n    use super::*;\n    \n    #[test]\n    fn test_basic_functionality() {{\n        // Test code for variant {}\n        assert!(true);\n    }}\n}}\n",
+            module_name,
+            variant,
+            module_code,
+            "// Additional helper functions\nfn process_result<T>(result: Result<T, &str>) -> Option<T> {\n    match result {\n        Ok(value) => Some(value),\n        Err(_) => None,\n    }\n}\n\nfn format_output(data: &str) -> String {\n    format!(\"[OUTPUT] {}\", data)\n}\n".repeat(30),
+            variant
+        );
+        return (format!("{}_{:02}.rs", module_name, variant), content.into_bytes());
+    }
+    template -= RUST_MODULES.len();
+
+    let (config_name, config_content) = CONFIG_TYPES[template];
+    let content = format!(
+        "// Configuration file for {} - Variant {}\n{}\n\n// Additional settings\n{}\n",
+        config_name,
+        variant,
+        config_content,
+        "// Default values\n// Environment: development\n// Version: 1.0.0\n// Last updated: 2024-01-01\n".repeat(200)
+    );
+    (format!("config_{}_{:02}.json", config_name, variant), content.into_bytes())
+}
+
+fn media_file(index: u64, rng: &mut Rng) -> (String, Vec<u8>) {
+    let extension = MEDIA_EXTENSIONS[(index % MEDIA_EXTENSIONS.len() as u64) as usize];
+    let size = MEDIA_MIN_SIZE + rng.below(MEDIA_MAX_SIZE - MEDIA_MIN_SIZE + 1);
+    let mut content = vec![0u8; size as usize];
+    rng.fill(&mut content);
+    (format!("media_{:05}.{}", index, extension), content)
+}
+
+/// xorshift64*: the output must not change with a dependency update
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        // Zero is a fixed point of xorshift
+        Self(seed ^ 0x9E37_79B9_7F4A_7C15)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    pub(crate) fn below(&mut self, bound: u64) -> u64 {
+        self.next_u64() % bound
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        ((self.next_u64() >> 11) as f64 / (1u64 << 53) as f64) < probability
+    }
+
+    fn fill(&mut self, buffer: &mut [u8]) {
+        for chunk in buffer.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use tempfile::tempdir;
+
+    fn listing(dir: &Path) -> Vec<(PathBuf, Vec<u8>)> {
+        let mut files: Vec<_> = walkdir::WalkDir::new(dir)
+            .into_iter()
+            .map(|entry| entry.unwrap())
+            .filter(|entry| entry.file_type().is_file())
+            .map(|entry| {
+                let relative = entry.path().strip_prefix(dir).unwrap().to_path_buf();
+                (relative, fs::read(entry.path()).unwrap())
+            })
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn test_generate_reproducible() {
+        let temp_dir = tempdir().unwrap();
+        let options = TestDataOptions {
+            output_path: temp_dir.path().join("a"),
+            profile: TestDataProfile::Mixed,
+            size: 2 * 1024 * 1024,
+            duplicate_ratio: 0.3,
+            ..Default::default()
+        };
+        let summary = generate_test_data(&options).unwrap();
+        assert!(summary.bytes >= options.size);
+        assert!(summary.duplicates > 0);
+        assert!(summary.files > summary.duplicates);
+
+        let again = TestDataOptions { output_path: temp_dir.path().join("b"), ..options };
+        assert_eq!(generate_test_data(&again).unwrap(), summary);
+        assert_eq!(listing(&temp_dir.path().join("a")), listing(&temp_dir.path().join("b")));
+
+        // Refuses to write into a populated directory
+        assert!(generate_test_data(&again).is_err());
+    }
+
+    #[test]
+    fn test_codebase_without_duplicates() {
+        let temp_dir = tempdir().unwrap();
+        let summary = generate_test_data(&TestDataOptions {
+            output_path: temp_dir.path().to_path_buf(),
+            size: 100_000,
+            duplicate_ratio: 0.0,
+            ..Default::default()
+        }).unwrap();
+
+        assert_eq!(summary.duplicates, 0);
+        assert!(!temp_dir.path().join("copies").exists());
+        assert!(temp_dir.path().join("react_header_01.jsx").exists());
+    }
+}
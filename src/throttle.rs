@@ -0,0 +1,133 @@
+//! Token-bucket bandwidth limiting of remote transfers
+//!
+//! A limiter refills at its rate up to one second of traffic. Transfers take
+//! the tokens of each chunk they move and wait while the bucket is in debt, so
+//! a burst is allowed after an idle period but the average rate holds.
+
+use std::io::{self, Read};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Most bytes moved between two waits, so the rate stays smooth within large reads
+const CHUNK: usize = 64 * 1024;
+
+/// Budget of bytes per second shared by the transfers using it
+#[derive(Debug)]
+pub struct RateLimiter {
+    bytes_per_second: f64,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// Bytes that may be moved right away; negative while transfers wait for earlier ones
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_second: u64) -> Self {
+        let bytes_per_second = bytes_per_second.max(1) as f64;
+        Self { bytes_per_second, bucket: Mutex::new(Bucket { tokens: bytes_per_second, refilled: Instant::now() }) }
+    }
+
+    /// Wait until `bytes` may be moved
+    pub fn acquire(&self, bytes: usize) {
+        let wait = self.reserve(bytes, Instant::now());
+        if !wait.is_zero() {
+            thread::sleep(wait);
+        }
+    }
+
+    /// Take `bytes` tokens at `now` and return how long the caller must wait for them
+    fn reserve(&self, bytes: usize, now: Instant) -> Duration {
+        let mut bucket = self.bucket.lock().unwrap_or_else(|e| e.into_inner());
+        let elapsed = now.saturating_duration_since(bucket.refilled).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.bytes_per_second).min(self.bytes_per_second);
+        bucket.refilled = now;
+        bucket.tokens -= bytes as f64;
+        if bucket.tokens < 0.0 {
+            Duration::from_secs_f64(-bucket.tokens / self.bytes_per_second)
+        } else {
+            Duration::ZERO
+        }
+    }
+}
+
+/// Upload and download limits of a backend; unlimited by default
+#[derive(Debug, Clone, Default)]
+pub struct Bandwidth {
+    upload: Option<Arc<RateLimiter>>,
+    download: Option<Arc<RateLimiter>>,
+}
+
+impl Bandwidth {
+    /// `bytes_per_second` each way, or unlimited
+    pub fn limited(bytes_per_second: Option<u64>) -> Self {
+        let limiter = || bytes_per_second.map(|rate| Arc::new(RateLimiter::new(rate)));
+        Self { upload: limiter(), download: limiter() }
+    }
+
+    /// Request body read within the upload limit
+    pub fn upload<R: Read>(&self, reader: R) -> Throttled<R> {
+        Throttled { inner: reader, limiter: self.upload.clone() }
+    }
+
+    /// Response body read within the download limit
+    pub fn download<R: Read>(&self, reader: R) -> Throttled<R> {
+        Throttled { inner: reader, limiter: self.download.clone() }
+    }
+}
+
+/// Reader paced by a limiter, if any
+pub struct Throttled<R> {
+    inner: R,
+    limiter: Option<Arc<RateLimiter>>,
+}
+
+impl<R: Read> Read for Throttled<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Some(limiter) = &self.limiter else {
+            return self.inner.read(buf);
+        };
+        let length = buf.len().min(CHUNK);
+        let read = self.inner.read(&mut buf[..length])?;
+        limiter.acquire(read);
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket() {
+        let limiter = RateLimiter::new(1000);
+        let start = Instant::now();
+        // A full bucket lets one second of traffic through at once
+        assert_eq!(limiter.reserve(1000, start), Duration::ZERO);
+        assert_eq!(limiter.reserve(500, start), Duration::from_millis(500));
+        // Debt is paid back at the rate before new tokens are available
+        assert_eq!(limiter.reserve(0, start + Duration::from_millis(500)), Duration::ZERO);
+        assert_eq!(limiter.reserve(250, start + Duration::from_millis(750)), Duration::ZERO);
+        // Idle time refills at most one second of traffic
+        assert_eq!(limiter.reserve(1500, start + Duration::from_secs(60)), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_throttled_reader() {
+        let data = vec![7u8; 300 * 1024];
+        let mut copy = Vec::new();
+        Bandwidth::default().download(&data[..]).read_to_end(&mut copy).unwrap();
+        assert_eq!(copy, data);
+
+        // One second of burst, then the remaining 100 KiB at 200 KiB/s
+        let start = Instant::now();
+        copy.clear();
+        Bandwidth::limited(Some(200 * 1024)).upload(&data[..]).read_to_end(&mut copy).unwrap();
+        assert_eq!(copy, data);
+        assert!(start.elapsed() >= Duration::from_millis(450));
+    }
+}
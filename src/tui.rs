@@ -0,0 +1,216 @@
+//! Live dashboard for long imaging jobs (`--tui`), drawn from [`Metrics`]
+
+use std::collections::VecDeque;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Gauge, List, Paragraph, Sparkline};
+use ratatui::Frame;
+
+use crate::metrics::Metrics;
+
+/// Interval between redraws and throughput samples
+const TICK: Duration = Duration::from_millis(250);
+
+/// Throughput samples kept for the graph
+const HISTORY: usize = 240;
+
+/// Dashboard drawn on the alternate screen by a background thread until
+/// [`Dashboard::stop`]; `q` closes it early and lets the job continue
+pub struct Dashboard {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl Dashboard {
+    pub fn start(title: String, metrics: Arc<Metrics>) -> io::Result<Self> {
+        let mut terminal = ratatui::try_init()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = Arc::clone(&stop);
+        let thread = thread::spawn(move || {
+            let mut state = DashboardState::new(title);
+            let result = (|| {
+                while !stopped.load(Ordering::Relaxed) {
+                    state.sample(&metrics);
+                    terminal.draw(|frame| draw(frame, &state, &metrics))?;
+                    if event::poll(TICK)? {
+                        if let Event::Key(key) = event::read()? {
+                            if key.kind != KeyEventKind::Press {
+                                continue;
+                            }
+                            // Raw mode swallows the interrupt signal
+                            if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+                                ratatui::restore();
+                                std::process::exit(130);
+                            }
+                            if matches!(key.code, KeyCode::Char('q') | KeyCode::Esc) {
+                                break;
+                            }
+                        }
+                    }
+                }
+                Ok(())
+            })();
+            ratatui::try_restore()?;
+            result
+        });
+        Ok(Self { stop, thread: Some(thread) })
+    }
+
+    /// Close the dashboard and give the terminal back
+    pub fn stop(mut self) -> io::Result<()> {
+        self.shutdown()
+    }
+
+    fn shutdown(&mut self) -> io::Result<()> {
+        self.stop.store(true, Ordering::Relaxed);
+        match self.thread.take().map(JoinHandle::join) {
+            Some(Ok(result)) => result,
+            Some(Err(_)) => Err(io::Error::other("dashboard thread panicked")),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Dashboard {
+    fn drop(&mut self) {
+        let _ = self.shutdown();
+    }
+}
+
+/// Throughput history, sampled once per tick
+struct DashboardState {
+    title: String,
+    started: Instant,
+    last_bytes: u64,
+    last_sample: Instant,
+    /// Bytes per second over each tick, newest last
+    throughput: VecDeque<u64>,
+}
+
+impl DashboardState {
+    fn new(title: String) -> Self {
+        let now = Instant::now();
+        Self { title, started: now, last_bytes: 0, last_sample: now, throughput: VecDeque::new() }
+    }
+
+    fn sample(&mut self, metrics: &Metrics) {
+        let bytes = metrics.bytes_processed.load(Ordering::Relaxed);
+        let elapsed = self.last_sample.elapsed().as_secs_f64();
+        if elapsed > 0.0 {
+            self.throughput.push_back((bytes.saturating_sub(self.last_bytes) as f64 / elapsed) as u64);
+            if self.throughput.len() > HISTORY {
+                self.throughput.pop_front();
+            }
+        }
+        self.last_bytes = bytes;
+        self.last_sample = Instant::now();
+    }
+}
+
+fn draw(frame: &mut Frame, state: &DashboardState, metrics: &Metrics) {
+    const MIB: f64 = 1024.0 * 1024.0;
+    let files = metrics.files_processed.load(Ordering::Relaxed);
+    let files_total = metrics.files_total.load(Ordering::Relaxed);
+    let bytes = metrics.bytes_processed.load(Ordering::Relaxed);
+    let bytes_total = metrics.bytes_total.load(Ordering::Relaxed);
+    let compressed = metrics.bytes_compressed.load(Ordering::Relaxed);
+    let unique = metrics.unique_blocks.load(Ordering::Relaxed);
+    let duplicate = metrics.duplicate_blocks.load(Ordering::Relaxed);
+    let elapsed = state.started.elapsed();
+    let speed = bytes as f64 / elapsed.as_secs_f64().max(0.001);
+    let eta = match bytes_total.checked_sub(bytes) {
+        Some(remaining) if speed > 0.0 && bytes > 0 => format!("{}s", (remaining as f64 / speed).ceil() as u64),
+        _ => "-".to_string(),
+    };
+
+    let [progress_area, stats_area, graph_area, workers_area] = Layout::vertical([
+        Constraint::Length(3),
+        Constraint::Length(5),
+        Constraint::Length(8),
+        Constraint::Min(3),
+    ])
+    .areas(frame.area());
+
+    let ratio = if bytes_total == 0 { 0.0 } else { (bytes as f64 / bytes_total as f64).min(1.0) };
+    frame.render_widget(
+        Gauge::default()
+            .block(Block::bordered().title(format!(" {} — {}s, q to close ", state.title, elapsed.as_secs())))
+            .gauge_style(Style::default().fg(Color::Green))
+            .ratio(ratio)
+            .label(format!(
+                "{:.1} / {:.1} MB, {} / {} files, ETA {}",
+                bytes as f64 / MIB,
+                bytes_total as f64 / MIB,
+                files,
+                files_total,
+                eta
+            )),
+        progress_area,
+    );
+
+    let stats = vec![
+        Line::from(format!("Throughput:     {:.1} MB/s", speed / MIB)),
+        Line::from(format!(
+            "Dedup hit rate: {:.1}% ({} unique, {} duplicate blocks)",
+            metrics.get_deduplication_ratio(),
+            unique,
+            duplicate
+        )),
+        Line::from(format!("Stored:         {:.1} MB ({:.1}% of input)", compressed as f64 / MIB, metrics.get_compression_ratio())),
+    ];
+    frame.render_widget(Paragraph::new(stats).block(Block::bordered().title(" Statistics ")), stats_area);
+
+    // Most recent samples that fit
+    let width = graph_area.width.saturating_sub(2) as usize;
+    let samples: Vec<u64> = state.throughput.iter().skip(state.throughput.len().saturating_sub(width)).copied().collect();
+    frame.render_widget(
+        Sparkline::default()
+            .block(Block::bordered().title(" Throughput "))
+            .style(Style::default().fg(Color::Cyan))
+            .data(samples),
+        graph_area,
+    );
+
+    let workers: Vec<String> = metrics
+        .activity()
+        .iter()
+        .map(|activity| format!("{:<6} {:>6.1}s  {}", activity.stage, activity.since.elapsed().as_secs_f64(), activity.path.display()))
+        .collect();
+    frame.render_widget(List::new(workers).block(Block::bordered().title(" Workers ")), workers_area);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+
+    #[test]
+    fn test_dashboard_shows_progress_and_workers() {
+        let metrics = Metrics::new();
+        metrics.set_totals(4, 4096);
+        metrics.increment_files();
+        metrics.add_bytes_processed(1024);
+        metrics.increment_unique_blocks();
+        metrics.increment_duplicate_blocks();
+        metrics.begin("dedup", Path::new("disk.img"));
+
+        let mut state = DashboardState::new("create-image rootfs".to_string());
+        state.sample(&metrics);
+        let mut terminal = Terminal::new(TestBackend::new(100, 24)).unwrap();
+        terminal.draw(|frame| draw(frame, &state, &metrics)).unwrap();
+
+        let screen: String = terminal.backend().buffer().content().iter().map(|cell| cell.symbol()).collect();
+        assert!(screen.contains("1 / 4 files"));
+        assert!(screen.contains("50.0% (1 unique, 1 duplicate blocks)"));
+        assert!(screen.contains("disk.img"));
+    }
+}
@@ -0,0 +1,114 @@
+use std::time::SystemTime;
+
+/// Parse a human-readable byte size such as `512`, `64K`, `10MB`, `1.5G` or `2GiB`.
+/// Suffixes are binary multiples (1K = 1024 bytes).
+pub fn parse_size(input: &str) -> Result<u64, String> {
+    let trimmed = input.trim();
+    let split = trimmed
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(trimmed.len());
+    let (number, suffix) = trimmed.split_at(split);
+
+    let value: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid size: {input}"))?;
+    let multiplier: u64 = match suffix.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1024,
+        "M" | "MB" | "MIB" => 1024 * 1024,
+        "G" | "GB" | "GIB" => 1024 * 1024 * 1024,
+        "T" | "TB" | "TIB" => 1024 * 1024 * 1024 * 1024,
+        _ => return Err(format!("invalid size suffix: {input}")),
+    };
+
+    Ok((value * multiplier as f64) as u64)
+}
+
+/// Parse a transfer rate in bytes per second such as `10MB/s` or `512K`; zero is rejected.
+pub fn parse_rate(input: &str) -> Result<u64, String> {
+    let trimmed = input.trim();
+    let size = trimmed.strip_suffix("/s").unwrap_or(trimmed);
+    match parse_size(size).map_err(|_| format!("invalid rate: {input}"))? {
+        0 => Err(format!("rate must be positive: {input}")),
+        rate => Ok(rate),
+    }
+}
+
+/// Parse a fraction given as `0.25` or `25%`, between 0 and 1 inclusive.
+pub fn parse_ratio(input: &str) -> Result<f64, String> {
+    let trimmed = input.trim();
+    let value = match trimmed.strip_suffix('%') {
+        Some(percent) => percent.trim().parse::<f64>().map(|value| value / 100.0),
+        None => trimmed.parse(),
+    }
+    .map_err(|_| format!("invalid ratio: {input}"))?;
+
+    if !(0.0..=1.0).contains(&value) {
+        return Err(format!("ratio must be between 0 and 1: {input}"));
+    }
+    Ok(value)
+}
+
+/// Parse a point in time given either as a date (`2025-01-31`, `2025-01-31 12:00:00`)
+/// or as a duration relative to now (`30d`, `12h`, `2weeks`).
+pub fn parse_time_threshold(input: &str) -> Result<SystemTime, String> {
+    let trimmed = input.trim();
+
+    if let Ok(duration) = humantime::parse_duration(trimmed) {
+        return SystemTime::now()
+            .checked_sub(duration)
+            .ok_or_else(|| format!("duration too large: {input}"));
+    }
+
+    let timestamp = if trimmed.len() == "2025-01-31".len() {
+        format!("{trimmed} 00:00:00")
+    } else {
+        trimmed.to_string()
+    };
+    humantime::parse_rfc3339_weak(&timestamp)
+        .map_err(|_| format!("expected a date (YYYY-MM-DD) or a duration (e.g. 30d): {input}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512").unwrap(), 512);
+        assert_eq!(parse_size("64K").unwrap(), 64 * 1024);
+        assert_eq!(parse_size("10MB").unwrap(), 10 * 1024 * 1024);
+        assert_eq!(parse_size("1.5G").unwrap(), 3 * 512 * 1024 * 1024);
+        assert!(parse_size("abc").is_err());
+        assert!(parse_size("10X").is_err());
+    }
+
+    #[test]
+    fn test_parse_rate() {
+        assert_eq!(parse_rate("10MB/s").unwrap(), 10 * 1024 * 1024);
+        assert_eq!(parse_rate("512K").unwrap(), 512 * 1024);
+        assert!(parse_rate("0/s").is_err());
+        assert!(parse_rate("fast").is_err());
+    }
+
+    #[test]
+    fn test_parse_ratio() {
+        assert_eq!(parse_ratio("0.25").unwrap(), 0.25);
+        assert_eq!(parse_ratio("10%").unwrap(), 0.1);
+        assert!(parse_ratio("1.5").is_err());
+        assert!(parse_ratio("ten").is_err());
+    }
+
+    #[test]
+    fn test_parse_time_threshold() {
+        let date = parse_time_threshold("2025-01-01").unwrap();
+        assert_eq!(date.duration_since(UNIX_EPOCH).unwrap().as_secs(), 1_735_689_600);
+
+        let relative = parse_time_threshold("30d").unwrap();
+        let age = SystemTime::now().duration_since(relative).unwrap();
+        assert!(age >= Duration::from_secs(30 * 86400));
+
+        assert!(parse_time_threshold("yesterday").is_err());
+    }
+}
@@ -0,0 +1,351 @@
+//! Resumable multipart uploads for remote backends
+//!
+//! A file is sent as numbered parts. Each part carries its SHA-256 so the
+//! service can reject a corrupted transfer, and failed requests are retried
+//! with exponential backoff. Acknowledged parts are recorded in a state file
+//! next to the source (`<file>.upload`): an interrupted upload resumes with the
+//! parts that are still missing, as long as the source has not changed.
+
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{debug, info, warn};
+
+/// One part of an upload, numbered from 1
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Part {
+    pub number: u32,
+    pub offset: u64,
+    pub length: u64,
+    pub sha256: [u8; 32],
+}
+
+/// Part acknowledged by the service
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CompletedPart {
+    pub number: u32,
+    /// Hex SHA-256 of the part
+    pub sha256: String,
+    /// Identifier returned by the service (ETag, block id...), needed to complete the upload
+    pub tag: String,
+}
+
+/// Service side of a multipart upload, implemented by each remote backend
+///
+/// Errors of kind `PermissionDenied`, `NotFound`, `InvalidInput` and
+/// `Unsupported` are final; any other error is retried.
+pub trait MultipartTarget {
+    /// Where the upload goes, e.g. its URL; a saved state is only resumed for the same destination
+    fn destination(&self) -> String;
+
+    /// Most parts an upload may have; the part size grows to stay under it
+    fn max_parts(&self) -> u32 {
+        10_000
+    }
+
+    /// Start an upload of `size` bytes and return its id
+    fn create(&mut self, size: u64) -> io::Result<String>;
+
+    /// Store one part and return the tag the service gave it
+    fn upload_part(&mut self, upload_id: &str, part: &Part, data: &[u8]) -> io::Result<String>;
+
+    /// Assemble the parts, given in order
+    fn complete(&mut self, upload_id: &str, parts: &[CompletedPart]) -> io::Result<()>;
+
+    /// Discard an upload that will not be resumed
+    fn abort(&mut self, _upload_id: &str) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// How failed requests are retried
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Tries per request, the first one included
+    pub attempts: u32,
+    /// Wait before the first retry, doubled after each failure
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self { attempts: 6, initial_delay: Duration::from_millis(500), max_delay: Duration::from_secs(30) }
+    }
+}
+
+impl RetryPolicy {
+    /// Run `request` until it succeeds, fails with a final error or runs out of attempts
+    pub fn run<T>(&self, what: &str, retries: &mut u64, mut request: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+        let mut delay = self.initial_delay;
+        let mut attempt = 1;
+        loop {
+            match request() {
+                Ok(value) => return Ok(value),
+                Err(e) if attempt < self.attempts && is_transient(&e) => {
+                    warn!(request = what, attempt, error = %e, delay_ms = delay.as_millis() as u64, "Request failed, retrying");
+                    thread::sleep(delay);
+                    delay = (delay * 2).min(self.max_delay);
+                    attempt += 1;
+                    *retries += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+fn is_transient(error: &io::Error) -> bool {
+    !matches!(
+        error.kind(),
+        io::ErrorKind::PermissionDenied | io::ErrorKind::NotFound | io::ErrorKind::InvalidInput | io::ErrorKind::Unsupported
+    )
+}
+
+#[derive(Debug, Clone)]
+pub struct UploadOptions {
+    /// Size of the parts, raised if the file would need more than the backend allows
+    pub part_size: u64,
+    pub retry: RetryPolicy,
+    /// Progress file [default: `<file>.upload`]
+    pub state_path: Option<PathBuf>,
+}
+
+impl Default for UploadOptions {
+    fn default() -> Self {
+        Self { part_size: 64 * 1024 * 1024, retry: RetryPolicy::default(), state_path: None }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UploadSummary {
+    pub parts: u32,
+    /// Parts already acknowledged by an interrupted run
+    pub resumed_parts: u32,
+    /// Bytes sent by this run
+    pub bytes_sent: u64,
+    /// Requests that failed and were sent again
+    pub retries: u64,
+}
+
+/// Upload in progress, saved after every acknowledged part
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct UploadState {
+    destination: String,
+    upload_id: String,
+    size: u64,
+    modified: u64,
+    part_size: u64,
+    parts: Vec<CompletedPart>,
+}
+
+/// Upload `path` through `target`, resuming a previous interrupted upload of the same file
+pub fn upload_file(path: &Path, target: &mut impl MultipartTarget, options: &UploadOptions) -> io::Result<UploadSummary> {
+    let state_path = options.state_path.clone().unwrap_or_else(|| default_state_path(path));
+    let mut file = File::open(path)?;
+    let metadata = file.metadata()?;
+    let size = metadata.len();
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let destination = target.destination();
+    let mut summary = UploadSummary::default();
+
+    let saved = read_state(&state_path)?;
+    let mut state = match saved {
+        Some(state) if state.destination == destination && state.size == size && state.modified == modified => {
+            info!(file = %path.display(), parts = state.parts.len(), "Resuming upload");
+            summary.resumed_parts = state.parts.len() as u32;
+            state
+        }
+        saved => {
+            if let Some(stale) = saved {
+                debug!(upload_id = %stale.upload_id, "Discarding the upload of an older version of the file");
+                let _ = target.abort(&stale.upload_id);
+            }
+            let part_size = options.part_size.max(size.div_ceil(target.max_parts() as u64)).max(1);
+            let upload_id = options.retry.run("create", &mut summary.retries, || target.create(size))?;
+            UploadState { destination, upload_id, size, modified, part_size, parts: Vec::new() }
+        }
+    };
+
+    let part_count = size.div_ceil(state.part_size).max(1) as u32;
+    let mut buffer = Vec::new();
+    for number in 1..=part_count {
+        if state.parts.iter().any(|part| part.number == number) {
+            continue;
+        }
+        let offset = (number - 1) as u64 * state.part_size;
+        let length = state.part_size.min(size - offset);
+        buffer.resize(length as usize, 0);
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut buffer)?;
+        let part = Part { number, offset, length, sha256: Sha256::digest(&buffer).into() };
+        let what = format!("part {}/{}", number, part_count);
+        let tag = options.retry.run(&what, &mut summary.retries, || target.upload_part(&state.upload_id, &part, &buffer))?;
+        state.parts.push(CompletedPart { number, sha256: hex(&part.sha256), tag });
+        write_state(&state_path, &state)?;
+        summary.bytes_sent += length;
+        debug!(part = number, parts = part_count, "Part uploaded");
+    }
+
+    state.parts.sort_by_key(|part| part.number);
+    options.retry.run("complete", &mut summary.retries, || target.complete(&state.upload_id, &state.parts))?;
+    match fs::remove_file(&state_path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    summary.parts = part_count;
+    info!(file = %path.display(), parts = part_count, resumed = summary.resumed_parts, retries = summary.retries, "Upload complete");
+    Ok(summary)
+}
+
+/// `<file>.upload`
+pub fn default_state_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".upload");
+    path.with_file_name(name)
+}
+
+fn read_state(path: &Path) -> io::Result<Option<UploadState>> {
+    match fs::read(path) {
+        Ok(bytes) => match serde_json::from_slice(&bytes) {
+            Ok(state) => Ok(Some(state)),
+            Err(e) => {
+                warn!(state = %path.display(), error = %e, "Ignoring unreadable upload state");
+                Ok(None)
+            }
+        },
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Replace the state file atomically, so a crash leaves the previous one
+fn write_state(path: &Path, state: &UploadState) -> io::Result<()> {
+    let directory = path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let mut staging = tempfile::NamedTempFile::new_in(directory)?;
+    serde_json::to_writer(staging.as_file_mut(), state)?;
+    staging.persist(path).map_err(|e| e.error)?;
+    Ok(())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use tempfile::tempdir;
+
+    /// In-memory service failing some requests
+    #[derive(Default)]
+    struct FlakyTarget {
+        uploads: u32,
+        parts: BTreeMap<u32, Vec<u8>>,
+        assembled: Option<Vec<u8>>,
+        calls: u32,
+        /// Every n-th request fails with a transient error
+        fail_every: Option<u32>,
+        /// Parts accepted before the connection is lost for good
+        stop_after: Option<usize>,
+    }
+
+    impl FlakyTarget {
+        fn request(&mut self) -> io::Result<()> {
+            self.calls += 1;
+            match self.fail_every {
+                Some(n) if self.calls.is_multiple_of(n) => Err(io::Error::new(io::ErrorKind::ConnectionReset, "reset")),
+                _ => Ok(()),
+            }
+        }
+    }
+
+    impl MultipartTarget for FlakyTarget {
+        fn destination(&self) -> String {
+            "memory://bucket/image.zpak".to_string()
+        }
+
+        fn create(&mut self, _size: u64) -> io::Result<String> {
+            self.request()?;
+            self.uploads += 1;
+            Ok(format!("upload-{}", self.uploads))
+        }
+
+        fn upload_part(&mut self, _upload_id: &str, part: &Part, data: &[u8]) -> io::Result<String> {
+            if self.stop_after.is_some_and(|limit| self.parts.len() >= limit) {
+                return Err(io::Error::new(io::ErrorKind::PermissionDenied, "connection lost"));
+            }
+            self.request()?;
+            assert_eq!(<[u8; 32]>::from(Sha256::digest(data)), part.sha256);
+            self.parts.insert(part.number, data.to_vec());
+            Ok(format!("etag-{}", part.number))
+        }
+
+        fn complete(&mut self, _upload_id: &str, parts: &[CompletedPart]) -> io::Result<()> {
+            self.request()?;
+            let numbers: Vec<u32> = parts.iter().map(|part| part.number).collect();
+            assert_eq!(numbers, self.parts.keys().copied().collect::<Vec<_>>());
+            self.assembled = Some(self.parts.values().flatten().copied().collect());
+            Ok(())
+        }
+    }
+
+    fn options() -> UploadOptions {
+        let retry = RetryPolicy { attempts: 3, initial_delay: Duration::ZERO, max_delay: Duration::ZERO };
+        UploadOptions { part_size: 1000, retry, state_path: None }
+    }
+
+    #[test]
+    fn test_retries_transient_failures() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("image.zpak");
+        let data: Vec<u8> = (0..4500u32).map(|i| (i * 7) as u8).collect();
+        fs::write(&path, &data).unwrap();
+
+        let mut target = FlakyTarget { fail_every: Some(3), ..Default::default() };
+        let summary = upload_file(&path, &mut target, &options()).unwrap();
+        assert_eq!((summary.parts, summary.resumed_parts, summary.bytes_sent), (5, 0, 4500));
+        assert!(summary.retries >= 2);
+        assert_eq!(target.assembled.unwrap(), data);
+        assert!(!default_state_path(&path).exists());
+    }
+
+    #[test]
+    fn test_resumes_interrupted_upload() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("image.zpak");
+        let data: Vec<u8> = (0..4500u32).map(|i| (i * 13) as u8).collect();
+        fs::write(&path, &data).unwrap();
+
+        let mut target = FlakyTarget { stop_after: Some(2), ..Default::default() };
+        assert_eq!(upload_file(&path, &mut target, &options()).unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+        assert!(default_state_path(&path).exists());
+
+        target.stop_after = None;
+        let summary = upload_file(&path, &mut target, &options()).unwrap();
+        assert_eq!((summary.resumed_parts, summary.bytes_sent, target.uploads), (2, 2500, 1));
+        assert_eq!(target.assembled.take().unwrap(), data);
+
+        // A modified file starts a new upload
+        target.stop_after = Some(1);
+        let _ = upload_file(&path, &mut target, &options());
+        fs::write(&path, b"changed").unwrap();
+        let file = File::options().write(true).open(&path).unwrap();
+        file.set_modified(std::time::SystemTime::UNIX_EPOCH + Duration::from_secs(1)).unwrap();
+        target.stop_after = None;
+        target.parts.clear();
+        let summary = upload_file(&path, &mut target, &options()).unwrap();
+        assert_eq!((summary.resumed_parts, target.uploads), (0, 3));
+        assert_eq!(target.assembled.unwrap(), b"changed");
+    }
+}
@@ -0,0 +1,668 @@
+use std::fs;
+use std::io::{self, BufRead, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::SystemTime;
+use crossbeam_channel::{bounded, unbounded, Receiver, Sender};
+use tracing::debug;
+use walkdir::WalkDir;
+
+/// Scan threads used by the command line when not told otherwise
+pub const DEFAULT_SCAN_THREADS: usize = 4;
+
+/// Marker of a cache directory, see <https://bford.info/cachedir/>
+pub const CACHEDIR_TAG: &str = "CACHEDIR.TAG";
+
+/// First bytes of a valid `CACHEDIR.TAG`, which tell it apart from a file of the same name
+const CACHEDIR_SIGNATURE: &[u8] = b"Signature: 8a477f597d28d172789f06886806bc55";
+
+/// Marker of a directory to leave out of backups, whatever its content
+pub const NOBACKUP: &str = ".nobackup";
+
+/// How entries that are neither regular files, directories nor symlinks
+/// (sockets, FIFOs, device nodes) are handled during a walk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum SpecialFilePolicy {
+    /// Ignore special files entirely
+    #[default]
+    Skip,
+    /// Keep special files as typed entries (formats that cannot store them still skip them)
+    Record,
+}
+
+/// Options controlling directory traversal for compression and imaging
+#[derive(Debug, Clone, Default)]
+pub struct WalkOptions {
+    /// Follow symbolic links and archive their targets instead of the links themselves
+    pub follow_symlinks: bool,
+
+    /// Policy for sockets, FIFOs and device nodes
+    pub special_files: SpecialFilePolicy,
+
+    /// Maximum recursion depth below the root (the root's children are depth 1)
+    pub max_depth: Option<usize>,
+
+    /// Skip regular files smaller than this many bytes
+    pub min_size: Option<u64>,
+
+    /// Skip regular files larger than this many bytes
+    pub max_size: Option<u64>,
+
+    /// Skip regular files last modified before this instant
+    pub newer_than: Option<SystemTime>,
+
+    /// Entries to include instead of walking the root, relative to the current
+    /// directory or absolute, and inside the root. Listed directories are added
+    /// without their contents, as `find` already lists those.
+    pub files_from: Option<Vec<PathBuf>>,
+
+    /// Threads listing directories ahead of the consumer; 0 or 1 walks on the
+    /// calling thread. Pays off on network filesystems, where each listing
+    /// and stat waits on a round trip.
+    pub scan_threads: usize,
+
+    /// Descend into directories marked as caches (`CACHEDIR.TAG`) or excluded
+    /// from backups (`.nobackup`), which a walk otherwise skips with their contents
+    pub keep_cache_dirs: bool,
+}
+
+impl WalkOptions {
+    /// Whether a regular file passes the size and age filters
+    fn accepts_file(&self, metadata: &fs::Metadata) -> bool {
+        let size = metadata.len();
+        if self.min_size.is_some_and(|min| size < min) || self.max_size.is_some_and(|max| size > max) {
+            return false;
+        }
+
+        if let Some(threshold) = self.newer_than {
+            // Files whose mtime cannot be read are kept rather than silently dropped
+            if metadata.modified().is_ok_and(|modified| modified < threshold) {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// Whether a directory below the root is left out along with its contents
+    fn skips_dir(&self, path: &Path) -> bool {
+        if self.keep_cache_dirs || !is_excluded_dir(path) {
+            return false;
+        }
+        debug!(path = %path.display(), "Skipping cache directory");
+        true
+    }
+}
+
+/// Whether `dir` holds a `.nobackup` file or a `CACHEDIR.TAG` with the right signature
+pub fn is_excluded_dir(dir: &Path) -> bool {
+    if dir.join(NOBACKUP).symlink_metadata().is_ok() {
+        return true;
+    }
+    let mut signature = [0u8; CACHEDIR_SIGNATURE.len()];
+    fs::File::open(dir.join(CACHEDIR_TAG))
+        .and_then(|mut tag| tag.read_exact(&mut signature))
+        .is_ok_and(|()| signature == CACHEDIR_SIGNATURE)
+}
+
+/// Type of a filesystem entry as seen by the walker
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EntryKind {
+    File,
+    Directory,
+    Symlink,
+    Fifo,
+    Socket,
+    CharDevice,
+    BlockDevice,
+    /// Named data stream of a file (NTFS alternate data stream), stored as
+    /// `file:name` right after the file it belongs to
+    Stream,
+}
+
+impl EntryKind {
+    pub fn from_file_type(file_type: &fs::FileType) -> Self {
+        if file_type.is_dir() {
+            return Self::Directory;
+        }
+        if file_type.is_symlink() {
+            return Self::Symlink;
+        }
+        if file_type.is_file() {
+            return Self::File;
+        }
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::FileTypeExt;
+            if file_type.is_fifo() {
+                return Self::Fifo;
+            }
+            if file_type.is_socket() {
+                return Self::Socket;
+            }
+            if file_type.is_char_device() {
+                return Self::CharDevice;
+            }
+            if file_type.is_block_device() {
+                return Self::BlockDevice;
+            }
+        }
+
+        // Anything else (unknown platform-specific types) is treated as a special file
+        Self::Fifo
+    }
+
+    /// Sockets, FIFOs and device nodes
+    pub fn is_special(&self) -> bool {
+        matches!(self, Self::Fifo | Self::Socket | Self::CharDevice | Self::BlockDevice)
+    }
+
+    /// Regular files and their data streams, whose content is stored in blocks
+    pub fn has_content(&self) -> bool {
+        matches!(self, Self::File | Self::Stream)
+    }
+}
+
+/// Entry that could not be read during a walk
+#[derive(Debug, thiserror::Error)]
+#[error("{}: {source}", path.display())]
+pub struct WalkError {
+    pub path: PathBuf,
+    #[source]
+    pub source: io::Error,
+}
+
+#[derive(Debug)]
+pub struct WalkedEntry {
+    pub path: PathBuf,
+    pub relative_path: PathBuf,
+    pub kind: EntryKind,
+    pub metadata: fs::Metadata,
+}
+
+/// Read a list of paths, one per line or NUL-separated (`find -print0`); empty entries are ignored
+pub fn read_file_list(reader: impl BufRead, nul_separated: bool) -> io::Result<Vec<PathBuf>> {
+    let separator = if nul_separated { b'\0' } else { b'\n' };
+    let mut paths = Vec::new();
+    for item in reader.split(separator) {
+        let mut item = item?;
+        if !nul_separated && item.last() == Some(&b'\r') {
+            item.pop();
+        }
+        if !item.is_empty() {
+            paths.push(path_from_bytes(item)?);
+        }
+    }
+    Ok(paths)
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: Vec<u8>) -> io::Result<PathBuf> {
+    use std::os::unix::ffi::OsStringExt;
+    Ok(PathBuf::from(std::ffi::OsString::from_vec(bytes)))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: Vec<u8>) -> io::Result<PathBuf> {
+    String::from_utf8(bytes)
+        .map(PathBuf::from)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Walk `root` recursively, or the entries of `options.files_from`, yielding the
+/// root itself first, according to `options`
+pub fn walk<'a>(
+    root: &'a Path,
+    options: &'a WalkOptions,
+) -> Box<dyn Iterator<Item = Result<WalkedEntry, WalkError>> + Send + 'a> {
+    if let Some(paths) = &options.files_from {
+        return Box::new(walk_listed(root, paths, options));
+    }
+    if options.scan_threads > 1 {
+        return Box::new(ParallelWalk::new(root, options));
+    }
+    let mut walker = WalkDir::new(root).follow_links(options.follow_symlinks);
+    if let Some(depth) = options.max_depth {
+        walker = walker.max_depth(depth);
+    }
+
+    // The root is walked even when marked, since it was asked for explicitly
+    Box::new(walker
+        .into_iter()
+        .filter_entry(move |entry| entry.depth() == 0 || !entry.file_type().is_dir() || !options.skips_dir(entry.path()))
+        .filter_map(move |entry| {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    let path = e.path().unwrap_or(root).to_path_buf();
+                    return Some(Err(WalkError { path, source: e.into() }));
+                }
+            };
+            let kind = EntryKind::from_file_type(&entry.file_type());
+
+            if kind.is_special() && options.special_files == SpecialFilePolicy::Skip {
+                debug!(path = %entry.path().display(), kind = ?kind, "Skipping special file");
+                return None;
+            }
+
+            let metadata = match entry.metadata() {
+                Ok(metadata) => metadata,
+                Err(e) => return Some(Err(WalkError { path: entry.path().to_path_buf(), source: e.into() })),
+            };
+            accept_entry(root, entry.path(), kind, metadata, options)
+        }))
+}
+
+/// Entries of an explicit list, after the root itself
+fn walk_listed<'a>(
+    root: &'a Path,
+    paths: &'a [PathBuf],
+    options: &'a WalkOptions,
+) -> impl Iterator<Item = Result<WalkedEntry, WalkError>> + 'a {
+    std::iter::once(root.to_path_buf())
+        .chain(paths.iter().cloned())
+        .filter_map(move |path| {
+            let error = |source: io::Error| Some(Err(WalkError { path: path.clone(), source }));
+            // Compared in absolute form, since `find` may print `./src/a` for a root `src`
+            let (absolute_root, absolute_path) = match std::path::absolute(root).and_then(|absolute_root| {
+                Ok((absolute_root, std::path::absolute(&path)?))
+            }) {
+                Ok(paths) => paths,
+                Err(e) => return error(e),
+            };
+            let metadata = if options.follow_symlinks { fs::metadata(&path) } else { fs::symlink_metadata(&path) };
+            let metadata = match metadata {
+                Ok(metadata) => metadata,
+                Err(e) => return error(e),
+            };
+            let kind = EntryKind::from_file_type(&metadata.file_type());
+            if kind.is_special() && options.special_files == SpecialFilePolicy::Skip {
+                debug!(path = %path.display(), kind = ?kind, "Skipping special file");
+                return None;
+            }
+            accept_entry(&absolute_root, &absolute_path, kind, metadata, options)
+        })
+}
+
+type WalkResult = Result<WalkedEntry, WalkError>;
+
+/// Directory to list, and where to send its entries
+struct ListJob {
+    dir: PathBuf,
+    depth: usize,
+    /// Canonical paths of the directories above, to detect link loops when following links
+    ancestors: Arc<Vec<PathBuf>>,
+    reply: Sender<Vec<Listed>>,
+}
+
+/// Entry of a listing; directories to descend into come with their ancestors
+struct Listed {
+    entry: WalkResult,
+    descend: Option<Arc<Vec<PathBuf>>>,
+}
+
+/// Listing of a directory, yielded entry by entry; the listings of its
+/// subdirectories are already queued
+type Frame = std::vec::IntoIter<(WalkResult, Option<Receiver<Vec<Listed>>>)>;
+
+/// Depth-first walk yielding entries sorted by name within each directory,
+/// while a pool of threads lists the directories found ahead of the consumer
+struct ParallelWalk {
+    jobs: Sender<ListJob>,
+    /// Tells the workers to drop queued jobs once the walk is abandoned
+    cancelled: Arc<AtomicBool>,
+    /// Directories being walked, innermost last
+    stack: Vec<(usize, Frame)>,
+    /// Listing to descend into before resuming the stack, with its depth
+    next: Option<(usize, Receiver<Vec<Listed>>)>,
+    root: Option<WalkResult>,
+}
+
+impl ParallelWalk {
+    fn new(root: &Path, options: &WalkOptions) -> Self {
+        let (job_tx, job_rx) = unbounded::<ListJob>();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let context = Arc::new((root.to_path_buf(), options.clone()));
+        for _ in 0..options.scan_threads {
+            let (job_rx, cancelled, context) = (job_rx.clone(), cancelled.clone(), context.clone());
+            // Workers exit once the walk drops its sender and the queue is drained
+            thread::spawn(move || {
+                let (root, options) = &*context;
+                for job in job_rx.iter() {
+                    if !cancelled.load(Ordering::Relaxed) {
+                        let _ = job.reply.send(list_directory(root, options, &job));
+                    }
+                }
+            });
+        }
+
+        let metadata = if options.follow_symlinks { fs::metadata(root) } else { fs::symlink_metadata(root) };
+        let (entry, next) = match metadata {
+            Ok(metadata) => {
+                let kind = EntryKind::from_file_type(&metadata.file_type());
+                // The root is descended into even when it is a link to a directory
+                let descend = options.max_depth != Some(0) && fs::metadata(root).is_ok_and(|metadata| metadata.is_dir());
+                let next = descend.then(|| {
+                    let ancestors = if options.follow_symlinks { fs::canonicalize(root).into_iter().collect() } else { Vec::new() };
+                    (0, submit(&job_tx, root.to_path_buf(), 0, Arc::new(ancestors)))
+                });
+                let entry = Ok(WalkedEntry { path: root.to_path_buf(), relative_path: PathBuf::new(), kind, metadata });
+                (entry, next)
+            }
+            Err(e) => (Err(WalkError { path: root.to_path_buf(), source: e }), None),
+        };
+        Self { jobs: job_tx, cancelled, stack: Vec::new(), next, root: Some(entry) }
+    }
+}
+
+impl Drop for ParallelWalk {
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Queue the listing of `dir` and return where it will arrive
+fn submit(jobs: &Sender<ListJob>, dir: PathBuf, depth: usize, ancestors: Arc<Vec<PathBuf>>) -> Receiver<Vec<Listed>> {
+    let (reply, listing) = bounded(1);
+    let _ = jobs.send(ListJob { dir, depth, ancestors, reply });
+    listing
+}
+
+impl Iterator for ParallelWalk {
+    type Item = WalkResult;
+
+    fn next(&mut self) -> Option<WalkResult> {
+        if let Some(root) = self.root.take() {
+            return Some(root);
+        }
+        loop {
+            if let Some((depth, listing)) = self.next.take() {
+                // A worker only drops the reply if it panicked
+                let frame: Vec<_> = listing.recv().unwrap_or_default()
+                    .into_iter()
+                    .map(|listed| {
+                        let children = listed.descend.zip(listed.entry.as_ref().ok())
+                            .map(|(ancestors, entry)| submit(&self.jobs, entry.path.clone(), depth + 1, ancestors));
+                        (listed.entry, children)
+                    })
+                    .collect();
+                self.stack.push((depth, frame.into_iter()));
+            }
+            let (depth, frame) = self.stack.last_mut()?;
+            match frame.next() {
+                Some((entry, children)) => {
+                    self.next = children.map(|children| (*depth + 1, children));
+                    return Some(entry);
+                }
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+}
+
+/// Entries of a directory sorted by name
+fn list_directory(root: &Path, options: &WalkOptions, job: &ListJob) -> Vec<Listed> {
+    let failed = |path: &Path, source: io::Error| Listed { entry: Err(WalkError { path: path.to_path_buf(), source }), descend: None };
+    let paths = fs::read_dir(&job.dir).and_then(|entries| entries.map(|entry| entry.map(|entry| entry.path())).collect::<io::Result<Vec<_>>>());
+    let mut paths = match paths {
+        Ok(paths) => paths,
+        Err(e) => return vec![failed(&job.dir, e)],
+    };
+    paths.sort_unstable_by(|a, b| a.file_name().cmp(&b.file_name()));
+
+    let depth = job.depth + 1;
+    let mut listing = Vec::with_capacity(paths.len());
+    for path in paths {
+        let metadata = if options.follow_symlinks { fs::metadata(&path) } else { fs::symlink_metadata(&path) };
+        let metadata = match metadata {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                listing.push(failed(&path, e));
+                continue;
+            }
+        };
+        let kind = EntryKind::from_file_type(&metadata.file_type());
+        if kind.is_special() && options.special_files == SpecialFilePolicy::Skip {
+            debug!(path = %path.display(), kind = ?kind, "Skipping special file");
+            continue;
+        }
+        if kind == EntryKind::Directory && options.skips_dir(&path) {
+            continue;
+        }
+        let mut descend = None;
+        if kind == EntryKind::Directory && options.max_depth.is_none_or(|max| depth < max) {
+            let mut ancestors = job.ancestors.clone();
+            if options.follow_symlinks {
+                match fs::canonicalize(&path) {
+                    Ok(canonical) if ancestors.contains(&canonical) => {
+                        let message = format!("File system loop found: {} points to an ancestor", path.display());
+                        listing.push(failed(&path, io::Error::other(message)));
+                        continue;
+                    }
+                    Ok(canonical) => Arc::make_mut(&mut ancestors).push(canonical),
+                    Err(e) => {
+                        listing.push(failed(&path, e));
+                        continue;
+                    }
+                }
+            }
+            descend = Some(ancestors);
+        }
+        if let Some(entry) = accept_entry(root, &path, kind, metadata, options) {
+            listing.push(Listed { entry, descend });
+        }
+    }
+    listing
+}
+
+/// Apply the size and age filters and compute the path relative to `root`
+fn accept_entry(
+    root: &Path,
+    path: &Path,
+    kind: EntryKind,
+    metadata: fs::Metadata,
+    options: &WalkOptions,
+) -> Option<Result<WalkedEntry, WalkError>> {
+    if kind == EntryKind::File && !options.accepts_file(&metadata) {
+        debug!(path = %path.display(), "Filtered out by size/age");
+        return None;
+    }
+
+    let relative_path = match path.strip_prefix(root) {
+        Ok(relative) => relative.to_path_buf(),
+        Err(e) => return Some(Err(WalkError { path: path.to_path_buf(), source: io::Error::other(e) })),
+    };
+
+    Some(Ok(WalkedEntry {
+        path: path.to_path_buf(),
+        relative_path,
+        kind,
+        metadata,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_walk_yields_files_and_directories() {
+        let temp_dir = tempdir().unwrap();
+        fs::create_dir(temp_dir.path().join("sub")).unwrap();
+        fs::write(temp_dir.path().join("sub/a.txt"), b"a").unwrap();
+
+        let options = WalkOptions::default();
+        let entries: Vec<WalkedEntry> = walk(temp_dir.path(), &options)
+            .collect::<Result<_, WalkError>>()
+            .unwrap();
+
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].kind, EntryKind::Directory);
+        assert!(entries.iter().any(|e| e.relative_path == Path::new("sub/a.txt") && e.kind == EntryKind::File));
+    }
+
+    #[test]
+    fn test_size_and_depth_filters() {
+        let temp_dir = tempdir().unwrap();
+        fs::create_dir_all(temp_dir.path().join("a/b")).unwrap();
+        fs::write(temp_dir.path().join("small.txt"), vec![0u8; 10]).unwrap();
+        fs::write(temp_dir.path().join("large.bin"), vec![0u8; 10_000]).unwrap();
+        fs::write(temp_dir.path().join("a/b/deep.txt"), vec![0u8; 100]).unwrap();
+
+        let files = |options: &WalkOptions| -> Vec<PathBuf> {
+            walk(temp_dir.path(), options)
+                .map(|e| e.unwrap())
+                .filter(|e| e.kind == EntryKind::File)
+                .map(|e| e.relative_path)
+                .collect()
+        };
+
+        let options = WalkOptions { min_size: Some(50), max_size: Some(1000), ..Default::default() };
+        assert_eq!(files(&options), vec![PathBuf::from("a/b/deep.txt")]);
+
+        let options = WalkOptions { max_depth: Some(1), ..Default::default() };
+        assert_eq!(files(&options).len(), 2);
+
+        let options = WalkOptions {
+            newer_than: Some(SystemTime::now() + std::time::Duration::from_secs(3600)),
+            ..Default::default()
+        };
+        assert!(files(&options).is_empty());
+    }
+
+    #[test]
+    fn test_parallel_walk_matches_sequential() {
+        let temp_dir = tempdir().unwrap();
+        for dir in ["b/x/deep", "a", "c/y"] {
+            fs::create_dir_all(temp_dir.path().join(dir)).unwrap();
+        }
+        for (i, file) in ["b/x/deep/1.txt", "b/2.txt", "a/3.txt", "c/y/4.txt", "5.txt", "b/x/6.txt"].iter().enumerate() {
+            fs::write(temp_dir.path().join(file), vec![0u8; i * 10]).unwrap();
+        }
+
+        let relative = |options: &WalkOptions| -> Vec<PathBuf> {
+            walk(temp_dir.path(), options).map(|e| e.unwrap().relative_path).collect()
+        };
+        for max_depth in [None, Some(0), Some(2)] {
+            let sequential = WalkOptions { max_depth, min_size: Some(10), ..Default::default() };
+            let parallel = WalkOptions { scan_threads: 3, ..sequential.clone() };
+            let mut expected = relative(&sequential);
+            let entries = relative(&parallel);
+            // Depth-first, sorted by name within each directory
+            assert_eq!(entries[0], Path::new(""));
+            if max_depth.is_none() {
+                assert_eq!(entries[1..4], [PathBuf::from("5.txt"), PathBuf::from("a"), PathBuf::from("a/3.txt")]);
+            }
+            let mut sorted = entries.clone();
+            sorted.sort();
+            expected.sort();
+            assert_eq!(sorted, expected);
+        }
+
+        // Abandoning a walk midway stops it cleanly
+        let options = WalkOptions { scan_threads: 2, ..Default::default() };
+        assert_eq!(walk(temp_dir.path(), &options).take(2).count(), 2);
+    }
+
+    #[test]
+    fn test_cache_dirs_skipped() {
+        let temp_dir = tempdir().unwrap();
+        for dir in ["target/debug", "cache", "backup/off", "fake"] {
+            fs::create_dir_all(temp_dir.path().join(dir)).unwrap();
+        }
+        fs::write(temp_dir.path().join("target").join(CACHEDIR_TAG), b"Signature: 8a477f597d28d172789f06886806bc55\n# cache").unwrap();
+        fs::write(temp_dir.path().join("target/debug/app"), b"binary").unwrap();
+        fs::write(temp_dir.path().join("backup/off").join(NOBACKUP), b"").unwrap();
+        fs::write(temp_dir.path().join("backup/kept.txt"), b"kept").unwrap();
+        // Without the signature, a file of that name marks nothing
+        fs::write(temp_dir.path().join("fake").join(CACHEDIR_TAG), b"not a cache").unwrap();
+        fs::write(temp_dir.path().join("cache/data"), b"data").unwrap();
+
+        let relative = |options: &WalkOptions| -> Vec<PathBuf> {
+            walk(temp_dir.path(), options).map(|e| e.unwrap().relative_path).collect()
+        };
+        for scan_threads in [0, 2] {
+            let entries = relative(&WalkOptions { scan_threads, ..Default::default() });
+            assert!(!entries.iter().any(|path| path.starts_with("target") || path.starts_with("backup/off")));
+            assert!(entries.contains(&PathBuf::from("backup/kept.txt")));
+            assert!(entries.contains(&PathBuf::from("fake").join(CACHEDIR_TAG)));
+            assert!(entries.contains(&PathBuf::from("cache/data")));
+
+            let entries = relative(&WalkOptions { scan_threads, keep_cache_dirs: true, ..Default::default() });
+            assert!(entries.contains(&PathBuf::from("target/debug/app")));
+            assert!(entries.contains(&PathBuf::from("backup/off").join(NOBACKUP)));
+        }
+
+        // A marked root is still walked
+        let target = temp_dir.path().join("target");
+        assert_eq!(walk(&target, &WalkOptions::default()).count(), 4);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_parallel_walk_detects_link_loops() {
+        let temp_dir = tempdir().unwrap();
+        fs::create_dir(temp_dir.path().join("sub")).unwrap();
+        std::os::unix::fs::symlink("..", temp_dir.path().join("sub/up")).unwrap();
+
+        let options = WalkOptions { follow_symlinks: true, scan_threads: 2, ..Default::default() };
+        let results: Vec<_> = walk(temp_dir.path(), &options).collect();
+        assert_eq!(results.len(), 3);
+        assert!(results[2].as_ref().is_err_and(|e| e.path.ends_with("sub/up")));
+    }
+
+    #[test]
+    fn test_files_from() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path().join("root");
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(root.join("sub/a.txt"), b"a").unwrap();
+        fs::write(root.join("b.txt"), b"b").unwrap();
+        fs::write(temp_dir.path().join("outside.txt"), b"c").unwrap();
+
+        let list = format!("{}\n\n{}\r\n", root.join("sub/a.txt").display(), root.join("sub").display());
+        let paths = read_file_list(list.as_bytes(), false).unwrap();
+        assert_eq!(paths, [root.join("sub/a.txt"), root.join("sub")]);
+        assert_eq!(read_file_list(&b"x\0y\0"[..], true).unwrap(), [PathBuf::from("x"), PathBuf::from("y")]);
+
+        let options = WalkOptions { files_from: Some(paths), ..Default::default() };
+        let entries: Vec<WalkedEntry> = walk(&root, &options).collect::<Result<_, WalkError>>().unwrap();
+        let relative: Vec<&Path> = entries.iter().map(|e| e.relative_path.as_path()).collect();
+        // Listed directories are not descended into
+        assert_eq!(relative, [Path::new(""), Path::new("sub/a.txt"), Path::new("sub")]);
+        assert_eq!(entries[2].kind, EntryKind::Directory);
+
+        let options = WalkOptions { files_from: Some(vec![temp_dir.path().join("outside.txt")]), ..Default::default() };
+        assert!(walk(&root, &options).any(|e| e.is_err()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink_policy() {
+        let temp_dir = tempdir().unwrap();
+        fs::write(temp_dir.path().join("target.txt"), b"data").unwrap();
+        std::os::unix::fs::symlink("target.txt", temp_dir.path().join("link.txt")).unwrap();
+
+        let options = WalkOptions::default();
+        let link = walk(temp_dir.path(), &options)
+            .map(|e| e.unwrap())
+            .find(|e| e.relative_path == Path::new("link.txt"))
+            .unwrap();
+        assert_eq!(link.kind, EntryKind::Symlink);
+
+        let options = WalkOptions { follow_symlinks: true, ..Default::default() };
+        let link = walk(temp_dir.path(), &options)
+            .map(|e| e.unwrap())
+            .find(|e| e.relative_path == Path::new("link.txt"))
+            .unwrap();
+        assert_eq!(link.kind, EntryKind::File);
+        assert_eq!(link.metadata.len(), 4);
+    }
+}
@@ -0,0 +1,389 @@
+//! WebDAV backend for `dav://` (HTTP) and `davs://` (HTTPS) URLs
+//!
+//! Objects are files under the collection holding the URL's file; missing
+//! collections are created on upload. Credentials come from `[storage.webdav]`
+//! in config.toml or the `ZIPPY_STORAGE__WEBDAV__*` variables: basic auth with
+//! a username and password, or bearer auth with a token. Under a Nextcloud
+//! files URL (`/remote.php/dav/files/USER/...`), files larger than one part
+//! are sent with Nextcloud's chunked uploads, which resume after an
+//! interruption; other servers get a single PUT.
+
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::ops::Range;
+use std::path::Path;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use tracing::debug;
+
+use crate::config::WebDavConfig;
+use crate::http::{self, percent_encode, xml_elements, xml_unescape};
+use crate::storage::{ObjectInfo, StorageBackend};
+use crate::throttle::Bandwidth;
+use crate::upload::{self, CompletedPart, MultipartTarget, Part, UploadOptions};
+
+/// Most chunks of a Nextcloud upload
+const MAX_CHUNKS: u32 = 10_000;
+
+const PROPFIND_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?><d:propfind xmlns:d="DAV:"><d:prop><d:resourcetype/><d:getcontentlength/></d:prop></d:propfind>"#;
+
+pub struct WebDavBackend {
+    /// `scheme://host[:port]`
+    origin: String,
+    /// Path of the root collection, without trailing slash
+    root: String,
+    /// Nextcloud collection of chunked uploads
+    uploads: Option<String>,
+    authorization: Option<String>,
+    agent: ureq::Agent,
+    bandwidth: Bandwidth,
+    upload: UploadOptions,
+}
+
+impl WebDavBackend {
+    /// `scheme` is `dav` or `davs`, `root` is `host[:port]/path`
+    pub fn new(scheme: &str, root: &str, config: &WebDavConfig) -> io::Result<Self> {
+        let (host, path) = root.split_once('/').unwrap_or((root, ""));
+        if host.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{}:// URLs start with a host", scheme)));
+        }
+        let authorization = match (&config.token, &config.username) {
+            (Some(token), _) => Some(format!("Bearer {}", token)),
+            (None, Some(username)) => {
+                let credentials = format!("{}:{}", username, config.password.as_deref().unwrap_or(""));
+                Some(format!("Basic {}", BASE64.encode(credentials)))
+            }
+            (None, None) => None,
+        };
+        let root = format!("/{}", path.trim_matches('/'));
+        Ok(Self {
+            origin: format!("{}://{}", if scheme == "davs" { "https" } else { "http" }, host),
+            uploads: nextcloud_uploads(&root),
+            root: root.trim_end_matches('/').to_string(),
+            authorization,
+            agent: http::agent(),
+            bandwidth: Bandwidth::default(),
+            upload: UploadOptions::default(),
+        })
+    }
+
+    /// Pace transfers within `bandwidth`
+    pub fn with_bandwidth(mut self, bandwidth: Bandwidth) -> Self {
+        self.bandwidth = bandwidth;
+        self
+    }
+
+    /// Backend of the collection holding a URL's file, and the file name
+    pub fn from_url(scheme: &str, rest: &str, config: &WebDavConfig) -> io::Result<(Self, String)> {
+        let Some((root, name)) = rest.trim_end_matches('/').rsplit_once('/').filter(|(_, name)| !name.is_empty()) else {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{}://{} does not name a file", scheme, rest)));
+        };
+        Ok((Self::new(scheme, root, config)?, name.to_string()))
+    }
+
+    /// Path of an object
+    fn path(&self, name: &str) -> String {
+        format!("{}/{}", self.root, name)
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.origin, percent_encode(path, true))
+    }
+
+    fn request(&self, method: &str, path: &str) -> ureq::Request {
+        let request = self.agent.request(method, &self.url(path));
+        match &self.authorization {
+            Some(authorization) => request.set("Authorization", authorization),
+            None => request,
+        }
+    }
+
+    /// Run a request with the retry policy of uploads
+    fn retried<T>(&self, what: &str, request: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+        self.upload.retry.run(what, &mut 0, request)
+    }
+
+    /// Create a collection and the missing ones leading to it
+    fn make_collection(&self, path: &str) -> io::Result<()> {
+        if path.is_empty() || path == "/" {
+            return Ok(());
+        }
+        match self.request("MKCOL", path).call() {
+            // 405 Method Not Allowed: it exists
+            Ok(_) | Err(ureq::Error::Status(405, _)) => Ok(()),
+            // 409 Conflict: its parent is missing
+            Err(ureq::Error::Status(409, _)) => {
+                self.make_collection(path.rsplit_once('/').map_or("", |(parent, _)| parent))?;
+                match self.request("MKCOL", path).call() {
+                    Ok(_) | Err(ureq::Error::Status(405, _)) => Ok(()),
+                    Err(e) => Err(http::request_error(path, e)),
+                }
+            }
+            Err(e) => Err(http::request_error(path, e)),
+        }
+    }
+
+    /// Children of a collection, `None` if it does not exist
+    fn children(&self, collection: &str) -> io::Result<Option<Vec<Entry>>> {
+        let response = self.retried("list collection", || {
+            match self.request("PROPFIND", &format!("{}/", collection)).set("Depth", "1").set("Content-Type", "application/xml").send_string(PROPFIND_BODY) {
+                Err(ureq::Error::Status(404, _)) => Ok(None),
+                response => Ok(Some(response.map_err(|e| http::request_error(collection, e))?.into_string()?)),
+            }
+        })?;
+        let Some(body) = response else {
+            return Ok(None);
+        };
+        let collection = collection.trim_end_matches('/');
+        Ok(Some(parse_multistatus(&body).into_iter().filter(|entry| entry.path.trim_end_matches('/') != collection).collect()))
+    }
+
+    /// Send a file in one request
+    fn put(&self, path: &str, source: &Path, size: u64) -> io::Result<()> {
+        self.retried("upload file", || {
+            self.request("PUT", path)
+                .set("Content-Length", &size.to_string())
+                .send(self.bandwidth.upload(File::open(source)?))
+                .map_err(|e| http::request_error(path, e))?;
+            Ok(())
+        })
+    }
+}
+
+/// Nextcloud uploads collection of a path under `/remote.php/dav/files/USER`
+fn nextcloud_uploads(root: &str) -> Option<String> {
+    let (base, rest) = root.split_once("/remote.php/dav/files/")?;
+    let user = rest.split('/').next().filter(|user| !user.is_empty())?;
+    Some(format!("{}/remote.php/dav/uploads/{}", base, user))
+}
+
+impl StorageBackend for WebDavBackend {
+    fn open(&self, name: &str) -> io::Result<Box<dyn Read + Send>> {
+        let path = self.path(name);
+        let response = self.retried("download file", || self.request("GET", &path).call().map_err(|e| http::request_error(name, e)))?;
+        Ok(Box::new(self.bandwidth.download(response.into_reader())))
+    }
+
+    fn read_range(&self, name: &str, range: Range<u64>) -> io::Result<Vec<u8>> {
+        if range.is_empty() {
+            return Ok(Vec::new());
+        }
+        let path = self.path(name);
+        let header = format!("bytes={}-{}", range.start, range.end - 1);
+        self.retried("download range", || {
+            let response = match self.request("GET", &path).set("Range", &header).call().map_err(|e| http::request_error(name, e)) {
+                // The range starts after the end of the file
+                Err(e) if http::status(&e) == Some(416) => return Ok(Vec::new()),
+                response => response?,
+            };
+            // Servers without range support send the whole file
+            let skip = if response.status() == 206 { 0 } else { range.start };
+            let mut reader = self.bandwidth.download(response.into_reader());
+            io::copy(&mut (&mut reader).take(skip), &mut io::sink())?;
+            let mut data = Vec::new();
+            reader.take(range.end - range.start).read_to_end(&mut data)?;
+            Ok(data)
+        })
+    }
+
+    fn write(&self, name: &str, source: &Path) -> io::Result<()> {
+        let path = self.path(name);
+        self.make_collection(path.rsplit_once('/').map_or("", |(parent, _)| parent))?;
+        let size = fs::metadata(source)?.len();
+        match &self.uploads {
+            Some(uploads) if size > self.upload.part_size => {
+                let mut target = ChunkedUpload { backend: self, uploads, path: &path, size };
+                let summary = upload::upload_file(source, &mut target, &self.upload)?;
+                debug!(file = name, chunks = summary.parts, resumed = summary.resumed_parts, retries = summary.retries, "Chunked upload assembled");
+                Ok(())
+            }
+            _ => self.put(&path, source, size),
+        }
+    }
+
+    fn list(&self, prefix: &str) -> io::Result<Vec<ObjectInfo>> {
+        // Walk from the deepest collection the prefix names
+        let start = match prefix.rsplit_once('/') {
+            Some((collection, _)) => self.path(collection),
+            None => self.root.clone(),
+        };
+        let mut objects = Vec::new();
+        let mut pending = vec![start];
+        while let Some(collection) = pending.pop() {
+            for entry in self.children(&collection)?.unwrap_or_default() {
+                let Some(name) = entry.path.strip_prefix(&format!("{}/", self.root)).map(|name| name.trim_end_matches('/').to_string()) else {
+                    continue;
+                };
+                if entry.collection {
+                    if format!("{}/", name).starts_with(prefix) || prefix.starts_with(&format!("{}/", name)) {
+                        pending.push(self.path(&name));
+                    }
+                } else if name.starts_with(prefix) {
+                    objects.push(ObjectInfo { name, size: entry.size });
+                }
+            }
+        }
+        objects.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(objects)
+    }
+
+    fn delete(&self, name: &str) -> io::Result<()> {
+        let path = self.path(name);
+        self.retried("delete file", || {
+            self.request("DELETE", &path).call().map_err(|e| http::request_error(name, e))?;
+            Ok(())
+        })
+    }
+}
+
+/// Resource of a PROPFIND response
+#[derive(Debug, PartialEq, Eq)]
+struct Entry {
+    /// Decoded path
+    path: String,
+    collection: bool,
+    size: u64,
+}
+
+fn parse_multistatus(body: &str) -> Vec<Entry> {
+    xml_elements(body, "response")
+        .into_iter()
+        .filter_map(|response| {
+            let href = xml_unescape(xml_elements(response, "href").first()?.trim());
+            // Absolute URLs are reduced to their path
+            let path = match href.split_once("://") {
+                Some((_, rest)) => rest.find('/').map_or("/", |slash| &rest[slash..]).to_string(),
+                None => href,
+            };
+            let collection = xml_elements(response, "resourcetype").iter().any(|kind| !xml_elements(kind, "collection").is_empty());
+            let size = xml_elements(response, "getcontentlength").first().and_then(|size| size.trim().parse().ok()).unwrap_or(0);
+            Some(Entry { path: percent_decode(&path), collection, size })
+        })
+        .collect()
+}
+
+/// Decode `%XX` escapes; invalid UTF-8 is replaced
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%').then(|| text.get(i + 1..i + 3)).flatten().and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Nextcloud chunked upload (version 2); the upload id names its collection
+struct ChunkedUpload<'a> {
+    backend: &'a WebDavBackend,
+    uploads: &'a str,
+    /// Path of the file being uploaded
+    path: &'a str,
+    size: u64,
+}
+
+impl ChunkedUpload<'_> {
+    fn destination_url(&self) -> String {
+        self.backend.url(self.path)
+    }
+}
+
+impl MultipartTarget for ChunkedUpload<'_> {
+    fn destination(&self) -> String {
+        self.destination_url()
+    }
+
+    fn max_parts(&self) -> u32 {
+        MAX_CHUNKS
+    }
+
+    fn create(&mut self, _size: u64) -> io::Result<String> {
+        let mut nonce = [0u8; 8];
+        getrandom::getrandom(&mut nonce).map_err(|e| io::Error::other(e.to_string()))?;
+        let id = format!("zippy-{}", nonce.iter().map(|byte| format!("{:02x}", byte)).collect::<String>());
+        let collection = format!("{}/{}", self.uploads, id);
+        self.backend
+            .request("MKCOL", &collection)
+            .set("Destination", &self.destination_url())
+            .call()
+            .map_err(|e| http::request_error(&collection, e))?;
+        Ok(id)
+    }
+
+    fn upload_part(&mut self, id: &str, part: &Part, data: &[u8]) -> io::Result<String> {
+        let chunk = format!("{}/{}/{}", self.uploads, id, part.number);
+        let request = self.backend.request("PUT", &chunk).set("Destination", &self.destination_url());
+        let response = http::send_bytes(&chunk, request, data, &self.backend.bandwidth)?;
+        Ok(response.header("ETag").unwrap_or_default().to_string())
+    }
+
+    fn complete(&mut self, id: &str, _parts: &[CompletedPart]) -> io::Result<()> {
+        let file = format!("{}/{}/.file", self.uploads, id);
+        self.backend
+            .request("MOVE", &file)
+            .set("Destination", &self.destination_url())
+            .set("OC-Total-Length", &self.size.to_string())
+            .set("Overwrite", "T")
+            .call()
+            .map_err(|e| http::request_error(self.path, e))?;
+        Ok(())
+    }
+
+    fn abort(&mut self, id: &str) -> io::Result<()> {
+        let collection = format!("{}/{}", self.uploads, id);
+        match self.backend.request("DELETE", &collection).call() {
+            Ok(_) | Err(ureq::Error::Status(404, _)) => Ok(()),
+            Err(e) => Err(http::request_error(&collection, e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_multistatus() {
+        let body = r#"<?xml version="1.0"?>
+            <d:multistatus xmlns:d="DAV:" xmlns:oc="http://owncloud.org/ns">
+              <d:response><d:href>/remote.php/dav/files/alice/backups/</d:href>
+                <d:propstat><d:prop><d:resourcetype><d:collection/></d:resourcetype></d:prop></d:propstat></d:response>
+              <d:response><d:href>https://cloud.example.com/remote.php/dav/files/alice/backups/R%26D%20notes.zpak</d:href>
+                <d:propstat><d:prop><d:resourcetype/><d:getcontentlength>2048</d:getcontentlength></d:prop></d:propstat></d:response>
+            </d:multistatus>"#;
+        assert_eq!(
+            parse_multistatus(body),
+            vec![
+                Entry { path: "/remote.php/dav/files/alice/backups/".to_string(), collection: true, size: 0 },
+                Entry { path: "/remote.php/dav/files/alice/backups/R&D notes.zpak".to_string(), collection: false, size: 2048 },
+            ]
+        );
+        assert_eq!(percent_decode("dir/%C3%A9%20x.zpak%"), "dir/é x.zpak%");
+    }
+
+    #[test]
+    fn test_backend_urls() {
+        let config = WebDavConfig { username: Some("alice".to_string()), password: Some("app-password".to_string()), token: None };
+        let (backend, name) = WebDavBackend::from_url("davs", "cloud.example.com/remote.php/dav/files/alice/My Backups/root.zpak", &config).unwrap();
+        assert_eq!(name, "root.zpak");
+        assert_eq!(backend.url(&backend.path(&name)), "https://cloud.example.com/remote.php/dav/files/alice/My%20Backups/root.zpak");
+        assert_eq!(backend.uploads.as_deref(), Some("/remote.php/dav/uploads/alice"));
+        assert_eq!(backend.authorization.as_deref(), Some("Basic YWxpY2U6YXBwLXBhc3N3b3Jk"));
+
+        let config = WebDavConfig { token: Some("t0k".to_string()), ..Default::default() };
+        let (backend, _) = WebDavBackend::from_url("dav", "nas:8080/backups/root.zpak", &config).unwrap();
+        assert_eq!(backend.url(&backend.path("x")), "http://nas:8080/backups/x");
+        assert_eq!((backend.uploads, backend.authorization.as_deref()), (None, Some("Bearer t0k")));
+        assert!(WebDavBackend::from_url("dav", "nas/", &config).is_err());
+    }
+}
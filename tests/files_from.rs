@@ -0,0 +1,43 @@
+//! `--files-from -` through the command line: the list read from standard
+//! input must reach the archive, not only the estimate made before it
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use tempfile::tempdir;
+use zippy::decompress::list_archive;
+use zippy::image::list_image;
+
+fn run_with_list(dir: &Path, args: &[&str], list: &str) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_zippy"))
+        .args(args)
+        .current_dir(dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(list.as_bytes()).unwrap();
+    assert!(child.wait().unwrap().success());
+}
+
+#[test]
+fn test_files_from_stdin() {
+    let temp_dir = tempdir().unwrap();
+    let dir = temp_dir.path();
+    fs::create_dir(dir.join("data")).unwrap();
+    for name in ["a.txt", "b.txt", "c.txt"] {
+        fs::write(dir.join("data").join(name), name).unwrap();
+    }
+    let list = "data/a.txt\ndata/c.txt\n";
+
+    run_with_list(dir, &["compress", "-i", "data", "-o", "data.zpp", "-T", "-"], list);
+    let paths: Vec<_> = list_archive(&dir.join("data.zpp")).unwrap().entries.into_iter().map(|entry| entry.path).collect();
+    assert_eq!(paths, ["a.txt", "c.txt"].map(PathBuf::from));
+
+    run_with_list(dir, &["create-image", "-i", "data", "-o", "data.zpak", "-T", "-"], list);
+    let mut paths: Vec<_> = list_image(&dir.join("data.zpak")).unwrap().entries.into_iter().map(|entry| entry.path).collect();
+    paths.sort();
+    assert_eq!(paths, ["a.txt", "c.txt"].map(PathBuf::from));
+}